@@ -0,0 +1,85 @@
+// Measures the block throughput of bitswap between two in-process nodes connected over the
+// loopback, from one node wanting the blocks to the blocks having been stored by it.
+//
+// The encoding of the messages and the handling of them by the behaviour are measured without
+// the network by the `message` benchmark of the `ipfs-bitswap` crate.
+
+use cid::{Cid, Codec};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use futures::future::try_join_all;
+use ipfs::{Block, Node};
+use multihash::Sha2_256;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::runtime::Runtime;
+
+/// The number of blocks fetched in each iteration.
+const BLOCKS: usize = 32;
+
+/// The block sizes from the small dag-pb nodes up to the default chunk size.
+const BLOCK_SIZES: [usize; 3] = [256, 16 * 1024, 256 * 1024];
+
+/// Keeps the blocks of the iterations distinct, so that none of them are found locally.
+static ROUND: AtomicU64 = AtomicU64::new(0);
+
+fn new_blocks(size: usize) -> Vec<Block> {
+    let round = ROUND.fetch_add(1, Ordering::Relaxed);
+
+    (0..BLOCKS)
+        .map(|i| {
+            let mut data = vec![0u8; size];
+            data[..8].copy_from_slice(&round.to_be_bytes());
+            data[8..16].copy_from_slice(&(i as u64).to_be_bytes());
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            Block::new(data.into_boxed_slice(), cid)
+        })
+        .collect()
+}
+
+/// Stores the blocks on the `provider` and has the `fetcher` get all of them at the same time. The
+/// storing is measured as well, but it is small next to the exchange with the in-memory stores.
+async fn exchange(provider: &Node, fetcher: &Node, blocks: Vec<Block>) {
+    let mut cids = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        cids.push(provider.put_block(block).await.unwrap());
+    }
+
+    try_join_all(cids.iter().map(|cid| fetcher.get_block(cid)))
+        .await
+        .unwrap();
+}
+
+pub fn two_nodes(c: &mut Criterion) {
+    let mut rt = Runtime::new().unwrap();
+
+    let (provider, fetcher) = rt.block_on(async {
+        let provider = Node::new("provider").await;
+        let fetcher = Node::new("fetcher").await;
+        fetcher.connect(provider.addrs[0].clone()).await.unwrap();
+        (provider, fetcher)
+    });
+
+    let mut group = c.benchmark_group("bitswap");
+    group.sample_size(20);
+
+    for &size in BLOCK_SIZES.iter() {
+        group.throughput(Throughput::Bytes((BLOCKS * size) as u64));
+
+        group.bench_with_input(BenchmarkId::new("two nodes", size), &size, |b, &size| {
+            b.iter_batched(
+                || new_blocks(size),
+                |blocks| rt.block_on(exchange(&provider, &fetcher, blocks)),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+
+    rt.block_on(async move {
+        fetcher.shutdown().await;
+        provider.shutdown().await;
+    });
+}
+
+criterion_group!(benches, two_nodes);
+criterion_main!(benches);
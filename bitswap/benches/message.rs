@@ -0,0 +1,156 @@
+// Measures the cost of the bitswap protocol path without the network: encoding and decoding of
+// the messages, and the handling of the received messages by the `Bitswap` behaviour, which is
+// the work the decision engine does for every message from a peer.
+//
+// The two-node block throughput is measured by the `bitswap` benchmark of the `ipfs` crate.
+
+use bytes::Bytes;
+use cid::{Cid, Codec};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use ipfs_bitswap::{Bitswap, Block, Message, MessageWrapper};
+use libp2p_core::{connection::ConnectionId, PeerId};
+use libp2p_swarm::NetworkBehaviour;
+use multihash::Sha2_256;
+
+/// The number of blocks in each of the messages, with the block sizes below.
+const BLOCKS: usize = 16;
+
+/// The block sizes from the small dag-pb nodes up to the default chunk size.
+const BLOCK_SIZES: [usize; 3] = [256, 16 * 1024, 256 * 1024];
+
+fn blocks(count: usize, size: usize) -> Vec<Block> {
+    (0..count)
+        .map(|i| {
+            let mut data = vec![0u8; size];
+            data[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            Block::new(data, cid)
+        })
+        .collect()
+}
+
+fn message_with_blocks(blocks: &[Block]) -> Message {
+    let mut message = Message::default();
+    for block in blocks {
+        message.add_block(block.clone());
+    }
+    message
+}
+
+fn message_with_wants(count: usize) -> Message {
+    let mut message = Message::default();
+    for block in blocks(count, 8) {
+        message.want_block(block.cid(), 1);
+    }
+    message
+}
+
+pub fn encode_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("message");
+
+    for &size in BLOCK_SIZES.iter() {
+        let message = message_with_blocks(&blocks(BLOCKS, size));
+        let encoded = Bytes::from(message.to_bytes());
+
+        group.throughput(Throughput::Bytes((BLOCKS * size) as u64));
+
+        group.bench_with_input(BenchmarkId::new("encode", size), &message, |b, message| {
+            b.iter(|| message.to_bytes())
+        });
+
+        // the decoding verifies the hashes of the blocks, which dominates for the larger blocks
+        group.bench_with_input(BenchmarkId::new("decode", size), &encoded, |b, encoded| {
+            b.iter(|| Message::from_bytes(encoded.clone()).unwrap())
+        });
+    }
+
+    let message = message_with_wants(1024);
+    let encoded = Bytes::from(message.to_bytes());
+
+    group.throughput(Throughput::Elements(1024));
+
+    group.bench_function("encode wantlist", |b| b.iter(|| message.to_bytes()));
+
+    group.bench_function("decode wantlist", |b| {
+        b.iter(|| Message::from_bytes(encoded.clone()).unwrap())
+    });
+
+    group.finish();
+}
+
+/// A behaviour with the given number of connected peers, each of which have been sent the
+/// wantlist of the wanted blocks.
+fn connected(peers: &[PeerId], wanted: &[Block]) -> Bitswap {
+    let mut bitswap = Bitswap::default();
+    for block in wanted {
+        bitswap.want_block(block.cid().to_owned(), 1);
+    }
+    for peer in peers {
+        bitswap.inject_connected(peer);
+    }
+    bitswap
+}
+
+pub fn decision_engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decision engine");
+
+    for &peer_count in [1, 16, 128].iter() {
+        let peers = (0..peer_count)
+            .map(|_| PeerId::random())
+            .collect::<Vec<_>>();
+
+        let wanted = blocks(BLOCKS, 256);
+        let received = message_with_blocks(&wanted);
+        let wants = message_with_wants(BLOCKS);
+
+        group.throughput(Throughput::Elements(BLOCKS as u64));
+
+        // receiving the wanted blocks cancels them from the wantlists of all of the peers
+        group.bench_with_input(
+            BenchmarkId::new("receive blocks", peer_count),
+            &peers,
+            |b, peers| {
+                b.iter_batched(
+                    || connected(peers, &wanted),
+                    |mut bitswap| {
+                        bitswap.inject_event(
+                            peers[0].clone(),
+                            ConnectionId::new(0),
+                            MessageWrapper::Rx(received.clone()),
+                        );
+                        bitswap
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+
+        group.throughput(Throughput::Elements((BLOCKS * peer_count) as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("receive wants", peer_count),
+            &peers,
+            |b, peers| {
+                b.iter_batched(
+                    || connected(peers, &[]),
+                    |mut bitswap| {
+                        for peer in peers {
+                            bitswap.inject_event(
+                                peer.clone(),
+                                ConnectionId::new(0),
+                                MessageWrapper::Rx(wants.clone()),
+                            );
+                        }
+                        bitswap
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, encode_decode, decision_engine);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+//! Decoding arbitrary bytes must not panic, and the decoded messages must survive a roundtrip.
+#![no_main]
+
+use ipfs_bitswap::Message;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = Message::from_bytes(data.to_vec()) {
+        let decoded = Message::from_bytes(message.to_bytes()).expect("reencoded message decodes");
+        assert_eq!(decoded.want(), message.want());
+        assert_eq!(decoded.cancel(), message.cancel());
+        assert_eq!(decoded.blocks(), message.blocks());
+    }
+});
@@ -0,0 +1,94 @@
+//! Drives the `Bitswap` behaviour with the operations read from the input, checking the wantlists
+//! after each of them.
+#![no_main]
+
+use cid::{Cid, Codec};
+use futures::task::noop_waker_ref;
+use ipfs_bitswap::{Bitswap, Block, Message, MessageWrapper};
+use libfuzzer_sys::fuzz_target;
+use libp2p_core::identity::{ed25519, PublicKey};
+use libp2p_core::{connection::ConnectionId, PeerId};
+use libp2p_swarm::NetworkBehaviour;
+use multihash::Sha2_256;
+use std::task::{Context, Poll};
+
+/// The number of distinct peers and blocks the operations refer to.
+const PEERS: u8 = 4;
+const BLOCKS: u8 = 8;
+
+fn peer(i: u8) -> PeerId {
+    let secret = ed25519::SecretKey::from_bytes([i % PEERS + 1; 32]).unwrap();
+    PublicKey::Ed25519(ed25519::Keypair::from(secret).public()).into_peer_id()
+}
+
+fn block(i: u8) -> Block {
+    let data = vec![i % BLOCKS];
+    let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+    Block::new(data, cid)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut bitswap = Bitswap::default();
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut connected = [false; PEERS as usize];
+
+    for op in data.chunks_exact(3) {
+        let (p, b) = (op[1] % PEERS, op[2]);
+        let peer_id = peer(p);
+        let block = block(b);
+
+        match op[0] % 7 {
+            0 if !connected[p as usize] => {
+                bitswap.inject_connected(&peer_id);
+                connected[p as usize] = true;
+            }
+            1 if connected[p as usize] => {
+                bitswap.inject_disconnected(&peer_id);
+                connected[p as usize] = false;
+            }
+            2 => bitswap.want_block(block.cid().to_owned(), i32::from(b)),
+            3 => bitswap.cancel_block(block.cid()),
+            4 if connected[p as usize] => {
+                let mut message = Message::default();
+                message.add_block(block.clone());
+                bitswap.inject_event(peer_id, ConnectionId::new(0), MessageWrapper::Rx(message));
+
+                // a received block is no longer wanted
+                assert!(!bitswap
+                    .local_wantlist()
+                    .iter()
+                    .any(|(cid, _)| cid == block.cid()));
+            }
+            5 if connected[p as usize] => {
+                let mut message = Message::default();
+                message.want_block(block.cid(), 1);
+                bitswap.inject_event(
+                    peer_id.clone(),
+                    ConnectionId::new(0),
+                    MessageWrapper::Rx(message),
+                );
+
+                // the want is recorded unless the block is wanted locally as well
+                let wanted_locally = bitswap
+                    .local_wantlist()
+                    .iter()
+                    .any(|(cid, _)| cid == block.cid());
+                let wanted_by_peer = bitswap
+                    .peer_wantlist(&peer_id)
+                    .unwrap()
+                    .iter()
+                    .any(|(cid, _)| cid == block.cid());
+                assert!(wanted_locally || wanted_by_peer);
+            }
+            6 => while let Poll::Ready(_) = bitswap.next_action(&mut ctx) {},
+            _ => {}
+        }
+
+        for (i, &is_connected) in connected.iter().enumerate() {
+            assert_eq!(
+                bitswap.peer_wantlist(&peer(i as u8)).is_some(),
+                is_connected
+            );
+        }
+    }
+});
@@ -5,11 +5,26 @@
 //!
 //! The `Bitswap` struct implements the `NetworkBehaviour` trait. When used, it
 //! will allow providing and reciving IPFS blocks.
+//!
+//! # Determinism
+//!
+//! The behaviour has no timers of its own and its maps are iterated in a
+//! deterministic order, so that the same sequence of `inject_*` calls always
+//! produces the same actions from [`Bitswap::next_action`]. This allows a
+//! simulator to drive many behaviours reproducibly without a swarm.
+//!
+//! # Sessions
+//!
+//! The wants of correlated blocks, such as the blocks of a single DAG, can be made in a session
+//! identified by a [`SessionId`](crate::SessionId). Once a peer has sent a block wanted in the session, the
+//! later wants of the session are sent only to the peers which have done so, instead of to all
+//! of the connected peers.
 use crate::block::Block;
 use crate::ledger::{Ledger, Message, Priority};
 use crate::protocol::{BitswapConfig, MessageWrapper};
+use crate::session::{Session, SessionId};
 use cid::Cid;
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use libp2p_core::{connection::ConnectionId, Multiaddr, PeerId};
 use libp2p_swarm::protocols_handler::{IntoProtocolsHandler, OneShotHandler, ProtocolsHandler};
@@ -18,13 +33,15 @@ use libp2p_swarm::{
 };
 use std::task::{Context, Poll};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, VecDeque},
     mem,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
+    time::Instant,
 };
+use tracing::Span;
 
 /// Event used to communicate with the swarm or the higher level behaviour.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -91,14 +108,19 @@ pub struct Bitswap {
     /// List of prospect peers to connect to.
     target_peers: FnvHashSet<PeerId>,
     /// Ledger
-    pub connected_peers: HashMap<PeerId, Ledger>,
+    pub connected_peers: FnvHashMap<PeerId, Ledger>,
     /// Wanted blocks
-    wanted_blocks: HashMap<Cid, Priority>,
+    wanted_blocks: FnvHashMap<Cid, Priority>,
+    /// The spans of the wanted blocks, which close when the block is received or the want is
+    /// cancelled.
+    want_spans: FnvHashMap<Cid, Span>,
     /// Blocks queued to be sent
     pub queued_blocks: UnboundedSender<(PeerId, Block)>,
     ready_blocks: UnboundedReceiver<(PeerId, Block)>,
     /// Statistics related to peers.
-    pub stats: HashMap<PeerId, Arc<Stats>>,
+    pub stats: FnvHashMap<PeerId, Arc<Stats>>,
+    /// The sessions of correlated wants.
+    sessions: BTreeMap<SessionId, Session>,
 }
 
 impl Default for Bitswap {
@@ -110,9 +132,11 @@ impl Default for Bitswap {
             target_peers: Default::default(),
             connected_peers: Default::default(),
             wanted_blocks: Default::default(),
+            want_spans: Default::default(),
             queued_blocks: tx,
             ready_blocks: rx,
             stats: Default::default(),
+            sessions: Default::default(),
         }
     }
 }
@@ -189,22 +213,147 @@ impl Bitswap {
     ///
     /// A user request
     pub fn want_block(&mut self, cid: Cid, priority: Priority) {
+        let span = self
+            .want_spans
+            .entry(cid.clone())
+            .or_insert_with(|| debug_span!("want", cid = %cid));
+        let _entered = span.enter();
+        debug!(priority, peers = self.connected_peers.len(), "wanted");
+
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
             ledger.want_block(&cid, priority);
         }
         self.wanted_blocks.insert(cid, priority);
     }
 
+    /// Starts a new session for the wants of correlated blocks.
+    pub fn new_session(&mut self) -> SessionId {
+        let session = SessionId::new();
+        self.sessions.insert(session, Session::default());
+        session
+    }
+
+    /// Ends the session. The outstanding wants of the session stay wanted until cancelled.
+    pub fn end_session(&mut self, session: SessionId) {
+        self.sessions.remove(&session);
+    }
+
+    /// Queues the wanted block for the peers which have sent the earlier blocks of the session,
+    /// or for all peers when none of them has yet. The session is started if it is not already
+    /// ongoing.
+    pub fn want_block_in_session(&mut self, session: SessionId, cid: Cid, priority: Priority) {
+        let targets = {
+            let session = self.sessions.entry(session).or_default();
+            session.want(cid.clone());
+            session.peers().to_vec()
+        };
+
+        if targets.is_empty() {
+            return self.want_block(cid, priority);
+        }
+
+        let span = self
+            .want_spans
+            .entry(cid.clone())
+            .or_insert_with(|| debug_span!("want", cid = %cid));
+        let _entered = span.enter();
+        debug!(
+            priority,
+            ?session,
+            peers = targets.len(),
+            "wanted in session"
+        );
+
+        for peer_id in &targets {
+            if let Some(ledger) = self.connected_peers.get_mut(peer_id) {
+                ledger.want_block(&cid, priority);
+            }
+        }
+        self.wanted_blocks.insert(cid, priority);
+    }
+
+    /// Queues the outstanding wants of the session for all peers, for when the peers of the
+    /// session are slow to answer them or don't have the blocks. The wants are broadcast at most
+    /// once per interval, which grows with each broadcast until a block of the session arrives,
+    /// and not at all while the session has no peers, as the wants were broadcast already.
+    pub fn broadcast_session(&mut self, session: SessionId) {
+        let now = Instant::now();
+        match self.sessions.get_mut(&session) {
+            Some(session) if session.broadcast(now) => {}
+            _ => return,
+        }
+
+        trace!(?session, "broadcasting the session wants");
+        self.send_session_wants(session);
+    }
+
+    /// Queues the outstanding wants of the session for all peers.
+    fn send_session_wants(&mut self, session: SessionId) {
+        let wants = match self.sessions.get(&session) {
+            Some(session) => session.wants().cloned().collect::<Vec<_>>(),
+            None => return,
+        };
+
+        for cid in wants {
+            if let Some(&priority) = self.wanted_blocks.get(&cid) {
+                for ledger in self.connected_peers.values_mut() {
+                    ledger.want_block(&cid, priority);
+                }
+            }
+        }
+    }
+
     /// Removes the block from our want list and updates all peers.
     ///
     /// Can be either a user request or be called when the block
     /// was received.
     pub fn cancel_block(&mut self, cid: &Cid) {
+        if let Some(span) = self.want_spans.remove(cid) {
+            let _entered = span.enter();
+            debug!("no longer wanted");
+        }
+
+        for session in self.sessions.values_mut() {
+            session.cancel(cid);
+        }
+
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
             ledger.cancel_block(cid);
         }
         self.wanted_blocks.remove(cid);
     }
+
+    /// Returns the next action for the swarm, as [`NetworkBehaviour::poll`] does, for driving the
+    /// behaviour without a swarm.
+    pub fn next_action(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Poll<NetworkBehaviourAction<Message, BitswapEvent>> {
+        use futures::stream::StreamExt;
+
+        while let Poll::Ready(Some((peer_id, block))) = self.ready_blocks.poll_next_unpin(ctx) {
+            self.send_block(peer_id, block);
+        }
+
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+
+        for (peer_id, ledger) in &mut self.connected_peers {
+            if let Some(message) = ledger.send() {
+                if let Some(peer_stats) = self.stats.get_mut(peer_id) {
+                    peer_stats.update_outgoing(message.blocks.len() as u64);
+                }
+
+                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer_id.clone(),
+                    handler: NotifyHandler::Any,
+                    event: message,
+                });
+            }
+        }
+        Poll::Pending
+    }
 }
 
 impl NetworkBehaviour for Bitswap {
@@ -234,6 +383,18 @@ impl NetworkBehaviour for Bitswap {
         self.connected_peers.remove(peer_id);
         // the related stats are not dropped, so that they
         // persist for peers regardless of disconnects
+
+        // the sessions left without peers fall back to asking everyone
+        let orphaned = self
+            .sessions
+            .iter_mut()
+            .filter(|(_, session)| session.disconnected(peer_id) && session.peers().is_empty())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for session in orphaned {
+            self.send_session_wants(session);
+        }
     }
 
     fn inject_event(&mut self, source: PeerId, _connection: ConnectionId, message: MessageWrapper) {
@@ -246,7 +407,10 @@ impl NetworkBehaviour for Bitswap {
             MessageWrapper::Rx(msg) => msg,
         };
 
-        debug!("bitswap: inject_event from {}: {:?}", source, message);
+        let span = debug_span!("bitswap message", peer = %source);
+        let _entered = span.enter();
+
+        debug!(?message, "received");
 
         let current_wantlist = self.local_wantlist();
 
@@ -279,6 +443,15 @@ impl NetworkBehaviour for Bitswap {
 
         // Process the incoming blocks.
         for block in mem::take(&mut message.blocks) {
+            if let Some(span) = self.want_spans.get(block.cid()) {
+                let _entered = span.enter();
+                debug!(peer = %source, "received");
+            }
+
+            for session in self.sessions.values_mut() {
+                session.received(&source, block.cid());
+            }
+
             self.cancel_block(&block.cid());
 
             let event = BitswapEvent::ReceivedBlock(source.clone(), block);
@@ -291,29 +464,187 @@ impl NetworkBehaviour for Bitswap {
     fn poll(&mut self, ctx: &mut Context, _: &mut impl PollParameters)
         -> Poll<NetworkBehaviourAction<<<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent, Self::OutEvent>>
     {
-        use futures::stream::StreamExt;
+        self.next_action(ctx)
+    }
+}
 
-        while let Poll::Ready(Some((peer_id, block))) = self.ready_blocks.poll_next_unpin(ctx) {
-            self.send_block(peer_id, block);
+#[cfg(test)]
+mod tests {
+    use super::Bitswap;
+    use crate::{Block, Message, MessageWrapper, SessionId};
+    use cid::{Cid, Codec};
+    use futures::task::noop_waker_ref;
+    use libp2p_core::identity::{ed25519, PublicKey};
+    use libp2p_core::{connection::ConnectionId, PeerId};
+    use libp2p_swarm::{NetworkBehaviour, NetworkBehaviourAction};
+    use multihash::Sha2_256;
+    use std::task::{Context, Poll};
+
+    fn peer(i: u8) -> PeerId {
+        let secret = ed25519::SecretKey::from_bytes([i; 32]).unwrap();
+        PublicKey::Ed25519(ed25519::Keypair::from(secret).public()).into_peer_id()
+    }
+
+    fn block(i: u32) -> Block {
+        let data = i.to_be_bytes().to_vec();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        Block::new(data, cid)
+    }
+
+    /// Runs the same scenario of many peers wanting and sending blocks, returning the actions.
+    fn simulate() -> Vec<String> {
+        let mut bitswap = Bitswap::default();
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let mut actions = Vec::new();
+
+        for i in 0..64 {
+            bitswap.want_block(block(i).cid().to_owned(), i as i32);
         }
 
-        if let Some(event) = self.events.pop_front() {
-            return Poll::Ready(event);
+        for i in 0..32u8 {
+            bitswap.inject_connected(&peer(i));
+
+            let mut message = Message::default();
+            message.want_block(block(1000 + u32::from(i)).cid(), 1);
+            message.add_block(block(u32::from(i)));
+            bitswap.inject_event(peer(i), ConnectionId::new(0), MessageWrapper::Rx(message));
+
+            while let Poll::Ready(action) = bitswap.next_action(&mut ctx) {
+                actions.push(format!("{:?}", action));
+            }
         }
 
-        for (peer_id, ledger) in &mut self.connected_peers {
-            if let Some(message) = ledger.send() {
-                if let Some(peer_stats) = self.stats.get_mut(peer_id) {
-                    peer_stats.update_outgoing(message.blocks.len() as u64);
-                }
+        actions
+    }
 
-                return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
-                    peer_id: peer_id.clone(),
-                    handler: NotifyHandler::Any,
-                    event: message,
-                });
+    #[test]
+    fn actions_are_deterministic() {
+        let first = simulate();
+        assert!(!first.is_empty());
+        assert_eq!(first, simulate());
+    }
+
+    /// Returns the peers to which the actions send a want of the block.
+    fn wanted_from(bitswap: &mut Bitswap, cid: &Cid) -> Vec<PeerId> {
+        let mut ctx = Context::from_waker(noop_waker_ref());
+        let mut peers = Vec::new();
+
+        while let Poll::Ready(action) = bitswap.next_action(&mut ctx) {
+            if let NetworkBehaviourAction::NotifyHandler { peer_id, event, .. } = action {
+                if event.want().contains_key(cid) {
+                    peers.push(peer_id);
+                }
             }
         }
-        Poll::Pending
+
+        peers.sort_by_key(|peer| peer.to_string());
+        peers
+    }
+
+    #[test]
+    fn session_targets_the_peers_which_answered() {
+        let mut bitswap = Bitswap::default();
+        for i in 0..3 {
+            bitswap.inject_connected(&peer(i));
+        }
+
+        let session = bitswap.new_session();
+
+        // nobody has answered yet, so the first want is broadcast
+        bitswap.want_block_in_session(session, block(0).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(0).cid()).len(), 3);
+
+        let mut message = Message::default();
+        message.add_block(block(0));
+        bitswap.inject_event(peer(1), ConnectionId::new(0), MessageWrapper::Rx(message));
+
+        bitswap.want_block_in_session(session, block(1).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(1).cid()), vec![peer(1)]);
+
+        // the wants outside of the session are still broadcast
+        bitswap.want_block(block(2).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(2).cid()).len(), 3);
+
+        // losing the only peer of the session falls back to the other peers
+        bitswap.inject_disconnected(&peer(1));
+        let mut expected = vec![peer(0), peer(2)];
+        expected.sort_by_key(|peer| peer.to_string());
+        assert_eq!(wanted_from(&mut bitswap, block(1).cid()), expected);
+    }
+
+    #[test]
+    fn session_starts_with_the_first_want() {
+        let mut bitswap = Bitswap::default();
+        for i in 0..2 {
+            bitswap.inject_connected(&peer(i));
+        }
+
+        let session = SessionId::new();
+        bitswap.want_block_in_session(session, block(0).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(0).cid()).len(), 2);
+
+        let mut message = Message::default();
+        message.add_block(block(0));
+        bitswap.inject_event(peer(0), ConnectionId::new(0), MessageWrapper::Rx(message));
+
+        bitswap.want_block_in_session(session, block(1).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(1).cid()), vec![peer(0)]);
+
+        // the other peers are asked as well once the session is broadcast
+        bitswap.broadcast_session(session);
+        assert_eq!(wanted_from(&mut bitswap, block(1).cid()).len(), 2);
+    }
+
+    #[test]
+    fn session_broadcasts_are_limited() {
+        let mut bitswap = Bitswap::default();
+        for i in 0..2 {
+            bitswap.inject_connected(&peer(i));
+        }
+
+        // the wants of a session without peers were already sent to everyone
+        let session = bitswap.new_session();
+        bitswap.want_block_in_session(session, block(0).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(0).cid()).len(), 2);
+        bitswap.broadcast_session(session);
+        assert!(wanted_from(&mut bitswap, block(0).cid()).is_empty());
+
+        let mut message = Message::default();
+        message.add_block(block(0));
+        bitswap.inject_event(peer(0), ConnectionId::new(0), MessageWrapper::Rx(message));
+
+        bitswap.want_block_in_session(session, block(1).cid().to_owned(), 1);
+        assert_eq!(wanted_from(&mut bitswap, block(1).cid()), vec![peer(0)]);
+
+        // the many fetches of the session waiting for their blocks broadcast only once
+        for _ in 0..8 {
+            bitswap.broadcast_session(session);
+        }
+        assert_eq!(wanted_from(&mut bitswap, block(1).cid()).len(), 2);
+    }
+
+    #[test]
+    fn session_broadcasts_back_off() {
+        use crate::session::Session;
+        use std::time::{Duration, Instant};
+
+        let mut session = Session::default();
+        session.want(block(0).cid().to_owned());
+        assert!(session.received(&peer(0), block(0).cid()));
+        session.want(block(1).cid().to_owned());
+
+        let start = Instant::now();
+        let after = |secs| start + Duration::from_secs(secs);
+
+        assert!(session.broadcast(start));
+        assert!(!session.broadcast(after(1)));
+        assert!(session.broadcast(after(2)));
+        assert!(!session.broadcast(after(5)));
+        assert!(session.broadcast(after(6)));
+
+        // receiving a block of the session resets the backoff
+        session.want(block(2).cid().to_owned());
+        assert!(session.received(&peer(1), block(2).cid()));
+        assert!(session.broadcast(after(7)));
     }
 }
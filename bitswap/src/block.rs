@@ -1,6 +1,8 @@
+use bytes::Bytes;
 use cid::Cid;
 
-/// An Ipfs block consisting of a [`Cid`] and the bytes of the block.
+/// An Ipfs block consisting of a [`Cid`] and the bytes of the block. The bytes are reference
+/// counted, so cloning the block, such as for sending it to many peers, does not copy them.
 ///
 /// Note: At the moment the equality is based on [`Cid`] equality, which is based on the triple
 /// `(cid::Version, cid::Codec, multihash)`.
@@ -9,7 +11,7 @@ pub struct Block {
     /// The content identifier for this block
     pub cid: Cid,
     /// The data of this block
-    pub data: Box<[u8]>,
+    pub data: Bytes,
 }
 
 impl PartialEq for Block {
@@ -21,8 +23,11 @@ impl PartialEq for Block {
 impl Eq for Block {}
 
 impl Block {
-    pub fn new(data: Box<[u8]>, cid: Cid) -> Self {
-        Self { cid, data }
+    pub fn new(data: impl Into<Bytes>, cid: Cid) -> Self {
+        Self {
+            cid,
+            data: data.into(),
+        }
     }
 
     pub fn cid(&self) -> &Cid {
@@ -33,7 +38,8 @@ impl Block {
         &self.data
     }
 
+    /// Copies the bytes into a `Vec`.
     pub fn into_vec(self) -> Vec<u8> {
-        self.data.into()
+        self.data.to_vec()
     }
 }
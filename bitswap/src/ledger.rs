@@ -2,13 +2,13 @@ use crate::bitswap_pb;
 use crate::block::Block;
 use crate::error::BitswapError;
 use crate::prefix::Prefix;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use cid::Cid;
 use core::convert::TryFrom;
-use prost::Message as ProstMessage;
-use std::{
-    collections::{HashMap, HashSet},
-    mem,
-};
+use fnv::{FnvHashMap, FnvHashSet};
+use prost::encoding::{self, WireType};
+use prost::{DecodeError, Message as ProstMessage};
+use std::mem;
 
 pub type Priority = i32;
 
@@ -16,9 +16,9 @@ pub type Priority = i32;
 #[derive(Debug, Default)]
 pub struct Ledger {
     /// The list of wanted blocks sent to the peer.
-    sent_want_list: HashMap<Cid, Priority>,
+    sent_want_list: FnvHashMap<Cid, Priority>,
     /// The list of wanted blocks received from the peer.
-    pub(crate) received_want_list: HashMap<Cid, Priority>,
+    pub(crate) received_want_list: FnvHashMap<Cid, Priority>,
     /// Queued message.
     message: Message,
 }
@@ -41,6 +41,11 @@ impl Ledger {
         self.message.cancel_block(cid);
     }
 
+    /// Returns true if either side has blocks wanted from the other.
+    pub fn is_active(&self) -> bool {
+        !self.sent_want_list.is_empty() || !self.received_want_list.is_empty()
+    }
+
     /// Returns the blocks wanted by the peer in unspecified order
     pub fn wantlist(&self) -> Vec<(Cid, Priority)> {
         self.received_want_list
@@ -69,9 +74,9 @@ impl Ledger {
 #[derive(Clone, PartialEq, Default)]
 pub struct Message {
     /// List of wanted blocks.
-    want: HashMap<Cid, Priority>,
+    want: FnvHashMap<Cid, Priority>,
     /// List of blocks to cancel.
-    cancel: HashSet<Cid>,
+    cancel: FnvHashSet<Cid>,
     /// Wheather it is the full list of wanted blocks.
     full: bool,
     /// List of blocks to send.
@@ -90,12 +95,12 @@ impl Message {
     }
 
     /// Returns the list of wanted blocks.
-    pub fn want(&self) -> &HashMap<Cid, Priority> {
+    pub fn want(&self) -> &FnvHashMap<Cid, Priority> {
         &self.want
     }
 
     /// Returns the list of cancelled blocks.
-    pub fn cancel(&self) -> &HashSet<Cid> {
+    pub fn cancel(&self) -> &FnvHashSet<Cid> {
         &self.cancel
     }
 
@@ -126,9 +131,14 @@ impl Message {
     }
 }
 
-impl Into<Vec<u8>> for &Message {
-    fn into(self) -> Vec<u8> {
-        let mut proto = bitswap_pb::Message::default();
+impl Message {
+    /// Encodes the message into the parts to be written out in order. The data of the blocks is
+    /// not copied, but the parts refer to the `Bytes` of the blocks in between the small parts
+    /// holding the rest of the message.
+    pub(crate) fn encode_parts(&self) -> Vec<Bytes> {
+        let mut parts = Vec::with_capacity(1 + 2 * self.blocks.len());
+        let mut header = BytesMut::new();
+
         let mut wantlist = bitswap_pb::message::Wantlist::default();
         for (cid, priority) in self.want() {
             let mut entry = bitswap_pb::message::wantlist::Entry::default();
@@ -142,33 +152,124 @@ impl Into<Vec<u8>> for &Message {
             entry.cancel = true;
             wantlist.entries.push(entry);
         }
+        if !wantlist.entries.is_empty() {
+            encoding::message::encode(WANTLIST, &wantlist, &mut header);
+        }
+
+        // the payload is written out by hand as the generated bitswap_pb::message::Block would
+        // need the data to be copied into a Vec
         for block in self.blocks() {
-            let mut payload = bitswap_pb::message::Block::default();
-            payload.prefix = Prefix::from(block.cid()).to_bytes();
-            payload.data = block.data().to_vec();
-            proto.payload.push(payload);
+            let prefix = Prefix::from(block.cid()).to_bytes();
+
+            encoding::encode_key(PAYLOAD, WireType::LengthDelimited, &mut header);
+            encoding::encode_varint(
+                (field_len(PAYLOAD_PREFIX, prefix.len())
+                    + field_len(PAYLOAD_DATA, block.data.len())) as u64,
+                &mut header,
+            );
+
+            encoding::encode_key(PAYLOAD_PREFIX, WireType::LengthDelimited, &mut header);
+            encoding::encode_varint(prefix.len() as u64, &mut header);
+            header.put_slice(&prefix);
+
+            if !block.data.is_empty() {
+                encoding::encode_key(PAYLOAD_DATA, WireType::LengthDelimited, &mut header);
+                encoding::encode_varint(block.data.len() as u64, &mut header);
+                parts.push(header.split().freeze());
+                parts.push(block.data.clone());
+            }
         }
-        if !wantlist.entries.is_empty() {
-            proto.wantlist = Some(wantlist);
+
+        if !header.is_empty() {
+            parts.push(header.freeze());
         }
-        let mut res = Vec::with_capacity(proto.encoded_len());
-        proto
-            .encode(&mut res)
-            .expect("there is no situation in which the protobuf message can be invalid");
-        res
+
+        parts
     }
-}
 
-impl Message {
     /// Turns this `Message` into a message that can be sent to a substream.
     pub fn to_bytes(&self) -> Vec<u8> {
-        self.into()
+        let parts = self.encode_parts();
+        let mut res = Vec::with_capacity(parts.iter().map(Bytes::len).sum());
+        for part in parts {
+            res.extend_from_slice(&part);
+        }
+        res
+    }
+
+    /// Creates a `Message` from bytes that were received from a substream. The data of the
+    /// blocks refers to the received bytes instead of being copied out of them.
+    pub fn from_bytes(bytes: impl Into<Bytes>) -> Result<Self, BitswapError> {
+        Self::try_from(bytes.into())
+    }
+}
+
+/// The tags of the fields of `bitswap_pb::Message` and `bitswap_pb::message::Block`.
+const WANTLIST: u32 = 1;
+const PAYLOAD: u32 = 3;
+const PAYLOAD_PREFIX: u32 = 1;
+const PAYLOAD_DATA: u32 = 2;
+
+/// The encoded length of a `bytes` field of the given length, which is omitted when empty.
+fn field_len(tag: u32, len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        encoding::key_len(tag) + encoding::encoded_len_varint(len as u64) + len
+    }
+}
+
+/// Splits off the length delimited value at the front of the buffer without copying.
+fn split_length_delimited(buf: &mut Bytes) -> Result<Bytes, DecodeError> {
+    let len = encoding::decode_varint(buf)?;
+    if len > buf.remaining() as u64 {
+        return Err(DecodeError::new("buffer underflow"));
+    }
+    Ok(buf.split_to(len as usize))
+}
+
+/// Skips over a field not needed from the message.
+fn skip_field(wire_type: WireType, buf: &mut Bytes) -> Result<(), DecodeError> {
+    let len = match wire_type {
+        WireType::Varint => {
+            encoding::decode_varint(buf)?;
+            0
+        }
+        WireType::SixtyFourBit => 8,
+        WireType::ThirtyTwoBit => 4,
+        WireType::LengthDelimited => {
+            split_length_delimited(buf)?;
+            0
+        }
+        WireType::StartGroup | WireType::EndGroup => {
+            return Err(DecodeError::new("groups are not supported"))
+        }
+    };
+
+    if len > buf.remaining() {
+        return Err(DecodeError::new("buffer underflow"));
     }
+    buf.advance(len);
+    Ok(())
+}
+
+/// Decodes a `bitswap_pb::message::Block` into the prefix and the data of the block, of which
+/// the data refers to the buffer.
+fn decode_payload(mut buf: Bytes) -> Result<(Bytes, Bytes), DecodeError> {
+    let mut prefix = Bytes::new();
+    let mut data = Bytes::new();
 
-    /// Creates a `Message` from bytes that were received from a substream.
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BitswapError> {
-        Self::try_from(bytes)
+    while buf.has_remaining() {
+        match encoding::decode_key(&mut buf)? {
+            (PAYLOAD_PREFIX, WireType::LengthDelimited) => {
+                prefix = split_length_delimited(&mut buf)?
+            }
+            (PAYLOAD_DATA, WireType::LengthDelimited) => data = split_length_delimited(&mut buf)?,
+            (_, wire_type) => skip_field(wire_type, &mut buf)?,
+        }
     }
+
+    Ok((prefix, data))
 }
 
 impl From<()> for Message {
@@ -177,32 +278,44 @@ impl From<()> for Message {
     }
 }
 
-impl TryFrom<&[u8]> for Message {
+impl TryFrom<Bytes> for Message {
     type Error = BitswapError;
-    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let proto: bitswap_pb::Message = bitswap_pb::Message::decode(bytes)?;
+    fn try_from(mut bytes: Bytes) -> Result<Self, Self::Error> {
         let mut message = Message::default();
-        for entry in proto.wantlist.unwrap_or_default().entries {
-            let cid = Cid::try_from(entry.block)?;
-            if entry.cancel {
-                message.cancel_block(&cid);
-            } else {
-                message.want_block(&cid, entry.priority);
+        while bytes.has_remaining() {
+            match encoding::decode_key(&mut bytes)? {
+                (WANTLIST, WireType::LengthDelimited) => {
+                    let wantlist = split_length_delimited(&mut bytes)?;
+                    let wantlist = bitswap_pb::message::Wantlist::decode(wantlist)?;
+                    for entry in wantlist.entries {
+                        let cid = Cid::try_from(entry.block)?;
+                        if entry.cancel {
+                            message.cancel_block(&cid);
+                        } else {
+                            message.want_block(&cid, entry.priority);
+                        }
+                    }
+                }
+                (PAYLOAD, WireType::LengthDelimited) => {
+                    let (prefix, data) = decode_payload(split_length_delimited(&mut bytes)?)?;
+                    let prefix = Prefix::new(&prefix)?;
+                    let cid = prefix.to_cid(&data)?;
+                    message.add_block(Block { cid, data });
+                }
+                (_, wire_type) => skip_field(wire_type, &mut bytes)?,
             }
         }
-        for payload in proto.payload {
-            let prefix = Prefix::new(&payload.prefix)?;
-            let cid = prefix.to_cid(&payload.data)?;
-            let block = Block {
-                cid,
-                data: payload.data.into_boxed_slice(),
-            };
-            message.add_block(block);
-        }
         Ok(message)
     }
 }
 
+impl TryFrom<&[u8]> for Message {
+    type Error = BitswapError;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::try_from(Bytes::copy_from_slice(bytes))
+    }
+}
+
 impl std::fmt::Debug for Message {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         let mut first = true;
@@ -238,3 +351,66 @@ impl std::fmt::Debug for Message {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{bitswap_pb, Message};
+    use crate::block::Block;
+    use crate::prefix::Prefix;
+    use cid::{Cid, Codec};
+    use multihash::Sha2_256;
+    use prost::Message as ProstMessage;
+
+    fn block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(data));
+        Block::new(data.to_vec(), cid)
+    }
+
+    #[test]
+    fn encoded_as_the_generated_message() {
+        let mut message = Message::default();
+        message.want_block(block(b"wanted").cid(), 3);
+        message.add_block(block(b"block"));
+        message.add_block(block(b""));
+
+        let mut proto = bitswap_pb::Message::default();
+        let mut wantlist = bitswap_pb::message::Wantlist::default();
+        let mut entry = bitswap_pb::message::wantlist::Entry::default();
+        entry.block = block(b"wanted").cid().to_bytes();
+        entry.priority = 3;
+        wantlist.entries.push(entry);
+        proto.wantlist = Some(wantlist);
+        for block in message.blocks() {
+            let mut payload = bitswap_pb::message::Block::default();
+            payload.prefix = Prefix::from(block.cid()).to_bytes();
+            payload.data = block.data().to_vec();
+            proto.payload.push(payload);
+        }
+
+        let mut expected = Vec::new();
+        proto.encode(&mut expected).unwrap();
+
+        assert_eq!(message.to_bytes(), expected);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut message = Message::default();
+        message.want_block(block(b"wanted").cid(), 1);
+        message.cancel_block(block(b"cancelled").cid());
+        message.add_block(block(b"first"));
+        message.add_block(block(b""));
+        message.add_block(block(&[7; 1024]));
+
+        let bytes = bytes::Bytes::from(message.to_bytes());
+        let decoded = Message::from_bytes(bytes.clone()).unwrap();
+
+        assert_eq!(decoded, message);
+
+        // the data of the decoded block refers to the received bytes
+        let data = decoded.blocks()[2].data();
+        let received = bytes.as_ptr() as usize..bytes.as_ptr() as usize + bytes.len();
+        assert!(received.contains(&(data.as_ptr() as usize)));
+        assert_eq!(data, &[7; 1024][..]);
+    }
+}
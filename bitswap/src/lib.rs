@@ -8,11 +8,14 @@ mod error;
 mod ledger;
 mod prefix;
 mod protocol;
+mod session;
 
 pub use self::behaviour::{Bitswap, BitswapEvent, Stats};
 pub use self::block::Block;
 pub use self::error::BitswapError;
-pub use self::ledger::Priority;
+pub use self::ledger::{Message, Priority};
+pub use self::protocol::MessageWrapper;
+pub use self::session::SessionId;
 
 mod bitswap_pb {
     include!(concat!(env!("OUT_DIR"), "/bitswap_pb.rs"));
@@ -8,7 +8,7 @@ use crate::ledger::Message;
 use core::future::Future;
 use core::iter;
 use core::pin::Pin;
-use futures::io::{AsyncRead, AsyncWrite};
+use futures::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use libp2p_core::{upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use std::io;
 
@@ -43,7 +43,8 @@ where
     fn upgrade_inbound(self, mut socket: TSocket, _info: Self::Info) -> Self::Future {
         Box::pin(async move {
             let packet = upgrade::read_one(&mut socket, MAX_BUF_SIZE).await?;
-            let message = Message::from_bytes(&packet)?;
+            // the blocks of the message refer to the packet instead of being copied out of it
+            let message = Message::from_bytes(packet)?;
             Ok(message)
         })
     }
@@ -70,8 +71,20 @@ where
     #[inline]
     fn upgrade_outbound(self, mut socket: TSocket, _info: Self::Info) -> Self::Future {
         Box::pin(async move {
-            let bytes = self.to_bytes();
-            upgrade::write_one(&mut socket, bytes).await
+            // framed the same as upgrade::write_one, but the data of the blocks is written out
+            // directly from the blocks instead of being copied into a single buffer first
+            let parts = self.encode_parts();
+            let len = parts.iter().map(|part| part.len()).sum();
+
+            let mut buf = unsigned_varint::encode::usize_buffer();
+            socket
+                .write_all(unsigned_varint::encode::usize(len, &mut buf))
+                .await?;
+            for part in parts {
+                socket.write_all(&part).await?;
+            }
+
+            socket.close().await
         })
     }
 }
@@ -0,0 +1,113 @@
+//! Sessions group the wants of correlated blocks, such as the blocks of a single DAG, so that
+//! the peers which have answered earlier wants of the session are asked first for the later
+//! ones instead of broadcasting every want to all of the peers.
+use cid::Cid;
+use fnv::FnvHashSet;
+use libp2p_core::PeerId;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The id of the next session, shared by all of the behaviours so that the ids can be created
+/// before the session is first used.
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
+/// The least time between two broadcasts of the outstanding wants of a session, doubled with each
+/// broadcast until a block wanted in the session is received.
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The most time between two broadcasts of the outstanding wants of a session.
+const MAX_BROADCAST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Identifies a session, which is started by the first want made in it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SessionId(u64);
+
+impl SessionId {
+    /// Returns an id not used by any earlier session.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        SessionId(NEXT_SESSION.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The state of a single session.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    /// The connected peers which have sent blocks wanted in this session, in the order they
+    /// first did.
+    peers: Vec<PeerId>,
+    /// The blocks wanted in this session which have not been received yet.
+    wants: FnvHashSet<Cid>,
+    /// When the outstanding wants were last broadcast.
+    last_broadcast: Option<Instant>,
+    /// The number of broadcasts since a block wanted in this session was last received.
+    broadcasts: u32,
+}
+
+impl Session {
+    /// Returns the peers to target with the wants of the session; empty when the wants need to
+    /// be broadcast.
+    pub(crate) fn peers(&self) -> &[PeerId] {
+        &self.peers
+    }
+
+    /// Returns the outstanding wants of the session.
+    pub(crate) fn wants(&self) -> impl Iterator<Item = &Cid> {
+        self.wants.iter()
+    }
+
+    pub(crate) fn want(&mut self, cid: Cid) {
+        self.wants.insert(cid);
+    }
+
+    pub(crate) fn cancel(&mut self, cid: &Cid) {
+        self.wants.remove(cid);
+    }
+
+    /// Records the block received from the peer, returning `true` if it was wanted in this
+    /// session.
+    pub(crate) fn received(&mut self, peer: &PeerId, cid: &Cid) -> bool {
+        if !self.wants.remove(cid) {
+            return false;
+        }
+
+        if !self.peers.contains(peer) {
+            self.peers.push(peer.clone());
+        }
+
+        self.broadcasts = 0;
+        true
+    }
+
+    /// Returns `true` if the outstanding wants are to be broadcast at `now`, recording the
+    /// broadcast. The wants of a session without peers have already been sent to all of the
+    /// peers, and the others are broadcast at most once per [`BROADCAST_INTERVAL`], backing off
+    /// with each broadcast.
+    pub(crate) fn broadcast(&mut self, now: Instant) -> bool {
+        if self.peers.is_empty() || self.wants.is_empty() {
+            return false;
+        }
+
+        if let Some(last) = self.last_broadcast {
+            let interval = BROADCAST_INTERVAL
+                .checked_mul(1u32.checked_shl(self.broadcasts).unwrap_or(u32::MAX))
+                .map(|interval| interval.min(MAX_BROADCAST_INTERVAL))
+                .unwrap_or(MAX_BROADCAST_INTERVAL);
+
+            if now.saturating_duration_since(last) < interval {
+                return false;
+            }
+        }
+
+        self.last_broadcast = Some(now);
+        self.broadcasts = self.broadcasts.saturating_add(1);
+        true
+    }
+
+    /// Forgets the disconnected peer, returning `true` if it was one of the session peers.
+    pub(crate) fn disconnected(&mut self, peer: &PeerId) -> bool {
+        let before = self.peers.len();
+        self.peers.retain(|p| p != peer);
+        self.peers.len() != before
+    }
+}
@@ -0,0 +1,359 @@
+//! Client of the `/api/v0` HTTP API of a remote go-ipfs or rust-ipfs daemon, implementing
+//! [`CoreApi`] so that it can be used in place of an embedded [`ipfs::Ipfs`].
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use cid::{Cid, Codec, Version};
+use hyper::client::HttpConnector;
+use hyper::{Body, Request, StatusCode};
+use ipfs::api::CoreApi;
+use ipfs::error::Error;
+use ipfs::{Block, IpfsPath, PeerId};
+use multihash::Code;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use url::Url;
+
+/// Client of the HTTP API at an address such as `http://127.0.0.1:5001`, or at a unix socket.
+#[derive(Debug, Clone)]
+pub struct Client {
+    base: Url,
+    client: Inner,
+}
+
+#[derive(Debug, Clone)]
+enum Inner {
+    Tcp(hyper::Client<HttpConnector>),
+    #[cfg(unix)]
+    Unix(hyper::Client<unix::UnixConnector>),
+}
+
+impl Client {
+    /// Creates a client for the API at the `base` address, under which the `/api/v0` endpoints
+    /// are.
+    pub fn new(base: &str) -> Result<Self, url::ParseError> {
+        let mut base = Url::parse(base)?;
+
+        // without the trailing slash joining the endpoints would replace the last segment
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+
+        Ok(Client {
+            base,
+            client: Inner::Tcp(hyper::Client::new()),
+        })
+    }
+
+    /// Creates a client for the API listening on the unix socket at the `path`.
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<std::path::PathBuf>) -> Self {
+        let connector = unix::UnixConnector::new(path.into());
+
+        Client {
+            // the host is not used for connecting
+            base: Url::parse("http://localhost/").expect("valid url"),
+            client: Inner::Unix(hyper::Client::builder().build(connector)),
+        }
+    }
+
+    /// Makes the request to the endpoint, such as `block/get`, with the given query parameters,
+    /// and the optional file as the multipart body. Returns the body of a successful response.
+    async fn post(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+        file: Option<&[u8]>,
+    ) -> Result<Bytes, Error> {
+        let mut url = self.base.join("api/v0/")?.join(endpoint)?;
+        url.query_pairs_mut().extend_pairs(query);
+
+        let request = Request::post(url.as_str());
+
+        let request = match file {
+            Some(data) => {
+                let (boundary, body) = multipart(data);
+                request
+                    .header(
+                        "content-type",
+                        format!("multipart/form-data; boundary={}", boundary),
+                    )
+                    .body(Body::from(body))?
+            }
+            None => request.body(Body::empty())?,
+        };
+
+        let response = match &self.client {
+            Inner::Tcp(client) => client.request(request).await?,
+            #[cfg(unix)]
+            Inner::Unix(client) => client.request(request).await?,
+        };
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+
+        if status != StatusCode::OK {
+            return Err(match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(error) => anyhow::anyhow!("{} failed: {}", endpoint, error.message),
+                Err(_) => anyhow::anyhow!("{} failed with {}", endpoint, status),
+            });
+        }
+
+        Ok(body)
+    }
+
+    async fn post_json<R: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+        file: Option<&[u8]>,
+    ) -> Result<R, Error> {
+        let body = self.post(endpoint, query, file).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+#[async_trait]
+impl CoreApi for Client {
+    async fn peer_id(&self) -> Result<PeerId, Error> {
+        let id: IdResponse = self.post_json("id", &[], None).await?;
+        id.id
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid peer id: {:?}", id.id))
+    }
+
+    async fn put_block(&self, block: Block) -> Result<Cid, Error> {
+        // go-ipfs has no version parameter, and only the "v0" format gives a CIDv0
+        let format = match block.cid.codec() {
+            Codec::DagProtobuf if block.cid.version() == Version::V0 => "v0",
+            Codec::DagProtobuf => "protobuf",
+            Codec::DagCBOR => "dag-cbor",
+            Codec::DagJSON => "dag-json",
+            Codec::Raw => "raw",
+            other => return Err(anyhow::anyhow!("unsupported codec: {:?}", other)),
+        };
+
+        let mhtype = match block.cid.hash().algorithm() {
+            Code::Sha2_256 => "sha2-256",
+            Code::Sha2_512 => "sha2-512",
+            other => return Err(anyhow::anyhow!("unsupported hash: {:?}", other)),
+        };
+
+        let put: PutResponse = self
+            .post_json(
+                "block/put",
+                &[("format", format), ("mhtype", mhtype)],
+                Some(&block.data),
+            )
+            .await?;
+
+        // the remote computes the cid from the parameters, which should lead to the same cid
+        if put.key != block.cid.to_string() {
+            return Err(anyhow::anyhow!(
+                "block {} was stored as {}",
+                block.cid,
+                put.key
+            ));
+        }
+
+        Ok(block.cid)
+    }
+
+    async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        let data = self
+            .post("block/get", &[("arg", cid.to_string().as_str())], None)
+            .await?;
+
+        // the remote is not trusted to return the right block
+        let hash = cid.hash().algorithm().digest(&data);
+        if hash.as_ref() != cid.hash() {
+            return Err(anyhow::anyhow!("the data of block {} does not match", cid));
+        }
+
+        Ok(Block::new(data, cid.to_owned()))
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error> {
+        let body = self.post("add", &[], Some(&data)).await?;
+
+        // the response has a line for each added file, the last one being the root
+        let last = body
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("add returned no files"))?;
+
+        let added: AddResponse = serde_json::from_slice(last)?;
+        Ok(added.hash.parse()?)
+    }
+
+    async fn cat(&self, path: &IpfsPath) -> Result<Vec<u8>, Error> {
+        let data = self
+            .post("cat", &[("arg", path.to_string().as_str())], None)
+            .await?;
+        Ok(data.to_vec())
+    }
+}
+
+/// Encodes the data as the only file of a `multipart/form-data` body, returning the boundary and
+/// the body.
+fn multipart(data: &[u8]) -> (String, Vec<u8>) {
+    // the boundary cannot appear in the data, which the hash of the data practically guarantees
+    let boundary = format!(
+        "rust-ipfs-{}",
+        multibase::encode(
+            multibase::Base::Base32Lower,
+            multihash::Sha2_256::digest(data).digest()
+        )
+    );
+
+    let mut body = Vec::with_capacity(data.len() + 2 * boundary.len() + 128);
+    body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+    body.extend_from_slice(
+        b"Content-Disposition: form-data; name=\"file\"; filename=\"file\"\r\n\
+          Content-Type: application/octet-stream\r\n\r\n",
+    );
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    (boundary, body)
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdResponse {
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PutResponse {
+    #[serde(rename = "Key")]
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+#[cfg(unix)]
+mod unix {
+    use futures::future::BoxFuture;
+    use hyper::client::connect::{Connected, Connection};
+    use hyper::service::Service;
+    use hyper::Uri;
+    use std::io;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::net::UnixStream;
+
+    /// Connects every request to the unix socket, whatever the host of the url.
+    #[derive(Debug, Clone)]
+    pub(super) struct UnixConnector(Arc<PathBuf>);
+
+    impl UnixConnector {
+        pub(super) fn new(path: PathBuf) -> Self {
+            UnixConnector(Arc::new(path))
+        }
+    }
+
+    impl Service<Uri> for UnixConnector {
+        type Response = UnixConnection;
+        type Error = io::Error;
+        type Future = BoxFuture<'static, io::Result<UnixConnection>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            let path = Arc::clone(&self.0);
+            Box::pin(async move { UnixStream::connect(&*path).await.map(UnixConnection) })
+        }
+    }
+
+    /// The stream of a connection, as hyper requires the [`Connection`] to be implemented.
+    pub(super) struct UnixConnection(UnixStream);
+
+    impl Connection for UnixConnection {
+        fn connected(&self) -> Connected {
+            Connected::new()
+        }
+    }
+
+    impl AsyncRead for UnixConnection {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for UnixConnection {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use cid::{Cid, Codec};
+    use ipfs::api::CoreApi;
+    use ipfs::path::PathRoot;
+    use ipfs::{Block, IpfsPath, Node};
+
+    #[tokio::test(max_threads = 1)]
+    async fn same_results_as_the_embedded_node() {
+        let ipfs = Node::new("test_node").await;
+
+        let (shutdown_tx, _) = tokio::sync::mpsc::channel::<()>(1);
+        let routes = crate::v0::routes(&*ipfs, shutdown_tx, &Default::default(), None);
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let client = Client::new(&format!("http://{}", addr)).unwrap();
+
+        assert_eq!(client.peer_id().await.unwrap(), ipfs.id);
+
+        let root = client.add(b"foobar\n".to_vec()).await.unwrap();
+        let embedded = CoreApi::add(&*ipfs, b"foobar\n".to_vec()).await.unwrap();
+        assert_eq!(root, embedded);
+
+        let path = IpfsPath::new(PathRoot::Ipld(root.clone()));
+        assert_eq!(client.cat(&path).await.unwrap(), b"foobar\n");
+
+        let block = client.get_block(&root).await.unwrap();
+        assert_eq!(block.cid, root);
+        assert_eq!(client.put_block(block.clone()).await.unwrap(), root);
+
+        let v1 = Cid::new_v1(Codec::DagProtobuf, root.hash().to_owned());
+        let block = Block::new(block.data, v1.clone());
+        assert_eq!(client.put_block(block).await.unwrap(), v1);
+    }
+}
@@ -1,8 +1,10 @@
 //! go-ipfs compatible configuration file handling and setup.
 
-use parity_multiaddr::{multiaddr, Multiaddr};
-use serde::{Deserialize, Serialize};
+use parity_multiaddr::{multiaddr, Multiaddr, Protocol};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::fs::{self, File};
+use std::net::IpAddr;
 use std::num::NonZeroU16;
 use std::path::Path;
 use std::str::FromStr;
@@ -120,6 +122,11 @@ pub fn init(
         Profile::Default => multiaddr!(Ip4([127, 0, 0, 1]), Tcp(4004u16)),
     };
 
+    let gateway_addr = match profiles[0] {
+        Profile::Test => None,
+        Profile::Default => Some(multiaddr!(Ip4([127, 0, 0, 1]), Tcp(8080u16))),
+    };
+
     let config_contents = CompatibleConfigFile {
         identity: Identity {
             peer_id: peer_id.clone(),
@@ -128,7 +135,10 @@ pub fn init(
         addresses: Addresses {
             swarm: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
             api: api_addr,
+            gateway: gateway_addr,
         },
+        gateway: None,
+        api: None,
     };
 
     let config_path = ipfs_path.join("config");
@@ -161,8 +171,22 @@ pub struct Config {
     pub keypair: ipfs::Keypair,
     /// Peer addresses for the ipfs node.
     pub swarm: Vec<Multiaddr>,
-    /// Address to run the API daemon on.
+    /// Address to run the API daemon on, either TCP or an `/unix/` socket path.
     pub api_addr: Multiaddr,
+    /// Address to run the HTTP gateway on, if any, either TCP or an `/unix/` socket path.
+    pub gateway_addr: Option<Multiaddr>,
+    /// Host of the gateway for serving the content from its subdomains, if any.
+    pub gateway_subdomain_host: Option<String>,
+    /// Allow adding content through the gateway.
+    pub gateway_writable: bool,
+    /// Serve the content of the DNSLink records of the request hosts.
+    pub gateway_dnslink: bool,
+    /// The CORS configuration and the allowed remote addresses of the API.
+    pub api_access: crate::v0::AccessOptions,
+    /// Serve the Prometheus metrics at `/metrics` of the API address.
+    pub api_metrics: bool,
+    /// The peers needed in the DHT routing table before `/readyz` reports the node ready.
+    pub api_min_routing_table_peers: usize,
 }
 
 /// Things which can go wrong when loading a `go-ipfs` compatible configuration file.
@@ -178,6 +202,10 @@ pub enum LoadingError {
     UnsupportedPrivateKeyType(i32),
     #[error("loaded PeerId {loaded:?} is not the same as in configuration file {stored:?}, this is likely a bug in rust-ipfs-http")]
     PeerIdMismatch { loaded: String, stored: String },
+    #[error("invalid value in API.HTTPHeaders.{0}: {1:?}")]
+    InvalidApiHeaderValue(&'static str, String),
+    #[error("empty token in API.Tokens")]
+    EmptyApiToken,
 }
 
 /// Loads a `go-ipfs` compatible configuration file from the given file.
@@ -202,10 +230,23 @@ pub fn load(config: File) -> Result<Config, LoadingError> {
         });
     }
 
+    let gateway = config_file.gateway.unwrap_or_default();
+    let api = config_file.api.unwrap_or_default();
+    let api_metrics = api.metrics;
+    let api_min_routing_table_peers = api.min_routing_table_peers;
+    let api_access = api.access_options()?;
+
     let config = Config {
         keypair: kp,
         swarm: config_file.addresses.swarm,
         api_addr: config_file.addresses.api,
+        gateway_addr: config_file.addresses.gateway,
+        gateway_subdomain_host: gateway.subdomain_host,
+        gateway_writable: gateway.writable,
+        gateway_dnslink: !gateway.no_dnslink,
+        api_access,
+        api_metrics,
+        api_min_routing_table_peers,
     };
 
     Ok(config)
@@ -280,14 +321,159 @@ fn pem_to_der(bytes: &[u8]) -> Vec<u8> {
 struct CompatibleConfigFile {
     identity: Identity,
     addresses: Addresses,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gateway: Option<Gateway>,
+    #[serde(rename = "API", default, skip_serializing_if = "Option::is_none")]
+    api: Option<Api>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct Addresses {
     swarm: Vec<Multiaddr>,
-    #[serde(rename = "API")]
+    #[serde(rename = "API", deserialize_with = "deserialize_listening_addr")]
     api: Multiaddr,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_listening_addr",
+        skip_serializing_if = "Option::is_none"
+    )]
+    gateway: Option<Multiaddr>,
+}
+
+/// Parses the listening address of the API or the gateway. As in go-ipfs, the path of an
+/// `/unix/` address extends to the end of the address, and it is stored without the leading
+/// slash to keep the address displayed the same.
+fn parse_listening_addr(s: &str) -> Result<Multiaddr, parity_multiaddr::Error> {
+    match s.strip_prefix("/unix/") {
+        Some(path) => Ok(Multiaddr::empty().with(Protocol::Unix(path.to_owned().into()))),
+        None => s.parse(),
+    }
+}
+
+fn deserialize_listening_addr<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Multiaddr, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_listening_addr(&s).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_listening_addr<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Multiaddr>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_listening_addr(&s))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Gateway {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subdomain_host: Option<String>,
+    #[serde(default)]
+    writable: bool,
+    #[serde(rename = "NoDNSLink", default)]
+    no_dnslink: bool,
+}
+
+/// The `API` section, where `HTTPHeaders` is compatible with go-ipfs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Api {
+    #[serde(rename = "HTTPHeaders", default)]
+    http_headers: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_addresses: Vec<IpAddr>,
+    #[serde(default)]
+    metrics: bool,
+    #[serde(default)]
+    min_routing_table_peers: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tokens: Vec<ApiToken>,
+}
+
+/// A bearer token of the API, and the endpoints it allows.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiToken {
+    token: String,
+    scope: TokenScope,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum TokenScope {
+    ReadOnly,
+    Admin,
+}
+
+impl Api {
+    fn access_options(mut self) -> Result<crate::v0::AccessOptions, LoadingError> {
+        use warp::http::{header::HeaderName, Method};
+
+        const ORIGIN: &str = "Access-Control-Allow-Origin";
+        const METHODS: &str = "Access-Control-Allow-Methods";
+        const HEADERS: &str = "Access-Control-Allow-Headers";
+
+        use crate::v0::Scope;
+
+        if self
+            .tokens
+            .iter()
+            .any(|token| token.token.trim().is_empty())
+        {
+            return Err(LoadingError::EmptyApiToken);
+        }
+
+        let tokens = self
+            .tokens
+            .into_iter()
+            .map(|token| {
+                let scope = match token.scope {
+                    TokenScope::ReadOnly => Scope::ReadOnly,
+                    TokenScope::Admin => Scope::Admin,
+                };
+                (token.token, scope)
+            })
+            .collect();
+
+        let mut options = crate::v0::AccessOptions::default()
+            .with_allowed_addresses(self.allowed_addresses)
+            .with_tokens(tokens);
+
+        if let Some(origins) = self.http_headers.remove(ORIGIN) {
+            options = options.with_allowed_origins(origins);
+        }
+
+        if let Some(methods) = self.http_headers.remove(METHODS) {
+            let methods = methods
+                .into_iter()
+                .map(|method| {
+                    Method::from_bytes(method.as_bytes())
+                        .map_err(|_| LoadingError::InvalidApiHeaderValue(METHODS, method))
+                })
+                .collect::<Result<_, _>>()?;
+            options = options.with_allowed_methods(methods);
+        }
+
+        if let Some(headers) = self.http_headers.remove(HEADERS) {
+            let headers = headers
+                .into_iter()
+                .map(|header| {
+                    HeaderName::from_bytes(header.as_bytes())
+                        .map_err(|_| LoadingError::InvalidApiHeaderValue(HEADERS, header))
+                })
+                .collect::<Result<_, _>>()?;
+            options = options.with_allowed_headers(headers);
+        }
+
+        for name in self.http_headers.keys() {
+            warn!("unsupported header in API.HTTPHeaders: {}", name);
+        }
+
+        Ok(options)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -375,4 +561,19 @@ aGVsbG8gd29ybGQ=
 
         assert_eq!(peer_id, input.peer_id);
     }
+
+    #[test]
+    fn unix_socket_listening_addrs() {
+        use parity_multiaddr::Protocol;
+
+        let addr = super::parse_listening_addr("/unix/tmp/ipfs/api.sock").unwrap();
+        assert_eq!(
+            addr.iter().collect::<Vec<_>>(),
+            vec![Protocol::Unix("tmp/ipfs/api.sock".into())]
+        );
+        assert_eq!(addr.to_string(), "/unix/tmp/ipfs/api.sock");
+
+        let addr = super::parse_listening_addr("/ip4/127.0.0.1/tcp/5001").unwrap();
+        assert_eq!(addr.iter().count(), 2);
+    }
 }
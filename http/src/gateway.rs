@@ -0,0 +1,1899 @@
+//! Read-only HTTP gateway serving UnixFS content at `/ipfs/<cid>[/path]`, similar to the gateway
+//! of go-ipfs.
+//!
+//! Files are streamed as they are walked, with the content type guessed from the file name or
+//! from the first bytes of the content. Directories are served through their `index.html`,
+//! or as an HTML listing of their entries when there is none. The files carry their Cid as the
+//! `ETag`, so the clients revalidating with `If-None-Match` are answered with 304 Not Modified.
+//!
+//! With [`GatewayOptions::with_subdomains`] the content is also served from the subdomains, as in
+//! `<cidv1>.ipfs.example.com`, which gives each root its own origin in browsers. The path-style
+//! requests to the gateway host are then redirected to the subdomains.
+//!
+//! With [`GatewayOptions::with_writable`] new content can be added with `POST /ipfs/`, and files
+//! can be written into existing directories with `PUT /ipfs/<cid>/<path>`. As the content is
+//! immutable, these create new roots which are returned in the `Ipfs-Hash` header, and nothing
+//! can be deleted.
+//!
+//! With [`GatewayOptions::with_dnslink`] the requests to other hosts with a DNSLink record, as in
+//! `_dnslink.example.com TXT "dnslink=/ipfs/<cid>"`, are served from the linked content at `/`.
+//!
+//! As the subdomains and the DNSLink hosts give the websites origins of their own, the paths not
+//! found under them are handled with the rules of the `_redirects` file at the root, such as
+//! `/* /index.html 200` for single-page apps, or else with the nearest `404.html`.
+//!
+//! For the clients verifying the content themselves, the block at the end of the path is returned
+//! as is with `Accept: application/vnd.ipld.raw` or `?format=raw`, and the whole DAG as a CAR with
+//! `Accept: application/vnd.ipld.car` or `?format=car`.
+
+use crate::v0::support::with_ipfs;
+use bytes::{Buf, Bytes};
+use cid::{Cid, Codec};
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use ipfs::dag::{ResolveError, ResolvedNode};
+use ipfs::ipld::dag_pb::{PbLink, PbNode};
+use ipfs::path::PathRoot;
+use ipfs::unixfs::ll::stat::{stat_block, NodeType};
+use ipfs::unixfs::{add, AddOptions};
+use ipfs::{Block, Ipfs, IpfsPath, IpfsTypes};
+use multihash::Sha2_256;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::convert::{Infallible, TryFrom};
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use warp::http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode};
+use warp::hyper::Body;
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+mod car;
+mod redirects;
+
+/// Configures how the gateway resolves the requests.
+#[derive(Debug, Clone, Default)]
+pub struct GatewayOptions {
+    subdomain_host: Option<String>,
+    writable: bool,
+    dnslink: bool,
+}
+
+impl GatewayOptions {
+    /// Serves the content from the subdomains of `host`, as in `<cidv1>.ipfs.<host>`, and
+    /// redirects the path-style requests made to the `host` to the subdomains.
+    pub fn with_subdomains(self, host: impl Into<String>) -> Self {
+        GatewayOptions {
+            subdomain_host: Some(host.into().to_ascii_lowercase()),
+            ..self
+        }
+    }
+
+    /// When true, content can be added with `POST` and `PUT` requests.
+    pub fn with_writable(self, writable: bool) -> Self {
+        GatewayOptions { writable, ..self }
+    }
+
+    /// When true, the requests to the hosts with a DNSLink record are served from the linked
+    /// content, which allows hosting websites by pointing the domain at the gateway.
+    pub fn with_dnslink(self, dnslink: bool) -> Self {
+        GatewayOptions { dnslink, ..self }
+    }
+}
+
+/// The gateway routes, to be served separately from the `/api/v0` routes.
+pub fn routes<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    options: GatewayOptions,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let options = Arc::new(options);
+    let options = warp::any().map(move || Arc::clone(&options));
+
+    let read = warp::get()
+        .and(warp::path::full())
+        .and(warp::query::<GatewayQuery>())
+        .and(warp::header::headers_cloned())
+        .and(with_ipfs(ipfs))
+        .and(options.clone())
+        .and_then(serve_path);
+
+    let write = warp::post()
+        .or(warp::put())
+        .unify()
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and(with_ipfs(ipfs))
+        .and(options)
+        .and_then(write_path);
+
+    // any other methods such as DELETE are rejected with 405 Method Not Allowed
+    read.or(write).unify()
+}
+
+#[derive(Debug, Deserialize)]
+struct GatewayQuery {
+    format: Option<String>,
+}
+
+const RAW_CONTENT_TYPE: &str = "application/vnd.ipld.raw";
+const CAR_CONTENT_TYPE: &str = "application/vnd.ipld.car";
+
+/// The verifiable response formats, instead of the decoded UnixFS content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    /// The block at the end of the path.
+    Raw,
+    /// The DAG rooted at the end of the path as a CAR v1.
+    Car,
+}
+
+/// The format requested with the `format` query parameter, which takes precedence, or with the
+/// `Accept` header.
+fn requested_format(
+    query: &GatewayQuery,
+    headers: &HeaderMap,
+) -> Result<Option<Format>, GatewayError> {
+    match query.format.as_deref() {
+        Some("raw") => return Ok(Some(Format::Raw)),
+        Some("car") => return Ok(Some(Format::Car)),
+        Some(other) => return Err(bad_request(format!("unsupported format: {:?}", other))),
+        None => {}
+    }
+
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    Ok(accept
+        .split(',')
+        .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+        .find_map(|media_type| match media_type {
+            RAW_CONTENT_TYPE => Some(Format::Raw),
+            CAR_CONTENT_TYPE => Some(Format::Car),
+            _ => None,
+        }))
+}
+
+async fn serve_path<T: IpfsTypes>(
+    path: FullPath,
+    query: GatewayQuery,
+    headers: HeaderMap,
+    ipfs: Ipfs<T>,
+    options: Arc<GatewayOptions>,
+) -> Result<Response<Body>, Infallible> {
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+
+    let routed = match route(path.as_str(), &headers, &options) {
+        Ok(Routed::DnsLink { domain, path }) => resolve_dnslink(&ipfs, &domain, &path).await,
+        routed => routed,
+    };
+
+    let served = match (routed, requested_format(&query, &headers)) {
+        (Ok(Routed::Serve(tail)), Ok(Some(format)))
+        | (Ok(Routed::Origin(tail)), Ok(Some(format))) => {
+            serve_verifiable(&tail, format, ipfs).await
+        }
+        (Ok(Routed::Serve(tail)), Ok(None)) => inner_serve(&tail, range, ipfs, false).await,
+        (Ok(Routed::Origin(tail)), Ok(None)) => inner_serve(&tail, range, ipfs, true).await,
+        (Ok(Routed::Redirect(location)), _) => Ok(redirect(&location)),
+        (Ok(Routed::DnsLink { .. }), _) => unreachable!("resolved above"),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+
+    Ok(match served {
+        Ok(response) => not_modified(response, headers.get(header::IF_NONE_MATCH)),
+        Err((status, message)) => error_response(status, message),
+    })
+}
+
+/// Replaces the successful response with 304 Not Modified when the `If-None-Match` of the request
+/// lists the `ETag` of the response.
+fn not_modified(response: Response<Body>, if_none_match: Option<&HeaderValue>) -> Response<Body> {
+    let etag = match response.headers().get(header::ETAG) {
+        Some(etag) if response.status().is_success() => etag,
+        _ => return response,
+    };
+
+    let matches = match (if_none_match.map(HeaderValue::to_str), etag.to_str()) {
+        (Some(Ok(tags)), Ok(etag)) => tags
+            .split(',')
+            .map(str::trim)
+            // the weak comparison of RFC 7232
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag),
+        _ => false,
+    };
+
+    if !matches {
+        return response;
+    }
+
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(header::ETAG, etag.clone())
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+type GatewayError = (StatusCode, String);
+
+async fn write_path<T: IpfsTypes>(
+    method: Method,
+    path: FullPath,
+    headers: HeaderMap,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + Unpin + 'static,
+    ipfs: Ipfs<T>,
+    options: Arc<GatewayOptions>,
+) -> Result<Response<Body>, Infallible> {
+    let body = body.map_ok(|mut buf| buf.to_bytes());
+
+    Ok(
+        match inner_write(method, path.as_str(), &headers, body, ipfs, &options).await {
+            Ok(response) => response,
+            Err((status, message)) => error_response(status, message),
+        },
+    )
+}
+
+async fn inner_write<T: IpfsTypes>(
+    method: Method,
+    path: &str,
+    headers: &HeaderMap,
+    body: impl Stream<Item = Result<Bytes, warp::Error>>,
+    ipfs: Ipfs<T>,
+    options: &GatewayOptions,
+) -> Result<Response<Body>, GatewayError> {
+    if !options.writable {
+        return Err((
+            StatusCode::METHOD_NOT_ALLOWED,
+            "the gateway is not writable".into(),
+        ));
+    }
+
+    if method == Method::POST {
+        if path.trim_end_matches('/') != "/ipfs" {
+            return Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "new content can only be posted to /ipfs/".into(),
+            ));
+        }
+
+        let added = add(&ipfs, body, AddOptions::default())
+            .await
+            .map_err(|e| internal(e.to_string()))?;
+
+        return Ok(created(&added.root, ""));
+    }
+
+    let routed = match route(path, headers, options)? {
+        // the content of the DNSLink records cannot be replaced, but the path-style requests can
+        // still be made to the same host
+        Routed::DnsLink { path, .. } => path_style(&path)?,
+        routed => routed,
+    };
+
+    let tail = match routed {
+        Routed::Serve(tail) | Routed::Origin(tail) => tail,
+        // the temporary redirect keeps the method and the body
+        Routed::Redirect(location) => {
+            return Ok(Response::builder()
+                .status(StatusCode::TEMPORARY_REDIRECT)
+                .header(header::LOCATION, location)
+                .body(Body::empty())
+                .expect("valid response"))
+        }
+        Routed::DnsLink { .. } => unreachable!("handled above"),
+    };
+
+    let (root, rest) = match tail.find('/') {
+        Some(at) => (&tail[..at], tail[at + 1..].trim_end_matches('/')),
+        None => (tail.as_str(), ""),
+    };
+
+    let root = parse_cid(root)?;
+
+    let decoded = percent_encoding::percent_decode_str(rest)
+        .decode_utf8()
+        .map_err(|_| bad_request("path is not valid UTF-8"))?;
+
+    let segments = decoded
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    if segments.is_empty() {
+        return Err(bad_request("the path of the file is missing"));
+    }
+
+    if segments
+        .iter()
+        .any(|&segment| segment == "." || segment == "..")
+    {
+        return Err(bad_request("the path cannot contain . or .. segments"));
+    }
+
+    let added = add(&ipfs, body, AddOptions::default())
+        .await
+        .map_err(|e| internal(e.to_string()))?;
+
+    let root = put_into_directory(&ipfs, root, &segments, added.root, added.total_size).await?;
+
+    Ok(created(&root, rest))
+}
+
+/// The data of an empty UnixFS directory, used for the directories created along the path.
+const EMPTY_DIRECTORY: &[u8] = &[0x08, 0x01];
+
+/// Links the file at the path of `segments` under the directory `root`, replacing any existing
+/// file, and creating the missing directories. Returns the new root directory.
+async fn put_into_directory<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: Cid,
+    segments: &[&str],
+    file: Cid,
+    file_size: u64,
+) -> Result<Cid, GatewayError> {
+    let (file_name, directories) = segments.split_last().expect("checked to be non-empty");
+
+    // the existing directories along the path, until the first missing one
+    let mut nodes = Vec::with_capacity(segments.len());
+    let mut next = Some(root);
+
+    for nth in 0..segments.len() {
+        let node = match next.take() {
+            Some(cid) => Some(load_directory(ipfs, &cid).await?),
+            None => None,
+        };
+
+        if let (Some(node), Some(name)) = (&node, directories.get(nth)) {
+            next = node
+                .links
+                .iter()
+                .find(|link| link.name == *name)
+                .map(|link| link.cid.clone());
+        }
+
+        nodes.push(node);
+    }
+
+    let mut child = PbLink {
+        cid: file,
+        name: (*file_name).to_owned(),
+        size: file_size,
+    };
+
+    // the directories are recreated from the bottom up, as each new directory changes its parent
+    for (nth, node) in nodes.into_iter().enumerate().rev() {
+        let mut node = node.unwrap_or_else(|| PbNode {
+            links: Vec::new(),
+            data: EMPTY_DIRECTORY.to_vec(),
+        });
+
+        node.links.retain(|link| link.name != child.name);
+        node.links.push(child);
+        node.links.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let links_size = node.links.iter().map(|link| link.size).sum::<u64>();
+        let bytes = node.into_bytes();
+        let size = bytes.len() as u64 + links_size;
+
+        let cid = Cid::new_v0(Sha2_256::digest(&bytes)).expect("sha2-256 is supported by cidv0");
+
+        ipfs.put_block(Block::new(bytes, cid.clone()))
+            .await
+            .map_err(|e| internal(e.to_string()))?;
+
+        child = PbLink {
+            cid,
+            name: nth
+                .checked_sub(1)
+                .map(|parent| directories[parent].to_owned())
+                .unwrap_or_default(),
+            size,
+        };
+    }
+
+    Ok(child.cid)
+}
+
+async fn load_directory<T: IpfsTypes>(ipfs: &Ipfs<T>, cid: &Cid) -> Result<PbNode, GatewayError> {
+    let not_a_directory = || (StatusCode::CONFLICT, format!("{} is not a directory", cid));
+
+    if cid.codec() != Codec::DagProtobuf {
+        return Err(not_a_directory());
+    }
+
+    let block = ipfs
+        .get_block(cid)
+        .await
+        .map_err(|e| internal(e.to_string()))?;
+
+    match stat_block(cid, &block.data).map(|stat| stat.node_type) {
+        Ok(NodeType::Directory) => {}
+        Ok(NodeType::HamtShard) => {
+            return Err((
+                StatusCode::NOT_IMPLEMENTED,
+                "writing to sharded directories is not supported".into(),
+            ))
+        }
+        _ => return Err(not_a_directory()),
+    }
+
+    PbNode::from_bytes(&block.data).map_err(|e| internal(e.to_string()))
+}
+
+fn created(root: &Cid, path: &str) -> Response<Body> {
+    let location = if path.is_empty() {
+        format!("/ipfs/{}", root)
+    } else {
+        format!("/ipfs/{}/{}", root, path)
+    };
+
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header("ipfs-hash", root.to_string())
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+/// How a request is handled, decided by [`route`].
+#[derive(Debug, PartialEq)]
+enum Routed {
+    /// Serve the content at the percent-encoded path which starts with the root Cid.
+    Serve(String),
+    /// Serve the content like [`Routed::Serve`], but from the root of an origin of its own, as
+    /// with the subdomains and DNSLink, where the `_redirects` and `404.html` of the root apply.
+    Origin(String),
+    /// Redirect to the location.
+    Redirect(String),
+    /// Serve the content at the path under the DNSLink record of the domain.
+    DnsLink { domain: String, path: String },
+}
+
+/// Maps the request to the content path, or to a redirect to the normalized subdomain.
+fn route(
+    path: &str,
+    headers: &HeaderMap,
+    options: &GatewayOptions,
+) -> Result<Routed, GatewayError> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(|host| {
+            host.rsplitn(2, ':')
+                .last()
+                .unwrap_or(host)
+                .to_ascii_lowercase()
+        });
+
+    let gateway_host = match options.subdomain_host.as_deref() {
+        Some(gateway_host) => gateway_host,
+        None => return other_host(host, path, options),
+    };
+
+    // any client can set the header, so only the two schemes are taken into the redirects
+    let scheme = match headers.get("x-forwarded-proto") {
+        Some(value) if value.as_bytes().eq_ignore_ascii_case(b"https") => "https",
+        _ => "http",
+    };
+
+    let subdomain = host.as_deref().and_then(|host| {
+        host.strip_suffix(gateway_host)
+            .and_then(|prefix| prefix.strip_suffix(".ipfs."))
+    });
+
+    match subdomain {
+        Some(label) if !label.contains('.') => {
+            let cid = parse_cid(label)?;
+            let normalized = cidv1(&cid);
+
+            if normalized != label {
+                Ok(Routed::Redirect(format!(
+                    "{}://{}.ipfs.{}{}",
+                    scheme, normalized, gateway_host, path
+                )))
+            } else {
+                Ok(Routed::Origin(format!("{}{}", label, path)))
+            }
+        }
+        Some(_) => Err((StatusCode::NOT_FOUND, "not found".into())),
+        None if host.as_deref() == Some(gateway_host) => {
+            let tail = match path_style(path)? {
+                Routed::Serve(tail) => tail,
+                redirect => return Ok(redirect),
+            };
+
+            let (root, rest) = match tail.find('/') {
+                Some(at) => tail.split_at(at),
+                None => (tail.as_str(), ""),
+            };
+
+            let cid = parse_cid(root)?;
+
+            Ok(Routed::Redirect(format!(
+                "{}://{}.ipfs.{}{}",
+                scheme,
+                cidv1(&cid),
+                gateway_host,
+                if rest.is_empty() { "/" } else { rest }
+            )))
+        }
+        None => other_host(host, path, options),
+    }
+}
+
+/// Routes the requests to the hosts other than the subdomain gateway host.
+fn other_host(
+    host: Option<String>,
+    path: &str,
+    options: &GatewayOptions,
+) -> Result<Routed, GatewayError> {
+    match host {
+        // only the fully qualified domain names can have the DNSLink records, and not for example
+        // localhost or the IP addresses
+        Some(domain)
+            if options.dnslink && domain.contains('.') && domain.parse::<IpAddr>().is_err() =>
+        {
+            Ok(Routed::DnsLink {
+                domain,
+                path: path.to_owned(),
+            })
+        }
+        _ => path_style(path),
+    }
+}
+
+/// Resolves the DNSLink record of the domain to the content path, or falls back to the
+/// path-style routing for the domains without a record.
+async fn resolve_dnslink<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    domain: &str,
+    path: &str,
+) -> Result<Routed, GatewayError> {
+    let resolved = match ipfs
+        .resolve_ipns(&IpfsPath::new(PathRoot::Dns(domain.to_owned())), true)
+        .await
+    {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            debug!("no DNSLink record for {}: {}", domain, e);
+            return path_style(path);
+        }
+    };
+
+    let root = match resolved.root().cid() {
+        Some(root) => root,
+        None => {
+            return Err((
+                StatusCode::BAD_GATEWAY,
+                format!("DNSLink of {} does not resolve to /ipfs/", domain),
+            ))
+        }
+    };
+
+    let mut tail = root.to_string();
+
+    for segment in resolved.iter() {
+        tail.push('/');
+        tail.extend(percent_encoding::utf8_percent_encode(segment, SEGMENT));
+    }
+
+    // the request path is already percent-encoded
+    tail.push_str(path);
+
+    Ok(Routed::Origin(tail))
+}
+
+fn path_style(path: &str) -> Result<Routed, GatewayError> {
+    match path.strip_prefix("/ipfs/") {
+        Some(tail) if !tail.is_empty() => Ok(Routed::Serve(tail.to_owned())),
+        _ => Err((StatusCode::NOT_FOUND, "not found".into())),
+    }
+}
+
+fn parse_cid(s: &str) -> Result<Cid, GatewayError> {
+    Cid::try_from(s).map_err(|e| bad_request(format!("invalid cid: {}", e)))
+}
+
+/// The Cid as version 1 in base32, the canonical form in the subdomains.
+fn cidv1(cid: &Cid) -> String {
+    Cid::new_v1(cid.codec(), cid.hash().to_owned()).to_string()
+}
+
+fn redirect(location: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+/// Serves the content at the percent-encoded `tail`, which starts with the root Cid. When `origin`
+/// is true, the paths which are not found are handled as in [`website_not_found`].
+async fn inner_serve<T: IpfsTypes>(
+    tail: &str,
+    range: Option<&str>,
+    ipfs: Ipfs<T>,
+    origin: bool,
+) -> Result<Response<Body>, GatewayError> {
+    let decoded = percent_encoding::percent_decode_str(tail)
+        .decode_utf8()
+        .map_err(|_| bad_request("path is not valid UTF-8"))?;
+
+    let path = IpfsPath::try_from(format!("/ipfs/{}", decoded).as_str())
+        .map_err(|e| bad_request(format!("invalid path: {}", e)))?;
+
+    let name = decoded
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or("");
+
+    let resolved = match resolve(&ipfs, path.clone()).await {
+        Err((StatusCode::NOT_FOUND, message)) if origin => {
+            return website_not_found(ipfs, &decoded, range, message).await
+        }
+        resolved => resolved?,
+    };
+
+    let Block { cid, data } = match resolved {
+        // raw leaves are served as is
+        Block { cid, data } if cid.codec() == Codec::Raw => {
+            let size = data.len() as u64;
+            let range = match requested_range(range, size) {
+                RequestedRange::Unsatisfiable => return Ok(unsatisfiable(size)),
+                RequestedRange::Partial(range) => Some(range),
+                RequestedRange::Full => None,
+            };
+
+            let content = match &range {
+                Some(range) => data[range.start as usize..range.end as usize].to_vec(),
+                None => data.to_vec(),
+            };
+
+            return Ok(file_response(
+                &cid,
+                name,
+                size,
+                range,
+                stream::once(async move { Ok::<_, Infallible>(content) }).boxed(),
+            )
+            .await);
+        }
+        block => block,
+    };
+
+    let node = stat_block(&cid, &data).map_err(|e| internal(e.to_string()))?;
+
+    match node.node_type {
+        NodeType::File => serve_file(ipfs, Block { cid, data }, name, node.size, range).await,
+        NodeType::Directory | NodeType::HamtShard => {
+            if !tail.ends_with('/') {
+                // the relative links in the index.html are relative to the directory; the
+                // location is relative as well to work with both path-style and subdomains
+                let last = tail.rsplit('/').next().unwrap_or_default();
+                return Ok(redirect(&format!("{}/", last)));
+            }
+
+            let index = path
+                .sub_path("index.html")
+                .map_err(|e| internal(e.to_string()))?;
+
+            match resolve(&ipfs, index).await {
+                Ok(block) => {
+                    let size = stat_block(&block.cid, &block.data)
+                        .map_err(|e| internal(e.to_string()))?
+                        .size;
+                    serve_file(ipfs, block, "index.html", size, range).await
+                }
+                Err((StatusCode::NOT_FOUND, _)) => {
+                    let entries = directory_entries(&ipfs, cid, data).await?;
+                    Ok(directory_listing(&decoded, &entries))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        NodeType::Symlink => Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "symlinks are not supported".into(),
+        )),
+    }
+}
+
+/// Handles the path not found under the root of a website with the first matching rule of the
+/// `_redirects` file at the root, or else with the `404.html` of the nearest parent directory.
+async fn website_not_found<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    decoded: &str,
+    range: Option<&str>,
+    message: String,
+) -> Result<Response<Body>, GatewayError> {
+    let (root, rest) = match decoded.find('/') {
+        Some(at) => decoded.split_at(at),
+        None => (decoded, ""),
+    };
+
+    if let Some(rules) = load_redirects(&ipfs, root).await? {
+        let path = if rest.is_empty() { "/" } else { rest };
+
+        if let Some((to, status)) = redirects::find(&rules, path) {
+            if status.is_redirection() {
+                // the substituted segments were decoded from the request path
+                let location = percent_encoding::utf8_percent_encode(&to, LOCATION).to_string();
+
+                return Response::builder()
+                    .status(status)
+                    .header(header::LOCATION, location)
+                    .body(Body::empty())
+                    .map_err(|_| bad_request("invalid redirect location"));
+            }
+
+            let mut tail = percent_encoding::utf8_percent_encode(root, SEGMENT).to_string();
+            for segment in to.split('/').skip(1) {
+                tail.push('/');
+                tail.extend(percent_encoding::utf8_percent_encode(segment, SEGMENT));
+            }
+
+            // the rewritten paths are served without applying the rules again
+            let range = if status == StatusCode::OK {
+                range
+            } else {
+                None
+            };
+            let mut response = serve_rewritten(tail, range.map(str::to_owned), ipfs).await?;
+
+            if status != StatusCode::OK {
+                *response.status_mut() = status;
+            }
+
+            return Ok(response);
+        }
+    }
+
+    let directories = rest
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    // the requested path itself was not found, so it is not one of the directories
+    for depth in (0..directories.len()).rev() {
+        let mut candidate = format!("/ipfs/{}", root);
+        for directory in &directories[..depth] {
+            candidate.push('/');
+            candidate.push_str(directory);
+        }
+        candidate.push_str("/404.html");
+
+        let candidate = match IpfsPath::try_from(candidate.as_str()) {
+            Ok(candidate) => candidate,
+            Err(_) => continue,
+        };
+
+        match resolve(&ipfs, candidate).await {
+            Ok(block) => {
+                let size = stat_block(&block.cid, &block.data)
+                    .map_err(|e| internal(e.to_string()))?
+                    .size;
+                let mut response = serve_file(ipfs, block, "404.html", size, None).await?;
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return Ok(response);
+            }
+            Err((StatusCode::NOT_FOUND, _)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err((StatusCode::NOT_FOUND, message))
+}
+
+/// Serves the target of a `_redirects` rewrite; boxed as the rewrites are served by
+/// [`inner_serve`] which they are called from.
+fn serve_rewritten<T: IpfsTypes>(
+    tail: String,
+    range: Option<String>,
+    ipfs: Ipfs<T>,
+) -> BoxFuture<'static, Result<Response<Body>, GatewayError>> {
+    async move { inner_serve(&tail, range.as_deref(), ipfs, false).await }.boxed()
+}
+
+/// Loads the rules of the `_redirects` file at the root, if there is one.
+async fn load_redirects<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    root: &str,
+) -> Result<Option<Vec<redirects::Rule>>, GatewayError> {
+    let path = IpfsPath::try_from(format!("/ipfs/{}/_redirects", root).as_str())
+        .map_err(|e| bad_request(format!("invalid path: {}", e)))?;
+
+    let block = match resolve(ipfs, path).await {
+        Ok(block) => block,
+        Err((StatusCode::NOT_FOUND, _)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let node = stat_block(&block.cid, &block.data).map_err(|e| internal(e.to_string()))?;
+    if node.node_type != NodeType::File || node.size > redirects::MAX_SIZE {
+        return Err(internal(
+            "_redirects is not a file of at most 64 KiB".into(),
+        ));
+    }
+
+    let content = ipfs::unixfs::cat(ipfs.clone(), block, None)
+        .await
+        .map_err(|e| internal(e.to_string()))?
+        .try_concat()
+        .await
+        .map_err(|e| internal(e.to_string()))?;
+
+    let content =
+        String::from_utf8(content).map_err(|_| internal("_redirects is not UTF-8".into()))?;
+
+    redirects::parse(&content)
+        .map(Some)
+        .map_err(|e| internal(format!("invalid _redirects: {}", e)))
+}
+
+/// An entry of a directory listing.
+struct Entry {
+    name: String,
+    cid: Cid,
+    /// The cumulative size of the blocks of the entry.
+    size: u64,
+}
+
+/// The deepest bucket of a HAMT sharded directory, as the 64 bits of the hash are used eight bits
+/// per level.
+const MAX_BUCKET_DEPTH: usize = 8;
+
+/// Returns the entries of the directory in the order of the names, loading the buckets of the
+/// HAMT sharded directories.
+async fn directory_entries<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    cid: Cid,
+    data: Bytes,
+) -> Result<Vec<Entry>, GatewayError> {
+    let mut entries = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(cid.clone());
+    let mut buckets = vec![(Block::new(data, cid), 0)];
+
+    while let Some((block, depth)) = buckets.pop() {
+        let sharded = stat_block(&block.cid, &block.data)
+            .map(|stat| stat.node_type == NodeType::HamtShard)
+            .map_err(|e| internal(e.to_string()))?;
+
+        let node = PbNode::from_bytes(&block.data).map_err(|e| internal(e.to_string()))?;
+
+        for link in node.links {
+            if !sharded {
+                entries.push(Entry {
+                    name: link.name,
+                    cid: link.cid,
+                    size: link.size,
+                });
+            } else if link.name.len() == 2 {
+                // link to a bucket, which only has the two character prefix as the name
+                if depth == MAX_BUCKET_DEPTH {
+                    return Err(internal(format!("HAMT shard {} is too deep", block.cid)));
+                }
+
+                if !visited.insert(link.cid.clone()) {
+                    // a crafted shard can link to a bucket more than once, or back to itself
+                    continue;
+                }
+
+                let bucket = ipfs
+                    .get_block(&link.cid)
+                    .await
+                    .map_err(|e| internal(e.to_string()))?;
+                buckets.push((bucket, depth + 1));
+            } else if let Some(name) = link.name.get(2..).filter(|name| !name.is_empty()) {
+                entries.push(Entry {
+                    name: name.to_owned(),
+                    cid: link.cid,
+                    size: link.size,
+                });
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// The characters escaped in the `Location` of the redirects, which can be complete URLs.
+const LOCATION: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`');
+
+/// The characters escaped in the names of the entries when used as relative links.
+const SEGMENT: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Renders the HTML index page of a directory without an `index.html`. The `path` ends with a
+/// slash, so the entries are linked relative to it.
+fn directory_listing(path: &str, entries: &[Entry]) -> Response<Body> {
+    let title = html_escape(&format!("/ipfs/{}", path));
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<table>\n",
+        title
+    );
+
+    // the root directory has no parent
+    if path.trim_end_matches('/').contains('/') {
+        html.push_str("<tr><td><a href=\"../\">..</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let href = percent_encoding::utf8_percent_encode(&entry.name, SEGMENT);
+        let name = html_escape(&entry.name);
+
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td><a href=\"/ipfs/{}\">{}</a></td><td>{}</td></tr>\n",
+            href,
+            name,
+            entry.cid,
+            entry.cid,
+            human_size(entry.size)
+        ));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))
+        .expect("valid response")
+}
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Resolves the path to the node at the end of it.
+async fn resolve_node<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    path: IpfsPath,
+) -> Result<ResolvedNode, GatewayError> {
+    let (resolved, _) = ipfs.dag().resolve(path, true).await.map_err(|e| match e {
+        ResolveError::NotFound(..)
+        | ResolveError::NoLinks(..)
+        | ResolveError::ListIndexOutOfRange { .. } => (StatusCode::NOT_FOUND, e.to_string()),
+        ResolveError::Symlink(..) => (StatusCode::NOT_IMPLEMENTED, e.to_string()),
+        e => internal(e.to_string()),
+    })?;
+
+    Ok(resolved)
+}
+
+/// Serves the block at the end of the path, or the DAG rooted at it, for the clients to verify.
+async fn serve_verifiable<T: IpfsTypes>(
+    tail: &str,
+    format: Format,
+    ipfs: Ipfs<T>,
+) -> Result<Response<Body>, GatewayError> {
+    let decoded = percent_encoding::percent_decode_str(tail)
+        .decode_utf8()
+        .map_err(|_| bad_request("path is not valid UTF-8"))?;
+
+    let path = IpfsPath::try_from(format!("/ipfs/{}", decoded).as_str())
+        .map_err(|e| bad_request(format!("invalid path: {}", e)))?;
+
+    let block = match resolve_node(&ipfs, path).await? {
+        ResolvedNode::Block(block) => block,
+        _ => return Err(bad_request("the path does not end at a block")),
+    };
+
+    let response = match format {
+        Format::Raw => Response::builder()
+            .header(header::CONTENT_TYPE, RAW_CONTENT_TYPE)
+            .header(header::CONTENT_LENGTH, block.data.len())
+            .body(Body::from(block.data)),
+        Format::Car => Response::builder()
+            .header(
+                header::CONTENT_TYPE,
+                format!("{}; version=1", CAR_CONTENT_TYPE),
+            )
+            .body(Body::wrap_stream(car::car_stream(ipfs, block))),
+    };
+
+    Ok(response.expect("valid response"))
+}
+
+/// Resolves the path to the block at the end of it.
+async fn resolve<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<Block, GatewayError> {
+    resolve_node(ipfs, path)
+        .await?
+        .into_unixfs_block()
+        .map_err(|e| bad_request(format!("path did not resolve to UnixFS: {}", e)))
+}
+
+async fn serve_file<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    block: Block,
+    name: &str,
+    size: u64,
+    range: Option<&str>,
+) -> Result<Response<Body>, GatewayError> {
+    let range = match requested_range(range, size) {
+        RequestedRange::Unsatisfiable => return Ok(unsatisfiable(size)),
+        RequestedRange::Partial(range) => Some(range),
+        RequestedRange::Full => None,
+    };
+
+    let cid = block.cid.clone();
+
+    // only the blocks covering the range are loaded
+    let content = ipfs::unixfs::cat(ipfs, block, range.clone())
+        .await
+        .map_err(|e| internal(e.to_string()))?;
+
+    Ok(file_response(&cid, name, size, range, content.boxed()).await)
+}
+
+/// The outcome of interpreting the `Range` header of a request.
+#[derive(Debug, PartialEq)]
+enum RequestedRange {
+    /// There was no header, or it could not be understood, so the whole file is sent.
+    Full,
+    /// A single range within the file, with the end exclusive.
+    Partial(Range<u64>),
+    /// The range starts after the end of the file.
+    Unsatisfiable,
+}
+
+/// Interprets the value of the `Range` header for a file of `size` bytes. Only single byte ranges
+/// are supported; requests for multiple ranges are answered with the whole file, as RFC 7233
+/// allows.
+fn requested_range(header: Option<&str>, size: u64) -> RequestedRange {
+    let spec = match header.and_then(|header| header.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return RequestedRange::Full,
+    };
+
+    let (start, end) = match spec.find('-') {
+        Some(at) => (&spec[..at], &spec[at + 1..]),
+        None => return RequestedRange::Full,
+    };
+
+    let range = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        // bytes=a-b, with b inclusive
+        (Some(start), Some(end)) if start <= end => start..end.saturating_add(1).min(size),
+        // bytes=a-
+        (Some(start), None) if end.is_empty() => start..size,
+        // bytes=-n, the last n bytes
+        (None, Some(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return RequestedRange::Unsatisfiable;
+            }
+            size.saturating_sub(suffix)..size
+        }
+        _ => return RequestedRange::Full,
+    };
+
+    if range.start >= size {
+        RequestedRange::Unsatisfiable
+    } else {
+        RequestedRange::Partial(range)
+    }
+}
+
+fn unsatisfiable(size: u64) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+/// Creates the response for the file content of `size` bytes, of which only the `range` is
+/// included in the `content` when given. The first chunk of the content is used to guess the
+/// content type when the name has no known extension.
+async fn file_response<E>(
+    cid: &Cid,
+    name: &str,
+    size: u64,
+    range: Option<Range<u64>>,
+    mut content: stream::BoxStream<'static, Result<Vec<u8>, E>>,
+) -> Response<Body>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let first = match content.next().await {
+        Some(Ok(first)) => first,
+        Some(Err(e)) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        None => Vec::new(),
+    };
+
+    let starts_at_beginning = range.as_ref().map(|range| range.start == 0).unwrap_or(true);
+
+    let content_type = mime_guess::from_path(name)
+        .first()
+        .map(|mime| mime.to_string())
+        .unwrap_or_else(|| {
+            if starts_at_beginning {
+                sniff_content_type(&first)
+            } else {
+                // the middle of the file cannot be sniffed
+                "application/octet-stream"
+            }
+            .to_owned()
+        });
+
+    let content = stream::once(async move { Ok(first) })
+        .chain(content)
+        .map(|res| res.map(Bytes::from));
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, format!("\"{}\"", cid));
+
+    let builder = match range {
+        Some(range) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+            )
+            .header(header::CONTENT_LENGTH, range.end - range.start),
+        None => builder.header(header::CONTENT_LENGTH, size),
+    };
+
+    builder
+        .body(Body::wrap_stream(content))
+        .expect("valid response")
+}
+
+/// Guesses the content type from the first bytes of the content, recognizing only the most
+/// common formats.
+fn sniff_content_type(bytes: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+    ];
+
+    for (signature, content_type) in SIGNATURES {
+        if bytes.starts_with(signature) {
+            return content_type;
+        }
+    }
+
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .map(|start| &bytes[start..])
+        .unwrap_or_default();
+
+    let html = [&b"<!doctype html"[..], b"<html"].iter().any(|prefix| {
+        start.len() >= prefix.len() && start[..prefix.len()].eq_ignore_ascii_case(prefix)
+    });
+
+    if html {
+        return "text/html; charset=utf-8";
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(_) => "text/plain; charset=utf-8",
+        // the chunk could have ended in the middle of a character
+        Err(e) if e.error_len().is_none() => "text/plain; charset=utf-8",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> GatewayError {
+    (StatusCode::BAD_REQUEST, message.into())
+}
+
+fn internal(message: String) -> GatewayError {
+    (StatusCode::INTERNAL_SERVER_ERROR, message)
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(message))
+        .expect("valid response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cidv1, html_escape, human_size, requested_format, requested_range, route, routes,
+        sniff_content_type, Format, GatewayOptions, GatewayQuery, RequestedRange, Routed,
+    };
+    use futures::stream;
+    use ipfs::unixfs::{add, AddOptions};
+    use ipfs::Node;
+
+    #[test]
+    fn sniffed_content_types() {
+        assert_eq!(sniff_content_type(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(
+            sniff_content_type(b"\n  <!DOCTYPE html><html>"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(sniff_content_type(b"foobar\n"), "text/plain; charset=utf-8");
+        assert_eq!(sniff_content_type(b"\xc3"), "text/plain; charset=utf-8");
+        assert_eq!(sniff_content_type(b"\x00\xff"), "application/octet-stream");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn serves_files_and_errors() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let cid = add(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .await
+            .unwrap()
+            .root;
+
+        let filter = routes(&*ipfs, GatewayOptions::default());
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}", cid))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(response.headers()["content-length"], "7");
+        assert_eq!(response.body(), &b"foobar\n"[..]);
+
+        let response = warp::test::request()
+            .path("/ipfs/not-a-cid")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 400);
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}/missing", cid))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn parsed_ranges() {
+        use RequestedRange::*;
+
+        assert_eq!(requested_range(None, 10), Full);
+        assert_eq!(requested_range(Some("bytes=2-4"), 10), Partial(2..5));
+        assert_eq!(requested_range(Some("bytes=2-100"), 10), Partial(2..10));
+        assert_eq!(requested_range(Some("bytes=7-"), 10), Partial(7..10));
+        assert_eq!(requested_range(Some("bytes=-3"), 10), Partial(7..10));
+        assert_eq!(requested_range(Some("bytes=-30"), 10), Partial(0..10));
+        assert_eq!(requested_range(Some("bytes=10-"), 10), Unsatisfiable);
+        assert_eq!(requested_range(Some("bytes=-0"), 10), Unsatisfiable);
+        assert_eq!(requested_range(Some("bytes=0-1,4-5"), 10), Full);
+        assert_eq!(requested_range(Some("bytes=4-2"), 10), Full);
+        assert_eq!(requested_range(Some("items=0-1"), 10), Full);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn serves_ranges() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let cid = add(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .await
+            .unwrap()
+            .root;
+
+        let filter = routes(&*ipfs, GatewayOptions::default());
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}", cid))
+            .header("range", "bytes=1-4")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.headers()["content-range"], "bytes 1-4/7");
+        assert_eq!(response.headers()["content-length"], "4");
+        assert_eq!(response.body(), &b"ooba"[..]);
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}", cid))
+            .header("range", "bytes=7-")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 416);
+        assert_eq!(response.headers()["content-range"], "bytes */7");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn revalidates_with_etag() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let cid = add(&*ipfs, content, AddOptions::default())
+            .await
+            .unwrap()
+            .root;
+
+        let filter = routes(&*ipfs, GatewayOptions::default());
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}", cid))
+            .reply(&filter)
+            .await;
+
+        let etag = format!("\"{}\"", cid);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+
+        for if_none_match in &[etag.clone(), format!("W/{}, \"other\"", etag), "*".into()] {
+            let response = warp::test::request()
+                .path(&format!("/ipfs/{}", cid))
+                .header("if-none-match", if_none_match.as_str())
+                .reply(&filter)
+                .await;
+
+            assert_eq!(response.status(), 304);
+            assert_eq!(response.headers()["etag"], etag.as_str());
+            assert!(response.body().is_empty());
+        }
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}", cid))
+            .header("if-none-match", "\"other\"")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn subdomain_routing() {
+        use warp::http::{header, HeaderMap};
+
+        let options = GatewayOptions::default().with_subdomains("example.com");
+        let v0 = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        let v1 = "bafybeif7ztnhq65lumvvtr4ekcwd2ifwgm3awq4zfr3srh462rwyinlb4y";
+
+        let headers = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, host.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(
+            route(
+                "/a/b",
+                &headers(&format!("{}.ipfs.example.com", v1)),
+                &options
+            )
+            .unwrap(),
+            Routed::Origin(format!("{}/a/b", v1))
+        );
+
+        assert_eq!(
+            route(
+                &format!("/ipfs/{}/a", v0),
+                &headers("example.com:8080"),
+                &options
+            )
+            .unwrap(),
+            Routed::Redirect(format!("http://{}.ipfs.example.com/a", v1))
+        );
+
+        // the hosts are case insensitive
+        let upper = v1.to_ascii_uppercase();
+        assert_eq!(
+            route(
+                "/",
+                &headers(&format!("{}.ipfs.example.com", upper)),
+                &options
+            )
+            .unwrap(),
+            Routed::Origin(format!("{}/", v1))
+        );
+
+        // other bases are redirected to base32
+        let base16 = "f0170122059948439065f29619ef41280cbb932be52c56d99c5966b65e0111239f098bbef";
+        assert_eq!(
+            route(
+                "/a",
+                &headers(&format!("{}.ipfs.example.com", base16)),
+                &options
+            )
+            .unwrap(),
+            Routed::Redirect(format!("http://{}.ipfs.example.com/a", v1))
+        );
+
+        assert_eq!(
+            route(
+                &format!("/ipfs/{}", v0),
+                &headers("127.0.0.1:8080"),
+                &options
+            )
+            .unwrap(),
+            Routed::Serve(v0.to_owned())
+        );
+
+        let (status, _) = route("/", &headers("not-a-cid.ipfs.example.com"), &options).unwrap_err();
+        assert_eq!(status, 400);
+
+        let (status, _) = route("/a", &headers("example.com"), &options).unwrap_err();
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn forwarded_proto_of_redirects() {
+        use warp::http::{header, HeaderMap};
+
+        let options = GatewayOptions::default().with_subdomains("example.com");
+        let v0 = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        let v1 = "bafybeif7ztnhq65lumvvtr4ekcwd2ifwgm3awq4zfr3srh462rwyinlb4y";
+
+        let redirect = |proto: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, "example.com".parse().unwrap());
+            headers.insert("x-forwarded-proto", proto.parse().unwrap());
+            route(&format!("/ipfs/{}", v0), &headers, &options).unwrap()
+        };
+
+        assert_eq!(
+            redirect("HTTPS"),
+            Routed::Redirect(format!("https://{}.ipfs.example.com/", v1))
+        );
+
+        for hostile in &["javascript", "https://evil.example/#", "ftp"] {
+            assert_eq!(
+                redirect(hostile),
+                Routed::Redirect(format!("http://{}.ipfs.example.com/", v1))
+            );
+        }
+    }
+
+    #[test]
+    fn dnslink_routing() {
+        let options = GatewayOptions::default()
+            .with_subdomains("example.com")
+            .with_dnslink(true);
+
+        let headers = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, host.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(
+            route("/a/b", &headers("docs.example.org:8080"), &options).unwrap(),
+            Routed::DnsLink {
+                domain: "docs.example.org".into(),
+                path: "/a/b".into()
+            }
+        );
+
+        // neither localhost nor the IP addresses can have DNSLink records
+        let v0 = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        for host in &["localhost:8080", "127.0.0.1:8080"] {
+            assert_eq!(
+                route(&format!("/ipfs/{}", v0), &headers(host), &options).unwrap(),
+                Routed::Serve(v0.to_owned())
+            );
+        }
+
+        let (status, _) = route("/a", &headers("example.com"), &options).unwrap_err();
+        assert_eq!(status, 404);
+
+        let options = GatewayOptions::default();
+        assert_eq!(
+            route(
+                &format!("/ipfs/{}", v0),
+                &headers("docs.example.org"),
+                &options
+            )
+            .unwrap(),
+            Routed::Serve(v0.to_owned())
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn writes_create_new_roots() {
+        use super::EMPTY_DIRECTORY;
+        use cid::Cid;
+        use ipfs::ipld::dag_pb::PbNode;
+        use ipfs::Block;
+        use multihash::Sha2_256;
+
+        let ipfs = Node::new("test_node").await;
+
+        let empty = PbNode {
+            links: Vec::new(),
+            data: EMPTY_DIRECTORY.to_vec(),
+        }
+        .into_bytes();
+        let empty_cid = Cid::new_v0(Sha2_256::digest(&empty)).unwrap();
+        ipfs.put_block(Block::new(empty, empty_cid.clone()))
+            .await
+            .unwrap();
+
+        let read_only = routes(&*ipfs, GatewayOptions::default());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/ipfs/")
+            .body("foobar\n")
+            .reply(&read_only)
+            .await;
+
+        assert_eq!(response.status(), 405);
+
+        let filter = routes(&*ipfs, GatewayOptions::default().with_writable(true));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/ipfs/")
+            .body("foobar\n")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 201);
+        let file = response.headers()["ipfs-hash"].to_str().unwrap().to_owned();
+        assert_eq!(response.headers()["location"], format!("/ipfs/{}", file));
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path(&format!("/ipfs/{}/a/b.txt", empty_cid))
+            .body("foobar\n")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 201);
+        let root = response.headers()["ipfs-hash"].to_str().unwrap().to_owned();
+        assert_eq!(
+            response.headers()["location"],
+            format!("/ipfs/{}/a/b.txt", root)
+        );
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}/a/b.txt", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.body(), &b"foobar\n"[..]);
+
+        // files cannot be written into files
+        let response = warp::test::request()
+            .method("PUT")
+            .path(&format!("/ipfs/{}/c.txt", file))
+            .body("foobar\n")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 409);
+
+        for path in &["a/../b.txt", "a/%2E%2E", "./b.txt"] {
+            let response = warp::test::request()
+                .method("PUT")
+                .path(&format!("/ipfs/{}/{}", empty_cid, path))
+                .body("foobar\n")
+                .reply(&filter)
+                .await;
+
+            assert_eq!(response.status(), 400, "{}", path);
+        }
+
+        let response = warp::test::request()
+            .method("DELETE")
+            .path(&format!("/ipfs/{}/a/b.txt", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 405);
+    }
+
+    #[test]
+    fn requested_formats() {
+        use warp::http::{header, HeaderMap};
+
+        let query = |format: Option<&str>| GatewayQuery {
+            format: format.map(String::from),
+        };
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(requested_format(&query(None), &headers).unwrap(), None);
+        assert_eq!(
+            requested_format(&query(Some("car")), &headers).unwrap(),
+            Some(Format::Car)
+        );
+        assert!(requested_format(&query(Some("tar")), &headers).is_err());
+
+        headers.insert(
+            header::ACCEPT,
+            "text/html, application/vnd.ipld.raw;q=0.9".parse().unwrap(),
+        );
+        assert_eq!(
+            requested_format(&query(None), &headers).unwrap(),
+            Some(Format::Raw)
+        );
+        assert_eq!(
+            requested_format(&query(Some("car")), &headers).unwrap(),
+            Some(Format::Car)
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn verifiable_formats() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let cid = add(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .await
+            .unwrap()
+            .root;
+
+        let root = ipfs.get_block(&cid).await.unwrap();
+
+        let filter = routes(&*ipfs, GatewayOptions::default());
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}", cid))
+            .header("accept", "application/vnd.ipld.raw")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/vnd.ipld.raw"
+        );
+        assert_eq!(response.body(), &root.data[..]);
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}?format=car", cid))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/vnd.ipld.car; version=1"
+        );
+
+        fn read_varint(bytes: &[u8], offset: &mut usize) -> usize {
+            let mut value = 0;
+            let mut shift = 0;
+            loop {
+                let byte = bytes[*offset];
+                *offset += 1;
+                value |= ((byte & 0x7f) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+
+        // the header, then the root and the four leaves of "fo", "ob", "ar" and "\n"
+        let body = response.body();
+        let mut offset = 0;
+        let header_len = read_varint(body, &mut offset);
+        offset += header_len;
+
+        let mut sections = Vec::new();
+        while offset < body.len() {
+            let len = read_varint(body, &mut offset);
+            sections.push(&body[offset..offset + len]);
+            offset += len;
+        }
+
+        assert_eq!(offset, body.len());
+        assert_eq!(sections.len(), 5);
+        assert!(sections[0].starts_with(&cid.to_bytes()));
+    }
+
+    #[test]
+    fn listing_helpers() {
+        assert_eq!(
+            html_escape("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+        assert_eq!(human_size(1000), "1000 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn directory_listing_and_index() {
+        use ipfs::unixfs::ll::dir::builder::{BufferingTreeBuilder, TreeOptions};
+        use ipfs::Block;
+
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let added = add(&*ipfs, content, AddOptions::default()).await.unwrap();
+
+        let mut builder = BufferingTreeBuilder::new(TreeOptions::default());
+        builder
+            .put_link("a <b>.txt", added.root.clone(), added.total_size)
+            .unwrap();
+        builder
+            .put_link("site/index.html", added.root, added.total_size)
+            .unwrap();
+
+        let mut root = None;
+        for node in builder.build() {
+            let node = node.unwrap();
+            ipfs.put_block(Block::new(node.block, node.cid.clone()))
+                .await
+                .unwrap();
+            root = Some(node.cid);
+        }
+        let root = root.unwrap();
+
+        let filter = routes(&*ipfs, GatewayOptions::default());
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}/", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(body.contains("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a>"));
+        assert!(body.contains("<a href=\"site\">site</a>"));
+        assert!(!body.contains("href=\"../\""));
+
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}/site/", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &b"foobar\n"[..]);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn website_redirects_and_404() {
+        use ipfs::unixfs::ll::dir::builder::{BufferingTreeBuilder, TreeOptions};
+        use ipfs::Block;
+
+        let ipfs = Node::new("test_node").await;
+
+        let mut builder = BufferingTreeBuilder::new(TreeOptions::default());
+        for &(path, content) in &[
+            (
+                "_redirects",
+                &b"/old /new.html 302\n/go/* /to/:splat 301\n/app/* /index.html 200\n"[..],
+            ),
+            ("index.html", b"index"),
+            ("docs/404.html", b"missing"),
+        ] {
+            let content = stream::iter(vec![Ok::<_, std::io::Error>(content.to_vec())]);
+            let added = add(&*ipfs, content, AddOptions::default()).await.unwrap();
+            builder
+                .put_link(path, added.root, added.total_size)
+                .unwrap();
+        }
+
+        let mut root = None;
+        for node in builder.build() {
+            let node = node.unwrap();
+            ipfs.put_block(Block::new(node.block, node.cid.clone()))
+                .await
+                .unwrap();
+            root = Some(node.cid);
+        }
+        let root = root.unwrap();
+
+        let filter = routes(
+            &*ipfs,
+            GatewayOptions::default().with_subdomains("example.com"),
+        );
+        let host = format!("{}.ipfs.example.com", cidv1(&root));
+
+        let response = warp::test::request()
+            .path("/old")
+            .header("host", &host)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.headers()["location"], "/new.html");
+
+        let response = warp::test::request()
+            .path("/go/a%0Ab%20c")
+            .header("host", &host)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 301);
+        assert_eq!(response.headers()["location"], "/to/a%0Ab%20c");
+
+        let response = warp::test::request()
+            .path("/app/some/route")
+            .header("host", &host)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &b"index"[..]);
+
+        let response = warp::test::request()
+            .path("/docs/a/b")
+            .header("host", &host)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.body(), &b"missing"[..]);
+
+        // the rules do not apply without an origin of their own
+        let response = warp::test::request()
+            .path(&format!("/ipfs/{}/app/some/route", root))
+            .header("host", "127.0.0.1:8080")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,92 @@
+//! Writing of the CAR v1 archives for the `application/vnd.ipld.car` responses.
+
+use async_stream::try_stream;
+use bytes::Bytes;
+use futures::stream::{Stream, TryStreamExt};
+use ipfs::ipld::{dag_cbor::DagCborCodec, decode_ipld, Ipld};
+use ipfs::{Block, Cid, Ipfs, IpfsTypes};
+use std::collections::BTreeMap;
+
+/// Streams the CAR with the blocks of the DAG rooted at `root`, in the depth-first order and
+/// without duplicates. The stream ends in an error if any of the blocks cannot be loaded.
+pub(super) fn car_stream<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    root: Block,
+) -> impl Stream<Item = Result<Bytes, ipfs::Error>> + Send + 'static {
+    try_stream! {
+        yield Bytes::from(header(&root.cid));
+
+        let ipld = decode_ipld(&root.cid, &root.data)?;
+        yield Bytes::from(section(&root));
+
+        let refs = ipfs::refs::iplds_refs(ipfs.clone(), vec![(root.cid, ipld)], None, true);
+        futures::pin_mut!(refs);
+
+        while let Some(edge) = refs.try_next().await? {
+            let block = ipfs.get_block(&edge.destination).await?;
+            yield Bytes::from(section(&block));
+        }
+    }
+}
+
+/// The varint prefixed dag-cbor header `{ roots: [root], version: 1 }`.
+fn header(root: &Cid) -> Vec<u8> {
+    let mut map = BTreeMap::new();
+    map.insert(
+        "roots".to_owned(),
+        Ipld::List(vec![Ipld::Link(root.to_owned())]),
+    );
+    map.insert("version".to_owned(), Ipld::Integer(1));
+
+    let encoded = DagCborCodec::encode(&Ipld::Map(map)).expect("the header can always be encoded");
+
+    let mut out = Vec::with_capacity(encoded.len() + 2);
+    write_varint(encoded.len() as u64, &mut out);
+    out.extend_from_slice(&encoded);
+    out
+}
+
+/// The varint prefixed Cid and data of the block.
+fn section(block: &Block) -> Vec<u8> {
+    let cid = block.cid.to_bytes();
+
+    let mut out = Vec::with_capacity(cid.len() + block.data.len() + 4);
+    write_varint((cid.len() + block.data.len()) as u64, &mut out);
+    out.extend_from_slice(&cid);
+    out.extend_from_slice(&block.data);
+    out
+}
+
+/// Writes the unsigned LEB128 varint used by the multiformats.
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_varint;
+
+    #[test]
+    fn varints() {
+        for &(value, expected) in &[
+            (0u64, &[0x00][..]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (300, &[0xac, 0x02]),
+        ] {
+            let mut out = Vec::new();
+            write_varint(value, &mut out);
+            assert_eq!(out, expected, "{}", value);
+        }
+    }
+}
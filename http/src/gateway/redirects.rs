@@ -0,0 +1,175 @@
+//! The `_redirects` file at the root of a website, with rules such as `/blog/* /posts/:splat 301`
+//! or `/* /index.html 200`, applied to the paths which are not found.
+
+use warp::http::StatusCode;
+
+/// The `_redirects` files larger than this are not read.
+pub(super) const MAX_SIZE: u64 = 64 * 1024;
+
+/// A rule of the `_redirects` file.
+#[derive(Debug, PartialEq)]
+pub(super) struct Rule {
+    /// The path with the `:name` placeholders and the `*` splat as the last segment.
+    from: String,
+    /// The target path or URL, where the placeholders and `:splat` are substituted.
+    to: String,
+    status: StatusCode,
+}
+
+/// Parses the rules, failing on the first invalid line.
+pub(super) fn parse(content: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+
+    for (nth, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |reason: &str| format!("line {}: {}", nth + 1, reason);
+
+        let mut fields = line.split_whitespace();
+        let from = fields.next().expect("the line is not empty");
+        let to = fields.next().ok_or_else(|| invalid("missing the target"))?;
+
+        let status = match fields.next() {
+            Some(status) => status
+                .parse::<u16>()
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .filter(|status| SUPPORTED.contains(&status.as_u16()))
+                .ok_or_else(|| invalid("unsupported status"))?,
+            None => StatusCode::MOVED_PERMANENTLY,
+        };
+
+        if fields.next().is_some() {
+            return Err(invalid("unexpected fields after the status"));
+        }
+
+        if !from.starts_with('/') {
+            return Err(invalid("the path does not start with a slash"));
+        }
+
+        let segments = from
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        if segments.iter().rev().skip(1).any(|&segment| segment == "*") {
+            return Err(invalid("the splat is not the last segment"));
+        }
+
+        // the rewrites are served from the same root
+        if !status.is_redirection() && !to.starts_with('/') {
+            return Err(invalid("the target of a rewrite is not a path"));
+        }
+
+        rules.push(Rule {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            status,
+        });
+    }
+
+    Ok(rules)
+}
+
+/// The status codes of the redirects and the rewrites.
+const SUPPORTED: &[u16] = &[200, 301, 302, 303, 307, 308, 404, 410, 451];
+
+/// Returns the target and the status of the first rule matching the path.
+pub(super) fn find(rules: &[Rule], path: &str) -> Option<(String, StatusCode)> {
+    rules.iter().find_map(|rule| {
+        matches(&rule.from, path).map(|mut bindings| {
+            // the longer names first so that `:a` does not replace the start of `:ab`
+            bindings.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+            let to = bindings.iter().fold(rule.to.clone(), |to, (name, value)| {
+                to.replace(&format!(":{}", name), value)
+            });
+
+            (to, rule.status)
+        })
+    })
+}
+
+/// Matches the path against the rule path, returning the values of the placeholders.
+fn matches<'a>(from: &'a str, path: &str) -> Option<Vec<(&'a str, String)>> {
+    let mut bindings = Vec::new();
+    let mut path = path.split('/').filter(|s| !s.is_empty());
+
+    for segment in from.split('/').filter(|s| !s.is_empty()) {
+        if segment == "*" {
+            bindings.push(("splat", path.collect::<Vec<_>>().join("/")));
+            return Some(bindings);
+        }
+
+        let actual = path.next()?;
+
+        if let Some(name) = segment.strip_prefix(':') {
+            bindings.push((name, actual.to_owned()));
+        } else if segment != actual {
+            return None;
+        }
+    }
+
+    match path.next() {
+        Some(_) => None,
+        None => Some(bindings),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, parse};
+    use warp::http::StatusCode;
+
+    #[test]
+    fn rules() {
+        let rules = parse(
+            "# comment\n\
+             /old /new\n\
+             /blog/:year/:month/:slug /posts/:year-:month/:slug 302\n\
+             /docs/* https://docs.example.com/:splat 308\n\
+             \n\
+             /missing/* /missing.html 404\n\
+             /* /index.html 200\n",
+        )
+        .unwrap();
+
+        let cases = &[
+            ("/old", "/new", 301),
+            ("/old/", "/new", 301),
+            ("/blog/2020/10/hello", "/posts/2020-10/hello", 302),
+            ("/docs/a/b.html", "https://docs.example.com/a/b.html", 308),
+            ("/docs", "https://docs.example.com/", 308),
+            ("/missing/page", "/missing.html", 404),
+            ("/blog/2020", "/index.html", 200),
+            ("/", "/index.html", 200),
+        ];
+
+        for &(path, to, status) in cases {
+            assert_eq!(
+                find(&rules, path),
+                Some((to.to_owned(), StatusCode::from_u16(status).unwrap())),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for content in &[
+            "/from",
+            "/from /to 999",
+            "/from /to 500",
+            "/from /to 301 extra",
+            "from /to",
+            "/*/a /to",
+            "/* https://example.com 200",
+        ] {
+            assert!(parse(content).is_err(), "{:?}", content);
+        }
+    }
+}
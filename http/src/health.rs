@@ -0,0 +1,112 @@
+//! The liveness and readiness probes of the node at `GET /healthz` and `GET /readyz`, which
+//! respond with `200 OK` or `503 Service Unavailable` and the [`ipfs::health::Health`] as JSON.
+
+use crate::v0::support::{with_ipfs, StringError};
+use ipfs::health::Health;
+use ipfs::supervisor::TaskState;
+use ipfs::{Ipfs, IpfsTypes};
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// The probe routes, where the node is ready once it has at least `min_routing_table_peers` peers
+/// in the DHT routing table.
+pub fn routes<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    min_routing_table_peers: usize,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let healthz = warp::path!("healthz")
+        .and(with_ipfs(ipfs))
+        .and_then(|ipfs| probe(ipfs, Health::is_live));
+
+    let readyz = warp::path!("readyz")
+        .and(with_ipfs(ipfs))
+        .and_then(move |ipfs| {
+            probe(ipfs, move |health: &Health| {
+                health.is_ready(min_routing_table_peers)
+            })
+        });
+
+    warp::get().and(healthz.or(readyz).unify())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response {
+    repo_open: bool,
+    listening: usize,
+    bootstrapped: bool,
+    routing_table_peers: usize,
+    failed_tasks: Vec<&'static str>,
+}
+
+async fn probe<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    passes: impl Fn(&Health) -> bool,
+) -> Result<warp::reply::Response, Rejection> {
+    let health = ipfs.health().await.map_err(StringError::from)?;
+
+    let status = if passes(&health) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let Health {
+        repo_open,
+        listening,
+        bootstrapped,
+        routing_table_peers,
+        tasks,
+    } = health;
+
+    let failed_tasks = tasks
+        .into_iter()
+        .filter(|(_, state)| *state == TaskState::Failed)
+        .map(|(name, _)| name)
+        .collect();
+
+    let response = Response {
+        repo_open,
+        listening,
+        bootstrapped,
+        routing_table_peers,
+        failed_tasks,
+    };
+
+    Ok(warp::reply::with_status(warp::reply::json(&response), status).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use ipfs::Node;
+
+    #[tokio::test(max_threads = 1)]
+    async fn probes() {
+        let ipfs = Node::new("test_node").await;
+
+        let response = warp::test::request()
+            .path("/healthz")
+            .reply(&routes(&*ipfs, 1))
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.body(),
+            &br#"{"RepoOpen":true,"Listening":1,"Bootstrapped":true,"RoutingTablePeers":0}"#[..]
+        );
+
+        // there are no peers in the routing table of the lone node
+        let response = warp::test::request()
+            .path("/readyz")
+            .reply(&routes(&*ipfs, 1))
+            .await;
+        assert_eq!(response.status(), 503);
+
+        let response = warp::test::request()
+            .path("/readyz")
+            .reply(&routes(&*ipfs, 0))
+            .await;
+        assert_eq!(response.status(), 200);
+    }
+}
@@ -8,4 +8,15 @@ extern crate tracing;
 
 pub mod v0;
 
+pub mod gateway;
+
+pub mod metrics;
+
+pub mod health;
+
+pub mod logging;
+
+#[cfg(feature = "http-client")]
+pub mod client;
+
 pub mod config;
@@ -0,0 +1,212 @@
+//! The global tracing subscriber of the daemon, with a [`LogFilter`] handle for changing the log
+//! levels of the modules at runtime, such as through `/api/v0/log/level`. With the `otlp` feature
+//! the spans are also exported to the OpenTelemetry collector at `OTEL_EXPORTER_OTLP_ENDPOINT`.
+//! Only the traces are exported; the collector can scrape the metrics from `/metrics` with its
+//! `prometheus` receiver.
+
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::util::SubscriberInitExt;
+
+type Reload = dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync;
+
+/// Handle to the filter of the installed subscriber, shared by the clones.
+#[derive(Clone)]
+pub struct LogFilter {
+    /// The directives of the current filter, such as `ipfs=debug`.
+    directives: Arc<Mutex<Vec<String>>>,
+    reload: Arc<Reload>,
+    /// Flushes and stops the export when the last clone is dropped.
+    #[cfg(feature = "otlp")]
+    _exporter: Option<Arc<opentelemetry_otlp::Uninstall>>,
+}
+
+impl std::fmt::Debug for LogFilter {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("LogFilter")
+            .field("directives", &self.directives())
+            .finish()
+    }
+}
+
+impl LogFilter {
+    /// Installs the global subscriber writing to the standard output, with the filter from
+    /// `RUST_LOG` or the `default` directives when it is not set.
+    pub fn init(default: &str) -> Self {
+        let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| default.to_owned());
+
+        // the standard output is left for the output of the commands
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new(&directives))
+            .with_writer(std::io::stderr)
+            .with_filter_reloading();
+        let handle = builder.reload_handle();
+        let subscriber = builder.finish();
+
+        #[cfg(feature = "otlp")]
+        let (subscriber, exporter) = {
+            use tracing_subscriber::layer::SubscriberExt;
+
+            let (tracer, exporter) = otlp::tracer();
+            let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+            (subscriber.with(layer), exporter)
+        };
+
+        subscriber.init();
+
+        #[allow(unused_mut)]
+        let mut filter = LogFilter::new(
+            &directives,
+            Arc::new(move |filter| handle.reload(filter).map_err(|e| e.to_string())),
+        );
+
+        #[cfg(feature = "otlp")]
+        {
+            filter._exporter = exporter.map(Arc::new);
+        }
+
+        filter
+    }
+
+    pub(crate) fn new(directives: &str, reload: Arc<Reload>) -> Self {
+        let directives = directives
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(String::from)
+            .collect();
+
+        LogFilter {
+            directives: Arc::new(Mutex::new(directives)),
+            reload,
+            #[cfg(feature = "otlp")]
+            _exporter: None,
+        }
+    }
+
+    /// Returns the directives of the current filter.
+    pub fn directives(&self) -> Vec<String> {
+        self.directives.lock().unwrap().clone()
+    }
+
+    /// Sets the level of the target, such as `ipfs_bitswap` or `ipfs::p2p`, replacing the earlier
+    /// directive for the target. The target `all` replaces all of the directives with the level.
+    pub fn set_level(&self, target: &str, level: &str) -> Result<(), String> {
+        let level = level
+            .parse::<LevelFilter>()
+            .map_err(|_| format!("invalid level: {:?}", level))?;
+
+        if target.is_empty() || target.contains(|c: char| c == ',' || c == '=') {
+            return Err(format!("invalid target: {:?}", target));
+        }
+
+        let mut directives = self.directives.lock().unwrap();
+
+        let updated = if target == "all" {
+            vec![level.to_string()]
+        } else {
+            let mut updated = directives
+                .iter()
+                .filter(|directive| directive.split('=').next() != Some(target))
+                .cloned()
+                .collect::<Vec<_>>();
+            updated.push(format!("{}={}", target, level));
+            updated
+        };
+
+        let filter = EnvFilter::try_new(updated.join(",")).map_err(|e| e.to_string())?;
+        (self.reload)(filter)?;
+
+        *directives = updated;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::sdk::trace::{Config, Tracer, TracerProvider};
+    use opentelemetry::sdk::Resource;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::Uninstall;
+
+    /// Returns the tracer exporting to the collector at `OTEL_EXPORTER_OTLP_ENDPOINT`, or a tracer
+    /// which drops the spans when the variable is not set or the exporter cannot be installed.
+    pub(super) fn tracer() -> (Tracer, Option<Uninstall>) {
+        let endpoint = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            Ok(endpoint) if !endpoint.is_empty() => endpoint,
+            _ => return (discarding(), None),
+        };
+
+        let config = Config::default().with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            env!("CARGO_PKG_NAME"),
+        )]));
+
+        let installed = opentelemetry_otlp::new_pipeline()
+            .with_endpoint(&endpoint)
+            .with_trace_config(config)
+            .install();
+
+        match installed {
+            Ok((tracer, uninstall)) => (tracer, Some(uninstall)),
+            Err(e) => {
+                // the subscriber is not installed yet, so this cannot be logged
+                eprintln!("Failed to export the traces to {}: {}", endpoint, e);
+                (discarding(), None)
+            }
+        }
+    }
+
+    fn discarding() -> Tracer {
+        TracerProvider::builder()
+            .build()
+            .get_tracer(env!("CARGO_PKG_NAME"), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogFilter;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn setting_levels() {
+        let reloaded = Arc::new(Mutex::new(Vec::new()));
+        let filter = {
+            let reloaded = Arc::clone(&reloaded);
+            LogFilter::new(
+                "ipfs=trace, ipfs_bitswap=info,warn",
+                Arc::new(move |filter| {
+                    reloaded.lock().unwrap().push(filter.to_string());
+                    Ok(())
+                }),
+            )
+        };
+
+        assert_eq!(
+            filter.directives(),
+            &["ipfs=trace", "ipfs_bitswap=info", "warn"]
+        );
+
+        filter.set_level("ipfs_bitswap", "trace").unwrap();
+        filter.set_level("ipfs::p2p", "DEBUG").unwrap();
+        assert_eq!(
+            filter.directives(),
+            &[
+                "ipfs=trace",
+                "warn",
+                "ipfs_bitswap=trace",
+                "ipfs::p2p=debug"
+            ]
+        );
+
+        filter.set_level("all", "error").unwrap();
+        assert_eq!(filter.directives(), &["error"]);
+        assert_eq!(reloaded.lock().unwrap().len(), 3);
+
+        assert!(filter.set_level("ipfs", "loud").is_err());
+        assert!(filter.set_level("ipfs=info", "info").is_err());
+        assert_eq!(filter.directives(), &["error"]);
+    }
+}
@@ -1,10 +1,15 @@
+use futures::future::{BoxFuture, FutureExt};
+use std::future::Future;
 use std::num::NonZeroU16;
 use std::path::PathBuf;
 use structopt::StructOpt;
+use warp::{Filter, Rejection, Reply};
 
-use ipfs::{Ipfs, IpfsOptions, IpfsTypes, UninitializedIpfs};
-use ipfs_http::{config, v0};
+use ipfs::api::CoreApi;
+use ipfs::{Block, Cid, Ipfs, IpfsOptions, IpfsPath, IpfsTypes, UninitializedIpfs};
+use ipfs_http::{client::Client, config, gateway, health, logging, metrics, v0};
 use parity_multiaddr::{Multiaddr, Protocol};
+use std::path::Path;
 
 #[macro_use]
 extern crate tracing;
@@ -24,23 +29,44 @@ enum Options {
         #[structopt(long, use_delimiter = true)]
         profile: Vec<config::Profile>,
     },
-    /// Start the IPFS node in the foreground (not detaching from parent process).
-    Daemon,
+    /// Start the IPFS node in the foreground (not detaching from parent process). The first
+    /// SIGINT or SIGTERM shuts the node down gracefully, and the second one right away.
+    Daemon {
+        /// Do not listen on the swarm addresses, so that no other peers connect to the node.
+        #[structopt(long)]
+        offline: bool,
+    },
+    /// Show the peer id of the node.
+    Id,
+    /// Add the file, or the standard input, and print the Cid of its root.
+    Add { file: Option<PathBuf> },
+    /// Print the content of the file at the IPFS path.
+    Cat { path: IpfsPath },
+    /// Get and put raw blocks.
+    Block(BlockCommand),
 }
 
-fn main() {
-    if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var(
-            "RUST_LOG",
-            "ipfs_http=trace,ipfs=trace,bitswap=trace,ipfs_unixfs=trace",
-        );
-    }
+/// The commands other than `init` and `daemon` run against the daemon when one is running with
+/// the same repo, and otherwise open the repo directly, like in go-ipfs.
+#[derive(Debug, StructOpt)]
+enum BlockCommand {
+    /// Print the data of the block.
+    Get { cid: Cid },
+    /// Store the file, or the standard input, as a block and print its Cid.
+    Put { file: Option<PathBuf> },
+}
 
-    tracing_subscriber::fmt::init();
+fn main() {
+    // the levels can be changed at runtime through /api/v0/log/level
+    let log_filter =
+        logging::LogFilter::init("ipfs_http=trace,ipfs=trace,bitswap=trace,ipfs_unixfs=trace");
 
     let opts = Options::from_args();
 
-    println!("Invoked with args: {:?}", opts);
+    if matches!(opts, Options::Init { .. } | Options::Daemon { .. }) {
+        // the output of the other commands is their result
+        println!("Invoked with args: {:?}", opts);
+    }
 
     // go-ipfs seems to deduce like this
     let home = std::env::var_os("IPFS_PATH")
@@ -63,6 +89,8 @@ fn main() {
 
     let config_path = home.join("config");
 
+    let offline = matches!(opts, Options::Daemon { offline: true });
+
     let config = match opts {
         Options::Init { bits, profile } => {
             println!("initializing IPFS node at {:?}", home);
@@ -110,26 +138,13 @@ fn main() {
                 }
             }
         }
-        Options::Daemon => {
-            if !config_path.is_file() {
-                eprintln!("Error: no IPFS repo found in {:?}", home);
-                eprintln!("please run: 'ipfs init'");
-                std::process::exit(1);
-            }
-
-            std::fs::File::open(config_path)
-                .map_err(config::LoadingError::ConfigurationFileOpening)
-                .and_then(config::load)
-                .unwrap()
-        }
+        Options::Daemon { .. } => load_config(&home, &config_path),
+        command => std::process::exit(run_command(&home, &config_path, command)),
     };
 
     println!("IPFS_PATH: {:?}", home);
     println!("Process id: {}", std::process::id());
 
-    // TODO: sigterm should initiate graceful shutdown, second time should shutdown right now
-    // NOTE: sigkill ... well surely it will stop the process right away
-
     let mut rt = tokio::runtime::Runtime::new().expect("Failed to create event loop");
 
     rt.block_on(async move {
@@ -139,8 +154,19 @@ fn main() {
             bootstrap: Vec::new(),
             mdns: false,
             kad_protocol: None,
-            listening_addrs: config.swarm,
+            kad_query: Default::default(),
+            pubsub: Default::default(),
+            listening_addrs: if offline { Vec::new() } else { config.swarm },
             span: None,
+            ipns_cache: Default::default(),
+            // twice a day, as go-ipfs does
+            reprovide_interval: if offline {
+                None
+            } else {
+                Some(std::time::Duration::from_secs(12 * 60 * 60))
+            },
+            connection_limits: Default::default(),
+            swarm_key: load_swarm_key(&home),
         };
 
         let (ipfs, task): (Ipfs<ipfs::Types>, _) = UninitializedIpfs::new(opts)
@@ -152,14 +178,55 @@ fn main() {
 
         let api_link_file = home.join("api");
 
-        let (addr, server) = serve(&ipfs, config.api_addr);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::channel::<()>(1);
+
+        tokio::spawn(shutdown_on_signals(shutdown_tx.clone()));
+
+        let (stop_gateway, gateway_stopped) = tokio::sync::oneshot::channel::<()>();
+
+        let gateway = if let Some(gateway_addr) = config.gateway_addr {
+            let mut options = gateway::GatewayOptions::default()
+                .with_writable(config.gateway_writable)
+                .with_dnslink(config.gateway_dnslink);
+            if let Some(host) = config.gateway_subdomain_host {
+                options = options.with_subdomains(host);
+            }
+
+            let routes = gateway::routes(&ipfs, options).with(warp::log(env!("CARGO_PKG_NAME")));
+            let (addr, gateway) = serve_at(routes, &gateway_addr, async move {
+                let _ = gateway_stopped.await;
+            });
+            println!("Gateway listening on {}", addr);
+            Some(tokio::spawn(gateway))
+        } else {
+            None
+        };
+
+        // the gateway stops taking new requests and completes the ongoing ones before the node
+        // exits
+        let drain_gateway = async move {
+            let _ = stop_gateway.send(());
+            if let Some(gateway) = gateway {
+                let _ = gateway.await;
+            }
+        };
+
+        let (addr, server) = serve(
+            &ipfs,
+            config.api_addr,
+            &config.api_access,
+            config.api_metrics,
+            config.api_min_routing_table_peers,
+            log_filter,
+            (shutdown_tx, shutdown_rx, drain_gateway),
+        );
 
         // shutdown future will handle signalling the exit
         drop(ipfs);
 
         // We can't simply reuse the address from the config as the test profile uses ephemeral
         // ports.
-        let api_multiaddr = format!("/ip4/{}/tcp/{}", addr.ip(), addr.port());
+        let api_multiaddr = addr.to_string();
 
         // this file is looked for when js-ipfsd-ctl checks optimistically if the IPFS_PATH has a
         // daemon running already. go-ipfs file does not contain newline at the end.
@@ -184,34 +251,390 @@ fn main() {
     info!("Shutdown complete");
 }
 
+/// Reads the key of the private network from the `swarm.key` of the repo, if there is one.
+fn load_swarm_key(home: &Path) -> Option<ipfs::p2p::PreSharedKey> {
+    match ipfs::p2p::read_swarm_key(home) {
+        Ok(swarm_key) => swarm_key,
+        Err(e) => {
+            eprintln!("Error: failed to read the swarm.key in {:?}: {}", home, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_config(home: &Path, config_path: &Path) -> config::Config {
+    if !config_path.is_file() {
+        eprintln!("Error: no IPFS repo found in {:?}", home);
+        eprintln!("please run: 'ipfs init'");
+        std::process::exit(1);
+    }
+
+    std::fs::File::open(config_path)
+        .map_err(config::LoadingError::ConfigurationFileOpening)
+        .and_then(config::load)
+        .unwrap()
+}
+
+/// Runs the command through the API of the running daemon, or with the repo opened offline when
+/// there is no daemon. Returns the exit code.
+fn run_command(home: &Path, config_path: &Path, command: Options) -> i32 {
+    let mut rt = tokio::runtime::Runtime::new().expect("Failed to create event loop");
+
+    rt.block_on(async move {
+        let result = match running_daemon(home) {
+            Ok(Some(client)) => execute(&client, command).await,
+            Ok(None) => {
+                let config = load_config(home, config_path);
+
+                let opts = IpfsOptions {
+                    ipfs_path: home.to_owned(),
+                    keypair: config.keypair,
+                    bootstrap: Vec::new(),
+                    mdns: false,
+                    kad_protocol: None,
+                    kad_query: Default::default(),
+                    pubsub: Default::default(),
+                    listening_addrs: Vec::new(),
+                    span: None,
+                    ipns_cache: Default::default(),
+                    reprovide_interval: None,
+                    connection_limits: Default::default(),
+                    swarm_key: load_swarm_key(home),
+                };
+
+                let (ipfs, task): (Ipfs<ipfs::Types>, _) = UninitializedIpfs::new(opts)
+                    .start()
+                    .await
+                    .expect("Initialization failed");
+
+                let task = tokio::spawn(task);
+                let result = execute(&Offline(&ipfs), command).await;
+
+                ipfs.exit_daemon().await;
+                let _ = task.await;
+
+                result
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                1
+            }
+        }
+    })
+}
+
+/// Returns the client for the daemon running with the repo, found from the `api` file which the
+/// daemon writes when it starts and truncates when it shuts down.
+fn running_daemon(home: &Path) -> Result<Option<Client>, ipfs::Error> {
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+
+    let addr = match std::fs::read_to_string(home.join("api")) {
+        Ok(addr) if !addr.trim().is_empty() => addr,
+        _ => return Ok(None),
+    };
+
+    let addr = addr.trim();
+
+    if addr.starts_with("/unix/") {
+        return running_daemon_at_unix(&addr["/unix".len()..]);
+    }
+
+    let addr = addr.parse::<Multiaddr>()?;
+    let socket_addr = match addr.iter().collect::<Vec<_>>().as_slice() {
+        [Protocol::Ip4(ip), Protocol::Tcp(port)] => SocketAddr::new((*ip).into(), *port),
+        [Protocol::Ip6(ip), Protocol::Tcp(port)] => SocketAddr::new((*ip).into(), *port),
+        _ => return Err(anyhow::anyhow!("unsupported API address: {}", addr)),
+    };
+
+    // the file is left behind by a daemon which did not shut down cleanly
+    if TcpStream::connect_timeout(&socket_addr, Duration::from_secs(1)).is_err() {
+        return Ok(None);
+    }
+
+    Ok(Some(Client::new(&format!("http://{}", socket_addr))?))
+}
+
+#[cfg(unix)]
+fn running_daemon_at_unix(path: &str) -> Result<Option<Client>, ipfs::Error> {
+    // the socket is left behind by a daemon which did not shut down cleanly
+    if std::os::unix::net::UnixStream::connect(path).is_err() {
+        return Ok(None);
+    }
+
+    Ok(Some(Client::unix(path)))
+}
+
+#[cfg(not(unix))]
+fn running_daemon_at_unix(path: &str) -> Result<Option<Client>, ipfs::Error> {
+    Err(anyhow::anyhow!(
+        "the daemon is listening on the unix socket {}, which is not supported on this platform",
+        path
+    ))
+}
+
+/// How long the commands run with the repo opened offline wait for the next block, as the blocks
+/// missing from the repo would otherwise be waited for until they are found from the other peers.
+const OFFLINE_BLOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The node opened for a single command, which does not wait for the missing blocks.
+struct Offline<'a>(&'a Ipfs<ipfs::Types>);
+
+#[async_trait::async_trait]
+impl CoreApi for Offline<'_> {
+    async fn peer_id(&self) -> Result<ipfs::PeerId, ipfs::Error> {
+        CoreApi::peer_id(self.0).await
+    }
+
+    async fn put_block(&self, block: Block) -> Result<Cid, ipfs::Error> {
+        CoreApi::put_block(self.0, block).await
+    }
+
+    async fn get_block(&self, cid: &Cid) -> Result<Block, ipfs::Error> {
+        tokio::time::timeout(OFFLINE_BLOCK_TIMEOUT, CoreApi::get_block(self.0, cid))
+            .await
+            .map_err(|_| anyhow::anyhow!("block {} is not in the repo", cid))?
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, ipfs::Error> {
+        CoreApi::add(self.0, data).await
+    }
+
+    async fn cat(&self, path: &IpfsPath) -> Result<Vec<u8>, ipfs::Error> {
+        use futures::stream::StreamExt;
+
+        let not_found = || anyhow::anyhow!("{} is not completely in the repo", path);
+
+        let content = tokio::time::timeout(
+            OFFLINE_BLOCK_TIMEOUT,
+            self.0.cat_unixfs(path.to_owned(), None),
+        )
+        .await
+        .map_err(|_| not_found())??;
+        futures::pin_mut!(content);
+
+        let mut data = Vec::new();
+        loop {
+            match tokio::time::timeout(OFFLINE_BLOCK_TIMEOUT, content.next()).await {
+                Ok(Some(chunk)) => data.extend_from_slice(&chunk?),
+                Ok(None) => return Ok(data),
+                Err(_) => return Err(not_found()),
+            }
+        }
+    }
+}
+
+async fn execute(api: &dyn CoreApi, command: Options) -> Result<(), ipfs::Error> {
+    use std::io::Write;
+
+    match command {
+        Options::Id => println!("{}", api.peer_id().await?),
+        Options::Add { file } => {
+            let data = read_input(file)?;
+            println!("added {}", api.add(data).await?);
+        }
+        Options::Cat { path } => std::io::stdout().write_all(&api.cat(&path).await?)?,
+        Options::Block(BlockCommand::Get { cid }) => {
+            std::io::stdout().write_all(&api.get_block(&cid).await?.data)?
+        }
+        Options::Block(BlockCommand::Put { file }) => {
+            let data = read_input(file)?;
+            let cid = Cid::new_v0(multihash::Sha2_256::digest(&data))?;
+            println!("{}", api.put_block(Block::new(data, cid)).await?);
+        }
+        Options::Init { .. } | Options::Daemon { .. } => unreachable!("not a command"),
+    }
+
+    Ok(())
+}
+
+/// Reads the file, or the standard input when there is no file.
+fn read_input(file: Option<PathBuf>) -> Result<Vec<u8>, std::io::Error> {
+    use std::io::Read;
+
+    match file {
+        Some(file) => std::fs::read(file),
+        None => {
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+    }
+}
+
 fn serve<Types: IpfsTypes>(
     ipfs: &Ipfs<Types>,
     listening_addr: Multiaddr,
-) -> (std::net::SocketAddr, impl std::future::Future<Output = ()>) {
-    use std::net::SocketAddr;
+    access: &v0::AccessOptions,
+    metrics_enabled: bool,
+    min_routing_table_peers: usize,
+    log_filter: logging::LogFilter,
+    (shutdown_tx, mut shutdown_rx, before_exit): (
+        tokio::sync::mpsc::Sender<()>,
+        tokio::sync::mpsc::Receiver<()>,
+        impl Future<Output = ()> + Send + 'static,
+    ),
+) -> (Multiaddr, BoxFuture<'static, ()>) {
     use tokio::stream::StreamExt;
-    use warp::Filter;
 
-    let (shutdown_tx, mut shutdown_rx) = tokio::sync::mpsc::channel::<()>(1);
+    let metrics = warp::any()
+        .and_then(move || async move {
+            if metrics_enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(metrics::routes(ipfs, access));
+
+    let probes = health::routes(ipfs, min_routing_table_peers);
 
-    let routes = v0::routes(ipfs, shutdown_tx);
+    let routes = metrics
+        .or(probes)
+        .or(v0::routes(ipfs, shutdown_tx, access, Some(log_filter)));
     let routes = routes.with(warp::log(env!("CARGO_PKG_NAME")));
 
     let ipfs = ipfs.clone();
 
+    serve_at(routes, &listening_addr, async move {
+        shutdown_rx.next().await;
+        info!("Shutdown trigger received; starting shutdown");
+        before_exit.await;
+        ipfs.exit_daemon().await;
+    })
+}
+
+/// Starts the graceful shutdown, the same as `/api/v0/shutdown`, on the first SIGINT or SIGTERM,
+/// and exits right away on the second one.
+async fn shutdown_on_signals(mut shutdown_tx: tokio::sync::mpsc::Sender<()>) {
+    signalled().await;
+    info!("Signal received; starting shutdown, signal again to exit right away");
+    let _ = shutdown_tx.send(()).await;
+
+    signalled().await;
+    info!("Signal received again; exiting");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+async fn signalled() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate =
+        signal(SignalKind::terminate()).expect("Failed to listen for the SIGTERM signals");
+
+    futures::future::select(tokio::signal::ctrl_c().boxed(), terminate.recv().boxed()).await;
+}
+
+#[cfg(not(unix))]
+async fn signalled() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Where the API or the gateway listens.
+enum Listener {
+    Tcp(std::net::SocketAddr),
+    Unix(PathBuf),
+}
+
+fn to_listener(listening_addr: &Multiaddr) -> Listener {
+    use std::net::SocketAddr;
+
     let components = listening_addr.iter().collect::<Vec<_>>();
 
-    let socket_addr = match components.as_slice() {
-        [Protocol::Ip4(ip), Protocol::Tcp(port)] => SocketAddr::new(ip.clone().into(), *port),
+    match components.as_slice() {
+        [Protocol::Ip4(ip), Protocol::Tcp(port)] => {
+            Listener::Tcp(SocketAddr::new(ip.clone().into(), *port))
+        }
+        // the path is stored without the leading slash, see `config::parse_listening_addr`
+        [Protocol::Unix(path)] => Listener::Unix(PathBuf::from("/").join(path.as_ref())),
         _ => panic!(
             "Couldn't convert MultiAddr into SocketAddr: {}",
             listening_addr
         ),
+    }
+}
+
+/// Serves the routes at the TCP or the unix socket address until the `shutdown` completes.
+/// Returns the address listened on, which differs from the configured one for the ephemeral
+/// ports, and the future of the server.
+fn serve_at<F, R>(
+    routes: F,
+    listening_addr: &Multiaddr,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> (Multiaddr, BoxFuture<'static, ()>)
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    match to_listener(listening_addr) {
+        Listener::Tcp(socket_addr) => {
+            let (addr, server) =
+                warp::serve(routes).bind_with_graceful_shutdown(socket_addr, shutdown);
+            let addr = Multiaddr::from(addr.ip()).with(Protocol::Tcp(addr.port()));
+            (addr, server.boxed())
+        }
+        Listener::Unix(path) => serve_unix(routes, path, shutdown),
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix<F, R>(
+    routes: F,
+    path: PathBuf,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> (Multiaddr, BoxFuture<'static, ()>)
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    use std::os::unix::fs::FileTypeExt;
+
+    // a socket left behind by a daemon which did not shut down cleanly
+    let stale = std::fs::symlink_metadata(&path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    if stale {
+        let _ = std::fs::remove_file(&path);
+    }
+
+    let listener = tokio::net::UnixListener::bind(&path)
+        .unwrap_or_else(|e| panic!("Couldn't bind to {:?}: {}", path, e));
+
+    let addr = Multiaddr::empty().with(Protocol::Unix(
+        path.to_string_lossy()
+            .trim_start_matches('/')
+            .to_owned()
+            .into(),
+    ));
+
+    let server = warp::serve(routes).serve_incoming(listener);
+
+    let server = async move {
+        futures::future::select(server.boxed(), shutdown.boxed()).await;
+
+        if let Err(e) = std::fs::remove_file(&path) {
+            info!("Failed to remove the socket {:?}: {}", path, e);
+        }
     };
 
-    warp::serve(routes).bind_with_graceful_shutdown(socket_addr, async move {
-        shutdown_rx.next().await;
-        info!("Shutdown trigger received; starting shutdown");
-        ipfs.exit_daemon().await;
-    })
+    (addr, server.boxed())
+}
+
+#[cfg(not(unix))]
+fn serve_unix<F, R>(
+    _routes: F,
+    path: PathBuf,
+    _shutdown: impl Future<Output = ()> + Send + 'static,
+) -> (Multiaddr, BoxFuture<'static, ()>) {
+    panic!(
+        "Unix sockets are not supported on this platform: {:?}",
+        path
+    )
 }
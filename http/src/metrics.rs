@@ -0,0 +1,157 @@
+//! Prometheus metrics of the node at `GET /metrics`, gathered from bitswap, the swarm and the DHT
+//! each time the metrics are scraped, followed by the metrics which the subsystems keep in
+//! [`ipfs::metrics::Registry`], such as the number of blocks in the repo.
+
+use crate::v0::support::{with_ipfs, StringError};
+use crate::v0::AccessOptions;
+use ipfs::{Ipfs, IpfsTypes};
+use warp::{Filter, Rejection, Reply};
+
+/// The metrics route, which is not enabled by default. The same remote addresses and tokens are
+/// allowed as for the API, and the read-only tokens are enough for scraping.
+pub fn routes<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+    access: &AccessOptions,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    access
+        .allowed_addresses()
+        .and(warp::get())
+        .and(access.authorized())
+        .and(warp::path!("metrics"))
+        .and(with_ipfs(ipfs))
+        .and_then(metrics)
+}
+
+async fn metrics<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let bitswap = ipfs.bitswap_stats().await.map_err(StringError::from)?;
+    let peers = ipfs.peers().await.map_err(StringError::from)?;
+    let listening = ipfs.addrs_local().await.map_err(StringError::from)?;
+    let dht = ipfs.dht_stats().await.map_err(StringError::from)?;
+
+    // the values kept by bitswap, the swarm and the DHT are copied into a registry of the scrape
+    let out = ipfs::metrics::Registry::default();
+
+    let counters = [
+        (
+            "ipfs_bitswap_blocks_sent_total",
+            "Blocks sent to other peers",
+            bitswap.blocks_sent,
+        ),
+        (
+            "ipfs_bitswap_data_sent_bytes_total",
+            "Bytes sent in blocks to other peers",
+            bitswap.data_sent,
+        ),
+        (
+            "ipfs_bitswap_blocks_received_total",
+            "Blocks received from other peers",
+            bitswap.blocks_received,
+        ),
+        (
+            "ipfs_bitswap_data_received_bytes_total",
+            "Bytes received in blocks from other peers",
+            bitswap.data_received,
+        ),
+        (
+            "ipfs_bitswap_duplicate_blocks_received_total",
+            "Blocks received which had already been received",
+            bitswap.dup_blks_received,
+        ),
+        (
+            "ipfs_bitswap_duplicate_data_received_bytes_total",
+            "Bytes received in blocks which had already been received",
+            bitswap.dup_data_received,
+        ),
+    ];
+
+    for (name, help, value) in counters.iter() {
+        out.counter(name, help).inc_by(*value);
+    }
+
+    let gauges = [
+        ("ipfs_bitswap_peers", "Bitswap peers", bitswap.peers.len()),
+        (
+            "ipfs_bitswap_wantlist_blocks",
+            "Blocks in the wantlist of the node",
+            bitswap.wantlist.len(),
+        ),
+        ("ipfs_swarm_peers", "Connected peers", peers.len()),
+        (
+            "ipfs_swarm_listen_addresses",
+            "Addresses the swarm is listening on",
+            listening.len(),
+        ),
+        (
+            "ipfs_dht_routing_table_peers",
+            "Peers in the DHT routing table",
+            dht.routing_table_peers,
+        ),
+    ];
+
+    for (name, help, value) in gauges.iter() {
+        out.gauge(name, help).set(*value as i64);
+    }
+
+    let mut body = out.encode();
+    body.push_str(&ipfs.metrics().encode());
+
+    Ok(warp::reply::with_header(
+        body,
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use crate::v0::{AccessOptions, Scope};
+    use cid::Cid;
+    use ipfs::{Block, Node};
+    use std::convert::TryFrom;
+
+    #[tokio::test(max_threads = 1)]
+    async fn metrics_in_text_format() {
+        let ipfs = Node::new("test_node").await;
+
+        let cid =
+            Cid::try_from("bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4").unwrap();
+        ipfs.put_block(Block::new(b"foobar\n".to_vec().into_boxed_slice(), cid))
+            .await
+            .unwrap();
+
+        let response = warp::test::request()
+            .path("/metrics")
+            .reply(&routes(&*ipfs, &AccessOptions::default()))
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(body.contains("# TYPE ipfs_repo_blocks gauge\nipfs_repo_blocks 1\n"));
+        assert!(body.contains("# TYPE ipfs_bitswap_blocks_sent_total counter\n"));
+        assert!(body.contains("# TYPE ipfs_dht_routing_table_peers gauge\n"));
+        assert!(body.contains(
+            "# TYPE ipfs_repo_blocks_stored_total counter\nipfs_repo_blocks_stored_total 1\n"
+        ));
+        assert!(body.contains("# TYPE ipfs_dht_query_duration_seconds histogram\n"));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn same_access_as_the_api() {
+        let ipfs = Node::new("test_node").await;
+
+        let access = AccessOptions::default().with_tokens(vec![("reader".into(), Scope::ReadOnly)]);
+        let routes = routes(&*ipfs, &access);
+
+        let response = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_ne!(response.status(), 200);
+
+        let response = warp::test::request()
+            .path("/metrics")
+            .header("authorization", "Bearer reader")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+    }
+}
@@ -2,9 +2,11 @@
 //!
 //! See https://docs.ipfs.io/reference/http/api/ for more information.
 
+use crate::logging::LogFilter;
 use ipfs::{Ipfs, IpfsTypes};
 use warp::{query, Filter};
 
+pub mod access;
 pub mod bitswap;
 pub mod block;
 pub mod bootstrap;
@@ -12,6 +14,7 @@ pub mod dag;
 pub mod dht;
 pub mod id;
 pub mod ipns;
+pub mod log;
 pub mod pin;
 pub mod pubsub;
 pub mod refs;
@@ -20,6 +23,7 @@ pub mod swarm;
 pub mod version;
 
 pub mod support;
+pub use access::{AccessOptions, Scope};
 pub use support::recover_as_message_response;
 pub(crate) use support::{with_ipfs, InvalidPeerId, NotImplemented, StringError};
 
@@ -64,12 +68,19 @@ macro_rules! and_boxed {
     };
 }
 
-/// Supported routes of the crate.
+/// Supported routes of the crate. The `log` endpoints are not implemented without the
+/// `log_filter` of the installed subscriber.
 pub fn routes<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
     shutdown_tx: tokio::sync::mpsc::Sender<()>,
+    access: &AccessOptions,
+    log_filter: Option<LogFilter>,
 ) -> impl warp::Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    let mount = warp::post().and(warp::path!("api" / "v0" / ..));
+    let mount = access
+        .allowed_addresses()
+        .and(warp::post())
+        .and(access.authorized())
+        .and(warp::path!("api" / "v0" / ..));
 
     let api = mount.and(combine!(
         and_boxed!(
@@ -110,6 +121,7 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("rm" / "all"), bootstrap::bootstrap_clear(ipfs)),
         )),
         warp::path("dag").and(combine!(
+            and_boxed!(warp::path!("get"), dag::get(ipfs)),
             and_boxed!(warp::path!("put"), dag::put(ipfs)),
             and_boxed!(warp::path!("resolve"), dag::resolve(ipfs)),
         )),
@@ -119,6 +131,10 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("provide"), dht::provide(ipfs)),
             and_boxed!(warp::path!("query"), dht::get_closest_peers(ipfs)),
         )),
+        warp::path("log").and(combine!(
+            and_boxed!(warp::path!("level"), log::level(log_filter.clone())),
+            and_boxed!(warp::path!("ls"), log::ls(log_filter)),
+        )),
         warp::path("pubsub").and(combine!(
             and_boxed!(warp::path!("peers"), pubsub::peers(ipfs)),
             and_boxed!(warp::path!("ls"), pubsub::list_subscriptions(ipfs)),
@@ -151,7 +167,7 @@ pub fn routes<T: IpfsTypes>(
         warp::path!("stats" / ..).and_then(not_implemented),
     ));
 
-    api.recover(recover_as_message_response)
+    api.recover(recover_as_message_response).with(access.cors())
 }
 
 pub(crate) async fn handle_shutdown(
@@ -182,7 +198,7 @@ mod tests {
 
         let (shutdown_tx, _) = tokio::sync::mpsc::channel::<()>(1);
 
-        routes(&ipfs, shutdown_tx)
+        routes(&ipfs, shutdown_tx, &Default::default(), None)
     }
 
     #[tokio::test(max_threads = 1)]
@@ -0,0 +1,356 @@
+//! Access control of the API: the CORS configuration for the browsers, the allow-list of the
+//! remote addresses, and the bearer tokens with their scopes.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use warp::http::header::HeaderName;
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::{Filter, Rejection};
+
+/// Configures which browser origins and which remote addresses can use the API.
+///
+/// By default the requests from all addresses are allowed without tokens, but cross-origin
+/// requests from the browsers are refused, as any website could otherwise use the API of the
+/// local node.
+#[derive(Debug, Clone)]
+pub struct AccessOptions {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<HeaderName>,
+    allowed_addresses: Vec<IpAddr>,
+    tokens: Vec<(String, Scope)>,
+}
+
+/// The endpoints an API token grants access to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Only the endpoints which do not modify the node or its content, such as `cat`, `block/get`
+    /// and `swarm/peers`, and the `/metrics`.
+    ReadOnly,
+    /// All of the endpoints, including `shutdown`.
+    Admin,
+}
+
+/// The endpoints under `/api/v0/` allowed with the [`Scope::ReadOnly`] tokens.
+const READ_ONLY_ENDPOINTS: &[&str] = &[
+    "bitswap/stat",
+    "bitswap/wantlist",
+    "block/get",
+    "block/stat",
+    "bootstrap/list",
+    "cat",
+    "dag/get",
+    "dag/resolve",
+    "dht/findpeer",
+    "dht/findprovs",
+    "dht/query",
+    "dns",
+    "get",
+    "id",
+    "log/ls",
+    "pin/ls",
+    "pubsub/ls",
+    "pubsub/peers",
+    "pubsub/sub",
+    "refs",
+    "refs/local",
+    "resolve",
+    "swarm/addrs",
+    "swarm/addrs/local",
+    "swarm/peers",
+    "version",
+];
+
+impl Default for AccessOptions {
+    fn default() -> Self {
+        AccessOptions {
+            allowed_origins: Vec::new(),
+            // all of the API endpoints are POST
+            allowed_methods: vec![Method::POST],
+            allowed_headers: Vec::new(),
+            allowed_addresses: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+impl AccessOptions {
+    /// The origins allowed to make cross-origin requests, such as `http://localhost:3000`, or `*`
+    /// for allowing any origin. Invalid origins are ignored with a warning.
+    pub fn with_allowed_origins(self, allowed_origins: Vec<String>) -> Self {
+        AccessOptions {
+            allowed_origins,
+            ..self
+        }
+    }
+
+    /// The methods allowed in the cross-origin requests, by default only `POST`.
+    pub fn with_allowed_methods(self, allowed_methods: Vec<Method>) -> Self {
+        AccessOptions {
+            allowed_methods,
+            ..self
+        }
+    }
+
+    /// The headers allowed in the cross-origin requests in addition to the simple headers, for
+    /// example `content-type`.
+    pub fn with_allowed_headers(self, allowed_headers: Vec<HeaderName>) -> Self {
+        AccessOptions {
+            allowed_headers,
+            ..self
+        }
+    }
+
+    /// The remote addresses allowed to use the API. When empty, which is the default, all
+    /// addresses are allowed.
+    pub fn with_allowed_addresses(self, allowed_addresses: Vec<IpAddr>) -> Self {
+        AccessOptions {
+            allowed_addresses,
+            ..self
+        }
+    }
+
+    /// The bearer tokens required in the `Authorization` header, and the scopes they grant. When
+    /// empty, which is the default, no tokens are required.
+    pub fn with_tokens(self, tokens: Vec<(String, Scope)>) -> Self {
+        AccessOptions { tokens, ..self }
+    }
+
+    /// The CORS configuration, which responds to the preflight requests and refuses the requests
+    /// from the origins not allowed with `403 Forbidden`.
+    pub(crate) fn cors(&self) -> warp::cors::Builder {
+        let builder = warp::cors()
+            .allow_methods(self.allowed_methods.iter().cloned())
+            .allow_headers(self.allowed_headers.iter().cloned());
+
+        if self.allowed_origins.iter().any(|origin| origin == "*") {
+            return builder.allow_any_origin();
+        }
+
+        // warp panics on invalid origins, so they are validated and normalized here
+        let origins = self
+            .allowed_origins
+            .iter()
+            .filter_map(|origin| match url::Url::parse(origin) {
+                Ok(url) if url.origin().is_tuple() => Some(url.origin().ascii_serialization()),
+                _ => {
+                    warn!("ignoring invalid CORS origin: {:?}", origin);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        builder.allow_origins(origins.iter().map(String::as_str))
+    }
+
+    /// Filter rejecting the requests from the remote addresses which are not allowed.
+    pub(crate) fn allowed_addresses(&self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        let allowed = Arc::new(self.allowed_addresses.clone());
+
+        warp::addr::remote()
+            .and_then(move |remote: Option<SocketAddr>| {
+                let allowed = Arc::clone(&allowed);
+                async move {
+                    let permitted = allowed.is_empty()
+                        || remote
+                            .map(|remote| allowed.contains(&remote.ip()))
+                            .unwrap_or(false);
+
+                    if permitted {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::custom(AddressNotAllowed))
+                    }
+                }
+            })
+            .untuple_one()
+    }
+
+    /// Filter rejecting the requests without a valid token, and the requests to the endpoints
+    /// outside of the scope of the token.
+    pub(crate) fn authorized(&self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        let tokens = Arc::new(self.tokens.clone());
+
+        warp::path::full()
+            .and(warp::header::optional::<String>("authorization"))
+            .and_then(move |path: FullPath, authorization: Option<String>| {
+                let tokens = Arc::clone(&tokens);
+                async move {
+                    if tokens.is_empty() {
+                        return Ok(());
+                    }
+
+                    let presented = authorization
+                        .as_deref()
+                        .and_then(|value| value.strip_prefix("Bearer "))
+                        .map(str::trim);
+
+                    let scope = presented.and_then(|presented| {
+                        tokens
+                            .iter()
+                            .find(|(token, _)| constant_time_eq(token, presented))
+                            .map(|(_, scope)| *scope)
+                    });
+
+                    match scope {
+                        Some(Scope::Admin) => Ok(()),
+                        Some(Scope::ReadOnly) if is_read_only(path.as_str()) => Ok(()),
+                        Some(Scope::ReadOnly) => Err(warp::reject::custom(OutOfScope)),
+                        None => Err(warp::reject::custom(Unauthorized)),
+                    }
+                }
+            })
+            .untuple_one()
+    }
+}
+
+fn is_read_only(path: &str) -> bool {
+    if path == "/metrics" {
+        return true;
+    }
+
+    path.strip_prefix("/api/v0/")
+        .map(|endpoint| READ_ONLY_ENDPOINTS.contains(&endpoint.trim_end_matches('/')))
+        .unwrap_or(false)
+}
+
+/// Compares the tokens in time which does not depend on the position of the first difference.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Rejection for the requests without a valid token.
+#[derive(Debug)]
+pub(crate) struct Unauthorized;
+impl warp::reject::Reject for Unauthorized {}
+
+/// Rejection for the requests to the endpoints outside of the scope of the token.
+#[derive(Debug)]
+pub(crate) struct OutOfScope;
+impl warp::reject::Reject for OutOfScope {}
+
+/// Rejection for the requests from the remote addresses which are not allowed.
+#[derive(Debug)]
+pub(crate) struct AddressNotAllowed;
+impl warp::reject::Reject for AddressNotAllowed {}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessOptions, OutOfScope, Scope, Unauthorized};
+    use warp::Filter;
+
+    fn routes(
+        options: &AccessOptions,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        options
+            .allowed_addresses()
+            .and(options.authorized())
+            .and(warp::post())
+            .map(|| "ok")
+            .with(options.cors())
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn cross_origin_requests() {
+        let options = AccessOptions::default()
+            .with_allowed_origins(vec!["http://localhost:3000".into(), "not an origin".into()]);
+        let routes = routes(&options);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .header("origin", "http://localhost:3000")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers()["access-control-allow-origin"],
+            "http://localhost:3000"
+        );
+
+        let resp = warp::test::request()
+            .method("POST")
+            .header("origin", "http://example.com")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 403);
+
+        // requests from outside of the browsers have no origin
+        let resp = warp::test::request().method("POST").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn remote_addresses() {
+        let options =
+            AccessOptions::default().with_allowed_addresses(vec!["127.0.0.1".parse().unwrap()]);
+        let routes = routes(&options);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .remote_addr("127.0.0.1:12345".parse().unwrap())
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .remote_addr("10.0.0.1:12345".parse().unwrap())
+            .reply(&routes)
+            .await;
+
+        assert_ne!(resp.status(), 200);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn token_scopes() {
+        let options = AccessOptions::default().with_tokens(vec![
+            ("reader".into(), Scope::ReadOnly),
+            ("admin".into(), Scope::Admin),
+        ]);
+        let filter = options.authorized();
+
+        let authorized = |path: &'static str, token: Option<&'static str>| {
+            let mut request = warp::test::request().method("POST").path(path);
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.filter(&filter)
+        };
+
+        assert!(authorized("/api/v0/cat?arg=foo", Some("reader"))
+            .await
+            .is_ok());
+        assert!(authorized("/api/v0/swarm/addrs/local", Some("reader"))
+            .await
+            .is_ok());
+        assert!(authorized("/api/v0/shutdown", Some("admin")).await.is_ok());
+
+        let rejection = authorized("/api/v0/shutdown", Some("reader"))
+            .await
+            .unwrap_err();
+        assert!(rejection.find::<OutOfScope>().is_some());
+
+        for token in &[None, Some("readers"), Some("")] {
+            let rejection = authorized("/api/v0/cat", *token).await.unwrap_err();
+            assert!(rejection.find::<Unauthorized>().is_some(), "{:?}", token);
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn no_tokens_required_by_default() {
+        let resp = warp::test::request()
+            .method("POST")
+            .reply(&routes(&AccessOptions::default()))
+            .await;
+
+        assert_eq!(resp.status(), 200);
+    }
+}
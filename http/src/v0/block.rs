@@ -36,7 +36,7 @@ async fn get_query<T: IpfsTypes>(
         .await
         .map_err(StringError::from)?
         .map_err(StringError::from)?
-        .into_vec();
+        .data;
 
     let response = Response::builder().body(data);
     Ok(response)
@@ -59,9 +59,10 @@ pub struct PutQuery {
 
 impl PutQuery {
     fn format(&self) -> Result<Codec, Rejection> {
-        Ok(match self.format.as_deref().unwrap_or("dag-pb") {
+        Ok(match self.format.as_deref().unwrap_or("v0") {
             "dag-cbor" => Codec::DagCBOR,
-            "dag-pb" => Codec::DagProtobuf,
+            // go-ipfs uses "v0" for the CIDv0 of dag-pb, the default, and "protobuf" for CIDv1
+            "v0" | "dag-pb" | "protobuf" => Codec::DagProtobuf,
             "dag-json" => Codec::DagJSON,
             "raw" => Codec::Raw,
             _ => return Err(StringError::from("unknown codec").into()),
@@ -76,8 +77,14 @@ impl PutQuery {
         })
     }
 
+    /// The version is CIDv0 only for the "v0" format, as with go-ipfs, unless given.
     fn version(&self) -> Result<Version, Rejection> {
-        Ok(match self.version.unwrap_or(0) {
+        let default = match self.format.as_deref() {
+            None | Some("v0") => 0,
+            Some(_) => 1,
+        };
+
+        Ok(match self.version.unwrap_or(default) {
             0 => Version::V0,
             1 => Version::V1,
             _ => return Err(StringError::from("invalid cid version").into()),
@@ -120,14 +127,10 @@ async fn inner_put<T: IpfsTypes>(
     // Haven't researched this deeply.
     let cid = Cid::new(opts.version()?, opts.format()?, digest).map_err(StringError::from)?;
 
-    // bad thing about Box<[u8]>: converting to it forces a reallocation; do it now that we know
-    // the cid was generated successfully.
-    let data = data.into_boxed_slice();
-
     let size = data.len();
     let key = cid.to_string();
 
-    let block = ipfs::Block { cid, data };
+    let block = ipfs::Block::new(data, cid);
 
     ipfs.put_block(block).await.map_err(StringError::from)?;
 
@@ -167,9 +170,8 @@ async fn rm_query<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     options: RmOptions,
 ) -> Result<impl Reply, Rejection> {
-    use futures::future::TryFutureExt;
-
     let RmOptions { args, force, quiet } = options;
+    let ipfs = &ipfs;
 
     let cids = args
         .into_iter()
@@ -179,7 +181,12 @@ async fn rm_query<T: IpfsTypes>(
 
     let futs: FuturesOrdered<_> = cids
         .into_iter()
-        .map(|cid| ipfs.remove_block(cid.clone()).map_err(move |e| (cid, e)))
+        .map(|cid| async move {
+            match ipfs.block_rm(&cid, force).await {
+                Ok(()) => Ok(cid),
+                Err(e) => Err((cid, e)),
+            }
+        })
         .collect();
 
     let responses = futs
@@ -193,7 +200,7 @@ async fn rm_query<T: IpfsTypes>(
             },
             Err((cid, e)) => RmResponse {
                 hash: cid.to_string(),
-                error: if force { "".to_string() } else { e.to_string() },
+                error: e.to_string(),
             },
         })
         .map(|response: RmResponse| serde_json::to_string(&response))
@@ -229,8 +236,8 @@ async fn stat_query<T: IpfsTypes>(
     query: GetStatOptions,
 ) -> Result<impl Reply, Rejection> {
     let cid: Cid = query.arg.parse().map_err(StringError::from)?;
-    let block = ipfs
-        .get_block(&cid)
+    let size = ipfs
+        .block_stat(&cid)
         .maybe_timeout(query.timeout.map(StringSerialized::into_inner))
         .await
         .map_err(StringError::from)?
@@ -238,6 +245,6 @@ async fn stat_query<T: IpfsTypes>(
 
     Ok(reply::json(&serde_json::json!({
         "Key": query.arg,
-        "Size": block.data().len(),
+        "Size": size,
     })))
 }
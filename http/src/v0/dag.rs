@@ -93,9 +93,7 @@ async fn put_query<T: IpfsTypes>(
         "Cid": { "/": cid.to_string() }
     });
 
-    // delay reallocation until cid has been generated
-    let data = data.into_boxed_slice();
-    let block = ipfs::Block { cid, data };
+    let block = ipfs::Block::new(data, cid);
     ipfs.put_block(block).await.map_err(StringError::from)?;
     Ok(reply::json(&reply))
 }
@@ -147,3 +145,78 @@ async fn inner_resolve<T: IpfsTypes>(
         "RemPath": StringSerialized(remaining),
     })))
 }
+
+/// Per https://docs.ipfs.io/reference/http/api/#api-v0-dag-get this endpoint resolves the path
+/// and returns the document it was resolved to, encoded as dag-json.
+pub fn get<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<GetOptions>())
+        .and_then(inner_get)
+}
+
+#[derive(Debug, Deserialize)]
+struct GetOptions {
+    arg: String,
+    timeout: Option<StringSerialized<humantime::Duration>>,
+}
+
+async fn inner_get<T: IpfsTypes>(ipfs: Ipfs<T>, opts: GetOptions) -> Result<impl Reply, Rejection> {
+    use ipfs::ipld::dag_json::json_encode;
+    use ipfs::IpfsPath;
+    use std::convert::TryFrom;
+
+    let path = IpfsPath::try_from(opts.arg.as_str()).map_err(StringError::from)?;
+
+    let ipld = ipfs
+        .get_dag(path)
+        .maybe_timeout(opts.timeout.map(StringSerialized::into_inner))
+        .await
+        .map_err(StringError::from)?
+        .map_err(StringError::from)?;
+
+    let body = json_encode(&ipld).map_err(StringError::from)?;
+
+    Ok(reply::with_header(
+        body.into_vec(),
+        "content-type",
+        "application/json",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use ipfs::{make_ipld, Node};
+
+    #[tokio::test(max_threads = 1)]
+    async fn get_resolves_path() {
+        let ipfs = Node::new("test_node").await;
+
+        let leaf = ipfs.put_dag(make_ipld!({ "name": "leaf" })).await.unwrap();
+        let root = ipfs
+            .put_dag(make_ipld!({ "link": leaf.clone(), "list": [1, 2] }))
+            .await
+            .unwrap();
+
+        let filter = super::get(&*ipfs);
+
+        let response = warp::test::request()
+            .path(&format!("/dag/get?arg=/ipfs/{}/link", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &br#"{"name":"leaf"}"#[..]);
+
+        let response = warp::test::request()
+            .path(&format!("/dag/get?arg={}", root))
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["link"]["/"], leaf.to_string());
+        assert_eq!(body["list"], serde_json::json!([1, 2]));
+    }
+}
@@ -0,0 +1,115 @@
+//! `/api/v0/log` endpoints for listing and changing the log levels of the daemon at runtime.
+
+use super::support::{NotImplemented, StringError};
+use crate::logging::LogFilter;
+use serde::Serialize;
+use warp::{reply, Filter, Rejection, Reply};
+
+/// Provides the filter of the daemon, or rejects as not implemented when the subscriber was not
+/// installed through [`LogFilter::init`].
+fn with_filter(
+    filter: Option<LogFilter>,
+) -> impl Filter<Extract = (LogFilter,), Error = Rejection> + Clone {
+    warp::any().and_then(move || {
+        let filter = filter.clone();
+        async move { filter.ok_or_else(|| warp::reject::custom(NotImplemented)) }
+    })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListResponse {
+    strings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct LevelResponse {
+    message: String,
+}
+
+/// Lists the directives of the current filter.
+pub fn ls(
+    filter: Option<LogFilter>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_filter(filter).map(|filter: LogFilter| {
+        reply::json(&ListResponse {
+            strings: filter.directives(),
+        })
+    })
+}
+
+/// Sets the level of the target given as the first argument, such as
+/// `/api/v0/log/level?arg=ipfs_bitswap&arg=trace`.
+pub fn level(
+    filter: Option<LogFilter>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_filter(filter)
+        .and(warp::filters::query::raw())
+        .and_then(level_query)
+}
+
+async fn level_query(filter: LogFilter, query: String) -> Result<impl Reply, Rejection> {
+    let args = url::form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _)| key == "arg")
+        .map(|(_, value)| value.into_owned())
+        .collect::<Vec<_>>();
+
+    let (target, level) = match args.as_slice() {
+        [target, level] => (target, level),
+        _ => {
+            return Err(StringError::from("expected the target and the level as arguments").into())
+        }
+    };
+
+    filter.set_level(target, level).map_err(StringError::from)?;
+
+    Ok(reply::json(&LevelResponse {
+        message: format!("Changed log level of '{}' to '{}'\n", target, level),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{level, ls};
+    use crate::logging::LogFilter;
+    use crate::v0::recover_as_message_response;
+    use std::sync::Arc;
+    use warp::Filter;
+
+    #[tokio::test(max_threads = 1)]
+    async fn changing_the_level() {
+        let filter = LogFilter::new("ipfs=info", Arc::new(|_| Ok(())));
+
+        let response = warp::test::request()
+            .path("/?arg=ipfs_bitswap&arg=trace")
+            .reply(&level(Some(filter.clone())))
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.body(),
+            &br#"{"Message":"Changed log level of 'ipfs_bitswap' to 'trace'\n"}"#[..]
+        );
+
+        let response = warp::test::request()
+            .path("/?arg=ipfs&arg=loud")
+            .reply(&level(Some(filter.clone())).recover(recover_as_message_response))
+            .await;
+        assert_eq!(response.status(), 500);
+
+        let response = warp::test::request().reply(&ls(Some(filter))).await;
+        assert_eq!(
+            response.body(),
+            &br#"{"Strings":["ipfs=info","ipfs_bitswap=trace"]}"#[..]
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn not_implemented_without_filter() {
+        let response = warp::test::request()
+            .reply(&ls(None).recover(recover_as_message_response))
+            .await;
+
+        assert_eq!(response.status(), 501);
+    }
+}
@@ -4,11 +4,14 @@
 //! semantics of getting the messages received on that topic from request onwards. This is
 //! implemented with [`tokio::sync::broadcast`] which supports these semantics.
 //!
-//! # Panics
+//! The messages are buffered per topic only up to the capacity of the broadcast channel, so a
+//! response which is not keeping up with the messages is ended instead of buffering the messages
+//! without limits. When the clients disconnect, the subscription is dropped after the last
+//! response has stopped reading from it.
 //!
 //! The subscription functionality *assumes* that there are no other users for
-//! `ipfs::Ipfs::pubsub_subscribe` and thus will panic if an subscription was made outside of this
-//! locking mechanism.
+//! `ipfs::Ipfs::pubsub_subscribe`; if a subscription was made outside of this locking mechanism,
+//! the requests to subscribe to the same topic fail.
 
 use futures::stream::{Stream, TryStream};
 use serde::{Deserialize, Serialize};
@@ -100,8 +103,6 @@ async fn inner_publish<T: IpfsTypes>(
 
 /// Handling of https://docs-beta.ipfs.io/reference/http/api/#api-v0-pubsub-sub
 ///
-/// # Panics
-///
 /// Note the module documentation.
 pub fn subscribe<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
@@ -111,7 +112,10 @@ pub fn subscribe<T: IpfsTypes>(
         .and(warp::any().map(move || pubsub.clone()))
         .and(warp::query::<TopicParameter>())
         .and_then(|ipfs, pubsub, TopicParameter { topic }| async move {
-            Ok::<_, warp::Rejection>(StreamResponse(inner_subscribe(ipfs, pubsub, topic).await))
+            let messages = inner_subscribe(ipfs, pubsub, topic)
+                .await
+                .map_err(StringError::from)?;
+            Ok::<_, warp::Rejection>(StreamResponse(messages))
         })
 }
 
@@ -119,7 +123,7 @@ async fn inner_subscribe<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     pubsub: Arc<Pubsub>,
     topic: String,
-) -> impl TryStream<Ok = PreformattedJsonMessage, Error = StreamError> {
+) -> Result<impl TryStream<Ok = PreformattedJsonMessage, Error = StreamError>, ipfs::Error> {
     // accessing this through mutex bets on "most accesses would need write access" as in most
     // requests would be asking for new subscriptions, which would require RwLock upgrading
     // from write, which is not supported operation either.
@@ -136,10 +140,8 @@ async fn inner_subscribe<T: IpfsTypes>(
             let topic = ve.key().clone();
 
             // the returned stream needs to be set up to be shoveled in a background task
-            let shoveled = ipfs
-                .pubsub_subscribe(topic.clone())
-                .await
-                .expect("new subscriptions shouldn't fail while holding the lock");
+            // this only fails if the topic was subscribed to outside of this module
+            let shoveled = ipfs.pubsub_subscribe(topic.clone()).await?;
 
             // using broadcast channel should allow us have N concurrent subscribes and
             // preformatted json should give us good enough performance. this channel can last over
@@ -162,12 +164,13 @@ async fn inner_subscribe<T: IpfsTypes>(
 
     // map recv errors into the StreamError and flatten
     let mut errored = false;
-    rx.into_stream()
+    Ok(rx
+        .into_stream()
         .map(|res| res.map_err(|_| StreamError::Recv).and_then(|res| res))
         .take_while(move |res| {
             // return until the first error
             !std::mem::replace(&mut errored, res.is_err())
-        })
+        }))
 }
 
 /// Shovel task takes items from the [`SubscriptionStream`], formats them and passes them on to
@@ -232,10 +235,15 @@ async fn shovel<T: IpfsTypes>(
                         "resubscribing with the existing broadcast channel to {:?}",
                         topic
                     );
-                    shoveled = ipfs
-                        .pubsub_subscribe(topic.clone())
-                        .await
-                        .expect("new subscriptions shouldn't fail while holding the lock");
+                    shoveled = match ipfs.pubsub_subscribe(topic.clone()).await {
+                        Ok(shoveled) => shoveled,
+                        Err(e) => {
+                            // dropping the sender ends the responses of the current subscribers
+                            warn!("failed to resubscribe to {:?}: {}", topic, e);
+                            oe.remove();
+                            return;
+                        }
+                    };
                 } else {
                     trace!(
                         "got a new subscriber to existing broadcast channel on {:?}",
@@ -518,9 +526,12 @@ impl<'a> Iterator for QueryAsRawPartsParser<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{publish_args, PublishArgs};
+    use super::{inner_subscribe, publish_args, PublishArgs, Pubsub};
     use futures::future::ready;
+    use ipfs::Node;
     use std::str;
+    use std::sync::Arc;
+    use std::time::Duration;
     use warp::reply::json;
     use warp::{test::request, Filter, Rejection, Reply};
 
@@ -571,4 +582,42 @@ mod tests {
             r#"{"message":"aedFIxDJZ2jS1eVB6Pkbv","topic":"some_channel"}"#
         );
     }
+
+    #[tokio::test(max_threads = 1)]
+    async fn unsubscribed_after_disconnect() {
+        let ipfs = Node::new("test_node").await;
+        let pubsub = Arc::new(Pubsub::default());
+
+        let first = inner_subscribe((*ipfs).clone(), Arc::clone(&pubsub), "topic".into())
+            .await
+            .unwrap();
+        let second = inner_subscribe((*ipfs).clone(), Arc::clone(&pubsub), "topic".into())
+            .await
+            .unwrap();
+
+        assert_eq!(ipfs.pubsub_subscribed().await.unwrap(), vec!["topic"]);
+
+        drop(first);
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+
+        // the remaining response keeps the subscription
+        assert_eq!(ipfs.pubsub_subscribed().await.unwrap(), vec!["topic"]);
+
+        drop(second);
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+
+        assert!(pubsub.subscriptions.lock().await.is_empty());
+        assert!(ipfs.pubsub_subscribed().await.unwrap().is_empty());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn subscribing_to_topic_subscribed_elsewhere_fails() {
+        let ipfs = Node::new("test_node").await;
+        let pubsub = Arc::new(Pubsub::default());
+
+        let _elsewhere = ipfs.pubsub_subscribe("topic".into()).await.unwrap();
+
+        let subscribed = inner_subscribe((*ipfs).clone(), pubsub, "topic".into()).await;
+        assert!(subscribed.is_err());
+    }
 }
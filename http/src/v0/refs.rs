@@ -351,7 +351,7 @@ mod tests {
 
             let block = Block {
                 cid,
-                data: (*data).into(),
+                data: data.to_vec().into(),
             };
 
             ipfs.put_block(block).await.unwrap();
@@ -1,20 +1,14 @@
 use crate::v0::support::{
     with_ipfs, MaybeTimeoutExt, StreamResponse, StringError, StringSerialized,
 };
-use async_stream::try_stream;
-use bytes::Bytes;
-use futures::stream::TryStream;
-use ipfs::unixfs::ll::walk::{self, ContinuedWalk, Walker};
-use ipfs::unixfs::{ll::file::FileReadFailed, TraversalFailed};
-use ipfs::{dag::ResolveError, Block, Ipfs, IpfsPath, IpfsTypes};
+use ipfs::unixfs::{
+    ll::file::{adder::Chunker, FileReadFailed},
+    TraversalFailed,
+};
+use ipfs::{dag::ResolveError, Ipfs, IpfsPath, IpfsTypes};
 use serde::Deserialize;
-use std::fmt;
-use std::path::Path;
 use warp::{query, Filter, Rejection, Reply};
 
-mod tar_helper;
-use tar_helper::TarHelper;
-
 mod add;
 
 #[derive(Debug, Deserialize)]
@@ -28,6 +22,11 @@ pub struct AddArgs {
     /// When true, a new directory is created to hold more than 1 root level directories.
     #[serde(default, rename = "wrap-with-directory")]
     wrap_with_directory: bool,
+    /// The chunker to use, for example `size-262144`, `rabin-{min}-{avg}-{max}` or `buzhash`.
+    chunker: Option<StringSerialized<Chunker>>,
+    /// When true, the files are laid out as trickle trees instead of balanced trees.
+    #[serde(default)]
+    trickle: bool,
 }
 
 pub fn add<T: IpfsTypes>(
@@ -100,153 +99,16 @@ pub fn get<T: IpfsTypes>(
 }
 
 async fn get_inner<T: IpfsTypes>(ipfs: Ipfs<T>, args: GetArgs) -> Result<impl Reply, Rejection> {
-    use futures::stream::TryStreamExt;
-
     let path = args.arg.into_inner();
 
     // FIXME: this timeout is only for the first step, should be for the whole walk!
-    let block = resolve_dagpb(&ipfs, path)
+    let stream = ipfs::unixfs::get_tar(ipfs, path)
         .maybe_timeout(args.timeout.map(StringSerialized::into_inner))
         .await
         .map_err(StringError::from)?
         .map_err(StringError::from)?;
 
-    Ok(StreamResponse(walk(ipfs, block).into_stream()))
-}
-
-async fn resolve_dagpb<T: IpfsTypes>(ipfs: &Ipfs<T>, path: IpfsPath) -> Result<Block, StringError> {
-    let (resolved, _) = ipfs
-        .dag()
-        .resolve(path, true)
-        .await
-        .map_err(StringError::from)?;
-
-    resolved.into_unixfs_block().map_err(StringError::from)
-}
-
-fn walk<Types: IpfsTypes>(
-    ipfs: Ipfs<Types>,
-    Block {
-        cid: root,
-        data: first_block_data,
-    }: Block,
-) -> impl TryStream<Ok = Bytes, Error = GetError> + 'static {
-    let mut cache = None;
-    let mut tar_helper = TarHelper::with_capacity(16 * 1024);
-
-    // the HTTP api uses the final Cid name as the root name in the generated tar
-    // archive.
-    let name = root.to_string();
-    let mut walker = Walker::new(root, name);
-
-    let mut buffer = Some(first_block_data);
-
-    try_stream! {
-        while walker.should_continue() {
-            let data = match buffer.take() {
-                Some(first) => first,
-                None => {
-                    let (next, _) = walker.pending_links();
-                    let Block { data, .. } = ipfs.get_block(next).await?;
-                    data
-                }
-            };
-
-            match walker.next(&data, &mut cache)? {
-                ContinuedWalk::Bucket(..) => {}
-                ContinuedWalk::File(segment, _, path, metadata, size) => {
-                    if segment.is_first() {
-                        for bytes in tar_helper.apply_file(path, metadata, size)?.iter_mut() {
-                            if let Some(bytes) = bytes.take() {
-                                yield bytes;
-                            }
-                        }
-                    }
-
-                    // even if the largest of files can have 256 kB blocks and about the same
-                    // amount of content, try to consume it in small parts not to grow the buffers
-                    // too much.
-
-                    let mut n = 0usize;
-                    let slice = segment.as_ref();
-                    let total = slice.len();
-
-                    while n < total {
-                        let next = tar_helper.buffer_file_contents(&slice[n..]);
-                        n += next.len();
-                        yield next;
-                    }
-
-                    if segment.is_last() {
-                        if let Some(zeroes) = tar_helper.pad(size) {
-                            yield zeroes;
-                        }
-                    }
-                },
-                ContinuedWalk::Directory(_, path, metadata) | ContinuedWalk::RootDirectory(_, path, metadata) => {
-                    for bytes in tar_helper.apply_directory(path, metadata)?.iter_mut() {
-                        if let Some(bytes) = bytes.take() {
-                            yield bytes;
-                        }
-                    }
-                },
-                ContinuedWalk::Symlink(bytes, _, path, metadata) => {
-                    // converting a symlink is the most tricky part
-                    let target = std::str::from_utf8(bytes).map_err(|_| GetError::NonUtf8Symlink)?;
-                    let target = Path::new(target);
-
-                    for bytes in tar_helper.apply_symlink(path, target, metadata)?.iter_mut() {
-                        if let Some(bytes) = bytes.take() {
-                            yield bytes;
-                        }
-                    }
-                },
-            };
-        }
-    }
-}
-
-#[derive(Debug)]
-enum GetError {
-    NonUtf8Symlink,
-    InvalidFileName(Vec<u8>),
-    InvalidLinkName(Vec<u8>),
-    Walk(walk::Error),
-    Loading(ipfs::Error),
-}
-
-impl From<ipfs::Error> for GetError {
-    fn from(e: ipfs::Error) -> Self {
-        GetError::Loading(e)
-    }
-}
-
-impl From<walk::Error> for GetError {
-    fn from(e: walk::Error) -> Self {
-        GetError::Walk(e)
-    }
-}
-
-impl fmt::Display for GetError {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use GetError::*;
-        match self {
-            NonUtf8Symlink => write!(fmt, "symlink target could not be converted to utf-8"),
-            Walk(e) => write!(fmt, "{}", e),
-            Loading(e) => write!(fmt, "loading failed: {}", e),
-            InvalidFileName(x) => write!(fmt, "filename cannot be put inside tar: {:?}", x),
-            InvalidLinkName(x) => write!(fmt, "symlink name cannot be put inside tar: {:?}", x),
-        }
-    }
-}
-
-impl std::error::Error for GetError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            GetError::Walk(e) => Some(e),
-            _ => None,
-        }
-    }
+    Ok(StreamResponse(stream))
 }
 
 #[cfg(test)]
@@ -433,7 +295,7 @@ mod tests {
 
         let block = Block {
             cid,
-            data: block.into(),
+            data: block.to_vec().into(),
         };
 
         ipfs.put_block(block)
@@ -1,5 +1,5 @@
 use super::AddArgs;
-use crate::v0::support::StringError;
+use crate::v0::support::{StringError, StringSerialized};
 use bytes::{
     buf::{BufExt, BufMutExt},
     Buf, BufMut, Bytes, BytesMut,
@@ -10,7 +10,7 @@ use ipfs::unixfs::ll::{
     dir::builder::{
         BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeNode, TreeOptions,
     },
-    file::adder::FileAdder,
+    file::adder::{Chunker, Collector, FileAdder, TrickleCollector},
 };
 use ipfs::{Block, Ipfs, IpfsTypes};
 use mime::Mime;
@@ -66,6 +66,7 @@ enum AddError {
     Parsing(MultipartError),
     Header(MultipartError),
     InvalidFilename(std::str::Utf8Error),
+    InvalidSymlinkTarget(std::str::Utf8Error),
     UnsupportedField(String),
     UnsupportedContentType(String),
     ResponseSerialization(serde_json::Error),
@@ -87,8 +88,9 @@ impl fmt::Display for AddError {
             Parsing(me) => write!(fmt, "invalid request body: {}", me),
             Header(me) => write!(fmt, "invalid multipart header(s): {}", me),
             InvalidFilename(e) => write!(fmt, "invalid multipart filename: {:?}", e),
+            InvalidSymlinkTarget(e) => write!(fmt, "invalid symlink target: {:?}", e),
             UnsupportedField(name) => write!(fmt, "unsupported field name: {:?}", name),
-            UnsupportedContentType(t) => write!(fmt, "unsupported content-type: {:?} (supported: application/{{octet-stream,x-directory}})", t),
+            UnsupportedContentType(t) => write!(fmt, "unsupported content-type: {:?} (supported: application/{{octet-stream,x-directory,symlink}})", t),
             ResponseSerialization(e) => write!(fmt, "progress serialization failed: {}", e),
             Persisting(e) => write!(fmt, "put_block failed: {}", e),
             TreeGathering(g) => write!(fmt, "invalid directory tree: {}", g),
@@ -115,6 +117,17 @@ where
             tree_opts.wrap_with_directory();
         }
 
+        let chunker = opts
+            .chunker
+            .map(StringSerialized::into_inner)
+            .unwrap_or_default();
+
+        let collector = if opts.trickle {
+            Collector::from(TrickleCollector::default())
+        } else {
+            Collector::default()
+        };
+
         let mut tree = BufferingTreeBuilder::new(tree_opts);
         let mut buffer = BytesMut::new();
 
@@ -152,7 +165,10 @@ where
                         Ok(())
                     }?;
 
-                    let mut adder = FileAdder::default();
+                    let mut adder = FileAdder::builder()
+                        .with_chunker(chunker.clone())
+                        .with_collector(collector.clone())
+                        .build();
                     // how many bytes we have stored as blocks
                     let mut total_written = 0u64;
                     // how many bytes of input we have read
@@ -256,6 +272,37 @@ where
                         .map_err(AddError::TreeGathering)?;
                     continue;
                 }
+                "application/symlink" => {
+                    // symlinks are sent like files, with the target as the body
+                    let _ = if field_name != "file" && !field_name.starts_with("file-") {
+                        Err(AddError::UnsupportedField(field_name.to_string()))
+                    } else {
+                        Ok(())
+                    }?;
+
+                    let mut target = BytesMut::new();
+                    while let Some(next) = field.try_next().await.map_err(AddError::Parsing)? {
+                        target.put(next);
+                    }
+
+                    let target = std::str::from_utf8(&target).map_err(AddError::InvalidSymlinkTarget)?;
+
+                    let (cid, block) = tree.put_symlink(&filename, target)
+                        .map_err(AddError::TreeGathering)?;
+                    let size = block.len() as u64;
+
+                    ipfs.put_block(Block::new(block, cid.clone())).await.map_err(AddError::Persisting)?;
+
+                    serde_json::to_writer((&mut buffer).writer(), &Response::Added {
+                        name: Cow::Borrowed(&filename),
+                        hash: Quoted(&cid),
+                        size: Quoted(size),
+                    }).map_err(AddError::ResponseSerialization)?;
+
+                    buffer.put(&b"\r\n"[..]);
+
+                    Ok(buffer.split().freeze())
+                }
                 unsupported => {
                     Err(AddError::UnsupportedContentType(unsupported.to_string()))
                 }
@@ -267,10 +314,15 @@ where
         let mut iter = tree.build();
 
         while let Some(res) = iter.next_borrowed() {
-            let TreeNode { path, cid, total_size, block } = res.map_err(AddError::TreeBuilding)?;
+            let TreeNode { path, cid, total_size, block, inner_shard } = res.map_err(AddError::TreeBuilding)?;
 
             // shame we need to allocate once again here..
-            ipfs.put_block(Block { cid: cid.to_owned(), data: block.into() }).await.map_err(AddError::Persisting)?;
+            ipfs.put_block(Block::new(block.to_vec(), cid.to_owned())).await.map_err(AddError::Persisting)?;
+
+            if inner_shard {
+                // only the root shard of a sharded directory is reported, like go-ipfs does
+                continue;
+            }
 
             serde_json::to_writer((&mut buffer).writer(), &Response::Added {
                 name: Cow::Borrowed(path),
@@ -324,7 +376,7 @@ async fn import_all(
         total += data.len() as u64;
         let block = Block {
             cid,
-            data: data.into_boxed_slice(),
+            data: data.into(),
         };
 
         let cid = ipfs.put_block(block).await?;
@@ -408,6 +460,107 @@ mod tests {
         );
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn add_with_chunker() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add?chunker=size-2")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(
+                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"foobar.txt\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    \r\n\
+                    foobar\n\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+            )
+            .reply(&add(&ipfs))
+            .await;
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+
+        // same as in `ipfs_unixfs::file::adder::tests::favourite_multi_block_file`
+        assert_eq!(
+            body,
+            "{\"Hash\":\"QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6\",\"Name\":\"foobar.txt\",\"Size\":\"221\"}\r\n"
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_with_trickle() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add?chunker=size-2&trickle=true")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(
+                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"foobar.txt\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    \r\n\
+                    foobar\n\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+            )
+            .reply(&add(&ipfs))
+            .await;
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+
+        // same as in `ipfs_unixfs::file::adder::tests::trickle_multi_block_file`
+        assert_eq!(
+            body,
+            "{\"Hash\":\"QmWfQ48ChJUj4vWKFsUDe4646xCBmXgdmNfhjz9T7crywd\",\"Name\":\"foobar.txt\",\"Size\":\"221\"}\r\n"
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_directory_with_symlink() {
+        let ipfs = tokio_ipfs().await;
+
+        let response = warp::test::request()
+            .path("/add")
+            .header(
+                "content-type",
+                "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+            )
+            .body(
+                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"dir\"; filename=\"foo\"\r\n\
+                    Content-Type: application/x-directory\r\n\
+                    \r\n\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"foo%2Fa\"\r\n\
+                    Content-Type: application/symlink\r\n\
+                    \r\n\
+                    b\
+                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+            )
+            .reply(&add(&ipfs))
+            .await;
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+        let mut lines = body.lines();
+
+        // same as in `ipfs_unixfs::symlink::tests::simple_symlink`
+        assert_eq!(
+            lines.next(),
+            Some("{\"Hash\":\"QmfLJN6HLyREnWr7QQNmgmuNziUhcbwUopkHQ8gD3pMfp6\",\"Name\":\"foo/a\",\"Size\":\"7\"}")
+        );
+        assert!(
+            lines.next().unwrap().contains("\"Name\":\"foo\""),
+            "{}",
+            body
+        );
+        assert_eq!(lines.next(), None);
+    }
+
     async fn tokio_ipfs() -> ipfs::Ipfs<ipfs::TestTypes> {
         let options = ipfs::IpfsOptions::inmemory_with_generated_keys();
         let (ipfs, fut) = ipfs::UninitializedIpfs::new(options).start().await.unwrap();
@@ -1,3 +1,4 @@
+use crate::v0::access::{AddressNotAllowed, OutOfScope, Unauthorized};
 use ipfs::{Ipfs, IpfsTypes};
 use serde::Serialize;
 use std::borrow::Cow;
@@ -195,6 +196,32 @@ pub async fn recover_as_message_response(
                 .to_json_reply(),
         );
         status = StatusCode::INTERNAL_SERVER_ERROR;
+    } else if err.find::<AddressNotAllowed>().is_some() {
+        resp = Box::new(
+            MessageKind::Error
+                .with_code(0)
+                .with_message("remote address not allowed")
+                .to_json_reply(),
+        );
+        status = StatusCode::FORBIDDEN;
+    } else if err.find::<Unauthorized>().is_some() {
+        resp = Box::new(warp::reply::with_header(
+            MessageKind::Error
+                .with_code(0)
+                .with_message("missing or invalid bearer token")
+                .to_json_reply(),
+            "www-authenticate",
+            "Bearer",
+        ));
+        status = StatusCode::UNAUTHORIZED;
+    } else if err.find::<OutOfScope>().is_some() {
+        resp = Box::new(
+            MessageKind::Error
+                .with_code(0)
+                .with_message("endpoint not allowed with the token")
+                .to_json_reply(),
+        );
+        status = StatusCode::FORBIDDEN;
     } else if err.find::<InvalidPeerId>().is_some() {
         resp = Box::new(
             MessageKind::Error
@@ -0,0 +1,80 @@
+//! [`CoreApi`], the common operations of an embedded [`Ipfs`] node, which allows the
+//! applications to switch to using a remote node, for example through the HTTP API client of the
+//! `ipfs-http` crate, without other changes.
+
+use crate::error::Error;
+use crate::{unixfs, Block, Ipfs, IpfsPath, IpfsTypes};
+use async_trait::async_trait;
+use cid::Cid;
+use futures::stream::{self, TryStreamExt};
+use libp2p::PeerId;
+
+/// The core operations of a node, shared by the embedded and the remote nodes.
+#[async_trait]
+pub trait CoreApi: Send + Sync {
+    /// Returns the [`PeerId`] of the node.
+    async fn peer_id(&self) -> Result<PeerId, Error>;
+
+    /// Stores the block, returning its Cid.
+    async fn put_block(&self, block: Block) -> Result<Cid, Error>;
+
+    /// Retrieves the block, waiting until it is found from the other peers when it is not
+    /// available locally.
+    async fn get_block(&self, cid: &Cid) -> Result<Block, Error>;
+
+    /// Adds the bytes as an UnixFS file with the default options, returning the Cid of the root.
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error>;
+
+    /// Returns the content of the UnixFS file at the path.
+    async fn cat(&self, path: &IpfsPath) -> Result<Vec<u8>, Error>;
+}
+
+#[async_trait]
+impl<Types: IpfsTypes> CoreApi for Ipfs<Types> {
+    async fn peer_id(&self) -> Result<PeerId, Error> {
+        let (public_key, _) = self.identity().await?;
+        Ok(public_key.into_peer_id())
+    }
+
+    async fn put_block(&self, block: Block) -> Result<Cid, Error> {
+        Ipfs::put_block(self, block).await
+    }
+
+    async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        Ipfs::get_block(self, cid).await
+    }
+
+    async fn add(&self, data: Vec<u8>) -> Result<Cid, Error> {
+        let content = stream::once(async move { Ok::<_, Error>(data) });
+        let added = unixfs::add(self, content, Default::default()).await?;
+        Ok(added.root)
+    }
+
+    async fn cat(&self, path: &IpfsPath) -> Result<Vec<u8>, Error> {
+        let content = self.cat_unixfs(path.to_owned(), None).await?;
+        Ok(content.try_concat().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoreApi;
+    use crate::path::PathRoot;
+    use crate::{IpfsPath, Node};
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_and_cat_through_the_trait() {
+        let ipfs = Node::new("test_node").await;
+        let api: &dyn CoreApi = &*ipfs;
+
+        let root = api.add(b"foobar\n".to_vec()).await.unwrap();
+        let content = api
+            .cat(&IpfsPath::new(PathRoot::Ipld(root.clone())))
+            .await
+            .unwrap();
+        assert_eq!(content, b"foobar\n");
+
+        let block = api.get_block(&root).await.unwrap();
+        assert_eq!(api.put_block(block).await.unwrap(), root);
+    }
+}
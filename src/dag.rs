@@ -3,8 +3,9 @@
 use crate::error::Error;
 use crate::ipld::{decode_ipld, encode_ipld, Ipld};
 use crate::path::{IpfsPath, SlashedPath};
-use crate::repo::RepoTypes;
+use crate::repo::{BlockSession, RepoTypes};
 use crate::{Block, Ipfs};
+use bytes::Bytes;
 use cid::{Cid, Codec, Version};
 use ipfs_unixfs::{
     dagpb::{wrap_node_data, NodeData},
@@ -44,6 +45,12 @@ pub enum ResolveError {
     #[error("tried to resolve through an object that had no links")]
     NoLinks(Cid, SlashedPath),
 
+    /// Path attempted to resolve through a UnixFS symlink, at the given path, to the given
+    /// target. The symlink is not followed, as the target is relative to the directory
+    /// containing it.
+    #[error("tried to resolve through a symlink to {:?}", String::from_utf8_lossy(.2))]
+    Symlink(Cid, SlashedPath, Vec<u8>),
+
     /// Path attempted to resolve through a property, index or link which did not exist.
     #[error("no link named {:?} under {0}", .1.iter().last().unwrap())]
     NotFound(Cid, SlashedPath),
@@ -88,6 +95,11 @@ enum RawResolveLocalError {
         document: Cid,
         segment_index: usize,
     },
+    Symlink {
+        document: Cid,
+        segment_index: usize,
+        target: Vec<u8>,
+    },
 }
 
 impl RawResolveLocalError {
@@ -112,6 +124,10 @@ impl RawResolveLocalError {
             | NotFound {
                 ref mut segment_index,
                 ..
+            }
+            | Symlink {
+                ref mut segment_index,
+                ..
             } => {
                 // NOTE: this is the **index** compared to the number of segments matched, i.e. **count**
                 // from `resolve_local`'s Ok return value.
@@ -154,6 +170,11 @@ impl RawResolveLocalError {
                 document,
                 segment_index,
             } => ResolveError::NotFound(document, path.into_truncated(segment_index + 1)),
+            Symlink {
+                document,
+                segment_index,
+                target,
+            } => ResolveError::Symlink(document, path.into_truncated(segment_index), target),
         }
     }
 }
@@ -270,9 +291,16 @@ impl<Types: RepoTypes> IpldDag<Types> {
         let mut total = 0;
 
         let mut cache = None;
+        // the blocks along the path are likely to be found from the same peers
+        let session = self.ipfs.repo.session();
 
         loop {
-            let block = match self.ipfs.repo.get_block(&current).await {
+            let block = match self
+                .ipfs
+                .repo
+                .get_block_in_session(&current, session.id())
+                .await
+            {
                 Ok(block) => block,
                 Err(e) => return Err(RawResolveLocalError::Loading(current, e)),
             };
@@ -290,7 +318,10 @@ impl<Types: RepoTypes> IpldDag<Types> {
 
             let (src, dest) = match resolution {
                 Complete(ResolvedNode::Link(src, dest)) => (src, dest),
-                Incomplete(src, lookup) => match self.resolve_hamt(lookup, &mut cache).await {
+                Incomplete(src, lookup) => match self
+                    .resolve_hamt(lookup, &mut cache, &session)
+                    .await
+                {
                     Ok(dest) => (src, dest),
                     Err(e) => return Err(RawResolveLocalError::UnsupportedDocument(src, e.into())),
                 },
@@ -316,13 +347,18 @@ impl<Types: RepoTypes> IpldDag<Types> {
         &self,
         mut lookup: ShardedLookup<'_>,
         cache: &mut Option<Cache>,
+        session: &BlockSession,
     ) -> Result<Cid, Error> {
         use MaybeResolved::*;
 
         loop {
             let (next, _) = lookup.pending_links();
 
-            let block = self.ipfs.repo.get_block(next).await?;
+            let block = self
+                .ipfs
+                .repo
+                .get_block_in_session(next, session.id())
+                .await?;
 
             match lookup.continue_walk(block.data(), cache)? {
                 NeedToLoadMore(next) => lookup = next,
@@ -344,7 +380,7 @@ pub enum ResolvedNode {
     /// Path ended in `Data` at a dag-pb node. This is usually not interesting and should be
     /// treated as a "Not found" error since dag-pb node did not have a *link* called `Data`. The variant
     /// exists as there are interface-ipfs-http tests which require this behaviour.
-    DagPbData(Cid, NodeData<Box<[u8]>>),
+    DagPbData(Cid, NodeData<Bytes>),
     /// Path ended on a !dag-pb document which was projected.
     Projection(Cid, Ipld),
     /// Local resolving ended with a link
@@ -362,10 +398,12 @@ impl ResolvedNode {
         }
     }
 
-    /// Unwraps the dagpb block variant and turns others into UnexpectedResolved.
+    /// Unwraps the dagpb or raw block variant and turns others into UnexpectedResolved. A raw
+    /// block is a single-leaf file, as added with raw leaves.
     /// This is useful wherever unixfs operations are continued after resolving an IpfsPath.
     pub fn into_unixfs_block(self) -> Result<Block, UnexpectedResolved> {
-        if self.source().codec() != cid::Codec::DagProtobuf {
+        let codec = self.source().codec();
+        if codec != cid::Codec::DagProtobuf && codec != cid::Codec::Raw {
             Err(UnexpectedResolved::UnexpectedCodec(
                 cid::Codec::DagProtobuf,
                 self,
@@ -465,7 +503,7 @@ fn resolve_local<'a>(
 /// `ResolvedNode::DagPbData`.
 fn resolve_local_dagpb<'a>(
     cid: Cid,
-    data: Box<[u8]>,
+    data: Bytes,
     segment: &'a str,
     is_last: bool,
     cache: &mut Option<Cache>,
@@ -490,9 +528,14 @@ fn resolve_local_dagpb<'a>(
                 segment_index: 0,
             })
         }
+        Err(ipfs_unixfs::ResolveError::Symlink(target)) => Err(RawResolveLocalError::Symlink {
+            document: cid,
+            segment_index: 0,
+            target,
+        }),
         Err(ipfs_unixfs::ResolveError::UnexpectedType(ut)) if ut.is_file() => {
-            // this might even be correct: files we know are not supported, however not sure if
-            // symlinks are, let alone custom unxifs types should such exist
+            // this might even be correct: files we know are not supported, let alone custom
+            // unixfs types should such exist
             Err(RawResolveLocalError::NotFound {
                 document: cid,
                 segment_index: 0,
@@ -969,7 +1012,7 @@ mod tests {
             let node = node.unwrap();
             let block = Block {
                 cid: node.cid.to_owned(),
-                data: node.block.into(),
+                data: node.block.to_vec().into(),
             };
 
             ipfs.put_block(block).await.unwrap();
@@ -991,4 +1034,48 @@ mod tests {
             format!("no link named \"second-best-file\" under {}", cids[1])
         );
     }
+
+    #[tokio::test(max_threads = 1)]
+    async fn fail_resolving_through_symlink() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+
+        let mut opts = ipfs_unixfs::dir::builder::TreeOptions::default();
+        opts.wrap_with_directory();
+
+        let mut tree = ipfs_unixfs::dir::builder::BufferingTreeBuilder::new(opts);
+        let (cid, block) = tree.put_symlink("something/link", "../elsewhere").unwrap();
+
+        ipfs.put_block(Block {
+            cid,
+            data: block.into(),
+        })
+        .await
+        .unwrap();
+
+        let mut root = None;
+
+        for node in tree.build() {
+            let node = node.unwrap();
+            root = Some(node.cid.clone());
+
+            ipfs.put_block(Block {
+                cid: node.cid,
+                data: node.block.into(),
+            })
+            .await
+            .unwrap();
+        }
+
+        let path = IpfsPath::from(root.unwrap())
+            .sub_path("something/link/anything-here")
+            .unwrap();
+
+        match ipfs.dag().resolve(path, true).await.unwrap_err() {
+            ResolveError::Symlink(_, path, target) => {
+                assert_eq!(path.to_string(), "something/link");
+                assert_eq!(target, b"../elsewhere");
+            }
+            e => panic!("{:?}", e),
+        }
+    }
 }
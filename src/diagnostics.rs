@@ -0,0 +1,386 @@
+//! The [`Diagnostics`] report of a node, collected by [`crate::Ipfs::diagnostics`], the
+//! [`StatsSnapshot`]s of [`crate::Ipfs::stats_poll`] and the optional periodic self-check of
+//! [`crate::Ipfs::self_check`].
+
+use crate::error::Error;
+use crate::events::IpfsEvent;
+use crate::{Ipfs, IpfsTypes};
+use async_stream::try_stream;
+use futures::stream::Stream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The state of the node and the process at the time of collecting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics {
+    /// The version of the `ipfs` crate.
+    pub version: &'static str,
+    /// The operating system, such as `linux` or `windows`.
+    pub os: &'static str,
+    /// The CPU architecture, such as `x86_64`.
+    pub arch: &'static str,
+    /// The memory usage of the process, when available on the platform.
+    pub memory: Option<MemoryUsage>,
+    /// The open file descriptors of the process, when available on the platform.
+    pub open_fds: Option<usize>,
+    /// The number of connected peers.
+    pub peers: usize,
+    /// The number of open connections, of which there can be many to the same peer.
+    pub connections: usize,
+    /// The requests waiting for the background tasks.
+    pub queues: QueueDepths,
+    /// The path of the repo.
+    pub repo_path: PathBuf,
+    /// The number of blocks in the repo, as counted by the repo when the blocks are stored and
+    /// removed, so that the blocks are not listed for each report.
+    pub repo_blocks: usize,
+    /// The bytes taken by the blockstore and the datastore on the disk, zero for the in-memory
+    /// repos.
+    pub repo_disk_usage: u64,
+    /// The cargo features the crate was built with.
+    pub features: Vec<&'static str>,
+}
+
+/// The memory usage of the process in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub resident: u64,
+    pub virtual_: u64,
+}
+
+/// The number of requests waiting in the queues of the background tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueDepths {
+    /// The blocks being fetched from the network for the local requests.
+    pub block_requests: usize,
+    /// The blocks in the bitswap wantlist.
+    pub wantlist: usize,
+}
+
+/// The state of the node at the end of an interval of [`crate::Ipfs::stats_poll`], and the
+/// changes over the interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsSnapshot {
+    /// The length of the interval, which can be longer than the requested one.
+    pub elapsed: Duration,
+    /// The bytes per second sent in blocks to other peers over the interval.
+    pub bitswap_send_rate: f64,
+    /// The bytes per second received in blocks from other peers over the interval.
+    pub bitswap_receive_rate: f64,
+    /// The number of blocks in the bitswap wantlist.
+    pub wantlist: usize,
+    /// The number of connected peers.
+    pub peers: usize,
+    /// The number of blocks in the repo.
+    pub repo_blocks: usize,
+    /// The change in the number of blocks in the repo over the interval.
+    pub repo_blocks_delta: i64,
+}
+
+/// The counters from which the [`StatsSnapshot`]s are computed.
+struct Sample {
+    at: Instant,
+    data_sent: u64,
+    data_received: u64,
+    wantlist: usize,
+    peers: usize,
+    repo_blocks: usize,
+}
+
+impl Sample {
+    async fn take<Types: IpfsTypes>(ipfs: &Ipfs<Types>) -> Result<Self, Error> {
+        let bitswap = ipfs.bitswap_stats().await?;
+        Ok(Sample {
+            at: Instant::now(),
+            data_sent: bitswap.data_sent,
+            data_received: bitswap.data_received,
+            wantlist: bitswap.wantlist.len(),
+            peers: ipfs.peers().await?.len(),
+            repo_blocks: ipfs.repo.block_count(),
+        })
+    }
+
+    fn since(&self, previous: &Sample) -> StatsSnapshot {
+        let elapsed = self.at - previous.at;
+        let rate = |now: u64, then: u64| now.saturating_sub(then) as f64 / elapsed.as_secs_f64();
+
+        StatsSnapshot {
+            elapsed,
+            bitswap_send_rate: rate(self.data_sent, previous.data_sent),
+            bitswap_receive_rate: rate(self.data_received, previous.data_received),
+            wantlist: self.wantlist,
+            peers: self.peers,
+            repo_blocks: self.repo_blocks,
+            repo_blocks_delta: self.repo_blocks as i64 - previous.repo_blocks as i64,
+        }
+    }
+}
+
+/// Yields a snapshot after each interval, ending with an error once the node is shut down.
+pub(crate) fn stats_poll<Types: IpfsTypes>(
+    ipfs: Ipfs<Types>,
+    interval: Duration,
+) -> impl Stream<Item = Result<StatsSnapshot, Error>> + Send + 'static {
+    try_stream! {
+        let mut previous = Sample::take(&ipfs).await?;
+
+        loop {
+            Types::TRuntime::delay_for(interval).await;
+
+            let current = Sample::take(&ipfs).await?;
+            yield current.since(&previous);
+            previous = current;
+        }
+    }
+}
+
+/// An unexpected state of the node found by the self-check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// There have been no connected peers for the duration.
+    NoPeers(Duration),
+}
+
+/// The options of the periodic self-check.
+#[derive(Debug, Clone)]
+pub struct SelfCheckOptions {
+    /// The time between the checks.
+    pub interval: Duration,
+    /// The time without peers after which [`Anomaly::NoPeers`] is reported.
+    pub no_peers_after: Duration,
+}
+
+impl Default for SelfCheckOptions {
+    fn default() -> Self {
+        SelfCheckOptions {
+            interval: Duration::from_secs(60),
+            no_peers_after: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+pub(crate) async fn collect<Types: IpfsTypes>(ipfs: &Ipfs<Types>) -> Result<Diagnostics, Error> {
+    let peers = ipfs.peers().await?.len();
+    // the swarm keeps count of all of the connections, of which there is one for each peer above
+    let connections = match ipfs.metrics().gauge_value("ipfs_swarm_connections") {
+        Some(connections) => connections.max(0) as usize,
+        None => peers,
+    };
+
+    let wantlist = ipfs.bitswap_wantlist(None).await?.len();
+    let repo_blocks = ipfs.repo.block_count();
+
+    let repo_path = ipfs.repo.path.clone();
+    let repo_disk_usage = {
+        let repo_path = repo_path.clone();
+        Types::TRuntime::spawn_blocking(move || {
+            ["blockstore", "datastore"]
+                .iter()
+                .map(|dir| disk_usage(&repo_path.join(dir)))
+                .sum::<u64>()
+        })
+        .await
+    };
+
+    let mut features = Vec::new();
+    if cfg!(feature = "test_go_interop") {
+        features.push("test_go_interop");
+    }
+    if cfg!(feature = "test_js_interop") {
+        features.push("test_js_interop");
+    }
+
+    Ok(Diagnostics {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        memory: memory_usage(),
+        open_fds: open_fds(),
+        peers,
+        connections,
+        queues: QueueDepths {
+            block_requests: ipfs.repo.subscriptions.subscriptions.lock().unwrap().len(),
+            wantlist,
+        },
+        repo_path,
+        repo_blocks,
+        repo_disk_usage,
+        features,
+    })
+}
+
+/// Checks the node at the interval until the node is shut down, logging and publishing the
+/// anomalies as [`IpfsEvent::Anomaly`].
+pub(crate) async fn self_check<Types: IpfsTypes>(ipfs: &Ipfs<Types>, options: SelfCheckOptions) {
+    let mut last_peer_seen = Instant::now();
+    let mut reported = false;
+
+    loop {
+        Types::TRuntime::delay_for(options.interval).await;
+
+        let peers = match ipfs.peers().await {
+            Ok(peers) => peers,
+            // the background task has exited
+            Err(_) => return,
+        };
+
+        if !peers.is_empty() {
+            last_peer_seen = Instant::now();
+            reported = false;
+            continue;
+        }
+
+        let without = last_peer_seen.elapsed();
+        // reported once for each period without peers
+        if without >= options.no_peers_after && !reported {
+            warn!("self-check: no peers for {}s", without.as_secs());
+            ipfs.repo
+                .bus
+                .publish(IpfsEvent::Anomaly(Anomaly::NoPeers(without)));
+            reported = true;
+        }
+    }
+}
+
+/// Returns the total size of the files under the path, zero when it does not exist.
+fn disk_usage(path: &Path) -> u64 {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => disk_usage(&entry.path()),
+            Ok(_) => entry.metadata().map(|meta| meta.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(target_os = "linux")]
+fn memory_usage() -> Option<MemoryUsage> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    // the values are in kilobytes, as in `VmRSS:     1234 kB`
+    let field = |name: &str| {
+        status
+            .lines()
+            .find(|line| line.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<u64>()
+            .ok()
+            .map(|kb| kb * 1024)
+    };
+
+    Some(MemoryUsage {
+        resident: field("VmRSS:")?,
+        virtual_: field("VmSize:")?,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory_usage() -> Option<MemoryUsage> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> Option<usize> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_fds() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disk_usage, Anomaly, SelfCheckOptions};
+    use crate::events::IpfsEvent;
+    use crate::{Block, Node};
+    use cid::{Cid, Codec};
+    use multihash::Sha2_256;
+    use std::time::Duration;
+
+    #[tokio::test(max_threads = 1)]
+    async fn report() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        ipfs.put_block(Block::new(data, cid)).await.unwrap();
+
+        let report = ipfs.diagnostics().await.unwrap();
+
+        assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report.peers, 0);
+        assert_eq!(report.connections, 0);
+        assert_eq!(report.queues.block_requests, 0);
+        assert_eq!(report.repo_blocks, 1);
+
+        if cfg!(target_os = "linux") {
+            assert!(report.memory.unwrap().resident > 0);
+            assert!(report.open_fds.unwrap() > 0);
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn stats_snapshots() {
+        use futures::stream::StreamExt;
+
+        let ipfs = Node::new("test_node").await;
+        let stats = ipfs.stats_poll(Duration::from_millis(50));
+        futures::pin_mut!(stats);
+
+        let first = stats.next().await.unwrap().unwrap();
+        assert!(first.elapsed >= Duration::from_millis(50));
+        assert_eq!(first.repo_blocks, 0);
+        assert_eq!(first.repo_blocks_delta, 0);
+
+        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        ipfs.put_block(Block::new(data, cid)).await.unwrap();
+
+        let second = stats.next().await.unwrap().unwrap();
+        assert_eq!(second.repo_blocks, 1);
+        assert_eq!(second.repo_blocks_delta, 1);
+        assert_eq!(second.peers, 0);
+        assert_eq!(second.bitswap_receive_rate, 0.0);
+    }
+
+    #[test]
+    fn disk_usage_of_nested_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("nested")).unwrap();
+        std::fs::write(tmp.path().join("a"), b"foo").unwrap();
+        std::fs::write(tmp.path().join("nested").join("b"), b"barbaz").unwrap();
+
+        assert_eq!(disk_usage(tmp.path()), 9);
+        assert_eq!(disk_usage(&tmp.path().join("missing")), 0);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn no_peers_reported_once() {
+        let ipfs = Node::new("test_node").await;
+        let mut events = ipfs.subscribe_events();
+
+        let options = SelfCheckOptions {
+            interval: Duration::from_millis(10),
+            no_peers_after: Duration::from_millis(30),
+        };
+
+        let checking = ipfs.self_check(options);
+        let _ = tokio::time::timeout(Duration::from_millis(200), checking).await;
+
+        match events.try_recv() {
+            Ok(IpfsEvent::Anomaly(Anomaly::NoPeers(without))) => {
+                assert!(without >= Duration::from_millis(30))
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+        assert!(events.try_recv().is_err());
+    }
+}
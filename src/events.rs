@@ -0,0 +1,130 @@
+//! The [`IpfsEvent`]s published by the subsystems of a node, which can be observed through
+//! [`crate::Ipfs::subscribe_events`].
+
+use crate::diagnostics::Anomaly;
+use cid::Cid;
+use libp2p::PeerId;
+use multihash::Multihash;
+use tokio::sync::broadcast;
+
+/// The number of events buffered for each subscriber, after which the slowest subscribers start
+/// to miss the oldest events.
+const CAPACITY: usize = 256;
+
+/// An event which happened in the node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IpfsEvent {
+    /// A new block was written to the blockstore.
+    BlockStored(Cid),
+    /// A block which was wanted was received from the peer.
+    WantResolved { cid: Cid, from: PeerId },
+    /// The first connection to the peer was established.
+    PeerConnected(PeerId),
+    /// The last connection to the peer was closed.
+    PeerDisconnected(PeerId),
+    /// The peer was found to provide the content with the multihash.
+    ProviderFound { key: Multihash, provider: PeerId },
+    /// The block was pinned.
+    PinAdded { cid: Cid, recursive: bool },
+    /// A garbage collection run of [`crate::Ipfs::gc`] completed, having removed the number of
+    /// blocks, or only found them with `dry_run`.
+    GcRun { removed: usize, dry_run: bool },
+    /// The self-check of [`crate::Ipfs::self_check`] found an anomaly.
+    Anomaly(Anomaly),
+    /// A supervised background task panicked or failed, and is restarted unless it has failed
+    /// too many times or cannot be restarted.
+    TaskFailed {
+        task: &'static str,
+        reason: String,
+        restarting: bool,
+    },
+}
+
+/// The sending side of the event bus, shared by the subsystems.
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus(broadcast::Sender<IpfsEvent>);
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        EventBus(tx)
+    }
+}
+
+impl EventBus {
+    /// Publishes the event to the current subscribers, if there are any.
+    pub(crate) fn publish(&self, event: IpfsEvent) {
+        // sending only fails when there are no subscribers
+        let _ = self.0.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<IpfsEvent> {
+        self.0.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IpfsEvent;
+    use crate::{Block, Node};
+    use cid::{Cid, Codec};
+    use futures::stream::TryStreamExt;
+    use multihash::Sha2_256;
+
+    #[tokio::test(max_threads = 1)]
+    async fn block_stored_and_pin_added() {
+        let ipfs = Node::new("test_node").await;
+        let mut events = ipfs.subscribe_events();
+
+        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+
+        ipfs.put_block(Block::new(data.clone(), cid.clone()))
+            .await
+            .unwrap();
+        // storing the block again does not publish anything
+        ipfs.put_block(Block::new(data, cid.clone())).await.unwrap();
+        ipfs.insert_pin(&cid, false).await.unwrap();
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            IpfsEvent::BlockStored(cid.clone())
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            IpfsEvent::PinAdded {
+                cid,
+                recursive: false
+            }
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn gc_run() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"garbage\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        ipfs.put_block(Block::new(data, cid)).await.unwrap();
+
+        let mut events = ipfs.subscribe_events();
+
+        ipfs.gc(true).try_collect::<Vec<_>>().await.unwrap();
+        ipfs.gc(false).try_collect::<Vec<_>>().await.unwrap();
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            IpfsEvent::GcRun {
+                removed: 1,
+                dry_run: true
+            }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            IpfsEvent::GcRun {
+                removed: 1,
+                dry_run: false
+            }
+        );
+    }
+}
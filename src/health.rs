@@ -0,0 +1,86 @@
+//! The [`Health`] of a node checked by [`crate::Ipfs::health`], from which the liveness and the
+//! readiness of the node are decided, such as for the probes of Kubernetes.
+
+use crate::error::Error;
+use crate::supervisor::TaskState;
+use crate::{Ipfs, IpfsTypes};
+use std::collections::BTreeMap;
+
+/// The state of the subsystems a node needs for serving the requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// The stores of the repo have been opened and the node has not been shut down.
+    pub repo_open: bool,
+    /// The number of addresses the swarm is listening on.
+    pub listening: usize,
+    /// A bootstrap query has found a peer, or there are no bootstrappers to bootstrap from.
+    pub bootstrapped: bool,
+    /// The number of peers in the DHT routing table.
+    pub routing_table_peers: usize,
+    /// The states of the supervised background tasks, such as the `swarm`.
+    pub tasks: BTreeMap<&'static str, TaskState>,
+}
+
+impl Health {
+    /// The node is alive as long as the repo is open and none of the supervised tasks have
+    /// failed for good.
+    pub fn is_live(&self) -> bool {
+        self.repo_open && !self.tasks.values().any(|state| *state == TaskState::Failed)
+    }
+
+    /// The node is ready when it is alive, listening, bootstrapped and has at least the given
+    /// number of peers in the routing table.
+    pub fn is_ready(&self, min_routing_table_peers: usize) -> bool {
+        self.is_live()
+            && self.listening > 0
+            && self.bootstrapped
+            && self.routing_table_peers >= min_routing_table_peers
+    }
+}
+
+pub(crate) async fn check<Types: IpfsTypes>(ipfs: &Ipfs<Types>) -> Result<Health, Error> {
+    let repo_open = ipfs.repo.is_open();
+    let listening = ipfs.addrs_local().await?.len();
+    let dht = ipfs.dht_stats().await?;
+    let bootstrapped = dht.bootstrapped || ipfs.get_bootstrappers().await?.is_empty();
+
+    Ok(Health {
+        repo_open,
+        listening,
+        bootstrapped,
+        routing_table_peers: dht.routing_table_peers,
+        tasks: ipfs.supervisor.tasks(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Node;
+
+    #[tokio::test(max_threads = 1)]
+    async fn ready_without_bootstrappers() {
+        let ipfs = Node::new("test_node").await;
+
+        let health = ipfs.health().await.unwrap();
+
+        assert!(health.repo_open);
+        assert!(health.listening > 0);
+        // there is nothing to bootstrap from
+        assert!(health.bootstrapped);
+        assert_eq!(health.routing_table_peers, 0);
+
+        assert!(health.is_ready(0));
+        assert!(!health.is_ready(1));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn not_live_after_shutdown() {
+        let ipfs = Node::new("test_node").await;
+
+        ipfs.repo.shutdown();
+
+        let health = ipfs.health().await.unwrap();
+        assert!(!health.is_live());
+        assert!(!health.is_ready(0));
+    }
+}
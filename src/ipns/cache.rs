@@ -0,0 +1,288 @@
+//! The cache of the resolved IPNS names and DNSLink domains, so that the repeated resolutions, such
+//! as by a gateway serving `/ipns/` paths, do not query the DHT or the DNS every time.
+
+use crate::error::Error;
+use crate::path::IpfsPath;
+use crate::repo::{Repo, RepoTypes};
+use lru::LruCache;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Configures the caching of the resolved names.
+#[derive(Debug, Clone)]
+pub struct CacheOptions {
+    /// How long a resolved name is used before resolving it again; defaults to one minute like
+    /// in go-ipfs. A zero duration disables the cache.
+    pub ttl: Duration,
+    /// When true, the resolved names are also stored in the datastore, from which they are used
+    /// until expiry after the node has been restarted. Defaults to false.
+    pub persist: bool,
+    /// The number of resolved names kept in memory, the least recently used ones being evicted
+    /// first; defaults to 128 like in go-ipfs. A zero capacity keeps none, in which case only the
+    /// persisted names are used.
+    pub capacity: usize,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions {
+            ttl: Duration::from_secs(60),
+            persist: false,
+            capacity: 128,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    path: IpfsPath,
+    expires: SystemTime,
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    /// The persisted form of the entry: the expiry in seconds since the unix epoch and the path,
+    /// separated by a space.
+    fn to_bytes(&self) -> Vec<u8> {
+        let expires = self
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        format!("{} {}", expires, self.path).into_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let string = std::str::from_utf8(bytes)?;
+        let mut parts = string.splitn(2, ' ');
+
+        let expires = parts.next().unwrap_or_default().parse::<u64>()?;
+        let path = IpfsPath::from_str(parts.next().unwrap_or_default())?;
+
+        Ok(Entry {
+            path,
+            expires: UNIX_EPOCH + Duration::from_secs(expires),
+        })
+    }
+}
+
+/// The resolved names keyed by the base58 encoded `PeerId` or the domain.
+pub(crate) struct Cache {
+    options: CacheOptions,
+    entries: Mutex<LruCache<String, Entry>>,
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Cache")
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl Cache {
+    pub(crate) fn new(options: CacheOptions) -> Self {
+        let entries = Mutex::new(LruCache::new(options.capacity));
+        Cache { options, entries }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.options.ttl > Duration::from_secs(0)
+    }
+
+    /// Returns the unexpired resolution of the name, looking into the datastore when the name is
+    /// not in memory and the cache is persisted.
+    pub(crate) async fn get<Types: RepoTypes>(
+        &self,
+        repo: &Repo<Types>,
+        name: &str,
+    ) -> Option<IpfsPath> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let key = name.to_owned();
+
+            match entries.get(&key) {
+                Some(entry) if !entry.is_expired() => return Some(entry.path.clone()),
+                Some(_) => {
+                    entries.pop(&key);
+                }
+                None => {}
+            }
+        }
+
+        if !self.options.persist {
+            return None;
+        }
+
+        let entry = match repo.get_cached_ipns(name).await {
+            Ok(Some(bytes)) => match Entry::from_bytes(&bytes) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!(
+                        "ignoring the invalid cached resolution of {:?}: {}",
+                        name, e
+                    );
+                    return None;
+                }
+            },
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("failed to read the cached resolution of {:?}: {}", name, e);
+                return None;
+            }
+        };
+
+        if entry.is_expired() {
+            return None;
+        }
+
+        let path = entry.path.clone();
+        self.entries.lock().unwrap().put(name.to_owned(), entry);
+        Some(path)
+    }
+
+    /// Caches the resolution of the name for the configured time to live.
+    pub(crate) async fn insert<Types: RepoTypes>(
+        &self,
+        repo: &Repo<Types>,
+        name: String,
+        path: IpfsPath,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let entry = Entry {
+            path,
+            expires: SystemTime::now() + self.options.ttl,
+        };
+
+        if self.options.persist {
+            if let Err(e) = repo.put_cached_ipns(&name, &entry.to_bytes()).await {
+                warn!("failed to persist the resolution of {:?}: {}", name, e);
+            }
+        }
+
+        self.entries.lock().unwrap().put(name, entry);
+    }
+
+    /// Removes the resolution of the name, so that the name is resolved again on the next use.
+    pub(crate) async fn invalidate<Types: RepoTypes>(
+        &self,
+        repo: &Repo<Types>,
+        name: &str,
+    ) -> Result<(), Error> {
+        self.entries.lock().unwrap().pop(&name.to_owned());
+
+        if self.options.persist {
+            repo.remove_cached_ipns(name).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheOptions};
+    use crate::path::IpfsPath;
+    use crate::Node;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    fn path() -> IpfsPath {
+        IpfsPath::from_str("/ipfs/QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap()
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn cached_until_expired_or_invalidated() {
+        let ipfs = Node::new("test_node").await;
+        let repo = &ipfs.repo;
+
+        let cache = Cache::new(CacheOptions {
+            ttl: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        assert_eq!(cache.get(repo, "example.com").await, None);
+
+        cache.insert(repo, "example.com".into(), path()).await;
+        assert_eq!(cache.get(repo, "example.com").await, Some(path()));
+
+        cache.invalidate(repo, "example.com").await.unwrap();
+        assert_eq!(cache.get(repo, "example.com").await, None);
+
+        cache.insert(repo, "example.com".into(), path()).await;
+        tokio::time::delay_for(Duration::from_millis(150)).await;
+        assert_eq!(cache.get(repo, "example.com").await, None);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn persisted_across_caches() {
+        let ipfs = Node::new("test_node").await;
+        let repo = &ipfs.repo;
+
+        let options = CacheOptions {
+            persist: true,
+            ..Default::default()
+        };
+
+        Cache::new(options.clone())
+            .insert(repo, "example.com".into(), path())
+            .await;
+
+        // as after restarting the node
+        let cache = Cache::new(options.clone());
+        assert_eq!(cache.get(repo, "example.com").await, Some(path()));
+
+        cache.invalidate(repo, "example.com").await.unwrap();
+        assert_eq!(Cache::new(options).get(repo, "example.com").await, None);
+        assert_eq!(repo.get_cached_ipns("example.com").await.unwrap(), None);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn disabled_with_zero_ttl() {
+        let ipfs = Node::new("test_node").await;
+        let repo = &ipfs.repo;
+
+        let cache = Cache::new(CacheOptions {
+            ttl: Duration::from_secs(0),
+            persist: true,
+            ..Default::default()
+        });
+
+        cache.insert(repo, "example.com".into(), path()).await;
+        assert_eq!(cache.get(repo, "example.com").await, None);
+        assert_eq!(repo.get_cached_ipns("example.com").await.unwrap(), None);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn least_recently_used_evicted() {
+        let ipfs = Node::new("test_node").await;
+        let repo = &ipfs.repo;
+
+        let cache = Cache::new(CacheOptions {
+            capacity: 2,
+            ..Default::default()
+        });
+
+        cache.insert(repo, "a.example.com".into(), path()).await;
+        cache.insert(repo, "b.example.com".into(), path()).await;
+        assert_eq!(cache.get(repo, "a.example.com").await, Some(path()));
+
+        cache.insert(repo, "c.example.com".into(), path()).await;
+        assert_eq!(cache.get(repo, "a.example.com").await, Some(path()));
+        assert_eq!(cache.get(repo, "b.example.com").await, None);
+        assert_eq!(cache.get(repo, "c.example.com").await, Some(path()));
+    }
+}
@@ -4,8 +4,16 @@ use crate::error::Error;
 use crate::path::{IpfsPath, PathRoot};
 use crate::repo::RepoTypes;
 use crate::Ipfs;
+use libp2p::PeerId;
+use std::time::Duration;
 
+mod cache;
+#[cfg(not(target_arch = "wasm32"))]
 mod dnslink;
+mod record;
+
+pub(crate) use cache::Cache;
+pub use cache::CacheOptions;
 
 /// IPNS facade around [`Ipns`].
 #[derive(Clone, Debug)]
@@ -18,13 +26,82 @@ impl<Types: RepoTypes> Ipns<Types> {
         Ipns { ipfs }
     }
 
-    /// Resolves a ipns path to an ipld path.
+    /// Resolves a ipns path to an ipld path, using the cached resolution while it is unexpired.
     pub async fn resolve(&self, path: &IpfsPath) -> Result<IpfsPath, Error> {
         let path = path.to_owned();
-        match path.root() {
-            PathRoot::Ipld(_) => Ok(path),
-            PathRoot::Ipns(_) => Err(anyhow::anyhow!("unimplemented")),
-            PathRoot::Dns(domain) => Ok(dnslink::resolve(domain).await?),
+
+        let name = match cache_key(path.root()) {
+            Some(name) => name,
+            None => return Ok(path),
+        };
+
+        let cache = &self.ipfs.ipns_cache;
+        let repo = &self.ipfs.repo;
+
+        if let Some(resolved) = cache.get(repo, &name).await {
+            trace!(name = %name, "resolved from the cache");
+            return Ok(resolved);
         }
+
+        let resolved = match path.root() {
+            PathRoot::Ipld(_) => unreachable!("ipld paths are not resolved"),
+            PathRoot::Ipns(peer_id) => record::resolve(&self.ipfs, peer_id).await?,
+            #[cfg(not(target_arch = "wasm32"))]
+            PathRoot::Dns(domain) => dnslink::resolve(domain).await?,
+            #[cfg(target_arch = "wasm32")]
+            PathRoot::Dns(_) => return Err(anyhow::anyhow!("DNSLink is not supported on wasm32")),
+        };
+
+        cache.insert(repo, name, resolved.clone()).await;
+
+        Ok(resolved)
+    }
+
+    /// Publishes the `path` under the name of the key of the keystore, returning the sequence
+    /// number of the record. The cached resolution of the name is removed.
+    pub async fn publish(
+        &self,
+        path: &IpfsPath,
+        key: &str,
+        lifetime: Duration,
+    ) -> Result<u64, Error> {
+        let keypair = self.ipfs.keystore.get(&self.ipfs.repo, key).await?;
+        let sequence = record::publish(&self.ipfs, &keypair, path, lifetime).await?;
+
+        let name = keypair.public().into_peer_id().to_base58();
+        self.ipfs
+            .ipns_cache
+            .invalidate(&self.ipfs.repo, &name)
+            .await?;
+
+        Ok(sequence)
+    }
+
+    /// Resolves the name of the peer through the records in the DHT, bypassing the cache.
+    pub async fn resolve_name(&self, peer_id: &PeerId) -> Result<IpfsPath, Error> {
+        record::resolve(&self.ipfs, peer_id).await
+    }
+
+    /// Removes the cached resolution of the root of the path, so that it will be resolved again.
+    pub async fn invalidate(&self, path: &IpfsPath) -> Result<(), Error> {
+        match cache_key(path.root()) {
+            Some(name) => {
+                self.ipfs
+                    .ipns_cache
+                    .invalidate(&self.ipfs.repo, &name)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// The name under which the resolution of the root is cached, or `None` for the ipld paths which
+/// need no resolving.
+fn cache_key(root: &PathRoot) -> Option<String> {
+    match root {
+        PathRoot::Ipld(_) => None,
+        PathRoot::Ipns(peer_id) => Some(peer_id.to_base58()),
+        PathRoot::Dns(domain) => Some(domain.to_owned()),
     }
 }
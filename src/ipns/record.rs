@@ -0,0 +1,238 @@
+//! Publishing and resolving of the `/ipns/<peer_id>` names through the IPNS records stored in
+//! the DHT, compatible with the records of go-ipfs `ipfs name publish`.
+
+use crate::error::Error;
+use crate::path::IpfsPath;
+use crate::repo::RepoTypes;
+use crate::Ipfs;
+use chrono::{DateTime, SecondsFormat, Utc};
+use libp2p::core::PublicKey;
+use libp2p::identity::Keypair;
+use libp2p::kad::Quorum;
+use libp2p::PeerId;
+use prost::Message;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/ipns_pb.rs"));
+}
+
+#[derive(Debug)]
+pub struct IpnsRecordError(&'static str);
+
+impl fmt::Display for IpnsRecordError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "IPNS record error: {}", self.0)
+    }
+}
+
+impl std::error::Error for IpnsRecordError {}
+
+/// Signs a record of the `path` with the `keypair`, valid for the `lifetime`, and puts it into the
+/// DHT under the name of the key. The sequence number follows the one of the record published
+/// earlier, which is kept in the repo, and is returned.
+pub async fn publish<Types: RepoTypes>(
+    ipfs: &Ipfs<Types>,
+    keypair: &Keypair,
+    path: &IpfsPath,
+    lifetime: Duration,
+) -> Result<u64, Error> {
+    let peer_id = keypair.public().into_peer_id();
+
+    let sequence = match ipfs.repo.get_ipns_record(&peer_id).await? {
+        Some(bytes) => pb::IpnsEntry::decode(&bytes[..])
+            .map(|previous| previous.sequence + 1)
+            .unwrap_or(0),
+        None => 0,
+    };
+
+    let value = path.to_string().into_bytes();
+    let eol = DateTime::<Utc>::from(SystemTime::now() + lifetime);
+    let validity = eol.to_rfc3339_opts(SecondsFormat::Nanos, true).into_bytes();
+    let signature = keypair.sign(&signed_bytes(&value, &validity))?;
+
+    // the public keys which are not inlined into the peer id travel with the record
+    let pub_key = if inlined_public_key(&peer_id).is_some() {
+        Vec::new()
+    } else {
+        keypair.public().into_protobuf_encoding()
+    };
+
+    let entry = pb::IpnsEntry {
+        value,
+        signature,
+        validity_type: pb::ipns_entry::ValidityType::Eol as i32,
+        validity,
+        sequence,
+        ttl: 0,
+        pub_key,
+    };
+
+    let mut bytes = Vec::with_capacity(entry.encoded_len());
+    entry.encode(&mut bytes)?;
+
+    ipfs.repo.put_ipns_record(&peer_id, &bytes).await?;
+    ipfs.dht_put(dht_key(&peer_id), bytes, Quorum::One).await?;
+
+    Ok(sequence)
+}
+
+/// Looks up the records of the `peer_id` from the DHT and returns the path of the first one with
+/// a valid signature which has not reached its end of life, preferring the records with the
+/// highest sequence number.
+pub async fn resolve<Types: RepoTypes>(
+    ipfs: &Ipfs<Types>,
+    peer_id: &PeerId,
+) -> Result<IpfsPath, Error> {
+    let values = ipfs.dht_get(dht_key(peer_id), Quorum::One).await?;
+    let now = DateTime::<Utc>::from(SystemTime::now());
+
+    let mut entries = values
+        .iter()
+        .filter_map(|value| pb::IpnsEntry::decode(&value[..]).ok())
+        .filter(|entry| verify(peer_id, entry).is_ok())
+        .filter(|entry| matches!(end_of_life(entry), Ok(eol) if eol > now))
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| b.sequence.cmp(&a.sequence));
+
+    let entry = entries
+        .into_iter()
+        .next()
+        .ok_or(IpnsRecordError("no valid records found"))?;
+
+    let value =
+        std::str::from_utf8(&entry.value).map_err(|_| IpnsRecordError("the value is not utf-8"))?;
+
+    IpfsPath::from_str(value)
+}
+
+/// The DHT key under which go-ipfs stores the records: `/ipns/` followed by the bytes of the
+/// `peer_id`.
+fn dht_key(peer_id: &PeerId) -> Vec<u8> {
+    let mut key = b"/ipns/".to_vec();
+    key.extend_from_slice(peer_id.as_bytes());
+    key
+}
+
+/// Verifies the signature of the record over the concatenation of the value, the validity and the
+/// validity type, which is always `EOL`.
+fn verify(peer_id: &PeerId, entry: &pb::IpnsEntry) -> Result<(), IpnsRecordError> {
+    let public_key = public_key(peer_id, entry)?;
+
+    if public_key.verify(
+        &signed_bytes(&entry.value, &entry.validity),
+        &entry.signature,
+    ) {
+        Ok(())
+    } else {
+        Err(IpnsRecordError("invalid signature"))
+    }
+}
+
+fn signed_bytes(value: &[u8], validity: &[u8]) -> Vec<u8> {
+    let mut signed = Vec::with_capacity(value.len() + validity.len() + 3);
+    signed.extend_from_slice(value);
+    signed.extend_from_slice(validity);
+    signed.extend_from_slice(b"EOL");
+    signed
+}
+
+/// The end of life of the record, which go-ipfs writes in RFC 3339 with nanoseconds.
+fn end_of_life(entry: &pb::IpnsEntry) -> Result<DateTime<Utc>, IpnsRecordError> {
+    if entry.validity_type != pb::ipns_entry::ValidityType::Eol as i32 {
+        return Err(IpnsRecordError("unsupported validity type"));
+    }
+
+    std::str::from_utf8(&entry.validity)
+        .ok()
+        .and_then(|validity| DateTime::parse_from_rfc3339(validity).ok())
+        .map(|eol| eol.with_timezone(&Utc))
+        .ok_or(IpnsRecordError("invalid validity"))
+}
+
+/// The protobuf encoded public key inlined in the `peer_id` as an identity multihash, which is the
+/// case with the ed25519 keys.
+fn inlined_public_key(peer_id: &PeerId) -> Option<&[u8]> {
+    match peer_id.as_bytes() {
+        [0x00, len, inlined @ ..] if *len as usize == inlined.len() => Some(inlined),
+        _ => None,
+    }
+}
+
+/// The public key is either inlined in the `peer_id` or carried in the record itself.
+fn public_key(peer_id: &PeerId, entry: &pb::IpnsEntry) -> Result<PublicKey, IpnsRecordError> {
+    let bytes = inlined_public_key(peer_id).unwrap_or(&entry.pub_key[..]);
+
+    let public_key = PublicKey::from_protobuf_encoding(bytes)
+        .map_err(|_| IpnsRecordError("invalid public key"))?;
+
+    if &public_key.clone().into_peer_id() != peer_id {
+        return Err(IpnsRecordError("the public key does not match the name"));
+    }
+
+    Ok(public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{end_of_life, pb, verify};
+    use libp2p::identity::Keypair;
+
+    fn signed_entry(keypair: &Keypair, value: &str) -> pb::IpnsEntry {
+        let validity = b"2030-01-01T00:00:00.000000000Z".to_vec();
+        let mut signed = value.as_bytes().to_vec();
+        signed.extend_from_slice(&validity);
+        signed.extend_from_slice(b"EOL");
+
+        pb::IpnsEntry {
+            value: value.as_bytes().to_vec(),
+            signature: keypair.sign(&signed).unwrap(),
+            validity_type: 0,
+            validity,
+            sequence: 0,
+            ttl: 0,
+            pub_key: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn verifies_inlined_ed25519_key() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().into_peer_id();
+        let entry = signed_entry(
+            &keypair,
+            "/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+        );
+
+        verify(&peer_id, &entry).unwrap();
+
+        let mut tampered = entry;
+        tampered.value = b"/ipfs/QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".to_vec();
+        assert!(verify(&peer_id, &tampered).is_err());
+
+        let other = Keypair::generate_ed25519().public().into_peer_id();
+        let entry = signed_entry(
+            &keypair,
+            "/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+        );
+        assert!(verify(&other, &entry).is_err());
+    }
+
+    #[test]
+    fn reads_go_ipfs_end_of_life() {
+        let keypair = Keypair::generate_ed25519();
+        let mut entry = signed_entry(
+            &keypair,
+            "/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+        );
+
+        let eol = end_of_life(&entry).unwrap();
+        assert_eq!(eol.to_rfc3339(), "2030-01-01T00:00:00+00:00");
+
+        entry.validity = b"tomorrow".to_vec();
+        assert!(end_of_life(&entry).is_err());
+    }
+}
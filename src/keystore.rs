@@ -0,0 +1,481 @@
+//! The keystore of the named keys besides the identity of the node, used for example to publish
+//! IPNS names of their own.
+//!
+//! The keys are stored in [`crate::repo::Column::Keystore`] of the datastore, encrypted with
+//! ChaCha20-Poly1305 under a secret derived with HKDF-SHA256 from the secret key of the identity of
+//! the node, so that the stored keys are of no use without the identity. The identity itself is
+//! always available under the name [`SELF_KEY`].
+//!
+//! libp2p does not expose the secret key of an RSA identity, so there is nothing to derive the
+//! secret from, and the named keys fail with [`KeystoreError::UnsupportedIdentity`] on the nodes
+//! with an RSA identity.
+
+use crate::error::Error;
+use crate::repo::{Repo, RepoTypes};
+use chacha20poly1305::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use libp2p::identity::{ed25519, secp256k1, Keypair};
+use libp2p::PeerId;
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+
+/// The name of the identity of the node, which cannot be generated, removed or renamed.
+pub const SELF_KEY: &str = "self";
+
+/// The HKDF info of the secret of the keystore, separating it from any other secret derived from
+/// the identity of the node.
+const SECRET_INFO: &[u8] = b"rust-ipfs keystore v1";
+
+const NONCE_LEN: usize = 12;
+
+/// The type of the keys generated with [`crate::Ipfs::key_gen`]. The RSA keys are not supported,
+/// as `libp2p` can neither generate nor encode them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    Ed25519,
+    Secp256k1,
+}
+
+impl KeyType {
+    /// The first byte of the stored keys.
+    fn tag(self) -> u8 {
+        match self {
+            KeyType::Ed25519 => 1,
+            KeyType::Secp256k1 => 2,
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyType::Ed25519 => write!(fmt, "ed25519"),
+            KeyType::Secp256k1 => write!(fmt, "secp256k1"),
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = KeystoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ed25519" => Ok(KeyType::Ed25519),
+            "secp256k1" => Ok(KeyType::Secp256k1),
+            other => Err(KeystoreError::UnsupportedType(other.to_owned())),
+        }
+    }
+}
+
+/// A named key of the keystore, as returned by [`crate::Ipfs::key_list`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub name: String,
+    /// The peer id of the public key, which is also the IPNS name published with the key.
+    pub id: PeerId,
+}
+
+/// The failures of the keystore operations besides the failures of the repo.
+#[derive(Debug, thiserror::Error)]
+pub enum KeystoreError {
+    #[error("invalid key name {0:?}")]
+    InvalidName(String),
+    #[error("the key name {:?} is reserved for the identity of the node", SELF_KEY)]
+    Reserved,
+    #[error("key {0:?} already exists")]
+    AlreadyExists(String),
+    #[error("no key named {0:?}")]
+    NotFound(String),
+    #[error("unsupported key type {0:?}")]
+    UnsupportedType(String),
+    #[error("key {0:?} cannot be decrypted with the identity of the node")]
+    Undecryptable(String),
+    #[error("the named keys need an ed25519 or secp256k1 identity of the node")]
+    UnsupportedIdentity,
+}
+
+/// The keystore of a node, see the module level documentation.
+pub(crate) struct Keystore {
+    identity: Keypair,
+    /// None for an RSA identity.
+    cipher: Option<ChaCha20Poly1305>,
+}
+
+impl fmt::Debug for Keystore {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Keystore").finish()
+    }
+}
+
+impl Keystore {
+    pub(crate) fn new(identity: Keypair) -> Self {
+        let ikm = match &identity {
+            Keypair::Ed25519(keypair) => keypair.secret().as_ref().to_vec(),
+            Keypair::Secp256k1(keypair) => keypair.secret().to_bytes().to_vec(),
+            Keypair::Rsa(_) => {
+                return Keystore {
+                    identity,
+                    cipher: None,
+                }
+            }
+        };
+
+        let mut secret = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &ikm)
+            .expand(SECRET_INFO, &mut secret)
+            .expect("32 bytes is a valid length for HKDF-SHA256");
+        let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&secret));
+
+        Keystore {
+            identity,
+            cipher: Some(cipher),
+        }
+    }
+
+    fn cipher(&self) -> Result<&ChaCha20Poly1305, KeystoreError> {
+        self.cipher
+            .as_ref()
+            .ok_or(KeystoreError::UnsupportedIdentity)
+    }
+
+    /// Returns the keypair of the given name, which is the identity of the node for [`SELF_KEY`].
+    pub(crate) async fn get<T: RepoTypes>(
+        &self,
+        repo: &Repo<T>,
+        name: &str,
+    ) -> Result<Keypair, Error> {
+        if name == SELF_KEY {
+            return Ok(self.identity.clone());
+        }
+
+        let stored = repo
+            .get_key(name)
+            .await?
+            .ok_or_else(|| KeystoreError::NotFound(name.to_owned()))?;
+
+        Ok(self.decrypt(name, &stored)?)
+    }
+
+    /// Generates and stores a new key of the given name, returning its peer id.
+    pub(crate) async fn generate<T: RepoTypes>(
+        &self,
+        repo: &Repo<T>,
+        name: &str,
+        key_type: KeyType,
+    ) -> Result<PeerId, Error> {
+        validate_name(name)?;
+        self.cipher()?;
+
+        if repo.get_key(name).await?.is_some() {
+            return Err(KeystoreError::AlreadyExists(name.to_owned()).into());
+        }
+
+        let keypair = match key_type {
+            KeyType::Ed25519 => Keypair::generate_ed25519(),
+            KeyType::Secp256k1 => Keypair::generate_secp256k1(),
+        };
+
+        repo.put_key(name, &self.encrypt(name, &keypair)?).await?;
+
+        Ok(keypair.public().into_peer_id())
+    }
+
+    /// Lists the keys, starting with the identity of the node.
+    pub(crate) async fn list<T: RepoTypes>(&self, repo: &Repo<T>) -> Result<Vec<KeyInfo>, Error> {
+        let mut names = repo.list_keys().await?;
+        names.sort();
+
+        let mut keys = Vec::with_capacity(names.len() + 1);
+        keys.push(KeyInfo {
+            name: SELF_KEY.to_owned(),
+            id: self.identity.public().into_peer_id(),
+        });
+
+        for name in names {
+            let id = self.get(repo, &name).await?.public().into_peer_id();
+            keys.push(KeyInfo { name, id });
+        }
+
+        Ok(keys)
+    }
+
+    /// Removes the key of the given name, returning its peer id.
+    pub(crate) async fn remove<T: RepoTypes>(
+        &self,
+        repo: &Repo<T>,
+        name: &str,
+    ) -> Result<PeerId, Error> {
+        validate_name(name)?;
+
+        let id = self.get(repo, name).await?.public().into_peer_id();
+        repo.remove_key(name).await?;
+
+        Ok(id)
+    }
+
+    /// Renames the key, returning its peer id. An existing key of the new name is replaced only
+    /// when `force` is true.
+    pub(crate) async fn rename<T: RepoTypes>(
+        &self,
+        repo: &Repo<T>,
+        old: &str,
+        new: &str,
+        force: bool,
+    ) -> Result<PeerId, Error> {
+        validate_name(old)?;
+        validate_name(new)?;
+
+        let keypair = self.get(repo, old).await?;
+
+        if old == new {
+            return Ok(keypair.public().into_peer_id());
+        }
+
+        if !force && repo.get_key(new).await?.is_some() {
+            return Err(KeystoreError::AlreadyExists(new.to_owned()).into());
+        }
+
+        // the name is authenticated along with the key, so the key is encrypted again
+        repo.put_key(new, &self.encrypt(new, &keypair)?).await?;
+        repo.remove_key(old).await?;
+
+        Ok(keypair.public().into_peer_id())
+    }
+
+    /// Encrypts the keypair into the nonce followed by the ciphertext.
+    fn encrypt(&self, name: &str, keypair: &Keypair) -> Result<Vec<u8>, Error> {
+        let mut plaintext = Vec::with_capacity(65);
+
+        match keypair {
+            Keypair::Ed25519(keypair) => {
+                plaintext.push(KeyType::Ed25519.tag());
+                plaintext.extend_from_slice(&keypair.encode());
+            }
+            Keypair::Secp256k1(keypair) => {
+                plaintext.push(KeyType::Secp256k1.tag());
+                plaintext.extend_from_slice(&keypair.secret().to_bytes());
+            }
+            Keypair::Rsa(_) => return Err(KeystoreError::UnsupportedType("rsa".into()).into()),
+        }
+
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let payload = Payload {
+            msg: &plaintext,
+            aad: name.as_bytes(),
+        };
+
+        let ciphertext = self
+            .cipher()?
+            .encrypt(GenericArray::from_slice(&nonce), payload)
+            .map_err(|_| anyhow::anyhow!("failed to encrypt key {:?}", name))?;
+
+        let mut stored = nonce.to_vec();
+        stored.extend_from_slice(&ciphertext);
+        Ok(stored)
+    }
+
+    fn decrypt(&self, name: &str, stored: &[u8]) -> Result<Keypair, KeystoreError> {
+        let undecryptable = || KeystoreError::Undecryptable(name.to_owned());
+
+        if stored.len() < NONCE_LEN {
+            return Err(undecryptable());
+        }
+
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: name.as_bytes(),
+        };
+
+        let mut plaintext = self
+            .cipher()?
+            .decrypt(GenericArray::from_slice(nonce), payload)
+            .map_err(|_| undecryptable())?;
+
+        match plaintext.split_first_mut() {
+            Some((tag, secret)) if *tag == KeyType::Ed25519.tag() => {
+                ed25519::Keypair::decode(secret)
+                    .map(Keypair::Ed25519)
+                    .map_err(|_| undecryptable())
+            }
+            Some((tag, secret)) if *tag == KeyType::Secp256k1.tag() => {
+                secp256k1::SecretKey::from_bytes(secret)
+                    .map(|secret| Keypair::Secp256k1(secret.into()))
+                    .map_err(|_| undecryptable())
+            }
+            _ => Err(undecryptable()),
+        }
+    }
+}
+
+/// Checks the name like go-ipfs does; the names are also the keys of the datastore.
+fn validate_name(name: &str) -> Result<(), KeystoreError> {
+    if name == SELF_KEY {
+        Err(KeystoreError::Reserved)
+    } else if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        Err(KeystoreError::InvalidName(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyType, Keystore, KeystoreError, SELF_KEY};
+    use crate::repo::{create_repo, RepoOptions};
+    use crate::{IpfsOptions, TestTypes};
+    use hex_literal::hex;
+    use libp2p::identity::Keypair;
+
+    #[tokio::test(max_threads = 1)]
+    async fn generate_list_rename_and_remove() {
+        let options = IpfsOptions::inmemory_with_generated_keys();
+        let (repo, _) = create_repo::<TestTypes>(RepoOptions::from(&options));
+        let keystore = Keystore::new(options.keypair.clone());
+
+        let ed = keystore
+            .generate(&repo, "ed", KeyType::Ed25519)
+            .await
+            .unwrap();
+        let secp = keystore
+            .generate(&repo, "secp", KeyType::Secp256k1)
+            .await
+            .unwrap();
+
+        let err = keystore
+            .generate(&repo, "ed", KeyType::Secp256k1)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeystoreError>(),
+            Some(KeystoreError::AlreadyExists(_))
+        ));
+
+        let names = keystore
+            .list(&repo)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|key| (key.name, key.id))
+            .collect::<Vec<_>>();
+
+        let identity = options.keypair.public().into_peer_id();
+        assert_eq!(
+            names,
+            vec![
+                (SELF_KEY.to_owned(), identity),
+                ("ed".to_owned(), ed.clone()),
+                ("secp".to_owned(), secp.clone())
+            ]
+        );
+
+        assert_eq!(
+            keystore.rename(&repo, "ed", "other", false).await.unwrap(),
+            ed
+        );
+        let renamed = keystore.get(&repo, "other").await.unwrap();
+        assert_eq!(renamed.public().into_peer_id(), ed);
+        assert!(keystore.get(&repo, "ed").await.is_err());
+
+        assert!(keystore
+            .rename(&repo, "other", "secp", false)
+            .await
+            .is_err());
+        assert!(keystore.remove(&repo, SELF_KEY).await.is_err());
+
+        assert_eq!(keystore.remove(&repo, "secp").await.unwrap(), secp);
+        assert_eq!(keystore.list(&repo).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn keys_need_the_same_identity() {
+        let options = IpfsOptions::inmemory_with_generated_keys();
+        let (repo, _) = create_repo::<TestTypes>(RepoOptions::from(&options));
+
+        let keystore = Keystore::new(options.keypair.clone());
+        let id = keystore
+            .generate(&repo, "key", KeyType::Ed25519)
+            .await
+            .unwrap();
+
+        // the secret is the same when the node starts again with the same identity
+        let again = Keystore::new(options.keypair.clone());
+        let keypair = again.get(&repo, "key").await.unwrap();
+        assert_eq!(keypair.public().into_peer_id(), id);
+
+        let other = Keystore::new(Keypair::generate_ed25519());
+        let err = other.get(&repo, "key").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeystoreError>(),
+            Some(KeystoreError::Undecryptable(_))
+        ));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn no_named_keys_with_rsa_identity() {
+        // libp2p cannot generate RSA keys, so this is one generated with openssl in PKCS#8
+        let mut pkcs8 = hex!(
+            "308204bc020100300d06092a864886f70d0101010500048204a6308204a20201
+            000282010100d536677e6216eedcda66a8d3e65c42e68a5a0a9967c3e5fb6be0
+            e89d44b12a496e6dcc66b61c6a12020fbd109a6c60480747a573e0b4aad21677
+            205f85b36037541249dadd9c3b3a5b73ad277c0acf982ba04c6c51e000e66d53
+            64ebaa7db1396d2f82e1ab3a6a2f85a266072fa62c32a6eb72edee81744d4d19
+            91d40fb90ef4ca4a8771c3f0fe21f64a8ad227e782f570ef2dc1415205272d4e
+            fa93c4a85d9397200d345726a2174a92a5125050191b3307cd9d4e694f77f1bf
+            7951704bbeb151638aa31cc0a90ea8f75814b9acb1d722b5806658c880dbe58a
+            1c70d26c82f4ae73a31d2634252aa2fb79a816aa772f2e9434d4b5636a232f28
+            2c3bd55274f1020301000102820100521170e0bacced0061a8a64f7a1a053e4b
+            fec099afd13dd2fb01454853ca85e661f52d7a539ab8515a2dc8ae7d623de53b
+            b81e3d6e3edc89a550114bf360ca2641dc1045888bbc58881f0c85683f990c8f
+            ae51fc57bf8b5926b7b4dee96f1c2a6896a3204e125c1112b521e49e38ae4688
+            ed93fd7592aabb9e73bb280f2d424dceba4f045b539039d6e3e19b5e01ec2fb0
+            a89259677ffbf7be846e53ab5f9665f936d7d196f97a3ad7e20887ce0c47ff34
+            1267742b9e79a902e037db849be7f7deefad965442d237fdaafdadbcc44eb68b
+            500f55174381c34d25a8d842a57afdf8508c48571f4b3a89551c2cc1067604b4
+            73a1977a48d06f6229d83de684928902818100fbe1be34a99c3934b0dce5f565
+            85aef12c5c19b011882b3c0144428b8a5fbc4991851aa8a7557c66d1c74d82d7
+            d1fffeaf568f873dffbc8cabb9c08c5ff944084fa1af3ccd56f35ebbf45107c1
+            eeaa7e3233adae31e94b23bfcb6c5abd014cf979143bdec8b50a0780bcecd31d
+            a090bec15b97b5a05446994454fed070e321b302818100d8b2cf61bc0dfac25f
+            92b34d00c2b39636294d91425db24d7691a43df4a2336db50511452b2b1c0662
+            c8b304a82b1087fbda0b1872be135c51d7be217815764212f3f6bd2e4c3196d7
+            29adc245a3cf95909ec79affff7dbe54220a14f3c51135a6321d9e7ede2178b2
+            bd24ff0e3c77eede834df428088e31e7cefe02693854cb028180762de38d57eb
+            19981447808993d9964c47d0eaa886814a2fbc3467b88af30227ce64122f015c
+            05a34457eb91316bb15db0acbf68e7f8fe614e0d383f64a4527c47c887acd277
+            3276c75f0949c77f1075675e81e5d23b306b1489004cce9336a7725cc0fe7553
+            23e2834fa11a28b3765659e87c94d625d47e02e6922c005b412b028180059efd
+            ca273aa59a8d281d068e527e9e12e19765813734fcd56a89bc21b0636d2118f4
+            8dbac4ef812ae6d1ddf90ec4eb021971d83c81b9b6d758a551f0ef7d95d1300a
+            e27564fc9451187fad5e69156567cef6968ab571e991fd200dd314f59152b252
+            1a5e681cae28d4c734e685a306cb36d4bad49f04313f361b58acaa0d69028180
+            167f0d349108b50af441a3fa7f56bb1ba1d204674ba742dcd8cf4a0349dc249d
+            0aa26425beff363cb06b9fcbe612fa274574c7cf036e8c885b7f643f5bc3e121
+            6198912fa1f66b04554cc28fb29e5fa527142f4c86e3565559b726342ad0ed2a
+            5e0265dad5176aac92b5b8beee917a96cd31131a4d41dad6ac4e300208c1d069"
+        );
+        let identity = Keypair::rsa_from_pkcs8(&mut pkcs8).unwrap();
+
+        let options = IpfsOptions::inmemory_with_generated_keys();
+        let (repo, _) = create_repo::<TestTypes>(RepoOptions::from(&options));
+        let keystore = Keystore::new(identity.clone());
+
+        let err = keystore
+            .generate(&repo, "key", KeyType::Ed25519)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeystoreError>(),
+            Some(KeystoreError::UnsupportedIdentity)
+        ));
+
+        // the identity itself is still available
+        let keys = keystore.list(&repo).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].id, identity.public().into_peer_id());
+        let keypair = keystore.get(&repo, SELF_KEY).await.unwrap();
+        assert_eq!(keypair.public().into_peer_id(), keys[0].id);
+    }
+}
@@ -22,17 +22,31 @@
 // the docs better.
 //#![allow(private_intra_doc_links)]
 
+pub mod api;
 pub mod config;
 pub mod dag;
+pub mod diagnostics;
 pub mod error;
+pub mod events;
+pub mod health;
 #[macro_use]
 pub mod ipld;
 pub mod ipns;
+pub mod keystore;
+pub mod metrics;
 pub mod p2p;
 pub mod path;
+mod prefetch;
 pub mod refs;
+pub mod replication;
 pub mod repo;
+mod reprovider;
+pub mod runtime;
 mod subscription;
+pub mod supervisor;
+pub mod sync;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_support;
 pub mod unixfs;
 
 #[macro_use]
@@ -73,6 +87,7 @@ use self::{
         create_swarm, SwarmOptions, TSwarm,
     },
     repo::{create_repo, Repo, RepoEvent, RepoOptions},
+    runtime::Runtime,
     subscription::SubscriptionFuture,
 };
 
@@ -94,16 +109,19 @@ pub use libp2p::{
     kad::{record::Key, Quorum},
 };
 
-/// Represents the configuration of the Ipfs node, its backing blockstore and datastore.
+/// Represents the configuration of the Ipfs node, its backing blockstore, datastore and runtime.
 pub trait IpfsTypes: RepoTypes {}
 impl<T: RepoTypes> IpfsTypes for T {}
 
 /// Default node configuration, currently with persistent block store and data store for pins.
+#[cfg(not(target_arch = "wasm32"))]
 #[derive(Debug)]
 pub struct Types;
+#[cfg(not(target_arch = "wasm32"))]
 impl RepoTypes for Types {
     type TBlockStore = repo::fs::FsBlockStore;
     type TDataStore = repo::fs::FsDataStore;
+    type TRuntime = runtime::TokioRuntime;
 }
 
 /// In-memory testing configuration used in tests.
@@ -112,6 +130,7 @@ pub struct TestTypes;
 impl RepoTypes for TestTypes {
     type TBlockStore = repo::mem::MemBlockStore;
     type TDataStore = repo::mem::MemDataStore;
+    type TRuntime = runtime::TokioRuntime;
 }
 
 /// Ipfs node options used to configure the node to be created with [`UninitializedIpfs`].
@@ -145,6 +164,12 @@ pub struct IpfsOptions {
     /// [`libp2p_kad::KademliaConfig::set_protocol_name`]: https://docs.rs/libp2p-kad/*/libp2p_kad/struct.KademliaConfig.html##method.set_protocol_name
     pub kad_protocol: Option<String>,
 
+    /// The parallelism, the timeout and the replication factor of the Kademlia queries.
+    pub kad_query: p2p::KadQueryOptions,
+
+    /// The pubsub router, floodsub by default or gossipsub, and the signing of the messages.
+    pub pubsub: p2p::PubsubOptions,
+
     /// Bound listening addresses; by default the node will not listen on any address.
     pub listening_addrs: Vec<Multiaddr>,
 
@@ -154,6 +179,25 @@ pub struct IpfsOptions {
     /// with this span or spans referring to this as their parent. Setting this other than `None`
     /// default is useful when running multiple nodes.
     pub span: Option<Span>,
+
+    /// The caching of the resolved IPNS names and DNSLink domains.
+    pub ipns_cache: ipns::CacheOptions,
+
+    /// The interval at which the provider records of all of the stored blocks are published to
+    /// the DHT, the first time shortly after the start. When `None`, only the blocks stored while
+    /// the node runs are provided, and their records are republished by `libp2p`'s Kademlia. The
+    /// newly stored blocks are provided through a bounded queue, and the ones stored while the
+    /// queue is full are provided only by the next round of the reprovider.
+    pub reprovide_interval: Option<std::time::Duration>,
+
+    /// The watermarks between which the number of the open connections is kept by disconnecting
+    /// the least valuable peers.
+    pub connection_limits: p2p::ConnectionLimits,
+
+    /// The pre-shared key of a private network, which only the peers with the same key can
+    /// connect to. The go-ipfs compatible `swarm.key` file of a repo can be read with
+    /// [`p2p::read_swarm_key`].
+    pub swarm_key: Option<p2p::PreSharedKey>,
 }
 
 impl fmt::Debug for IpfsOptions {
@@ -166,8 +210,18 @@ impl fmt::Debug for IpfsOptions {
             .field("keypair", &DebuggableKeypair(&self.keypair))
             .field("mdns", &self.mdns)
             .field("kad_protocol", &self.kad_protocol)
+            .field("kad_query", &self.kad_query)
+            .field("pubsub", &self.pubsub)
             .field("listening_addrs", &self.listening_addrs)
             .field("span", &self.span)
+            .field("ipns_cache", &self.ipns_cache)
+            .field("reprovide_interval", &self.reprovide_interval)
+            .field("connection_limits", &self.connection_limits)
+            // only the fingerprint, so that the key isn't logged
+            .field(
+                "swarm_key",
+                &self.swarm_key.map(|psk| psk.fingerprint().to_string()),
+            )
             .finish()
     }
 }
@@ -184,8 +238,14 @@ impl IpfsOptions {
             bootstrap: Default::default(),
             // default to lan kad for go-ipfs use in tests
             kad_protocol: Some("/ipfs/lan/kad/1.0.0".to_owned()),
+            kad_query: Default::default(),
+            pubsub: Default::default(),
             listening_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
             span: None,
+            ipns_cache: Default::default(),
+            reprovide_interval: None,
+            connection_limits: Default::default(),
+            swarm_key: None,
         }
     }
 }
@@ -226,6 +286,9 @@ pub struct Ipfs<Types: IpfsTypes> {
     repo: Arc<Repo<Types>>,
     keys: DebuggableKeypair<Keypair>,
     to_task: Sender<IpfsEvent>,
+    ipns_cache: Arc<ipns::Cache>,
+    keystore: Arc<keystore::Keystore>,
+    supervisor: supervisor::Supervisor,
 }
 
 impl<Types: IpfsTypes> Clone for Ipfs<Types> {
@@ -235,6 +298,9 @@ impl<Types: IpfsTypes> Clone for Ipfs<Types> {
             repo: Arc::clone(&self.repo),
             keys: self.keys.clone(),
             to_task: self.to_task.clone(),
+            ipns_cache: Arc::clone(&self.ipns_cache),
+            keystore: Arc::clone(&self.keystore),
+            supervisor: self.supervisor.clone(),
         }
     }
 }
@@ -256,6 +322,10 @@ enum IpfsEvent {
     Listeners(Channel<Vec<Multiaddr>>),
     /// Connections
     Connections(Channel<Vec<Connection>>),
+    /// Counts of the connections
+    SwarmStats(Channel<p2p::SwarmStats>),
+    /// Reachability
+    NatStatus(Channel<p2p::NatStatus>),
     /// Disconnect
     Disconnect(MultiaddrWithPeerId, Channel<()>),
     /// Request background task to return the listened and external addresses
@@ -270,6 +340,7 @@ enum IpfsEvent {
         OneshotSender<Vec<(Cid, ipfs_bitswap::Priority)>>,
     ),
     BitswapStats(OneshotSender<BitswapStats>),
+    DhtStats(OneshotSender<DhtStats>),
     AddListeningAddress(Multiaddr, Channel<Multiaddr>),
     RemoveListeningAddress(Multiaddr, Channel<()>),
     Bootstrap(Channel<SubscriptionFuture<KadResult, String>>),
@@ -333,6 +404,7 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
     /// Initialize the ipfs node. The returned `Ipfs` value is cloneable, send and sync, and the
     /// future should be spawned on a executor as soon as possible.
     pub async fn start(self) -> Result<(Ipfs<Types>, impl Future<Output = ()>), Error> {
+        use futures::future::FutureExt;
         use futures::stream::StreamExt;
 
         let UninitializedIpfs {
@@ -344,6 +416,9 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
 
         repo.init().await?;
 
+        let counting = Arc::clone(&repo);
+        Types::TRuntime::spawn(Box::pin(async move { counting.count_blocks().await }));
+
         let (to_task, receiver) = channel::<IpfsEvent>(1);
 
         let facade_span = options
@@ -353,31 +428,87 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
 
         let swarm_span = tracing::trace_span!(parent: facade_span.clone(), "swarm");
 
+        let keystore = keystore::Keystore::new(keys.clone());
+
         let ipfs = Ipfs {
             span: facade_span,
             repo: repo.clone(),
             keys: DebuggableKeypair(keys),
             to_task,
+            ipns_cache: Arc::new(ipns::Cache::new(options.ipns_cache.clone())),
+            keystore: Arc::new(keystore),
+            supervisor: supervisor::Supervisor::new(repo.bus.clone()),
         };
 
+        let bus = repo.bus.clone();
+        let metrics = SwarmMetrics::register(&repo.registry);
         let swarm_options = SwarmOptions::from(&options);
         let swarm = create_swarm(swarm_options, swarm_span, repo).await?;
 
         let IpfsOptions {
-            listening_addrs, ..
+            listening_addrs,
+            reprovide_interval,
+            ..
         } = options;
 
+        // the provider tasks hold neither the `Ipfs` nor the facade channel, which would keep the
+        // background task running after all of the `Ipfs` clones have been dropped, and they
+        // stop together with the background task
+        let (provide_requests, provide_receiver) = channel(reprovider::PARALLELISM);
+        let provider = reprovider::Provider::new(Arc::clone(&ipfs.repo), provide_requests);
+        let (shutdown, stopped) = oneshot_channel();
+        let stopped = stopped.shared();
+
+        let (provide_queue, queued) = channel(reprovider::QUEUE_CAPACITY);
+        let queued = Arc::new(futures::lock::Mutex::new(queued));
+        {
+            let provider = provider.clone();
+            let stopped = stopped.clone();
+            ipfs.supervise(
+                "provider",
+                supervisor::RestartPolicy::default(),
+                move || {
+                    reprovider::until_shutdown(
+                        reprovider::provide_queued(provider.clone(), Arc::clone(&queued)),
+                        stopped.clone(),
+                    )
+                },
+            );
+        }
+
+        if let Some(interval) = reprovide_interval {
+            ipfs.supervise(
+                "reprovider",
+                supervisor::RestartPolicy::default(),
+                move || {
+                    reprovider::until_shutdown(
+                        reprovider::run(provider.clone(), interval),
+                        stopped.clone(),
+                    )
+                },
+            );
+        }
+
         let mut fut = IpfsFuture {
             repo_events: repo_events.fuse(),
             from_facade: receiver.fuse(),
+            bus,
+            metrics,
             swarm,
             listening_addresses: HashMap::with_capacity(listening_addrs.len()),
+            provide_queue,
+            provide_requests: provide_receiver.fuse(),
+            shutdown: Some(shutdown),
         };
 
         for addr in listening_addrs.into_iter() {
             fut.start_add_listener_address(addr, None);
         }
 
+        // a panic of the swarm cannot be recovered from, but is reported instead of the node
+        // silently stopping to respond
+        let fut = ipfs.supervisor.guard("swarm", fut);
+
         Ok((ipfs, fut))
     }
 }
@@ -399,9 +530,10 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Forgetting the returned future will not result in memory unsafety, but it can
     /// deadlock other tasks.
     pub async fn put_block(&self, block: Block) -> Result<Cid, Error> {
+        let span = debug_span!(parent: &self.span, "put_block", cid = %block.cid);
         self.repo
             .put_block(block)
-            .instrument(self.span.clone())
+            .instrument(span)
             .await
             .map(|(cid, _put_status)| cid)
     }
@@ -409,7 +541,82 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Retrieves a block from the local blockstore, or starts fetching from the network or join an
     /// already started fetch.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
-        self.repo.get_block(cid).instrument(self.span.clone()).await
+        let span = debug_span!(parent: &self.span, "get_block", cid = %cid);
+        self.repo.get_block(cid).instrument(span).await
+    }
+
+    /// Retrieves the data of a block like [`Ipfs::get_block`], without copying the large blocks of
+    /// the filesystem blockstore into memory.
+    pub async fn get_block_data(&self, cid: &Cid) -> Result<repo::BlockData, Error> {
+        let span = debug_span!(parent: &self.span, "get_block_data", cid = %cid);
+        self.repo.get_block_data(cid).instrument(span).await
+    }
+
+    /// Subscribes to the [`events::IpfsEvent`]s published from now on. A subscriber which falls
+    /// behind by too many events receives [`tokio::sync::broadcast::RecvError::Lagged`] and
+    /// misses the oldest events.
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<events::IpfsEvent> {
+        self.repo.bus.subscribe()
+    }
+
+    /// Returns the [`metrics::Registry`] of the node, to which the subsystems register their
+    /// metrics.
+    pub fn metrics(&self) -> &metrics::Registry {
+        &self.repo.registry
+    }
+
+    /// Returns the blockstore of the node, such as the test doubles of
+    /// [`test_support::doubles`] for scripting the responses and inspecting the recorded calls.
+    #[cfg(feature = "test-utils")]
+    pub fn block_store(&self) -> &Types::TBlockStore {
+        &self.repo.block_store
+    }
+
+    /// Collects the [`diagnostics::Diagnostics`] report of the node and the process.
+    pub async fn diagnostics(&self) -> Result<diagnostics::Diagnostics, Error> {
+        diagnostics::collect(self)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Checks the [`health::Health`] of the node, for the liveness and readiness probes.
+    pub async fn health(&self) -> Result<health::Health, Error> {
+        health::check(self).instrument(self.span.clone()).await
+    }
+
+    /// Spawns the task created by the `factory` under the supervision of the node, which creates
+    /// and spawns the task again after it panics or returns an error, as allowed by the `policy`.
+    /// The failures are published as [`events::IpfsEvent::TaskFailed`] and the state of the task
+    /// is included in the [`health::Health`] under the `name`.
+    pub fn supervise<F, Fut>(
+        &self,
+        name: &'static str,
+        policy: supervisor::RestartPolicy,
+        factory: F,
+    ) where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.supervisor
+            .supervise::<Types::TRuntime, _, _>(name, policy, factory)
+    }
+
+    /// Returns a stream of the [`diagnostics::StatsSnapshot`]s taken after each interval, with the
+    /// rates and the changes computed over the interval.
+    pub fn stats_poll(
+        &self,
+        interval: std::time::Duration,
+    ) -> impl Stream<Item = Result<diagnostics::StatsSnapshot, Error>> + Send + 'static {
+        diagnostics::stats_poll(self.clone(), interval).instrument(self.span.clone())
+    }
+
+    /// Checks the node periodically until it is shut down, logging and publishing the anomalies
+    /// to the [`events::IpfsEvent`] subscribers. The returned future should be spawned to enable
+    /// the checks.
+    pub async fn self_check(&self, options: diagnostics::SelfCheckOptions) {
+        diagnostics::self_check(self, options)
+            .instrument(self.span.clone())
+            .await
     }
 
     /// Remove block from the ipfs repo. A pinned block cannot be removed.
@@ -420,6 +627,80 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
+    /// Puts the `data` into the ipfs repo as a block of the `codec`, hashed with the `hash`
+    /// function, and returns the Cid of the block. The Cid is of version 0 for dag-pb blocks hashed
+    /// with SHA2-256, as with the default `v0` format of go-ipfs `block put`, and of version 1
+    /// otherwise. Unlike here, go-ipfs gives a version 1 Cid for an explicit `protobuf` format;
+    /// such a block is put with [`Ipfs::put_block`]. The data is stored as is without checking
+    /// that it can be decoded with the `codec`.
+    ///
+    /// The blocks are read back with [`Ipfs::block_get`].
+    pub async fn block_put(
+        &self,
+        data: impl Into<bytes::Bytes>,
+        codec: Codec,
+        hash: multihash::Code,
+    ) -> Result<Cid, Error> {
+        let data = data.into();
+        let version = if codec == Codec::DagProtobuf && hash == multihash::Code::Sha2_256 {
+            cid::Version::V0
+        } else {
+            cid::Version::V1
+        };
+        let cid = Cid::new(version, codec, hash.digest(&data))?;
+
+        self.put_block(Block::new(data, cid)).await
+    }
+
+    /// Returns the data of the block like go-ipfs `block get`, fetching the block from the network
+    /// if it isn't stored locally.
+    pub async fn block_get(&self, cid: &Cid) -> Result<bytes::Bytes, Error> {
+        let span = debug_span!(parent: &self.span, "block_get", cid = %cid);
+        let block = self.repo.get_block(cid).instrument(span).await?;
+        Ok(block.data)
+    }
+
+    /// Returns the size of the block in bytes, fetching the block from the network if it isn't
+    /// stored locally.
+    pub async fn block_stat(&self, cid: &Cid) -> Result<usize, Error> {
+        let span = debug_span!(parent: &self.span, "block_stat", cid = %cid);
+        let data = self.repo.get_block_data(cid).instrument(span).await?;
+        Ok(data.len())
+    }
+
+    /// Removes the block from the ipfs repo like [`Ipfs::remove_block`], except that with `force`
+    /// removing a block which is not stored succeeds. A pinned block cannot be removed even with
+    /// `force`.
+    pub async fn block_rm(&self, cid: &Cid, force: bool) -> Result<(), Error> {
+        let span = debug_span!(parent: &self.span, "block_rm", cid = %cid, force);
+        async move {
+            if force
+                && !self.repo.is_block_pinned(cid).await?
+                && !self.repo.contains_block(cid).await?
+            {
+                return Ok(());
+            }
+
+            self.repo.remove_block(cid).await.map(|_| ())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Removes the blocks which are neither pinned nor reachable from the recursive pins, yielding
+    /// the removed Cids, or only the Cids which would be removed with `dry_run`. See
+    /// [`repo::Repo::gc`].
+    pub fn gc(&self, dry_run: bool) -> impl Stream<Item = Result<Cid, Error>> + Send + '_ {
+        self.repo.gc(dry_run)
+    }
+
+    /// Returns a guard which keeps [`Ipfs::gc`] from starting until it is dropped. Hold it over
+    /// adding and then pinning the blocks, so that they are not removed before being pinned. See
+    /// [`repo::Repo::gc_guard`].
+    pub async fn gc_guard(&self) -> repo::GcGuard<'_> {
+        self.repo.gc_guard().await
+    }
+
     /// Pins a given Cid recursively or directly (non-recursively).
     ///
     /// Pins on a block are additive in sense that a previously directly (non-recursively) pinned
@@ -572,9 +853,19 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     ///
     /// See [`IpldDag::get`] for more information.
     pub async fn get_dag(&self, path: IpfsPath) -> Result<Ipld, Error> {
+        let span = debug_span!(
+            parent: &self.span,
+            "get_dag",
+            path = %path,
+            cid = tracing::field::Empty
+        );
+        if let Some(cid) = path.root().cid() {
+            span.record("cid", &tracing::field::display(cid));
+        }
+
         self.dag()
             .get(path)
-            .instrument(self.span.clone())
+            .instrument(span)
             .await
             .map_err(Error::new)
     }
@@ -599,7 +890,121 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
-    /// Resolves a ipns path to an ipld path; currently only supports dnslink resolution.
+    /// Creates a stream which will yield a tar archive of the UnixFS tree from the given starting
+    /// point, similar to the one go-ipfs creates for `ipfs get`.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::get_tar` directly.
+    /// Requires the `tar` feature.
+    #[cfg(feature = "tar")]
+    pub async fn get_tar(
+        &self,
+        starting_point: impl Into<unixfs::StartingPoint>,
+    ) -> Result<
+        impl Stream<Item = Result<bytes::Bytes, unixfs::GetError>> + Send + '_,
+        unixfs::GetError,
+    > {
+        // convert early not to worry about the lifetime of parameter
+        let starting_point = starting_point.into();
+        unixfs::get_tar(self, starting_point)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Adds the bytes read from the `reader` until its end as an UnixFS file, storing the blocks as
+    /// they are created, and returns the Cid of the root and the total size of the blocks.
+    ///
+    /// To add with an owned `Ipfs`, please use `ipfs::unixfs::add_from_reader` directly.
+    pub async fn add_unixfs<R: futures::io::AsyncRead>(
+        &self,
+        reader: R,
+        opts: unixfs::AddOptions,
+    ) -> Result<unixfs::AddedFile, unixfs::AddError> {
+        unixfs::add_from_reader(self, reader, opts)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Adds the bytes from the `content` stream as an UnixFS file, returning a stream of the
+    /// progress which ends in either an error or `AddProgress::Finished` with the Cid of the root.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::add_with_progress`
+    /// directly.
+    pub fn add_with_progress<'a, St, B, E>(
+        &'a self,
+        content: St,
+        opts: unixfs::AddOptions,
+    ) -> impl Stream<Item = Result<unixfs::AddProgress, unixfs::AddError>> + 'a
+    where
+        St: Stream<Item = Result<B, E>> + 'a,
+        B: AsRef<[u8]> + 'a,
+        E: Into<Error> + 'a,
+    {
+        unixfs::add_with_progress(self, content, opts)
+    }
+
+    /// Publishes the `path` as the IPNS name of the key, `/ipns/<peer_id>`, by putting a record
+    /// signed with the key and valid for the `lifetime` into the DHT. The key is one of the
+    /// [`Ipfs::key_list`], with [`keystore::SELF_KEY`] being the key of the node. Returns the
+    /// sequence number of the record, which grows with each publish.
+    pub async fn name_publish(
+        &self,
+        path: &IpfsPath,
+        key: &str,
+        lifetime: std::time::Duration,
+    ) -> Result<u64, Error> {
+        self.ipns()
+            .publish(path, key, lifetime)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Generates a new key of the given name and type into the keystore, returning the peer id of
+    /// the key. The names are unique, and [`keystore::SELF_KEY`] is reserved for the key of the
+    /// node. The named keys are not available on the nodes with an RSA identity.
+    pub async fn key_gen(&self, name: &str, key_type: keystore::KeyType) -> Result<PeerId, Error> {
+        self.keystore
+            .generate(&self.repo, name, key_type)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Lists the keys of the keystore, starting with the key of the node.
+    pub async fn key_list(&self) -> Result<Vec<keystore::KeyInfo>, Error> {
+        self.keystore
+            .list(&self.repo)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Removes the key of the given name from the keystore, returning the peer id of the key.
+    pub async fn key_rm(&self, name: &str) -> Result<PeerId, Error> {
+        self.keystore
+            .remove(&self.repo, name)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Renames the key, returning the peer id of the key. A key of the new name is replaced only
+    /// with `force`.
+    pub async fn key_rename(&self, old: &str, new: &str, force: bool) -> Result<PeerId, Error> {
+        self.keystore
+            .rename(&self.repo, old, new, force)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Resolves the IPNS name of the peer through the records in the DHT, choosing the valid
+    /// record with the highest sequence number which has not reached its end of life. Unlike
+    /// [`Ipfs::resolve_ipns`], the resolution is never cached.
+    pub async fn name_resolve(&self, peer_id: &PeerId) -> Result<IpfsPath, Error> {
+        self.ipns()
+            .resolve_name(peer_id)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Resolves a ipns path to an ipld path, through the IPNS records of the peer ids in the DHT or
+    /// the DNSLink records of the domains.
     pub async fn resolve_ipns(&self, path: &IpfsPath, recursive: bool) -> Result<IpfsPath, Error> {
         async move {
             let ipns = self.ipns();
@@ -623,6 +1028,15 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
+    /// Removes the cached resolution of the IPNS name or the DNSLink domain at the root of the
+    /// path, so that the next [`Ipfs::resolve_ipns`] resolves it again.
+    pub async fn invalidate_ipns(&self, path: &IpfsPath) -> Result<(), Error> {
+        self.ipns()
+            .invalidate(path)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Connects to the peer at the given Multiaddress.
     ///
     /// Accepts only multiaddresses with the PeerId to authenticate the connection.
@@ -684,6 +1098,31 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
+    /// Returns the counts of the open connections and of the peers disconnected for the
+    /// connections being over the [`IpfsOptions::connection_limits`].
+    pub async fn swarm_stats(&self) -> Result<p2p::SwarmStats, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.clone().send(IpfsEvent::SwarmStats(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns whether the node is reachable by the other peers, as inferred from the addresses
+    /// the peers observe the node at and from the connections they open to it. The confirmed
+    /// observed addresses are added to the external addresses of the node.
+    pub async fn nat_status(&self) -> Result<p2p::NatStatus, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task.clone().send(IpfsEvent::NatStatus(tx)).await?;
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Disconnects a given peer.
     ///
     /// At the moment the peer is disconnected by temporarily banning the peer and unbanning it
@@ -856,6 +1295,19 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
+    /// Returns the current DHT statistics.
+    pub async fn dht_stats(&self) -> Result<DhtStats, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.clone().send(IpfsEvent::DhtStats(tx)).await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Add a given multiaddr as a listening address. Will fail if the address is unsupported, or
     /// if it is already being listened on. Currently will invoke `Swarm::listen_on` internally,
     /// keep the ListenerId for later `remove_listening_address` use in a HashMap.
@@ -967,11 +1419,14 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Establishes the node as a provider of a block with the given Cid: it publishes a provider
     /// record with the given key (Cid) and the node's PeerId to the peers closest to the key. The
-    /// publication of provider records is periodically repeated as per the interval specified in
-    /// `libp2p`'s  `KademliaConfig`.
+    /// publication of provider records is periodically repeated by the reprovider as per
+    /// [`IpfsOptions::reprovide_interval`], or when it is not set, as per the interval specified
+    /// in `libp2p`'s `KademliaConfig`.
+    ///
+    /// The blocks stored through [`Ipfs::put_block`] are provided without calling this.
     pub async fn provide(&self, cid: Cid) -> Result<(), Error> {
         // don't provide things we don't actually have
-        if self.repo.get_block_now(&cid).await?.is_none() {
+        if !self.repo.contains_block(&cid).await? {
             return Err(anyhow!(
                 "Error: block {} not found locally, cannot provide",
                 cid
@@ -1101,6 +1556,24 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         refs::iplds_refs(self, iplds, max_depth, unique)
     }
 
+    /// Replicates the blocks of the DAG under the `root` selected by the `selector` from the
+    /// `peer`, connecting to it first unless already connected. The selected blocks already in
+    /// the repo are not fetched again, which makes repeating an interrupted sync cheap.
+    ///
+    /// The missing blocks are requested through bitswap, so they can also be provided by the
+    /// other connected peers. The blocks not received within [`replication::BLOCK_TIMEOUT`] are
+    /// reported in [`replication::SyncStats::missing`], without syncing the blocks below them.
+    pub async fn sync(
+        &self,
+        peer: MultiaddrWithPeerId,
+        root: Cid,
+        selector: replication::Selector,
+    ) -> Result<replication::SyncStats, Error> {
+        replication::sync(self, peer, root, selector, replication::BLOCK_TIMEOUT)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Obtain the list of addresses of bootstrapper nodes that are currently used.
     pub async fn get_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
         async move {
@@ -1203,7 +1676,39 @@ struct IpfsFuture<Types: IpfsTypes> {
     swarm: TSwarm<Types>,
     repo_events: Fuse<Receiver<RepoEvent>>,
     from_facade: Fuse<Receiver<IpfsEvent>>,
+    bus: events::EventBus,
+    metrics: SwarmMetrics,
     listening_addresses: HashMap<Multiaddr, (ListenerId, Option<Channel<Multiaddr>>)>,
+    /// The newly stored blocks to be provided by the [`reprovider::provide_queued`] task.
+    provide_queue: Sender<Cid>,
+    /// The provider records requested by the tasks of the [`reprovider`].
+    provide_requests: Fuse<Receiver<reprovider::ProvideRequest>>,
+    /// Dropped once the background task stops, which stops the tasks of the [`reprovider`].
+    shutdown: Option<OneshotSender<()>>,
+}
+
+/// The metrics of the swarm in the [`metrics::Registry`] of the node.
+struct SwarmMetrics {
+    connections: metrics::Gauge,
+    connections_established: metrics::Counter,
+    connections_pruned: metrics::Counter,
+}
+
+impl SwarmMetrics {
+    fn register(registry: &metrics::Registry) -> Self {
+        SwarmMetrics {
+            connections: registry
+                .gauge("ipfs_swarm_connections", "Open connections to other peers"),
+            connections_established: registry.counter(
+                "ipfs_swarm_connections_established_total",
+                "Connections established to or from other peers",
+            ),
+            connections_pruned: registry.counter(
+                "ipfs_swarm_connections_pruned_total",
+                "Peers disconnected for the connections being over the limits",
+            ),
+        }
+    }
 }
 
 impl<TRepoTypes: RepoTypes> IpfsFuture<TRepoTypes> {
@@ -1356,6 +1861,33 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                     SwarmEvent::NewListenAddr(addr) => {
                         self.complete_listening_address_adding(addr);
                     }
+                    SwarmEvent::ConnectionEstablished {
+                        peer_id,
+                        num_established,
+                        ..
+                    } => {
+                        self.metrics.connections.inc();
+                        self.metrics.connections_established.inc();
+                        if num_established.get() == 1 {
+                            self.bus.publish(events::IpfsEvent::PeerConnected(peer_id));
+                        }
+
+                        for disconnector in self.swarm.prune_connections() {
+                            self.metrics.connections_pruned.inc();
+                            disconnector.disconnect(&mut self.swarm);
+                        }
+                    }
+                    SwarmEvent::ConnectionClosed {
+                        peer_id,
+                        num_established,
+                        ..
+                    } => {
+                        self.metrics.connections.dec();
+                        if num_established == 0 {
+                            self.bus
+                                .publish(events::IpfsEvent::PeerDisconnected(peer_id));
+                        }
+                    }
                     _ => trace!("{:?}", inner),
                 }
             }
@@ -1389,6 +1921,12 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                         let connections = self.swarm.connections();
                         ret.send(Ok(connections.collect())).ok();
                     }
+                    IpfsEvent::SwarmStats(ret) => {
+                        ret.send(Ok(self.swarm.swarm_stats())).ok();
+                    }
+                    IpfsEvent::NatStatus(ret) => {
+                        ret.send(Ok(self.swarm.nat_status())).ok();
+                    }
                     IpfsEvent::Disconnect(addr, ret) => {
                         if let Some(disconnector) = self.swarm.disconnect(addr) {
                             disconnector.disconnect(&mut self.swarm);
@@ -1414,7 +1952,6 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                         let _ = ret.send(());
                     }
                     IpfsEvent::PubsubPeers(Some(topic), ret) => {
-                        let topic = libp2p::floodsub::Topic::new(topic);
                         let _ = ret.send(self.swarm.pubsub().subscribed_peers(&topic));
                     }
                     IpfsEvent::PubsubPeers(None, ret) => {
@@ -1440,6 +1977,19 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                         let wantlist = self.swarm.bitswap().local_wantlist();
                         let _ = ret.send((stats, peers, wantlist).into());
                     }
+                    IpfsEvent::DhtStats(ret) => {
+                        let routing_table_peers = self
+                            .swarm
+                            .kademlia()
+                            .kbuckets()
+                            .map(|bucket| bucket.num_entries())
+                            .sum();
+                        let bootstrapped = self.swarm.is_bootstrapped();
+                        let _ = ret.send(DhtStats {
+                            routing_table_peers,
+                            bootstrapped,
+                        });
+                    }
                     IpfsEvent::AddListeningAddress(addr, ret) => {
                         self.start_add_listener_address(addr, Some(ret));
                     }
@@ -1530,27 +2080,39 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                     }
                     IpfsEvent::Exit => {
                         // FIXME: we could do a proper teardown
+                        self.shutdown.take();
                         return Poll::Ready(());
                     }
                 }
             }
 
+            while let Poll::Ready(Some((cid, ret))) =
+                Pin::new(&mut self.provide_requests).poll_next(ctx)
+            {
+                let _ = ret.send(self.swarm.start_providing(cid));
+            }
+
             // Poll::Ready(None) and Poll::Pending can be used to break out of the loop, clippy
             // wants this to be written with a `while let`.
             while let Poll::Ready(Some(evt)) = Pin::new(&mut self.repo_events).poll_next(ctx) {
                 match evt {
                     RepoEvent::WantBlock(cid) => self.swarm.want_block(cid),
+                    RepoEvent::WantBlockInSession(session, cid) => {
+                        self.swarm.want_block_in_session(session, cid)
+                    }
+                    RepoEvent::BroadcastSession(session) => {
+                        self.swarm.bitswap().broadcast_session(session)
+                    }
+                    RepoEvent::EndSession(session) => self.swarm.bitswap().end_session(session),
                     RepoEvent::UnwantBlock(cid) => self.swarm.bitswap().cancel_block(&cid),
-                    RepoEvent::NewBlock(cid, ret) => {
+                    RepoEvent::NewBlock(cid) => {
                         // TODO: consider if cancel is applicable in cases where we provide the
                         // associated Block ourselves
                         self.swarm.bitswap().cancel_block(&cid);
-                        // currently disabled; see https://github.com/rs-ipfs/rust-ipfs/pull/281#discussion_r465583345
-                        // for details regarding the concerns about enabling this functionality as-is
-                        if false {
-                            let _ = ret.send(self.swarm.start_providing(cid));
-                        } else {
-                            let _ = ret.send(Err(anyhow!("not actively providing blocks yet")));
+                        if let Err(e) = self.provide_queue.try_send(cid) {
+                            if e.is_full() {
+                                trace!(cid = %e.into_inner(), "provide queue is full");
+                            }
                         }
                     }
                     RepoEvent::RemovedBlock(cid) => self.swarm.stop_providing_block(&cid),
@@ -1610,12 +2172,22 @@ impl
     }
 }
 
+/// DHT statistics
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DhtStats {
+    /// The number of peers in the routing table
+    pub routing_table_peers: usize,
+    /// Whether a bootstrap query has found a peer since the start
+    pub bootstrapped: bool,
+}
+
 #[doc(hidden)]
 pub use node::Node;
 
 /// Node module provides an easy to use interface used in `tests/`.
 mod node {
     use super::*;
+    use futures::channel::oneshot::Receiver as OneshotReceiver;
     use std::convert::TryFrom;
 
     /// Node encapsulates everything to setup a testing instance so that multi-node tests become
@@ -1624,7 +2196,8 @@ mod node {
         pub ipfs: Ipfs<TestTypes>,
         pub id: PeerId,
         pub addrs: Vec<Multiaddr>,
-        pub bg_task: tokio::task::JoinHandle<()>,
+        /// Completes once the background task of the node has stopped.
+        stopped: OneshotReceiver<()>,
     }
 
     impl Node {
@@ -1647,14 +2220,21 @@ mod node {
                 .in_current_span()
                 .await
                 .unwrap();
-            let bg_task = tokio::task::spawn(fut.in_current_span());
+            let (stopped_tx, stopped) = oneshot_channel();
+            <TestTypes as RepoTypes>::TRuntime::spawn(Box::pin(
+                async move {
+                    fut.await;
+                    let _ = stopped_tx.send(());
+                }
+                .in_current_span(),
+            ));
             let addrs = ipfs.identity().await.unwrap().1;
 
             Node {
                 ipfs,
                 id,
                 addrs,
-                bg_task,
+                stopped,
             }
         }
 
@@ -1707,7 +2287,7 @@ mod node {
 
         pub async fn shutdown(self) {
             self.ipfs.exit_daemon().await;
-            let _ = self.bg_task.await;
+            let _ = self.stopped.await;
         }
     }
 
@@ -1745,6 +2325,58 @@ mod tests {
         assert_eq!(block, new_block);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn test_block_put_stat_and_rm() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"hello block\n".to_vec();
+        let cid = ipfs
+            .block_put(data.clone(), Codec::Raw, multihash::Code::Sha2_512)
+            .await
+            .unwrap();
+        assert_eq!(
+            cid,
+            Cid::new_v1(Codec::Raw, multihash::Sha2_512::digest(&data))
+        );
+        assert_eq!(ipfs.block_get(&cid).await.unwrap(), &data[..]);
+        assert_eq!(ipfs.block_stat(&cid).await.unwrap(), data.len());
+
+        // the same defaults as with go-ipfs
+        let v0 = ipfs
+            .block_put(data.clone(), Codec::DagProtobuf, multihash::Code::Sha2_256)
+            .await
+            .unwrap();
+        assert_eq!(v0, Cid::new_v0(Sha2_256::digest(&data)).unwrap());
+
+        ipfs.insert_pin(&v0, false).await.unwrap();
+        assert!(ipfs.block_rm(&v0, true).await.is_err());
+        ipfs.remove_pin(&v0, false).await.unwrap();
+        ipfs.block_rm(&v0, false).await.unwrap();
+
+        ipfs.block_rm(&cid, false).await.unwrap();
+        assert!(ipfs.block_rm(&cid, false).await.is_err());
+        ipfs.block_rm(&cid, true).await.unwrap();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn pinned_block_is_not_removed_through_another_codec() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"{}".to_vec();
+        let json = ipfs
+            .block_put(data.clone(), Codec::DagJSON, multihash::Code::Sha2_256)
+            .await
+            .unwrap();
+        ipfs.insert_pin(&json, false).await.unwrap();
+
+        // the block stores keep a single block for all of the Cids of a multihash
+        let raw = Cid::new_v1(Codec::Raw, json.hash().to_owned());
+        assert!(ipfs.block_rm(&raw, false).await.is_err());
+        assert!(ipfs.block_rm(&raw, true).await.is_err());
+        assert!(ipfs.remove_block(raw.clone()).await.is_err());
+        assert_eq!(ipfs.block_get(&raw).await.unwrap(), &data[..]);
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_put_and_get_dag() {
         let ipfs = Node::new("test_node").await;
@@ -1767,4 +2399,34 @@ mod tests {
         ipfs.remove_pin(&cid, false).await.unwrap();
         assert!(!ipfs.is_pinned(&cid).await.unwrap());
     }
+
+    #[tokio::test(max_threads = 1)]
+    async fn background_task_stops_after_dropping_ipfs() {
+        use std::time::Duration;
+
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.reprovide_interval = Some(Duration::from_secs(1));
+        let (ipfs, fut): (Ipfs<TestTypes>, _) = UninitializedIpfs::new(opts).start().await.unwrap();
+        let (stopped_tx, stopped) = oneshot_channel();
+        <TestTypes as RepoTypes>::TRuntime::spawn(Box::pin(async move {
+            fut.await;
+            let _ = stopped_tx.send(());
+        }));
+        let repo = Arc::downgrade(&ipfs.repo);
+
+        drop(ipfs);
+        tokio::time::timeout(Duration::from_secs(5), stopped)
+            .await
+            .expect("the background task should complete")
+            .unwrap();
+
+        // the supervised provider tasks stop as well, releasing the repo
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while repo.upgrade().is_some() {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the provider tasks should release the repo");
+    }
 }
@@ -0,0 +1,123 @@
+//! The [`Registry`] of the metrics of a node, where the repo, bitswap, kademlia and the swarm
+//! register their counters, gauges and histograms. The metrics are kept by the `prometheus` crate,
+//! available through [`crate::Ipfs::metrics`] and can be encoded in the Prometheus text format.
+
+use prometheus::proto::MetricType;
+use prometheus::{core::Collector, Encoder, HistogramOpts, TextEncoder};
+
+/// A monotonically increasing count.
+pub type Counter = prometheus::IntCounter;
+
+/// A value which can go up and down.
+pub type Gauge = prometheus::IntGauge;
+
+/// The distribution of the observed values over buckets of upper bounds.
+pub type Histogram = prometheus::Histogram;
+
+/// The buckets for durations in seconds, from 5 milliseconds to a minute.
+pub const DURATION_BUCKETS: &[f64] = &[0.005, 0.025, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0];
+
+/// The metrics registered by the subsystems of a node, shared between the clones.
+#[derive(Debug, Clone, Default)]
+pub struct Registry(prometheus::Registry);
+
+impl Registry {
+    /// Registers a [`Counter`] under the name, returning it so that it can be stored by the
+    /// registering subsystem.
+    ///
+    /// Panics if the name is not a valid metric name or has already been registered.
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        self.register(Counter::new(name, help))
+    }
+
+    /// Registers a [`Gauge`] under the name, see [`Registry::counter`].
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        self.register(Gauge::new(name, help))
+    }
+
+    /// Registers a [`Histogram`] with the given bucket upper bounds in increasing order under the
+    /// name, see [`Registry::counter`].
+    pub fn histogram(&self, name: &str, help: &str, buckets: &[f64]) -> Histogram {
+        let opts = HistogramOpts::new(name, help).buckets(buckets.to_vec());
+        self.register(Histogram::with_opts(opts))
+    }
+
+    fn register<M: Collector + Clone + 'static>(&self, metric: prometheus::Result<M>) -> M {
+        let metric = metric.expect("invalid metric");
+        self.0
+            .register(Box::new(metric.clone()))
+            .expect("the metric names of a node are unique");
+        metric
+    }
+
+    /// Returns the current value of the gauge registered under the name.
+    pub fn gauge_value(&self, name: &str) -> Option<i64> {
+        self.0
+            .gather()
+            .iter()
+            .filter(|family| family.get_field_type() == MetricType::GAUGE)
+            .find(|family| family.get_name() == name)
+            .and_then(|family| family.get_metric().first())
+            .map(|metric| metric.get_gauge().get_value() as i64)
+    }
+
+    /// Encodes the metrics in the Prometheus text format, ordered by name.
+    pub fn encode(&self) -> String {
+        let mut out = Vec::new();
+        // the metrics are validated on registration, and writing to a Vec cannot fail
+        TextEncoder::new()
+            .encode(&self.0.gather(), &mut out)
+            .expect("encoding the metrics failed");
+        String::from_utf8(out).expect("the text format is utf-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+
+    #[test]
+    fn encoding() {
+        let registry = Registry::default();
+
+        let counter = registry.counter("test_total", "Things counted");
+        let gauge = registry.gauge("test_level", "Current level");
+        let histogram = registry.histogram("test_seconds", "Durations", &[0.5, 1.0]);
+
+        counter.inc_by(3);
+        gauge.inc();
+        gauge.inc();
+        gauge.dec();
+        histogram.observe(0.25);
+        histogram.observe(0.75);
+        histogram.observe(2.0);
+
+        assert_eq!(
+            registry.encode(),
+            "# HELP test_level Current level\n\
+             # TYPE test_level gauge\n\
+             test_level 1\n\
+             # HELP test_seconds Durations\n\
+             # TYPE test_seconds histogram\n\
+             test_seconds_bucket{le=\"0.5\"} 1\n\
+             test_seconds_bucket{le=\"1\"} 2\n\
+             test_seconds_bucket{le=\"+Inf\"} 3\n\
+             test_seconds_sum 3\n\
+             test_seconds_count 3\n\
+             # HELP test_total Things counted\n\
+             # TYPE test_total counter\n\
+             test_total 3\n"
+        );
+
+        assert_eq!(registry.gauge_value("test_level"), Some(1));
+        assert_eq!(registry.gauge_value("test_total"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn registering_twice() {
+        let registry = Registry::default();
+        registry.counter("test_total", "Things counted");
+        registry.counter("test_total", "Things counted");
+    }
+}
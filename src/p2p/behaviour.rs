@@ -1,34 +1,57 @@
+use super::nat::NatStatus;
 use super::pubsub::Pubsub;
-use super::swarm::{Connection, Disconnector, SwarmApi};
+use super::swarm::{Connection, Disconnector, SwarmApi, SwarmStats};
 use crate::config::BOOTSTRAP_NODES;
+use crate::events::IpfsEvent;
+use crate::metrics::{Counter, Histogram, Registry, DURATION_BUCKETS};
 use crate::p2p::{MultiaddrWithPeerId, SwarmOptions};
 use crate::repo::{BlockPut, Repo};
 use crate::subscription::{SubscriptionFuture, SubscriptionRegistry};
 use crate::IpfsTypes;
 use anyhow::anyhow;
 use cid::Cid;
-use ipfs_bitswap::{Bitswap, BitswapEvent};
+use ipfs_bitswap::{Bitswap, BitswapEvent, SessionId};
 use libp2p::core::{Multiaddr, PeerId};
 use libp2p::identify::{Identify, IdentifyEvent};
-use libp2p::kad::record::{store::MemoryStore, Key, Record};
-use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent, Quorum};
+use libp2p::kad::record::{
+    store::{MemoryStore, MemoryStoreConfig},
+    Key, Record,
+};
+use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent, QueryId, Quorum};
+#[cfg(not(target_arch = "wasm32"))]
 use libp2p::mdns::{MdnsEvent, TokioMdns};
 use libp2p::ping::{Ping, PingEvent};
+#[cfg(not(target_arch = "wasm32"))]
 use libp2p::swarm::toggle::Toggle;
 use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourEventProcess};
 use multibase::Base;
-use std::{convert::TryInto, sync::Arc};
-use tokio::task;
+use multihash::Multihash;
+use std::{collections::HashMap, convert::TryInto, sync::Arc, time::Instant};
+use tracing::Span;
+use tracing_futures::Instrument;
 
 /// Behaviour type.
 #[derive(libp2p::NetworkBehaviour)]
 pub struct Behaviour<Types: IpfsTypes> {
     #[behaviour(ignore)]
     repo: Arc<Repo<Types>>,
+    #[behaviour(ignore)]
+    peer_id: PeerId,
+    /// Left out of wasm32 builds, where the peers are found through the bootstrappers.
+    #[cfg(not(target_arch = "wasm32"))]
     mdns: Toggle<TokioMdns>,
     kademlia: Kademlia<MemoryStore>,
     #[behaviour(ignore)]
     kad_subscriptions: SubscriptionRegistry<KadResult, String>,
+    /// The spans and the starting times of the started queries, the spans being entered while
+    /// handling the results and closed once the query finishes.
+    #[behaviour(ignore)]
+    kad_queries: HashMap<QueryId, (Span, Instant)>,
+    #[behaviour(ignore)]
+    metrics: BehaviourMetrics,
+    /// Set once a bootstrap query has found a peer.
+    #[behaviour(ignore)]
+    bootstrapped: bool,
     bitswap: Bitswap,
     ping: Ping,
     identify: Identify,
@@ -36,6 +59,44 @@ pub struct Behaviour<Types: IpfsTypes> {
     pub swarm: SwarmApi,
 }
 
+/// The bitswap and kademlia metrics in the [`Registry`] of the node.
+#[derive(Debug)]
+struct BehaviourMetrics {
+    wants_received: Counter,
+    cancels_received: Counter,
+    queries_started: Counter,
+    query_duration: Histogram,
+    providers_found: Counter,
+}
+
+impl BehaviourMetrics {
+    fn register(registry: &Registry) -> Self {
+        BehaviourMetrics {
+            wants_received: registry.counter(
+                "ipfs_bitswap_wants_received_total",
+                "Blocks wanted by other peers",
+            ),
+            cancels_received: registry.counter(
+                "ipfs_bitswap_cancels_received_total",
+                "Wants cancelled by other peers",
+            ),
+            queries_started: registry.counter(
+                "ipfs_dht_queries_started_total",
+                "DHT queries started by the node",
+            ),
+            query_duration: registry.histogram(
+                "ipfs_dht_query_duration_seconds",
+                "Time taken by the DHT queries started by the node",
+                DURATION_BUCKETS,
+            ),
+            providers_found: registry.counter(
+                "ipfs_dht_providers_found_total",
+                "Providers found through the DHT",
+            ),
+        }
+    }
+}
+
 /// Represents the result of a Kademlia query.
 #[derive(Debug, Clone, PartialEq)]
 pub enum KadResult {
@@ -51,6 +112,7 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<void::Void> for Behaviour<Ty
     fn inject_event(&mut self, _event: void::Void) {}
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl<Types: IpfsTypes> NetworkBehaviourEventProcess<MdnsEvent> for Behaviour<Types> {
     fn inject_event(&mut self, event: MdnsEvent) {
         match event {
@@ -80,8 +142,23 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<KademliaEvent> for Behaviour
 
         match event {
             QueryResult { result, id, .. } => {
+                let finished = self.kademlia.query(&id).is_none();
+                let span = if finished {
+                    self.kad_queries.remove(&id).map(|(span, started)| {
+                        self.metrics
+                            .query_duration
+                            .observe(started.elapsed().as_secs_f64());
+                        span
+                    })
+                } else {
+                    self.kad_queries.get(&id).map(|(span, _)| span.clone())
+                };
+                // the queries started by kademlia itself, such as republishing, are not tracked
+                let span = span.unwrap_or_else(Span::none);
+                let _entered = span.enter();
+
                 // make sure the query is exhausted
-                if self.kademlia.query(&id).is_none() {
+                if finished {
                     match result {
                         // these subscriptions return actual values
                         GetClosestPeers(_) | GetProviders(_) | GetRecord(_) => {}
@@ -104,6 +181,7 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<KademliaEvent> for Behaviour
                             "kad: bootstrapped with {}, {} peers remain",
                             peer, num_remaining
                         );
+                        self.bootstrapped = true;
                     }
                     Bootstrap(Err(BootstrapError::Timeout { .. })) => {
                         warn!("kad: timed out while trying to bootstrap");
@@ -134,10 +212,28 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<KademliaEvent> for Behaviour
                         }
                     }
                     GetProviders(Ok(GetProvidersOk {
-                        key: _,
+                        key,
                         providers,
                         closest_peers: _,
                     })) => {
+                        self.metrics.providers_found.inc_by(providers.len() as u64);
+
+                        if let Ok(key) = Multihash::from_bytes(key.to_vec()) {
+                            for provider in &providers {
+                                self.repo.bus.publish(IpfsEvent::ProviderFound {
+                                    key: key.clone(),
+                                    provider: provider.to_owned(),
+                                });
+                            }
+                        }
+
+                        // the providers are asked for the wanted blocks once connected
+                        for provider in &providers {
+                            if *provider != self.peer_id {
+                                self.bitswap.connect(provider.to_owned());
+                            }
+                        }
+
                         if self.kademlia.query(&id).is_none() {
                             let providers = providers.into_iter().collect::<Vec<_>>();
 
@@ -315,12 +411,17 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<BitswapEvent> for Behaviour<
             BitswapEvent::ReceivedBlock(peer_id, block) => {
                 let repo = self.repo.clone();
                 let peer_stats = Arc::clone(&self.bitswap.stats.get(&peer_id).unwrap());
-                task::spawn(async move {
+                let span = debug_span!("store received block", cid = %block.cid, peer = %peer_id);
+                let store = async move {
                     let bytes = block.data().len() as u64;
                     let res = repo.put_block(block.clone()).await;
                     match res {
-                        Ok((_, uniqueness)) => match uniqueness {
-                            BlockPut::NewBlock => peer_stats.update_incoming_unique(bytes),
+                        Ok((cid, uniqueness)) => match uniqueness {
+                            BlockPut::NewBlock => {
+                                peer_stats.update_incoming_unique(bytes);
+                                repo.bus
+                                    .publish(IpfsEvent::WantResolved { cid, from: peer_id });
+                            }
                             BlockPut::Existed => peer_stats.update_incoming_duplicate(bytes),
                         },
                         Err(e) => {
@@ -333,9 +434,11 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<BitswapEvent> for Behaviour<
                             return;
                         }
                     };
-                });
+                };
+                Types::TRuntime::spawn(Box::pin(store.instrument(span)));
             }
             BitswapEvent::ReceivedWant(peer_id, cid, priority) => {
+                self.metrics.wants_received.inc();
                 info!(
                     "Peer {} wants block {} with priority {}",
                     peer_id, cid, priority
@@ -343,8 +446,9 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<BitswapEvent> for Behaviour<
 
                 let queued_blocks = self.bitswap().queued_blocks.clone();
                 let repo = self.repo.clone();
+                let span = debug_span!("serve wanted block", cid = %cid, peer = %peer_id);
 
-                task::spawn(async move {
+                let serve = async move {
                     match repo.get_block_now(&cid).await {
                         Ok(Some(block)) => {
                             let _ = queued_blocks.unbounded_send((peer_id, block));
@@ -359,9 +463,10 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<BitswapEvent> for Behaviour<
                             );
                         }
                     }
-                });
+                };
+                Types::TRuntime::spawn(Box::pin(serve.instrument(span)));
             }
-            BitswapEvent::ReceivedCancel(..) => {}
+            BitswapEvent::ReceivedCancel(..) => self.metrics.cancels_received.inc(),
         }
     }
 }
@@ -407,6 +512,15 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<PingEvent> for Behaviour<Typ
 impl<Types: IpfsTypes> NetworkBehaviourEventProcess<IdentifyEvent> for Behaviour<Types> {
     fn inject_event(&mut self, event: IdentifyEvent) {
         trace!("identify: {:?}", event);
+
+        if let IdentifyEvent::Received {
+            peer_id,
+            observed_addr,
+            ..
+        } = event
+        {
+            self.swarm.observed_addr(&peer_id, observed_addr);
+        }
     }
 }
 
@@ -415,6 +529,7 @@ impl<Types: IpfsTypes> Behaviour<Types> {
     pub async fn new(options: SwarmOptions, repo: Arc<Repo<Types>>) -> Self {
         info!("net: starting with peer id {}", options.peer_id);
 
+        #[cfg(not(target_arch = "wasm32"))]
         let mdns = if options.mdns {
             Some(TokioMdns::new().expect("Failed to create mDNS service"))
         } else {
@@ -422,11 +537,22 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         }
         .into();
 
-        let store = MemoryStore::new(options.peer_id.to_owned());
+        // all of the stored blocks are provided, which are bounded by the repo instead
+        let store_config = MemoryStoreConfig {
+            max_provided_keys: usize::MAX,
+            ..Default::default()
+        };
+        let store = MemoryStore::with_config(options.peer_id.to_owned(), store_config);
 
         let mut kad_config = KademliaConfig::default();
         kad_config.disjoint_query_paths(true);
-        kad_config.set_query_timeout(std::time::Duration::from_secs(300));
+        kad_config.set_query_timeout(options.kad_query.timeout);
+        kad_config.set_parallelism(options.kad_query.parallelism);
+        kad_config.set_replication_factor(options.kad_query.replication_factor);
+        if options.reprovide_interval.is_some() {
+            // the reprovider publishes the records of all of the stored blocks instead
+            kad_config.set_provider_publication_interval(None);
+        }
         if let Some(protocol) = options.kad_protocol {
             kad_config.set_protocol_name(protocol.into_bytes());
         }
@@ -443,8 +569,10 @@ impl<Types: IpfsTypes> Behaviour<Types> {
             "rust-ipfs".into(),
             options.keypair.public(),
         );
-        let pubsub = Pubsub::new(options.peer_id);
+        let pubsub = Pubsub::new(&options.keypair, &options.pubsub);
+        let metrics = BehaviourMetrics::register(&repo.registry);
         let mut swarm = SwarmApi::default();
+        swarm.limits = options.connection_limits;
 
         for (addr, _peer_id) in &options.bootstrap {
             if let Ok(addr) = addr.to_owned().try_into() {
@@ -454,9 +582,14 @@ impl<Types: IpfsTypes> Behaviour<Types> {
 
         Behaviour {
             repo,
+            peer_id: options.peer_id,
+            #[cfg(not(target_arch = "wasm32"))]
             mdns,
             kademlia,
             kad_subscriptions: Default::default(),
+            kad_queries: Default::default(),
+            metrics,
+            bootstrapped: false,
             bitswap,
             ping,
             identify,
@@ -504,18 +637,62 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         self.swarm.disconnect(addr)
     }
 
+    /// Returns the peers to disconnect for the connections being over the limits, marking them
+    /// disconnected. The peers with an active bitswap ledger or with pubsub topics in common with
+    /// the local node are kept.
+    pub fn prune_connections(&mut self) -> Vec<Disconnector> {
+        let bitswap = &self.bitswap;
+        let pubsub = &self.pubsub;
+
+        let pruned = self.swarm.prune_candidates(|peer| {
+            bitswap
+                .connected_peers
+                .get(peer)
+                .map_or(false, |ledger| ledger.is_active())
+                || pubsub.has_common_topics(peer)
+        });
+
+        pruned
+            .iter()
+            .filter_map(|peer| self.swarm.disconnect_peer(peer))
+            .collect()
+    }
+
+    pub fn swarm_stats(&self) -> SwarmStats {
+        self.swarm.stats()
+    }
+
+    pub fn nat_status(&self) -> NatStatus {
+        self.swarm.nat_status()
+    }
+
     // FIXME: it would be best if get_providers is called only in case the already connected
     // peers don't have it
     pub fn want_block(&mut self, cid: Cid) {
         let key = cid.hash().as_bytes().to_owned();
-        self.kademlia.get_providers(key.into());
+        let id = self.kademlia.get_providers(key.into());
+        self.track_query(
+            id,
+            debug_span!("kad query", kind = "get_providers", cid = %cid),
+        );
         self.bitswap.want_block(cid, 1);
     }
 
+    /// Like [`Behaviour::want_block`] but wants the block in the bitswap `session`.
+    pub fn want_block_in_session(&mut self, session: SessionId, cid: Cid) {
+        let key = cid.hash().as_bytes().to_owned();
+        let id = self.kademlia.get_providers(key.into());
+        self.track_query(
+            id,
+            debug_span!("kad query", kind = "get_providers", cid = %cid),
+        );
+        self.bitswap.want_block_in_session(session, cid, 1);
+    }
+
     pub fn stop_providing_block(&mut self, cid: &Cid) {
         info!("Finished providing block {}", cid.to_string());
-        //let hash = Multihash::from_bytes(cid.to_bytes()).unwrap();
-        //self.kademlia.remove_providing(&hash);
+        let key = Key::from(cid.hash().as_bytes().to_owned());
+        self.kademlia.stop_providing(&key);
     }
 
     pub fn pubsub(&mut self) -> &mut Pubsub {
@@ -528,7 +705,10 @@ impl<Types: IpfsTypes> Behaviour<Types> {
 
     pub fn bootstrap(&mut self) -> Result<SubscriptionFuture<KadResult, String>, anyhow::Error> {
         match self.kademlia.bootstrap() {
-            Ok(id) => Ok(self.kad_subscriptions.create_subscription(id.into(), None)),
+            Ok(id) => {
+                self.track_query(id, debug_span!("kad query", kind = "bootstrap"));
+                Ok(self.kad_subscriptions.create_subscription(id.into(), None))
+            }
             Err(e) => {
                 error!("kad: can't bootstrap the node: {:?}", e);
                 Err(anyhow!("kad: can't bootstrap the node: {:?}", e))
@@ -536,21 +716,32 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         }
     }
 
+    /// Returns true once a bootstrap query has found a peer.
+    pub fn is_bootstrapped(&self) -> bool {
+        self.bootstrapped
+    }
+
     pub fn kademlia(&mut self) -> &mut Kademlia<MemoryStore> {
         &mut self.kademlia
     }
 
     pub fn get_closest_peers(&mut self, id: PeerId) -> SubscriptionFuture<KadResult, String> {
+        let span = debug_span!("kad query", kind = "get_closest_peers", peer = %id);
         let id = id.to_base58();
 
-        self.kad_subscriptions
-            .create_subscription(self.kademlia.get_closest_peers(id.as_bytes()).into(), None)
+        let id = self.kademlia.get_closest_peers(id.as_bytes());
+        self.track_query(id, span);
+        self.kad_subscriptions.create_subscription(id.into(), None)
     }
 
     pub fn get_providers(&mut self, cid: Cid) -> SubscriptionFuture<KadResult, String> {
         let key = Key::from(cid.hash().as_bytes().to_owned());
-        self.kad_subscriptions
-            .create_subscription(self.kademlia.get_providers(key).into(), None)
+        let id = self.kademlia.get_providers(key);
+        self.track_query(
+            id,
+            debug_span!("kad query", kind = "get_providers", cid = %cid),
+        );
+        self.kad_subscriptions.create_subscription(id.into(), None)
     }
 
     pub fn start_providing(
@@ -559,7 +750,13 @@ impl<Types: IpfsTypes> Behaviour<Types> {
     ) -> Result<SubscriptionFuture<KadResult, String>, anyhow::Error> {
         let key = Key::from(cid.hash().as_bytes().to_owned());
         match self.kademlia.start_providing(key) {
-            Ok(id) => Ok(self.kad_subscriptions.create_subscription(id.into(), None)),
+            Ok(id) => {
+                self.track_query(
+                    id,
+                    debug_span!("kad query", kind = "start_providing", cid = %cid),
+                );
+                Ok(self.kad_subscriptions.create_subscription(id.into(), None))
+            }
             Err(e) => {
                 error!("kad: can't provide a key: {:?}", e);
                 Err(anyhow!("kad: can't provide the key: {:?}", e))
@@ -568,8 +765,14 @@ impl<Types: IpfsTypes> Behaviour<Types> {
     }
 
     pub fn dht_get(&mut self, key: Key, quorum: Quorum) -> SubscriptionFuture<KadResult, String> {
-        self.kad_subscriptions
-            .create_subscription(self.kademlia.get_record(&key, quorum).into(), None)
+        let span = debug_span!(
+            "kad query",
+            kind = "get_record",
+            key = %multibase::encode(Base::Base32Lower, &key)
+        );
+        let id = self.kademlia.get_record(&key, quorum);
+        self.track_query(id, span);
+        self.kad_subscriptions.create_subscription(id.into(), None)
     }
 
     pub fn dht_put(
@@ -578,6 +781,11 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         value: Vec<u8>,
         quorum: Quorum,
     ) -> Result<SubscriptionFuture<KadResult, String>, anyhow::Error> {
+        let span = debug_span!(
+            "kad query",
+            kind = "put_record",
+            key = %multibase::encode(Base::Base32Lower, &key)
+        );
         let record = Record {
             key,
             value,
@@ -585,7 +793,10 @@ impl<Types: IpfsTypes> Behaviour<Types> {
             expires: None,
         };
         match self.kademlia.put_record(record, quorum) {
-            Ok(id) => Ok(self.kad_subscriptions.create_subscription(id.into(), None)),
+            Ok(id) => {
+                self.track_query(id, span);
+                Ok(self.kad_subscriptions.create_subscription(id.into(), None))
+            }
             Err(e) => {
                 error!("kad: can't put a record: {:?}", e);
                 Err(anyhow!("kad: can't provide the record: {:?}", e))
@@ -593,6 +804,16 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         }
     }
 
+    /// Keeps the span and the starting time of the query until the query finishes.
+    fn track_query(&mut self, id: QueryId, span: Span) {
+        {
+            let _entered = span.enter();
+            debug!("started");
+        }
+        self.metrics.queries_started.inc();
+        self.kad_queries.insert(id, (span, Instant::now()));
+    }
+
     pub fn get_bootstrappers(&self) -> Vec<Multiaddr> {
         self.swarm
             .bootstrappers
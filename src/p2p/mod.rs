@@ -1,21 +1,31 @@
 //! P2P handling for IPFS nodes.
 use crate::repo::Repo;
+use crate::runtime::Runtime;
 use crate::{IpfsOptions, IpfsTypes};
 use libp2p::identity::Keypair;
 use libp2p::Swarm;
 use libp2p::{Multiaddr, PeerId};
 use std::io;
+use std::marker::PhantomData;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::Span;
 
 pub(crate) mod addr;
 mod behaviour;
+mod nat;
 pub(crate) mod pubsub;
 mod swarm;
 mod transport;
 
 pub use addr::{MultiaddrWithPeerId, MultiaddrWithoutPeerId};
-pub use {behaviour::KadResult, swarm::Connection};
+pub use behaviour::KadResult;
+pub use libp2p::pnet::PreSharedKey;
+pub use nat::NatStatus;
+pub use pubsub::{MessageSigning, PubsubOptions, PubsubRouter};
+pub use swarm::{Connection, ConnectionLimits, SwarmStats};
+pub use transport::read_swarm_key;
 
 /// Type alias for [`libp2p::Swarm`] running the [`behaviour::Behaviour`] with the given [`IpfsTypes`].
 pub type TSwarm<T> = Swarm<behaviour::Behaviour<T>>;
@@ -32,6 +42,63 @@ pub struct SwarmOptions {
     pub mdns: bool,
     /// Custom Kademlia protocol name, see [`IpfsOptions::kad_protocol`].
     pub kad_protocol: Option<String>,
+    /// The configuration of the Kademlia queries, see [`IpfsOptions::kad_query`].
+    pub kad_query: KadQueryOptions,
+    /// The pubsub router and message signing, see [`IpfsOptions::pubsub`].
+    pub pubsub: PubsubOptions,
+    /// The interval of the reprovider, see [`IpfsOptions::reprovide_interval`].
+    pub reprovide_interval: Option<Duration>,
+    /// The limits on the open connections, see [`IpfsOptions::connection_limits`].
+    pub connection_limits: ConnectionLimits,
+    /// The key of the private network, see [`IpfsOptions::swarm_key`].
+    pub swarm_key: Option<PreSharedKey>,
+}
+
+/// Configures the Kademlia queries, such as the provider lookups made when fetching blocks.
+///
+/// The defaults suit a long running node. [`KadQueryOptions::interactive`] gives up sooner on
+/// the lookups while querying more peers at a time, and [`KadQueryOptions::background`] keeps
+/// the load on the network low.
+#[derive(Debug, Clone)]
+pub struct KadQueryOptions {
+    /// The number of peers queried at the same time on each query path, known as alpha in the
+    /// Kademlia paper; defaults to 3.
+    pub parallelism: NonZeroUsize,
+    /// The time after which a query is given up; defaults to 5 minutes.
+    pub timeout: Duration,
+    /// The number of the closest peers the records are stored to and a query looks for, known
+    /// as k in the Kademlia paper; defaults to 20.
+    pub replication_factor: NonZeroUsize,
+}
+
+impl Default for KadQueryOptions {
+    fn default() -> Self {
+        KadQueryOptions {
+            parallelism: NonZeroUsize::new(3).unwrap(),
+            timeout: Duration::from_secs(300),
+            replication_factor: NonZeroUsize::new(20).unwrap(),
+        }
+    }
+}
+
+impl KadQueryOptions {
+    /// For the fetches a user is waiting for: queries more peers at a time and gives up after 30
+    /// seconds.
+    pub fn interactive() -> Self {
+        KadQueryOptions {
+            parallelism: NonZeroUsize::new(10).unwrap(),
+            timeout: Duration::from_secs(30),
+            ..Default::default()
+        }
+    }
+
+    /// For the nodes mostly idling or reproviding: queries a single peer at a time.
+    pub fn background() -> Self {
+        KadQueryOptions {
+            parallelism: NonZeroUsize::new(1).unwrap(),
+            ..Default::default()
+        }
+    }
 }
 
 impl From<&IpfsOptions> for SwarmOptions {
@@ -41,6 +108,11 @@ impl From<&IpfsOptions> for SwarmOptions {
         let bootstrap = options.bootstrap.clone();
         let mdns = options.mdns;
         let kad_protocol = options.kad_protocol.clone();
+        let kad_query = options.kad_query.clone();
+        let pubsub = options.pubsub.clone();
+        let reprovide_interval = options.reprovide_interval;
+        let connection_limits = options.connection_limits.clone();
+        let swarm_key = options.swarm_key;
 
         SwarmOptions {
             keypair,
@@ -48,6 +120,11 @@ impl From<&IpfsOptions> for SwarmOptions {
             bootstrap,
             mdns,
             kad_protocol,
+            kad_query,
+            pubsub,
+            reprovide_interval,
+            connection_limits,
+            swarm_key,
         }
     }
 }
@@ -61,27 +138,30 @@ pub async fn create_swarm<TIpfsTypes: IpfsTypes>(
     let peer_id = options.peer_id.clone();
 
     // Set up an encrypted TCP transport over the Mplex protocol.
-    let transport = transport::build_transport(options.keypair.clone())?;
+    let transport = transport::build_transport(options.keypair.clone(), options.swarm_key)?;
 
     // Create a Kademlia behaviour
     let behaviour = behaviour::build_behaviour(options, repo).await;
 
     // Create a Swarm
     let swarm = libp2p::swarm::SwarmBuilder::new(transport, behaviour, peer_id)
-        .executor(Box::new(SpannedExecutor(swarm_span)))
+        .executor(Box::new(SpannedExecutor::<TIpfsTypes::TRuntime>(
+            swarm_span,
+            PhantomData,
+        )))
         .build();
 
     Ok(swarm)
 }
 
-struct SpannedExecutor(Span);
+struct SpannedExecutor<R>(Span, PhantomData<R>);
 
-impl libp2p::core::Executor for SpannedExecutor {
+impl<R: Runtime> libp2p::core::Executor for SpannedExecutor<R> {
     fn exec(
         &self,
         future: std::pin::Pin<Box<dyn std::future::Future<Output = ()> + 'static + Send>>,
     ) {
         use tracing_futures::Instrument;
-        tokio::task::spawn(future.instrument(self.0.clone()));
+        R::spawn(Box::pin(future.instrument(self.0.clone())));
     }
 }
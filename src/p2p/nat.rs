@@ -0,0 +1,191 @@
+//! Tracking of the reachability of the node from the addresses the other peers observe it at and
+//! from the connections the other peers open to it.
+//!
+//! The AutoNAT protocol, in which the other peers are asked to dial back, is not available with
+//! the current `libp2p`, so the status is inferred from the connections the node has anyway: an
+//! observed address is confirmed once enough distinct peers report the same IP address and port,
+//! which happens when the NAT maps the listening port consistently, and the node is known to be
+//! publicly reachable while a peer is connected to it from a public address.
+use libp2p::core::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// The number of distinct peers which need to report the same address for it to be confirmed.
+const CONFIRMATIONS: usize = 4;
+
+/// The number of the observed addresses tracked at most.
+const MAX_TRACKED: usize = 64;
+
+/// The reachability of the node, see [`crate::Ipfs::nat_status`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NatStatus {
+    /// Neither is a peer connected to the node from a public address nor has an observed address
+    /// been confirmed yet.
+    Unknown,
+    /// A peer is connected to the node from a public address; the confirmed observed addresses.
+    Public(Vec<Multiaddr>),
+    /// The observed addresses have been confirmed, but no peer is connected to the node from a
+    /// public address; the node is likely behind a NAT or a firewall.
+    Private(Vec<Multiaddr>),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct NatTracker {
+    /// The peers which have reported each of the observed addresses.
+    observers: HashMap<Multiaddr, HashSet<PeerId>>,
+    /// The number of the open connections from each of the public remote addresses.
+    inbound: HashMap<Multiaddr, usize>,
+}
+
+impl NatTracker {
+    /// Records the address the peer observed the node at, returning the address once it becomes
+    /// confirmed, to be added to the external addresses of the swarm.
+    ///
+    /// The whole address is compared, as the observations of the outgoing connections carry
+    /// ephemeral ports, which cannot be dialed.
+    pub(crate) fn observed(&mut self, peer_id: &PeerId, addr: Multiaddr) -> Option<Multiaddr> {
+        ip_of(&addr).filter(is_public)?;
+
+        if !self.observers.contains_key(&addr) && self.observers.len() >= MAX_TRACKED {
+            return None;
+        }
+
+        let peers = self.observers.entry(addr.clone()).or_default();
+
+        if peers.insert(peer_id.to_owned()) && peers.len() == CONFIRMATIONS {
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
+    /// Records a connection opened by a peer from the `remote` address.
+    pub(crate) fn inbound(&mut self, remote: &Multiaddr) {
+        if ip_of(remote).filter(is_public).is_some() {
+            *self.inbound.entry(remote.to_owned()).or_default() += 1;
+        }
+    }
+
+    /// Records the closing of a connection recorded with [`NatTracker::inbound`].
+    pub(crate) fn inbound_closed(&mut self, remote: &Multiaddr) {
+        if let Some(count) = self.inbound.get_mut(remote) {
+            *count -= 1;
+            if *count == 0 {
+                self.inbound.remove(remote);
+            }
+        }
+    }
+
+    pub(crate) fn status(&self) -> NatStatus {
+        let confirmed = self
+            .observers
+            .iter()
+            .filter(|(_, peers)| peers.len() >= CONFIRMATIONS)
+            .map(|(addr, _)| addr.clone())
+            .collect::<Vec<_>>();
+
+        if !self.inbound.is_empty() {
+            NatStatus::Public(confirmed)
+        } else if !confirmed.is_empty() {
+            NatStatus::Private(confirmed)
+        } else {
+            NatStatus::Unknown
+        }
+    }
+}
+
+/// Returns the leading IP address of the `Multiaddr` as a `Multiaddr` of its own.
+fn ip_of(addr: &Multiaddr) -> Option<Multiaddr> {
+    match addr.iter().next()? {
+        ip @ Protocol::Ip4(_) | ip @ Protocol::Ip6(_) => Some(Multiaddr::empty().with(ip)),
+        _ => None,
+    }
+}
+
+fn is_public(ip: &Multiaddr) -> bool {
+    match ip.iter().next() {
+        Some(Protocol::Ip4(ip)) => is_public_v4(ip),
+        Some(Protocol::Ip6(ip)) => is_public_v6(ip),
+        _ => false,
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        // the shared address space of carrier-grade NATs, 100.64.0.0/10
+        || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        // the unique local addresses, fc00::/7
+        || first & 0xfe00 == 0xfc00
+        // the link local addresses, fe80::/10
+        || first & 0xffc0 == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NatStatus, NatTracker, CONFIRMATIONS};
+    use libp2p::identity::Keypair;
+    use libp2p::{Multiaddr, PeerId};
+
+    fn peer() -> PeerId {
+        Keypair::generate_ed25519().public().into_peer_id()
+    }
+
+    #[test]
+    fn confirms_the_observed_addresses() {
+        let mut tracker = NatTracker::default();
+        assert_eq!(tracker.status(), NatStatus::Unknown);
+
+        // the private addresses tell nothing about the reachability
+        assert_eq!(
+            tracker.observed(&peer(), "/ip4/192.168.1.2/tcp/4001".parse().unwrap()),
+            None
+        );
+
+        // the ephemeral ports of the outgoing connections are never confirmed
+        for port in 0..CONFIRMATIONS {
+            let addr = format!("/ip4/1.2.3.4/tcp/{}", 50000 + port);
+            assert_eq!(tracker.observed(&peer(), addr.parse().unwrap()), None);
+        }
+
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+
+        // the same peer reporting again doesn't confirm the address
+        let observer = peer();
+        for _ in 0..CONFIRMATIONS {
+            assert_eq!(tracker.observed(&observer, addr.clone()), None);
+        }
+
+        let mut confirmed = None;
+        for _ in 1..CONFIRMATIONS {
+            confirmed = tracker.observed(&peer(), addr.clone());
+        }
+
+        assert_eq!(confirmed, Some(addr.clone()));
+        assert_eq!(tracker.status(), NatStatus::Private(vec![addr.clone()]));
+
+        tracker.inbound(&"/ip4/127.0.0.1/tcp/50200".parse().unwrap());
+        assert_eq!(tracker.status(), NatStatus::Private(vec![addr.clone()]));
+
+        let remote = "/ip4/5.6.7.8/tcp/50200".parse().unwrap();
+        tracker.inbound(&remote);
+        tracker.inbound(&remote);
+        assert_eq!(tracker.status(), NatStatus::Public(vec![addr.clone()]));
+
+        // reachable for as long as a connection from a public address is open
+        tracker.inbound_closed(&remote);
+        assert_eq!(tracker.status(), NatStatus::Public(vec![addr.clone()]));
+        tracker.inbound_closed(&remote);
+        assert_eq!(tracker.status(), NatStatus::Private(vec![addr]));
+    }
+}
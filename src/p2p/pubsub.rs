@@ -7,39 +7,100 @@ use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 
-use libp2p::core::{
-    connection::{ConnectedPoint, ConnectionId, ListenerId},
-    Multiaddr, PeerId,
+use libp2p::core::PeerId;
+use libp2p::floodsub::{self, Floodsub, FloodsubConfig, FloodsubEvent, FloodsubMessage};
+use libp2p::gossipsub::{
+    self, Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, MessageAuthenticity,
+    ValidationMode,
 };
-use libp2p::floodsub::{Floodsub, FloodsubConfig, FloodsubEvent, FloodsubMessage, Topic};
-use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters, ProtocolsHandler};
+use libp2p::identity::Keypair;
+use libp2p::swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters};
+
+/// Configures the pubsub of the node.
+#[derive(Debug, Clone, Default)]
+pub struct PubsubOptions {
+    /// The router carrying the subscriptions and the published messages; defaults to floodsub.
+    pub router: PubsubRouter,
+    /// How the messages published through gossipsub are signed; defaults to signing them with
+    /// the key of the node. The floodsub messages are never signed.
+    pub signing: MessageSigning,
+}
+
+/// The pubsub routers, of which one is used for the subscriptions and the publishing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PubsubRouter {
+    Floodsub,
+    Gossipsub,
+}
+
+impl Default for PubsubRouter {
+    fn default() -> Self {
+        PubsubRouter::Floodsub
+    }
+}
 
-/// Currently a thin wrapper around Floodsub, perhaps supporting both Gossipsub and Floodsub later.
+/// The signing of the published gossipsub messages, which also decides the messages accepted
+/// from the other peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageSigning {
+    /// The messages are signed with the key of the node, and only the messages with a valid
+    /// signature are accepted.
+    Signed,
+    /// The messages carry the node as the author but are not signed, and the unsigned messages
+    /// are accepted.
+    Author,
+    /// The messages carry no author nor signature, and only such messages are accepted.
+    Anonymous,
+}
+
+impl Default for MessageSigning {
+    fn default() -> Self {
+        MessageSigning::Signed
+    }
+}
+
+/// Wraps Floodsub and Gossipsub, using the one chosen in [`PubsubOptions::router`] for the
+/// subscriptions and the publishing; the other one stays idle.
+///
 /// Allows single subscription to a topic with only unbounded senders. Tracks the peers subscribed
 /// to different topics. The messages in the streams are wrapped in `Arc` as they technically could
 /// be sent to multiple topics, but this api is not provided.
+#[derive(libp2p::NetworkBehaviour)]
+#[behaviour(out_event = "void::Void", poll_method = "poll_unsubscriptions")]
 pub struct Pubsub {
-    streams: HashMap<Topic, channel::UnboundedSender<Arc<PubsubMessage>>>,
-    peers: HashMap<PeerId, Vec<Topic>>,
+    #[behaviour(ignore)]
+    streams: HashMap<String, channel::UnboundedSender<Arc<PubsubMessage>>>,
+    #[behaviour(ignore)]
+    peers: HashMap<PeerId, Vec<String>>,
     floodsub: Floodsub,
+    gossipsub: Gossipsub,
+    #[behaviour(ignore)]
+    router: PubsubRouter,
+    /// Gossipsub does not deliver the messages published by the node to itself, unlike floodsub,
+    /// so they are delivered to the local subscriptions with these sequence numbers.
+    #[behaviour(ignore)]
+    local_peer_id: PeerId,
+    #[behaviour(ignore)]
+    local_sequence_number: u64,
     // the subscription streams implement Drop and will send out their topic name through the
     // sender cloned from here if they are dropped before the stream has ended.
+    #[behaviour(ignore)]
     unsubscriptions: (
         channel::UnboundedSender<String>,
         channel::UnboundedReceiver<String>,
     ),
 }
 
-/// Adaptation hopefully supporting somehow both Floodsub and Gossipsub Messages in the future
+/// The message of either of the routers.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct PubsubMessage {
+    /// The author of the message, or for the anonymous gossipsub messages the peer the message
+    /// was received from.
     pub source: PeerId,
     pub data: Vec<u8>,
-    // this could be an enum for gossipsub message compat, it uses u64, though the floodsub
-    // sequence numbers looked like 8 bytes in testing..
+    // the floodsub sequence numbers looked like 8 bytes in testing, the gossipsub u64 sequence
+    // numbers are converted into 8 big endian bytes
     pub sequence_number: Vec<u8>,
-    // TODO: gossipsub uses topichashes, haven't checked if we could have some unifying abstraction
-    // or if we should have a hash to name mapping internally?
     pub topics: Vec<String>,
 }
 
@@ -61,6 +122,25 @@ impl From<FloodsubMessage> for PubsubMessage {
     }
 }
 
+impl PubsubMessage {
+    /// The gossipsub topics are not hashed, so the hashes are the topic names.
+    fn from_gossipsub(propagation_source: PeerId, message: GossipsubMessage) -> Self {
+        PubsubMessage {
+            source: message.source.unwrap_or(propagation_source),
+            data: message.data,
+            sequence_number: message
+                .sequence_number
+                .map(|seqno| seqno.to_be_bytes().to_vec())
+                .unwrap_or_default(),
+            topics: message
+                .topics
+                .into_iter()
+                .map(|topic| topic.into_string())
+                .collect(),
+        }
+    }
+}
+
 /// Stream of a pubsub messages. Implements [`FusedStream`].
 pub struct SubscriptionStream {
     on_drop: Option<channel::UnboundedSender<String>>,
@@ -124,16 +204,40 @@ impl FusedStream for SubscriptionStream {
 }
 
 impl Pubsub {
-    /// Delegates the `peer_id` over to [`Floodsub::new`] and internally only does accounting on
-    /// top of the floodsub.
-    pub fn new(peer_id: PeerId) -> Self {
+    /// Creates both of the routers, subscribing and publishing through the one chosen in the
+    /// `options`, and internally only does accounting on top of them.
+    pub fn new(keypair: &Keypair, options: &PubsubOptions) -> Self {
+        let peer_id = keypair.public().into_peer_id();
         let (tx, rx) = channel::unbounded();
-        let mut config = FloodsubConfig::new(peer_id);
+
+        let mut config = FloodsubConfig::new(peer_id.clone());
         config.subscribe_local_messages = true;
+
+        let (authenticity, validation) = match options.signing {
+            MessageSigning::Signed => (
+                MessageAuthenticity::Signed(keypair.clone()),
+                ValidationMode::Strict,
+            ),
+            MessageSigning::Author => (
+                MessageAuthenticity::Author(peer_id.clone()),
+                ValidationMode::Permissive,
+            ),
+            MessageSigning::Anonymous => {
+                (MessageAuthenticity::Anonymous, ValidationMode::Anonymous)
+            }
+        };
+        let gossipsub_config = GossipsubConfigBuilder::new()
+            .validation_mode(validation)
+            .build();
+
         Pubsub {
             streams: HashMap::new(),
             peers: HashMap::new(),
             floodsub: Floodsub::from_config(config),
+            gossipsub: Gossipsub::new(authenticity, gossipsub_config),
+            router: options.router,
+            local_peer_id: peer_id,
+            local_sequence_number: 0,
             unsubscriptions: (tx, rx),
         }
     }
@@ -143,30 +247,28 @@ impl Pubsub {
     pub fn subscribe(&mut self, topic: impl Into<String>) -> Option<SubscriptionStream> {
         use std::collections::hash_map::Entry;
 
-        let topic = Topic::new(topic);
-
-        match self.streams.entry(topic) {
-            Entry::Vacant(ve) => {
-                // TODO: this could also be bounded; we could send the message and drop the
-                // subscription if it ever became full.
-                let (tx, rx) = channel::unbounded();
-
-                // there are probably some invariants which need to hold for the topic...
-                assert!(
-                    self.floodsub.subscribe(ve.key().clone()),
-                    "subscribing to a unsubscribed topic should have succeeded"
-                );
-
-                let name = ve.key().id().to_string();
-                ve.insert(tx);
-                Some(SubscriptionStream {
-                    on_drop: Some(self.unsubscriptions.0.clone()),
-                    topic: Some(name),
-                    inner: rx,
-                })
-            }
-            Entry::Occupied(_) => None,
+        let topic = topic.into();
+
+        if let Entry::Occupied(_) = self.streams.entry(topic.clone()) {
+            return None;
         }
+
+        // TODO: this could also be bounded; we could send the message and drop the
+        // subscription if it ever became full.
+        let (tx, rx) = channel::unbounded();
+
+        // there are probably some invariants which need to hold for the topic...
+        assert!(
+            self.router_subscribe(&topic),
+            "subscribing to a unsubscribed topic should have succeeded"
+        );
+
+        self.streams.insert(topic.clone(), tx);
+        Some(SubscriptionStream {
+            on_drop: Some(self.unsubscriptions.0.clone()),
+            topic: Some(topic),
+            inner: rx,
+        })
     }
 
     /// Unsubscribes from a topic. Unsubscription is usually done through dropping the
@@ -174,10 +276,10 @@ impl Pubsub {
     ///
     /// Returns true if an existing subscription was dropped, false otherwise
     pub fn unsubscribe(&mut self, topic: impl Into<String>) -> bool {
-        let topic = Topic::new(topic);
+        let topic = topic.into();
         if self.streams.remove(&topic).is_some() {
             assert!(
-                self.floodsub.unsubscribe(topic),
+                self.router_unsubscribe(&topic),
                 "sender removed but unsubscription failed"
             );
             true
@@ -186,9 +288,34 @@ impl Pubsub {
         }
     }
 
-    /// See [`Floodsub::publish_any`]
+    /// See [`Floodsub::publish_any`] and [`Gossipsub::publish`]. Publishing through gossipsub
+    /// fails when there are no peers to publish to, which is only logged, as floodsub does.
     pub fn publish(&mut self, topic: impl Into<String>, data: impl Into<Vec<u8>>) {
-        self.floodsub.publish_any(Topic::new(topic), data);
+        let topic = topic.into();
+        let data = data.into();
+
+        match self.router {
+            PubsubRouter::Floodsub => {
+                self.floodsub.publish_any(floodsub::Topic::new(topic), data);
+            }
+            PubsubRouter::Gossipsub => {
+                if let Err(e) = self
+                    .gossipsub
+                    .publish(&gossipsub::Topic::new(topic.clone()), data.clone())
+                {
+                    debug!("publishing to {:?} failed: {:?}", topic, e);
+                }
+
+                self.local_sequence_number += 1;
+                let msg = PubsubMessage {
+                    source: self.local_peer_id.clone(),
+                    data,
+                    sequence_number: self.local_sequence_number.to_be_bytes().to_vec(),
+                    topics: vec![topic],
+                };
+                self.deliver(msg);
+            }
+        }
     }
 
     /// Returns the known peers subscribed to any topic
@@ -197,11 +324,11 @@ impl Pubsub {
     }
 
     /// Returns the peers known to subscribe to the given topic
-    pub fn subscribed_peers(&self, topic: &Topic) -> Vec<PeerId> {
+    pub fn subscribed_peers(&self, topic: &str) -> Vec<PeerId> {
         self.peers
             .iter()
             .filter_map(|(k, v)| {
-                if v.contains(topic) {
+                if v.iter().any(|t| t == topic) {
                     Some(k.clone())
                 } else {
                     None
@@ -210,17 +337,20 @@ impl Pubsub {
             .collect()
     }
 
+    /// Returns true if the peer is known to subscribe to any of the currently subscribed topics.
+    pub fn has_common_topics(&self, peer_id: &PeerId) -> bool {
+        self.peers.get(peer_id).map_or(false, |topics| {
+            topics.iter().any(|topic| self.streams.contains_key(topic))
+        })
+    }
+
     /// Returns the list of currently subscribed topics. This can contain topics for which stream
     /// has been dropped but no messages have yet been received on the topics after the drop.
     pub fn subscribed_topics(&self) -> Vec<String> {
-        self.streams
-            .keys()
-            .map(Topic::id)
-            .map(String::from)
-            .collect()
+        self.streams.keys().cloned().collect()
     }
 
-    /// See [`Floodsub::add_node_from_partial_view`]
+    /// See [`Floodsub::add_node_from_partial_view`]; gossipsub finds the peers on its own.
     pub fn add_node_to_partial_view(&mut self, peer_id: PeerId) {
         self.floodsub.add_node_to_partial_view(peer_id);
     }
@@ -229,108 +359,97 @@ impl Pubsub {
     pub fn remove_node_from_partial_view(&mut self, peer_id: &PeerId) {
         self.floodsub.remove_node_from_partial_view(peer_id);
     }
-}
 
-type PubsubNetworkBehaviourAction = NetworkBehaviourAction<
-    <<Pubsub as NetworkBehaviour>::ProtocolsHandler as ProtocolsHandler>::InEvent,
-    <Pubsub as NetworkBehaviour>::OutEvent,
->;
-
-impl NetworkBehaviour for Pubsub {
-    type ProtocolsHandler = <Floodsub as NetworkBehaviour>::ProtocolsHandler;
-    type OutEvent = void::Void;
-
-    fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        self.floodsub.new_handler()
-    }
-
-    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
-        self.floodsub.addresses_of_peer(peer_id)
-    }
-
-    fn inject_connected(&mut self, peer_id: &PeerId) {
-        self.floodsub.inject_connected(peer_id)
-    }
-
-    fn inject_disconnected(&mut self, peer_id: &PeerId) {
-        self.floodsub.inject_disconnected(peer_id)
-    }
-
-    fn inject_connection_established(
-        &mut self,
-        peer_id: &PeerId,
-        connection_id: &ConnectionId,
-        connected_point: &ConnectedPoint,
-    ) {
-        self.floodsub
-            .inject_connection_established(peer_id, connection_id, connected_point)
-    }
-
-    fn inject_connection_closed(
-        &mut self,
-        peer_id: &PeerId,
-        connection_id: &ConnectionId,
-        connected_point: &ConnectedPoint,
-    ) {
-        self.floodsub
-            .inject_connection_closed(peer_id, connection_id, connected_point)
+    fn router_subscribe(&mut self, topic: &str) -> bool {
+        match self.router {
+            PubsubRouter::Floodsub => self.floodsub.subscribe(floodsub::Topic::new(topic)),
+            PubsubRouter::Gossipsub => self
+                .gossipsub
+                .subscribe(gossipsub::Topic::new(topic.to_owned())),
+        }
     }
 
-    fn inject_event(
-        &mut self,
-        peer_id: PeerId,
-        connection: ConnectionId,
-        event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
-    ) {
-        self.floodsub.inject_event(peer_id, connection, event)
+    fn router_unsubscribe(&mut self, topic: &str) -> bool {
+        match self.router {
+            PubsubRouter::Floodsub => self.floodsub.unsubscribe(floodsub::Topic::new(topic)),
+            PubsubRouter::Gossipsub => self
+                .gossipsub
+                .unsubscribe(gossipsub::Topic::new(topic.to_owned())),
+        }
     }
 
-    fn inject_addr_reach_failure(
-        &mut self,
-        peer_id: Option<&PeerId>,
-        addr: &Multiaddr,
-        error: &dyn std::error::Error,
-    ) {
-        self.floodsub
-            .inject_addr_reach_failure(peer_id, addr, error)
-    }
+    /// Sends the message to the subscriptions of its topics, unsubscribing the ones whose
+    /// receivers have been dropped.
+    fn deliver(&mut self, msg: PubsubMessage) {
+        use std::collections::hash_map::Entry;
 
-    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
-        self.floodsub.inject_dial_failure(peer_id)
+        let topics = msg.topics.clone();
+        let msg = Arc::new(msg);
+        let mut buffer = None;
+
+        for topic in topics {
+            if let Entry::Occupied(oe) = self.streams.entry(topic) {
+                let sent = buffer.take().unwrap_or_else(|| Arc::clone(&msg));
+
+                if let Err(se) = oe.get().unbounded_send(sent) {
+                    // receiver has dropped
+                    let (topic, _) = oe.remove_entry();
+                    debug!("unsubscribing via SendError from {:?}", topic);
+                    assert!(
+                        self.router_unsubscribe(&topic),
+                        "Failed to unsubscribe following SendError"
+                    );
+                    buffer = Some(se.into_inner());
+                }
+            } else {
+                // we had unsubscribed from the topic after the router had received the message
+            }
+        }
     }
 
-    fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
-        self.floodsub.inject_new_listen_addr(addr)
-    }
+    fn peer_subscribed(&mut self, peer_id: PeerId, topic: String) {
+        let topics = self.peers.entry(peer_id.clone()).or_insert_with(Vec::new);
+        let appeared = topics.is_empty();
+        if topics.iter().find(|&t| t == &topic).is_none() {
+            topics.push(topic);
+        }
 
-    fn inject_expired_listen_addr(&mut self, addr: &Multiaddr) {
-        self.floodsub.inject_expired_listen_addr(addr)
+        if appeared {
+            debug!("peer appeared as pubsub subscriber: {}", peer_id);
+        }
     }
 
-    fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
-        self.floodsub.inject_new_external_addr(addr)
-    }
+    fn peer_unsubscribed(&mut self, peer_id: PeerId, topic: &str) {
+        use std::collections::hash_map::Entry;
 
-    fn inject_listener_error(&mut self, id: ListenerId, err: &(dyn std::error::Error + 'static)) {
-        self.floodsub.inject_listener_error(id, err)
+        if let Entry::Occupied(mut oe) = self.peers.entry(peer_id.clone()) {
+            let topics = oe.get_mut();
+            if let Some(pos) = topics.iter().position(|t| t == topic) {
+                topics.swap_remove(pos);
+            }
+            if topics.is_empty() {
+                debug!("peer disappeared as pubsub subscriber: {}", peer_id);
+                oe.remove();
+            }
+        }
     }
 
-    fn poll(
+    /// Unsubscribes the topics of the dropped [`SubscriptionStream`]s; called after the routers
+    /// have been polled.
+    fn poll_unsubscriptions<T>(
         &mut self,
         ctx: &mut Context,
-        poll: &mut impl PollParameters,
-    ) -> Poll<PubsubNetworkBehaviourAction> {
+        _: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<T, void::Void>> {
         use futures::stream::StreamExt;
-        use std::collections::hash_map::Entry;
 
         loop {
             match self.unsubscriptions.1.poll_next_unpin(ctx) {
                 Poll::Ready(Some(dropped)) => {
-                    let topic = Topic::new(dropped);
-                    if self.streams.remove(&topic).is_some() {
-                        debug!("unsubscribing via drop from {:?}", topic.id());
+                    if self.streams.remove(&dropped).is_some() {
+                        debug!("unsubscribing via drop from {:?}", dropped);
                         assert!(
-                            self.floodsub.unsubscribe(topic),
+                            self.router_unsubscribe(&dropped),
                             "Failed to unsubscribe a dropped subscription"
                         );
                     } else {
@@ -340,92 +459,37 @@ impl NetworkBehaviour for Pubsub {
                     }
                 }
                 Poll::Ready(None) => unreachable!("we own the sender"),
-                Poll::Pending => break,
+                Poll::Pending => return Poll::Pending,
             }
         }
+    }
+}
 
-        loop {
-            match futures::ready!(self.floodsub.poll(ctx, poll)) {
-                NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Message(msg)) => {
-                    let topics = msg.topics.clone();
-                    let msg = Arc::new(PubsubMessage::from(msg));
-                    let mut buffer = None;
-
-                    for topic in topics {
-                        if let Entry::Occupied(oe) = self.streams.entry(topic) {
-                            let sent = buffer.take().unwrap_or_else(|| Arc::clone(&msg));
-
-                            if let Err(se) = oe.get().unbounded_send(sent) {
-                                // receiver has dropped
-                                let (topic, _) = oe.remove_entry();
-                                debug!("unsubscribing via SendError from {:?}", topic.id());
-                                assert!(
-                                    self.floodsub.unsubscribe(topic),
-                                    "Failed to unsubscribe following SendError"
-                                );
-                                buffer = Some(se.into_inner());
-                            }
-                        } else {
-                            // we had unsubscribed from the topic after Floodsub had received the
-                            // message
-                        }
-                    }
-
-                    continue;
-                }
-                NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Subscribed {
-                    peer_id,
-                    topic,
-                }) => {
-                    let topics = self.peers.entry(peer_id.clone()).or_insert_with(Vec::new);
-                    let appeared = topics.is_empty();
-                    if topics.iter().find(|&t| t == &topic).is_none() {
-                        topics.push(topic);
-                    }
-
-                    if appeared {
-                        debug!("peer appeared as pubsub subscriber: {}", peer_id);
-                    }
-
-                    continue;
-                }
-                NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Unsubscribed {
-                    peer_id,
-                    topic,
-                }) => {
-                    if let Entry::Occupied(mut oe) = self.peers.entry(peer_id.clone()) {
-                        let topics = oe.get_mut();
-                        if let Some(pos) = topics.iter().position(|t| t == &topic) {
-                            topics.swap_remove(pos);
-                        }
-                        if topics.is_empty() {
-                            debug!("peer disappeared as pubsub subscriber: {}", peer_id);
-                            oe.remove();
-                        }
-                    }
+impl NetworkBehaviourEventProcess<FloodsubEvent> for Pubsub {
+    fn inject_event(&mut self, event: FloodsubEvent) {
+        match event {
+            FloodsubEvent::Message(msg) => self.deliver(PubsubMessage::from(msg)),
+            FloodsubEvent::Subscribed { peer_id, topic } => {
+                self.peer_subscribed(peer_id, topic.id().to_owned())
+            }
+            FloodsubEvent::Unsubscribed { peer_id, topic } => {
+                self.peer_unsubscribed(peer_id, topic.id())
+            }
+        }
+    }
+}
 
-                    continue;
-                }
-                NetworkBehaviourAction::DialAddress { address } => {
-                    return Poll::Ready(NetworkBehaviourAction::DialAddress { address });
-                }
-                NetworkBehaviourAction::DialPeer { peer_id, condition } => {
-                    return Poll::Ready(NetworkBehaviourAction::DialPeer { peer_id, condition });
-                }
-                NetworkBehaviourAction::NotifyHandler {
-                    peer_id,
-                    event,
-                    handler,
-                } => {
-                    return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
-                        peer_id,
-                        event,
-                        handler,
-                    });
-                }
-                NetworkBehaviourAction::ReportObservedAddr { address } => {
-                    return Poll::Ready(NetworkBehaviourAction::ReportObservedAddr { address });
-                }
+impl NetworkBehaviourEventProcess<GossipsubEvent> for Pubsub {
+    fn inject_event(&mut self, event: GossipsubEvent) {
+        match event {
+            GossipsubEvent::Message(propagation_source, _id, msg) => {
+                self.deliver(PubsubMessage::from_gossipsub(propagation_source, msg))
+            }
+            GossipsubEvent::Subscribed { peer_id, topic } => {
+                self.peer_subscribed(peer_id, topic.into_string())
+            }
+            GossipsubEvent::Unsubscribed { peer_id, topic } => {
+                self.peer_unsubscribed(peer_id, topic.as_str())
             }
         }
     }
@@ -1,3 +1,4 @@
+use crate::p2p::nat::{NatStatus, NatTracker};
 use crate::p2p::{MultiaddrWithPeerId, MultiaddrWithoutPeerId};
 use crate::subscription::{SubscriptionFuture, SubscriptionRegistry};
 use core::task::{Context, Poll};
@@ -8,7 +9,7 @@ use libp2p::swarm::protocols_handler::{
 use libp2p::swarm::{self, NetworkBehaviour, PollParameters, Swarm};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::{TryFrom, TryInto};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// A description of currently active connection.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -19,6 +20,50 @@ pub struct Connection {
     pub rtt: Option<Duration>,
 }
 
+/// The limits on the number of the open connections, which are enforced by disconnecting the
+/// least valuable peers: the ones without an active bitswap ledger and without pubsub topics in
+/// common with the local node.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimits {
+    /// The number of connections the pruning stops at; defaults to 600.
+    pub low_water: usize,
+    /// The number of connections above which the peers connected for longer than the grace period
+    /// are pruned; defaults to 900.
+    pub high_water: usize,
+    /// The number of connections above which also the peers within the grace period are pruned;
+    /// defaults to 1200.
+    pub hard_limit: usize,
+    /// The time for which a newly connected peer is not pruned below the hard limit; defaults to
+    /// 20 seconds.
+    pub grace_period: Duration,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            low_water: 600,
+            high_water: 900,
+            hard_limit: 1200,
+            grace_period: Duration::from_secs(20),
+        }
+    }
+}
+
+/// The counts of the open connections.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SwarmStats {
+    /// The number of the connected peers.
+    pub peers: usize,
+    /// The number of the open connections.
+    pub connections: usize,
+    /// The number of the connections opened by the other peers.
+    pub inbound: usize,
+    /// The number of the connections opened by the local node.
+    pub outbound: usize,
+    /// The number of the peers disconnected for the connections being over the limits.
+    pub pruned: u64,
+}
+
 /// Disconnected will use banning to disconnect a node. Disconnecting a single peer connection is
 /// not supported at the moment.
 pub struct Disconnector {
@@ -45,6 +90,13 @@ pub struct SwarmApi {
     connections: HashMap<MultiaddrWithoutPeerId, PeerId>,
     roundtrip_times: HashMap<PeerId, Duration>,
     connected_peers: HashMap<PeerId, Vec<MultiaddrWithoutPeerId>>,
+    /// The time of the first of the open connections to each of the connected peers.
+    connected_since: HashMap<PeerId, Instant>,
+    /// The addresses of the connections opened by the other peers.
+    inbound: HashSet<MultiaddrWithoutPeerId>,
+    pruned: u64,
+    nat: NatTracker,
+    pub(crate) limits: ConnectionLimits,
     pub(crate) bootstrappers: HashSet<MultiaddrWithPeerId>,
 }
 
@@ -122,11 +174,99 @@ impl SwarmApi {
         }
     }
 
+    /// Disconnects all of the connections to the peer, like [`SwarmApi::disconnect`].
+    pub fn disconnect_peer(&mut self, peer_id: &PeerId) -> Option<Disconnector> {
+        if self.connected_peers.contains_key(peer_id) {
+            self.mark_disconnected(peer_id);
+            Some(Disconnector {
+                peer_id: peer_id.to_owned(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the counts of the open connections.
+    pub fn stats(&self) -> SwarmStats {
+        let connections = self.connection_count();
+        let inbound = self.inbound.len();
+
+        SwarmStats {
+            peers: self.connected_peers.len(),
+            connections,
+            inbound,
+            outbound: connections.saturating_sub(inbound),
+            pruned: self.pruned,
+        }
+    }
+
+    /// Records the address the peer observed the local node at, adding it to the external
+    /// addresses of the swarm once enough peers have confirmed it.
+    pub fn observed_addr(&mut self, peer_id: &PeerId, addr: Multiaddr) {
+        if let Some(address) = self.nat.observed(peer_id, addr) {
+            debug!("confirmed the observed address {}", address);
+            self.events
+                .push_back(NetworkBehaviourAction::ReportObservedAddr { address });
+        }
+    }
+
+    pub fn nat_status(&self) -> NatStatus {
+        self.nat.status()
+    }
+
+    fn connection_count(&self) -> usize {
+        self.connected_peers.values().map(Vec::len).sum()
+    }
+
+    /// Returns the peers to disconnect in order to get from above the high watermark down to the
+    /// low watermark, the most recently connected first. The peers for which `is_valuable`
+    /// returns true and the bootstrappers are never pruned.
+    pub(crate) fn prune_candidates(
+        &mut self,
+        is_valuable: impl Fn(&PeerId) -> bool,
+    ) -> Vec<PeerId> {
+        let connections = self.connection_count();
+        if connections <= self.limits.high_water {
+            return Vec::new();
+        }
+
+        let over_hard_limit = connections > self.limits.hard_limit;
+        let grace_period = self.limits.grace_period;
+        let bootstrappers = &self.bootstrappers;
+
+        let mut candidates = self
+            .connected_since
+            .iter()
+            .filter(|(_, since)| over_hard_limit || since.elapsed() >= grace_period)
+            .filter(|(peer, _)| !bootstrappers.iter().any(|addr| addr.peer_id == **peer))
+            .filter(|(peer, _)| !is_valuable(peer))
+            .map(|(peer, since)| (*since, peer.to_owned()))
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|(left, _), (right, _)| right.cmp(left));
+
+        let mut excess = connections.saturating_sub(self.limits.low_water);
+        let mut pruned = Vec::new();
+
+        for (_, peer) in candidates {
+            if excess == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(self.connected_peers.get(&peer).map_or(0, Vec::len));
+            pruned.push(peer);
+        }
+
+        self.pruned += pruned.len() as u64;
+        pruned
+    }
+
     fn mark_disconnected(&mut self, peer_id: &PeerId) {
         for address in self.connected_peers.remove(peer_id).into_iter().flatten() {
             self.connections.remove(&address);
+            self.inbound.remove(&address);
         }
         self.roundtrip_times.remove(peer_id);
+        self.connected_since.remove(peer_id);
     }
 }
 
@@ -163,6 +303,14 @@ impl NetworkBehaviour for SwarmApi {
         connections.push(addr.clone());
 
         self.connections.insert(addr.clone(), peer_id.clone());
+        self.connected_since
+            .entry(peer_id.clone())
+            .or_insert_with(Instant::now);
+
+        if let ConnectedPoint::Listener { send_back_addr, .. } = cp {
+            self.inbound.insert(addr.clone());
+            self.nat.inbound(send_back_addr);
+        }
 
         if let ConnectedPoint::Dialer { .. } = cp {
             let addr = MultiaddrWithPeerId {
@@ -198,8 +346,14 @@ impl NetworkBehaviour for SwarmApi {
         };
         if became_empty {
             self.connected_peers.remove(peer_id);
+            self.connected_since.remove(peer_id);
         }
         self.connections.remove(&closed_addr);
+        self.inbound.remove(&closed_addr);
+
+        if let ConnectedPoint::Listener { send_back_addr, .. } = cp {
+            self.nat.inbound_closed(send_back_addr);
+        }
 
         if let ConnectedPoint::Dialer { .. } = cp {
             let addr = MultiaddrWithPeerId::from((closed_addr, peer_id.to_owned()));
@@ -288,10 +442,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn prunes_the_least_valuable_peers() {
+        let mut swarm = SwarmApi::default();
+        swarm.limits = ConnectionLimits {
+            low_water: 1,
+            high_water: 2,
+            hard_limit: 10,
+            grace_period: Duration::from_secs(0),
+        };
+
+        let peers = (0..3)
+            .map(|_| Keypair::generate_ed25519().public().into_peer_id())
+            .collect::<Vec<_>>();
+
+        for (i, peer) in peers.iter().enumerate() {
+            let cp = ConnectedPoint::Listener {
+                local_addr: "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+                send_back_addr: format!("/ip4/127.0.0.{}/tcp/4001", i + 2).parse().unwrap(),
+            };
+            swarm.inject_connection_established(peer, &ConnectionId::new(i), &cp);
+
+            // nothing is pruned up to the high watermark
+            if i < 2 {
+                assert!(swarm.prune_candidates(|_| false).is_empty());
+            }
+        }
+
+        let stats = swarm.stats();
+        assert_eq!((stats.peers, stats.inbound, stats.outbound), (3, 3, 0));
+
+        // the valuable peer is kept even though the low watermark is not reached
+        let mut pruned = swarm.prune_candidates(|peer| *peer == peers[1]);
+        pruned.sort_by_key(|peer| peer.to_string());
+        let mut expected = vec![peers[0].clone(), peers[2].clone()];
+        expected.sort_by_key(|peer| peer.to_string());
+        assert_eq!(pruned, expected);
+
+        for peer in &pruned {
+            assert!(swarm.disconnect_peer(peer).is_some());
+        }
+
+        let stats = swarm.stats();
+        assert_eq!((stats.peers, stats.connections, stats.pruned), (1, 1, 2));
+    }
+
     fn mk_transport() -> (PeerId, TTransport) {
         let key = Keypair::generate_ed25519();
         let peer_id = key.public().into_peer_id();
-        let transport = build_transport(key).unwrap();
+        let transport = build_transport(key, None).unwrap();
         (peer_id, transport)
     }
 }
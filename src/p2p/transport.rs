@@ -1,15 +1,26 @@
+use libp2p::core::either::EitherTransport;
 use libp2p::core::muxing::StreamMuxerBox;
 use libp2p::core::transport::boxed::Boxed;
 use libp2p::core::transport::upgrade::Version;
+#[cfg(any(test, feature = "test-utils"))]
+use libp2p::core::transport::MemoryTransport;
 use libp2p::core::upgrade::SelectUpgrade;
+#[cfg(not(target_arch = "wasm32"))]
 use libp2p::dns::DnsConfig;
 use libp2p::identity;
 use libp2p::mplex::MplexConfig;
 use libp2p::noise::{self, NoiseConfig};
+use libp2p::pnet::{PnetConfig, PreSharedKey};
+#[cfg(not(target_arch = "wasm32"))]
 use libp2p::tcp::TokioTcpConfig;
+#[cfg(target_arch = "wasm32")]
+use libp2p::wasm_ext::{ffi::websocket_transport, ExtTransport};
 use libp2p::yamux::Config as YamuxConfig;
 use libp2p::{PeerId, Transport};
+use std::fs;
 use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
 
 /// Transport type.
@@ -17,14 +28,37 @@ pub(crate) type TTransport = Boxed<(PeerId, StreamMuxerBox), Error>;
 
 /// Builds the transport that serves as a common ground for all connections.
 ///
-/// Set up an encrypted TCP transport over the Mplex protocol.
-pub fn build_transport(keypair: identity::Keypair) -> io::Result<TTransport> {
+/// Set up an encrypted TCP transport over the Mplex protocol, or the `libp2p::wasm_ext` websocket
+/// transport on `wasm32`. With the `test-utils` feature, the in-process memory transport is
+/// available as well for the `/memory/` addresses used by `crate::test_support`.
+///
+/// With the pre-shared key of a private network, the pnet handshake is made on all of the
+/// connections before anything else, so the peers without the key cannot connect.
+pub fn build_transport(
+    keypair: identity::Keypair,
+    swarm_key: Option<PreSharedKey>,
+) -> io::Result<TTransport> {
     let xx_keypair = noise::Keypair::<noise::X25519Spec>::new()
         .into_authentic(&keypair)
         .unwrap();
     let noise_config = NoiseConfig::xx(xx_keypair).into_authenticated();
 
-    Ok(DnsConfig::new(TokioTcpConfig::new().nodelay(true))?
+    #[cfg(not(target_arch = "wasm32"))]
+    let network = DnsConfig::new(TokioTcpConfig::new().nodelay(true))?;
+    #[cfg(target_arch = "wasm32")]
+    let network = ExtTransport::new(websocket_transport());
+
+    #[cfg(any(test, feature = "test-utils"))]
+    let network = MemoryTransport::default().or_transport(network);
+
+    let network = match swarm_key {
+        Some(psk) => EitherTransport::Left(
+            network.and_then(move |socket, _| PnetConfig::new(psk).handshake(socket)),
+        ),
+        None => EitherTransport::Right(network),
+    };
+
+    Ok(network
         .upgrade(Version::V1)
         .authenticate(noise_config)
         .multiplex(SelectUpgrade::new(
@@ -36,3 +70,17 @@ pub fn build_transport(keypair: identity::Keypair) -> io::Result<TTransport> {
         .map_err(|err| Error::new(ErrorKind::Other, err))
         .boxed())
 }
+
+/// Reads the pre-shared key of a go-ipfs style private network from the `swarm.key` file in the
+/// repo at `ipfs_path`, returning `None` when there is no such file.
+pub fn read_swarm_key(ipfs_path: &Path) -> io::Result<Option<PreSharedKey>> {
+    let text = match fs::read_to_string(ipfs_path.join("swarm.key")) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    PreSharedKey::from_str(&text)
+        .map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
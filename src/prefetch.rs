@@ -0,0 +1,150 @@
+//! The [`Prefetcher`] used by the DAG traversals to fetch the upcoming blocks concurrently, instead
+//! of waiting for a round trip to the providers for each of the blocks in turn. The blocks are
+//! fetched in a bitswap session, so that the peers which have sent the earlier blocks of the DAG
+//! are asked first.
+
+use crate::error::Error;
+use crate::repo::{BlockData, BlockSession};
+use crate::{Ipfs, IpfsTypes};
+use cid::Cid;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::{HashMap, HashSet};
+
+/// The default number of blocks requested ahead of the traversal.
+pub(crate) const DEFAULT_WINDOW: usize = 32;
+
+/// Fetches the blocks like [`Ipfs::get_block_data`] does, requesting up to `window` of the upcoming
+/// blocks at the same time. The fetched blocks are held until they are asked for.
+pub(crate) struct Prefetcher<Types: IpfsTypes> {
+    ipfs: Ipfs<Types>,
+    session: BlockSession,
+    window: usize,
+    /// The blocks being fetched or held, which are at most `window`.
+    requested: HashSet<Cid>,
+    in_flight: FuturesUnordered<BoxFuture<'static, (Cid, Result<BlockData, Error>)>>,
+    ready: HashMap<Cid, Result<BlockData, Error>>,
+}
+
+impl<Types: IpfsTypes> Prefetcher<Types> {
+    pub(crate) fn new(ipfs: Ipfs<Types>, window: usize) -> Self {
+        Prefetcher {
+            session: ipfs.repo.session(),
+            ipfs,
+            window: window.max(1),
+            requested: Default::default(),
+            in_flight: Default::default(),
+            ready: Default::default(),
+        }
+    }
+
+    /// Returns the data of the block for the `cid`, first requesting the blocks of the `upcoming` Cids in the
+    /// order of the traversal for as long as there is room in the window.
+    pub(crate) async fn get<'a>(
+        &mut self,
+        cid: &Cid,
+        upcoming: impl IntoIterator<Item = &'a Cid>,
+    ) -> Result<BlockData, Error> {
+        self.request(cid);
+
+        for next in upcoming {
+            if self.requested.len() >= self.window {
+                break;
+            }
+            self.request(next);
+        }
+
+        loop {
+            if let Some(res) = self.ready.remove(cid) {
+                self.requested.remove(cid);
+                return res;
+            }
+
+            match self.in_flight.next().await {
+                Some((fetched, res)) => {
+                    self.ready.insert(fetched, res);
+                }
+                None => unreachable!("{} was requested but is neither ready nor in flight", cid),
+            }
+        }
+    }
+
+    fn request(&mut self, cid: &Cid) {
+        if !self.requested.insert(cid.to_owned()) {
+            return;
+        }
+
+        let ipfs = self.ipfs.clone();
+        let session = self.session.id();
+        let cid = cid.to_owned();
+        self.in_flight.push(
+            async move {
+                let res = ipfs.repo.get_block_data_in_session(&cid, session).await;
+                (cid, res)
+            }
+            .boxed(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Prefetcher;
+    use crate::{Block, Node};
+    use cid::{Cid, Codec};
+    use multihash::Sha2_256;
+
+    #[tokio::test(max_threads = 1)]
+    async fn blocks_in_any_order_within_the_window() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut cids = Vec::new();
+        for i in 0..5u8 {
+            let data = vec![i].into_boxed_slice();
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            ipfs.put_block(Block::new(data, cid.clone())).await.unwrap();
+            cids.push(cid);
+        }
+
+        let mut prefetcher = Prefetcher::new(ipfs.ipfs.clone(), 3);
+
+        let data = prefetcher.get(&cids[0], &cids[1..]).await.unwrap();
+        assert_eq!(&*data, &[0]);
+        // the first block was handed out, leaving the two following it
+        assert_eq!(prefetcher.requested.len(), 2);
+
+        let data = prefetcher.get(&cids[2], &cids[3..]).await.unwrap();
+        assert_eq!(&*data, &[2]);
+        assert_eq!(prefetcher.requested.len(), 2);
+
+        for &nth in &[1, 3, 4] {
+            let data = prefetcher.get(&cids[nth], None).await.unwrap();
+            assert_eq!(&*data, &[nth as u8]);
+        }
+        assert!(prefetcher.requested.is_empty());
+        assert!(prefetcher.ready.is_empty());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn fetches_from_peers_in_a_session() {
+        let a = Node::new("a").await;
+        let b = Node::new("b").await;
+
+        let mut cids = Vec::new();
+        for i in 0..3u8 {
+            let data = vec![i].into_boxed_slice();
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            a.put_block(Block::new(data, cid.clone())).await.unwrap();
+            cids.push(cid);
+        }
+
+        b.connect(a.addrs[0].clone()).await.unwrap();
+
+        let mut prefetcher = Prefetcher::new(b.ipfs.clone(), 2);
+
+        for (nth, cid) in cids.iter().enumerate() {
+            let data = prefetcher.get(cid, &cids[nth + 1..]).await.unwrap();
+            assert_eq!(&*data, &[nth as u8]);
+        }
+    }
+}
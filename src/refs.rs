@@ -1,6 +1,7 @@
 //! `refs` or the references of dag-pb and other supported IPLD formats functionality.
 
 use crate::ipld::{decode_ipld, Ipld};
+use crate::prefetch::{Prefetcher, DEFAULT_WINDOW};
 use crate::{Block, Ipfs, IpfsTypes};
 use async_stream::stream;
 use cid::{self, Cid};
@@ -104,7 +105,8 @@ impl IpldRefs {
 ///
 /// `js-ipfs` does seem to do a recursive descent on all links. Looking at the tests it would
 /// appear that `go-ipfs` implements this in similar fashion. This implementation is breadth-first
-/// to be simpler at least.
+/// to be simpler at least. The blocks of the queued links are fetched concurrently, up to a
+/// bounded window ahead of the walk.
 ///
 /// Related: https://github.com/ipfs/js-ipfs/pull/2982
 ///
@@ -180,6 +182,12 @@ where
             return;
         }
 
+        let mut prefetcher = if download_blocks {
+            Some(Prefetcher::new(ipfs.borrow().clone(), DEFAULT_WINDOW))
+        } else {
+            None
+        };
+
         while let Some((depth, cid, source, link_name)) = work.pop_front() {
             let traverse_links = match max_depth {
                 Some(d) if d <= depth => {
@@ -195,9 +203,15 @@ where
             // `MaybeOwned` which we don't necessarily need.
             let borrowed = ipfs.borrow();
 
-            let data = if download_blocks {
-                match borrowed.get_block(&cid).await {
-                    Ok(Block { data, .. }) => data,
+            let data = if let Some(prefetcher) = prefetcher.as_mut() {
+                // the links past the max_depth are not going to be loaded
+                let upcoming = work
+                    .iter()
+                    .filter(|(depth, ..)| max_depth.map(|d| *depth < d).unwrap_or(true))
+                    .map(|(_, cid, ..)| cid);
+
+                match prefetcher.get(&cid, upcoming).await {
+                    Ok(data) => data,
                     Err(e) => {
                         warn!("failed to load {}, linked from {}: {}", cid, source, e);
                         // TODO: yield error msg
@@ -209,7 +223,7 @@ where
                 }
             } else {
                 match borrowed.repo.get_block_now(&cid).await {
-                    Ok(Some(Block { data, .. })) => data,
+                    Ok(Some(Block { data, .. })) => data.into(),
                     Ok(None) => {
                         yield Err(IpldRefsError::BlockNotFound(cid.to_owned()));
                         return;
@@ -250,7 +264,7 @@ where
     }
 }
 
-fn ipld_links(
+pub(crate) fn ipld_links(
     cid: &Cid,
     ipld: Ipld,
 ) -> impl Iterator<Item = (Option<String>, Cid)> + Send + 'static {
@@ -510,7 +524,7 @@ mod tests {
 
             let block = Block {
                 cid,
-                data: (*data).into(),
+                data: data.to_vec().into(),
             };
 
             ipfs.put_block(block).await.unwrap();
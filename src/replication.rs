@@ -0,0 +1,222 @@
+//! Replication of a DAG from a peer with [`Ipfs::sync`]: the blocks selected by the [`Selector`]
+//! which are already in the repo are read locally, and only the missing ones are requested, the
+//! blocks of each level of the DAG concurrently.
+
+use crate::error::Error;
+use crate::ipld::decode_ipld;
+use crate::p2p::MultiaddrWithPeerId;
+use crate::prefetch::DEFAULT_WINDOW;
+use crate::refs::ipld_links;
+use crate::runtime::Runtime;
+use crate::{Block, Ipfs, IpfsTypes};
+use cid::Cid;
+use futures::future::{self, Either};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Selects the blocks of the DAG to replicate, starting from the root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+    /// The root and all of the blocks reachable from it.
+    All,
+    /// The blocks at most the given number of links away from the root; zero selects only the
+    /// root.
+    Depth(u64),
+}
+
+impl Selector {
+    fn follows_links_at(&self, depth: u64) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Depth(max) => depth < *max,
+        }
+    }
+}
+
+/// The time to wait for each of the missing blocks before giving up on it, as the peer might
+/// not have all of the selected blocks.
+pub const BLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The outcome of [`Ipfs::sync`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    /// The number of the selected blocks which were already in the repo.
+    pub present: usize,
+    /// The number of the selected blocks which were fetched.
+    pub fetched: usize,
+    /// The number of bytes in the fetched blocks.
+    pub fetched_bytes: u64,
+    /// The selected blocks which could not be fetched in time. The blocks linked from them were
+    /// not synced.
+    pub missing: Vec<Cid>,
+}
+
+pub(crate) async fn sync<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    peer: MultiaddrWithPeerId,
+    root: Cid,
+    selector: Selector,
+    block_timeout: Duration,
+) -> Result<SyncStats, Error> {
+    let connected = ipfs
+        .peers()
+        .await?
+        .iter()
+        .any(|conn| conn.addr.peer_id == peer.peer_id);
+
+    if !connected {
+        ipfs.connect(peer).await?;
+    }
+
+    let mut stats = SyncStats::default();
+    let mut visited = HashSet::new();
+    let mut level = vec![root];
+    let mut depth = 0;
+
+    while !level.is_empty() {
+        let follow = selector.follows_links_at(depth);
+        let mut next_level = Vec::new();
+        let mut missing = Vec::new();
+
+        // only the links of the blocks are kept, so the memory use depends on the width of the
+        // DAG and not on the size of its blocks
+        for cid in level.drain(..) {
+            match ipfs.repo.get_block_now(&cid).await? {
+                Some(block) if follow => {
+                    stats.present += 1;
+                    push_links(&block, &mut visited, &mut next_level)?;
+                }
+                Some(_) => stats.present += 1,
+                None => missing.push(cid),
+            }
+        }
+
+        trace!(depth, missing = missing.len(), "syncing level");
+
+        let mut fetched = stream::iter(missing)
+            .map(|cid| async move {
+                let fetch = ipfs.get_block(&cid);
+                futures::pin_mut!(fetch);
+
+                match future::select(fetch, Types::TRuntime::delay_for(block_timeout)).await {
+                    Either::Left((Ok(block), _)) => Ok((cid, Some(block))),
+                    Either::Left((Err(e), _)) => Err(e),
+                    Either::Right(_) => Ok((cid, None)),
+                }
+            })
+            .buffer_unordered(DEFAULT_WINDOW);
+
+        while let Some((cid, block)) = fetched.try_next().await? {
+            match block {
+                Some(block) => {
+                    stats.fetched += 1;
+                    stats.fetched_bytes += block.data.len() as u64;
+
+                    if follow {
+                        push_links(&block, &mut visited, &mut next_level)?;
+                    }
+                }
+                None => {
+                    debug!(%cid, "timed out fetching a block");
+                    stats.missing.push(cid);
+                }
+            }
+        }
+
+        if !follow {
+            break;
+        }
+
+        level = next_level;
+        depth += 1;
+    }
+
+    Ok(stats)
+}
+
+/// Queues the links of the `block` not seen before into the `level`.
+fn push_links(
+    block: &Block,
+    visited: &mut HashSet<Cid>,
+    level: &mut Vec<Cid>,
+) -> Result<(), Error> {
+    let ipld = decode_ipld(&block.cid, &block.data)?;
+    for (_, next) in ipld_links(&block.cid, ipld) {
+        if visited.insert(next.clone()) {
+            level.push(next);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Selector, SyncStats};
+    use crate::p2p::MultiaddrWithPeerId;
+    use crate::{make_ipld, Node};
+    use cid::{Cid, Codec};
+    use multihash::Sha2_256;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    #[tokio::test(max_threads = 1)]
+    async fn fetches_only_the_missing_blocks() {
+        let a = Node::new("a").await;
+        let b = Node::new("b").await;
+
+        let dag = a.dag();
+        let leaf = dag.put(make_ipld!([1, 2]), Codec::DagCBOR).await.unwrap();
+        let mid = dag
+            .put(make_ipld!([leaf.clone()]), Codec::DagCBOR)
+            .await
+            .unwrap();
+        let root = dag
+            .put(make_ipld!([mid.clone()]), Codec::DagCBOR)
+            .await
+            .unwrap();
+
+        let peer = MultiaddrWithPeerId::try_from(a.addrs[0].clone()).unwrap();
+
+        let stats = b
+            .sync(peer.clone(), root.clone(), Selector::Depth(1))
+            .await
+            .unwrap();
+        assert_eq!((stats.present, stats.fetched), (0, 2));
+        assert!(b.repo.get_block_now(&leaf).await.unwrap().is_none());
+
+        let stats = b.sync(peer, root, Selector::All).await.unwrap();
+        assert_eq!(
+            stats,
+            SyncStats {
+                present: 2,
+                fetched: 1,
+                fetched_bytes: stats.fetched_bytes,
+                missing: Vec::new(),
+            }
+        );
+        assert!(b.repo.get_block_now(&leaf).await.unwrap().is_some());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn reports_the_blocks_the_peer_does_not_have() {
+        let a = Node::new("a").await;
+        let b = Node::new("b").await;
+
+        let nowhere = Cid::new_v1(Codec::Raw, Sha2_256::digest(b"nowhere"));
+        let root = a
+            .dag()
+            .put(make_ipld!([nowhere.clone()]), Codec::DagCBOR)
+            .await
+            .unwrap();
+
+        let peer = MultiaddrWithPeerId::try_from(a.addrs[0].clone()).unwrap();
+
+        let stats = super::sync(&b, peer, root, Selector::All, Duration::from_millis(500))
+            .await
+            .unwrap();
+
+        assert_eq!((stats.present, stats.fetched), (0, 1));
+        assert_eq!(stats.missing, vec![nowhere]);
+    }
+}
@@ -1,5 +1,10 @@
 ///! "Interface" tests for pin store, maybe more later
-use crate::repo::DataStore;
+use crate::repo::{DataStore, PinMode};
+use cid::Cid;
+use futures::{StreamExt, TryStreamExt};
+use multihash::Sha2_256;
+use proptest::prelude::*;
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tempfile::TempDir;
@@ -203,6 +208,38 @@ macro_rules! pinstore_interface_tests {
                 assert!(both.is_empty(), "{:?}", both);
             }
 
+            #[tokio::test(max_threads = 1)]
+            async fn hash_pinned_with_any_codec() {
+                let repo = DSTestContext::with($factory).await;
+
+                let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+                let empty =
+                    Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+                let other =
+                    Cid::try_from("QmbrFTo4s6H23W6wmoZKQC2vSogGeQ4dYiceSqJddzrKVa").unwrap();
+                let raw = |cid: &Cid| Cid::new_v1(cid::Codec::Raw, cid.hash().to_owned());
+
+                assert!(!repo.is_hash_pinned(&raw(&root)).await.unwrap());
+
+                repo.insert_recursive_pin(
+                    &root,
+                    futures::stream::iter(vec![Ok(empty.clone())]).boxed(),
+                )
+                .await
+                .unwrap();
+
+                assert!(repo.is_hash_pinned(&raw(&root)).await.unwrap());
+                assert!(repo.is_hash_pinned(&raw(&empty)).await.unwrap());
+                assert!(!repo.is_hash_pinned(&raw(&other)).await.unwrap());
+
+                // pinned with a codec which is not among the usual ones
+                let json = Cid::new_v1(cid::Codec::DagJSON, other.hash().to_owned());
+                repo.insert_direct_pin(&json).await.unwrap();
+
+                assert!(repo.is_hash_pinned(&raw(&other)).await.unwrap());
+                assert!(repo.is_hash_pinned(&other).await.unwrap());
+            }
+
             #[tokio::test(max_threads = 1)]
             async fn indirect_can_be_pinned_directly() {
                 let repo = DSTestContext::with($factory).await;
@@ -314,3 +351,116 @@ macro_rules! pinstore_interface_tests {
         }
     };
 }
+
+/// The number of distinct Cids the [`PinOp`]s refer to; kept small so that the operations often
+/// hit the same pins.
+const CIDS: usize = 8;
+
+/// The Cid at the `index` of the small pool of Cids used by the [`PinOp`]s.
+fn cid(index: usize) -> Cid {
+    Cid::new_v0(Sha2_256::digest(&[(index % CIDS) as u8])).unwrap()
+}
+
+/// The fixed references of the Cid at the `index` when pinned recursively, so that the removals
+/// of the recursive pins name the same references as the insertions.
+fn references(index: usize) -> Vec<Cid> {
+    (1..3).map(|offset| cid(index + offset)).collect()
+}
+
+/// The operations of the stateful property tests, applied in order to a fresh [`DataStore`].
+#[derive(Debug, Clone)]
+pub enum PinOp {
+    InsertDirect(usize),
+    InsertRecursive(usize),
+    RemoveDirect(usize),
+    RemoveRecursive(usize),
+}
+
+/// Generates a single [`PinOp`].
+pub fn pin_op() -> impl Strategy<Value = PinOp> {
+    prop_oneof![
+        (0..CIDS).prop_map(PinOp::InsertDirect),
+        (0..CIDS).prop_map(PinOp::InsertRecursive),
+        (0..CIDS).prop_map(PinOp::RemoveDirect),
+        (0..CIDS).prop_map(PinOp::RemoveRecursive),
+    ]
+}
+
+/// Generates a sequence of up to 32 [`PinOp`]s.
+pub fn pin_ops() -> impl Strategy<Value = Vec<PinOp>> {
+    prop::collection::vec(pin_op(), 0..32)
+}
+
+/// What can be observed of a [`DataStore`] after applying each of the [`PinOp`]s: whether the
+/// operation succeeded, the pinned Cids and the recursively pinned Cids.
+///
+/// The mode of the Cids pinned both directly and indirectly is left out, as the backends are
+/// allowed to report either.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Observed {
+    succeeded: bool,
+    pinned: BTreeSet<String>,
+    recursive: BTreeSet<String>,
+}
+
+/// Applies the `ops` to the `datastore`, returning the observations after each of them.
+pub async fn apply<T: DataStore>(datastore: &T, ops: &[PinOp]) -> Vec<Observed> {
+    let mut observed = Vec::with_capacity(ops.len());
+
+    for op in ops {
+        let result = match *op {
+            PinOp::InsertDirect(i) => datastore.insert_direct_pin(&cid(i)).await,
+            PinOp::InsertRecursive(i) => {
+                let refs = futures::stream::iter(references(i).into_iter().map(Ok)).boxed();
+                datastore.insert_recursive_pin(&cid(i), refs).await
+            }
+            PinOp::RemoveDirect(i) => datastore.remove_direct_pin(&cid(i)).await,
+            PinOp::RemoveRecursive(i) => {
+                let refs = futures::stream::iter(references(i).into_iter().map(Ok)).boxed();
+                datastore.remove_recursive_pin(&cid(i), refs).await
+            }
+        };
+
+        observed.push(Observed {
+            succeeded: result.is_ok(),
+            pinned: listed(datastore, None).await,
+            recursive: listed(datastore, Some(PinMode::Recursive)).await,
+        });
+    }
+
+    observed
+}
+
+async fn listed<T: DataStore>(datastore: &T, mode: Option<PinMode>) -> BTreeSet<String> {
+    datastore
+        .list(mode)
+        .await
+        .map_ok(|(cid, _mode)| cid.to_string())
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod backends_agree {
+    use super::{apply, pin_ops, DSTestContext};
+    use crate::repo::{fs::FsDataStore, mem::MemDataStore, DataStore};
+    use proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn mem_and_fs_datastores(ops in pin_ops()) {
+            let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+            let (mem, fs) = rt.block_on(async {
+                let mem = DSTestContext::with(MemDataStore::new).await;
+                let fs = DSTestContext::with(<FsDataStore>::new).await;
+                (apply(&*mem, &ops).await, apply(&*fs, &ops).await)
+            });
+
+            prop_assert_eq!(mem, fs);
+        }
+    }
+}
@@ -3,7 +3,11 @@
 //! Consists of [`FsDataStore`] and [`FsBlockStore`].
 
 use crate::error::Error;
+use crate::runtime::{Runtime, TokioRuntime};
 use async_trait::async_trait;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
 use std::path::PathBuf;
 use std::sync::{atomic::AtomicU64, Arc};
 use tokio::sync::Semaphore;
@@ -17,9 +21,16 @@ mod pinstore;
 mod blocks;
 pub use blocks::FsBlockStore;
 
+/// The io_uring reads and writes of the blocks, used with the `io-uring` feature on linux
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
 /// Path mangling done for pins and blocks
 mod paths;
-use paths::{block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
+use paths::{
+    block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path, BlockLayout, FLATFS_SHARDING,
+    SHARDING_FILE,
+};
 
 /// FsDataStore which uses the filesystem as a lockable key-value store. Maintains a similar to
 /// [`FsBlockStore`] sharded two level storage. Direct have empty files, recursive pins record all of
@@ -27,9 +38,10 @@ use paths::{block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
 ///
 /// When modifying, single lock is used.
 ///
-/// For the [`crate::repo::PinStore`] implementation see `fs/pinstore.rs`.
+/// For the [`crate::repo::PinStore`] implementation see `fs/pinstore.rs`. The blocking file system
+/// work is run with the [`Runtime`] `R`.
 #[derive(Debug)]
-pub struct FsDataStore {
+pub struct FsDataStore<R: Runtime = TokioRuntime> {
     /// The base directory under which we have a sharded directory structure, and the individual
     /// blocks are stored under the shard. See unixfs/examples/cat.rs for read example.
     path: PathBuf,
@@ -43,23 +55,62 @@ pub struct FsDataStore {
 
     /// Not really needed
     written_bytes: AtomicU64,
+
+    runtime: PhantomData<R>,
+}
+
+impl<R: Runtime> FsDataStore<R> {
+    /// The directory of the column, next to the directory of the pins.
+    fn column_path(&self, col: Column) -> PathBuf {
+        self.path.with_file_name(match col {
+            Column::Ipns => "ipns",
+            Column::Keystore => "keystore",
+        })
+    }
+
+    /// The file of the key in the column. The keys can be binary, so the file names are the keys
+    /// encoded in lowercase base32 multibase.
+    fn value_path(&self, col: Column, key: &[u8]) -> PathBuf {
+        let mut path = self.column_path(col);
+        path.push(multibase::encode(multibase::Base::Base32Lower, key));
+        path
+    }
+}
+
+/// Lists the names of the files in the shard directories under `path` on a blocking thread of the
+/// runtime, skipping the files directly under `path`.
+async fn list_sharded<R: Runtime>(path: PathBuf) -> std::io::Result<Vec<OsString>> {
+    R::spawn_blocking(move || {
+        let mut names = Vec::new();
+        for shard in std::fs::read_dir(path)? {
+            let shard = shard?;
+            if shard.file_type()?.is_dir() {
+                for entry in std::fs::read_dir(shard.path())? {
+                    names.push(entry?.file_name());
+                }
+            }
+        }
+        Ok(names)
+    })
+    .await
 }
 
-/// The column operations are all unimplemented pending at least downscoping of the
-/// DataStore trait itself.
+/// The values of the columns are stored in a file per key, in a directory per column.
 #[async_trait]
-impl DataStore for FsDataStore {
+impl<R: Runtime> DataStore for FsDataStore<R> {
     fn new(mut root: PathBuf) -> Self {
         root.push("pins");
         FsDataStore {
             path: root,
             lock: Arc::new(Semaphore::new(1)),
             written_bytes: Default::default(),
+            runtime: PhantomData,
         }
     }
 
     async fn init(&self) -> Result<(), Error> {
-        tokio::fs::create_dir_all(&self.path).await?;
+        let path = self.path.clone();
+        R::spawn_blocking(move || std::fs::create_dir_all(path)).await?;
         Ok(())
     }
 
@@ -67,20 +118,96 @@ impl DataStore for FsDataStore {
         Ok(())
     }
 
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        let path = self.value_path(col, key);
+        match R::spawn_blocking(move || std::fs::metadata(path)).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let path = self.value_path(col, key);
+        match R::spawn_blocking(move || std::fs::read(path)).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let dir = self.column_path(col);
+        let path = self.value_path(col, key);
+        let value = value.to_vec();
+
+        let _permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
+
+        R::spawn_blocking(move || -> std::io::Result<()> {
+            let temp = path.with_extension("tmp");
+
+            std::fs::create_dir_all(dir)?;
+
+            if let Column::Keystore = col {
+                // the file is created readable only by the owner before the key is written into it
+                match std::fs::remove_file(&temp) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+
+                let mut options = std::fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                options.open(&temp)?;
+            }
+
+            // write through a temporary file so that the readers never see a partially written
+            // value
+            std::fs::write(&temp, value)?;
+            std::fs::rename(&temp, &path)
+        })
+        .await?;
+
+        Ok(())
     }
 
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let path = self.value_path(col, key);
+
+        let _permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
+
+        match R::spawn_blocking(move || std::fs::remove_file(path)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, col: Column) -> Result<Vec<Vec<u8>>, Error> {
+        let path = self.column_path(col);
+
+        let names = match R::spawn_blocking(move || {
+            std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<std::io::Result<Vec<_>>>()
+        })
+        .await
+        {
+            Ok(names) => names,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(names
+            .into_iter()
+            .filter_map(|name| name.into_string().ok())
+            // the temporary files of the interrupted writes are skipped along with anything else
+            // which isn't a multibase encoded key
+            .filter_map(|name| multibase::decode(&name).ok())
+            .map(|(_, key)| key)
+            .collect())
     }
 
     async fn wipe(&self) {
@@ -89,4 +216,73 @@ impl DataStore for FsDataStore {
 }
 
 #[cfg(test)]
-crate::pinstore_interface_tests!(common_tests, crate::repo::fs::FsDataStore::new);
+crate::pinstore_interface_tests!(common_tests, <crate::repo::fs::FsDataStore>::new);
+
+#[cfg(test)]
+mod tests {
+    use super::FsDataStore;
+    use crate::repo::{Column, DataStore};
+    use std::env::temp_dir;
+
+    #[tokio::test(max_threads = 1)]
+    async fn test_fs_datastore_columns() {
+        let mut tmp = temp_dir();
+        tmp.push("datastore_columns");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = <FsDataStore>::new(tmp.clone());
+        let col = Column::Ipns;
+        let key = [0, 1, 2, 3];
+        let value = [5, 6, 7, 8];
+
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        assert_eq!(store.contains(col, &key).await.unwrap(), false);
+        assert_eq!(store.get(col, &key).await.unwrap(), None);
+        assert!(store.list(col).await.unwrap().is_empty());
+        store.remove(col, &key).await.unwrap();
+
+        store.put(col, &key, &value).await.unwrap();
+        assert_eq!(store.contains(col, &key).await.unwrap(), true);
+        assert_eq!(store.get(col, &key).await.unwrap(), Some(value.to_vec()));
+        assert_eq!(store.list(col).await.unwrap(), vec![key.to_vec()]);
+        assert!(store.list(Column::Keystore).await.unwrap().is_empty());
+
+        // the values persist over reopening the store
+        let store = <FsDataStore>::new(tmp.clone());
+        store.open().await.unwrap();
+        assert_eq!(store.get(col, &key).await.unwrap(), Some(value.to_vec()));
+
+        store.remove(col, &key).await.unwrap();
+        assert_eq!(store.contains(col, &key).await.unwrap(), false);
+        assert_eq!(store.get(col, &key).await.unwrap(), None);
+        assert!(store.list(col).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(max_threads = 1)]
+    async fn keystore_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut tmp = temp_dir();
+        tmp.push("datastore_keystore_mode");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = <FsDataStore>::new(tmp.clone());
+        store.init().await.unwrap();
+
+        for _ in 0..2 {
+            store
+                .put(Column::Keystore, b"key", b"secret")
+                .await
+                .unwrap();
+
+            let path = store.value_path(Column::Keystore, b"key");
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
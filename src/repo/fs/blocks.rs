@@ -1,28 +1,40 @@
-use super::{block_path, filestem_to_block_cid};
-use super::{BlockRm, BlockRmError, RepoCid};
+use super::{block_path, filestem_to_block_cid, BlockLayout, FLATFS_SHARDING, SHARDING_FILE};
+use super::{list_sharded, BlockRm, BlockRmError, RepoCid};
 use crate::error::Error;
-use crate::repo::{BlockPut, BlockStore};
+use crate::repo::{BlockData, BlockPut, BlockStore};
+use crate::runtime::{Runtime, TokioRuntime};
 use crate::Block;
 use async_trait::async_trait;
+use bytes::Bytes;
 use cid::Cid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::io::Read;
-use std::path::PathBuf;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
-use tokio::fs;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::Span;
 use tracing_futures::Instrument;
 
 type ArcMutexMap<A, B> = Arc<Mutex<HashMap<A, B>>>;
 
+/// The size from which [`FsBlockStore::get_data`] memory-maps the blocks instead of reading them,
+/// which the default 256 KiB unixfs chunks exceed.
+const MMAP_THRESHOLD: u64 = 256 * 1024;
+
 /// File system backed block store.
 ///
-/// For information on path mangling, please see `block_path` and `filestem_to_block_cid`.
+/// The new stores use the go-ipfs flatfs layout, so that a go-ipfs `blocks` directory can be opened
+/// as well. The stores created by the earlier versions keep their layout, which is told apart by
+/// the missing `SHARDING` file. For information on path mangling, please see `block_path` and
+/// `filestem_to_block_cid`.
+///
+/// The writers and the blocking reads are run with the [`Runtime`] `R`.
 #[derive(Debug)]
-pub struct FsBlockStore {
+pub struct FsBlockStore<R: Runtime = TokioRuntime> {
     /// The base directory under which we have a sharded directory structure, and the individual
     /// blocks are stored under the shard. See unixfs/examples/cat.rs for read example.
     path: PathBuf,
@@ -35,6 +47,36 @@ pub struct FsBlockStore {
     /// Initially used to demonstrate a bug, not really needed anymore. Could be used as a basis
     /// for periodic synching to disk to know much space we have used.
     written_bytes: AtomicU64,
+
+    /// The queue of the writers, which are started on the first `put`.
+    writer: Mutex<Option<mpsc::Sender<PendingWrite>>>,
+
+    /// The layout found by `init` or `open`.
+    layout: Mutex<BlockLayout>,
+
+    runtime: PhantomData<R>,
+}
+
+/// The number of writers, each writing a batch of the queued blocks at a time.
+const WRITERS: usize = 4;
+
+/// The most blocks written in a single batch.
+const MAX_BATCH: usize = 64;
+
+/// The most blocks queued for the writers, enough for a batch for each of them. When the writers
+/// fall behind, `put` waits for room in the queue.
+const QUEUE_CAPACITY: usize = WRITERS * MAX_BATCH;
+
+/// The outcome of writing a block: the outer error when the block file could not be created, as
+/// when it exists already, and the inner error when writing the created file failed.
+type WriteResult = Result<Result<usize, std::io::Error>, std::io::Error>;
+
+/// A block queued for the writers.
+struct PendingWrite {
+    target_path: PathBuf,
+    data: Bytes,
+    span: Span,
+    done: oneshot::Sender<WriteResult>,
 }
 
 /// A helper used to remove our key from `FsBlockStore::writes`. It is quite inefficient, some
@@ -72,7 +114,25 @@ enum WriteCompletion {
     NotOngoing,
 }
 
-impl FsBlockStore {
+impl<R: Runtime> FsBlockStore<R> {
+    /// Returns the queue of the writers, starting the writers on the first call.
+    fn writer(&self) -> mpsc::Sender<PendingWrite> {
+        let mut writer = self.writer.lock().expect("cannot support poisoned");
+
+        if let Some(tx) = writer.as_ref() {
+            return tx.clone();
+        }
+
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        for _ in 0..WRITERS {
+            R::spawn(Box::pin(write_batches::<R>(Arc::clone(&rx))));
+        }
+
+        *writer = Some(tx.clone());
+        tx
+    }
+
     /// Returns the same Cid in either case. Ok variant is returned in case it is suspected the
     /// write completed successfully or there was never any write ongoing. Err variant is returned
     /// if it's known that the write failed.
@@ -102,34 +162,83 @@ impl FsBlockStore {
     }
 }
 
+impl<R: Runtime> FsBlockStore<R> {
+    fn layout(&self) -> BlockLayout {
+        *self.layout.lock().unwrap()
+    }
+
+    fn block_path(&self, cid: &Cid) -> PathBuf {
+        block_path(self.path.clone(), cid, self.layout())
+    }
+
+    /// Reads the layout from the `SHARDING` file, which is written into an empty directory. A
+    /// directory with blocks but no `SHARDING` file has the legacy layout.
+    async fn detect_layout(&self) -> Result<(), Error> {
+        let sharding = self.path.join(SHARDING_FILE);
+
+        let path = self.path.clone();
+        let layout = R::spawn_blocking(move || -> Result<_, Error> {
+            match std::fs::read_to_string(&sharding) {
+                Ok(s) if s.trim_end() == FLATFS_SHARDING => Ok(BlockLayout::FlatFs),
+                Ok(s) => Err(anyhow::anyhow!(
+                    "unsupported sharding {:?} in {}",
+                    s.trim_end(),
+                    sharding.display()
+                )),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    let mut entries = std::fs::read_dir(&path)?;
+                    if entries.next().transpose()?.is_some() {
+                        Ok(BlockLayout::Legacy)
+                    } else {
+                        std::fs::write(&sharding, format!("{}\n", FLATFS_SHARDING))?;
+                        Ok(BlockLayout::FlatFs)
+                    }
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await?;
+
+        trace!(path = %self.path.display(), layout = ?layout, "opened blocks");
+        *self.layout.lock().unwrap() = layout;
+        Ok(())
+    }
+}
+
 #[async_trait]
-impl BlockStore for FsBlockStore {
+impl<R: Runtime> BlockStore for FsBlockStore<R> {
     fn new(path: PathBuf) -> Self {
         FsBlockStore {
             path,
             //cids: Default::default(),
             writes: Arc::new(Mutex::new(HashMap::with_capacity(8))),
             written_bytes: Default::default(),
+            writer: Default::default(),
+            layout: Mutex::new(BlockLayout::FlatFs),
+            runtime: PhantomData,
         }
     }
 
     async fn init(&self) -> Result<(), Error> {
-        fs::create_dir_all(self.path.clone()).await?;
-        Ok(())
+        let path = self.path.clone();
+        R::spawn_blocking(move || std::fs::create_dir_all(path)).await?;
+        self.detect_layout().await
     }
 
+    /// Opening does not scan the blocks, so the time taken does not grow with the size of the
+    /// repo. The blocks are only walked when they are listed with `BlockStore::list`.
     async fn open(&self) -> Result<(), Error> {
         // TODO: we probably want to cache the space usage?
-        Ok(())
+        self.detect_layout().await
     }
 
     async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
-        let path = block_path(self.path.clone(), cid);
+        let path = self.block_path(cid);
 
         // why doesn't this synchronize with the rest? Not sure if there is any use for this method
         // actually. When does it matter if a block exists, except for testing.
 
-        let metadata = match fs::metadata(path).await {
+        let metadata = match R::spawn_blocking(move || std::fs::metadata(path)).await {
             Ok(m) => m,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
             Err(e) => return Err(e.into()),
@@ -146,13 +255,13 @@ impl BlockStore for FsBlockStore {
                 return Ok(None);
             }
 
-            let path = block_path(self.path.clone(), cid);
+            let path = self.block_path(cid);
 
             let cid = cid.to_owned();
 
             // probably best to do everything in the blocking thread if we are to issue multiple
             // syscalls
-            tokio::task::spawn_blocking(move || {
+            R::spawn_blocking(move || {
                 let mut file = match std::fs::File::open(path) {
                     Ok(file) => file,
                     Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
@@ -163,12 +272,49 @@ impl BlockStore for FsBlockStore {
 
                 let len = file.metadata()?.len();
 
-                let mut data = Vec::with_capacity(len as usize);
-                file.read_to_end(&mut data)?;
-                let block = Block::new(data.into_boxed_slice(), cid);
+                let data = read_file(&mut file, len)?;
+                let block = Block::new(data, cid);
                 Ok(Some(block))
             })
-            .await?
+            .await
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn get_data(&self, cid: &Cid) -> Result<Option<BlockData>, Error> {
+        let span = tracing::trace_span!("get block data", cid = %cid);
+
+        async move {
+            if let WriteCompletion::KnownBad = self.write_completion(cid).await {
+                return Ok(None);
+            }
+
+            let path = self.block_path(cid);
+
+            R::spawn_blocking(move || {
+                let mut file = match std::fs::File::open(path) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                };
+
+                let len = file.metadata()?.len();
+
+                if len >= MMAP_THRESHOLD {
+                    // safety: the blocks are written to a temporary file which is then renamed, so
+                    // the mapped file is never modified; removing the block only unlinks it, which
+                    // keeps the mapping valid.
+                    let map = unsafe { memmap::Mmap::map(&file)? };
+                    return Ok(Some(BlockData::new(map)));
+                }
+
+                let data = read_file(&mut file, len)?;
+                Ok(Some(BlockData::new(data)))
+            })
+            .await
         }
         .instrument(span)
         .await
@@ -179,7 +325,7 @@ impl BlockStore for FsBlockStore {
 
         let span = tracing::trace_span!("put block", cid = %block.cid());
 
-        let target_path = block_path(self.path.clone(), &block.cid());
+        let target_path = self.block_path(&block.cid());
         let cid = block.cid;
         let data = block.data;
 
@@ -212,47 +358,17 @@ impl BlockStore for FsBlockStore {
             // create this in case the winner is dropped while awaiting
             let cleanup = RemoveOnDrop(self.writes.clone(), Some(RepoCid(cid.to_owned())));
 
-            // launch a blocking task for the filesystem mutation.
-            let je = tokio::task::spawn_blocking(move || {
-                // pick winning writer with filesystem and create_new; this error will be the 1st
-                // nested level
-
-                // this is blocking context, use this instead of instrument
-                let _entered = inner_span.enter();
-
-                let sharded = target_path
-                    .parent()
-                    .expect("we already have at least the shard parent");
-
-                // FIXME: missing fsync on directories; for example after winning the race we could
-                // fsync the parent and parent.parent
-                std::fs::create_dir_all(sharded)?;
-
-                let target = std::fs::OpenOptions::new()
-                    .write(true)
-                    .create_new(true)
-                    .open(&target_path)?;
-
-                let temp_path = target_path.with_extension("tmp");
-
-                match write_through_tempfile(target, &target_path, temp_path, &data) {
-                    Ok(()) => {
-                        trace!("successfully wrote the block");
-                        Ok::<_, std::io::Error>(Ok(data.len()))
-                    }
-                    Err(e) => {
-                        match std::fs::remove_file(&target_path) {
-                            Ok(_) => debug!("removed partially written {:?}", target_path),
-                            Err(removal) => warn!(
-                                "failed to remove partially written {:?}: {}",
-                                target_path, removal
-                            ),
-                        }
-                        Ok(Err(e))
-                    }
-                }
-            })
-            .await;
+            // queue the write for the writers, which write the queued blocks in batches
+            let (done, written) = oneshot::channel();
+            let pending = PendingWrite {
+                target_path,
+                data,
+                span: inner_span,
+                done,
+            };
+            // the write is dropped if the writers have stopped, which is seen as the error below
+            let _ = self.writer().send(pending).await;
+            let je = written.await;
 
             // this is quite unfortunate but can't think of a way which would handle cleanup in drop
             // and not waste much effort.
@@ -318,13 +434,9 @@ impl BlockStore for FsBlockStore {
                         Ok((cid.to_owned(), BlockPut::Existed))
                     }
                 }
-                Err(e) if e.is_cancelled() => {
-                    trace!("runtime is shutting down: {}", e);
-                    Err(e.into())
-                }
                 Err(e) => {
-                    // as of writing this, we didn't have panicking inside the task
-                    error!("blocking put task panicked or something else: {}", e);
+                    // the runtime is shutting down, or the writer panicked
+                    trace!("writer stopped before writing the block: {}", e);
                     Err(e.into())
                 }
             }
@@ -334,7 +446,7 @@ impl BlockStore for FsBlockStore {
     }
 
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
-        let path = block_path(self.path.clone(), cid);
+        let path = self.block_path(cid);
 
         let span = trace_span!("remove block", cid = %cid);
 
@@ -342,7 +454,7 @@ impl BlockStore for FsBlockStore {
             WriteCompletion::KnownBad => Ok(Err(BlockRmError::NotFound(cid.to_owned()))),
             completion => {
                 trace!(cid = %cid, completion = ?completion, "removing block after synchronizing");
-                match fs::remove_file(path).await {
+                match R::spawn_blocking(move || std::fs::remove_file(path)).await {
                     // FIXME: not sure if theres any point in taking cid ownership here?
                     Ok(()) => Ok(Ok(BlockRm::Removed(cid.to_owned()))),
                     Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -355,42 +467,20 @@ impl BlockStore for FsBlockStore {
     }
 
     async fn list(&self) -> Result<Vec<Cid>, Error> {
-        use futures::future::{ready, Either};
-        use futures::stream::{empty, TryStreamExt};
-
         let span = tracing::trace_span!("listing blocks");
 
-        async move {
-            let stream = fs::read_dir(self.path.clone()).await?;
-
-            // FIXME: written as a stream to make the Vec be BoxStream<'static, Cid>
-            let vec = stream
-                .and_then(|d| async move {
-                    // map over the shard directories
-                    Ok(if d.file_type().await?.is_dir() {
-                        Either::Left(fs::read_dir(d.path()).await?)
-                    } else {
-                        Either::Right(empty())
-                    })
-                })
-                // flatten each
-                .try_flatten()
-                // convert the paths ending in ".data" into cid
-                .try_filter_map(|d| {
-                    let name = d.file_name();
-                    let path: &std::path::Path = name.as_ref();
-
-                    ready(if path.extension() != Some("data".as_ref()) {
-                        Ok(None)
-                    } else {
-                        let maybe_cid = filestem_to_block_cid(path.file_stem());
-                        Ok(maybe_cid)
-                    })
-                })
-                .try_collect::<Vec<_>>()
-                .await?;
+        let layout = self.layout();
 
-            Ok(vec)
+        async move {
+            let names = list_sharded::<R>(self.path.clone()).await?;
+
+            // convert the paths ending in ".data" into cid
+            Ok(names
+                .iter()
+                .map(Path::new)
+                .filter(|path| path.extension() == Some("data".as_ref()))
+                .filter_map(|path| filestem_to_block_cid(path.file_stem(), layout))
+                .collect())
         }
         .instrument(span)
         .await
@@ -401,34 +491,174 @@ impl BlockStore for FsBlockStore {
     }
 }
 
+/// Takes the queued blocks in batches of up to [`MAX_BATCH`] until the store is dropped. While the
+/// writers are busy, up to [`QUEUE_CAPACITY`] blocks queue up to be written in the next batches.
+async fn write_batches<R: Runtime>(rx: Arc<tokio::sync::Mutex<mpsc::Receiver<PendingWrite>>>) {
+    loop {
+        let batch = {
+            let mut rx = rx.lock().await;
+
+            let mut batch = match rx.recv().await {
+                Some(first) => vec![first],
+                None => return,
+            };
+
+            while batch.len() < MAX_BATCH {
+                match rx.try_recv() {
+                    Ok(next) => batch.push(next),
+                    Err(_) => break,
+                }
+            }
+
+            batch
+        };
+
+        // the outcomes are sent from the blocking task; a panic there drops the senders
+        let _ = R::spawn_blocking(move || {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| write_batch(batch)))
+        })
+        .await;
+    }
+}
+
+/// Writes the blocks of the batch, then syncs each of the directories the blocks were written to
+/// once before sending out the outcomes.
+fn write_batch(batch: Vec<PendingWrite>) {
+    trace!(blocks = batch.len(), "writing a batch");
+
+    let mut directories = HashSet::new();
+    let mut outcomes = Vec::with_capacity(batch.len());
+
+    for PendingWrite {
+        target_path,
+        data,
+        span,
+        done,
+    } in batch
+    {
+        let _entered = span.enter();
+
+        let res = write_block(&target_path, &data);
+
+        if let Ok(Ok(_)) = res {
+            if let Some(dir) = target_path.parent() {
+                directories.insert(dir.to_owned());
+            }
+        }
+
+        outcomes.push((done, res));
+    }
+
+    for dir in directories {
+        if let Err(e) = sync_directory(&dir) {
+            warn!("failed to sync the directory {:?}: {}", dir, e);
+        }
+    }
+
+    for (done, res) in outcomes {
+        // the put may have been dropped
+        let _ = done.send(res);
+    }
+}
+
+fn write_block(target_path: &Path, data: &[u8]) -> WriteResult {
+    // pick winning writer with filesystem and create_new; this error will be the 1st nested level
+
+    let sharded = target_path
+        .parent()
+        .expect("we already have at least the shard parent");
+
+    std::fs::create_dir_all(sharded)?;
+
+    let target = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target_path)?;
+
+    let temp_path = target_path.with_extension("tmp");
+
+    match write_through_tempfile(target, &target_path, temp_path, data) {
+        Ok(()) => {
+            trace!("successfully wrote the block");
+            Ok(Ok(data.len()))
+        }
+        Err(e) => {
+            match std::fs::remove_file(&target_path) {
+                Ok(_) => debug!("removed partially written {:?}", target_path),
+                Err(removal) => warn!(
+                    "failed to remove partially written {:?}: {}",
+                    target_path, removal
+                ),
+            }
+            Ok(Err(e))
+        }
+    }
+}
+
+/// Syncs the directory so that the renames into it are persisted.
+#[cfg(unix)]
+fn sync_directory(dir: &Path) -> Result<(), std::io::Error> {
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// The directories cannot be opened as files on windows.
+#[cfg(not(unix))]
+fn sync_directory(_dir: &Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+/// Reads the whole file through io_uring when it is enabled and available.
+fn read_file(file: &mut std::fs::File, len: u64) -> Result<Vec<u8>, std::io::Error> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        if let Some(res) = super::uring::read(file, len) {
+            return res;
+        }
+    }
+
+    let mut data = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Writes the data and syncs the file, with a single submission when io_uring is enabled and
+/// available.
+fn write_and_sync(file: &mut std::fs::File, data: &[u8]) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        if let Some(res) = super::uring::write_and_sync(file, data) {
+            return res;
+        }
+    }
+
+    file.write_all(data)?;
+    file.flush()?;
+
+    // safe default
+    file.sync_all()
+}
+
 fn write_through_tempfile(
     target: std::fs::File,
     target_path: impl AsRef<std::path::Path>,
     temp_path: impl AsRef<std::path::Path>,
     data: &[u8],
 ) -> Result<(), std::io::Error> {
-    use std::io::Write;
-
     let mut temp = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
         .open(&temp_path)?;
 
-    temp.write_all(&*data)?;
-    temp.flush()?;
-
-    // safe default
-    temp.sync_all()?;
+    write_and_sync(&mut temp, data)?;
 
     drop(temp);
     drop(target);
 
-    std::fs::rename(temp_path, target_path)?;
-
-    // FIXME: there should be a directory fsync here as well
-
-    Ok(())
+    // the directory is synced once for the batch in write_batch
+    std::fs::rename(temp_path, target_path)
 }
 
 #[cfg(test)]
@@ -447,7 +677,7 @@ mod tests {
         let mut tmp = temp_dir();
         tmp.push("blockstore1");
         std::fs::remove_dir_all(tmp.clone()).ok();
-        let store = FsBlockStore::new(tmp.clone());
+        let store = <FsBlockStore>::new(tmp.clone());
 
         let data = b"1".to_vec().into_boxed_slice();
         let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
@@ -480,6 +710,37 @@ mod tests {
         std::fs::remove_dir_all(tmp).ok();
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn large_blocks_are_mapped() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_mmap");
+        std::fs::remove_dir_all(&tmp).ok();
+        let store = <FsBlockStore>::new(tmp.clone());
+        store.init().await.unwrap();
+
+        for len in &[1024, MMAP_THRESHOLD as usize + 1] {
+            let data = (0..*len)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>()
+                .into_boxed_slice();
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            store
+                .put(Block::new(data.clone(), cid.clone()))
+                .await
+                .unwrap();
+
+            let read = store.get_data(&cid).await.unwrap().unwrap();
+            assert_eq!(&*read, &*data);
+
+            // the mapping stays valid after the removal
+            store.remove(&cid).await.unwrap().unwrap();
+            assert_eq!(&*read, &*data);
+            assert!(store.get_data(&cid).await.unwrap().is_none());
+        }
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_fs_blockstore_open() {
         let mut tmp = temp_dir();
@@ -490,14 +751,14 @@ mod tests {
         let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
         let block = Block::new(data, cid);
 
-        let block_store = FsBlockStore::new(tmp.clone());
+        let block_store = <FsBlockStore>::new(tmp.clone());
         block_store.init().await.unwrap();
         block_store.open().await.unwrap();
 
         assert!(!block_store.contains(block.cid()).await.unwrap());
         block_store.put(block.clone()).await.unwrap();
 
-        let block_store = FsBlockStore::new(tmp.clone());
+        let block_store = <FsBlockStore>::new(tmp.clone());
         block_store.open().await.unwrap();
         assert!(block_store.contains(block.cid()).await.unwrap());
         assert_eq!(block_store.get(block.cid()).await.unwrap().unwrap(), block);
@@ -505,13 +766,110 @@ mod tests {
         std::fs::remove_dir_all(&tmp).ok();
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn new_store_uses_flatfs() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_flatfs");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let data = b"1".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+
+        let block_store = <FsBlockStore>::new(tmp.clone());
+        block_store.init().await.unwrap();
+        block_store
+            .put(Block::new(data, cid.clone()))
+            .await
+            .unwrap();
+
+        let sharding = std::fs::read_to_string(tmp.join(SHARDING_FILE)).unwrap();
+        assert_eq!(sharding, "/repo/flatfs/shard/v1/next-to-last/2\n");
+
+        // named as go-ipfs names the blocks
+        let key = multibase::Base::Base32Upper.encode(cid.hash().as_bytes());
+        let shard = &key[key.len() - 3..key.len() - 1];
+        assert!(tmp.join(shard).join(format!("{}.data", key)).is_file());
+
+        assert_eq!(block_store.list().await.unwrap(), vec![cid]);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn legacy_store_keeps_its_layout() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_legacy");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let data = b"1".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+
+        let legacy = block_path(tmp.clone(), &cid, BlockLayout::Legacy);
+        std::fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        std::fs::write(&legacy, &data).unwrap();
+
+        let block_store = <FsBlockStore>::new(tmp.clone());
+        block_store.open().await.unwrap();
+
+        assert!(!tmp.join(SHARDING_FILE).exists());
+        assert_eq!(block_store.get(&cid).await.unwrap().unwrap().data(), &*data);
+        assert_eq!(block_store.list().await.unwrap(), vec![cid]);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn more_puts_than_the_queue_holds() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_batches");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let store = Arc::new(<FsBlockStore>::new(tmp.clone()));
+        store.init().await.unwrap();
+
+        // more than fit in the queue, so that some of the puts wait for room in it
+        let count = QUEUE_CAPACITY + 1;
+
+        let blocks = (0..count as u32)
+            .map(|i| {
+                let data = i.to_be_bytes().to_vec().into_boxed_slice();
+                let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+                Block::new(data, cid)
+            })
+            .collect::<Vec<_>>();
+
+        let join_handles = blocks
+            .iter()
+            .cloned()
+            .map(|block| {
+                let store = Arc::clone(&store);
+                tokio::spawn(async move { store.put(block).await })
+            })
+            .collect::<Vec<_>>();
+
+        for jh in join_handles {
+            let res = jh.await.expect("join error");
+            assert_eq!(res.unwrap().1, BlockPut::NewBlock);
+        }
+
+        assert_eq!(store.written_bytes.load(Ordering::SeqCst), 4 * count as u64);
+
+        let store = <FsBlockStore>::new(tmp.clone());
+        store.open().await.unwrap();
+        for block in blocks {
+            assert_eq!(store.get(block.cid()).await.unwrap().unwrap(), block);
+        }
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_fs_blockstore_list() {
         let mut tmp = temp_dir();
         tmp.push("blockstore_list");
         std::fs::remove_dir_all(&tmp).ok();
 
-        let block_store = FsBlockStore::new(tmp.clone());
+        let block_store = <FsBlockStore>::new(tmp.clone());
         block_store.init().await.unwrap();
         block_store.open().await.unwrap();
 
@@ -536,7 +894,7 @@ mod tests {
         tmp.push("race_to_insert_new");
         std::fs::remove_dir_all(&tmp).ok();
 
-        let single = FsBlockStore::new(tmp.clone());
+        let single = <FsBlockStore>::new(tmp.clone());
         single.init().await.unwrap();
 
         let single = Arc::new(single);
@@ -546,7 +904,7 @@ mod tests {
 
         let block = Block {
             cid,
-            data: data.into(),
+            data: data.to_vec().into(),
         };
 
         let count = 10;
@@ -567,7 +925,7 @@ mod tests {
         tmp.push("race_to_insert_existing");
         std::fs::remove_dir_all(&tmp).ok();
 
-        let single = FsBlockStore::new(tmp.clone());
+        let single = <FsBlockStore>::new(tmp.clone());
         single.init().await.unwrap();
 
         let single = Arc::new(single);
@@ -577,7 +935,7 @@ mod tests {
 
         let block = Block {
             cid,
-            data: data.into(),
+            data: data.to_vec().into(),
         };
 
         single.put(block.clone()).await.unwrap();
@@ -620,13 +978,11 @@ mod tests {
         let mut existing = 0usize;
 
         for jh in join_handles {
-            let res = jh.await;
+            let res = jh.await.expect("join error");
 
-            match res {
-                Ok(Ok((_, BlockPut::NewBlock))) => writes += 1,
-                Ok(Ok((_, BlockPut::Existed))) => existing += 1,
-                Ok(Err(e)) => println!("joinhandle err: {}", e),
-                _ => unreachable!("join error"),
+            match res.expect("put failed") {
+                (_, BlockPut::NewBlock) => writes += 1,
+                (_, BlockPut::Existed) => existing += 1,
             }
         }
 
@@ -640,7 +996,7 @@ mod tests {
         tmp.push("remove");
         std::fs::remove_dir_all(&tmp).ok();
 
-        let single = FsBlockStore::new(tmp.clone());
+        let single = <FsBlockStore>::new(tmp.clone());
 
         single.init().await.unwrap();
 
@@ -649,7 +1005,7 @@ mod tests {
 
         let block = Block {
             cid: cid.clone(),
-            data: data.into(),
+            data: data.to_vec().into(),
         };
 
         assert_eq!(single.list().await.unwrap().len(), 0);
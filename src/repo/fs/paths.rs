@@ -1,14 +1,33 @@
-use cid::Cid;
+use cid::{Cid, Codec};
 use core::convert::TryFrom;
+use multihash::Multihash;
 use std::path::PathBuf;
 
-pub fn block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
-    // this is ascii always, and wasteful until we can drop the cid for multihash ... which is
-    // probably soon, we just need turn /refs/local to use /pin/list.
-    let key = if cid.version() == cid::Version::V1 {
-        cid.to_string()
-    } else {
-        Cid::new_v1(cid.codec(), cid.hash().to_owned()).to_string()
+/// The name of the file recording the sharding of a flatfs directory.
+pub const SHARDING_FILE: &str = "SHARDING";
+
+/// The sharding of go-ipfs flatfs, which is the only one supported.
+pub const FLATFS_SHARDING: &str = "/repo/flatfs/shard/v1/next-to-last/2";
+
+/// The naming of the block files under the blocks directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockLayout {
+    /// The go-ipfs flatfs layout, where the blocks are named by the uppercase base32 of their
+    /// multihash, and the directory has a `SHARDING` file.
+    FlatFs,
+    /// The layout of the earlier versions, where the blocks are named by their CIDv1 and there is
+    /// no `SHARDING` file.
+    Legacy,
+}
+
+pub fn block_path(mut base: PathBuf, cid: &Cid, layout: BlockLayout) -> PathBuf {
+    let key = match layout {
+        // the codec is not part of the name, so the blocks with the same multihash share a file
+        BlockLayout::FlatFs => multibase::Base::Base32Upper.encode(cid.hash().as_bytes()),
+        // this is ascii always, and wasteful until we can drop the cid for multihash ... which is
+        // probably soon, we just need turn /refs/local to use /pin/list.
+        BlockLayout::Legacy if cid.version() == cid::Version::V1 => cid.to_string(),
+        BlockLayout::Legacy => Cid::new_v1(cid.codec(), cid.hash().to_owned()).to_string(),
     };
 
     shard(&mut base, &key);
@@ -17,8 +36,20 @@ pub fn block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
     base
 }
 
-pub fn filestem_to_block_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
+/// Decodes the file stem produced by [`block_path`], ignoring errors. The blocks of the
+/// [`BlockLayout::FlatFs`] are listed as raw CIDv1s, as go-ipfs does, since the names do not
+/// include the codec.
+pub fn filestem_to_block_cid(
+    file_stem: Option<&std::ffi::OsStr>,
+    layout: BlockLayout,
+) -> Option<Cid> {
     file_stem.and_then(|stem| stem.to_str()).and_then(|s| {
+        if layout == BlockLayout::FlatFs {
+            let bytes = multibase::Base::Base32Upper.decode(s).ok()?;
+            let hash = Multihash::from_bytes(bytes).ok()?;
+            return Some(Cid::new_v1(Codec::Raw, hash));
+        }
+
         // this isn't an interchangeable way to store esp. cidv0
         let cid = Cid::try_from(s);
 
@@ -77,8 +108,8 @@ fn shard(path: &mut PathBuf, key: &str) {
 #[cfg(test)]
 mod tests {
 
-    use super::shard;
-    use cid::Cid;
+    use super::{shard, BlockLayout};
+    use cid::{Cid, Codec};
     use std::convert::TryFrom;
     use std::path::{Path, PathBuf};
 
@@ -113,7 +144,7 @@ mod tests {
     }
 
     #[test]
-    fn cid_to_block_path() {
+    fn cid_to_legacy_block_path() {
         // block_path canonicalizes the path; not sure if there's any point nor does it really
         // match how the locking is done (through the multihash) but ... It's close. not spending
         // time fixing this right now but hopefully moving over to storing just multihashes soon.
@@ -125,8 +156,8 @@ mod tests {
 
         let base = PathBuf::from("another_root");
 
-        let cid_v0_path = super::block_path(base.clone(), &cid_v0);
-        let cid_v1_path = super::block_path(base, &cid_v1);
+        let cid_v0_path = super::block_path(base.clone(), &cid_v0, BlockLayout::Legacy);
+        let cid_v1_path = super::block_path(base, &cid_v1, BlockLayout::Legacy);
 
         assert_eq!(cid_v0_path, cid_v1_path);
 
@@ -139,7 +170,27 @@ mod tests {
     }
 
     #[test]
-    fn block_path_to_cid() {
+    fn cid_to_flatfs_block_path() {
+        let cid_v0 = "QmTEn8ypAkbJXZUXCRHBorwF2jM8uTUW9yRLzrcQouSoD4";
+        let cid_v0 = Cid::try_from(cid_v0).unwrap();
+        let cid_v1 = "bafybeicizfmyaovkw4pnrwpa4kcirzaveabyw4vsixt45mrrhr2xm2d5lm";
+        let cid_v1 = Cid::try_from(cid_v1).unwrap();
+
+        let base = PathBuf::from("go_root");
+
+        let cid_v0_path = super::block_path(base.clone(), &cid_v0, BlockLayout::FlatFs);
+        let cid_v1_path = super::block_path(base, &cid_v1, BlockLayout::FlatFs);
+
+        assert_eq!(cid_v0_path, cid_v1_path);
+
+        // as written by go-ipfs
+        let expected = "go_root/2W/CIQERSKZQA5KVNY63DM6BYUERDSBKIADRNZLERPHZ2ZDCPDVOZUH2WY.data";
+
+        assert_eq!(cid_v1_path, Path::new(expected));
+    }
+
+    #[test]
+    fn legacy_block_path_to_cid() {
         let cid_v1 = "bafybeicizfmyaovkw4pnrwpa4kcirzaveabyw4vsixt45mrrhr2xm2d5lm";
         let cid_v1 = Cid::try_from(cid_v1).unwrap();
 
@@ -147,21 +198,43 @@ mod tests {
             "another_root/5l/bafybeicizfmyaovkw4pnrwpa4kcirzaveabyw4vsixt45mrrhr2xm2d5lm.data";
         let path = Path::new(path);
 
-        let parsed = super::filestem_to_block_cid(path.file_stem());
+        let parsed = super::filestem_to_block_cid(path.file_stem(), BlockLayout::Legacy);
 
         assert_eq!(parsed, Some(cid_v1));
     }
 
+    #[test]
+    fn flatfs_block_path_to_cid() {
+        let cid_v0 = "QmTEn8ypAkbJXZUXCRHBorwF2jM8uTUW9yRLzrcQouSoD4";
+        let cid_v0 = Cid::try_from(cid_v0).unwrap();
+
+        let path = "go_root/2W/CIQERSKZQA5KVNY63DM6BYUERDSBKIADRNZLERPHZ2ZDCPDVOZUH2WY.data";
+        let path = Path::new(path);
+
+        let parsed = super::filestem_to_block_cid(path.file_stem(), BlockLayout::FlatFs).unwrap();
+
+        assert_eq!(parsed.codec(), Codec::Raw);
+        assert_eq!(parsed.hash(), cid_v0.hash());
+    }
+
     #[test]
     fn invalid_block_path_is_silently_ignored() {
         let block_path = Path::new("another_root/ba/foobar.data");
-        assert_eq!(super::filestem_to_block_cid(block_path.file_stem()), None);
+        for layout in &[BlockLayout::Legacy, BlockLayout::FlatFs] {
+            assert_eq!(
+                super::filestem_to_block_cid(block_path.file_stem(), *layout),
+                None
+            );
+        }
     }
 
     #[test]
     fn invalid_pin_path_is_silently_ignored() {
         let pin_path = Path::new("another_root/ca/foocar.recursive");
-        assert_eq!(super::filestem_to_block_cid(pin_path.file_stem()), None);
+        assert_eq!(
+            super::filestem_to_block_cid(pin_path.file_stem(), BlockLayout::Legacy),
+            None
+        );
     }
 
     #[test]
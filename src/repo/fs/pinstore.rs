@@ -1,16 +1,16 @@
 //! Persistent filesystem backed pin store. See [`FsDataStore`] for more information.
-use super::{filestem_to_pin_cid, pin_path, FsDataStore};
+use super::{filestem_to_pin_cid, list_sharded, pin_path, FsDataStore};
 use crate::error::Error;
 use crate::repo::{PinKind, PinMode, PinStore, References};
+use crate::runtime::Runtime;
 use async_trait::async_trait;
 use cid::Cid;
 use core::convert::TryFrom;
 use futures::future::Either;
-use futures::stream::{empty, StreamExt, TryStreamExt};
+use futures::stream::{StreamExt, TryStreamExt};
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::fs;
 use tokio::sync::Semaphore;
 use tracing_futures::Instrument;
 
@@ -18,11 +18,11 @@ use tracing_futures::Instrument;
 // parent module.
 
 #[async_trait]
-impl PinStore for FsDataStore {
+impl<R: Runtime> PinStore for FsDataStore<R> {
     async fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
         let path = pin_path(self.path.clone(), cid);
 
-        if read_direct_or_recursive(path).await?.is_some() {
+        if read_direct_or_recursive::<R>(path).await?.is_some() {
             return Ok(true);
         }
 
@@ -39,7 +39,8 @@ impl PinStore for FsDataStore {
         while let Some(recursive) = st.try_next().await? {
             // TODO: it might be much better to just deserialize the vec one by one and comparing while
             // going
-            let (_, references) = read_recursively_pinned(self.path.clone(), recursive).await?;
+            let (_, references) =
+                read_recursively_pinned::<R>(self.path.clone(), recursive).await?;
 
             // if we always wrote down the cids in some order we might be able to binary search?
             if references.into_iter().any(move |x| x == *cid) {
@@ -50,6 +51,36 @@ impl PinStore for FsDataStore {
         Ok(false)
     }
 
+    async fn is_hash_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        // the pin files are named by the Cids which were pinned, so the multihashes of the direct
+        // and recursive pins are found by listing the files, after which the recursive pins are
+        // read at most once
+        let hash = block.hash().as_bytes();
+        let pins = self.list_pinfiles().await.try_collect::<Vec<_>>().await?;
+
+        if pins
+            .iter()
+            .any(|(pinned, _)| pinned.hash().as_bytes() == hash)
+        {
+            return Ok(true);
+        }
+
+        let recursive = pins
+            .into_iter()
+            .filter(|(_, mode)| *mode == PinMode::Recursive)
+            .map(|(pinned, _)| pinned);
+
+        for pinned in recursive {
+            let (_, references) = read_recursively_pinned::<R>(self.path.clone(), pinned).await?;
+
+            if references.iter().any(|x| x.hash().as_bytes() == hash) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
         let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
 
@@ -57,7 +88,7 @@ impl PinStore for FsDataStore {
 
         let span = tracing::Span::current();
 
-        tokio::task::spawn_blocking(move || {
+        R::spawn_blocking(move || {
             // move the permit to the blocking thread to ensure we keep it as long as needed
             let _permit = permit;
             let _entered = span.enter();
@@ -73,7 +104,7 @@ impl PinStore for FsDataStore {
             f.sync_all()?;
             Ok(())
         })
-        .await??;
+        .await?;
 
         Ok(())
     }
@@ -93,7 +124,7 @@ impl PinStore for FsDataStore {
 
         let span = tracing::Span::current();
 
-        tokio::task::spawn_blocking(move || {
+        R::spawn_blocking(move || {
             let _permit = permit; // again move to the threadpool thread
             let _entered = span.enter();
 
@@ -140,7 +171,7 @@ impl PinStore for FsDataStore {
 
             Ok::<_, Error>(())
         })
-        .await??;
+        .await?;
 
         Ok(())
     }
@@ -152,7 +183,7 @@ impl PinStore for FsDataStore {
 
         let span = tracing::Span::current();
 
-        tokio::task::spawn_blocking(move || {
+        R::spawn_blocking(move || {
             let _permit = permit; // move in to threadpool thread
             let _entered = span.enter();
 
@@ -175,7 +206,7 @@ impl PinStore for FsDataStore {
                 Err(e) => Err(e.into()),
             }
         })
-        .await??;
+        .await?;
 
         Ok(())
     }
@@ -187,7 +218,7 @@ impl PinStore for FsDataStore {
 
         let span = tracing::Span::current();
 
-        tokio::task::spawn_blocking(move || {
+        R::spawn_blocking(move || {
             let _permit = permit; // move into threadpool thread
             let _entered = span.enter();
 
@@ -228,7 +259,7 @@ impl PinStore for FsDataStore {
                 Ok(())
             }
         })
-        .await??;
+        .await?;
 
         Ok(())
     }
@@ -301,7 +332,7 @@ impl PinStore for FsDataStore {
             // the threadpool passing adds probably some messaging latency, maybe run small
             // amount in parallel?
             let mut recursive = futures::stream::iter(recursive.into_iter().map(Ok))
-                .map_ok(move |cid| read_recursively_pinned(path.clone(), cid))
+                .map_ok(move |cid| read_recursively_pinned::<R>(path.clone(), cid))
                 .try_buffer_unordered(4);
 
             while let Some((_, next_batch)) = recursive.try_next().await? {
@@ -341,7 +372,7 @@ impl PinStore for FsDataStore {
         let (mut response, mut remaining) = if check_direct {
             // find the recursive and direct ones by just seeing if the files exist
             let base = self.path.clone();
-            tokio::task::spawn_blocking(move || {
+            R::spawn_blocking(move || {
                 for (i, cid) in ids.into_iter().enumerate() {
                     let mut path = pin_path(base.clone(), &cid);
 
@@ -374,7 +405,7 @@ impl PinStore for FsDataStore {
 
                 Ok((response, remaining))
             })
-            .await??
+            .await?
         } else {
             for (i, cid) in ids.into_iter().enumerate() {
                 remaining.entry(cid).or_insert(i);
@@ -403,7 +434,7 @@ impl PinStore for FsDataStore {
                         Ok(None)
                     })
                 })
-                .map_ok(|cid| read_recursively_pinned(self.path.clone(), cid))
+                .map_ok(|cid| read_recursively_pinned::<R>(self.path.clone(), cid))
                 .try_buffer_unordered(4);
 
             futures::pin_mut!(recursives);
@@ -433,47 +464,34 @@ impl PinStore for FsDataStore {
     }
 }
 
-impl FsDataStore {
+impl<R: Runtime> FsDataStore<R> {
     async fn list_pinfiles(
         &self,
     ) -> impl futures::stream::Stream<Item = Result<(Cid, PinMode), Error>> + 'static {
-        let stream = match tokio::fs::read_dir(self.path.clone()).await {
-            Ok(st) => Either::Left(st),
+        let names = match list_sharded::<R>(self.path.clone()).await {
+            Ok(names) => names,
             // make this into a stream which will only yield the initial error
-            Err(e) => Either::Right(futures::stream::once(futures::future::ready(Err(e)))),
+            Err(e) => {
+                return Either::Right(futures::stream::once(futures::future::ready(Err(e.into()))))
+            }
         };
 
-        stream
-            .and_then(|d| async move {
-                // map over the shard directories
-                Ok(if d.file_type().await?.is_dir() {
-                    Either::Left(fs::read_dir(d.path()).await?)
-                } else {
-                    Either::Right(empty())
-                })
-            })
-            // flatten each
-            .try_flatten()
-            .map_err(Error::new)
-            // convert the paths ending in ".data" into cid
-            .try_filter_map(|d| {
-                let name = d.file_name();
-                let path: &std::path::Path = name.as_ref();
-
-                let mode = if path.extension() == Some("recursive".as_ref()) {
-                    Some(PinMode::Recursive)
-                } else if path.extension() == Some("direct".as_ref()) {
-                    Some(PinMode::Direct)
-                } else {
-                    None
-                };
-
-                let maybe_tuple = mode.and_then(move |mode| {
-                    filestem_to_pin_cid(path.file_stem()).map(move |cid| (cid, mode))
-                });
-
-                futures::future::ready(Ok(maybe_tuple))
-            })
+        // convert the paths ending in ".recursive" or ".direct" into cid
+        let pins = names.into_iter().filter_map(|name| {
+            let path: &std::path::Path = name.as_ref();
+
+            let mode = if path.extension() == Some("recursive".as_ref()) {
+                PinMode::Recursive
+            } else if path.extension() == Some("direct".as_ref()) {
+                PinMode::Direct
+            } else {
+                return None;
+            };
+
+            filestem_to_pin_cid(path.file_stem()).map(|cid| Ok((cid, mode)))
+        });
+
+        Either::Left(futures::stream::iter(pins.collect::<Vec<_>>()))
     }
 }
 
@@ -482,11 +500,14 @@ impl FsDataStore {
 /// On file not found error returns an empty Vec as if nothing had happened. This is because we
 /// do "atomic writes" and file removals are expected to be atomic, but reads don't synchronize on
 /// writes, so while iterating it's possible that recursive pin is removed.
-async fn read_recursively_pinned(path: PathBuf, cid: Cid) -> Result<(Cid, Vec<Cid>), Error> {
+async fn read_recursively_pinned<R: Runtime>(
+    path: PathBuf,
+    cid: Cid,
+) -> Result<(Cid, Vec<Cid>), Error> {
     // our fancy format is a Vec<Cid> as json
     let mut path = pin_path(path, &cid);
     path.set_extension("recursive");
-    let contents = match tokio::fs::read(path).await {
+    let contents = match R::spawn_blocking(move || std::fs::read(path)).await {
         Ok(vec) => vec,
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
             // per method comment, return empty Vec; the pins may have seemed to be present earlier
@@ -509,8 +530,10 @@ async fn read_recursively_pinned(path: PathBuf, cid: Cid) -> Result<(Cid, Vec<Ci
     Ok((cid, found))
 }
 
-async fn read_direct_or_recursive(mut block_path: PathBuf) -> Result<Option<PinMode>, Error> {
-    tokio::task::spawn_blocking(move || Ok(sync_read_direct_or_recursive(&mut block_path))).await?
+async fn read_direct_or_recursive<R: Runtime>(
+    mut block_path: PathBuf,
+) -> Result<Option<PinMode>, Error> {
+    Ok(R::spawn_blocking(move || sync_read_direct_or_recursive(&mut block_path)).await)
 }
 
 fn sync_read_direct_or_recursive(block_path: &mut PathBuf) -> Option<PinMode> {
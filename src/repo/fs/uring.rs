@@ -0,0 +1,214 @@
+use io_uring::{opcode, squeue, types, IoUring};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The entries of each ring, of which at most two are in use at a time.
+const ENTRIES: u32 = 4;
+
+/// Set once creating a ring has failed, as on the kernels older than 5.1 or when the syscalls are
+/// filtered, after which the rings are not attempted again.
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The ring of the blocking thread, created on the first use.
+    static RING: RefCell<Option<IoUring>> = RefCell::new(None);
+}
+
+/// Runs `f` with the ring of the current thread, or returns `None` when io_uring is not
+/// available and the caller should fall back to the standard library. The ring is given as an
+/// `Option` for [`submit`] to discard a ring it can no longer use.
+fn with_ring<T>(f: impl FnOnce(&mut Option<IoUring>) -> io::Result<T>) -> Option<io::Result<T>> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+
+        if ring.is_none() {
+            match IoUring::new(ENTRIES) {
+                Ok(created) => *ring = Some(created),
+                Err(e) => {
+                    if !UNAVAILABLE.swap(true, Ordering::Relaxed) {
+                        debug!("io_uring is not available, using blocking io: {}", e);
+                    }
+                    return None;
+                }
+            }
+        }
+
+        Some(f(&mut ring))
+    })
+}
+
+/// Submits the entries and waits for all of their completions, returning the results in the
+/// order of the entries.
+///
+/// This never returns while the kernel could still be using the buffers of the entries: the
+/// interrupted waits are retried, and when the entries cannot be submitted at all the ring still
+/// holding them is discarded, so that they are never submitted after the buffers are gone.
+/// A ring which returns completions not belonging to the entries is discarded as well.
+fn submit(slot: &mut Option<IoUring>, entries: &[squeue::Entry]) -> io::Result<Vec<i32>> {
+    let ring = slot
+        .as_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "io_uring was discarded"))?;
+
+    for (nth, entry) in entries.iter().enumerate() {
+        let entry = entry.clone().user_data(nth as u64);
+        // safety: the buffers of the entries outlive the wait below
+        let pushed = unsafe { ring.submission().available().push(entry).is_ok() };
+
+        if !pushed {
+            // the entries pushed already must not be submitted later
+            *slot = None;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "submission queue is full",
+            ));
+        }
+    }
+
+    let mut results = vec![None; entries.len()];
+    let mut completed = 0;
+    let mut unexpected = false;
+
+    while completed < entries.len() {
+        if let Err(e) = ring.submit_and_wait(entries.len() - completed) {
+            match e.kind() {
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => {}
+                _ if ring.submission().len() + completed == entries.len() => {
+                    // none of the entries reached the kernel
+                    *slot = None;
+                    return Err(e);
+                }
+                _ => {
+                    // the kernel is using some of the buffers, which cannot be freed before the
+                    // completions of the entries arrive
+                    warn!("waiting for io_uring completions failed, retrying: {}", e);
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+            }
+        }
+
+        for cqe in ring.completion().available() {
+            match results.get_mut(cqe.user_data() as usize) {
+                Some(result @ None) => {
+                    *result = Some(cqe.result());
+                    completed += 1;
+                }
+                _ => unexpected = true,
+            }
+        }
+    }
+
+    if unexpected {
+        // the completions of an earlier submission got mixed up with these
+        *slot = None;
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "unexpected io_uring completion",
+        ));
+    }
+
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("one result per entry"))
+        .collect())
+}
+
+fn check(result: i32) -> io::Result<usize> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Reads the file of `len` bytes from the start, or returns `None` when io_uring is not available.
+pub(super) fn read(file: &File, len: u64) -> Option<io::Result<Vec<u8>>> {
+    with_ring(|ring| {
+        let fd = types::Target::Fd(file.as_raw_fd());
+        let mut data = vec![0u8; len as usize];
+        let mut filled = 0;
+
+        while filled < data.len() {
+            let remaining = &mut data[filled..];
+            let read = opcode::Read::new(fd, remaining.as_mut_ptr(), remaining.len() as _)
+                .offset(filled as _)
+                .build();
+
+            match check(submit(ring, &[read])?[0])? {
+                // the file was truncated since the length was read
+                0 => break,
+                n => filled += n,
+            }
+        }
+
+        data.truncate(filled);
+        Ok(data)
+    })
+}
+
+/// Writes the data from the start of the file and syncs it with a linked fsync, or returns `None`
+/// when io_uring is not available.
+pub(super) fn write_and_sync(file: &File, data: &[u8]) -> Option<io::Result<()>> {
+    with_ring(|ring| {
+        let fd = types::Target::Fd(file.as_raw_fd());
+        let mut written = 0;
+
+        loop {
+            let remaining = &data[written..];
+            let write = opcode::Write::new(fd, remaining.as_ptr(), remaining.len() as _)
+                .offset(written as _)
+                .build()
+                .flags(squeue::Flags::IO_LINK);
+            let fsync = opcode::Fsync::new(fd).build();
+
+            let results = submit(ring, &[write, fsync])?;
+            written += check(results[0])?;
+
+            if written < data.len() {
+                // the fsync was run after the short write, continue with the rest of the data
+                continue;
+            }
+
+            check(results[1])?;
+            return Ok(());
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, write_and_sync};
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn roundtrip() {
+        let path = std::env::temp_dir().join(format!("uring_roundtrip_{}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+
+        let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let written = match write_and_sync(&file, &data) {
+            Some(res) => res,
+            // io_uring is not available on the host running the tests
+            None => return,
+        };
+        written.unwrap();
+
+        assert_eq!(read(&file, data.len() as u64).unwrap().unwrap(), data);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
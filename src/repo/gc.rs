@@ -0,0 +1,294 @@
+//! Mark-and-sweep garbage collection of the blocks, see [`Repo::gc`].
+
+use super::{PinMode, Repo, RepoTypes};
+use crate::error::Error;
+use crate::events::IpfsEvent;
+use crate::ipld::{decode_ipld, BlockError};
+use crate::refs::ipld_links;
+use crate::runtime::Runtime;
+use async_stream::stream;
+use cid::Cid;
+use futures::lock::{Mutex, MutexGuard};
+use futures::stream::{Stream, TryStreamExt};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How often a collection waiting for the [`GcGuard`]s to be dropped checks them.
+const GUARDS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Keeps the garbage collection from running while the blocks are being stored or pinned.
+#[derive(Debug, Default)]
+pub(super) struct GcLock {
+    /// The number of the live [`GcGuard`]s.
+    guards: AtomicUsize,
+    /// Held by the collection while it runs, and briefly when creating the guards.
+    collecting: Mutex<()>,
+}
+
+/// Keeps [`Repo::gc`] from starting while held, as returned by [`Repo::gc_guard`]. The guards can
+/// be nested, as a collection waiting for the guards to be dropped does not keep new ones from
+/// being created.
+#[derive(Debug)]
+pub struct GcGuard<'a>(&'a GcLock);
+
+impl Drop for GcGuard<'_> {
+    fn drop(&mut self) {
+        self.0.guards.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The multihash of the block, as the same block can be referred to by the Cids of different
+/// versions and codecs, such as the raw Cids listed by the flatfs block store.
+fn mark_key(cid: &Cid) -> Vec<u8> {
+    cid.hash().as_bytes().to_vec()
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Returns a guard which keeps [`Repo::gc`] from starting until it is dropped, waiting for a
+    /// running collection to complete first. Storing and pinning the blocks hold a guard; an
+    /// operation storing blocks and pinning them after, such as adding a file and pinning it,
+    /// should hold a guard from start to end so that the blocks are not removed before the pin.
+    pub async fn gc_guard(&self) -> GcGuard<'_> {
+        let lock = &self.gc_lock;
+        let _collecting = lock.collecting.lock().await;
+        lock.guards.fetch_add(1, Ordering::SeqCst);
+        GcGuard(lock)
+    }
+
+    /// Waits until there are no [`GcGuard`]s, returning the guard of the collection. The lock is
+    /// not held while waiting, so that the holders of the guards can create nested guards.
+    pub(super) async fn lock_for_gc(&self) -> MutexGuard<'_, ()> {
+        let lock = &self.gc_lock;
+
+        loop {
+            let collecting = lock.collecting.lock().await;
+
+            if lock.guards.load(Ordering::SeqCst) == 0 {
+                return collecting;
+            }
+
+            drop(collecting);
+            TRepoTypes::TRuntime::delay_for(GUARDS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Removes the blocks which are not pinned nor reachable from the recursive pins, yielding
+    /// the Cids of the removed blocks. With `dry_run` the blocks which would be removed are
+    /// yielded without removing them. An [`IpfsEvent::GcRun`] is published once the whole
+    /// stream has been consumed.
+    ///
+    /// The collection starts once the [`GcGuard`]s of the operations storing or pinning blocks
+    /// have been dropped, and no blocks can be stored or pinned until it completes. The blocks
+    /// of a codec which cannot be decoded are kept if marked, but their links are not followed.
+    pub fn gc(&self, dry_run: bool) -> impl Stream<Item = Result<Cid, Error>> + Send + '_ {
+        stream! {
+            let _collecting = self.lock_for_gc().await;
+
+            let marked = match self.mark().await {
+                Ok(marked) => marked,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let blocks = match self.list_blocks().await {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            debug!(marked = marked.len(), blocks = blocks.len(), dry_run, "sweeping");
+
+            let mut removed = 0;
+
+            for cid in blocks {
+                if marked.contains(&mark_key(&cid)) {
+                    continue;
+                }
+
+                if dry_run {
+                    removed += 1;
+                    yield Ok(cid);
+                    continue;
+                }
+
+                // the pins were marked, and no pins can be added while collecting
+                match self.remove_unpinned_block(&cid).await {
+                    Ok(cid) => {
+                        removed += 1;
+                        yield Ok(cid);
+                    }
+                    Err(e) => yield Err(e),
+                }
+            }
+
+            self.bus.publish(IpfsEvent::GcRun { removed, dry_run });
+        }
+    }
+
+    /// Marks the pinned blocks and the blocks reachable from the recursive pins through the
+    /// locally available blocks.
+    async fn mark(&self) -> Result<HashSet<Vec<u8>>, Error> {
+        let pins = self.list_pins(None).await.try_collect::<Vec<_>>().await?;
+
+        let mut marked = HashSet::with_capacity(pins.len());
+        let mut work = Vec::new();
+
+        for (cid, mode) in pins {
+            marked.insert(mark_key(&cid));
+            if mode == PinMode::Recursive {
+                work.push(cid);
+            }
+        }
+
+        // the indirect pins are recorded already, this is for the blocks stored after pinning,
+        // such as the ones which were missing when the pin was made
+        let mut visited = HashSet::new();
+        while let Some(cid) = work.pop() {
+            if !visited.insert(mark_key(&cid)) {
+                continue;
+            }
+
+            let block = match self.get_block_now(&cid).await? {
+                Some(block) => block,
+                None => continue,
+            };
+
+            let ipld = match decode_ipld(&cid, block.data()) {
+                Ok(ipld) => ipld,
+                Err(BlockError::UnsupportedCodec(codec)) => {
+                    trace!(cid = %cid, ?codec, "not following the links of an unsupported codec");
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            for (_, next) in ipld_links(&cid, ipld) {
+                marked.insert(mark_key(&next));
+                work.push(next);
+            }
+        }
+
+        Ok(marked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{make_ipld, Block, Node};
+    use cid::{Cid, Codec};
+    use futures::stream::{StreamExt, TryStreamExt};
+    use multihash::Sha2_256;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[tokio::test(max_threads = 1)]
+    async fn removes_only_unpinned_blocks() {
+        let ipfs = Node::new("test_node").await;
+
+        let dag = ipfs.dag();
+        let leaf = dag.put(make_ipld!("leaf"), Codec::DagCBOR).await.unwrap();
+        let root = dag
+            .put(make_ipld!([leaf.clone()]), Codec::DagCBOR)
+            .await
+            .unwrap();
+        let direct = dag.put(make_ipld!("direct"), Codec::DagCBOR).await.unwrap();
+        let garbage = dag
+            .put(make_ipld!("garbage"), Codec::DagCBOR)
+            .await
+            .unwrap();
+
+        ipfs.insert_pin(&root, true).await.unwrap();
+        ipfs.insert_pin(&direct, false).await.unwrap();
+
+        let would_remove = ipfs.repo.gc(true).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(would_remove, vec![garbage.clone()]);
+        assert!(ipfs.repo.contains_block(&garbage).await.unwrap());
+
+        let removed = ipfs.repo.gc(false).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(removed, vec![garbage.clone()]);
+
+        let remaining = ipfs
+            .repo
+            .list_blocks()
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let expected = vec![leaf, root, direct].into_iter().collect::<HashSet<_>>();
+        assert_eq!(remaining, expected);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn pinned_block_is_not_removed_by_raw_cid() {
+        let ipfs = Node::new("test_node").await;
+
+        let cid = ipfs
+            .dag()
+            .put(make_ipld!("pinned"), Codec::DagCBOR)
+            .await
+            .unwrap();
+        ipfs.insert_pin(&cid, false).await.unwrap();
+
+        // the filesystem block store lists the blocks with the raw Cids
+        let raw = Cid::new_v1(Codec::Raw, cid.hash().to_owned());
+
+        assert!(ipfs.repo.remove_block(&raw).await.is_err());
+        assert!(ipfs.repo.contains_block(&cid).await.unwrap());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn unsupported_codec_is_a_leaf() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"not decodable".to_vec();
+        let cid = Cid::new_v1(Codec::GitRaw, Sha2_256::digest(&data));
+        ipfs.put_block(Block::new(data.into_boxed_slice(), cid.clone()))
+            .await
+            .unwrap();
+        ipfs.repo
+            .insert_recursive_pin(&cid, futures::stream::empty().boxed())
+            .await
+            .unwrap();
+
+        let garbage = ipfs
+            .dag()
+            .put(make_ipld!("garbage"), Codec::DagCBOR)
+            .await
+            .unwrap();
+
+        let removed = ipfs.repo.gc(false).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(removed, vec![garbage]);
+        assert!(ipfs.repo.contains_block(&cid).await.unwrap());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn gc_waits_for_the_guards() {
+        let ipfs = Node::new("test_node").await;
+
+        let guard = ipfs.repo.gc_guard().await;
+        let nested = ipfs.repo.gc_guard().await;
+
+        let garbage = ipfs
+            .dag()
+            .put(make_ipld!("garbage"), Codec::DagCBOR)
+            .await
+            .unwrap();
+
+        let gc = ipfs.repo.gc(false).try_collect::<Vec<_>>();
+        futures::pin_mut!(gc);
+
+        assert!(tokio::time::timeout(Duration::from_millis(300), &mut gc)
+            .await
+            .is_err());
+
+        drop(nested);
+        drop(guard);
+
+        assert_eq!(gc.await.unwrap(), vec![garbage]);
+    }
+}
@@ -91,6 +91,7 @@ impl BlockStore for MemBlockStore {
 #[derive(Debug, Default)]
 pub struct MemDataStore {
     ipns: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    keystore: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
     // this could also be PinDocument however doing any serialization allows to see the required
     // error types easier
     pin: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
@@ -399,6 +400,7 @@ impl DataStore for MemDataStore {
     async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Keystore => &self.keystore,
         };
         let contains = map.lock().await.contains_key(key);
         Ok(contains)
@@ -407,6 +409,7 @@ impl DataStore for MemDataStore {
     async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Keystore => &self.keystore,
         };
         let value = map.lock().await.get(key).map(|value| value.to_owned());
         Ok(value)
@@ -415,6 +418,7 @@ impl DataStore for MemDataStore {
     async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Keystore => &self.keystore,
         };
         map.lock().await.insert(key.to_owned(), value.to_owned());
         Ok(())
@@ -423,13 +427,24 @@ impl DataStore for MemDataStore {
     async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
         let map = match col {
             Column::Ipns => &self.ipns,
+            Column::Keystore => &self.keystore,
         };
         map.lock().await.remove(key);
         Ok(())
     }
 
+    async fn list(&self, col: Column) -> Result<Vec<Vec<u8>>, Error> {
+        let map = match col {
+            Column::Ipns => &self.ipns,
+            Column::Keystore => &self.keystore,
+        };
+        let keys = map.lock().await.keys().cloned().collect();
+        Ok(keys)
+    }
+
     async fn wipe(&self) {
         self.ipns.lock().await.clear();
+        self.keystore.lock().await.clear();
         self.pin.lock().await.clear();
     }
 }
@@ -739,11 +754,15 @@ mod tests {
         let get = store.get(col, &key);
         assert_eq!(get.await.unwrap(), Some(value.to_vec()));
 
+        assert_eq!(store.list(col).await.unwrap(), vec![key.to_vec()]);
+        assert!(store.list(Column::Keystore).await.unwrap().is_empty());
+
         store.remove(col, &key).await.unwrap();
         let contains = store.contains(col, &key);
         assert_eq!(contains.await.unwrap(), false);
         let get = store.get(col, &key);
         assert_eq!(get.await.unwrap(), None);
+        assert!(store.list(col).await.unwrap().is_empty());
     }
 
     #[test]
@@ -1,33 +1,47 @@
 //! Storage implementation(s) backing the [`crate::Ipfs`].
 use crate::error::Error;
-use crate::p2p::KadResult;
+use crate::events::{EventBus, IpfsEvent};
+use crate::metrics::{Counter, Gauge, Histogram, Registry, DURATION_BUCKETS};
 use crate::path::IpfsPath;
-use crate::subscription::{RequestKind, SubscriptionFuture, SubscriptionRegistry};
+use crate::runtime::Runtime;
+use crate::subscription::{RequestKind, SubscriptionRegistry};
 use crate::{Block, IpfsOptions};
 use async_trait::async_trait;
+use bytes::Bytes;
 use cid::{self, Cid};
 use core::convert::TryFrom;
 use core::fmt::Debug;
-use futures::channel::{
-    mpsc::{channel, Receiver, Sender},
-    oneshot,
-};
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::future::{self, Either};
 use futures::sink::SinkExt;
+use futures::stream::TryStreamExt;
+use ipfs_bitswap::SessionId;
 use libp2p::core::PeerId;
 use std::borrow::Borrow;
+use std::future::Future;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing_futures::Instrument;
 
 #[macro_use]
 #[cfg(test)]
 mod common_tests;
 
+/// Not available on wasm32, which has no filesystem: the stores are supplied through the
+/// `RepoTypes` of the embedder.
+#[cfg(not(target_arch = "wasm32"))]
 pub mod fs;
+mod gc;
+pub use gc::GcGuard;
 pub mod mem;
 
 pub trait RepoTypes: Send + Sync + 'static {
     type TBlockStore: BlockStore;
     type TDataStore: DataStore;
+    type TRuntime: Runtime;
 }
 
 #[derive(Clone, Debug)]
@@ -43,6 +57,17 @@ impl From<&IpfsOptions> for RepoOptions {
     }
 }
 
+/// The key of a cached resolution in [`Column::Ipns`], which cannot be mistaken for the binary
+/// `PeerId` keys of [`Repo::put_ipns`].
+fn cached_ipns_key(name: &str) -> Vec<u8> {
+    format!("cache/{}", name).into_bytes()
+}
+
+/// The key of the last IPNS record published by the node in [`Column::Ipns`].
+fn ipns_record_key(peer_id: &PeerId) -> Vec<u8> {
+    format!("record/{}", peer_id.to_base58()).into_bytes()
+}
+
 pub fn create_repo<TRepoTypes: RepoTypes>(
     options: RepoOptions,
 ) -> (Repo<TRepoTypes>, Receiver<RepoEvent>) {
@@ -89,7 +114,49 @@ pub enum BlockRmError {
     NotFound(Cid),
 }
 
+/// The data of a block shared between the clones, such as the memory-mapped file of a large block
+/// in the filesystem blockstore.
+#[derive(Clone)]
+pub struct BlockData(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl BlockData {
+    pub fn new<T: AsRef<[u8]> + Send + Sync + 'static>(data: T) -> Self {
+        BlockData(Arc::new(data))
+    }
+}
+
+impl From<Bytes> for BlockData {
+    fn from(data: Bytes) -> Self {
+        BlockData::new(data)
+    }
+}
+
+impl std::ops::Deref for BlockData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        AsRef::<[u8]>::as_ref(&*self.0)
+    }
+}
+
+impl AsRef<[u8]> for BlockData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Debug for BlockData {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "BlockData({} bytes)", self.len())
+    }
+}
+
 /// This API is being discussed and evolved, which will likely lead to breakage.
+///
+/// Each of the methods returns a future for the single request, so any number of requests can be
+/// in flight at the same time without a shared stream of responses to correlate. Implementations
+/// doing blocking IO should move it to [`Runtime::spawn_blocking`] of the `TRuntime` in the
+/// returned futures, as the filesystem blockstore does.
 // FIXME: why is this unpin? doesn't probably need to be since all of the futures are Box::pin'd.
 #[async_trait]
 pub trait BlockStore: Debug + Send + Sync + Unpin + 'static {
@@ -98,6 +165,13 @@ pub trait BlockStore: Debug + Send + Sync + Unpin + 'static {
     async fn open(&self) -> Result<(), Error>;
     async fn contains(&self, cid: &Cid) -> Result<bool, Error>;
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error>;
+    /// Returns the data of the block, which the stores can share instead of copying.
+    async fn get_data(&self, cid: &Cid) -> Result<Option<BlockData>, Error> {
+        Ok(self
+            .get(cid)
+            .await?
+            .map(|block| BlockData::from(block.data)))
+    }
     async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error>;
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error>;
     async fn list(&self) -> Result<Vec<Cid>, Error>;
@@ -113,6 +187,8 @@ pub trait DataStore: PinStore + Debug + Send + Sync + Unpin + 'static {
     async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
     async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error>;
     async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error>;
+    /// Returns the keys of all of the values in the column.
+    async fn list(&self, col: Column) -> Result<Vec<Vec<u8>>, Error>;
     async fn wipe(&self);
 }
 
@@ -122,6 +198,23 @@ type References<'a> = futures::stream::BoxStream<'a, Result<Cid, crate::refs::Ip
 pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
     async fn is_pinned(&self, block: &Cid) -> Result<bool, Error>;
 
+    /// Returns true if the multihash of the `block` is pinned under any Cid version or codec, as
+    /// the block stores which store the blocks by the multihash, such as the flatfs layout of the
+    /// `FsBlockStore`, have one block for all of those Cids. The default implementation goes
+    /// through all of the pins.
+    async fn is_hash_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        let hash = block.hash().as_bytes();
+        let mut pins = self.list(None).await;
+
+        while let Some((pinned, _)) = pins.try_next().await? {
+            if pinned.hash().as_bytes() == hash {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error>;
 
     async fn insert_recursive_pin(
@@ -160,6 +253,8 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
 #[derive(Clone, Copy, Debug)]
 pub enum Column {
     Ipns,
+    /// The encrypted keys of [`crate::keystore`], keyed by their names.
+    Keystore,
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -205,21 +300,69 @@ impl<C: Borrow<Cid>> PinKind<C> {
 
 #[derive(Debug)]
 pub struct Repo<TRepoTypes: RepoTypes> {
-    block_store: TRepoTypes::TBlockStore,
+    pub(crate) block_store: TRepoTypes::TBlockStore,
     data_store: TRepoTypes::TDataStore,
     events: Sender<RepoEvent>,
     pub(crate) subscriptions: SubscriptionRegistry<Block, String>,
+    pub(crate) bus: EventBus,
+    pub(crate) registry: Registry,
+    metrics: RepoMetrics,
+    pub(crate) path: PathBuf,
+    /// Set once the stores have been initialized or opened, and cleared on shutdown.
+    open: AtomicBool,
+    gc_lock: gc::GcLock,
 }
 
+/// The metrics of the repo in the [`Registry`] of the node.
+#[derive(Debug)]
+struct RepoMetrics {
+    blocks: Gauge,
+    blocks_stored: Counter,
+    blocks_fetched: Counter,
+    fetch_duration: Histogram,
+    pins_added: Counter,
+}
+
+impl RepoMetrics {
+    fn register(registry: &Registry) -> Self {
+        RepoMetrics {
+            blocks: registry.gauge("ipfs_repo_blocks", "Blocks in the repo"),
+            blocks_stored: registry.counter(
+                "ipfs_repo_blocks_stored_total",
+                "New blocks written to the blockstore",
+            ),
+            blocks_fetched: registry.counter(
+                "ipfs_repo_blocks_fetched_total",
+                "Blocks which were not found locally and were fetched from the network",
+            ),
+            fetch_duration: registry.histogram(
+                "ipfs_repo_block_fetch_duration_seconds",
+                "Time taken to fetch the blocks from the network",
+                DURATION_BUCKETS,
+            ),
+            pins_added: registry.counter(
+                "ipfs_repo_pins_added_total",
+                "Direct and recursive pins added",
+            ),
+        }
+    }
+}
+
+/// The time after which the outstanding wants of a session are sent to all of the peers, as the
+/// peers of the session might not have the blocks.
+const SESSION_BROADCAST_DELAY: Duration = Duration::from_secs(1);
+
 /// Events used to communicate to the swarm on repo changes.
 #[derive(Debug)]
 pub enum RepoEvent {
     WantBlock(Cid),
+    /// Wants the block from the peers of the bitswap session.
+    WantBlockInSession(SessionId, Cid),
+    /// Sends the outstanding wants of the session to all of the peers.
+    BroadcastSession(SessionId),
+    EndSession(SessionId),
     UnwantBlock(Cid),
-    NewBlock(
-        Cid,
-        oneshot::Sender<Result<SubscriptionFuture<KadResult, String>, anyhow::Error>>,
-    ),
+    NewBlock(Cid),
     RemovedBlock(Cid),
 }
 
@@ -235,21 +378,54 @@ impl TryFrom<RequestKind> for RepoEvent {
     }
 }
 
+/// A bitswap session of the [`Repo`], which ends when this is dropped.
+#[derive(Debug)]
+pub struct BlockSession {
+    id: SessionId,
+    events: Sender<RepoEvent>,
+}
+
+impl BlockSession {
+    /// Returns the id to pass to [`Repo::get_block_in_session`].
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+}
+
+impl Drop for BlockSession {
+    fn drop(&mut self) {
+        // a new sender always has room for one message, so this only fails if the background
+        // task has exited
+        self.events
+            .clone()
+            .try_send(RepoEvent::EndSession(self.id))
+            .ok();
+    }
+}
+
 impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     pub fn new(options: RepoOptions) -> (Self, Receiver<RepoEvent>) {
         let mut blockstore_path = options.path.clone();
-        let mut datastore_path = options.path;
+        let mut datastore_path = options.path.clone();
         blockstore_path.push("blockstore");
         datastore_path.push("datastore");
         let block_store = TRepoTypes::TBlockStore::new(blockstore_path);
         let data_store = TRepoTypes::TDataStore::new(datastore_path);
         let (sender, receiver) = channel(1);
+        let registry = Registry::default();
+        let metrics = RepoMetrics::register(&registry);
         (
             Repo {
                 block_store,
                 data_store,
                 events: sender,
                 subscriptions: Default::default(),
+                bus: Default::default(),
+                registry,
+                metrics,
+                path: options.path,
+                open: AtomicBool::new(false),
+                gc_lock: Default::default(),
             },
             receiver,
         )
@@ -258,6 +434,7 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     /// Shutdowns the repo, cancelling any pending subscriptions; Likely going away after some
     /// refactoring, see notes on [`crate::Ipfs::exit_daemon`].
     pub fn shutdown(&self) {
+        self.open.store(false, Ordering::Release);
         self.subscriptions.shutdown();
     }
 
@@ -265,49 +442,66 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         let f1 = self.block_store.init();
         let f2 = self.data_store.init();
         let (r1, r2) = futures::future::join(f1, f2).await;
-        if r1.is_err() {
-            r1
-        } else {
-            r2
-        }
+        let res = if r1.is_err() { r1 } else { r2 };
+        self.open.store(res.is_ok(), Ordering::Release);
+        res
     }
 
     pub async fn open(&self) -> Result<(), Error> {
         let f1 = self.block_store.open();
         let f2 = self.data_store.open();
         let (r1, r2) = futures::future::join(f1, f2).await;
-        if r1.is_err() {
-            r1
-        } else {
-            r2
+        let res = if r1.is_err() { r1 } else { r2 };
+        self.open.store(res.is_ok(), Ordering::Release);
+        res
+    }
+
+    /// Counts the blocks in the block store once in the background after starting the node, after
+    /// which the count is kept up to date as the blocks are stored and removed. The blocks are
+    /// stored and removed while counting, so the count is approximate: the blocks stored or
+    /// removed during the listing can be counted twice or not at all.
+    pub(crate) async fn count_blocks(&self) {
+        match self.block_store.list().await {
+            // the changes made while listing have already been counted
+            Ok(blocks) => self.metrics.blocks.add(blocks.len() as i64),
+            Err(e) => warn!("failed to count the blocks: {}", e),
         }
     }
 
+    /// Returns the approximate number of blocks in the block store, which only includes the
+    /// blocks stored after starting the node until the existing blocks have been counted.
+    pub fn block_count(&self) -> usize {
+        self.metrics.blocks.get().max(0) as usize
+    }
+
+    /// Returns true when the stores have been initialized or opened and the repo has not been
+    /// shut down.
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire)
+    }
+
     /// Puts a block into the block store.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let _guard = self.gc_guard().await;
         let cid = block.cid.clone();
         let (_cid, res) = self.block_store.put(block.clone()).await?;
 
-        // FIXME: this doesn't cause actual DHT providing yet, only some
-        // bitswap housekeeping; we might want to not ignore the channel
-        // errors when we actually start providing on the DHT
         if let BlockPut::NewBlock = res {
+            self.metrics.blocks.inc();
+            self.metrics.blocks_stored.inc();
+            self.bus.publish(IpfsEvent::BlockStored(cid.clone()));
+
             self.subscriptions
                 .finish_subscription(cid.clone().into(), Ok(block));
 
             // sending only fails if no one is listening anymore
-            // and that is okay with us.
-            let (tx, rx) = oneshot::channel();
-
+            // and that is okay with us. the block is provided on the DHT in the background, as
+            // waiting for the query would hold up storing the blocks of a large file.
             self.events
                 .clone()
-                .send(RepoEvent::NewBlock(cid.clone(), tx))
+                .send(RepoEvent::NewBlock(cid.clone()))
                 .await
                 .ok();
-
-            if let Ok(Ok(kad_subscription)) = rx.await {
-                kad_subscription.await?;
-            }
         }
 
         Ok((cid, res))
@@ -316,28 +510,136 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     /// Retrives a block from the block store, or starts fetching it from the network and awaits
     /// until it has been fetched.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        self.fetch_block(cid, None).await
+    }
+
+    /// Starts a bitswap session for fetching correlated blocks, such as the blocks of a single
+    /// DAG, with [`Repo::get_block_in_session`].
+    pub fn session(&self) -> BlockSession {
+        BlockSession {
+            id: SessionId::new(),
+            events: self.events.clone(),
+        }
+    }
+
+    /// Like [`Repo::get_block`] but asks only the peers which have sent the earlier blocks of the
+    /// session, until the block has not arrived for a while.
+    pub async fn get_block_in_session(
+        &self,
+        cid: &Cid,
+        session: SessionId,
+    ) -> Result<Block, Error> {
+        self.fetch_block(cid, Some(session)).await
+    }
+
+    async fn fetch_block(&self, cid: &Cid, session: Option<SessionId>) -> Result<Block, Error> {
         // FIXME: here's a race: block_store might give Ok(None) and we get to create our
         // subscription after the put has completed. So maybe create the subscription first, then
         // cancel it?
         if let Some(block) = self.get_block_now(&cid).await? {
             Ok(block)
         } else {
+            debug!("block not found locally, fetching");
+            let started = Instant::now();
             let subscription = self
                 .subscriptions
                 .create_subscription(cid.clone().into(), Some(self.events.clone()));
+            let want = match session {
+                Some(session) => RepoEvent::WantBlockInSession(session, cid.clone()),
+                None => RepoEvent::WantBlock(cid.clone()),
+            };
             // sending only fails if no one is listening anymore
             // and that is okay with us.
-            self.events
-                .clone()
-                .send(RepoEvent::WantBlock(cid.clone()))
-                .await
-                .ok();
-            Ok(subscription.await?)
+            self.events.clone().send(want).await.ok();
+            let block = match session {
+                Some(session) => {
+                    self.wait_in_session(subscription, session)
+                        .instrument(debug_span!("fetch", ?session))
+                        .await?
+                }
+                None => subscription.instrument(debug_span!("fetch")).await?,
+            };
+            debug!("block fetched");
+            self.metrics.blocks_fetched.inc();
+            self.metrics
+                .fetch_duration
+                .observe(started.elapsed().as_secs_f64());
+            Ok(block)
+        }
+    }
+
+    /// Waits for the `fetch` to complete, asking for the outstanding wants of the session to be
+    /// sent to all of the peers whenever nothing has arrived in [`SESSION_BROADCAST_DELAY`]. The
+    /// bitswap behaviour limits the broadcasts of a session made by its concurrent fetches.
+    async fn wait_in_session<F: Future + Unpin>(
+        &self,
+        mut fetch: F,
+        session: SessionId,
+    ) -> F::Output {
+        loop {
+            let delay = TRepoTypes::TRuntime::delay_for(SESSION_BROADCAST_DELAY);
+
+            match future::select(fetch, delay).await {
+                Either::Left((output, _)) => return output,
+                Either::Right((_, pending)) => {
+                    debug!(?session, "session peers are slow, asking everyone");
+                    fetch = pending;
+                    self.events
+                        .clone()
+                        .send(RepoEvent::BroadcastSession(session))
+                        .await
+                        .ok();
+                }
+            }
+        }
+    }
+
+    /// Like [`Repo::get_block`] but returns only the data, which the blockstore can share instead
+    /// of copying, as the filesystem blockstore does by memory-mapping the large blocks.
+    pub async fn get_block_data(&self, cid: &Cid) -> Result<BlockData, Error> {
+        self.fetch_block_data(cid, None).await
+    }
+
+    /// Like [`Repo::get_block_data`] but fetches the block in the session, as
+    /// [`Repo::get_block_in_session`] does.
+    pub async fn get_block_data_in_session(
+        &self,
+        cid: &Cid,
+        session: SessionId,
+    ) -> Result<BlockData, Error> {
+        self.fetch_block_data(cid, Some(session)).await
+    }
+
+    async fn fetch_block_data(
+        &self,
+        cid: &Cid,
+        session: Option<SessionId>,
+    ) -> Result<BlockData, Error> {
+        if let Some(block) = inlined_block(cid) {
+            return Ok(block.data.into());
         }
+
+        if let Some(data) = self.block_store.get_data(cid).await? {
+            return Ok(data);
+        }
+
+        self.fetch_block(cid, session)
+            .await
+            .map(|block| block.data.into())
+    }
+
+    /// Checks if the block store has the block, without reading it.
+    pub async fn contains_block(&self, cid: &Cid) -> Result<bool, Error> {
+        self.block_store.contains(cid).await
     }
 
-    /// Retrives a block from the block store if it's available locally.
+    /// Retrives a block from the block store if it's available locally. Blocks inlined into
+    /// their Cid with the identity hash are always available.
     pub async fn get_block_now(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        if let Some(block) = inlined_block(cid) {
+            return Ok(Some(block));
+        }
+
         self.block_store.get(&cid).await
     }
 
@@ -347,10 +649,16 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 
     /// Remove block from the block store.
     pub async fn remove_block(&self, cid: &Cid) -> Result<Cid, Error> {
-        if self.is_pinned(&cid).await? {
+        if self.is_block_pinned(&cid).await? {
             return Err(anyhow::anyhow!("block to remove is pinned"));
         }
 
+        self.remove_unpinned_block(cid).await
+    }
+
+    /// Removes the block without checking the pins, for the callers which have already checked
+    /// them.
+    async fn remove_unpinned_block(&self, cid: &Cid) -> Result<Cid, Error> {
         // FIXME: Need to change location of pinning logic.
         // I like this pattern of the repo abstraction being some sort of
         // "clearing house" for the underlying result enums, but this
@@ -358,6 +666,7 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         match self.block_store.remove(&cid).await? {
             Ok(success) => match success {
                 BlockRm::Removed(_cid) => {
+                    self.metrics.blocks.dec();
                     // sending only fails if the background task has exited
                     self.events
                         .clone()
@@ -404,12 +713,94 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         self.data_store.remove(Column::Ipns, ipns.as_bytes()).await
     }
 
+    /// Get a cached resolution of an IPNS name or a DNSLink domain from the datastore.
+    pub(crate) async fn get_cached_ipns(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.data_store
+            .get(Column::Ipns, &cached_ipns_key(name))
+            .await
+    }
+
+    /// Put a cached resolution of an IPNS name or a DNSLink domain into the datastore.
+    pub(crate) async fn put_cached_ipns(&self, name: &str, value: &[u8]) -> Result<(), Error> {
+        self.data_store
+            .put(Column::Ipns, &cached_ipns_key(name), value)
+            .await
+    }
+
+    /// Get the last IPNS record published for the peer id from the datastore.
+    pub(crate) async fn get_ipns_record(&self, peer_id: &PeerId) -> Result<Option<Vec<u8>>, Error> {
+        self.data_store
+            .get(Column::Ipns, &ipns_record_key(peer_id))
+            .await
+    }
+
+    /// Put the IPNS record published for the peer id into the datastore.
+    pub(crate) async fn put_ipns_record(
+        &self,
+        peer_id: &PeerId,
+        record: &[u8],
+    ) -> Result<(), Error> {
+        self.data_store
+            .put(Column::Ipns, &ipns_record_key(peer_id), record)
+            .await
+    }
+
+    /// Remove a cached resolution of an IPNS name or a DNSLink domain from the datastore.
+    pub(crate) async fn remove_cached_ipns(&self, name: &str) -> Result<(), Error> {
+        self.data_store
+            .remove(Column::Ipns, &cached_ipns_key(name))
+            .await
+    }
+
+    /// Get the encrypted key of the given name from the datastore.
+    pub(crate) async fn get_key(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.data_store.get(Column::Keystore, name.as_bytes()).await
+    }
+
+    /// Put the encrypted key of the given name into the datastore.
+    pub(crate) async fn put_key(&self, name: &str, key: &[u8]) -> Result<(), Error> {
+        self.data_store
+            .put(Column::Keystore, name.as_bytes(), key)
+            .await
+    }
+
+    /// Remove the key of the given name from the datastore.
+    pub(crate) async fn remove_key(&self, name: &str) -> Result<(), Error> {
+        self.data_store
+            .remove(Column::Keystore, name.as_bytes())
+            .await
+    }
+
+    /// List the names of the keys in the datastore.
+    pub(crate) async fn list_keys(&self) -> Result<Vec<String>, Error> {
+        let names = self.data_store.list(Column::Keystore).await?;
+
+        names
+            .into_iter()
+            .map(|name| String::from_utf8(name).map_err(Error::from))
+            .collect()
+    }
+
     pub async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
-        self.data_store.insert_direct_pin(cid).await
+        let _guard = self.gc_guard().await;
+        self.data_store.insert_direct_pin(cid).await?;
+        self.metrics.pins_added.inc();
+        self.bus.publish(IpfsEvent::PinAdded {
+            cid: cid.to_owned(),
+            recursive: false,
+        });
+        Ok(())
     }
 
     pub async fn insert_recursive_pin(&self, cid: &Cid, refs: References<'_>) -> Result<(), Error> {
-        self.data_store.insert_recursive_pin(cid, refs).await
+        let _guard = self.gc_guard().await;
+        self.data_store.insert_recursive_pin(cid, refs).await?;
+        self.metrics.pins_added.inc();
+        self.bus.publish(IpfsEvent::PinAdded {
+            cid: cid.to_owned(),
+            recursive: true,
+        });
+        Ok(())
     }
 
     pub async fn remove_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
@@ -425,6 +816,13 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         self.data_store.is_pinned(&cid).await
     }
 
+    /// Checks if the block is pinned with any Cid of the same multihash, as the block stores may
+    /// list the block with a Cid of another version or codec than it was pinned with, such as the
+    /// raw Cids listed by the flatfs block store.
+    pub async fn is_block_pinned(&self, cid: &Cid) -> Result<bool, Error> {
+        self.data_store.is_hash_pinned(cid).await
+    }
+
     pub async fn list_pins(
         &self,
         mode: Option<PinMode>,
@@ -440,3 +838,14 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         self.data_store.query(cids, requirement).await
     }
 }
+
+/// Returns the block for Cids using the identity hash, which contain the block itself.
+pub(crate) fn inlined_block(cid: &Cid) -> Option<Block> {
+    let hash = cid.hash();
+
+    if hash.algorithm() != multihash::Code::Identity {
+        return None;
+    }
+
+    Some(Block::new(hash.digest().to_vec(), cid.to_owned()))
+}
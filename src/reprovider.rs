@@ -0,0 +1,159 @@
+//! The reprovider, which periodically publishes the provider records of all of the stored blocks
+//! to the DHT, so that the records outlive the restarts of the node and the expiry of the records
+//! held by the other peers.
+
+use crate::error::Error;
+use crate::p2p::KadResult;
+use crate::repo::{Repo, RepoTypes};
+use crate::runtime::Runtime;
+use crate::subscription::SubscriptionFuture;
+use anyhow::anyhow;
+use cid::Cid;
+use futures::channel::mpsc::{Receiver, Sender};
+use futures::channel::oneshot::{
+    channel as oneshot_channel, Canceled, Receiver as OneshotReceiver, Sender as OneshotSender,
+};
+use futures::future::{self, Either, Future, Shared};
+use futures::lock::Mutex;
+use futures::sink::SinkExt;
+use futures::stream::{self, StreamExt};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The delay before the first round, giving the node time to connect to its peers.
+const INITIAL_DELAY: Duration = Duration::from_secs(60);
+
+/// The number of provider records published at the same time.
+pub(crate) const PARALLELISM: usize = 16;
+
+/// The number of the newly stored blocks waiting to be provided; the blocks stored while the queue
+/// is full are not provided before the next round of the reprovider.
+pub(crate) const QUEUE_CAPACITY: usize = 1024;
+
+/// A request to the background task of the node to start providing the Cid, answered with the
+/// query publishing the provider record.
+pub(crate) type ProvideRequest = (Cid, OneshotSender<SubscriptionFuture<KadResult, String>>);
+
+/// Completes once the background task of the node has stopped, either after
+/// [`crate::Ipfs::exit_daemon`] or after all of the [`crate::Ipfs`] clones have been dropped.
+pub(crate) type Shutdown = Shared<OneshotReceiver<()>>;
+
+/// The handle of the provider tasks to the node. Unlike [`crate::Ipfs`], it does not keep the
+/// background task of the node running.
+pub(crate) struct Provider<Types: RepoTypes> {
+    repo: Arc<Repo<Types>>,
+    requests: Sender<ProvideRequest>,
+}
+
+impl<Types: RepoTypes> Clone for Provider<Types> {
+    fn clone(&self) -> Self {
+        Provider {
+            repo: Arc::clone(&self.repo),
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+impl<Types: RepoTypes> Provider<Types> {
+    pub(crate) fn new(repo: Arc<Repo<Types>>, requests: Sender<ProvideRequest>) -> Self {
+        Provider { repo, requests }
+    }
+
+    /// Publishes the provider record of the stored block, like [`crate::Ipfs::provide`].
+    async fn provide(&self, cid: Cid) -> Result<(), Error> {
+        if !self.repo.contains_block(&cid).await? {
+            return Err(anyhow!(
+                "Error: block {} not found locally, cannot provide",
+                cid
+            ));
+        }
+
+        let (tx, rx) = oneshot_channel();
+        self.requests.clone().send((cid, tx)).await?;
+
+        match rx.await?.await {
+            Ok(KadResult::Complete) => Ok(()),
+            Ok(_) => unreachable!(),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+}
+
+/// Runs the supervised task until it completes or the node is shut down, finishing the task
+/// without an error in the latter case.
+pub(crate) async fn until_shutdown<F>(task: F, shutdown: Shutdown) -> Result<(), Error>
+where
+    F: Future<Output = Result<(), Error>>,
+{
+    futures::pin_mut!(task);
+
+    match future::select(task, shutdown).await {
+        Either::Left((res, _)) => res,
+        Either::Right((Ok(()), _)) | Either::Right((Err(Canceled), _)) => Ok(()),
+    }
+}
+
+/// Provides the newly stored blocks from the `queue`, `PARALLELISM` at a time. The receiver is
+/// shared so that the task can be restarted by the supervisor.
+pub(crate) async fn provide_queued<Types: RepoTypes>(
+    provider: Provider<Types>,
+    queue: Arc<Mutex<Receiver<Cid>>>,
+) -> Result<(), Error> {
+    let mut queue = queue.lock().await;
+    let provider = &provider;
+
+    (&mut *queue)
+        .for_each_concurrent(PARALLELISM, |cid| async move {
+            if let Err(e) = provider.provide(cid.clone()).await {
+                debug!(%cid, "failed to provide: {}", e);
+            }
+        })
+        .await;
+
+    Ok(())
+}
+
+/// Publishes the provider records of the stored blocks every `interval`, starting shortly after
+/// the node has started. Returns only if the blocks cannot be listed.
+pub(crate) async fn run<Types: RepoTypes>(
+    provider: Provider<Types>,
+    interval: Duration,
+) -> Result<(), Error> {
+    Types::TRuntime::delay_for(INITIAL_DELAY).await;
+
+    loop {
+        let started = Instant::now();
+        let (provided, failed) = reprovide(&provider).await?;
+        info!(
+            provided,
+            failed,
+            elapsed = ?started.elapsed(),
+            "reprovided the stored blocks"
+        );
+
+        Types::TRuntime::delay_for(interval).await;
+    }
+}
+
+/// Publishes the provider records of all of the stored blocks once, returning the number of the
+/// records published and of the ones which could not be.
+pub(crate) async fn reprovide<Types: RepoTypes>(
+    provider: &Provider<Types>,
+) -> Result<(usize, usize), Error> {
+    let cids = provider.repo.list_blocks().await?;
+
+    let results = stream::iter(cids)
+        .map(|cid| async move {
+            let res = provider.provide(cid.clone()).await;
+            if let Err(ref e) = res {
+                debug!(%cid, "failed to reprovide: {}", e);
+            }
+            res
+        })
+        .buffer_unordered(PARALLELISM)
+        .collect::<Vec<_>>()
+        .await;
+
+    let provided = results.iter().filter(|res| res.is_ok()).count();
+    Ok((provided, results.len() - provided))
+}
@@ -0,0 +1,56 @@
+//! The executor of the background tasks, the blocking work and the timers of the node, supplied
+//! through [`crate::RepoTypes::TRuntime`] so that embedders can use their own executor.
+//!
+//! The filesystem backed stores in [`crate::repo::fs`] run all of their file system work on the
+//! blocking threads of the runtime they are parameterized with; the tokio synchronization types
+//! they use work on any executor. The TCP transport and the mdns discovery still require a tokio
+//! runtime to be entered, as does the blocking API of [`crate::sync`].
+
+use futures::future::BoxFuture;
+use std::fmt;
+use std::time::Duration;
+
+/// The executor used by the node.
+pub trait Runtime: fmt::Debug + Send + Sync + 'static {
+    /// Runs the future in the background, detached from the caller.
+    fn spawn(future: BoxFuture<'static, ()>);
+
+    /// Runs the closure on a thread where blocking is allowed, completing with its result.
+    ///
+    /// # Panics
+    ///
+    /// The returned future panics if the closure panicked.
+    fn spawn_blocking<F, R>(f: F) -> BoxFuture<'static, R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static;
+
+    /// Completes once the `duration` has elapsed.
+    fn delay_for(duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The default [`Runtime`] on top of the tokio runtime the node is started in.
+#[derive(Debug)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn(future: BoxFuture<'static, ()>) {
+        tokio::task::spawn(future);
+    }
+
+    fn spawn_blocking<F, R>(f: F) -> BoxFuture<'static, R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(f)
+                .await
+                .expect("the blocking task panicked")
+        })
+    }
+
+    fn delay_for(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::delay_for(duration))
+    }
+}
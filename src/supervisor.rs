@@ -0,0 +1,252 @@
+//! Supervision of the background tasks of a node: the panics of the swarm future are caught and
+//! reported instead of silently stopping the node, and the restartable tasks started through
+//! [`crate::Ipfs::supervise`] are restarted with a backoff after crashing. The failures are
+//! published as [`IpfsEvent::TaskFailed`] and the states of the tasks are included in the
+//! [`crate::health::Health`].
+
+use crate::error::Error;
+use crate::events::{EventBus, IpfsEvent};
+use crate::runtime::Runtime;
+use futures::future::{Future, FutureExt};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The state of a supervised task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    /// The task is running, after the given number of restarts.
+    Running { restarts: u32 },
+    /// The task crashed and is waiting for the backoff to elapse before restarting.
+    Restarting { restarts: u32 },
+    /// The task crashed and is not restarted anymore.
+    Failed,
+    /// The task completed.
+    Finished,
+}
+
+/// How the crashed tasks are restarted: after the `initial_backoff`, doubling for each
+/// consecutive crash up to the `max_backoff`, until `max_restarts` restarts have been made.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// The backoff before the restart following the given number of earlier restarts.
+    fn backoff(&self, restarts: u32) -> Duration {
+        let factor = 1u32.checked_shl(restarts).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map(|backoff| backoff.min(self.max_backoff))
+            .unwrap_or(self.max_backoff)
+    }
+}
+
+/// Tracks the states of the supervised tasks of a node, shared between the clones.
+#[derive(Debug, Clone)]
+pub(crate) struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<&'static str, TaskState>>>,
+    bus: EventBus,
+}
+
+impl Supervisor {
+    pub(crate) fn new(bus: EventBus) -> Self {
+        Supervisor {
+            tasks: Default::default(),
+            bus,
+        }
+    }
+
+    /// The current states of the supervised tasks by their names.
+    pub(crate) fn tasks(&self) -> BTreeMap<&'static str, TaskState> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    fn set(&self, name: &'static str, state: TaskState) {
+        self.tasks.lock().unwrap().insert(name, state);
+    }
+
+    fn failed(&self, name: &'static str, reason: String, restarting: bool) {
+        error!(task = name, restarting, "task failed: {}", reason);
+        self.bus.publish(IpfsEvent::TaskFailed {
+            task: name,
+            reason,
+            restarting,
+        });
+    }
+
+    /// Wraps the future, which cannot be restarted, so that its panic is recorded and reported
+    /// before the panic is resumed.
+    pub(crate) fn guard<F>(&self, name: &'static str, future: F) -> impl Future<Output = ()>
+    where
+        F: Future<Output = ()>,
+    {
+        let supervisor = self.clone();
+        supervisor.set(name, TaskState::Running { restarts: 0 });
+
+        async move {
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(()) => supervisor.set(name, TaskState::Finished),
+                Err(panic) => {
+                    supervisor.set(name, TaskState::Failed);
+                    supervisor.failed(name, panic_message(&*panic), false);
+                    std::panic::resume_unwind(panic);
+                }
+            }
+        }
+    }
+
+    /// Spawns the task created by the `factory`, creating and spawning it again after it panics
+    /// or returns an error, as allowed by the `policy`.
+    pub(crate) fn supervise<R, F, Fut>(
+        &self,
+        name: &'static str,
+        policy: RestartPolicy,
+        mut factory: F,
+    ) where
+        R: Runtime,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        supervisor.set(name, TaskState::Running { restarts: 0 });
+
+        R::spawn(Box::pin(async move {
+            let mut restarts = 0;
+
+            loop {
+                let reason = match AssertUnwindSafe(factory()).catch_unwind().await {
+                    Ok(Ok(())) => {
+                        supervisor.set(name, TaskState::Finished);
+                        return;
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(panic) => panic_message(&*panic),
+                };
+
+                if restarts >= policy.max_restarts {
+                    supervisor.set(name, TaskState::Failed);
+                    supervisor.failed(name, reason, false);
+                    return;
+                }
+
+                supervisor.set(name, TaskState::Restarting { restarts });
+                supervisor.failed(name, reason, true);
+
+                R::delay_for(policy.backoff(restarts)).await;
+
+                restarts += 1;
+                supervisor.set(name, TaskState::Running { restarts });
+            }
+        }));
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RestartPolicy, Supervisor, TaskState};
+    use crate::events::{EventBus, IpfsEvent};
+    use crate::runtime::TokioRuntime;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RestartPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(8));
+        assert_eq!(policy.backoff(10), Duration::from_secs(60));
+        assert_eq!(policy.backoff(40), Duration::from_secs(60));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn restarts_until_finished() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let supervisor = Supervisor::new(bus);
+        let runs = Arc::new(AtomicU32::new(0));
+
+        supervisor.supervise::<TokioRuntime, _, _>("flaky", policy(5), {
+            let runs = Arc::clone(&runs);
+            move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if run < 2 {
+                        panic!("run {}", run);
+                    }
+                    Ok(())
+                }
+            }
+        });
+
+        for run in 0..2 {
+            assert_eq!(
+                events.recv().await.unwrap(),
+                IpfsEvent::TaskFailed {
+                    task: "flaky",
+                    reason: format!("run {}", run),
+                    restarting: true,
+                }
+            );
+        }
+
+        while supervisor.tasks()["flaky"] != TaskState::Finished {
+            tokio::time::delay_for(Duration::from_millis(1)).await;
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn gives_up_after_max_restarts() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let supervisor = Supervisor::new(bus);
+
+        supervisor.supervise::<TokioRuntime, _, _>("broken", policy(1), || async {
+            Err(anyhow::anyhow!("broken"))
+        });
+
+        let restarting = |event| match event {
+            IpfsEvent::TaskFailed { restarting, .. } => restarting,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        assert!(restarting(events.recv().await.unwrap()));
+        assert!(!restarting(events.recv().await.unwrap()));
+        assert_eq!(supervisor.tasks()["broken"], TaskState::Failed);
+    }
+}
@@ -0,0 +1,199 @@
+//! Blocking wrapper around [`Ipfs`] for the applications and scripts which do not use async. The
+//! [`SyncIpfs`] runs the node on a runtime of its own, blocking the calling thread for the
+//! duration of each call.
+//!
+//! The methods must not be called from within an async context, such as from a future running
+//! on a tokio runtime.
+
+use crate::error::Error;
+use crate::unixfs::{AddOptions, AddedFile};
+use crate::{Block, Cid, Ipfs, IpfsOptions, IpfsPath, IpfsTypes, UninitializedIpfs};
+use crate::{Multiaddr, MultiaddrWithPeerId, PublicKey};
+use futures::stream::{self, TryStreamExt};
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::Path;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// The size of the reads of the files added with [`SyncIpfs::add_file`], matching the default
+/// chunk size.
+const READ_SIZE: usize = 256 * 1024;
+
+/// The blocking facade of a node running on a runtime owned by this value. The methods can be
+/// called from several threads at the same time.
+pub struct SyncIpfs<Types: IpfsTypes> {
+    ipfs: Ipfs<Types>,
+    handle: Handle,
+    runtime: Runtime,
+}
+
+impl<Types: IpfsTypes> SyncIpfs<Types> {
+    /// Starts the node with the `options` on a new runtime.
+    pub fn start(options: IpfsOptions) -> Result<Self, Error> {
+        let mut runtime = Builder::new().threaded_scheduler().enable_all().build()?;
+
+        let (ipfs, fut) = runtime.block_on(UninitializedIpfs::<Types>::new(options).start())?;
+        runtime.spawn(fut);
+
+        Ok(SyncIpfs {
+            ipfs,
+            handle: runtime.handle().clone(),
+            runtime,
+        })
+    }
+
+    /// Returns the async facade of the node, for the functionality not wrapped here.
+    pub fn ipfs(&self) -> &Ipfs<Types> {
+        &self.ipfs
+    }
+
+    /// Runs the future to completion on the runtime of the node.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+
+    /// Adds the file at the `path` as an UnixFS file with the default [`AddOptions`].
+    pub fn add_file<P: AsRef<Path>>(&self, path: P) -> Result<AddedFile, Error> {
+        let mut file = File::open(path)?;
+
+        // the reads block the calling thread, which the future is polled on
+        let chunks = std::iter::from_fn(move || {
+            let mut buf = vec![0; READ_SIZE];
+            match file.read(&mut buf) {
+                Ok(0) => None,
+                Ok(read) => {
+                    buf.truncate(read);
+                    Some(Ok::<_, io::Error>(buf))
+                }
+                Err(e) => Some(Err(e)),
+            }
+        });
+
+        let added = self.block_on(crate::unixfs::add(
+            &self.ipfs,
+            stream::iter(chunks),
+            AddOptions::default(),
+        ))?;
+
+        Ok(added)
+    }
+
+    /// Adds the `bytes` as an UnixFS file with the default [`AddOptions`].
+    pub fn add_bytes(&self, bytes: &[u8]) -> Result<AddedFile, Error> {
+        let content = stream::once(async { Ok::<_, io::Error>(bytes) });
+        let added = self.block_on(crate::unixfs::add(
+            &self.ipfs,
+            content,
+            AddOptions::default(),
+        ))?;
+
+        Ok(added)
+    }
+
+    /// Reads the whole UnixFS file at the `path`.
+    pub fn cat(&self, path: IpfsPath) -> Result<Vec<u8>, Error> {
+        self.block_on(async {
+            let content = self.ipfs.cat_unixfs(path, None).await?;
+            Ok(content.try_concat().await?)
+        })
+    }
+
+    /// See [`Ipfs::put_block`].
+    pub fn put_block(&self, block: Block) -> Result<Cid, Error> {
+        self.block_on(self.ipfs.put_block(block))
+    }
+
+    /// See [`Ipfs::get_block`].
+    pub fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
+        self.block_on(self.ipfs.get_block(cid))
+    }
+
+    /// See [`Ipfs::insert_pin`].
+    pub fn pin_add(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
+        self.block_on(self.ipfs.insert_pin(cid, recursive))
+    }
+
+    /// See [`Ipfs::remove_pin`].
+    pub fn pin_rm(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
+        self.block_on(self.ipfs.remove_pin(cid, recursive))
+    }
+
+    /// See [`Ipfs::is_pinned`].
+    pub fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
+        self.block_on(self.ipfs.is_pinned(cid))
+    }
+
+    /// See [`Ipfs::resolve_ipns`].
+    pub fn resolve_ipns(&self, path: &IpfsPath, recursive: bool) -> Result<IpfsPath, Error> {
+        self.block_on(self.ipfs.resolve_ipns(path, recursive))
+    }
+
+    /// See [`Ipfs::connect`].
+    pub fn connect(&self, target: MultiaddrWithPeerId) -> Result<(), Error> {
+        self.block_on(self.ipfs.connect(target))
+    }
+
+    /// See [`Ipfs::identity`].
+    pub fn identity(&self) -> Result<(PublicKey, Vec<Multiaddr>), Error> {
+        self.block_on(self.ipfs.identity())
+    }
+
+    /// Stops the node and its runtime.
+    pub fn exit(self) {
+        let SyncIpfs {
+            ipfs, mut runtime, ..
+        } = self;
+        runtime.block_on(ipfs.exit_daemon());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncIpfs;
+    use crate::{IpfsOptions, IpfsPath, TestTypes};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn add_and_cat_without_async() {
+        let ipfs =
+            SyncIpfs::<TestTypes>::start(IpfsOptions::inmemory_with_generated_keys()).unwrap();
+
+        let added = ipfs.add_bytes(b"foobar\n").unwrap();
+        let path = IpfsPath::from(added.root.clone());
+
+        assert_eq!(ipfs.cat(path).unwrap(), b"foobar\n");
+
+        ipfs.pin_add(&added.root, true).unwrap();
+        assert!(ipfs.is_pinned(&added.root).unwrap());
+
+        ipfs.exit();
+    }
+
+    #[test]
+    fn calls_from_several_threads() {
+        let ipfs = Arc::new(
+            SyncIpfs::<TestTypes>::start(IpfsOptions::inmemory_with_generated_keys()).unwrap(),
+        );
+
+        let threads = (0..4u8)
+            .map(|i| {
+                let ipfs = Arc::clone(&ipfs);
+                thread::spawn(move || {
+                    let added = ipfs.add_bytes(&[i; 16]).unwrap();
+                    ipfs.cat(IpfsPath::from(added.root)).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap(), vec![i as u8; 16]);
+        }
+
+        match Arc::try_unwrap(ipfs) {
+            Ok(ipfs) => ipfs.exit(),
+            Err(_) => unreachable!("all of the threads were joined"),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Support for the tests running many nodes in the same process. The nodes spawned by
+//! [`spawn_nodes`] have in-memory repos and are connected over the libp2p memory transport, so
+//! that the tests for bitswap, the DHT or pubsub need neither the disk nor the network.
+//!
+//! Available with the `test-utils` feature, which the integration tests enable through the
+//! dev-dependency of the crate on itself.
+
+#[cfg(feature = "test-utils")]
+pub mod doubles;
+
+use crate::error::Error;
+use crate::{IpfsOptions, Node};
+use cid::Cid;
+use libp2p::multiaddr::Protocol;
+use std::time::{Duration, Instant};
+
+/// The interval at which [`wait_for_block`] checks the nodes.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The way in which nodes are connected to each other; to be used with [`spawn_nodes`] or
+/// [`connect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// no connections
+    None,
+    /// a > b > c
+    Line,
+    /// a > b > c > a
+    Ring,
+    /// a <> b <> c <> a
+    Mesh,
+    /// a > b, a > c
+    Star,
+}
+
+/// Spawns `count` nodes listening on the memory transport and connects them in the `topology`.
+/// The nodes are named by their index for the spans.
+pub async fn spawn_nodes(count: usize, topology: Topology) -> Vec<Node> {
+    let mut nodes = Vec::with_capacity(count);
+
+    for i in 0..count {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.listening_addrs = vec![Protocol::Memory(0).into()];
+        opts.span = Some(trace_span!("ipfs", node = %i));
+
+        nodes.push(Node::with_options(opts).await);
+    }
+
+    connect(&nodes, topology).await;
+
+    nodes
+}
+
+/// Connects the nodes in the `topology` through the first listening address of each node.
+///
+/// # Panics
+///
+/// When connecting fails.
+pub async fn connect(nodes: &[Node], topology: Topology) {
+    let count = nodes.len();
+
+    match topology {
+        Topology::Line | Topology::Ring => {
+            for i in 0..count.saturating_sub(1) {
+                nodes[i]
+                    .connect(nodes[i + 1].addrs[0].clone())
+                    .await
+                    .unwrap();
+            }
+            if topology == Topology::Ring && count > 1 {
+                nodes[count - 1]
+                    .connect(nodes[0].addrs[0].clone())
+                    .await
+                    .unwrap();
+            }
+        }
+        Topology::Mesh => {
+            for i in 0..count {
+                for (j, peer) in nodes.iter().enumerate() {
+                    if i != j {
+                        nodes[i].connect(peer.addrs[0].clone()).await.unwrap();
+                    }
+                }
+            }
+        }
+        Topology::Star => {
+            for node in nodes.iter().skip(1) {
+                nodes[0].connect(node.addrs[0].clone()).await.unwrap();
+            }
+        }
+        Topology::None => {}
+    }
+}
+
+/// Waits until all of the `nodes` have stored the block, such as after it has propagated to them
+/// through bitswap. Fails if some of the nodes have not stored the block within the `timeout`.
+pub async fn wait_for_block(nodes: &[Node], cid: &Cid, timeout: Duration) -> Result<(), Error> {
+    let started = Instant::now();
+    let mut pending = nodes.iter().collect::<Vec<_>>();
+
+    loop {
+        let mut remaining = Vec::with_capacity(pending.len());
+        for node in pending {
+            if !node.repo.contains_block(cid).await? {
+                remaining.push(node);
+            }
+        }
+        pending = remaining;
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(anyhow::anyhow!(
+                "{} was not stored by {} of the {} nodes within {:?}",
+                cid,
+                pending.len(),
+                nodes.len(),
+                timeout
+            ));
+        }
+
+        tokio::time::delay_for(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spawn_nodes, wait_for_block, Topology};
+    use crate::Block;
+    use cid::{Cid, Codec};
+    use futures::future::try_join_all;
+    use multihash::Sha2_256;
+    use std::time::Duration;
+
+    const N: usize = 4;
+
+    #[tokio::test(max_threads = 1)]
+    async fn topologies_over_memory() {
+        let nodes = spawn_nodes(N, Topology::Star).await;
+
+        assert!(nodes[0].addrs[0].to_string().starts_with("/memory/"));
+        assert_eq!(nodes[0].peers().await.unwrap().len(), N - 1);
+        for node in &nodes[1..] {
+            assert_eq!(node.peers().await.unwrap().len(), 1);
+        }
+
+        let nodes = spawn_nodes(N, Topology::Ring).await;
+        for node in &nodes {
+            assert_eq!(node.peers().await.unwrap().len(), 2);
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn block_propagates_to_all() {
+        let nodes = spawn_nodes(N, Topology::Mesh).await;
+
+        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        nodes[0]
+            .put_block(Block::new(data, cid.clone()))
+            .await
+            .unwrap();
+
+        let timeout = Duration::from_millis(50);
+        assert!(wait_for_block(&nodes, &cid, timeout).await.is_err());
+
+        try_join_all(nodes[1..].iter().map(|node| node.get_block(&cid)))
+            .await
+            .unwrap();
+
+        wait_for_block(&nodes, &cid, Duration::from_secs(10))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,346 @@
+//! Test doubles for the code embedding the node, available with the `test-utils` feature: a
+//! blockstore answering with scripted responses, a wrapper recording the calls to any blockstore,
+//! and a [`ScriptedPeer`] answering the bitswap wants over the libp2p memory transport with the
+//! scripted responses. Nodes using the blockstores are created with [`MockTypes`], and the stores
+//! are reached through [`crate::Ipfs::block_store`].
+
+use crate::error::Error;
+use crate::repo::mem::{MemBlockStore, MemDataStore};
+use crate::repo::{BlockData, BlockPut, BlockRm, BlockRmError, BlockStore, RepoTypes};
+use crate::runtime::{Runtime, TokioRuntime};
+use crate::{Block, Ipfs, IpfsOptions, UninitializedIpfs};
+use async_trait::async_trait;
+use cid::Cid;
+use libp2p::multiaddr::{Multiaddr, Protocol};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The repo types with the [`ScriptedBlockStore`] wrapped in the [`RecordingBlockStore`].
+#[derive(Debug)]
+pub struct MockTypes;
+impl RepoTypes for MockTypes {
+    type TBlockStore = RecordingBlockStore<ScriptedBlockStore>;
+    type TDataStore = MemDataStore;
+    type TRuntime = TokioRuntime;
+}
+
+/// The scripted response to the reads of a block.
+#[derive(Debug, Clone)]
+pub enum Scripted {
+    /// The block is found, regardless of whether it has been stored.
+    Found(Block),
+    /// The block is not found, regardless of whether it has been stored.
+    Missing,
+    /// The read fails with the error message.
+    Fail(String),
+    /// The read never completes, as if the request was dropped.
+    Hang,
+}
+
+/// A blockstore answering the reads of the scripted Cids with the scripted responses, after the
+/// optional delay. The other Cids are stored in memory. Unlike the stores of the repo, the
+/// scripted Cids are matched exactly, not by the multihash.
+#[derive(Debug, Default)]
+pub struct ScriptedBlockStore {
+    responses: Mutex<HashMap<Cid, Scripted>>,
+    delay: Mutex<Option<Duration>>,
+    fallback: MemBlockStore,
+}
+
+impl ScriptedBlockStore {
+    /// Answers the following reads of the `cid` with the `response`.
+    pub fn respond(&self, cid: Cid, response: Scripted) {
+        self.responses.lock().unwrap().insert(cid, response);
+    }
+
+    /// Removes the scripted response of the `cid`.
+    pub fn forget(&self, cid: &Cid) {
+        self.responses.lock().unwrap().remove(cid);
+    }
+
+    /// Delays all of the following operations by the `delay`, or stops delaying for `None`.
+    pub fn set_delay(&self, delay: Option<Duration>) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    async fn delayed(&self) {
+        let delay = *self.delay.lock().unwrap();
+        if let Some(delay) = delay {
+            <MockTypes as RepoTypes>::TRuntime::delay_for(delay).await;
+        }
+    }
+
+    fn scripted(&self, cid: &Cid) -> Option<Scripted> {
+        self.responses.lock().unwrap().get(cid).cloned()
+    }
+}
+
+#[async_trait]
+impl BlockStore for ScriptedBlockStore {
+    fn new(_path: PathBuf) -> Self {
+        Default::default()
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        Ok(self.get(cid).await?.is_some())
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.delayed().await;
+
+        match self.scripted(cid) {
+            Some(Scripted::Found(block)) => Ok(Some(block)),
+            Some(Scripted::Missing) => Ok(None),
+            Some(Scripted::Fail(message)) => Err(anyhow::anyhow!(message)),
+            Some(Scripted::Hang) => futures::future::pending().await,
+            None => self.fallback.get(cid).await,
+        }
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        self.delayed().await;
+        self.fallback.put(block).await
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.delayed().await;
+        self.fallback.remove(cid).await
+    }
+
+    async fn list(&self) -> Result<Vec<Cid>, Error> {
+        self.delayed().await;
+        self.fallback.list().await
+    }
+
+    async fn wipe(&self) {
+        self.responses.lock().unwrap().clear();
+        self.fallback.wipe().await
+    }
+}
+
+/// A call made to the [`RecordingBlockStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockStoreCall {
+    Contains(Cid),
+    Get(Cid),
+    GetData(Cid),
+    Put(Cid),
+    Remove(Cid),
+    List,
+    Wipe,
+}
+
+/// Records the calls to the wrapped blockstore in the order they were made.
+#[derive(Debug)]
+pub struct RecordingBlockStore<S> {
+    inner: S,
+    calls: Mutex<Vec<BlockStoreCall>>,
+}
+
+impl<S> RecordingBlockStore<S> {
+    /// Returns the wrapped blockstore.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the calls made so far.
+    pub fn calls(&self) -> Vec<BlockStoreCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns and forgets the calls made so far.
+    pub fn take_calls(&self) -> Vec<BlockStoreCall> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    fn record(&self, call: BlockStoreCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[async_trait]
+impl<S: BlockStore> BlockStore for RecordingBlockStore<S> {
+    fn new(path: PathBuf) -> Self {
+        RecordingBlockStore {
+            inner: S::new(path),
+            calls: Default::default(),
+        }
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        self.inner.init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        self.inner.open().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        self.record(BlockStoreCall::Contains(cid.to_owned()));
+        self.inner.contains(cid).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.record(BlockStoreCall::Get(cid.to_owned()));
+        self.inner.get(cid).await
+    }
+
+    async fn get_data(&self, cid: &Cid) -> Result<Option<BlockData>, Error> {
+        self.record(BlockStoreCall::GetData(cid.to_owned()));
+        self.inner.get_data(cid).await
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        self.record(BlockStoreCall::Put(block.cid.clone()));
+        self.inner.put(block).await
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.record(BlockStoreCall::Remove(cid.to_owned()));
+        self.inner.remove(cid).await
+    }
+
+    async fn list(&self) -> Result<Vec<Cid>, Error> {
+        self.record(BlockStoreCall::List);
+        self.inner.list().await
+    }
+
+    async fn wipe(&self) {
+        self.record(BlockStoreCall::Wipe);
+        self.inner.wipe().await
+    }
+}
+
+/// A peer listening on the libp2p memory transport, which answers the bitswap wants of the nodes
+/// connected to it with the scripted responses of its [`ScriptedBlockStore`], after the delay of
+/// the store. The wants of the [`Scripted::Missing`], [`Scripted::Fail`] and [`Scripted::Hang`]
+/// Cids are dropped without an answer, as by a peer which does not have the blocks.
+#[derive(Debug)]
+pub struct ScriptedPeer {
+    ipfs: Ipfs<MockTypes>,
+    /// The listening addresses of the peer, ending with its `/p2p/` for connecting to it.
+    pub addrs: Vec<Multiaddr>,
+}
+
+impl ScriptedPeer {
+    /// Starts the peer.
+    ///
+    /// # Panics
+    ///
+    /// When the node of the peer fails to start.
+    pub async fn spawn() -> Self {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.listening_addrs = vec![Protocol::Memory(0).into()];
+        opts.span = Some(trace_span!("ipfs", node = "scripted"));
+
+        let (ipfs, fut): (Ipfs<MockTypes>, _) = UninitializedIpfs::new(opts)
+            .start()
+            .await
+            .expect("the scripted peer failed to start");
+        <MockTypes as RepoTypes>::TRuntime::spawn(Box::pin(fut));
+
+        let addrs = ipfs.identity().await.expect("the node is running").1;
+
+        ScriptedPeer { ipfs, addrs }
+    }
+
+    /// Returns the blockstore for scripting the responses of the peer.
+    pub fn store(&self) -> &ScriptedBlockStore {
+        self.ipfs.block_store().inner()
+    }
+
+    /// Returns the node of the peer, such as for connecting it to the nodes or for the calls
+    /// recorded by its [`RecordingBlockStore`].
+    pub fn ipfs(&self) -> &Ipfs<MockTypes> {
+        &self.ipfs
+    }
+
+    /// Stops the node of the peer.
+    pub async fn shutdown(self) {
+        self.ipfs.exit_daemon().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockStoreCall, MockTypes, Scripted, ScriptedPeer};
+    use crate::repo::RepoTypes;
+    use crate::runtime::Runtime;
+    use crate::test_support::{spawn_nodes, Topology};
+    use crate::{Block, Ipfs, IpfsOptions, UninitializedIpfs};
+    use cid::{Cid, Codec};
+    use multihash::Sha2_256;
+    use std::time::Duration;
+
+    fn block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(data));
+        Block::new(data.to_vec().into_boxed_slice(), cid)
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn scripted_and_recorded() {
+        let (ipfs, fut): (Ipfs<MockTypes>, _) =
+            UninitializedIpfs::new(IpfsOptions::inmemory_with_generated_keys())
+                .start()
+                .await
+                .unwrap();
+        <MockTypes as RepoTypes>::TRuntime::spawn(Box::pin(fut));
+
+        let stored = block(b"stored");
+        let scripted = block(b"scripted");
+
+        ipfs.put_block(stored.clone()).await.unwrap();
+
+        let store = ipfs.block_store();
+        store.inner().respond(stored.cid.clone(), Scripted::Missing);
+        store
+            .inner()
+            .respond(scripted.cid.clone(), Scripted::Found(scripted.clone()));
+
+        assert!(!ipfs.repo.contains_block(&stored.cid).await.unwrap());
+        assert_eq!(
+            ipfs.get_block(&scripted.cid).await.unwrap().data,
+            scripted.data
+        );
+
+        let calls = store.take_calls();
+        assert!(calls.contains(&BlockStoreCall::Contains(stored.cid.clone())));
+        assert!(calls.contains(&BlockStoreCall::Get(scripted.cid.clone())));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn scripted_peer_answers_the_wants() {
+        let peer = ScriptedPeer::spawn().await;
+        let nodes = spawn_nodes(1, Topology::None).await;
+        nodes[0].connect(peer.addrs[0].clone()).await.unwrap();
+
+        let found = block(b"found");
+        let dropped = block(b"dropped");
+
+        peer.store()
+            .respond(found.cid.clone(), Scripted::Found(found.clone()));
+        peer.store().respond(dropped.cid.clone(), Scripted::Hang);
+        peer.store().set_delay(Some(Duration::from_millis(10)));
+
+        assert_eq!(nodes[0].get_block(&found.cid).await.unwrap(), found);
+
+        let timeout = Duration::from_millis(200);
+        assert!(
+            tokio::time::timeout(timeout, nodes[0].get_block(&dropped.cid))
+                .await
+                .is_err()
+        );
+
+        peer.shutdown().await;
+    }
+}
@@ -0,0 +1,683 @@
+use crate::repo::BlockPut;
+use crate::runtime::Runtime;
+use crate::{Block, Error, Ipfs, IpfsTypes};
+use async_stream::try_stream;
+use cid::Cid;
+use futures::future;
+use futures::io::{AsyncRead, AsyncReadExt};
+use futures::stream::{self, Stream, StreamExt};
+use ipfs_unixfs::file::adder::{
+    BalancedCollector, Chunker, Collector, FileAdder, TrickleCollector,
+};
+use ipfs_unixfs::CidOptions;
+use std::borrow::Borrow;
+use std::io;
+use tokio::sync::mpsc;
+use tracing_futures::Instrument;
+
+/// Options for adding UnixFS files with [`add`].
+#[derive(Debug, Clone)]
+pub struct AddOptions {
+    /// The chunker used to split the file into leaf blocks; defaults to 256 KiB fixed size chunks
+    /// like in go-ipfs. Smaller chunks can deduplicate better at the cost of more blocks.
+    pub chunker: Chunker,
+    /// The layout of the file tree; defaults to the balanced layout with at most 174 links per
+    /// link block like in go-ipfs.
+    pub collector: Collector,
+    /// When true, the blockstore is checked for each created block and the blocks which exist
+    /// already are not handed to the blockstore again. Speeds up re-adding mostly unchanged
+    /// content. Defaults to false.
+    pub dedup: bool,
+    /// The Cid version, hash function and the inlining of small blocks; defaults to Cid version 0
+    /// with SHA2-256 like in go-ipfs. Inlined blocks are not stored.
+    pub cid_options: CidOptions,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        AddOptions {
+            chunker: Chunker::default(),
+            collector: Collector::default(),
+            dedup: false,
+            cid_options: CidOptions::default(),
+        }
+    }
+}
+
+impl AddOptions {
+    /// Configures fixed size chunking with the given chunk size in bytes.
+    ///
+    /// # Panics
+    ///
+    /// When `chunk_size` is zero.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be larger than zero");
+        self.chunker = Chunker::Size(chunk_size);
+        self
+    }
+
+    /// Configures the chunker, which can also be parsed from the go-ipfs style strings like
+    /// `size-262144`, `rabin-{min}-{avg}-{max}` or `buzhash`:
+    ///
+    /// ```
+    /// # use ipfs::unixfs::AddOptions;
+    /// let opts = AddOptions::default().with_chunker("rabin-16k-64k-256k".parse().unwrap());
+    /// ```
+    pub fn with_chunker(mut self, chunker: Chunker) -> Self {
+        self.chunker = chunker;
+        self
+    }
+
+    /// Configures the balanced layout with at most `branching_factor` links per link block. A
+    /// smaller branching factor trades deeper trees for smaller link blocks.
+    ///
+    /// # Panics
+    ///
+    /// When `branching_factor` is zero.
+    pub fn with_branching_factor(mut self, branching_factor: usize) -> Self {
+        self.collector = BalancedCollector::with_branching_factor(branching_factor).into();
+        self
+    }
+
+    /// Configures the trickle layout like go-ipfs `add --trickle`, which is faster to read
+    /// sequentially from the start, for example when streaming media.
+    pub fn with_trickle(mut self) -> Self {
+        self.collector = TrickleCollector::default().into();
+        self
+    }
+
+    /// Configures checking the blockstore for existing blocks before storing them.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Configures the Cid version, the hash function and the inline limit.
+    pub fn with_cid_options(mut self, cid_options: CidOptions) -> Self {
+        self.cid_options = cid_options;
+        self
+    }
+}
+
+/// The outcome of [`add`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddedFile {
+    /// The Cid of the root block of the file.
+    pub root: Cid,
+    /// The total size of all of the blocks of the file.
+    pub total_size: u64,
+    /// The total size of the blocks which already existed in the blockstore.
+    pub deduplicated: u64,
+}
+
+/// Progress of adding a file with [`add_with_progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddProgress {
+    /// Reported after the blocks created from an item of the `content` stream have been stored.
+    /// The values are cumulative.
+    Progress {
+        /// The amount of bytes read from the `content` stream.
+        bytes: u64,
+        /// The amount of blocks created.
+        blocks: u64,
+        /// The total size of the blocks which already existed in the blockstore.
+        deduplicated: u64,
+    },
+    /// The file has been added; this is always the last item.
+    Finished(AddedFile),
+}
+
+/// Adds the bytes from the `content` stream as an UnixFS file, storing the blocks as they are
+/// created. This is generic over the different kinds of ways to own an `Ipfs` value, similar to
+/// [`crate::unixfs::cat`].
+///
+/// Returns the Cid of the root block and the sizes of the blocks of the file, including the size
+/// of the blocks which already existed. See [`add_with_progress`] for following the progress.
+pub async fn add<Types, MaybeOwned, St, B, E>(
+    ipfs: MaybeOwned,
+    content: St,
+    opts: AddOptions,
+) -> Result<AddedFile, AddError>
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>>,
+    St: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<Error>,
+{
+    // the root is recorded once known, for the exported traces to carry it
+    let span = debug_span!("add", cid = tracing::field::Empty);
+
+    async {
+        let progress = add_with_progress(ipfs, content, opts);
+        futures::pin_mut!(progress);
+
+        while let Some(next) = progress.next().await {
+            if let AddProgress::Finished(added) = next? {
+                span.record("cid", &tracing::field::display(&added.root));
+                return Ok::<_, AddError>(added);
+            }
+        }
+
+        unreachable!("the progress always ends in an error or AddProgress::Finished");
+    }
+    .instrument(span.clone())
+    .await
+}
+
+/// The size of the reads from the reader given to [`add_from_reader`].
+const READ_SIZE: usize = 256 * 1024;
+
+/// Adds the bytes read from the `reader` until its end as an UnixFS file like [`add`], storing
+/// the blocks as they are created.
+pub async fn add_from_reader<Types, MaybeOwned, R>(
+    ipfs: MaybeOwned,
+    reader: R,
+    opts: AddOptions,
+) -> Result<AddedFile, AddError>
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>>,
+    R: AsyncRead,
+{
+    add(ipfs, read_chunks(reader), opts).await
+}
+
+/// Turns the `reader` into a stream of the chunks read from it.
+fn read_chunks<R: AsyncRead>(reader: R) -> impl Stream<Item = io::Result<Vec<u8>>> {
+    stream::unfold(Some(Box::pin(reader)), |reader| async move {
+        let mut reader = reader?;
+        let mut buf = vec![0u8; READ_SIZE];
+
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) => return None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    return Some((Ok(buf), Some(reader)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                // the stream ends after the error
+                Err(e) => return Some((Err(e), None)),
+            }
+        }
+    })
+}
+
+/// Adds the bytes from the `content` stream as an UnixFS file like [`add`], returning a stream of
+/// the progress, which can be used to display the progress of adding large files. The last item
+/// is either an error or [`AddProgress::Finished`].
+pub fn add_with_progress<'a, Types, MaybeOwned, St, B, E>(
+    ipfs: MaybeOwned,
+    content: St,
+    opts: AddOptions,
+) -> impl Stream<Item = Result<AddProgress, AddError>> + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + 'a,
+    St: Stream<Item = Result<B, E>> + 'a,
+    B: AsRef<[u8]> + 'a,
+    E: Into<Error> + 'a,
+{
+    try_stream! {
+        let ipfs = ipfs.borrow();
+        // keeps the blocks of the file from being collected before all of them have been stored
+        let _guard = ipfs.gc_guard().await;
+        let dedup = opts.dedup;
+
+        let adder = FileAdder::builder()
+            .with_chunker(opts.chunker)
+            .with_collector(opts.collector)
+            .with_cid_options(opts.cid_options)
+            .build();
+
+        // the items of the content are chunked and hashed on a blocking thread while the next
+        // items are read and the blocks of the previous items are stored
+        let (input_tx, input_rx) = mpsc::channel(QUEUED_ITEMS);
+        let (hashed_tx, hashed_rx) = mpsc::channel(QUEUED_ITEMS);
+        Types::TRuntime::spawn(Box::pin(
+            hash_blocks::<Types::TRuntime>(adder, input_rx, hashed_tx).in_current_span(),
+        ));
+
+        // reading only ever produces the error of the content, as the read items go to hashing
+        let reading = stream::once(read_items(content, input_tx))
+            .filter_map(|res| future::ready(res.err().map(Err)));
+
+        // the stores of the items complete in the order of the items for the progress
+        let storing = hashed_rx
+            .map(|hashed| store_all(ipfs, hashed, dedup))
+            .buffered(QUEUED_ITEMS);
+
+        let stages = stream::select(reading, storing);
+        futures::pin_mut!(stages);
+
+        let mut sizes = Sizes::default();
+        let mut bytes_read = 0u64;
+        let mut root = None;
+
+        while let Some(stored) = stages.next().await {
+            let stored = stored?;
+
+            bytes_read += stored.bytes;
+            sizes.blocks += stored.sizes.blocks;
+            sizes.total += stored.sizes.total;
+            sizes.existing += stored.sizes.existing;
+
+            if stored.finished {
+                root = stored.last;
+                break;
+            }
+
+            yield AddProgress::Progress {
+                bytes: bytes_read,
+                blocks: sizes.blocks,
+                deduplicated: sizes.existing,
+            };
+        }
+
+        // the hashing task only stops early when it panics
+        let root = root.expect("finishing FileAdder always produces at least the root block");
+
+        yield AddProgress::Finished(AddedFile {
+            root,
+            total_size: sizes.total,
+            deduplicated: sizes.existing,
+        });
+    }
+}
+
+/// The items of the content read ahead of hashing, and the hashed items ahead of storing.
+const QUEUED_ITEMS: usize = 4;
+
+/// The blocks of an item stored at the same time.
+const CONCURRENT_PUTS: usize = 16;
+
+/// The blocks created from an item of the content, or when finishing the file.
+struct Hashed {
+    /// The length of the item, or zero when finishing.
+    bytes: u64,
+    blocks: Vec<(Cid, Vec<u8>)>,
+    finished: bool,
+}
+
+/// The outcome of storing the blocks of a [`Hashed`].
+struct Stored {
+    bytes: u64,
+    sizes: Sizes,
+    /// The Cid of the last block, which is the root when finished.
+    last: Option<Cid>,
+    finished: bool,
+}
+
+#[derive(Default)]
+struct Sizes {
+    blocks: u64,
+    total: u64,
+    existing: u64,
+}
+
+/// Sends the items of the content to be hashed until the content ends or the hashing stops.
+async fn read_items<St, B, E>(content: St, mut input: mpsc::Sender<Vec<u8>>) -> Result<(), AddError>
+where
+    St: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<Error>,
+{
+    futures::pin_mut!(content);
+
+    while let Some(next) = content.next().await {
+        let next = next.map_err(|e| AddError::Input(e.into()))?;
+
+        if input.send(next.as_ref().to_vec()).await.is_err() {
+            // the hashing stopped
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Chunks and hashes the items on a blocking thread, finishing the file once the input ends.
+async fn hash_blocks<R: Runtime>(
+    mut adder: FileAdder,
+    mut input: mpsc::Receiver<Vec<u8>>,
+    mut output: mpsc::Sender<Hashed>,
+) {
+    while let Some(item) = input.recv().await {
+        let len = item.len() as u64;
+
+        let (returned, blocks) = R::spawn_blocking(move || {
+            let mut blocks = Vec::new();
+            let mut bytes = &item[..];
+
+            while !bytes.is_empty() {
+                let (created, consumed) = adder.push(bytes);
+                blocks.extend(created);
+                bytes = &bytes[consumed..];
+            }
+
+            (adder, blocks)
+        })
+        .await;
+
+        adder = returned;
+
+        let hashed = Hashed {
+            bytes: len,
+            blocks,
+            finished: false,
+        };
+
+        if output.send(hashed).await.is_err() {
+            // the add was dropped
+            return;
+        }
+    }
+
+    let blocks = R::spawn_blocking(move || adder.finish().collect::<Vec<_>>()).await;
+
+    let hashed = Hashed {
+        bytes: 0,
+        blocks,
+        finished: true,
+    };
+
+    let _ = output.send(hashed).await;
+}
+
+/// Stores the blocks with up to [`CONCURRENT_PUTS`] of them at the same time. With `dedup` the
+/// blocks are stored only if they do not exist yet.
+async fn store_all<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    hashed: Hashed,
+    dedup: bool,
+) -> Result<Stored, AddError> {
+    let Hashed {
+        bytes,
+        blocks,
+        finished,
+    } = hashed;
+
+    let last = blocks.last().map(|(cid, _)| cid.to_owned());
+
+    let mut sizes = Sizes::default();
+
+    let mut puts = stream::iter(blocks)
+        .map(|(cid, data)| store(ipfs, cid, data, dedup))
+        .buffer_unordered(CONCURRENT_PUTS);
+
+    while let Some(res) = puts.next().await {
+        let (len, existed) = res?;
+        sizes.blocks += 1;
+        sizes.total += len;
+        if existed {
+            sizes.existing += len;
+        }
+    }
+
+    Ok(Stored {
+        bytes,
+        sizes,
+        last,
+        finished,
+    })
+}
+
+/// Stores the block unless it is inlined in the Cid, returning the length of the block and
+/// whether it existed already.
+async fn store<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    cid: Cid,
+    data: Vec<u8>,
+    dedup: bool,
+) -> Result<(u64, bool), AddError> {
+    let len = data.len() as u64;
+
+    if crate::repo::inlined_block(&cid).is_some() {
+        // the block is contained in the Cid
+        return Ok((len, false));
+    }
+
+    if dedup
+        && ipfs
+            .repo
+            .contains_block(&cid)
+            .await
+            .map_err(AddError::Persisting)?
+    {
+        return Ok((len, true));
+    }
+
+    let block = Block {
+        cid,
+        data: data.into(),
+    };
+
+    let (_, put) = ipfs
+        .repo
+        .put_block(block)
+        .await
+        .map_err(AddError::Persisting)?;
+
+    Ok((len, put == BlockPut::Existed))
+}
+
+/// Types of failures which can occur while adding an UnixFS file.
+#[derive(Debug, thiserror::Error)]
+pub enum AddError {
+    /// The input stream produced an error.
+    #[error("reading the input failed")]
+    Input(#[source] Error),
+
+    /// Storing a created block failed.
+    #[error("storing a block failed")]
+    Persisting(#[source] Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add, add_from_reader, add_with_progress, AddOptions, AddProgress, AddedFile, Chunker,
+    };
+    use crate::Node;
+    use futures::stream::{self, TryStreamExt};
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_from_reader_in_many_reads() {
+        let ipfs = Node::new("test_node").await;
+
+        // longer than a single read
+        let content = (0..super::READ_SIZE * 2 + 1)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+
+        let added = add_from_reader(&*ipfs, &content[..], AddOptions::default())
+            .await
+            .unwrap();
+
+        let from_stream = add(
+            &*ipfs,
+            stream::iter(vec![Ok::<_, std::io::Error>(content.clone())]),
+            AddOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        // the blocks were already stored by the first add, so only the rest can be compared
+        assert_eq!(added.root, from_stream.root);
+        assert_eq!(added.total_size, from_stream.total_size);
+
+        let read_back = crate::unixfs::cat(&*ipfs, added.root, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(read_back, content);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_with_chunk_size() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+
+        let cid = add(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .await
+            .unwrap()
+            .root;
+
+        // same as in `ipfs_unixfs::file::adder::tests::favourite_multi_block_file`
+        assert_eq!(
+            cid.to_string(),
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+        );
+
+        let read_back = crate::unixfs::cat(&*ipfs, cid, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(read_back, b"foobar\n");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn readding_deduplicates() {
+        let ipfs = Node::new("test_node").await;
+
+        let opts = AddOptions::default().with_chunk_size(2).with_dedup(true);
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let first = add(&*ipfs, content, opts.clone()).await.unwrap();
+        assert_eq!(first.deduplicated, 0);
+
+        // only the last leaf and the root change
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobaz\n".to_vec())]);
+        let second = add(&*ipfs, content, opts).await.unwrap();
+
+        assert_ne!(first.root, second.root);
+        assert_eq!(first.total_size, second.total_size);
+        assert!(second.deduplicated > 0);
+        assert!(second.deduplicated < second.total_size);
+
+        // without dedup the existing blocks are still reported
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobaz\n".to_vec())]);
+        let third = add(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            third,
+            AddedFile {
+                deduplicated: third.total_size,
+                ..second
+            }
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn progress_ends_in_finished() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![
+            Ok::<_, std::io::Error>(b"foo".to_vec()),
+            Ok(b"bar\n".to_vec()),
+        ]);
+
+        let progress = add_with_progress(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            &progress[..2],
+            &[
+                AddProgress::Progress {
+                    bytes: 3,
+                    blocks: 1,
+                    deduplicated: 0
+                },
+                AddProgress::Progress {
+                    bytes: 7,
+                    blocks: 3,
+                    deduplicated: 0
+                },
+            ]
+        );
+
+        match &progress[2] {
+            AddProgress::Finished(added) => assert_eq!(
+                added.root.to_string(),
+                "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+            ),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        assert_eq!(progress.len(), 3);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn many_items_produce_the_same_blocks() {
+        use ipfs_unixfs::file::adder::FileAdder;
+
+        let ipfs = Node::new("test_node").await;
+
+        let items = (0..100u32)
+            .map(|i| i.to_be_bytes().repeat(i as usize % 7))
+            .collect::<Vec<_>>();
+
+        let mut adder = FileAdder::builder().with_chunker(Chunker::Size(5)).build();
+        let mut expected = Vec::new();
+        for item in &items {
+            let mut bytes = &item[..];
+            while !bytes.is_empty() {
+                let (blocks, consumed) = adder.push(bytes);
+                expected.extend(blocks.map(|(cid, _)| cid));
+                bytes = &bytes[consumed..];
+            }
+        }
+        expected.extend(adder.finish().map(|(cid, _)| cid));
+
+        let content = stream::iter(items.into_iter().map(Ok::<_, std::io::Error>));
+        let added = add(&*ipfs, content, AddOptions::default().with_chunk_size(5))
+            .await
+            .unwrap();
+
+        assert_eq!(&added.root, expected.last().unwrap());
+
+        let mut stored = ipfs.refs_local().await.unwrap();
+        stored.sort_by_key(|cid| cid.to_string());
+        expected.sort_by_key(|cid| cid.to_string());
+        expected.dedup();
+        assert_eq!(stored, expected);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn inlined_leaves_are_not_stored() {
+        use ipfs_unixfs::CidOptions;
+
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let opts = AddOptions::default()
+            .with_chunk_size(2)
+            .with_cid_options(CidOptions::default().with_inline_limit(32));
+
+        let added = add(&*ipfs, content, opts).await.unwrap();
+
+        // only the root with the links is too large to be inlined
+        assert_eq!(ipfs.refs_local().await.unwrap(), vec![added.root.clone()]);
+
+        let read_back = crate::unixfs::cat(&*ipfs, added.root, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap();
+
+        assert_eq!(read_back, b"foobar\n");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::prefetch::{Prefetcher, DEFAULT_WINDOW};
 use crate::{
     dag::{ResolveError, UnexpectedResolved},
     Block, Error, Ipfs, IpfsTypes,
@@ -6,6 +7,7 @@ use async_stream::stream;
 use cid::Cid;
 use futures::stream::Stream;
 use ipfs_unixfs::file::{visit::IdleFileVisit, FileReadFailed};
+use ipfs_unixfs::{verify_block, VerificationFailed};
 use std::borrow::Borrow;
 use std::ops::Range;
 
@@ -14,7 +16,10 @@ use std::ops::Range;
 /// and an owned value. Passing an owned value allows the return value to be `'static`, which can
 /// be helpful in some contexts, like the http.
 ///
-/// Returns a stream of bytes on the file pointed with the Cid.
+/// Returns a stream of bytes on the file pointed with the Cid. Every block is verified against its
+/// Cid before any of its content is yielded, and the stream ends with
+/// [`TraversalFailed::Verification`] on the first corrupted block. The blocks of the pending links
+/// are fetched concurrently, up to a bounded window ahead of the walk.
 pub async fn cat<'a, Types, MaybeOwned>(
     ipfs: MaybeOwned,
     starting_point: impl Into<StartingPoint>,
@@ -25,7 +30,7 @@ where
     MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
 {
     let mut visit = IdleFileVisit::default();
-    if let Some(range) = range {
+    if let Some(range) = range.clone() {
         visit = visit.with_target_range(range);
     }
 
@@ -46,21 +51,35 @@ where
         StartingPoint::Right(block) => block,
     };
 
+    verify_block(&cid, &data).map_err(|e| TraversalFailed::Verification(cid.clone(), e))?;
+
     let mut cache = None;
     // Start the visit from the root block. We need to move the both components as Options into the
     // stream as we can't yet return them from this Future context.
-    let (visit, bytes) = match visit.start(&data) {
-        Ok((bytes, _, _, visit)) => {
-            let bytes = if !bytes.is_empty() {
-                Some(bytes.to_vec())
-            } else {
-                None
-            };
+    let (visit, bytes) = if cid.codec() == cid::Codec::Raw {
+        // a single-leaf file added with raw leaves is the content as is
+        let len = data.len() as u64;
+        let range = range.unwrap_or(0..len);
+        let start = range.start.min(len);
+        let end = range.end.min(len).max(start);
+        let bytes =
+            Some(data[start as usize..end as usize].to_vec()).filter(|bytes| !bytes.is_empty());
 
-            (visit, bytes)
-        }
-        Err(e) => {
-            return Err(TraversalFailed::Walking(cid, e));
+        (None, bytes)
+    } else {
+        match visit.start(&data) {
+            Ok((bytes, _, _, visit)) => {
+                let bytes = if !bytes.is_empty() {
+                    Some(bytes.to_vec())
+                } else {
+                    None
+                };
+
+                (visit, bytes)
+            }
+            Err(e) => {
+                return Err(TraversalFailed::Walking(cid, e));
+            }
         }
     };
 
@@ -80,23 +99,25 @@ where
             None => return,
         };
 
+        let mut prefetcher = Prefetcher::new(ipfs.borrow().clone(), DEFAULT_WINDOW);
+
         loop {
-            // TODO: if it was possible, it would make sense to start downloading N of these
-            // we could just create an FuturesUnordered which would drop the value right away. that
-            // would probably always cost many unnecessary clones, but it would be nice to "shut"
-            // the subscriber so that it will only resolve to a value but still keep the operation
-            // going. Not that we have any "operation" concept of the Want yet.
-            let (next, _) = visit.pending_links();
+            let (next, upcoming) = visit.pending_links();
+            let cid = next.to_owned();
 
-            let borrow = ipfs.borrow();
-            let Block { cid, data } = match borrow.get_block(&next).await {
-                Ok(block) => block,
+            let data = match prefetcher.get(next, upcoming).await {
+                Ok(data) => data,
                 Err(e) => {
-                    yield Err(TraversalFailed::Loading(next.to_owned(), e));
+                    yield Err(TraversalFailed::Loading(cid, e));
                     return;
                 },
             };
 
+            if let Err(e) = verify_block(&cid, &data) {
+                yield Err(TraversalFailed::Verification(cid, e));
+                return;
+            }
+
             match visit.continue_walk(&data, &mut cache) {
                 Ok((bytes, next_visit)) => {
                     if !bytes.is_empty() {
@@ -144,8 +165,8 @@ pub enum TraversalFailed {
     #[error("path resolving failed")]
     Resolving(#[source] ResolveError),
 
-    /// The given path was resolved to non dag-pb block, does not happen when starting the walk
-    /// from a block.
+    /// The given path was resolved to non dag-pb or raw block, does not happen when starting the
+    /// walk from a block.
     #[error("path resolved to unexpected")]
     Path(#[source] UnexpectedResolved),
 
@@ -156,4 +177,102 @@ pub enum TraversalFailed {
     /// Processing of the block failed
     #[error("walk failed on {}", .0)]
     Walking(Cid, #[source] FileReadFailed),
+
+    /// The content of the block did not match its Cid
+    #[error("verification of {} failed", .0)]
+    Verification(Cid, #[source] VerificationFailed),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cat, TraversalFailed};
+    use crate::{Block, Node};
+    use futures::stream::StreamExt;
+    use ipfs_unixfs::file::adder::FileAdder;
+
+    #[tokio::test(max_threads = 1)]
+    async fn corrupted_block_ends_the_stream() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = b"foobar\n";
+        let mut adder = FileAdder::builder().with_chunk_size(2).build();
+        let mut blocks = Vec::new();
+        let mut written = 0;
+
+        while written < content.len() {
+            let (completed, pushed) = adder.push(&content[written..]);
+            blocks.extend(completed);
+            written += pushed;
+        }
+
+        blocks.extend(adder.finish());
+
+        // the second leaf gets the content of the first leaf
+        let corrupted = blocks[1].0.clone();
+        blocks[1].1 = blocks[0].1.clone();
+        let root = blocks.last().unwrap().0.clone();
+
+        for (cid, data) in blocks {
+            let block = Block {
+                cid,
+                data: data.into(),
+            };
+            ipfs.put_block(block).await.unwrap();
+        }
+
+        let read = cat(&*ipfs, root, None)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].as_ref().unwrap(), b"fo");
+
+        match &read[1] {
+            Err(TraversalFailed::Verification(cid, _)) => assert_eq!(cid, &corrupted),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn raw_leaf_root() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = b"foobar\n";
+        let mut adder = FileAdder::builder().with_raw_leaves(true).build();
+        let (completed, pushed) = adder.push(content);
+        let mut blocks = completed.collect::<Vec<_>>();
+        assert_eq!(pushed, content.len());
+
+        blocks.extend(adder.finish());
+        assert_eq!(blocks.len(), 1);
+
+        let (root, data) = blocks.into_iter().next().unwrap();
+        assert_eq!(root.codec(), cid::Codec::Raw);
+
+        let block = Block {
+            cid: root.clone(),
+            data: data.into(),
+        };
+        ipfs.put_block(block).await.unwrap();
+
+        for (range, expected) in vec![
+            (None, &b"foobar\n"[..]),
+            (Some(1..3), &b"oo"[..]),
+            (Some(5..100), &b"r\n"[..]),
+        ] {
+            let read = cat(&*ipfs, root.clone(), range)
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .concat();
+
+            assert_eq!(read, expected);
+        }
+    }
 }
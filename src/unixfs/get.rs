@@ -0,0 +1,190 @@
+use super::StartingPoint;
+use crate::{
+    dag::{ResolveError, UnexpectedResolved},
+    Block, Error, Ipfs, IpfsTypes,
+};
+use async_stream::try_stream;
+use bytes::Bytes;
+use futures::stream::Stream;
+use ipfs_unixfs::walk::{self, ContinuedWalk, Walker};
+use std::borrow::Borrow;
+use std::path::Path;
+
+mod tar_helper;
+use tar_helper::TarHelper;
+
+/// IPFS get operation, producing a stream of a tar archive of the UnixFS tree, similar to the one
+/// go-ipfs creates for `ipfs get`. Like [`crate::unixfs::cat`], this is generic over the different
+/// kinds of ways to own an `Ipfs` value.
+///
+/// The root of the archive is named after the Cid of the root. The tree is walked as the archive
+/// is being read, so only a single block of the tree is held in memory at a time.
+pub async fn get_tar<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    starting_point: impl Into<StartingPoint>,
+) -> Result<impl Stream<Item = Result<Bytes, GetError>> + Send + 'a, GetError>
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    let Block {
+        cid: root,
+        data: first_block_data,
+    } = match starting_point.into() {
+        StartingPoint::Left(path) => {
+            let borrow = ipfs.borrow();
+            let dag = borrow.dag();
+            let (resolved, _) = dag.resolve(path, true).await.map_err(GetError::Resolving)?;
+            resolved.into_unixfs_block().map_err(GetError::Path)?
+        }
+        StartingPoint::Right(block) => block,
+    };
+
+    let mut cache = None;
+    let mut tar_helper = TarHelper::with_capacity(16 * 1024);
+
+    // the HTTP api uses the final Cid name as the root name in the generated tar
+    // archive.
+    let name = root.to_string();
+    let mut walker = Walker::new(root, name);
+
+    let mut buffer = Some(first_block_data);
+
+    Ok(try_stream! {
+        while walker.should_continue() {
+            let data = match buffer.take() {
+                Some(first) => first,
+                None => {
+                    let (next, _) = walker.pending_links();
+                    let Block { data, .. } = ipfs.borrow().get_block(next).await?;
+                    data
+                }
+            };
+
+            match walker.next(&data, &mut cache)? {
+                ContinuedWalk::Bucket(..) => {}
+                ContinuedWalk::File(segment, _, path, metadata, size) => {
+                    if segment.is_first() {
+                        for bytes in tar_helper.apply_file(path, metadata, size)?.iter_mut() {
+                            if let Some(bytes) = bytes.take() {
+                                yield bytes;
+                            }
+                        }
+                    }
+
+                    // even if the largest of files can have 256 kB blocks and about the same
+                    // amount of content, try to consume it in small parts not to grow the buffers
+                    // too much.
+
+                    let mut n = 0usize;
+                    let slice = segment.as_ref();
+                    let total = slice.len();
+
+                    while n < total {
+                        let next = tar_helper.buffer_file_contents(&slice[n..]);
+                        n += next.len();
+                        yield next;
+                    }
+
+                    if segment.is_last() {
+                        if let Some(zeroes) = tar_helper.pad(size) {
+                            yield zeroes;
+                        }
+                    }
+                },
+                ContinuedWalk::Directory(_, path, metadata) | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                    for bytes in tar_helper.apply_directory(path, metadata)?.iter_mut() {
+                        if let Some(bytes) = bytes.take() {
+                            yield bytes;
+                        }
+                    }
+                },
+                ContinuedWalk::Symlink(bytes, _, path, metadata) => {
+                    // converting a symlink is the most tricky part
+                    let target = std::str::from_utf8(bytes).map_err(|_| GetError::NonUtf8Symlink)?;
+                    let target = Path::new(target);
+
+                    for bytes in tar_helper.apply_symlink(path, target, metadata)?.iter_mut() {
+                        if let Some(bytes) = bytes.take() {
+                            yield bytes;
+                        }
+                    }
+                },
+            };
+        }
+    })
+}
+
+/// Types of failures which can occur while creating the tar archive of an UnixFS tree.
+#[derive(Debug, thiserror::Error)]
+pub enum GetError {
+    /// Failure to resolve the given path; does not happen when given a block.
+    #[error("path resolving failed")]
+    Resolving(#[source] ResolveError),
+
+    /// The given path was resolved to non dag-pb block, does not happen when starting the walk
+    /// from a block.
+    #[error("path resolved to unexpected")]
+    Path(#[source] UnexpectedResolved),
+
+    /// A symlink target could not be put inside the archive as it was not UTF-8.
+    #[error("symlink target could not be converted to utf-8")]
+    NonUtf8Symlink,
+
+    /// A path could not be put inside the archive.
+    #[error("filename cannot be put inside tar: {:?}", .0)]
+    InvalidFileName(Vec<u8>),
+
+    /// A symlink target could not be put inside the archive.
+    #[error("symlink name cannot be put inside tar: {:?}", .0)]
+    InvalidLinkName(Vec<u8>),
+
+    /// Processing of a block failed.
+    #[error("{}", .0)]
+    Walk(#[from] walk::Error),
+
+    /// Loading of a block failed.
+    #[error("loading failed: {}", .0)]
+    Loading(#[from] Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_tar;
+    use crate::unixfs::{add, AddOptions};
+    use crate::Node;
+    use futures::stream::{self, TryStreamExt};
+    use std::io::Read;
+
+    #[tokio::test(max_threads = 1)]
+    async fn multiblock_file_as_tar() {
+        let ipfs = Node::new("test_node").await;
+
+        let content = stream::iter(vec![Ok::<_, std::io::Error>(b"foobar\n".to_vec())]);
+        let cid = add(&*ipfs, content, AddOptions::default().with_chunk_size(2))
+            .await
+            .unwrap()
+            .root;
+
+        let bytes = get_tar(&*ipfs, cid.clone())
+            .await
+            .unwrap()
+            .map_ok(|bytes| bytes.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+
+        let mut archive = tar::Archive::new(std::io::Cursor::new(bytes));
+        let mut entries = archive.entries().unwrap();
+
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_str(), Some(&*cid.to_string()));
+        assert_eq!(entry.header().entry_type(), tar::EntryType::Regular);
+
+        let mut read_back = Vec::new();
+        entry.read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, b"foobar\n");
+
+        assert!(entries.next().is_none());
+    }
+}
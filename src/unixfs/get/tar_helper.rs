@@ -1,4 +1,4 @@
-///! Tar helper is internal to `/get` implementation. It uses some private parts of the `tar-rs`
+///! Tar helper is internal to `get_tar` implementation. It uses some private parts of the `tar-rs`
 ///! crate to provide a `BytesMut` writing implementation instead of one using `std::io` interfaces.
 ///!
 ///! Code was originally taken and modified from the dependency version of `tar-rs`. The most
@@ -7,12 +7,12 @@
 ///! `Bytes` (copying) code.
 use super::GetError;
 use bytes::{buf::BufMut, Bytes, BytesMut};
-use ipfs::unixfs::ll::Metadata;
+use ipfs_unixfs::Metadata;
 use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 use tar::{EntryType, Header};
 
-/// Tar helper is internal to `get` implementation. It uses some private parts of the `tar-rs`
+/// Tar helper is internal to `get_tar` implementation. It uses some private parts of the `tar-rs`
 /// crate to append the headers and the contents to a pair of `bytes::Bytes` operated in a
 /// round-robin fashion.
 pub(super) struct TarHelper {
@@ -1,13 +1,28 @@
 //! Adaptation for `ipfs-unixfs` crate functionality on top of [`crate::Ipfs`].
 //!
-//! Adding files and directory structures is supported but not exposed via an API. See examples and
-//! `ipfs-http`.
+//! Adding single files is supported through [`add`]. Adding directory structures is supported but
+//! not exposed via an API. See examples and `ipfs-http`. Whole trees can be exported as tar
+//! archives with `get_tar` when the `tar` feature is enabled. [`AsyncFileAdderWriter`] allows writing a file into a
+//! [`ipfs_unixfs::file::adder::FileAdder`] with `futures::io::AsyncWrite`.
 
 pub use ipfs_unixfs as ll;
 
+mod add;
+pub use add::{
+    add, add_from_reader, add_with_progress, AddError, AddOptions, AddProgress, AddedFile,
+};
+
 mod cat;
 pub use cat::{cat, StartingPoint, TraversalFailed};
 
+#[cfg(feature = "tar")]
+mod get;
+#[cfg(feature = "tar")]
+pub use get::{get_tar, GetError};
+
+mod writer;
+pub use writer::AsyncFileAdderWriter;
+
 #[cfg(test)]
 mod tests {
     #[test]
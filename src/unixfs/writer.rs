@@ -0,0 +1,141 @@
+use cid::Cid;
+use futures::channel::mpsc;
+use futures::io::AsyncWrite;
+use futures::sink::Sink;
+use ipfs_unixfs::file::adder::FileAdder;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// [`futures::io::AsyncWrite`] adapter for [`FileAdder`], sending the completed blocks to the
+/// receiving half returned by [`AsyncFileAdderWriter::new`]. This allows using
+/// `futures::io::copy` to add a file instead of calling [`FileAdder::push`] in a loop. See
+/// [`ipfs_unixfs::file::adder::FileAdderWriter`] for the blocking variant.
+///
+/// Closing the writer completes the tree, sends the remaining blocks and closes the channel, after
+/// which the last received block is the root block.
+pub struct AsyncFileAdderWriter {
+    adder: Option<FileAdder>,
+    pending: VecDeque<(Cid, Vec<u8>)>,
+    tx: mpsc::Sender<(Cid, Vec<u8>)>,
+}
+
+impl fmt::Debug for AsyncFileAdderWriter {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("AsyncFileAdderWriter")
+            .field("adder", &self.adder)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl AsyncFileAdderWriter {
+    /// Creates a new writer for `adder`, returning it and the receiver for the created blocks.
+    /// Writing will wait when `buffer` blocks have not yet been received.
+    pub fn new(adder: FileAdder, buffer: usize) -> (Self, mpsc::Receiver<(Cid, Vec<u8>)>) {
+        let (tx, rx) = mpsc::channel(buffer);
+        let writer = AsyncFileAdderWriter {
+            adder: Some(adder),
+            pending: VecDeque::new(),
+            tx,
+        };
+        (writer, rx)
+    }
+
+    /// Sends the pending blocks, returning `Poll::Ready` once all of them have been sent.
+    fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            futures::ready!(Pin::new(&mut self.tx).poll_ready(cx)).map_err(disconnected)?;
+            let next = self.pending.pop_front().expect("checked to be non-empty");
+            Pin::new(&mut self.tx)
+                .start_send(next)
+                .map_err(disconnected)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AsyncFileAdderWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        futures::ready!(this.poll_send_pending(cx))?;
+
+        let adder = match this.adder.as_mut() {
+            Some(adder) => adder,
+            None => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "writer has already been closed",
+                )))
+            }
+        };
+
+        let (blocks, consumed) = adder.push(buf);
+        this.pending.extend(blocks);
+
+        // the consumed bytes have been accepted regardless of whether or not the blocks could all
+        // be sent right away; the rest will be sent before accepting any more.
+        let _ = this.poll_send_pending(cx)?;
+
+        Poll::Ready(Ok(consumed))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_send_pending(cx))?;
+        Pin::new(&mut this.tx).poll_flush(cx).map_err(disconnected)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Some(adder) = this.adder.take() {
+            futures::ready!(this.poll_send_pending(cx))?;
+            this.pending.extend(adder.finish());
+        }
+
+        futures::ready!(this.poll_send_pending(cx))?;
+        Pin::new(&mut this.tx).poll_close(cx).map_err(disconnected)
+    }
+}
+
+fn disconnected(e: mpsc::SendError) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncFileAdderWriter;
+    use futures::io::AsyncWriteExt;
+    use futures::stream::StreamExt;
+    use ipfs_unixfs::file::adder::FileAdder;
+
+    #[tokio::test(max_threads = 1)]
+    async fn copy_into_async_writer() {
+        let adder = FileAdder::builder().with_chunk_size(2).build();
+        let (mut writer, rx) = AsyncFileAdderWriter::new(adder, 1);
+
+        let writing = async move {
+            futures::io::copy(&mut &b"foobar\n"[..], &mut writer)
+                .await
+                .unwrap();
+            writer.close().await.unwrap();
+        };
+
+        let ((), blocks) = futures::future::join(writing, rx.collect::<Vec<_>>()).await;
+
+        // same as in `ipfs_unixfs::file::adder::tests::favourite_multi_block_file`
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(
+            blocks.last().unwrap().0.to_string(),
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+        );
+    }
+}
@@ -58,7 +58,7 @@ async fn bitswap_stress_test() {
         if filter(i) {
             node.put_block(Block {
                 cid: cid.clone(),
-                data: data.clone(),
+                data: data.clone().into(),
             })
             .await
             .unwrap();
@@ -11,7 +11,7 @@ fn create_block() -> Block {
     let data = b"hello block\n".to_vec().into_boxed_slice();
     let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
 
-    Block { cid, data }
+    Block::new(data, cid)
 }
 
 // verify that a put block can be received via get_block and the data matches
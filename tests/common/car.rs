@@ -0,0 +1,99 @@
+//! Minimal reading and writing of the CAR v1 archives exchanged with the foreign nodes through
+//! `dag/export` and `dag/import`.
+
+use ipfs::ipld::{encode_ipld, Ipld};
+use ipfs::{Block, Cid};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Writes the archive with the single `root` and the `blocks`.
+#[allow(dead_code)]
+pub fn write(root: &Cid, blocks: &[Block]) -> Vec<u8> {
+    let mut map = BTreeMap::new();
+    map.insert(
+        "roots".to_owned(),
+        Ipld::List(vec![Ipld::Link(root.clone())]),
+    );
+    map.insert("version".to_owned(), Ipld::Integer(1));
+    let header = encode_ipld(&Ipld::Map(map), cid::Codec::DagCBOR).unwrap();
+
+    let mut out = Vec::new();
+    write_varint(header.len() as u64, &mut out);
+    out.extend_from_slice(&header);
+
+    for block in blocks {
+        let cid = block.cid.to_bytes();
+        write_varint((cid.len() + block.data.len()) as u64, &mut out);
+        out.extend_from_slice(&cid);
+        out.extend_from_slice(&block.data);
+    }
+
+    out
+}
+
+/// Reads the blocks of the archive, skipping the header.
+#[allow(dead_code)]
+pub fn read(mut car: &[u8]) -> Vec<Block> {
+    let header_len = read_varint(&mut car) as usize;
+    car = &car[header_len..];
+
+    let mut blocks = Vec::new();
+
+    while !car.is_empty() {
+        let len = read_varint(&mut car) as usize;
+        let (section, rest) = car.split_at(len);
+        car = rest;
+
+        let cid_len = cid_len(section);
+        let cid = Cid::try_from(&section[..cid_len]).unwrap();
+        let data = section[cid_len..].to_vec().into_boxed_slice();
+        blocks.push(Block::new(data, cid));
+    }
+
+    blocks
+}
+
+/// The length of the binary Cid at the start of the `section`.
+fn cid_len(section: &[u8]) -> usize {
+    // a CIDv0 is always the 34 byte sha2-256 multihash
+    if section.starts_with(&[0x12, 0x20]) {
+        return 34;
+    }
+
+    let mut rest = section;
+    let _version = read_varint(&mut rest);
+    let _codec = read_varint(&mut rest);
+    let _hash = read_varint(&mut rest);
+    let digest_len = read_varint(&mut rest) as usize;
+
+    section.len() - rest.len() + digest_len
+}
+
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(input: &mut &[u8]) -> u64 {
+    let mut value = 0u64;
+
+    for (i, &byte) in input.iter().enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return value;
+        }
+    }
+
+    panic!("truncated varint");
+}
@@ -1,5 +1,5 @@
 #[cfg(any(feature = "test_go_interop", feature = "test_js_interop"))]
-pub use common::{api_call, ForeignNode};
+pub use common::{api_call, api_call_raw, ForeignNode};
 
 #[cfg(all(not(feature = "test_go_interop"), not(feature = "test_js_interop")))]
 #[allow(dead_code)]
@@ -19,20 +19,40 @@ pub mod common {
 
     #[derive(Debug)]
     pub struct ForeignNode {
-        pub dir: PathBuf,
-        pub daemon: Child,
+        /// The temporary repo of the spawned daemon; `None` for the provided daemons.
+        pub dir: Option<PathBuf>,
+        /// The spawned daemon; `None` for the provided daemons.
+        pub daemon: Option<Child>,
         pub id: PeerId,
         pub pk: PublicKey,
         pub addrs: Vec<Multiaddr>,
-        pub binary_path: String,
+        pub binary_path: Option<String>,
         pub api_port: u16,
     }
 
     impl ForeignNode {
+        /// Spawns a new foreign daemon, or uses the already running one when the API port of it is
+        /// given in the `GO_IPFS_API_PORT` or `JS_IPFS_API_PORT` environment variable.
         #[allow(dead_code)]
         pub fn new() -> ForeignNode {
             use std::{io::Read, net::SocketAddr, str};
 
+            // this environment variable can point to the API port of an already running daemon
+            #[cfg(feature = "test_go_interop")]
+            const ENV_IPFS_API_PORT: &str = "GO_IPFS_API_PORT";
+            #[cfg(feature = "test_js_interop")]
+            const ENV_IPFS_API_PORT: &str = "JS_IPFS_API_PORT";
+
+            if let Ok(api_port) = env::var(ENV_IPFS_API_PORT) {
+                let api_port = api_port.parse().unwrap_or_else(|_| {
+                    panic!(
+                        "the {} environment variable is not a port",
+                        ENV_IPFS_API_PORT
+                    )
+                });
+                return ForeignNode::provided(api_port);
+            }
+
             // this environment variable should point to the location of the foreign ipfs binary
             #[cfg(feature = "test_go_interop")]
             const ENV_IPFS_PATH: &str = "GO_IPFS_PATH";
@@ -108,27 +128,32 @@ pub mod common {
                 .unwrap()
                 .stdout;
 
-            let node_id_stdout = String::from_utf8_lossy(&node_id);
-            let ForeignNodeId {
+            let (id, pk, addrs) = parse_id(&node_id);
+
+            ForeignNode {
+                dir: Some(tmp_dir),
+                daemon: Some(daemon),
                 id,
-                addresses,
-                public_key,
-                ..
-            } = serde_json::de::from_str(&node_id_stdout).unwrap();
+                pk,
+                addrs,
+                binary_path: Some(binary_path),
+                api_port,
+            }
+        }
 
-            let id = id.parse().unwrap();
-            let pk = PublicKey::from_protobuf_encoding(
-                &base64::decode(public_key.into_bytes()).unwrap(),
-            )
-            .unwrap();
+        /// Uses the already running daemon with the API at the `api_port`, which is not stopped on
+        /// drop.
+        fn provided(api_port: u16) -> ForeignNode {
+            let node_id = post(api_port, "id", None);
+            let (id, pk, addrs) = parse_id(&node_id);
 
             ForeignNode {
-                dir: tmp_dir,
-                daemon,
+                dir: None,
+                daemon: None,
                 id,
                 pk,
-                addrs: addresses,
-                binary_path,
+                addrs,
+                binary_path: None,
                 api_port,
             }
         }
@@ -141,30 +166,77 @@ pub mod common {
 
     impl Drop for ForeignNode {
         fn drop(&mut self) {
-            let _ = self.daemon.kill();
-            let _ = fs::remove_dir_all(&self.dir);
+            if let Some(ref mut daemon) = self.daemon {
+                let _ = daemon.kill();
+            }
+            if let Some(ref dir) = self.dir {
+                let _ = fs::remove_dir_all(dir);
+            }
         }
     }
 
+    fn parse_id(output: &[u8]) -> (PeerId, PublicKey, Vec<Multiaddr>) {
+        let ForeignNodeId {
+            id,
+            addresses,
+            public_key,
+            ..
+        } = serde_json::de::from_slice(output).unwrap();
+
+        let id = id.parse().unwrap();
+        let pk =
+            PublicKey::from_protobuf_encoding(&base64::decode(public_key.into_bytes()).unwrap())
+                .unwrap();
+
+        (id, pk, addresses)
+    }
+
+    fn post(api_port: u16, call: &str, data: Option<&[u8]>) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut command = Command::new("curl");
+        command
+            .arg("-s")
+            .arg("-X")
+            .arg("POST")
+            .arg(&format!("http://127.0.0.1:{}/api/v0/{}", api_port, call))
+            .stdout(Stdio::piped());
+
+        if data.is_some() {
+            // the data is sent as the multipart file from the stdin
+            command.arg("-F").arg("file=@-").stdin(Stdio::piped());
+        }
+
+        let mut child = command.spawn().unwrap();
+
+        if let Some(data) = data {
+            child.stdin.take().unwrap().write_all(data).unwrap();
+        }
+
+        child.wait_with_output().unwrap().stdout
+    }
+
     // this one is not a method on ForeignNode, as only its port number is needed and we don't
     // want to restrict ourselves from calling it from spawned tasks or threads (or to make the
     // internals of ForeignNode complicated by making it Clone)
     #[allow(dead_code)]
     pub async fn api_call<T: AsRef<str>>(api_port: u16, call: T) -> String {
-        let bytes = Command::new("curl")
-            .arg("-X")
-            .arg("POST")
-            .arg(&format!(
-                "http://127.0.0.1:{}/api/v0/{}",
-                api_port,
-                call.as_ref()
-            ))
-            .output()
-            .unwrap()
-            .stdout;
+        let bytes = post(api_port, call.as_ref(), None);
         String::from_utf8(bytes).unwrap()
     }
 
+    /// Like [`api_call`] but returns the raw bytes of the response, as needed for `block/get` or
+    /// `dag/export`, and optionally uploads the `data` as a file, as needed for `block/put` or
+    /// `dag/import`.
+    #[allow(dead_code)]
+    pub async fn api_call_raw<T: AsRef<str>>(
+        api_port: u16,
+        call: T,
+        data: Option<&[u8]>,
+    ) -> Vec<u8> {
+        post(api_port, call.as_ref(), data)
+    }
+
     #[derive(Deserialize, Debug)]
     #[cfg_attr(feature = "test_go_interop", serde(rename_all = "PascalCase"))]
     #[cfg_attr(feature = "test_js_interop", serde(rename_all = "camelCase"))]
@@ -1,23 +1,14 @@
+pub mod car;
 pub mod interop;
 
 use ipfs::Node;
 
-/// The way in which nodes are connected to each other; to be used with spawn_nodes.
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Topology {
-    /// no connections
-    None,
-    /// a > b > c
-    Line,
-    /// a > b > c > a
-    Ring,
-    /// a <> b <> c <> a
-    Mesh,
-    /// a > b, a > c
-    Star,
-}
+#[allow(unused_imports)]
+pub use ipfs::test_support::Topology;
 
+/// Spawns the nodes listening on TCP, as needed for connecting them with the foreign nodes, and
+/// connects them in the `topology`. See [`ipfs::test_support::spawn_nodes`] for the nodes over
+/// the memory transport.
 #[allow(dead_code)]
 pub async fn spawn_nodes(count: usize, topology: Topology) -> Vec<Node> {
     let mut nodes = Vec::with_capacity(count);
@@ -27,37 +18,7 @@ pub async fn spawn_nodes(count: usize, topology: Topology) -> Vec<Node> {
         nodes.push(node);
     }
 
-    match topology {
-        Topology::Line | Topology::Ring => {
-            for i in 0..(count - 1) {
-                nodes[i]
-                    .connect(nodes[i + 1].addrs[0].clone())
-                    .await
-                    .unwrap();
-            }
-            if topology == Topology::Ring {
-                nodes[count - 1]
-                    .connect(nodes[0].addrs[0].clone())
-                    .await
-                    .unwrap();
-            }
-        }
-        Topology::Mesh => {
-            for i in 0..count {
-                for (j, peer) in nodes.iter().enumerate() {
-                    if i != j {
-                        nodes[i].connect(peer.addrs[0].clone()).await.unwrap();
-                    }
-                }
-            }
-        }
-        Topology::Star => {
-            for node in nodes.iter().skip(1) {
-                nodes[0].connect(node.addrs[0].clone()).await.unwrap();
-            }
-        }
-        Topology::None => {}
-    }
+    ipfs::test_support::connect(&nodes, topology).await;
 
     nodes
 }
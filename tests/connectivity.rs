@@ -1,4 +1,4 @@
-use ipfs::Node;
+use ipfs::{p2p::PreSharedKey, IpfsOptions, Node};
 use libp2p::{multiaddr::Protocol, Multiaddr};
 use std::time::Duration;
 use tokio::time::timeout;
@@ -26,6 +26,31 @@ async fn connect_two_nodes_by_addr() {
         .expect("should have connected");
 }
 
+// Make sure only the nodes with the same pre-shared key can connect to a private network.
+#[tokio::test(max_threads = 1)]
+async fn connect_private_network() {
+    let private = |key: Option<[u8; 32]>| {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.swarm_key = key.map(PreSharedKey::new);
+        Node::with_options(opts)
+    };
+
+    let node_a = private(Some([1; 32])).await;
+    let node_b = private(Some([1; 32])).await;
+    let outsider = private(None).await;
+    let other_network = private(Some([2; 32])).await;
+
+    timeout(TIMEOUT, node_a.connect(node_b.addrs[0].clone()))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+
+    for node in &[outsider, other_network] {
+        let res = timeout(TIMEOUT, node.connect(node_a.addrs[0].clone())).await;
+        assert!(!matches!(res, Ok(Ok(()))), "should not have connected");
+    }
+}
+
 // Make sure only a `Multiaddr` with `/p2p/` can be used to connect.
 #[tokio::test(max_threads = 1)]
 #[should_panic(expected = "called `Result::unwrap()` on an `Err` value: MissingProtocolP2p")]
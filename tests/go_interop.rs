@@ -0,0 +1,212 @@
+//! Tests against a go-ipfs daemon, either spawned from `GO_IPFS_PATH` or the one already running
+//! with the API at `GO_IPFS_API_PORT`. The daemons are spawned with the `test` profile, and the
+//! nodes find each other through the LAN DHT, which is the default `kad_protocol` of
+//! `IpfsOptions::inmemory_with_generated_keys`.
+#![cfg(feature = "test_go_interop")]
+
+use cid::{Cid, Codec};
+use futures::stream::TryStreamExt;
+use ipfs::{Block, IpfsPath, MultiaddrWithPeerId, Node};
+use multihash::Sha2_256;
+use std::convert::TryFrom;
+use std::time::Duration;
+use tokio::time::timeout;
+
+mod common;
+use common::car;
+use common::interop::{api_call, api_call_raw, ForeignNode};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn create_block(data: &[u8]) -> Block {
+    let data = data.to_vec().into_boxed_slice();
+    let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+
+    Block::new(data, cid)
+}
+
+/// Connects the nodes and adds the foreign node to the routing table of the local one.
+async fn connected_pair(name: &str) -> (Node, ForeignNode) {
+    let node = Node::new(name).await;
+    let foreign = ForeignNode::new();
+
+    node.connect(foreign.addrs[0].clone()).await.unwrap();
+    node.add_peer(foreign.id.clone(), foreign.addrs[0].clone())
+        .await
+        .unwrap();
+
+    (node, foreign)
+}
+
+#[tokio::test(max_threads = 1)]
+async fn bitswap_from_go() {
+    let (node, foreign) = connected_pair("bitswap_from_go").await;
+    let block = create_block(b"from go-ipfs\n");
+
+    let response = api_call_raw(
+        foreign.api_port,
+        "block/put?format=raw&mhtype=sha2-256",
+        Some(&block.data[..]),
+    )
+    .await;
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.contains(&block.cid.to_string()), "{}", response);
+
+    let found = timeout(TIMEOUT, node.get_block(&block.cid))
+        .await
+        .expect("get_block did not complete in time")
+        .unwrap();
+
+    assert_eq!(found.data, block.data);
+}
+
+#[tokio::test(max_threads = 1)]
+async fn bitswap_to_go() {
+    let (node, foreign) = connected_pair("bitswap_to_go").await;
+    let block = create_block(b"from rust-ipfs\n");
+
+    node.put_block(block.clone()).await.unwrap();
+
+    let data = timeout(
+        TIMEOUT,
+        api_call_raw(
+            foreign.api_port,
+            format!("block/get?arg={}", block.cid),
+            None,
+        ),
+    )
+    .await
+    .expect("block/get did not complete in time");
+
+    assert_eq!(data, &block.data[..]);
+}
+
+#[tokio::test(max_threads = 1)]
+async fn dht_providing() {
+    let (node, foreign) = connected_pair("dht_providing").await;
+
+    // go-ipfs finds the rust node as the provider
+    let block = create_block(b"provided by rust-ipfs\n");
+    node.put_block(block.clone()).await.unwrap();
+    timeout(TIMEOUT, node.provide(block.cid.clone()))
+        .await
+        .expect("provide did not complete in time")
+        .unwrap();
+
+    let providers = api_call(
+        foreign.api_port,
+        format!("dht/findprovs?arg={}&num-providers=1", block.cid),
+    )
+    .await;
+    assert!(providers.contains(&node.id.to_string()), "{}", providers);
+
+    // the rust node finds go-ipfs as the provider
+    let block = create_block(b"provided by go-ipfs\n");
+    api_call_raw(
+        foreign.api_port,
+        "block/put?format=raw&mhtype=sha2-256",
+        Some(&block.data[..]),
+    )
+    .await;
+    api_call(foreign.api_port, format!("dht/provide?arg={}", block.cid)).await;
+
+    let providers = timeout(TIMEOUT, node.get_providers(block.cid.clone()))
+        .await
+        .expect("get_providers did not complete in time")
+        .unwrap();
+    assert!(providers.contains(&foreign.id), "{:?}", providers);
+}
+
+#[tokio::test(max_threads = 1)]
+async fn ipns_resolution() {
+    let (node, foreign) = connected_pair("ipns_resolution").await;
+    let block = create_block(b"published by go-ipfs\n");
+
+    api_call_raw(
+        foreign.api_port,
+        "block/put?format=raw&mhtype=sha2-256",
+        Some(&block.data[..]),
+    )
+    .await;
+    let published = api_call(
+        foreign.api_port,
+        format!("name/publish?arg=/ipfs/{}&allow-offline=true", block.cid),
+    )
+    .await;
+    assert!(published.contains(&foreign.id.to_string()), "{}", published);
+
+    let name = IpfsPath::try_from(format!("/ipns/{}", foreign.id).as_str()).unwrap();
+    let resolved = timeout(TIMEOUT, node.resolve_ipns(&name, false))
+        .await
+        .expect("resolve_ipns did not complete in time")
+        .unwrap();
+
+    let expected = IpfsPath::try_from(format!("/ipfs/{}", block.cid).as_str()).unwrap();
+    assert_eq!(resolved, expected);
+}
+
+#[tokio::test(max_threads = 1)]
+async fn car_from_go() {
+    let (node, foreign) = connected_pair("car_from_go").await;
+    let content = b"exported from go-ipfs as a CAR of many blocks\n".repeat(10);
+
+    let added = api_call_raw(
+        foreign.api_port,
+        "add?chunker=size-64&pin=false",
+        Some(&content[..]),
+    )
+    .await;
+    let added: serde_json::Value = serde_json::from_slice(&added).unwrap();
+    let root = Cid::try_from(added["Hash"].as_str().unwrap()).unwrap();
+
+    let exported = api_call_raw(foreign.api_port, format!("dag/export?arg={}", root), None).await;
+    let blocks = car::read(&exported);
+    assert!(blocks.len() > 1);
+
+    // the node is disconnected first so that the blocks can only come from the CAR
+    node.disconnect(MultiaddrWithPeerId::try_from(foreign.addrs[0].clone()).unwrap())
+        .await
+        .unwrap();
+
+    for block in blocks {
+        node.put_block(block).await.unwrap();
+    }
+
+    let cat = node
+        .cat_unixfs(root, None)
+        .await
+        .unwrap()
+        .try_concat()
+        .await
+        .unwrap();
+
+    assert_eq!(cat, content);
+}
+
+#[tokio::test(max_threads = 1)]
+async fn car_to_go() {
+    let node = Node::new("car_to_go").await;
+    let foreign = ForeignNode::new();
+
+    let leaf = create_block(b"imported to go-ipfs from a CAR\n");
+    node.put_block(leaf.clone()).await.unwrap();
+    let root = node
+        .put_dag(ipfs::make_ipld!({ "leaf": leaf.cid.clone() }))
+        .await
+        .unwrap();
+    let root = node.get_block(&root).await.unwrap();
+
+    // the nodes are not connected, so the blocks can only come from the CAR
+    let archive = car::write(&root.cid, &[root.clone(), leaf.clone()]);
+    api_call_raw(foreign.api_port, "dag/import", Some(&archive[..])).await;
+
+    for block in &[root, leaf] {
+        let data = api_call_raw(
+            foreign.api_port,
+            format!("block/get?arg={}", block.cid),
+            None,
+        )
+        .await;
+        assert_eq!(data, &block.data[..]);
+    }
+}
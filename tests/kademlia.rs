@@ -1,4 +1,5 @@
 use cid::{Cid, Codec};
+use ipfs::keystore::{KeyType, SELF_KEY};
 use ipfs::{p2p::MultiaddrWithPeerId, Block, Node};
 use libp2p::{kad::Quorum, multiaddr::Protocol, Multiaddr};
 use multihash::Sha2_256;
@@ -166,7 +167,7 @@ async fn dht_providing() {
     nodes[last_index]
         .put_block(Block {
             cid: cid.clone(),
-            data,
+            data: data.into(),
         })
         .await
         .unwrap();
@@ -182,6 +183,46 @@ async fn dht_providing() {
         .contains(&nodes[last_index].id.clone()));
 }
 
+/// Check if the stored blocks are provided and fetched from the providers found through the DHT.
+#[tokio::test(max_threads = 1)]
+async fn dht_providing_stored_blocks() {
+    const CHAIN_LEN: usize = 10;
+    let (nodes, foreign_node) = spawn_bootstrapped_nodes(CHAIN_LEN).await;
+    let last_index = CHAIN_LEN - if foreign_node.is_none() { 1 } else { 2 };
+
+    // the last node only stores the block, which provides it in the background
+    let data = b"hello stored block\n".to_vec().into_boxed_slice();
+    let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+    nodes[last_index]
+        .put_block(Block {
+            cid: cid.clone(),
+            data: data.clone().into(),
+        })
+        .await
+        .unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        while !nodes[0]
+            .get_providers(cid.clone())
+            .await
+            .unwrap()
+            .contains(&nodes[last_index].id)
+        {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("the block was not provided in time");
+
+    // the first node connects to the provider it finds, without being told about it
+    let block = timeout(Duration::from_secs(10), nodes[0].get_block(&cid))
+        .await
+        .expect("the block was not fetched in time")
+        .unwrap();
+
+    assert_eq!(block.data(), &data[..]);
+}
+
 /// Check if Ipfs::{get, put} does its job.
 #[tokio::test(max_threads = 1)]
 async fn dht_get_put() {
@@ -201,3 +242,51 @@ async fn dht_get_put() {
     // and the first node should be able to get it
     assert_eq!(nodes[0].dht_get(key, quorum).await.unwrap(), vec![value]);
 }
+
+/// Check if Ipfs::{name_publish, name_resolve} does its job.
+#[tokio::test(max_threads = 1)]
+async fn ipns_publish_resolve() {
+    const CHAIN_LEN: usize = 10;
+    let (nodes, foreign_node) = spawn_bootstrapped_nodes(CHAIN_LEN).await;
+    let last_index = CHAIN_LEN - if foreign_node.is_none() { 1 } else { 2 };
+
+    let lifetime = Duration::from_secs(60);
+    let first: ipfs::IpfsPath = "/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        .parse()
+        .unwrap();
+    let second: ipfs::IpfsPath = "/ipfs/QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"
+        .parse()
+        .unwrap();
+
+    // the last node publishes under its name twice, the sequence number growing
+    let publisher = &nodes[last_index];
+    assert_eq!(
+        publisher
+            .name_publish(&first, SELF_KEY, lifetime)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        publisher
+            .name_publish(&second, SELF_KEY, lifetime)
+            .await
+            .unwrap(),
+        1
+    );
+
+    // and the first node should resolve the name to the latest path
+    assert_eq!(nodes[0].name_resolve(&publisher.id).await.unwrap(), second);
+
+    // the names of the other keys of the keystore are published the same way
+    let other = publisher.key_gen("other", KeyType::Ed25519).await.unwrap();
+    assert_eq!(
+        publisher
+            .name_publish(&first, "other", lifetime)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(nodes[0].name_resolve(&other).await.unwrap(), first);
+    assert_eq!(nodes[0].name_resolve(&publisher.id).await.unwrap(), second);
+}
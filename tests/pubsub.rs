@@ -139,6 +139,60 @@ async fn publish_between_two_nodes() {
     assert!(disappeared, "timed out before a saw b's unsubscription");
 }
 
+#[tokio::test(max_threads = 1)]
+async fn publish_between_two_gossipsub_nodes() {
+    use ipfs::p2p::PubsubRouter;
+    use ipfs::IpfsOptions;
+
+    let mut nodes = Vec::new();
+    for _ in 0..2 {
+        // the messages are signed by default
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.pubsub.router = PubsubRouter::Gossipsub;
+        nodes.push(Node::with_options(opts).await);
+    }
+    nodes[0].connect(nodes[1].addrs[0].clone()).await.unwrap();
+
+    let topic = "gossip".to_owned();
+
+    let mut a_msgs = nodes[0].pubsub_subscribe(topic.clone()).await.unwrap();
+    let mut b_msgs = nodes[1].pubsub_subscribe(topic.clone()).await.unwrap();
+
+    let mut appeared = false;
+    for _ in 0..100usize {
+        if nodes[0]
+            .pubsub_peers(Some(topic.clone()))
+            .await
+            .unwrap()
+            .contains(&nodes[1].id)
+        {
+            appeared = true;
+            break;
+        }
+        timeout(Duration::from_millis(100), pending::<()>())
+            .await
+            .unwrap_err();
+    }
+
+    assert!(appeared, "timed out before b appeared as a gossipsub peer");
+
+    nodes[0]
+        .pubsub_publish(topic.clone(), b"foobar".to_vec())
+        .await
+        .unwrap();
+
+    // the messages published by the node are delivered to itself as with floodsub
+    for st in &mut [a_msgs.by_ref(), b_msgs.by_ref()] {
+        let msg = timeout(Duration::from_secs(10), st.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.source, nodes[0].id);
+        assert_eq!(msg.data, b"foobar");
+        assert_eq!(msg.topics, &[topic.clone()]);
+    }
+}
+
 #[cfg(any(feature = "test_go_interop", feature = "test_js_interop"))]
 #[tokio::test(max_threads = 1)]
 #[ignore = "doesn't work yet"]
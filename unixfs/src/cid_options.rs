@@ -0,0 +1,234 @@
+use cid::{Cid, Version};
+use core::fmt;
+use multihash::MultihashDigest;
+
+/// Multicodec code for dag-pb, the codec of all of the UnixFS blocks.
+pub(crate) const DAG_PB_CODEC: u64 = 0x70;
+
+/// Multihash code for the identity "hash", which is the content itself.
+const IDENTITY: u64 = 0x00;
+
+/// The largest block which can be inlined, limited by the maximum multihash digest size.
+pub const MAX_INLINE_LIMIT: usize = 64;
+
+/// Hash functions which can be used to create the Cids for the created blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFunction {
+    /// SHA2-256, the default and the only one which can be used with Cid version 0.
+    Sha2_256,
+    /// BLAKE2b with 256 bit output.
+    Blake2b256,
+    /// BLAKE3 with 256 bit output.
+    Blake3_256,
+}
+
+impl Default for HashFunction {
+    fn default() -> Self {
+        HashFunction::Sha2_256
+    }
+}
+
+impl HashFunction {
+    fn digest(&self, block: &[u8]) -> multihash::Multihash {
+        use multihash::Code;
+
+        let code = match self {
+            HashFunction::Sha2_256 => Code::Sha2_256,
+            HashFunction::Blake2b256 => Code::Blake2b256,
+            HashFunction::Blake3_256 => Code::Blake3_256,
+        };
+
+        code.digest(block)
+    }
+}
+
+impl core::str::FromStr for HashFunction {
+    type Err = UnsupportedHashFunction;
+
+    /// Parses the hash function names used by go-ipfs: `sha2-256`, `blake2b-256` and `blake3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sha2-256" => HashFunction::Sha2_256,
+            "blake2b-256" => HashFunction::Blake2b256,
+            "blake3" => HashFunction::Blake3_256,
+            _ => return Err(UnsupportedHashFunction(s.to_owned())),
+        })
+    }
+}
+
+/// The hash function name could not be parsed into a [`HashFunction`].
+#[derive(Debug)]
+pub struct UnsupportedHashFunction(String);
+
+impl fmt::Display for UnsupportedHashFunction {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "unsupported hash function: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedHashFunction {}
+
+/// Configures the Cid version and the hash function used for the blocks created by
+/// [`crate::file::adder::FileAdder`] and [`crate::dir::builder::BufferingTreeBuilder`].
+///
+/// Defaults to Cid version 0 with SHA2-256, which matches the go-ipfs defaults. Cid version 0 can
+/// only be used with SHA2-256, so other hash functions require version 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CidOptions {
+    version: Version,
+    hash: HashFunction,
+    inline_limit: Option<usize>,
+}
+
+impl Default for CidOptions {
+    fn default() -> Self {
+        CidOptions {
+            version: Version::V0,
+            hash: HashFunction::Sha2_256,
+            inline_limit: None,
+        }
+    }
+}
+
+impl CidOptions {
+    /// Creates options for Cid version 1 with the given hash function.
+    ///
+    /// Note: go-ipfs enables raw leaves by default when Cid version 1 is used, see
+    /// [`crate::file::adder::FileAdderBuilder::with_raw_leaves`].
+    pub fn v1(hash: HashFunction) -> Self {
+        CidOptions {
+            version: Version::V1,
+            hash,
+            inline_limit: None,
+        }
+    }
+
+    /// Configures the blocks of at most `limit` bytes to be inlined into their Cids using the
+    /// identity hash, like go-ipfs `--inline`, which avoids storing and fetching the tiny blocks.
+    /// The Cids of the inlined blocks are always Cid version 1. go-ipfs uses 32 bytes as the
+    /// default limit.
+    ///
+    /// # Panics
+    ///
+    /// When `limit` is larger than [`MAX_INLINE_LIMIT`].
+    pub fn with_inline_limit(self, limit: usize) -> Self {
+        assert!(
+            limit <= MAX_INLINE_LIMIT,
+            "inline limit cannot be larger than {}",
+            MAX_INLINE_LIMIT
+        );
+        CidOptions {
+            inline_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Returns the configured Cid version.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the configured hash function.
+    pub fn hash_function(&self) -> HashFunction {
+        self.hash
+    }
+
+    /// Returns the configured inline limit, if any.
+    pub fn inline_limit(&self) -> Option<usize> {
+        self.inline_limit
+    }
+
+    /// Creates the Cid for a dag-pb block.
+    pub(crate) fn dag_pb(&self, block: &[u8]) -> Cid {
+        if let Some(cid) = self.inlined(DAG_PB_CODEC, block) {
+            return cid;
+        }
+
+        let mh = self.hash.digest(block);
+        match self.version {
+            Version::V0 => Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0"),
+            Version::V1 => Cid::new_v1(DAG_PB_CODEC, mh),
+        }
+    }
+
+    /// Creates the Cid for a raw block, which is always Cid version 1.
+    pub(crate) fn raw(&self, block: &[u8]) -> Cid {
+        if let Some(cid) = self.inlined(crate::RAW_CODEC, block) {
+            return cid;
+        }
+
+        Cid::new_v1(crate::RAW_CODEC, self.hash.digest(block))
+    }
+
+    fn inlined(&self, codec: u64, block: &[u8]) -> Option<Cid> {
+        let limit = self.inline_limit?;
+
+        if block.len() > limit {
+            return None;
+        }
+
+        let mh = multihash::Multihash::wrap(IDENTITY, block)
+            .expect("inline limit is at most the maximum digest size");
+
+        Some(Cid::new_v1(codec, mh))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CidOptions, HashFunction};
+
+    #[test]
+    fn default_is_v0() {
+        assert_eq!(
+            CidOptions::default().dag_pb(b"").to_string(),
+            "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n"
+        );
+    }
+
+    #[test]
+    fn v1_dag_pb() {
+        let cid = CidOptions::v1(HashFunction::Sha2_256).dag_pb(b"");
+        assert_eq!(
+            cid.to_string(),
+            "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+    }
+
+    #[test]
+    fn inlined_blocks() {
+        let opts = CidOptions::default().with_inline_limit(4);
+
+        let inlined = opts.dag_pb(b"1234");
+        assert_eq!(inlined.version(), cid::Version::V1);
+        assert_eq!(u64::from(inlined.hash().code()), super::IDENTITY);
+        assert_eq!(inlined.hash().digest(), b"1234");
+
+        let raw = opts.raw(b"1234");
+        assert_eq!(u64::from(raw.codec()), crate::RAW_CODEC);
+        assert_eq!(raw.hash().digest(), b"1234");
+
+        // larger blocks are hashed as usual
+        assert_eq!(
+            opts.dag_pb(b"12345"),
+            CidOptions::default().dag_pb(b"12345")
+        );
+    }
+
+    #[test]
+    fn parse_hash_functions() {
+        assert_eq!(
+            "sha2-256".parse::<HashFunction>().unwrap(),
+            HashFunction::Sha2_256
+        );
+        assert_eq!(
+            "blake2b-256".parse::<HashFunction>().unwrap(),
+            HashFunction::Blake2b256
+        );
+        assert_eq!(
+            "blake3".parse::<HashFunction>().unwrap(),
+            HashFunction::Blake3_256
+        );
+        assert!("md5".parse::<HashFunction>().is_err());
+    }
+}
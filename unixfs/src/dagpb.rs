@@ -1,7 +1,9 @@
 ///! dag-pb support operations. Placing this module inside unixfs module is a bit unfortunate but
 ///! follows from the inseparability of dag-pb and UnixFS.
-use crate::pb::PBNode;
+use crate::pb::{PBLink, PBNode};
+use crate::CidOptions;
 use alloc::borrow::Cow;
+use cid::Cid;
 use core::convert::TryFrom;
 use core::fmt;
 use core::ops::Range;
@@ -100,10 +102,113 @@ impl<T: AsRef<[u8]>, B: AsRef<[u8]>> PartialEq<B> for NodeData<T> {
     }
 }
 
+/// Builder for arbitrary dag-pb nodes, for example for creating custom structures which link to
+/// UnixFS trees. The links are written in the order they were added.
+///
+/// The Tsize for a link to the built node is the length of the block added to the sizes of the
+/// links, which is returned by [`NodeBuilder::total_size`].
+#[derive(Debug, Default, Clone)]
+pub struct NodeBuilder {
+    data: Option<Vec<u8>>,
+    links: Vec<(String, Cid, u64)>,
+    cid_options: CidOptions,
+}
+
+impl NodeBuilder {
+    /// Configures the `Data` of the node; by default the node has no data.
+    pub fn with_data(self, data: impl Into<Vec<u8>>) -> Self {
+        NodeBuilder {
+            data: Some(data.into()),
+            ..self
+        }
+    }
+
+    /// Adds a named link to `target`, with the cumulative size of the linked subtree.
+    pub fn with_link(mut self, name: impl Into<String>, target: Cid, total_size: u64) -> Self {
+        self.links.push((name.into(), target, total_size));
+        self
+    }
+
+    /// Configures the Cid version and the hash function; defaults to Cid version 0 with
+    /// SHA2-256.
+    pub fn with_cid_options(self, cid_options: CidOptions) -> Self {
+        NodeBuilder {
+            cid_options,
+            ..self
+        }
+    }
+
+    /// Returns the sum of the sizes of the links, and the length of the `block` created with
+    /// [`NodeBuilder::build`].
+    pub fn total_size(&self, block: &[u8]) -> u64 {
+        block.len() as u64 + self.links.iter().map(|(_, _, size)| size).sum::<u64>()
+    }
+
+    /// Serializes the node, returning the Cid and the block.
+    pub fn build(&self) -> (Cid, Vec<u8>) {
+        use quick_protobuf::{MessageWrite, Writer};
+
+        let links = self
+            .links
+            .iter()
+            .map(|(name, target, total_size)| PBLink {
+                Hash: Some(Cow::Owned(target.to_bytes())),
+                Name: Some(Cow::Borrowed(name.as_str())),
+                Tsize: Some(*total_size),
+            })
+            .collect();
+
+        let node = PBNode {
+            Links: links,
+            Data: self.data.as_deref().map(Cow::Borrowed),
+        };
+
+        let mut block = Vec::with_capacity(node.get_size());
+        let mut writer = Writer::new(&mut block);
+        node.write_message(&mut writer)
+            .expect("writing to a vec cannot fail");
+
+        let cid = self.cid_options.dag_pb(&block);
+        (cid, block)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::subslice_to_range;
 
+    #[test]
+    fn build_node() {
+        use super::{node_data, NodeBuilder};
+        use crate::pb::PBNode;
+        use cid::Cid;
+        use core::convert::TryFrom;
+
+        // empty directory
+        let (empty, block) = NodeBuilder::default().with_data(&b"\x08\x01"[..]).build();
+        assert_eq!(
+            empty.to_string(),
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"
+        );
+
+        let builder = NodeBuilder::default()
+            .with_link("a", empty.clone(), block.len() as u64)
+            .with_data(&b"manifest"[..]);
+
+        let (_, built) = builder.build();
+        assert_eq!(builder.total_size(&built), built.len() as u64 + 4);
+        assert_eq!(node_data(&built).unwrap(), Some(&b"manifest"[..]));
+
+        let parsed = PBNode::try_from(built.as_slice()).unwrap();
+        assert_eq!(parsed.Links.len(), 1);
+        assert_eq!(parsed.Links[0].Name.as_deref(), Some("a"));
+        assert_eq!(parsed.Links[0].Tsize, Some(4));
+        assert_eq!(
+            Cid::try_from(parsed.Links[0].Hash.as_deref().unwrap()).unwrap(),
+            empty
+        );
+    }
+
     #[test]
     fn subslice_ranges() {
         let full = &b"01234"[..];
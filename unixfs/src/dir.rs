@@ -4,6 +4,8 @@ use cid::Cid;
 use core::convert::TryFrom;
 use core::fmt;
 
+mod murmur3;
+
 mod sharded_lookup;
 pub use sharded_lookup::{Cache, LookupError, ShardError, ShardedLookup};
 
@@ -13,6 +15,9 @@ pub(crate) use directory::{check_directory_supported, UnexpectedDirectoryPropert
 /// Directory tree builder.
 pub mod builder;
 
+mod diff;
+pub use diff::{diff, Change, Diff, DiffError};
+
 pub(crate) fn check_hamtshard_supported(
     mut flat: FlatUnixFs<'_>,
 ) -> Result<FlatUnixFs<'_>, ShardError> {
@@ -47,6 +52,10 @@ pub fn resolve<'needle>(
         }
         Err(ParsingFailed::InvalidUnixFs(_, PBNode { Links: links, .. }))
         | Err(ParsingFailed::NoData(PBNode { Links: links, .. })) => links,
+        Ok(symlink) if symlink.data.Type == UnixFsType::Symlink => {
+            let target = symlink.data.Data.unwrap_or_default();
+            return Err(ResolveError::Symlink(target.into_owned()));
+        }
         Ok(other) => {
             // go-ipfs does not resolve links under File, probably it's not supposed to work on
             // anything else then; returning NotFound would be correct, but perhaps it's even more
@@ -100,6 +109,9 @@ pub enum MaybeResolved<'needle> {
 pub enum ResolveError {
     /// The target block was a UnixFs node that doesn't support resolving, e.g. a file.
     UnexpectedType(UnexpectedNodeType),
+    /// The target block was a symlink to the given target path, which is not followed as it is
+    /// relative to the directory containing the symlink.
+    Symlink(Vec<u8>),
     /// A directory had unsupported properties. These are not encountered during walking sharded
     /// directories.
     UnexpectedDirProperties(UnexpectedDirectoryProperties),
@@ -121,6 +133,11 @@ impl fmt::Display for ResolveError {
         use ResolveError::*;
         match self {
             UnexpectedType(ut) => write!(fmt, "unexpected type for UnixFs: {:?}", ut),
+            Symlink(target) => write!(
+                fmt,
+                "cannot resolve through a symlink to {:?}",
+                String::from_utf8_lossy(target)
+            ),
             UnexpectedDirProperties(udp) => write!(fmt, "unexpected directory properties: {}", udp),
             Read(e) => write!(fmt, "parsing failed: {}", e),
             Lookup(e) => write!(fmt, "{}", e),
@@ -212,7 +229,7 @@ impl MultipleMatchingLinks {
 #[cfg(test)]
 mod tests {
 
-    use super::{resolve, MaybeResolved};
+    use super::{resolve, MaybeResolved, ResolveError};
     use crate::test_support::FakeBlockstore;
     use cid::Cid;
     use core::convert::TryFrom;
@@ -270,6 +287,17 @@ mod tests {
         resolve(&payload[..], "anything", &mut None).unwrap_err();
     }
 
+    #[test]
+    fn errors_with_symlink_target() {
+        let mut payload = Vec::new();
+        crate::symlink::serialize_symlink_block("../foo/bar", &mut payload);
+
+        match resolve(&payload[..], "anything", &mut None) {
+            Err(ResolveError::Symlink(target)) => assert_eq!(target, b"../foo/bar"),
+            x => panic!("{:?}", x),
+        }
+    }
+
     #[test]
     fn sharded_directory_linking_to_non_sharded() {
         // created this test case out of doubt that we could fail a traversal as ShardedLookup
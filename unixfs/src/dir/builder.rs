@@ -1,5 +1,7 @@
+use crate::CidOptions;
 use cid::Cid;
 use core::fmt;
+use std::path::PathBuf;
 
 mod dir_builder;
 use dir_builder::DirBuilder;
@@ -10,8 +12,16 @@ pub use iter::{OwnedTreeNode, PostOrderIterator, TreeNode};
 mod buffered;
 pub use buffered::BufferingTreeBuilder;
 
+mod streaming;
+pub use streaming::{StreamingTreeBuilder, StreamingTreeFailed};
+
+mod spill;
+use spill::{SpillFile, SpilledTree};
+
 mod custom_pb;
-use custom_pb::CustomFlatUnixFs;
+use custom_pb::{estimated_size, CustomFlatUnixFs};
+
+mod hamt;
 
 enum Entry {
     Leaf(Leaf),
@@ -55,6 +65,12 @@ impl fmt::Debug for Leaf {
 pub struct TreeOptions {
     block_size_limit: Option<u64>,
     wrap_with_directory: bool,
+    cid_options: CidOptions,
+    memory_budget: Option<usize>,
+    spill_directory: Option<PathBuf>,
+    overwrite_policy: OverwritePolicy,
+    shard_threshold: Option<u64>,
+    force_sharding: bool,
 }
 
 impl Default for TreeOptions {
@@ -63,6 +79,13 @@ impl Default for TreeOptions {
             // this is just a guess; our bitswap message limit is a bit more
             block_size_limit: Some(512 * 1024),
             wrap_with_directory: false,
+            cid_options: CidOptions::default(),
+            memory_budget: None,
+            spill_directory: None,
+            overwrite_policy: OverwritePolicy::default(),
+            // go-ipfs shards the directories estimated to be at least this large
+            shard_threshold: Some(256 * 1024),
+            force_sharding: false,
         }
     }
 }
@@ -79,6 +102,68 @@ impl TreeOptions {
     pub fn wrap_with_directory(&mut self) {
         self.wrap_with_directory = true;
     }
+
+    /// Overrides the default Cid version 0 and SHA2-256 used for the directory and symlink
+    /// blocks.
+    pub fn cid_options(&mut self, cid_options: CidOptions) {
+        self.cid_options = cid_options;
+    }
+
+    /// Limits the approximate amount of memory in bytes used by `BufferingTreeBuilder` for the
+    /// added entries. When the limit is exceeded, the entries are written to a temporary file
+    /// and read back while building, which allows building trees with millions of entries.
+    /// Defaults to `None`, or no limit.
+    pub fn memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    /// Overrides the directory for the temporary files used when the `memory_budget` is
+    /// exceeded. Defaults to `std::env::temp_dir()`.
+    pub fn spill_directory(&mut self, directory: PathBuf) {
+        self.spill_directory = Some(directory);
+    }
+
+    /// Overrides what `BufferingTreeBuilder` does when a path is added again, or when a path has
+    /// already been added as a file or a symlink and is now needed as a directory. Defaults to
+    /// `OverwritePolicy::Error`. The `StreamingTreeBuilder` always errors.
+    pub fn overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.overwrite_policy = policy;
+    }
+
+    /// Overrides the estimated size in bytes of the directory at or above which the directory is
+    /// HAMT sharded instead. Like in go-ipfs, the size is estimated as the sum of the lengths of
+    /// the names and the Cids of the links, not as the size of the flat directory block. If the
+    /// threshold is set to `None`, the directories are sharded only when `force_sharding` has been
+    /// given. Defaults to 256 KiB.
+    pub fn shard_threshold(&mut self, threshold: Option<u64>) {
+        self.shard_threshold = threshold;
+    }
+
+    /// When called, all of the directories are HAMT sharded, regardless of their size.
+    pub fn force_sharding(&mut self) {
+        self.force_sharding = true;
+    }
+}
+
+/// What to do with a path which has already been added to a `BufferingTreeBuilder`, see
+/// [`TreeOptions::overwrite_policy`]. Adding entries into an already added directory, or setting
+/// its metadata, is never a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Fail with `TreeBuildingFailed::DuplicatePath` or `TreeBuildingFailed::LeafAsDirectory`.
+    Error,
+    /// The latest entry replaces the earlier one, including a whole directory subtree when a link
+    /// is added at the path of a directory.
+    Replace,
+    /// The first entry is kept and the later entries at the same path are ignored, along with any
+    /// entries which would need a file or a symlink to be a directory.
+    KeepFirst,
+}
+
+impl Default for OverwritePolicy {
+    fn default() -> Self {
+        OverwritePolicy::Error
+    }
 }
 
 /// Tree building failure cases.
@@ -97,6 +182,11 @@ pub enum TreeBuildingFailed {
     DuplicatePath(String),
     /// The given full path had already been added as a link to an opaque entry.
     LeafAsDirectory(String),
+    /// The given full path was inside a directory which had already been completed by the
+    /// `StreamingTreeBuilder`, as the entries were not added in a sorted order.
+    UnsortedPath(String),
+    /// The entries could not be written to a temporary file after exceeding the memory budget.
+    Spill(std::io::Error),
 }
 
 impl fmt::Display for TreeBuildingFailed {
@@ -118,20 +208,42 @@ impl fmt::Display for TreeBuildingFailed {
                 "attempted to use already added leaf as a subdirectory: {:?}",
                 s
             ),
+            UnsortedPath(s) => write!(
+                fmt,
+                "path is inside an already completed directory: {:?}",
+                s
+            ),
+            Spill(e) => write!(fmt, "failed to write entries to a temporary file: {}", e),
         }
     }
 }
 
-impl std::error::Error for TreeBuildingFailed {}
+impl std::error::Error for TreeBuildingFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TreeBuildingFailed::Spill(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 /// Failure cases for `PostOrderIterator` creating the tree dag-pb nodes.
 #[derive(Debug)]
 pub enum TreeConstructionFailed {
     /// Failed to serialize the protobuf node for the directory
     Protobuf(quick_protobuf::Error),
-    /// The resulting directory would be too large and HAMT sharding is yet to be implemented or
-    /// denied.
+    /// The resulting directory would be too large and HAMT sharding was disabled, or a single
+    /// HAMT shard would be too large.
     TooLargeBlock(u64),
+    /// The two names have the same hash, so they cannot be placed into different buckets of a
+    /// HAMT sharded directory.
+    HashCollision(String, String),
+    /// Reading the entries back from the temporary files failed.
+    Spill(std::io::Error),
+    /// The entries written to the temporary files could not be added to the tree. As the
+    /// entries were not all in memory, the duplicates and other conflicts between the spilled
+    /// entries are found only while building.
+    SpilledEntry(TreeBuildingFailed),
 }
 
 impl fmt::Display for TreeConstructionFailed {
@@ -141,11 +253,24 @@ impl fmt::Display for TreeConstructionFailed {
         match self {
             Protobuf(e) => write!(fmt, "serialization failed: {}", e),
             TooLargeBlock(size) => write!(fmt, "attempted to create block of {} bytes", size),
+            HashCollision(a, b) => write!(fmt, "names {:?} and {:?} have the same hash", a, b),
+            Spill(e) => write!(fmt, "failed to read entries from a temporary file: {}", e),
+            SpilledEntry(e) => write!(fmt, "{}", e),
         }
     }
 }
 
-impl std::error::Error for TreeConstructionFailed {}
+impl std::error::Error for TreeConstructionFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use TreeConstructionFailed::*;
+
+        match self {
+            Spill(e) => Some(e),
+            SpilledEntry(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 struct NamedLeaf(String, Cid, u64);
@@ -1,9 +1,14 @@
-use super::{DirBuilder, Entry, Leaf, PostOrderIterator, TreeBuildingFailed, TreeOptions};
+use super::{
+    DirBuilder, Leaf, PostOrderIterator, SpillFile, SpilledTree, TreeBuildingFailed, TreeOptions,
+};
 use crate::Metadata;
-use alloc::collections::btree_map::Entry::*;
 use cid::Cid;
 
 /// UnixFs directory tree builder which buffers entries until `build()` is called.
+///
+/// When a memory budget has been set with [`TreeOptions::memory_budget`], the buffered entries
+/// are written to temporary files whenever the budget is exceeded, and merged back in a sorted
+/// order while building.
 #[derive(Debug)]
 pub struct BufferingTreeBuilder {
     /// At the root there can be only one element, unless an option was given to create a new
@@ -14,6 +19,9 @@ pub struct BufferingTreeBuilder {
     // recover all children's rendered Cids
     counter: u64,
     opts: TreeOptions,
+    // approximate amount of memory used by the buffered entries
+    buffered_bytes: usize,
+    spilled: Vec<SpillFile>,
 }
 
 impl Default for BufferingTreeBuilder {
@@ -30,6 +38,8 @@ impl BufferingTreeBuilder {
             longest_path: 0,
             counter: 1,
             opts,
+            buffered_bytes: 0,
+            spilled: Vec::new(),
         }
     }
 
@@ -42,16 +52,43 @@ impl BufferingTreeBuilder {
         target: Cid,
         total_size: u64,
     ) -> Result<(), TreeBuildingFailed> {
+        let estimate = estimate_entry(full_path, &target);
+
         let leaf = Leaf {
             link: target,
             total_size,
         };
 
+        let policy = self.opts.overwrite_policy;
+
         self.modify_with(full_path, |parent, basename, _| {
             parent
-                .put_leaf(basename, leaf)
+                .put_leaf(basename, leaf, policy)
                 .map_err(|_| TreeBuildingFailed::DuplicatePath(full_path.to_string()))
-        })
+        })?;
+
+        self.buffered(estimate)
+    }
+
+    /// Serializes a symlink pointing to `target` and registers it at the given path. Returns the
+    /// Cid and the serialized block, which needs to be stored by the caller similar to the blocks
+    /// produced by `FileAdder`.
+    ///
+    /// The `target` is not validated in any way, as the symlinks validity depends on the
+    /// filesystem it will be exported to.
+    pub fn put_symlink(
+        &mut self,
+        full_path: &str,
+        target: &str,
+    ) -> Result<(Cid, Vec<u8>), TreeBuildingFailed> {
+        let mut block = Vec::new();
+        crate::symlink::serialize_symlink_block(target, &mut block);
+
+        let cid = self.opts.cid_options.dag_pb(&block);
+
+        self.put_link(full_path, cid.clone(), block.len() as u64)?;
+
+        Ok((cid, block))
     }
 
     /// Directories get "put" implicitly through the put files, and directories need to be adjusted
@@ -61,16 +98,48 @@ impl BufferingTreeBuilder {
         full_path: &str,
         metadata: Metadata,
     ) -> Result<(), TreeBuildingFailed> {
+        let policy = self.opts.overwrite_policy;
+
         // create all paths along the way
         //
         // set if not set, error otherwise? FIXME: doesn't error atm
         self.modify_with(full_path, |parent, basename, id| {
-            parent
-                .add_or_get_node(basename, id)
-                .map_err(|_| TreeBuildingFailed::LeafAsDirectory(full_path.to_string()))?
-                .set_metadata(metadata);
+            let node = parent
+                .add_or_get_node(basename, id, policy)
+                .map_err(|_| TreeBuildingFailed::LeafAsDirectory(full_path.to_string()))?;
+
+            if let Some(node) = node {
+                node.set_metadata(metadata);
+            }
             Ok(())
-        })
+        })?;
+
+        self.buffered(full_path.len() + ENTRY_OVERHEAD)
+    }
+
+    /// Accounts for a newly buffered entry, spilling all of the buffered entries to a temporary
+    /// file if the memory budget is exceeded.
+    fn buffered(&mut self, estimate: usize) -> Result<(), TreeBuildingFailed> {
+        self.buffered_bytes += estimate;
+
+        match self.opts.memory_budget {
+            Some(budget) if self.buffered_bytes > budget => {}
+            _ => return Ok(()),
+        }
+
+        let directory = self
+            .opts
+            .spill_directory
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+
+        let root = core::mem::replace(&mut self.root_builder, DirBuilder::root(0));
+        let spilled = SpillFile::write(&directory, root).map_err(TreeBuildingFailed::Spill)?;
+
+        self.spilled.push(spilled);
+        self.buffered_bytes = 0;
+
+        Ok(())
     }
 
     fn modify_with<F>(&mut self, full_path: &str, f: F) -> Result<(), TreeBuildingFailed>
@@ -99,6 +168,7 @@ impl BufferingTreeBuilder {
 
         // needed to avoid borrowing into the DirBuilder::new calling closure
         let counter = &mut self.counter;
+        let policy = self.opts.overwrite_policy;
 
         while let Some((depth, next)) = remaining.next() {
             let last = remaining.peek().is_none();
@@ -122,7 +192,10 @@ impl BufferingTreeBuilder {
             }
 
             // our first level can be full, depending on the options given
-            let full = depth == 0 && !self.opts.wrap_with_directory && !dir_builder.is_empty();
+            let full = depth == 0
+                && !self.opts.wrap_with_directory
+                && !dir_builder.is_empty()
+                && !dir_builder.nodes.contains_key(next);
 
             if last {
                 let mut next_id = Some(*counter);
@@ -145,21 +218,24 @@ impl BufferingTreeBuilder {
                 return ret;
             }
 
-            let parent_id = dir_builder.id;
+            if full {
+                return Err(TreeBuildingFailed::TooManyRootLevelEntries);
+            }
 
-            dir_builder = match (full, dir_builder.nodes.entry(next.to_string())) {
-                (_, Occupied(oe)) => oe
-                    .into_mut()
-                    .as_dir_builder()
-                    .map_err(|_| TreeBuildingFailed::LeafAsDirectory(full_path.to_string()))?,
-                (false, Vacant(ve)) => {
-                    let next_id = *counter;
-                    *counter += 1;
-                    ve.insert(Entry::Directory(DirBuilder::new(parent_id, next_id)))
-                        .as_dir_builder()
-                        .expect("safe: we just inserted a DirBuilder")
-                }
-                (true, Vacant(_)) => return Err(TreeBuildingFailed::TooManyRootLevelEntries),
+            let mut next_id = Some(*counter);
+
+            let next = dir_builder
+                .add_or_get_node(next.to_string(), &mut next_id, policy)
+                .map_err(|_| TreeBuildingFailed::LeafAsDirectory(full_path.to_string()))?;
+
+            if next_id.is_none() {
+                *counter += 1;
+            }
+
+            dir_builder = match next {
+                Some(next) => next,
+                // the leaf is kept and the entry under it is ignored
+                None => return Ok(()),
             };
         }
 
@@ -179,15 +255,31 @@ impl BufferingTreeBuilder {
     /// its data during the walk. `PostOrderIterator` implements `Iterator` while also allowing
     /// borrowed access via `next_borrowed`.
     pub fn build(self) -> PostOrderIterator {
-        PostOrderIterator::new(self.root_builder, self.opts, self.longest_path)
+        if self.spilled.is_empty() {
+            PostOrderIterator::new(self.root_builder, self.opts, self.longest_path)
+        } else {
+            let spilled = SpilledTree::new(self.spilled, self.root_builder, self.opts.clone());
+            PostOrderIterator::from_spilled(spilled, self.opts)
+        }
     }
 }
 
+/// Rough estimate of the bookkeeping of a single entry in the `DirBuilder` tree, in addition to
+/// the name and the Cid.
+const ENTRY_OVERHEAD: usize = 64;
+
+fn estimate_entry(full_path: &str, target: &Cid) -> usize {
+    full_path.len() + target.hash().digest().len() + ENTRY_OVERHEAD
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        super::OwnedTreeNode, BufferingTreeBuilder, Metadata, TreeBuildingFailed, TreeOptions,
+        super::{OwnedTreeNode, TreeConstructionFailed},
+        BufferingTreeBuilder, Metadata, TreeBuildingFailed, TreeOptions,
     };
+    use crate::dir::builder::OverwritePolicy;
+    use crate::pb::UnixFsType;
     use cid::Cid;
     use core::convert::TryFrom;
 
@@ -361,6 +453,57 @@ mod tests {
         assert_eq!(actual, &["a/b/c/d", "a/b/c", "a/b", "a",])
     }
 
+    #[test]
+    fn metadata_is_written_to_directories() {
+        let mut builder = BufferingTreeBuilder::default();
+        let metadata = Metadata::default()
+            .with_mode(0o750)
+            .with_mtime(1_600_000_000, 123_456_789);
+
+        builder.set_metadata("a/b", metadata.clone()).unwrap();
+        builder.put_link("a/b/c.txt", some_cid(0), 1).unwrap();
+
+        let actual = builder
+            .build()
+            .map(|res| {
+                res.map(|OwnedTreeNode { path, block, .. }| {
+                    let flat = crate::pb::FlatUnixFs::try_from(&block[..]).unwrap();
+                    (path, Metadata::from(&flat.data))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            actual,
+            &[
+                ("a/b".to_string(), metadata),
+                ("a".to_string(), Metadata::default())
+            ]
+        );
+    }
+
+    #[test]
+    fn cid_v1_directories() {
+        use crate::{CidOptions, HashFunction};
+
+        let cid_options = CidOptions::v1(HashFunction::Blake2b256);
+        let mut opts = TreeOptions::default();
+        opts.cid_options(cid_options);
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        let (symlink, _) = builder.put_symlink("a/c", "b.txt").unwrap();
+
+        assert_eq!(symlink.version(), cid::Version::V1);
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].cid, cid_options.dag_pb(&nodes[0].block));
+        assert_eq!(u64::from(nodes[0].cid.codec()), 0x70);
+    }
+
     #[test]
     fn set_metadata_on_file() {
         let mut builder = BufferingTreeBuilder::default();
@@ -376,6 +519,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn put_symlink_next_to_file() {
+        let mut builder = BufferingTreeBuilder::default();
+
+        builder
+            .put_link(
+                "foo_directory/b/car",
+                Cid::try_from("QmNYVgoDXh3dqC1jjCuYqQ9w4XfiocehPZjEPiQiCVYv33").unwrap(),
+                12,
+            )
+            .unwrap();
+
+        let (cid, block) = builder.put_symlink("foo_directory/a", "b").unwrap();
+
+        // same as in `crate::symlink::tests::simple_symlink`
+        assert_eq!(
+            cid.to_string(),
+            "QmfLJN6HLyREnWr7QQNmgmuNziUhcbwUopkHQ8gD3pMfp6"
+        );
+        assert_eq!(block.len(), 7);
+
+        let last = builder.build().last().unwrap().unwrap();
+
+        // same as in `crate::symlink::tests::symlinks_in_trees_rooted`
+        assert_eq!(
+            last.cid.to_string(),
+            "QmZDVQHwjHwA4SyzEDtJLNxmZeJVK1W8BWFAHV61x2Rs19"
+        );
+    }
+
+    #[test]
+    fn put_symlink_twice() {
+        let mut builder = BufferingTreeBuilder::default();
+        builder.put_symlink("a/b", "c").unwrap();
+        let err = builder.put_symlink("a/b", "d").unwrap_err();
+
+        assert!(
+            matches!(err, TreeBuildingFailed::DuplicatePath(_)),
+            "{:?}",
+            err
+        );
+    }
+
     #[test]
     fn dir_with_cidv1_link() {
         // this is `echo '{ "name": "hello" }` | ./ipfs dag put`
@@ -396,6 +582,333 @@ mod tests {
         verify_results(expected, actual);
     }
 
+    #[test]
+    fn spilled_tree_matches_buffered() {
+        let spill_directory = spill_directory("matches");
+
+        let metadata = Metadata::default().with_mode(0o750);
+        let build = |budget| {
+            let mut opts = TreeOptions::default();
+            opts.wrap_with_directory();
+            opts.memory_budget(budget);
+            opts.spill_directory(spill_directory.clone());
+
+            let mut builder = BufferingTreeBuilder::new(opts);
+            builder.put_link("a/b/c.txt", some_cid(0), 1).unwrap();
+            // sorts between "a" and "a/b" as a string, but not as a path
+            builder.put_link("a-b.txt", some_cid(1), 1).unwrap();
+            builder.set_metadata("a/b", metadata.clone()).unwrap();
+            builder.put_link("a/d.txt", some_cid(2), 1).unwrap();
+            builder.set_metadata("e", Metadata::default()).unwrap();
+            builder.put_link("a/b/f.txt", some_cid(3), 1).unwrap();
+
+            let mut nodes = builder
+                .build()
+                .map(|res| res.map(|n| (n.path, n.cid.to_string(), n.total_size)))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            nodes.sort();
+            nodes
+        };
+
+        let buffered = build(None);
+        // spills after every entry
+        let spilled = build(Some(1));
+
+        assert_eq!(spilled, buffered);
+        assert_eq!(
+            buffered
+                .iter()
+                .map(|(path, ..)| path.as_str())
+                .collect::<Vec<_>>(),
+            &["", "a", "a/b", "e"]
+        );
+
+        // the temporary files are removed once the build is complete
+        assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0);
+        std::fs::remove_dir(&spill_directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn spill_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let spill_directory = spill_directory("private");
+
+        let mut opts = TreeOptions::default();
+        opts.memory_budget(Some(1));
+        opts.spill_directory(spill_directory.clone());
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("a.txt", some_cid(0), 1).unwrap();
+        builder.put_link("b.txt", some_cid(1), 1).unwrap();
+
+        let modes = std::fs::read_dir(&spill_directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().permissions().mode() & 0o777)
+            .collect::<Vec<_>>();
+
+        assert!(!modes.is_empty());
+        assert!(modes.iter().all(|&mode| mode == 0o600), "{:?}", modes);
+
+        drop(builder);
+        assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0);
+        std::fs::remove_dir(&spill_directory).unwrap();
+    }
+
+    #[test]
+    fn duplicate_found_while_building_spilled() {
+        let spill_directory = spill_directory("duplicate");
+
+        let mut opts = TreeOptions::default();
+        opts.memory_budget(Some(1));
+        opts.spill_directory(spill_directory.clone());
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        // not found right away as the first one has already been spilled
+        builder.put_link("a/b.txt", some_cid(1), 1).unwrap();
+
+        let err = builder
+            .build()
+            .collect::<Result<Vec<_>, _>>()
+            .err()
+            .unwrap();
+
+        assert!(
+            matches!(
+                err,
+                TreeConstructionFailed::SpilledEntry(TreeBuildingFailed::DuplicatePath(_))
+            ),
+            "{:?}",
+            err
+        );
+
+        assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0);
+        std::fs::remove_dir(&spill_directory).unwrap();
+    }
+
+    #[test]
+    fn overwrite_policies() {
+        let spill_directory = spill_directory("overwrite");
+        let metadata = Metadata::default().with_mode(0o750);
+
+        let options = |policy, budget| {
+            let mut opts = TreeOptions::default();
+            opts.wrap_with_directory();
+            opts.overwrite_policy(policy);
+            opts.memory_budget(budget);
+            opts.spill_directory(spill_directory.clone());
+            opts
+        };
+
+        let build = |policy, budget| {
+            let mut builder = BufferingTreeBuilder::new(options(policy, budget));
+            builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+            builder.put_link("a/c/d.txt", some_cid(1), 1).unwrap();
+            builder.set_metadata("a/c", metadata.clone()).unwrap();
+            // the same path again
+            builder.put_link("a/b.txt", some_cid(2), 1).unwrap();
+            // a link in place of a directory
+            builder.put_link("a/c", some_cid(3), 1).unwrap();
+            // a directory in place of a link
+            builder.put_link("a/b.txt/e.txt", some_cid(4), 1).unwrap();
+            builder
+        };
+
+        let mut replaced = BufferingTreeBuilder::new(options(OverwritePolicy::Error, None));
+        replaced.put_link("a/b.txt/e.txt", some_cid(4), 1).unwrap();
+        replaced.put_link("a/c", some_cid(3), 1).unwrap();
+
+        let mut kept = BufferingTreeBuilder::new(options(OverwritePolicy::Error, None));
+        kept.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        kept.put_link("a/c/d.txt", some_cid(1), 1).unwrap();
+        kept.set_metadata("a/c", metadata.clone()).unwrap();
+
+        for &(policy, ref expected) in &[
+            (OverwritePolicy::Replace, sorted_nodes(replaced)),
+            (OverwritePolicy::KeepFirst, sorted_nodes(kept)),
+        ] {
+            assert_eq!(&sorted_nodes(build(policy, None)), expected, "{:?}", policy);
+            // spills after every entry, so the conflicts are resolved while building
+            assert_eq!(
+                &sorted_nodes(build(policy, Some(1))),
+                expected,
+                "{:?}",
+                policy
+            );
+        }
+
+        assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0);
+        std::fs::remove_dir(&spill_directory).unwrap();
+    }
+
+    #[test]
+    fn forced_sharding_resolves_every_entry() {
+        let mut opts = TreeOptions::default();
+        opts.force_sharding();
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+        for i in 0..1000 {
+            builder
+                .put_link(&format!("dir/file-{}", i), some_cid(i), 1)
+                .unwrap();
+        }
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        // the nested shards come first, and only the root shard is not an inner shard
+        let (root, shards) = nodes.split_last().unwrap();
+        assert_eq!(root.path, "dir");
+        assert!(!root.inner_shard);
+        assert!(!shards.is_empty());
+        assert!(shards.iter().all(|n| n.inner_shard && n.path == "dir"));
+
+        let blocks = nodes
+            .iter()
+            .map(|n| (n.cid.to_string(), &n.block[..]))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        for i in 0..1000 {
+            let name = format!("file-{}", i);
+            assert_eq!(lookup(&blocks, &root.block, &name), Some(some_cid(i)));
+        }
+
+        assert_eq!(lookup(&blocks, &root.block, "file-1000"), None);
+    }
+
+    #[test]
+    fn large_directories_are_sharded() {
+        let spill_directory = spill_directory("sharded");
+
+        let build = |threshold, budget| {
+            let mut opts = TreeOptions::default();
+            opts.shard_threshold(threshold);
+            opts.memory_budget(budget);
+            opts.spill_directory(spill_directory.clone());
+
+            let mut builder = BufferingTreeBuilder::new(opts);
+            for i in 0..100 {
+                builder
+                    .put_link(&format!("dir/file-{}", i), some_cid(i), 1)
+                    .unwrap();
+            }
+            builder
+                .put_link("dir/small/file", some_cid(100), 1)
+                .unwrap();
+
+            let mut nodes = builder
+                .build()
+                .map(|res| {
+                    res.map(|n| {
+                        let flat = crate::pb::FlatUnixFs::try_from(&n.block[..]).unwrap();
+                        (n.path, n.cid.to_string(), n.inner_shard, flat.data.Type)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            nodes.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+            nodes
+        };
+
+        let types = |nodes: &[(String, String, bool, UnixFsType)]| {
+            nodes
+                .iter()
+                .filter(|(_, _, inner_shard, _)| !inner_shard)
+                .map(|(path, _, _, ty)| (path.clone(), *ty))
+                .collect::<Vec<_>>()
+        };
+
+        let flat = build(None, None);
+        assert_eq!(
+            types(&flat),
+            &[
+                ("dir".to_owned(), UnixFsType::Directory),
+                ("dir/small".to_owned(), UnixFsType::Directory)
+            ]
+        );
+
+        // only the directory over the threshold is sharded
+        let sharded = build(Some(1024), None);
+        assert_eq!(
+            types(&sharded),
+            &[
+                ("dir".to_owned(), UnixFsType::HAMTShard),
+                ("dir/small".to_owned(), UnixFsType::Directory)
+            ]
+        );
+
+        // the spilled entries are built through `StreamingTreeBuilder` which shards the same way
+        assert_eq!(build(Some(1024), Some(1)), sharded);
+
+        assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0);
+        std::fs::remove_dir(&spill_directory).unwrap();
+    }
+
+    #[test]
+    fn shard_threshold_uses_the_go_ipfs_estimate() {
+        let root_type = |threshold| {
+            let mut opts = TreeOptions::default();
+            opts.shard_threshold(Some(threshold));
+
+            let mut builder = BufferingTreeBuilder::new(opts);
+            builder.put_link("dir/a", some_cid(0), 1).unwrap();
+
+            let root = builder.build().last().unwrap().unwrap();
+            crate::pb::FlatUnixFs::try_from(&root.block[..])
+                .unwrap()
+                .data
+                .Type
+        };
+
+        // the name of the only link and the 34 bytes of its Cid
+        assert_eq!(root_type(1 + 34), UnixFsType::HAMTShard);
+        assert_eq!(root_type(1 + 34 + 1), UnixFsType::Directory);
+    }
+
+    /// Resolves the name in the HAMT sharded directory through the given blocks.
+    fn lookup(
+        blocks: &std::collections::HashMap<String, &[u8]>,
+        root: &[u8],
+        name: &str,
+    ) -> Option<Cid> {
+        use crate::dir::{resolve, MaybeResolved};
+
+        let mut resolved = resolve(root, name, &mut None).unwrap();
+
+        loop {
+            let lookup = match resolved {
+                MaybeResolved::Found(cid) => return Some(cid),
+                MaybeResolved::NotFound => return None,
+                MaybeResolved::NeedToLoadMore(lookup) => lookup,
+            };
+
+            let next = blocks[&lookup.pending_links().0.to_string()];
+            resolved = lookup.continue_walk(next, &mut None).unwrap();
+        }
+    }
+
+    fn sorted_nodes(builder: BufferingTreeBuilder) -> Vec<(String, String, u64)> {
+        let mut nodes = builder
+            .build()
+            .map(|res| res.map(|n| (n.path, n.cid.to_string(), n.total_size)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        nodes.sort();
+        nodes
+    }
+
+    fn spill_directory(test: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("unixfs-spill-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
     fn verify_results(
         mut expected: Vec<(
             impl AsRef<str> + core::fmt::Debug,
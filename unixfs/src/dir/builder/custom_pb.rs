@@ -38,6 +38,16 @@ impl<'a> MessageWrite for CustomFlatUnixFs<'a> {
     }
 }
 
+/// Returns the size go-ipfs estimates for the directory of the `links` when deciding whether to
+/// shard it: the sum of the lengths of the names and the Cids of the links.
+pub(super) fn estimated_size(links: &[Option<NamedLeaf>]) -> u64 {
+    links
+        .iter()
+        .flatten()
+        .map(|NamedLeaf(name, cid, _)| (name.len() + WriteableCid(cid).get_size()) as u64)
+        .sum()
+}
+
 /// Custom NamedLeaf as PBLink "adapter."
 struct NamedLeafAsPBLink<'a>(&'a NamedLeaf);
 
@@ -1,4 +1,4 @@
-use super::{Entry, Leaf};
+use super::{Entry, Leaf, OverwritePolicy};
 use crate::Metadata;
 use alloc::collections::btree_map::Entry::*;
 use alloc::collections::BTreeMap;
@@ -12,7 +12,7 @@ pub(super) struct DirBuilder {
     /// Immediate files, symlinks or directories in this directory
     pub nodes: BTreeMap<String, Entry>,
     /// Metadata for this directory
-    metadata: Metadata,
+    pub metadata: Metadata,
     /// Id of the parent; None for the root node
     pub parent_id: Option<u64>,
     /// Internal id, used for propagating Cids back from children during post order visit.
@@ -39,9 +39,23 @@ impl DirBuilder {
         }
     }
 
-    pub fn put_leaf(&mut self, key: String, leaf: Leaf) -> Result<(), DuplicateName> {
+    /// Puts the leaf at `key`, resolving a conflict with an existing entry according to the
+    /// `policy`.
+    pub fn put_leaf(
+        &mut self,
+        key: String,
+        leaf: Leaf,
+        policy: OverwritePolicy,
+    ) -> Result<(), DuplicateName> {
         match self.nodes.entry(key) {
-            Occupied(_) => Err(DuplicateName),
+            Occupied(mut oe) => match policy {
+                OverwritePolicy::Error => Err(DuplicateName),
+                OverwritePolicy::Replace => {
+                    oe.insert(Entry::Leaf(leaf));
+                    Ok(())
+                }
+                OverwritePolicy::KeepFirst => Ok(()),
+            },
             Vacant(ve) => {
                 ve.insert(Entry::Leaf(leaf));
                 Ok(())
@@ -49,17 +63,38 @@ impl DirBuilder {
         }
     }
 
+    /// Returns the directory at `key`, creating it with the `id` if needed. An existing leaf at
+    /// `key` is handled according to the `policy`: it is replaced with a new directory, or `None`
+    /// is returned when the leaf is to be kept.
     pub fn add_or_get_node(
         &mut self,
         key: String,
         id: &mut Option<u64>,
-    ) -> Result<&mut DirBuilder, FoundLeaf> {
+        policy: OverwritePolicy,
+    ) -> Result<Option<&mut DirBuilder>, FoundLeaf> {
+        let parent_id = self.id;
+
         match self.nodes.entry(key) {
-            Occupied(oe) => oe.into_mut().as_dir_builder().map_err(|_| FoundLeaf),
+            Occupied(oe) => {
+                let entry = oe.into_mut();
+
+                if let Entry::Leaf(_) = entry {
+                    match policy {
+                        OverwritePolicy::Error => return Err(FoundLeaf),
+                        OverwritePolicy::KeepFirst => return Ok(None),
+                        OverwritePolicy::Replace => {
+                            let id = id.take().unwrap();
+                            *entry = Entry::Directory(Self::new(parent_id, id));
+                        }
+                    }
+                }
+
+                Ok(Some(entry.as_dir_builder().expect("leaf was replaced")))
+            }
             Vacant(ve) => {
                 let id = id.take().unwrap();
-                let entry = ve.insert(Entry::Directory(Self::new(self.id, id)));
-                Ok(entry.as_dir_builder().expect("just inserted"))
+                let entry = ve.insert(Entry::Directory(Self::new(parent_id, id)));
+                Ok(Some(entry.as_dir_builder().expect("just inserted")))
             }
         }
     }
@@ -0,0 +1,138 @@
+//! Rendering of the HAMT sharded directories, laid out the same way as go-ipfs does: the names are
+//! hashed with murmur3 and each level of shards consumes one byte of the hash, giving 256 buckets
+//! per shard. A bucket with a single entry links to the entry directly under a name prefixed with
+//! the uppercase hex of the bucket index, and a bucket with more entries links to a nested shard
+//! under just the prefix.
+use super::{
+    iter::write_node, Leaf, NamedLeaf, OwnedTreeNode, TreeConstructionFailed, TreeOptions,
+};
+use crate::dir::murmur3::{bucket_prefix, hash64, FANOUT, HASH_TYPE};
+use crate::pb::{UnixFs, UnixFsType};
+use crate::Metadata;
+use alloc::borrow::Cow;
+
+/// Renders the links as a HAMT sharded directory. The root shard is written to the `buffer` and
+/// the nested shards are pushed to `shards` in post order, with the given `path`.
+pub(super) fn render(
+    path: &str,
+    links: &[Option<NamedLeaf>],
+    metadata: &Metadata,
+    buffer: &mut Vec<u8>,
+    shards: &mut Vec<OwnedTreeNode>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let mut entries = links
+        .iter()
+        .map(|link| link.as_ref().expect("all links are ready when rendering"))
+        .map(|link| (hash64(link.0.as_bytes()), link))
+        .collect::<Vec<_>>();
+
+    // sorting by the hash makes the entries of every bucket at every depth contiguous, and the
+    // buckets end up in the order of their index, which is the order of the links in the shard
+    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let shard = Shard {
+        path,
+        buffer,
+        shards,
+        opts,
+    };
+
+    shard.render(&entries, 0, Some(metadata))
+}
+
+struct Shard<'a> {
+    path: &'a str,
+    buffer: &'a mut Vec<u8>,
+    shards: &'a mut Vec<OwnedTreeNode>,
+    opts: &'a TreeOptions,
+}
+
+impl Shard<'_> {
+    /// Renders the shard of the `entries` at `depth` into the buffer. Only the root shard has
+    /// metadata.
+    fn render(
+        mut self,
+        entries: &[([u8; 8], &NamedLeaf)],
+        depth: usize,
+        metadata: Option<&Metadata>,
+    ) -> Result<Leaf, TreeConstructionFailed> {
+        let mut links = Vec::new();
+        let mut bitfield = [0u8; (FANOUT / 8) as usize];
+        let mut rest = entries;
+
+        while let Some((hash, _)) = rest.first() {
+            let index = hash[depth];
+            let len = rest
+                .iter()
+                .take_while(|(other, _)| other[depth] == index)
+                .count();
+            let (bucket, remaining) = rest.split_at(len);
+            rest = remaining;
+
+            // the bitfield is a big-endian number where the bit of the bucket index is set
+            bitfield[bitfield.len() - 1 - index as usize / 8] |= 1 << (index % 8);
+
+            let prefix = bucket_prefix(hash, depth).expect("depth is checked before descending");
+            let prefix = core::str::from_utf8(&prefix).expect("prefix is ascii hex");
+
+            if let [(_, NamedLeaf(name, cid, total_size))] = bucket {
+                let name = format!("{}{}", prefix, name);
+                links.push(Some(NamedLeaf(name, cid.clone(), *total_size)));
+                continue;
+            }
+
+            if depth + 1 == hash.len() {
+                // the entries have the same 64-bit hash, and would need to be placed in the same
+                // bucket on every level
+                let first = (bucket[0].1).0.clone();
+                let second = (bucket[1].1).0.clone();
+                return Err(TreeConstructionFailed::HashCollision(first, second));
+            }
+
+            let nested = Shard {
+                path: self.path,
+                buffer: &mut *self.buffer,
+                shards: &mut *self.shards,
+                opts: self.opts,
+            };
+
+            let leaf = nested.render(bucket, depth + 1, None)?;
+
+            self.shards.push(OwnedTreeNode {
+                path: self.path.to_owned(),
+                cid: leaf.link.clone(),
+                total_size: leaf.total_size,
+                block: self.buffer.as_slice().into(),
+                inner_shard: true,
+            });
+
+            links.push(Some(NamedLeaf(
+                prefix.to_owned(),
+                leaf.link,
+                leaf.total_size,
+            )));
+        }
+
+        // the leading zeroes are trimmed like go-ipfs does, as the bitfield used to be a bigint
+        let used = bitfield
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(bitfield.len());
+        let bitfield = &bitfield[used..];
+
+        let mut data = UnixFs {
+            Type: UnixFsType::HAMTShard,
+            Data: Some(Cow::Borrowed(bitfield)).filter(|bitfield| !bitfield.is_empty()),
+            hashType: Some(HASH_TYPE),
+            fanout: Some(FANOUT),
+            ..Default::default()
+        };
+
+        if let Some(metadata) = metadata {
+            metadata.apply_to(&mut data);
+        }
+
+        write_node(&links, data, self.buffer, self.opts)
+    }
+}
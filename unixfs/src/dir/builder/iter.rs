@@ -1,9 +1,10 @@
 use super::{
-    CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
+    estimated_size, hamt, CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, SpilledTree,
+    TreeConstructionFailed, TreeOptions,
 };
+use crate::Metadata;
 use cid::Cid;
 use core::fmt;
-use multihash::MultihashDigest;
 use std::collections::HashMap;
 
 /// Constructs the directory nodes required for a tree.
@@ -24,6 +25,13 @@ pub struct PostOrderIterator {
     total_size: u64,
     // from TreeOptions
     opts: TreeOptions,
+    // the entries were spilled to temporary files, and are built through this instead
+    spilled: Option<SpilledTree>,
+    // the shards of the latest HAMT sharded directory, popped in post order with the root shard
+    // last
+    shards: Vec<OwnedTreeNode>,
+    // the latest returned node when it was not rendered into `block_buffer`
+    owned: Option<OwnedTreeNode>,
 }
 
 /// The link list used to create the directory node. This list is created from a the BTreeMap
@@ -56,9 +64,11 @@ enum Visited {
         /// Leaves will be stored directly in this field when there are no DirBuilder descendants,
         /// in the `PostOrderIterator::persisted_cids` otherwise.
         leaves: LeafStorage,
+        metadata: Metadata,
     },
     PostRoot {
         leaves: LeafStorage,
+        metadata: Metadata,
     },
 }
 
@@ -75,95 +85,142 @@ impl PostOrderIterator {
             cid: None,
             total_size: 0,
             opts,
+            spilled: None,
+            shards: Vec::new(),
+            owned: None,
         }
     }
 
-    fn render_directory(
+    pub(super) fn from_spilled(spilled: SpilledTree, opts: TreeOptions) -> Self {
+        PostOrderIterator {
+            full_path: String::new(),
+            old_depth: 0,
+            block_buffer: Default::default(),
+            pending: Vec::new(),
+            persisted_cids: Default::default(),
+            reused_children: Vec::new(),
+            cid: None,
+            total_size: 0,
+            opts,
+            spilled: Some(spilled),
+            shards: Vec::new(),
+            owned: None,
+        }
+    }
+
+    /// Renders the directory of the `links` into the `buffer`. When the directory is HAMT
+    /// sharded, the nested shards are pushed to `shards` and the root shard is rendered into the
+    /// `buffer`.
+    pub(super) fn render_directory(
+        path: &str,
         links: &[Option<NamedLeaf>],
+        metadata: &Metadata,
         buffer: &mut Vec<u8>,
-        block_size_limit: &Option<u64>,
+        shards: &mut Vec<OwnedTreeNode>,
+        opts: &TreeOptions,
     ) -> Result<Leaf, TreeConstructionFailed> {
         use crate::pb::{UnixFs, UnixFsType};
-        use quick_protobuf::{BytesWriter, MessageWrite, Writer};
-        use sha2::{Digest, Sha256};
-
-        // FIXME: ideas on how to turn this into a HAMT sharding on some heuristic. we probably
-        // need to introduce states in to the "iterator":
-        //
-        // 1. bucketization
-        // 2. another post order visit of the buckets?
-        //
-        // the nested post order visit should probably re-use the existing infra ("message
-        // passing") and new ids can be generated by giving this iterator the counter from
-        // BufferedTreeBuilder.
-        //
-        // could also be that the HAMT shard building should start earlier, since the same
-        // heuristic can be detected *at* bufferedtreewriter. there the split would be easier, and
-        // this would "just" be a single node rendering, and not need any additional states..
-
-        let node = CustomFlatUnixFs {
-            links,
-            data: UnixFs {
-                Type: UnixFsType::Directory,
-                ..Default::default()
-            },
-        };
 
-        let size = node.get_size();
+        let mut data = UnixFs {
+            Type: UnixFsType::Directory,
+            ..Default::default()
+        };
 
-        if let Some(limit) = block_size_limit {
-            let size = size as u64;
-            if *limit < size {
-                // FIXME: this could probably be detected at builder
-                return Err(TreeConstructionFailed::TooLargeBlock(size));
-            }
-        }
+        metadata.apply_to(&mut data);
 
-        let cap = buffer.capacity();
+        // go-ipfs compares its estimate of the size instead of the size of the block
+        let sharded = opts.force_sharding
+            || opts
+                .shard_threshold
+                .map(|threshold| estimated_size(links) >= threshold)
+                .unwrap_or(false);
 
-        if let Some(additional) = size.checked_sub(cap) {
-            buffer.reserve(additional);
+        if sharded {
+            hamt::render(path, links, metadata, buffer, shards, opts)
+        } else {
+            write_node(links, data, buffer, opts)
         }
+    }
 
-        if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
-            let zeroes = [0; 8];
-
-            while needed_zeroes > 8 {
-                buffer.extend_from_slice(&zeroes[..]);
-                needed_zeroes -= zeroes.len();
-            }
-
-            buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    /// Renders the directory at `full_path` into the `block_buffer`, or when it is sharded, the
+    /// shards into `shards`.
+    fn render(
+        &mut self,
+        leaves: &[Option<NamedLeaf>],
+        metadata: &Metadata,
+    ) -> Result<Leaf, TreeConstructionFailed> {
+        let leaf = Self::render_directory(
+            &self.full_path,
+            leaves,
+            metadata,
+            &mut self.block_buffer,
+            &mut self.shards,
+            &self.opts,
+        )?;
+
+        self.cid = Some(leaf.link.clone());
+        self.total_size = leaf.total_size;
+
+        if !self.shards.is_empty() {
+            // the root shard is returned after all of the nested shards
+            self.shards.push(OwnedTreeNode {
+                path: self.full_path.clone(),
+                cid: leaf.link.clone(),
+                total_size: leaf.total_size,
+                block: self.block_buffer.as_slice().into(),
+                inner_shard: false,
+            });
+            self.shards.reverse();
         }
 
-        let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
-        node.write_message(&mut writer)
-            .map_err(TreeConstructionFailed::Protobuf)?;
+        Ok(leaf)
+    }
 
-        buffer.truncate(size);
+    /// Returns the first node of the latest rendered directory.
+    fn rendered(&mut self) -> TreeNode<'_> {
+        if let Some(shard) = self.shards.pop() {
+            return self.return_owned(shard);
+        }
 
-        let mh = multihash::Code::Sha2_256.digest(&buffer);
-        let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+        TreeNode {
+            path: self.full_path.as_str(),
+            cid: self.cid.as_ref().unwrap(),
+            total_size: self.total_size,
+            block: &self.block_buffer,
+            inner_shard: false,
+        }
+    }
 
-        let combined_from_links = links
-            .iter()
-            .map(|opt| {
-                opt.as_ref()
-                    .map(|NamedLeaf(_, _, total_size)| total_size)
-                    .unwrap()
-            })
-            .sum::<u64>();
+    fn return_owned(&mut self, node: OwnedTreeNode) -> TreeNode<'_> {
+        self.owned = Some(node);
+        let node = self.owned.as_ref().expect("just set");
 
-        Ok(Leaf {
-            link: cid,
-            total_size: buffer.len() as u64 + combined_from_links,
-        })
+        TreeNode {
+            path: node.path.as_str(),
+            cid: &node.cid,
+            total_size: node.total_size,
+            block: &node.block,
+            inner_shard: node.inner_shard,
+        }
     }
 
     /// Construct the next dag-pb node, if any.
     ///
     /// Returns a `TreeNode` of the latest constructed tree node.
     pub fn next_borrowed(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+        if let Some(shard) = self.shards.pop() {
+            return Some(Ok(self.return_owned(shard)));
+        }
+
+        if let Some(spilled) = self.spilled.as_mut() {
+            let node = match spilled.next()? {
+                Ok(node) => node,
+                Err(e) => return Some(Err(e)),
+            };
+
+            return Some(Ok(self.return_owned(node)));
+        }
+
         while let Some(visited) = self.pending.pop() {
             let (name, depth) = match &visited {
                 Visited::DescentRoot(_) => (None, 0),
@@ -187,7 +244,10 @@ impl PostOrderIterator {
                         leaves.into()
                     };
 
-                    self.pending.push(Visited::PostRoot { leaves });
+                    self.pending.push(Visited::PostRoot {
+                        leaves,
+                        metadata: node.metadata,
+                    });
                     self.pending.extend(children.drain(..));
                 }
                 Visited::Descent {
@@ -214,6 +274,7 @@ impl PostOrderIterator {
                         depth,
                         leaves,
                         index,
+                        metadata: node.metadata,
                     });
 
                     self.pending.extend(children.drain(..));
@@ -223,23 +284,16 @@ impl PostOrderIterator {
                     name,
                     leaves,
                     index,
+                    metadata,
                     ..
                 } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
-                    let buffer = &mut self.block_buffer;
 
-                    let leaf = match Self::render_directory(
-                        &leaves,
-                        buffer,
-                        &self.opts.block_size_limit,
-                    ) {
+                    let leaf = match self.render(&leaves, &metadata) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
                     };
 
-                    self.cid = Some(leaf.link.clone());
-                    self.total_size = leaf.total_size;
-
                     {
                         // name is None only for wrap_with_directory, which cannot really be
                         // propagated up but still the parent_id is allowed to be None
@@ -259,40 +313,20 @@ impl PostOrderIterator {
                         }
                     }
 
-                    return Some(Ok(TreeNode {
-                        path: self.full_path.as_str(),
-                        cid: self.cid.as_ref().unwrap(),
-                        total_size: self.total_size,
-                        block: &self.block_buffer,
-                    }));
+                    return Some(Ok(self.rendered()));
                 }
-                Visited::PostRoot { leaves } => {
+                Visited::PostRoot { leaves, metadata } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
 
                     if !self.opts.wrap_with_directory {
                         break;
                     }
 
-                    let buffer = &mut self.block_buffer;
-
-                    let leaf = match Self::render_directory(
-                        &leaves,
-                        buffer,
-                        &self.opts.block_size_limit,
-                    ) {
-                        Ok(leaf) => leaf,
-                        Err(e) => return Some(Err(e)),
-                    };
-
-                    self.cid = Some(leaf.link.clone());
-                    self.total_size = leaf.total_size;
+                    if let Err(e) = self.render(&leaves, &metadata) {
+                        return Some(Err(e));
+                    }
 
-                    return Some(Ok(TreeNode {
-                        path: self.full_path.as_str(),
-                        cid: self.cid.as_ref().unwrap(),
-                        total_size: self.total_size,
-                        block: &self.block_buffer,
-                    }));
+                    return Some(Ok(self.rendered()));
                 }
             }
         }
@@ -310,6 +344,9 @@ impl Iterator for PostOrderIterator {
 }
 
 /// Borrowed representation of a node in the tree.
+///
+/// A HAMT sharded directory is made of multiple nodes: the nested shards are returned first, with
+/// the path of the directory, followed by the root shard.
 pub struct TreeNode<'a> {
     /// Full path to the node.
     pub path: &'a str,
@@ -319,6 +356,9 @@ pub struct TreeNode<'a> {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: &'a [u8],
+    /// True for the nested shards of a HAMT sharded directory, which are not directories of
+    /// their own.
+    pub inner_shard: bool,
 }
 
 impl<'a> fmt::Debug for TreeNode<'a> {
@@ -328,6 +368,7 @@ impl<'a> fmt::Debug for TreeNode<'a> {
             .field("cid", &format_args!("{}", self.cid))
             .field("total_size", &self.total_size)
             .field("size", &self.block.len())
+            .field("inner_shard", &self.inner_shard)
             .finish()
     }
 }
@@ -340,6 +381,7 @@ impl TreeNode<'_> {
             cid: self.cid.to_owned(),
             total_size: self.total_size,
             block: self.block.into(),
+            inner_shard: self.inner_shard,
         }
     }
 }
@@ -354,6 +396,68 @@ pub struct OwnedTreeNode {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: Box<[u8]>,
+    /// True for the nested shards of a HAMT sharded directory, see [`TreeNode::inner_shard`].
+    pub inner_shard: bool,
+}
+
+/// Writes the dag-pb node of the `links` and the `data` into the `buffer`.
+pub(super) fn write_node(
+    links: &[Option<NamedLeaf>],
+    data: crate::pb::UnixFs<'_>,
+    buffer: &mut Vec<u8>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    use quick_protobuf::{BytesWriter, MessageWrite, Writer};
+
+    let node = CustomFlatUnixFs { links, data };
+
+    let size = node.get_size();
+
+    if let Some(limit) = &opts.block_size_limit {
+        let size = size as u64;
+        if *limit < size {
+            return Err(TreeConstructionFailed::TooLargeBlock(size));
+        }
+    }
+
+    let cap = buffer.capacity();
+
+    if let Some(additional) = size.checked_sub(cap) {
+        buffer.reserve(additional);
+    }
+
+    if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
+        let zeroes = [0; 8];
+
+        while needed_zeroes > 8 {
+            buffer.extend_from_slice(&zeroes[..]);
+            needed_zeroes -= zeroes.len();
+        }
+
+        buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    }
+
+    let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
+    node.write_message(&mut writer)
+        .map_err(TreeConstructionFailed::Protobuf)?;
+
+    buffer.truncate(size);
+
+    let cid = opts.cid_options.dag_pb(&buffer);
+
+    let combined_from_links = links
+        .iter()
+        .map(|opt| {
+            opt.as_ref()
+                .map(|NamedLeaf(_, _, total_size)| total_size)
+                .unwrap()
+        })
+        .sum::<u64>();
+
+    Ok(Leaf {
+        link: cid,
+        total_size: buffer.len() as u64 + combined_from_links,
+    })
 }
 
 fn update_full_path(
@@ -0,0 +1,556 @@
+use super::{
+    DirBuilder, Entry, OverwritePolicy, OwnedTreeNode, StreamingTreeBuilder, StreamingTreeFailed,
+    TreeConstructionFailed, TreeOptions,
+};
+use crate::Metadata;
+use alloc::collections::VecDeque;
+use cid::Cid;
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Used to make the names of the spill files unique within the process.
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An entry of the tree in the flattened form used in the spill files.
+#[derive(Debug, PartialEq)]
+enum Record {
+    Leaf {
+        path: String,
+        link: Cid,
+        total_size: u64,
+    },
+    Directory {
+        path: String,
+        metadata: Metadata,
+    },
+}
+
+impl Record {
+    fn path(&self) -> &str {
+        match self {
+            Record::Leaf { path, .. } | Record::Directory { path, .. } => path,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self, Record::Leaf { .. })
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Record::Leaf {
+                path,
+                link,
+                total_size,
+            } => {
+                w.write_all(&[0])?;
+                write_bytes(w, path.as_bytes())?;
+                write_bytes(w, &link.to_bytes())?;
+                w.write_all(&total_size.to_le_bytes())
+            }
+            Record::Directory { path, metadata } => {
+                w.write_all(&[1])?;
+                write_bytes(w, path.as_bytes())?;
+
+                let mode = metadata.mode();
+                w.write_all(&[mode.is_some() as u8])?;
+                w.write_all(&mode.unwrap_or(0).to_le_bytes())?;
+
+                let mtime = metadata.mtime();
+                let (seconds, nanos) = mtime.unwrap_or((0, 0));
+                w.write_all(&[mtime.is_some() as u8])?;
+                w.write_all(&seconds.to_le_bytes())?;
+                w.write_all(&nanos.to_le_bytes())
+            }
+        }
+    }
+
+    /// Reads the next record, or returns `None` at the end of the file.
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Option<Record>> {
+        let mut kind = [0u8; 1];
+        if r.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+
+        let path = String::from_utf8(read_bytes(r)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let record = match kind[0] {
+            0 => {
+                let link = Cid::try_from(read_bytes(r)?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let total_size = read_u64(r)?;
+                Record::Leaf {
+                    path,
+                    link,
+                    total_size,
+                }
+            }
+            1 => {
+                let mut metadata = Metadata::default();
+
+                let has_mode = read_u8(r)? != 0;
+                let mode = read_u32(r)?;
+                if has_mode {
+                    metadata = metadata.with_mode(mode);
+                }
+
+                let has_mtime = read_u8(r)? != 0;
+                let seconds = read_u64(r)? as i64;
+                let nanos = read_u32(r)?;
+                if has_mtime {
+                    metadata = metadata.with_mtime(seconds, nanos);
+                }
+
+                Record::Directory { path, metadata }
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown record kind: {}", other),
+                ))
+            }
+        };
+
+        Ok(Some(record))
+    }
+}
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)? as usize;
+    let mut bytes = vec![0; len];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Orders the paths so that the entries of a directory directly follow the directory itself,
+/// which is the order required by `StreamingTreeBuilder`. Plain string order would place `a-b`
+/// between `a` and `a/b`.
+fn compare_paths(a: &str, b: &str) -> Ordering {
+    a.split('/').cmp(b.split('/'))
+}
+
+/// Flattens the tree into records in the depth-first order, which matches `compare_paths`.
+fn flatten(root: DirBuilder) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut stack = vec![(String::new(), root.nodes.into_iter())];
+
+    while let Some((prefix, mut nodes)) = stack.pop() {
+        let (name, entry) = match nodes.next() {
+            Some(next) => next,
+            None => continue,
+        };
+
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        stack.push((prefix, nodes));
+
+        match entry {
+            Entry::Leaf(leaf) => records.push(Record::Leaf {
+                path,
+                link: leaf.link,
+                total_size: leaf.total_size,
+            }),
+            Entry::Directory(dir) => {
+                records.push(Record::Directory {
+                    path: path.clone(),
+                    metadata: dir.metadata,
+                });
+                stack.push((path, dir.nodes.into_iter()));
+            }
+        }
+    }
+
+    records
+}
+
+/// A sorted run of records written to a temporary file, which is removed when dropped.
+#[derive(Debug)]
+pub(super) struct SpillFile {
+    path: PathBuf,
+}
+
+impl SpillFile {
+    /// Writes the entries of the tree into a new file in the `directory`.
+    pub(super) fn write(directory: &Path, root: DirBuilder) -> io::Result<Self> {
+        let (path, file) = create_new(directory)?;
+        let spill = SpillFile { path };
+
+        let mut writer = BufWriter::new(file);
+
+        for record in flatten(root) {
+            record.write_to(&mut writer)?;
+        }
+
+        writer.flush()?;
+
+        Ok(spill)
+    }
+}
+
+/// Creates a new spill file readable only by the owner. The file must not exist already, as the
+/// default directory is shared with the other users, who could have planted a symlink under the
+/// name; the creation is retried under another name a few times.
+fn create_new(directory: &Path) -> io::Result<(PathBuf, File)> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut attempts = 0;
+
+    loop {
+        // the time makes the names harder to guess than the process id and counter alone
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+
+        let path = directory.join(format!(
+            "unixfs-tree-{}-{}-{:08x}.spill",
+            std::process::id(),
+            SPILL_COUNTER.fetch_add(1, AtomicOrdering::Relaxed),
+            nanos
+        ));
+
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 16 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        // there is nothing to do if this fails, and the file is in the temporary directory
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+enum Run {
+    Disk(BufReader<File>),
+    Memory(alloc::vec::IntoIter<Record>),
+}
+
+impl Run {
+    fn next(&mut self) -> io::Result<Option<Record>> {
+        match self {
+            Run::Disk(reader) => Record::read_from(reader),
+            Run::Memory(records) => Ok(records.next()),
+        }
+    }
+}
+
+/// An entry which was resolved according to the `OverwritePolicy`: the entries under `path` from
+/// the runs before `run` (`Replace`) or after `run` (`KeepFirst`) are ignored.
+struct Shadow {
+    path: String,
+    run: usize,
+}
+
+/// Merges the spilled runs and the remaining in-memory entries, feeding them to a
+/// `StreamingTreeBuilder` in the sorted order.
+pub(super) struct SpilledTree {
+    files: Vec<SpillFile>,
+    // the entries which were not spilled; taken as the last run when starting
+    memory: Option<Vec<Record>>,
+    runs: Vec<Run>,
+    // the next record of each run, or `None` if the run has been exhausted
+    heads: Vec<Option<Record>>,
+    builder: Option<StreamingTreeBuilder>,
+    ready: VecDeque<OwnedTreeNode>,
+    // the path of the latest directory record, used to merge the same directory from many runs
+    last_directory: Option<String>,
+    policy: OverwritePolicy,
+    // the first record of the next group, read while collecting the previous group
+    pending: Option<(usize, Record)>,
+    shadows: Vec<Shadow>,
+}
+
+impl SpilledTree {
+    pub(super) fn new(files: Vec<SpillFile>, remaining: DirBuilder, opts: TreeOptions) -> Self {
+        let policy = opts.overwrite_policy;
+
+        SpilledTree {
+            files,
+            memory: Some(flatten(remaining)),
+            runs: Vec::new(),
+            heads: Vec::new(),
+            builder: Some(StreamingTreeBuilder::new(opts)),
+            ready: VecDeque::new(),
+            last_directory: None,
+            policy,
+            pending: None,
+            shadows: Vec::new(),
+        }
+    }
+
+    /// Opens the spill files and reads the first record of each run, unless already started.
+    fn start(&mut self) -> io::Result<()> {
+        let memory = match self.memory.take() {
+            Some(memory) => memory,
+            None => return Ok(()),
+        };
+
+        for file in &self.files {
+            let reader = BufReader::new(File::open(&file.path)?);
+            self.runs.push(Run::Disk(reader));
+        }
+
+        // the in-memory entries are the latest
+        self.runs.push(Run::Memory(memory.into_iter()));
+
+        for run in &mut self.runs {
+            self.heads.push(run.next()?);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the smallest of the next records of the runs along with the index of the run. The
+    /// earlier runs are preferred for the same path, so that the later changes are applied last.
+    fn next_record(&mut self) -> io::Result<Option<(usize, Record)>> {
+        self.start()?;
+
+        let mut smallest: Option<usize> = None;
+
+        for (i, head) in self.heads.iter().enumerate() {
+            let head = match head {
+                Some(head) => head,
+                None => continue,
+            };
+
+            let smaller = match smallest.and_then(|s| self.heads[s].as_ref()) {
+                Some(current) => compare_paths(head.path(), current.path()) == Ordering::Less,
+                None => true,
+            };
+
+            if smaller {
+                smallest = Some(i);
+            }
+        }
+
+        let i = match smallest {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let next = self.runs[i].next()?;
+        Ok(core::mem::replace(&mut self.heads[i], next).map(|record| (i, record)))
+    }
+
+    /// Returns all of the records for the next path, leaving out the ones shadowed by an earlier
+    /// resolved conflict. Returns an empty group when all of the records have been read.
+    fn next_group(&mut self) -> io::Result<Vec<(usize, Record)>> {
+        let mut group: Vec<(usize, Record)> = Vec::new();
+
+        loop {
+            let next = match self.pending.take() {
+                Some(next) => next,
+                None => match self.next_record()? {
+                    Some(next) => next,
+                    None => break,
+                },
+            };
+
+            if let Some((_, first)) = group.first() {
+                if first.path() != next.1.path() {
+                    self.pending = Some(next);
+                    break;
+                }
+            }
+
+            if !self.is_shadowed(next.0, next.1.path()) {
+                group.push(next);
+            }
+        }
+
+        Ok(group)
+    }
+
+    fn is_shadowed(&mut self, run: usize, path: &str) -> bool {
+        // the records are in the depth-first order, so the subtree of a shadow is never revisited
+        self.shadows.retain(|shadow| {
+            path.len() > shadow.path.len()
+                && path.starts_with(shadow.path.as_str())
+                && path.as_bytes()[shadow.path.len()] == b'/'
+        });
+
+        let policy = self.policy;
+
+        self.shadows.iter().any(|shadow| match policy {
+            OverwritePolicy::Replace => run < shadow.run,
+            OverwritePolicy::KeepFirst => run > shadow.run,
+            OverwritePolicy::Error => false,
+        })
+    }
+
+    /// Resolves the records for the same path according to the `OverwritePolicy`. With
+    /// `OverwritePolicy::Error` all of the records are returned for the `StreamingTreeBuilder` to
+    /// find the duplicates.
+    fn resolve(&mut self, mut group: Vec<(usize, Record)>) -> Vec<Record> {
+        let winner = match self.policy {
+            OverwritePolicy::Error => return group.into_iter().map(|(_, r)| r).collect(),
+            OverwritePolicy::Replace => match group.iter().rposition(|(_, r)| r.is_leaf()) {
+                Some(last_leaf) => {
+                    self.shadows.push(Shadow {
+                        path: group[last_leaf].1.path().to_owned(),
+                        run: group[last_leaf].0,
+                    });
+
+                    if last_leaf + 1 == group.len() {
+                        group.drain(..last_leaf);
+                    } else {
+                        // the directories created after the leaf are merged
+                        group.drain(..=last_leaf);
+                    }
+                    group
+                }
+                None => group,
+            },
+            OverwritePolicy::KeepFirst => {
+                if group[0].1.is_leaf() {
+                    self.shadows.push(Shadow {
+                        path: group[0].1.path().to_owned(),
+                        run: group[0].0,
+                    });
+                    group.truncate(1);
+                } else {
+                    group.retain(|(_, r)| !r.is_leaf());
+                }
+                group
+            }
+        };
+
+        let mut records = winner.into_iter().map(|(_, r)| r);
+        let first = records.next().expect("groups are never empty");
+
+        match first {
+            Record::Directory { path, metadata } => {
+                // the latest metadata which was set, if any
+                let metadata = records
+                    .filter_map(|r| match r {
+                        Record::Directory { metadata, .. } if !metadata.is_empty() => {
+                            Some(metadata)
+                        }
+                        _ => None,
+                    })
+                    .last()
+                    .unwrap_or(metadata);
+
+                vec![Record::Directory { path, metadata }]
+            }
+            leaf => vec![leaf],
+        }
+    }
+
+    fn feed(
+        builder: &mut StreamingTreeBuilder,
+        last_directory: &mut Option<String>,
+        record: Record,
+    ) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        match record {
+            Record::Leaf {
+                path,
+                link,
+                total_size,
+            } => builder.put_link(&path, link, total_size),
+            Record::Directory { path, metadata } => {
+                let seen = last_directory.as_deref() == Some(path.as_str());
+
+                if seen && metadata.is_empty() {
+                    // the directory was only implied by its entries in the later run
+                    return Ok(Vec::new());
+                }
+
+                let completed = builder.set_metadata(&path, metadata)?;
+                *last_directory = Some(path);
+                Ok(completed)
+            }
+        }
+    }
+}
+
+impl Iterator for SpilledTree {
+    type Item = Result<OwnedTreeNode, TreeConstructionFailed>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(node) = self.ready.pop_front() {
+                return Some(Ok(node));
+            }
+
+            self.builder.as_ref()?;
+
+            let completed = match self.next_group() {
+                Ok(group) if group.is_empty() => {
+                    self.builder.take().expect("checked above").finish()
+                }
+                Ok(group) => {
+                    let records = self.resolve(group);
+                    let builder = self.builder.as_mut().expect("checked above");
+                    let last_directory = &mut self.last_directory;
+
+                    records
+                        .into_iter()
+                        .try_fold(Vec::new(), |mut completed, record| {
+                            completed.extend(Self::feed(builder, last_directory, record)?);
+                            Ok(completed)
+                        })
+                }
+                Err(e) => Err(StreamingTreeFailed::Construction(
+                    TreeConstructionFailed::Spill(e),
+                )),
+            };
+
+            match completed {
+                Ok(completed) => self.ready.extend(completed),
+                Err(StreamingTreeFailed::Building(e)) => {
+                    self.builder = None;
+                    return Some(Err(TreeConstructionFailed::SpilledEntry(e)));
+                }
+                Err(StreamingTreeFailed::Construction(e)) => {
+                    // nothing sensible can be returned after an error
+                    self.builder = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
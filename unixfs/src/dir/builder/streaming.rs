@@ -0,0 +1,495 @@
+use super::{
+    Leaf, NamedLeaf, OwnedTreeNode, PostOrderIterator, TreeBuildingFailed, TreeConstructionFailed,
+    TreeOptions,
+};
+use crate::Metadata;
+use alloc::collections::BTreeMap;
+use cid::Cid;
+use core::fmt;
+
+/// UnixFs directory tree builder which creates the directory blocks as soon as all of their
+/// entries have been added, unlike [`super::BufferingTreeBuilder`] which holds the whole tree
+/// until `build()`. Only the directories on the path to the latest added entry are held in memory.
+///
+/// The entries must be added in an order where all of the entries of a directory, including the
+/// entries of its subdirectories, are added before any entry outside of that directory. Such an
+/// order is produced by sorting the paths or by a depth-first walk of a filesystem. Returning to
+/// an already completed directory is an error.
+#[derive(Debug)]
+pub struct StreamingTreeBuilder {
+    /// The open directories; the first is the root, followed by the directories on the path of the
+    /// latest added entry.
+    stack: Vec<OpenDirectory>,
+    block_buffer: Vec<u8>,
+    opts: TreeOptions,
+}
+
+#[derive(Debug)]
+struct OpenDirectory {
+    name: String,
+    entries: BTreeMap<String, Completed>,
+    metadata: Metadata,
+}
+
+impl OpenDirectory {
+    fn new(name: String) -> Self {
+        OpenDirectory {
+            name,
+            entries: Default::default(),
+            metadata: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Completed {
+    leaf: Leaf,
+    directory: bool,
+}
+
+impl Default for StreamingTreeBuilder {
+    fn default() -> Self {
+        Self::new(TreeOptions::default())
+    }
+}
+
+impl StreamingTreeBuilder {
+    /// Construct a new tree builder with the given configuration.
+    pub fn new(opts: TreeOptions) -> Self {
+        StreamingTreeBuilder {
+            stack: vec![OpenDirectory::new(String::new())],
+            block_buffer: Vec::new(),
+            opts,
+        }
+    }
+
+    /// Registers the given path to be a link to the cid that follows, similar to
+    /// [`super::BufferingTreeBuilder::put_link`].
+    ///
+    /// Returns the directories which were completed because the path is outside of them, in
+    /// post order. The blocks of the returned directories need to be stored by the caller.
+    pub fn put_link(
+        &mut self,
+        full_path: &str,
+        target: Cid,
+        total_size: u64,
+    ) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        let segments = validate(full_path)?;
+        let (basename, directories) = segments
+            .split_last()
+            .expect("str::split always returns at least one element");
+
+        let common = self.common_depth(directories);
+
+        let duplicate = if common == directories.len() {
+            // the leaf goes into an already open directory, where it cannot exist yet
+            self.stack[common].entries.contains_key(*basename)
+                || self.stack.get(common + 1).map(|open| open.name.as_str()) == Some(*basename)
+        } else {
+            false
+        };
+
+        if duplicate {
+            return Err(TreeBuildingFailed::DuplicatePath(full_path.to_string()).into());
+        }
+
+        let completed = self.descend(full_path, directories, common)?;
+
+        let leaf = Completed {
+            leaf: Leaf {
+                link: target,
+                total_size,
+            },
+            directory: false,
+        };
+
+        self.stack
+            .last_mut()
+            .expect("root is never popped")
+            .entries
+            .insert(basename.to_string(), leaf);
+
+        Ok(completed)
+    }
+
+    /// Sets the metadata of the directory at the given path, creating it if it does not exist yet.
+    /// Like [`StreamingTreeBuilder::put_link`], returns the directories completed by moving to
+    /// the given path.
+    pub fn set_metadata(
+        &mut self,
+        full_path: &str,
+        metadata: Metadata,
+    ) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        let directories = validate(full_path)?;
+        let common = self.common_depth(&directories);
+        let completed = self.descend(full_path, &directories, common)?;
+
+        self.stack
+            .last_mut()
+            .expect("root is never popped")
+            .metadata = metadata;
+
+        Ok(completed)
+    }
+
+    /// Completes all of the remaining directories, returning them in post order. The root
+    /// directory is created only when the `wrap_with_directory` option has been given, in which
+    /// case it is the last of the returned nodes.
+    pub fn finish(mut self) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        let mut completed = Vec::new();
+        self.complete_until(1, &mut completed)?;
+
+        if self.opts.wrap_with_directory {
+            let root = self.stack.pop().expect("root is never popped");
+            let leaf = self.render("", root.entries, &root.metadata, &mut completed)?;
+            completed.push(OwnedTreeNode {
+                path: String::new(),
+                cid: leaf.link,
+                total_size: leaf.total_size,
+                block: self.block_buffer.as_slice().into(),
+                inner_shard: false,
+            });
+        }
+
+        Ok(completed)
+    }
+
+    /// Returns the amount of directories in `directories` which are already open.
+    fn common_depth(&self, directories: &[&str]) -> usize {
+        self.stack[1..]
+            .iter()
+            .zip(directories.iter())
+            .take_while(|(open, name)| open.name == **name)
+            .count()
+    }
+
+    /// Completes the open directories which are not part of `directories` and opens the ones
+    /// which are not open yet. Checks for the possible errors before completing any directories,
+    /// so that no completed directories are lost on error.
+    fn descend(
+        &mut self,
+        full_path: &str,
+        directories: &[&str],
+        common: usize,
+    ) -> Result<Vec<OwnedTreeNode>, StreamingTreeFailed> {
+        let first = directories.first().copied();
+        let root_level = self
+            .stack
+            .get(1)
+            .map(|open| open.name.as_str())
+            .or_else(|| {
+                self.stack[0]
+                    .entries
+                    .keys()
+                    .next()
+                    .map(|name| name.as_str())
+            });
+
+        if !self.opts.wrap_with_directory {
+            let new_root_level = match first {
+                Some(first) => Some(first),
+                // a leaf at the root level, which should be the only entry
+                None => full_path.split('/').next(),
+            };
+
+            if root_level.is_some() && root_level != new_root_level {
+                return Err(TreeBuildingFailed::TooManyRootLevelEntries.into());
+            }
+        }
+
+        if let Some(next) = directories.get(common) {
+            // the first directory to be opened must not have been completed yet
+            match self.stack[common].entries.get(*next) {
+                Some(Completed {
+                    directory: true, ..
+                }) => return Err(TreeBuildingFailed::UnsortedPath(full_path.to_string()).into()),
+                Some(_) => {
+                    return Err(TreeBuildingFailed::LeafAsDirectory(full_path.to_string()).into())
+                }
+                None => {}
+            }
+        }
+
+        let mut completed = Vec::new();
+        self.complete_until(common + 1, &mut completed)?;
+
+        self.stack.extend(
+            directories[common..]
+                .iter()
+                .map(|name| OpenDirectory::new(name.to_string())),
+        );
+
+        Ok(completed)
+    }
+
+    /// Completes the open directories until there are `depth` open directories left.
+    fn complete_until(
+        &mut self,
+        depth: usize,
+        completed: &mut Vec<OwnedTreeNode>,
+    ) -> Result<(), StreamingTreeFailed> {
+        while self.stack.len() > depth {
+            let path = self.stack[1..]
+                .iter()
+                .map(|open| open.name.as_str())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            let dir = self.stack.pop().expect("checked length");
+            let leaf = self.render(&path, dir.entries, &dir.metadata, completed)?;
+
+            completed.push(OwnedTreeNode {
+                path,
+                cid: leaf.link.clone(),
+                total_size: leaf.total_size,
+                block: self.block_buffer.as_slice().into(),
+                inner_shard: false,
+            });
+
+            self.stack
+                .last_mut()
+                .expect("root is never popped")
+                .entries
+                .insert(
+                    dir.name,
+                    Completed {
+                        leaf,
+                        directory: true,
+                    },
+                );
+        }
+
+        Ok(())
+    }
+
+    /// Renders the directory into the block buffer, pushing the nested shards to `completed` when
+    /// it is HAMT sharded.
+    fn render(
+        &mut self,
+        path: &str,
+        entries: BTreeMap<String, Completed>,
+        metadata: &Metadata,
+        completed: &mut Vec<OwnedTreeNode>,
+    ) -> Result<Leaf, TreeConstructionFailed> {
+        let links = entries
+            .into_iter()
+            .map(|(name, completed)| {
+                Some(NamedLeaf(
+                    name,
+                    completed.leaf.link,
+                    completed.leaf.total_size,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        self.block_buffer.clear();
+        PostOrderIterator::render_directory(
+            path,
+            &links,
+            metadata,
+            &mut self.block_buffer,
+            completed,
+            &self.opts,
+        )
+    }
+}
+
+/// Applies the same validation as `BufferingTreeBuilder`, returning the path segments.
+fn validate(full_path: &str) -> Result<Vec<&str>, TreeBuildingFailed> {
+    if full_path.ends_with('/') {
+        return Err(TreeBuildingFailed::PathEndsInSlash(full_path.to_string()));
+    }
+
+    if full_path.contains("//") {
+        return Err(TreeBuildingFailed::RepeatSlashesInPath(
+            full_path.to_string(),
+        ));
+    }
+
+    if full_path.starts_with('/') {
+        return Err(TreeBuildingFailed::RootedPath(full_path.to_string()));
+    }
+
+    Ok(full_path.split('/').collect())
+}
+
+/// Failure cases for [`StreamingTreeBuilder`], which both validates the paths and creates the
+/// directory blocks.
+#[derive(Debug)]
+pub enum StreamingTreeFailed {
+    /// The given path could not be added.
+    Building(TreeBuildingFailed),
+    /// A completed directory could not be created.
+    Construction(TreeConstructionFailed),
+}
+
+impl From<TreeBuildingFailed> for StreamingTreeFailed {
+    fn from(e: TreeBuildingFailed) -> Self {
+        StreamingTreeFailed::Building(e)
+    }
+}
+
+impl From<TreeConstructionFailed> for StreamingTreeFailed {
+    fn from(e: TreeConstructionFailed) -> Self {
+        StreamingTreeFailed::Construction(e)
+    }
+}
+
+impl fmt::Display for StreamingTreeFailed {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use StreamingTreeFailed::*;
+
+        match self {
+            Building(e) => write!(fmt, "{}", e),
+            Construction(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreamingTreeFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use StreamingTreeFailed::*;
+
+        match self {
+            Building(e) => Some(e),
+            Construction(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamingTreeBuilder, StreamingTreeFailed};
+    use crate::dir::builder::{BufferingTreeBuilder, TreeBuildingFailed, TreeOptions};
+    use crate::Metadata;
+    use cid::Cid;
+
+    #[test]
+    fn same_as_buffering() {
+        let paths = [
+            "a/b/c/d.txt",
+            "a/b/c/e.txt",
+            "a/b/f.txt",
+            "a/g/h.txt",
+            "a/i.txt",
+            "j/k.txt",
+            "l.txt",
+        ];
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+
+        let mut buffering = BufferingTreeBuilder::new(opts.clone());
+        let mut streaming = StreamingTreeBuilder::new(opts);
+        let mut actual = Vec::new();
+
+        let metadata = Metadata::default().with_mode(0o755);
+        buffering.set_metadata("a/g", metadata.clone()).unwrap();
+
+        for (i, path) in paths.iter().enumerate() {
+            buffering.put_link(path, some_cid(i), 1).unwrap();
+
+            if *path == "a/g/h.txt" {
+                actual.extend(streaming.set_metadata("a/g", metadata.clone()).unwrap());
+            }
+
+            actual.extend(streaming.put_link(path, some_cid(i), 1).unwrap());
+        }
+
+        actual.extend(streaming.finish().unwrap());
+
+        let mut expected = buffering
+            .build()
+            .map(|res| res.map(|n| (n.path, n.cid, n.total_size)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut actual = actual
+            .into_iter()
+            .map(|n| (n.path, n.cid, n.total_size))
+            .collect::<Vec<_>>();
+
+        // the visiting order between siblings differs
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        actual.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn completes_directories_early() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut builder = StreamingTreeBuilder::new(opts);
+
+        assert!(builder
+            .put_link("a/b/c.txt", some_cid(0), 1)
+            .unwrap()
+            .is_empty());
+        assert!(builder
+            .put_link("a/d.txt", some_cid(1), 1)
+            .unwrap()
+            .iter()
+            .map(|n| n.path.as_str())
+            .eq(vec!["a/b"]));
+        assert!(builder
+            .put_link("e.txt", some_cid(2), 1)
+            .unwrap()
+            .iter()
+            .map(|n| n.path.as_str())
+            .eq(vec!["a"]));
+
+        let root = builder.finish().unwrap();
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].path, "");
+    }
+
+    #[test]
+    fn unsorted_input() {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut builder = StreamingTreeBuilder::new(opts);
+
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+        builder.put_link("c/d.txt", some_cid(1), 1).unwrap();
+
+        match builder.put_link("a/e.txt", some_cid(2), 1) {
+            Err(StreamingTreeFailed::Building(TreeBuildingFailed::UnsortedPath(_))) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match builder.put_link("c/d.txt/f.txt", some_cid(2), 1) {
+            Err(StreamingTreeFailed::Building(TreeBuildingFailed::LeafAsDirectory(_))) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match builder.put_link("c/d.txt", some_cid(2), 1) {
+            Err(StreamingTreeFailed::Building(TreeBuildingFailed::DuplicatePath(_))) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn denied_multiple_root_entries() {
+        let mut builder = StreamingTreeBuilder::default();
+
+        builder.put_link("a/b.txt", some_cid(0), 1).unwrap();
+
+        match builder.put_link("c.txt", some_cid(1), 1) {
+            Err(StreamingTreeFailed::Building(TreeBuildingFailed::TooManyRootLevelEntries)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // without wrap_with_directory, the root is not created
+        let completed = builder.finish().unwrap();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].path, "a");
+    }
+
+    /// Returns a quick and dirty sha2-256 of the given number as a Cidv0
+    fn some_cid(number: usize) -> Cid {
+        use multihash::{Code, MultihashDigest};
+        let mh = Code::Sha2_256.digest(&number.to_le_bytes());
+        Cid::new_v0(mh).unwrap()
+    }
+}
@@ -0,0 +1,421 @@
+use super::try_convert_cid;
+use crate::pb::{FlatUnixFs, UnixFsType};
+use crate::walk::Error;
+use alloc::collections::{BTreeMap, VecDeque};
+use cid::Cid;
+use core::convert::TryFrom;
+use core::fmt;
+
+/// A difference between two UnixFS trees, found by [`diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The entry exists only in the new tree.
+    Added {
+        /// The path of the entry, relative to the roots.
+        path: String,
+        /// The Cid of the entry in the new tree.
+        cid: Cid,
+    },
+    /// The entry exists only in the old tree.
+    Removed {
+        /// The path of the entry, relative to the roots.
+        path: String,
+        /// The Cid of the entry in the old tree.
+        cid: Cid,
+    },
+    /// The entry exists in both trees but has changed, and is not a directory in both of the
+    /// trees. Changed directories are not reported, only the changed entries within them.
+    Modified {
+        /// The path of the entry, relative to the roots. Empty when the roots themselves are not
+        /// directories.
+        path: String,
+        /// The Cid of the entry in the old tree.
+        old: Cid,
+        /// The Cid of the entry in the new tree.
+        new: Cid,
+    },
+}
+
+/// Compares the trees of `old` and `new` roots, returning an iterator of the [`Change`]s between
+/// them. Blocks are loaded through the `fetch` callback as they are needed.
+///
+/// Subtrees with the same Cid are skipped without loading them, so the amount of blocks loaded
+/// depends on the size of the changes, not the size of the trees. Only the root blocks of the
+/// changed entries are loaded to find out if they are directories. The directories are visited
+/// depth first, and the changes within a single directory are in the order of the names.
+pub fn diff<F, B, E>(old: Cid, new: Cid, fetch: F) -> Diff<F>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    let candidates = if old == new {
+        Vec::new()
+    } else {
+        vec![(String::new(), old, new)]
+    };
+
+    Diff {
+        fetch,
+        ready: VecDeque::new(),
+        candidates,
+    }
+}
+
+/// Iterator over the [`Change`]s between two trees, created with [`diff`].
+pub struct Diff<F> {
+    fetch: F,
+    ready: VecDeque<Change>,
+    // pairs of differing entries which need to be loaded to find out if they are directories
+    candidates: Vec<(String, Cid, Cid)>,
+}
+
+impl<F> fmt::Debug for Diff<F> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Diff")
+            .field("ready", &self.ready.len())
+            .field("candidates", &self.candidates.len())
+            .finish()
+    }
+}
+
+impl<F, B, E> Diff<F>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    fn compare(&mut self, path: String, old: Cid, new: Cid) -> Result<(), DiffError<E>> {
+        let old_entries = entries(&mut self.fetch, &old)?;
+        let new_entries = entries(&mut self.fetch, &new)?;
+
+        let (old_entries, new_entries) = match (old_entries, new_entries) {
+            (Some(old_entries), Some(new_entries)) => (old_entries, new_entries),
+            _ => {
+                self.ready.push_back(Change::Modified { path, old, new });
+                return Ok(());
+            }
+        };
+
+        let mut old_entries = old_entries.into_iter().peekable();
+        let mut new_entries = new_entries.into_iter().peekable();
+        let mut nested = Vec::new();
+
+        let join = |name: &str| {
+            if path.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}/{}", path, name)
+            }
+        };
+
+        loop {
+            let ordering = match (old_entries.peek(), new_entries.peek()) {
+                (Some((o, _)), Some((n, _))) => o.cmp(n),
+                (Some(_), None) => core::cmp::Ordering::Less,
+                (None, Some(_)) => core::cmp::Ordering::Greater,
+                (None, None) => break,
+            };
+
+            match ordering {
+                core::cmp::Ordering::Less => {
+                    let (name, cid) = old_entries.next().unwrap();
+                    self.ready.push_back(Change::Removed {
+                        path: join(&name),
+                        cid,
+                    });
+                }
+                core::cmp::Ordering::Greater => {
+                    let (name, cid) = new_entries.next().unwrap();
+                    self.ready.push_back(Change::Added {
+                        path: join(&name),
+                        cid,
+                    });
+                }
+                core::cmp::Ordering::Equal => {
+                    let (name, old) = old_entries.next().unwrap();
+                    let (_, new) = new_entries.next().unwrap();
+
+                    if old != new {
+                        nested.push((join(&name), old, new));
+                    }
+                }
+            }
+        }
+
+        // reversed to pop them in the order of names
+        self.candidates.extend(nested.into_iter().rev());
+
+        Ok(())
+    }
+}
+
+impl<F, B, E> Iterator for Diff<F>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    type Item = Result<Change, DiffError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.ready.pop_front() {
+                return Some(Ok(change));
+            }
+
+            let (path, old, new) = self.candidates.pop()?;
+
+            if let Err(e) = self.compare(path, old, new) {
+                // nothing sensible can be returned after an error
+                self.candidates.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Returns the entries of the directory, or `None` if the block is not a directory. Loads the
+/// buckets of HAMT sharded directories.
+fn entries<F, B, E>(fetch: &mut F, cid: &Cid) -> Result<Option<BTreeMap<String, Cid>>, DiffError<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    if u64::from(cid.codec()) == crate::RAW_CODEC {
+        return Ok(None);
+    }
+
+    let mut entries = BTreeMap::new();
+    let mut buckets = vec![cid.to_owned()];
+    let mut root = true;
+
+    while let Some(cid) = buckets.pop() {
+        let block = fetch(&cid).map_err(DiffError::Fetch)?;
+        let flat = FlatUnixFs::try_from(block.as_ref()).map_err(Error::from)?;
+
+        let sharded = match flat.data.Type {
+            UnixFsType::Directory if root => false,
+            UnixFsType::HAMTShard => true,
+            _ if root => return Ok(None),
+            other => return Err(Error::UnexpectedType(other.into()).into()),
+        };
+
+        root = false;
+
+        for (nth, link) in flat.links.into_iter().enumerate() {
+            let name = link.Name.as_deref().unwrap_or_default().to_owned();
+            let target = try_convert_cid(nth, link).map_err(Error::from)?;
+
+            if !sharded {
+                entries.insert(name, target);
+                continue;
+            }
+
+            match name.get(2..) {
+                // link to a bucket, which only has the two character prefix as the name
+                Some("") => buckets.push(target),
+                Some(rest) => {
+                    entries.insert(rest.to_owned(), target);
+                }
+                None => return Err(DiffError::InvalidShardLinkName(name)),
+            }
+        }
+    }
+
+    Ok(Some(entries))
+}
+
+/// Errors which can happen while comparing trees with [`diff`].
+#[derive(Debug)]
+pub enum DiffError<E> {
+    /// The `fetch` callback failed.
+    Fetch(E),
+    /// A block could not be read.
+    Read(Error),
+    /// A link of a HAMT sharded directory had a name which does not start with the two character
+    /// prefix of the bucket.
+    InvalidShardLinkName(String),
+}
+
+impl<E> From<Error> for DiffError<E> {
+    fn from(e: Error) -> Self {
+        DiffError::Read(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for DiffError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DiffError::*;
+        match self {
+            Fetch(e) => write!(fmt, "failed to fetch a block: {}", e),
+            Read(e) => write!(fmt, "failed to read a block: {}", e),
+            InvalidShardLinkName(name) => write!(fmt, "invalid sharded link name: {:?}", name),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for DiffError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use DiffError::*;
+        match self {
+            Fetch(e) => Some(e),
+            Read(e) => Some(e),
+            InvalidShardLinkName(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, Change, DiffError};
+    use crate::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use crate::CidOptions;
+    use cid::Cid;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+
+    #[test]
+    fn changed_entries() {
+        let mut blocks = HashMap::new();
+
+        let (old, old_dirs) = build(
+            &mut blocks,
+            &[
+                ("a/b/c.txt", "c"),
+                ("a/b/d.txt", "d"),
+                ("a/unchanged/e.txt", "e"),
+                ("f.txt", "f"),
+                ("g/h.txt", "h"),
+            ],
+        );
+
+        let (new, _) = build(
+            &mut blocks,
+            &[
+                ("a/b/c.txt", "c2"),
+                ("a/b/i.txt", "i"),
+                ("a/unchanged/e.txt", "e"),
+                ("f.txt", "f"),
+                ("g", "now a file"),
+            ],
+        );
+
+        let mut fetched = Vec::new();
+
+        let changes = diff(old, new, |cid: &Cid| {
+            fetched.push(cid.clone());
+            Ok::<_, Infallible>(blocks[cid].clone())
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+        let raw = |content: &str| CidOptions::default().raw(content.as_bytes());
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::Modified {
+                    path: "a/b/c.txt".into(),
+                    old: raw("c"),
+                    new: raw("c2"),
+                },
+                Change::Removed {
+                    path: "a/b/d.txt".into(),
+                    cid: raw("d"),
+                },
+                Change::Added {
+                    path: "a/b/i.txt".into(),
+                    cid: raw("i"),
+                },
+                Change::Modified {
+                    path: "g".into(),
+                    old: old_dirs["g"].clone(),
+                    new: raw("now a file"),
+                },
+            ]
+        );
+
+        assert!(!fetched.contains(&old_dirs["a/unchanged"]));
+    }
+
+    #[test]
+    fn same_roots() {
+        let mut blocks = HashMap::new();
+        let (root, _) = build(&mut blocks, &[("a.txt", "a")]);
+
+        let changes = diff(
+            root.clone(),
+            root,
+            |_: &Cid| -> Result<Vec<u8>, Infallible> { unreachable!("nothing should be fetched") },
+        );
+
+        assert_eq!(changes.count(), 0);
+    }
+
+    #[test]
+    fn invalid_sharded_link_names() {
+        use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+        use alloc::borrow::Cow;
+        use quick_protobuf::{MessageWrite, Writer};
+
+        let mut blocks = HashMap::new();
+        let (old, _) = build(&mut blocks, &[("a.txt", "a")]);
+        let target = CidOptions::default().raw(b"a");
+
+        // too short, and the prefix ending inside of a multibyte character
+        for &name in &["a", "0\u{e9}.txt"] {
+            let shard = FlatUnixFs {
+                links: vec![PBLink {
+                    Hash: Some(Cow::Owned(target.to_bytes())),
+                    Name: Some(Cow::Borrowed(name)),
+                    Tsize: Some(1),
+                }],
+                data: UnixFs {
+                    Type: UnixFsType::HAMTShard,
+                    ..Default::default()
+                },
+            };
+
+            let mut block = Vec::with_capacity(shard.get_size());
+            shard.write_message(&mut Writer::new(&mut block)).unwrap();
+            let new = CidOptions::default().dag_pb(&block);
+            blocks.insert(new.clone(), block);
+
+            let e = diff(old.clone(), new, |cid: &Cid| {
+                Ok::<_, Infallible>(blocks[cid].clone())
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+
+            assert!(
+                matches!(&e, DiffError::InvalidShardLinkName(n) if n == name),
+                "{:?}",
+                e
+            );
+        }
+    }
+
+    /// Returns the root and the Cids of the directories by their paths.
+    fn build(
+        blocks: &mut HashMap<Cid, Vec<u8>>,
+        files: &[(&str, &str)],
+    ) -> (Cid, HashMap<String, Cid>) {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        for (path, content) in files {
+            let cid = CidOptions::default().raw(content.as_bytes());
+            blocks.insert(cid.clone(), content.as_bytes().to_vec());
+            builder.put_link(path, cid, content.len() as u64).unwrap();
+        }
+
+        let mut dirs = HashMap::new();
+
+        for node in builder.build() {
+            let node = node.unwrap();
+            blocks.insert(node.cid.clone(), node.block.into_vec());
+            dirs.insert(node.path, node.cid);
+        }
+
+        (dirs.remove("").unwrap(), dirs)
+    }
+}
@@ -0,0 +1,128 @@
+//! Minimal murmur3 x64 128-bit implementation used to place names into HAMT buckets.
+//!
+//! go-ipfs (through go-unixfs) uses the first 64 bits of the 128-bit hash in big-endian order,
+//! consuming the bits from the most significant end. With the only supported fanout of 256 this
+//! means that the bucket index at depth `n` is the `n`th byte of that output.
+
+/// Multicodec code for murmur3-x64-64, the only supported `hashType` in HAMTShard nodes.
+pub(crate) const HASH_TYPE: u64 = 0x22;
+
+/// Only supported `fanout` in HAMTShard nodes, which gives us 8 bits per level.
+pub(crate) const FANOUT: u64 = 256;
+
+const C1: u64 = 0x87c3_7b91_1142_53d5;
+const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+/// Returns the first 64 bits of the murmur3 x64 128-bit hash of `data` with seed zero, as
+/// big-endian bytes.
+pub(crate) fn hash64(data: &[u8]) -> [u8; 8] {
+    let (h1, _) = murmur3_x64_128(data, 0);
+    h1.to_be_bytes()
+}
+
+/// Returns the uppercase hex formatted bucket prefix for the `depth` level of a HAMT, or `None`
+/// if the hash has been exhausted.
+pub(crate) fn bucket_prefix(hash: &[u8; 8], depth: usize) -> Option<[u8; 2]> {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let byte = *hash.get(depth)?;
+    Some([HEX[(byte >> 4) as usize], HEX[(byte & 0x0f) as usize]])
+}
+
+fn murmur3_x64_128(data: &[u8], seed: u64) -> (u64, u64) {
+    let mut h1 = seed;
+    let mut h2 = seed;
+
+    let mut chunks = data.chunks_exact(16);
+
+    for chunk in &mut chunks {
+        let k1 = read_u64_le(&chunk[..8]);
+        let k2 = read_u64_le(&chunk[8..]);
+
+        h1 ^= mix_k1(k1);
+        h1 = h1.rotate_left(27).wrapping_add(h2);
+        h1 = h1.wrapping_mul(5).wrapping_add(0x52dc_e729);
+
+        h2 ^= mix_k2(k2);
+        h2 = h2.rotate_left(31).wrapping_add(h1);
+        h2 = h2.wrapping_mul(5).wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = chunks.remainder();
+
+    if tail.len() > 8 {
+        h2 ^= mix_k2(read_u64_le(&tail[8..]));
+    }
+
+    if !tail.is_empty() {
+        h1 ^= mix_k1(read_u64_le(&tail[..tail.len().min(8)]));
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    (h1, h2)
+}
+
+/// Reads up to 8 bytes as little-endian u64, zero-padding the missing high bytes.
+fn read_u64_le(bytes: &[u8]) -> u64 {
+    debug_assert!(bytes.len() <= 8);
+    bytes
+        .iter()
+        .rev()
+        .fold(0u64, |acc, &b| (acc << 8) | u64::from(b))
+}
+
+fn mix_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2)
+}
+
+fn mix_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1)
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_prefix, hash64};
+    use hex_literal::hex;
+
+    #[test]
+    fn short_names() {
+        // these are the bucket prefixes found in the sharded directory fixture in
+        // `sharded_lookup::tests`
+        assert_eq!(hash64(b"bin"), hex!("F5E71BF01EC40F2C"));
+        assert_eq!(hash64(b"doc"), hex!("6A7E3CC033DBEF8A"));
+        assert_eq!(hash64(b"Makefile"), hex!("CDBBE77DD5F43765"));
+    }
+
+    #[test]
+    fn names_longer_than_a_block() {
+        // 19 bytes: one full 16 byte block and a tail of three bytes
+        assert_eq!(hash64(b"long-named-file-038"), hex!("13693B5064A5452D"));
+    }
+
+    #[test]
+    fn prefixes() {
+        let hash = hash64(b"long-named-file-016");
+        assert_eq!(bucket_prefix(&hash, 0), Some(*b"07"));
+        assert_eq!(bucket_prefix(&hash, 1), Some(*b"48"));
+        assert_eq!(bucket_prefix(&hash, 8), None);
+    }
+}
@@ -1,3 +1,4 @@
+use super::murmur3;
 use super::{try_convert_cid, MaybeResolved, MultipleMatchingLinks, ResolveError};
 use crate::pb::{FlatUnixFs, PBLink, ParsingFailed, UnixFsType};
 use crate::{InvalidCidInLink, UnexpectedNodeType};
@@ -27,20 +28,27 @@ impl fmt::Debug for Cache {
 
 /// `ShardedLookup` can walk over multiple HAMT sharded directory nodes which allows multiple block
 /// spanning directories.
+///
+/// The needle is hashed the same way go-ipfs does when placing the entries, so only the single
+/// bucket path which could contain the needle is walked.
 pub struct ShardedLookup<'needle> {
     links: VecDeque<Cid>,
     // this will be tricky if we ever need to have case-insensitive resolving *but* we can then
     // make a custom Cow type; important not to expose Cow in any API.
     needle: Cow<'needle, str>,
+    hash: [u8; 8],
+    // depth of the bucket in `links`
+    depth: usize,
 }
 
 impl fmt::Debug for ShardedLookup<'_> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "ShardedLookup {{ links: {}, needle: {:?} }}",
+            "ShardedLookup {{ links: {}, needle: {:?}, depth: {} }}",
             self.links.len(),
             self.needle.as_ref(),
+            self.depth,
         )
     }
 }
@@ -84,9 +92,13 @@ impl<'needle> ShardedLookup<'needle> {
         let found = Self::partition(
             hamt.links.into_iter(),
             self.needle.as_ref(),
+            &self.hash,
+            self.depth,
             &mut self.links,
         )?;
 
+        self.depth += 1;
+
         if let Some(cid) = found {
             *cache = Some(self.links.into());
             Ok(MaybeResolved::Found(cid))
@@ -101,9 +113,19 @@ impl<'needle> ShardedLookup<'needle> {
     /// Transforms this `ShardedLookup` into a `ShardedLookup<'static>` by taking ownership of the
     /// needle we are trying to find.
     pub fn with_owned_needle(self) -> ShardedLookup<'static> {
-        let ShardedLookup { links, needle } = self;
+        let ShardedLookup {
+            links,
+            needle,
+            hash,
+            depth,
+        } = self;
         let needle = Cow::Owned(needle.into_owned());
-        ShardedLookup { links, needle }
+        ShardedLookup {
+            links,
+            needle,
+            hash,
+            depth,
+        }
     }
 
     /// Finds or starts a lookup of multiple buckets.
@@ -118,7 +140,9 @@ impl<'needle> ShardedLookup<'needle> {
 
         let mut links = cache.take().map(|c| c.buffer).unwrap_or_default();
 
-        let found = Self::partition(hamt.links.into_iter(), needle, &mut links)?;
+        let hash = murmur3::hash64(needle.as_bytes());
+
+        let found = Self::partition(hamt.links.into_iter(), needle, &hash, 0, &mut links)?;
 
         if let Some(cid) = found {
             *cache = Some(links.into());
@@ -130,6 +154,8 @@ impl<'needle> ShardedLookup<'needle> {
             Ok(MaybeResolved::NeedToLoadMore(ShardedLookup {
                 links,
                 needle: Cow::Borrowed(needle),
+                hash,
+                depth: 1,
             }))
         }
     }
@@ -140,7 +166,9 @@ impl<'needle> ShardedLookup<'needle> {
     pub(crate) fn check_supported(hamt: &mut FlatUnixFs<'_>) -> Result<(), ShardError> {
         assert_eq!(hamt.data.Type, UnixFsType::HAMTShard);
 
-        if hamt.data.fanout != Some(256) || hamt.data.hashType != Some(34) {
+        if hamt.data.fanout != Some(murmur3::FANOUT)
+            || hamt.data.hashType != Some(murmur3::HASH_TYPE)
+        {
             Err(ShardError::UnsupportedProperties {
                 hash_type: hamt.data.hashType,
                 fanout: hamt.data.fanout,
@@ -155,20 +183,38 @@ impl<'needle> ShardedLookup<'needle> {
         }
     }
 
-    /// Partition the original links based on their kind; if the link:
+    /// Partition the original links of a bucket at `depth` based on their kind and the hashed
+    /// needle; if the link:
     ///
-    ///  - matches the needle uniquely, it will be returned as `Some(cid)`
-    ///  - is a bucket, it is pushed back to the work
+    ///  - has the bucket prefix and matches the needle uniquely, it will be returned as
+    ///    `Some(cid)`
+    ///  - is the bucket for the prefix, it is pushed back to the work
+    ///
+    /// Links with any other prefix cannot contain the needle and are skipped.
     fn partition<'a>(
         iter: impl Iterator<Item = PBLink<'a>>,
         needle: &str,
+        hash: &[u8; 8],
+        depth: usize,
         work: &mut VecDeque<Cid>,
     ) -> Result<Option<Cid>, PartitioningError> {
+        let prefix = match murmur3::bucket_prefix(hash, depth) {
+            Some(prefix) => prefix,
+            // go-ipfs cannot create deeper trees than the hash allows for
+            None => return Ok(None),
+        };
+
         let mut found = None;
 
         for (i, link) in iter.enumerate() {
             let name = link.Name.as_deref().unwrap_or_default();
 
+            // the magic number of two comes from the fanout (256) being formatted as hex
+            if name.len() < 2 || name.as_bytes()[..2] != prefix {
+                // no match, not interesting for us
+                continue;
+            }
+
             if name.len() > 2 && &name[2..] == needle {
                 if let Some(first) = found.take() {
                     return Err(MultipleMatchingLinks::from((first, (i, link))).into());
@@ -176,11 +222,10 @@ impl<'needle> ShardedLookup<'needle> {
                     found = Some((i, try_convert_cid(i, link)?));
                 }
             } else if name.len() == 2 {
-                // the magic number of two comes from the fanout (256) probably
                 let cid = try_convert_cid(i, link)?;
                 work.push_back(cid);
             } else {
-                // no match, not interesting for us
+                // a different entry in the same bucket
             }
         }
 
@@ -350,9 +395,6 @@ mod tests {
 
         // calling shardedlookup directly makes little sense, but through `resolve` it would make
         // sense
-
-        // testing this is a bit ... not nice, since we can only find out if there is a negative
-        // hit through exhausting the buckets
         let found = ShardedLookup::lookup_or_start(parsed, "bin", &mut None);
 
         match found {
@@ -366,8 +408,8 @@ mod tests {
     fn found_in_the_other_bucket() {
         let parsed = FlatUnixFs::try_from(DIR).unwrap();
 
-        // there is a single bin "B9" which would contain "formal" but our implementation cannot
-        // see it, it just guesses to start looking up in other buckets
+        // there is a single bin "B9" which contains "formal" as the first byte of the hash of
+        // "formal" is 0xB9
         let see_next = ShardedLookup::lookup_or_start(parsed, "formal", &mut None);
 
         let next = match see_next {
@@ -378,7 +420,7 @@ mod tests {
         {
             let (first, mut rest) = next.pending_links();
 
-            // there is only one bin: in other cases we would just walk in BFS order
+            // only the matching bucket is queued
             assert_eq!(
                 first.to_string(),
                 "QmfQgmYMYmGQP4X6V3JhTELkQmGVP9kpJgv9duejQ8vWez"
@@ -394,6 +436,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn definitive_negative_without_loading_buckets() {
+        let parsed = FlatUnixFs::try_from(DIR).unwrap();
+
+        // "doc" would be in bucket "6A" which only contains "docs"
+        let res = ShardedLookup::lookup_or_start(parsed, "doc", &mut None);
+
+        match res {
+            Ok(MaybeResolved::NotFound) => {}
+            x => unreachable!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn walks_only_the_matching_buckets() {
+        use crate::test_support::FakeBlockstore;
+
+        let blocks = FakeBlockstore::with_fixtures();
+        let root = blocks.get_by_str("QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk");
+
+        let mut cache = None;
+
+        for i in &[3, 4, 9, 16, 17, 25, 33, 34, 37, 38, 40, 41, 48, 49, 50, 58] {
+            let needle = format!("long-named-file-{:03}", i);
+            let parsed = FlatUnixFs::try_from(root).unwrap();
+
+            let mut loaded = 0;
+            let mut res = ShardedLookup::lookup_or_start(parsed, &needle, &mut cache).unwrap();
+
+            let cid = loop {
+                match res {
+                    MaybeResolved::Found(cid) => break cid,
+                    MaybeResolved::NeedToLoadMore(next) => {
+                        let (first, mut rest) = next.pending_links();
+                        assert!(rest.next().is_none(), "more than one bucket to look into");
+                        let block = blocks.get_by_cid(first);
+                        loaded += 1;
+                        res = next.continue_walk(block, &mut cache).unwrap();
+                    }
+                    MaybeResolved::NotFound => unreachable!("{} was not found", needle),
+                }
+            };
+
+            assert_eq!(loaded, 1, "{} should be in the second level", needle);
+            assert_eq!(
+                cid.to_string(),
+                "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+            );
+        }
+    }
+
     #[test]
     fn unsupported_hash_type_or_fanout() {
         use crate::pb::{FlatUnixFs, UnixFs, UnixFsType};
@@ -0,0 +1,426 @@
+use crate::walk::{self, ContinuedWalk, Walker};
+use crate::Metadata;
+use cid::Cid;
+use core::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Configures which of the stored properties are restored by [`export`]. By default the mode,
+/// the modification time and the symlinks are all restored.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    mode: bool,
+    mtime: bool,
+    symlinks: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            mode: true,
+            mtime: true,
+            symlinks: true,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// When true, the permission bits of the stored mode are set on the files and directories.
+    /// Only supported on unix platforms.
+    pub fn with_mode(self, mode: bool) -> Self {
+        ExportOptions { mode, ..self }
+    }
+
+    /// When true, the stored modification time is set on the files, directories and symlinks.
+    /// Requires the `filetime` feature.
+    pub fn with_mtime(self, mtime: bool) -> Self {
+        ExportOptions { mtime, ..self }
+    }
+
+    /// When true, the symlinks are created, otherwise they are skipped. Creating symlinks is only
+    /// supported on unix platforms.
+    pub fn with_symlinks(self, symlinks: bool) -> Self {
+        ExportOptions { symlinks, ..self }
+    }
+}
+
+/// Exports the UnixFS tree at `root` to the `target` path on the local filesystem, loading the
+/// blocks through the `fetch` callback. The root file or directory is created at the `target`,
+/// and the existing files are overwritten, but no existing symlinks are followed.
+///
+/// The stored UnixFS 1.5 metadata is restored according to the `options`. The symlinks are created
+/// only after all of the files and directories have been written, so that no file is written
+/// through a symlink of the tree. The metadata of the directories is applied last, so that
+/// read-only directories can be exported.
+pub fn export<F, B, E>(
+    root: &Cid,
+    target: &Path,
+    options: ExportOptions,
+    mut fetch: F,
+) -> Result<(), ExportFailed<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    let mut walker = Walker::new(root.to_owned(), String::new());
+    let mut cache = None;
+
+    let mut file: Option<File> = None;
+    let mut directories = Vec::new();
+    let mut symlinks = Vec::new();
+
+    while walker.should_continue() {
+        let block = {
+            let (next, _) = walker.pending_links();
+            fetch(next).map_err(ExportFailed::Fetch)?
+        };
+
+        match walker.next(block.as_ref(), &mut cache)? {
+            ContinuedWalk::Bucket(..) => {}
+            ContinuedWalk::File(segment, _, path, metadata, _) => {
+                let path = target_path(target, path)?;
+
+                if segment.is_first() {
+                    refuse_symlinks(target, &path)?;
+                    file = Some(File::create(&path)?);
+                }
+
+                let current = file
+                    .as_mut()
+                    .expect("the file was created with the first segment");
+                current.write_all(segment.as_ref())?;
+
+                if segment.is_last() {
+                    file = None;
+                    apply_metadata(&path, metadata, &options)?;
+                }
+            }
+            ContinuedWalk::Directory(_, path, metadata)
+            | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                let path = target_path(target, path)?;
+                refuse_symlinks(target, &path)?;
+                fs::create_dir_all(&path)?;
+                directories.push((path, metadata.to_owned()));
+            }
+            ContinuedWalk::Symlink(bytes, _, path, metadata) if options.symlinks => {
+                let path = target_path(target, path)?;
+                let link_target = core::str::from_utf8(bytes)
+                    .map_err(|_| ExportFailed::NonUtf8Symlink(path.clone()))?;
+
+                symlinks.push((path, link_target.to_owned(), metadata.to_owned()));
+            }
+            ContinuedWalk::Symlink(..) => {}
+        }
+    }
+
+    for (path, link_target, metadata) in symlinks {
+        refuse_symlinks(target, &path)?;
+        create_symlink(&link_target, &path)?;
+
+        #[cfg(feature = "filetime")]
+        {
+            if let Some(mtime) = metadata.mtime_as_filetime().filter(|_| options.mtime) {
+                filetime::set_symlink_file_times(&path, mtime, mtime)?;
+            }
+        }
+
+        #[cfg(not(feature = "filetime"))]
+        let _ = metadata;
+    }
+
+    // the entries are visited before the next sibling directory, so setting the metadata in the
+    // reverse order handles the most nested directories first
+    for (path, metadata) in directories.iter().rev() {
+        apply_metadata(path, metadata, &options)?;
+    }
+
+    Ok(())
+}
+
+/// Joins the path of the walked entry to the `target`, refusing any paths which would not stay
+/// under the `target`.
+fn target_path<E>(target: &Path, path: &Path) -> Result<PathBuf, ExportFailed<E>> {
+    let mut joined = target.to_path_buf();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => joined.push(name),
+            _ => return Err(ExportFailed::InvalidPath(path.to_path_buf())),
+        }
+    }
+
+    Ok(joined)
+}
+
+/// Refuses to write to the `path` under the `target` when the path or any of its parents under the
+/// `target` is an existing symlink, which could point outside of the `target`.
+fn refuse_symlinks<E>(target: &Path, path: &Path) -> Result<(), ExportFailed<E>> {
+    let relative = path.strip_prefix(target).unwrap_or(path);
+    let mut current = target.to_path_buf();
+
+    for component in relative.components() {
+        current.push(component);
+
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(ExportFailed::InvalidPath(path.to_path_buf()));
+            }
+            Ok(_) => {}
+            // nothing exists below a missing path
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_metadata(path: &Path, metadata: &Metadata, options: &ExportOptions) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(mode) = metadata.mode().filter(|_| options.mode) {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    #[cfg(feature = "filetime")]
+    {
+        if let Some(mtime) = metadata.mtime_as_filetime().filter(|_| options.mtime) {
+            filetime::set_file_mtime(path, mtime)?;
+        }
+    }
+
+    #[cfg(not(all(unix, feature = "filetime")))]
+    let _ = (path, metadata, options);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "creating symlinks is only supported on unix",
+    ))
+}
+
+/// Errors which can happen while exporting with [`export`].
+#[derive(Debug)]
+pub enum ExportFailed<E> {
+    /// The `fetch` callback failed.
+    Fetch(E),
+    /// Walking the tree failed.
+    Walk(walk::Error),
+    /// Writing to the filesystem failed.
+    Io(io::Error),
+    /// An entry had a name which would have been written outside of the target, such as `..`, or
+    /// the path of the entry went through an existing symlink.
+    InvalidPath(PathBuf),
+    /// The target of the symlink at the path was not valid UTF-8.
+    NonUtf8Symlink(PathBuf),
+}
+
+impl<E> From<walk::Error> for ExportFailed<E> {
+    fn from(e: walk::Error) -> Self {
+        ExportFailed::Walk(e)
+    }
+}
+
+impl<E> From<io::Error> for ExportFailed<E> {
+    fn from(e: io::Error) -> Self {
+        ExportFailed::Io(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ExportFailed<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ExportFailed::*;
+
+        match self {
+            Fetch(e) => write!(fmt, "failed to fetch a block: {}", e),
+            Walk(e) => write!(fmt, "{}", e),
+            Io(e) => write!(fmt, "{}", e),
+            InvalidPath(path) => write!(fmt, "invalid path in the tree: {:?}", path),
+            NonUtf8Symlink(path) => write!(fmt, "symlink target is not UTF-8 at {:?}", path),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for ExportFailed<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ExportFailed::*;
+
+        match self {
+            Fetch(e) => Some(e),
+            Walk(e) => Some(e),
+            Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(test, unix, feature = "filetime"))]
+mod tests {
+    use super::{export, ExportOptions};
+    use crate::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use crate::file::adder::FileAdder;
+    use crate::Metadata;
+    use cid::Cid;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn tree() -> (Cid, HashMap<Cid, Vec<u8>>) {
+        let mut blocks = HashMap::new();
+
+        let (file, total_size) = {
+            let adder = FileAdder::builder()
+                .with_chunk_size(2)
+                .with_metadata(Metadata::default().with_mode(0o600).with_mtime(1_000, 0))
+                .build();
+
+            let mut total_size = 0;
+            let mut last = None;
+            for (cid, block) in adder.collect_blocks(b"foobar\n", 0) {
+                total_size += block.len() as u64;
+                blocks.insert(cid.clone(), block);
+                last = Some(cid);
+            }
+            (last.unwrap(), total_size)
+        };
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        builder.put_link("a/foobar", file, total_size).unwrap();
+        let (cid, block) = builder.put_symlink("a/link", "foobar").unwrap();
+        blocks.insert(cid, block);
+        builder
+            .set_metadata(
+                "a",
+                Metadata::default().with_mode(0o500).with_mtime(2_000, 0),
+            )
+            .unwrap();
+
+        let mut root = None;
+        for node in builder.build() {
+            let node = node.unwrap();
+            blocks.insert(node.cid.clone(), node.block.into_vec());
+            root = Some(node.cid);
+        }
+
+        (root.unwrap(), blocks)
+    }
+
+    fn export_to(test: &str, options: ExportOptions) -> PathBuf {
+        let (root, blocks) = tree();
+
+        let target =
+            std::env::temp_dir().join(format!("unixfs-export-{}-{}", test, std::process::id()));
+
+        export(&root, &target, options, |cid: &Cid| {
+            Ok::<_, Infallible>(&blocks[cid])
+        })
+        .unwrap();
+
+        target
+    }
+
+    fn cleanup(target: PathBuf) {
+        fs::set_permissions(target.join("a"), fs::Permissions::from_mode(0o700)).unwrap();
+        fs::remove_dir_all(target).unwrap();
+    }
+
+    fn mtime(path: PathBuf) -> i64 {
+        let metadata = fs::symlink_metadata(path).unwrap();
+        filetime::FileTime::from_last_modification_time(&metadata).unix_seconds()
+    }
+
+    #[test]
+    fn metadata_is_restored() {
+        let target = export_to("restored", ExportOptions::default());
+
+        let file = target.join("a/foobar");
+        assert_eq!(fs::read(&file).unwrap(), b"foobar\n");
+        assert_eq!(
+            fs::metadata(&file).unwrap().permissions().mode() & 0o7777,
+            0o600
+        );
+        assert_eq!(mtime(file), 1_000);
+
+        let dir = target.join("a");
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o7777,
+            0o500
+        );
+        assert_eq!(mtime(dir), 2_000);
+
+        assert_eq!(
+            fs::read_link(target.join("a/link")).unwrap(),
+            PathBuf::from("foobar")
+        );
+
+        cleanup(target);
+    }
+
+    #[test]
+    fn metadata_opted_out() {
+        let options = ExportOptions::default()
+            .with_mode(false)
+            .with_mtime(false)
+            .with_symlinks(false);
+
+        let target = export_to("opted_out", options);
+
+        let file = target.join("a/foobar");
+        assert_eq!(fs::read(&file).unwrap(), b"foobar\n");
+        assert_ne!(mtime(file), 1_000);
+
+        let dir = target.join("a");
+        assert_ne!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o7777,
+            0o500
+        );
+
+        assert!(fs::symlink_metadata(target.join("a/link")).is_err());
+
+        cleanup(target);
+    }
+
+    #[test]
+    fn existing_symlinks_are_not_followed() {
+        let (root, blocks) = tree();
+
+        let base =
+            std::env::temp_dir().join(format!("unixfs-export-symlinked-{}", std::process::id()));
+        let outside = base.join("outside");
+        let target = base.join("target");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        std::os::unix::fs::symlink(&outside, target.join("a")).unwrap();
+
+        let res = export(&root, &target, ExportOptions::default(), |cid: &Cid| {
+            Ok::<_, Infallible>(&blocks[cid])
+        });
+
+        assert!(
+            matches!(res, Err(super::ExportFailed::InvalidPath(_))),
+            "{:?}",
+            res
+        );
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
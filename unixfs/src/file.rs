@@ -16,6 +16,14 @@ pub mod visit;
 /// File adder capable of constructing UnixFs v1 trees
 pub mod adder;
 
+/// Random access reading of files with `read_at` and `seek`.
+mod seekable;
+pub use seekable::{SeekableFile, SeekableFileError};
+
+/// Mapping byte ranges of files to the blocks holding them.
+mod block_ranges;
+pub use block_ranges::{block_ranges, BlockRange, BlockRangesFailed};
+
 /// Describes the errors which can happen during a visit or lower level block-by-block walking of
 /// the DAG.
 #[derive(Debug)]
@@ -101,6 +109,21 @@ pub enum FileError {
         /// directories.
         fanout: Option<u64>,
     },
+    /// A raw leaf block had a different length than the one recorded in the linking block.
+    RawLeafSizeMismatch {
+        /// Length as recorded in the blocksizes of the linking block.
+        expected: u64,
+        /// Actual length of the raw block.
+        actual: u64,
+    },
+    /// A dag-pb block had a different length of content than the one recorded in the linking
+    /// block, as when a link block was recognized as a leaf by the sizes recorded in the link.
+    LeafSizeMismatch {
+        /// Length as recorded in the blocksizes of the linking block.
+        expected: u64,
+        /// Actual length of the content of the block.
+        actual: u64,
+    },
 }
 
 impl fmt::Display for FileError {
@@ -133,6 +156,16 @@ impl fmt::Display for FileError {
                 "unsupported: File or Raw with hash_type {:?} or fanount {:?}",
                 hash_type, fanout
             ),
+            RawLeafSizeMismatch { expected, actual } => write!(
+                fmt,
+                "raw leaf has {} bytes while {} were expected",
+                actual, expected
+            ),
+            LeafSizeMismatch { expected, actual } => write!(
+                fmt,
+                "leaf has {} bytes of content while {} were expected",
+                actual, expected
+            ),
         }
     }
 }
@@ -1,21 +1,35 @@
 use cid::Cid;
 
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use crate::{CidOptions, Metadata};
 use alloc::borrow::Cow;
 use core::fmt;
-use multihash::MultihashDigest;
 use quick_protobuf::{MessageWrite, Writer};
 
-use sha2::{Digest, Sha256};
+mod buzhash;
+pub use buzhash::BuzhashChunker;
+
+mod rabin;
+pub use rabin::RabinChunker;
+
+mod writer;
+pub use writer::FileAdderWriter;
+
+mod append;
+pub use append::AppendFailed;
+
+mod trickle;
+pub use trickle::TrickleCollector;
 
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
 /// Custom file tree builder can be created with [`FileAdder::builder()`] and configuring the
 /// chunker and collector.
 ///
-/// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
-/// inline links.
+/// Current implementation maintains an internal buffer for the block creation and by default
+/// produces Cid version 0 links with SHA2-256, or Cid version 1 links for the leaves when using
+/// raw leaves. The Cid version and the hash function can be configured with [`CidOptions`].
+/// Currently does not support inline links.
 #[derive(Default)]
 pub struct FileAdder {
     chunker: Chunker,
@@ -27,14 +41,23 @@ pub struct FileAdder {
     // large file and using a minimal chunk size. Could be that this must be moved to Collector to
     // help collector (or layout) to decide how this should be persisted.
     unflushed_links: Vec<Link>,
+    // written to the root block of the file
+    metadata: Metadata,
+    // the first whole chunk of a file with metadata, held back until it is known whether it is
+    // also the last one, as the root leaf holds the metadata like with go-ipfs
+    first_chunk: Option<Vec<u8>>,
+    // leaves are written as `raw` codec blocks instead of UnixFS File blocks
+    raw_leaves: bool,
+    cid_options: CidOptions,
 }
 
 impl fmt::Debug for FileAdder {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "FileAdder {{ chunker: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
+            "FileAdder {{ chunker: {:?}, raw_leaves: {}, block_buffer: {}/{}, unflushed_links: {} }}",
             self.chunker,
+            self.raw_leaves,
             self.block_buffer.len(),
             self.block_buffer.capacity(),
             LinkFormatter(&self.unflushed_links),
@@ -86,6 +109,7 @@ impl fmt::Display for LinkFormatter<'_> {
 
 /// Represents an intermediate structure which will be serialized into link blocks as both PBLink
 /// and UnixFs::blocksize. Also holds `depth`, which helps with compaction of the link blocks.
+#[derive(Clone)]
 struct Link {
     /// Depth of this link. Zero is leaf, and anything above it is, at least for
     /// [`BalancedCollector`], the compacted link blocks.
@@ -111,18 +135,52 @@ impl fmt::Debug for Link {
 }
 
 /// Convenience type to facilitate configuring [`FileAdder`]s.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    metadata: Metadata,
+    raw_leaves: bool,
+    cid_options: CidOptions,
 }
 
 impl FileAdderBuilder {
     /// Configures the builder to use the given chunker.
+    ///
+    /// # Panics
+    ///
+    /// When given a `Chunker::Size(0)`.
     pub fn with_chunker(self, chunker: Chunker) -> Self {
+        if let Chunker::Size(0) = chunker {
+            panic!("chunk size must be larger than zero");
+        }
         FileAdderBuilder { chunker, ..self }
     }
 
+    /// Configures the builder to use fixed size chunks of `chunk_size` bytes. Shorthand for
+    /// `with_chunker(Chunker::Size(chunk_size))`.
+    ///
+    /// # Panics
+    ///
+    /// When `chunk_size` is zero.
+    pub fn with_chunk_size(self, chunk_size: usize) -> Self {
+        self.with_chunker(Chunker::Size(chunk_size))
+    }
+
+    /// Configures the builder to use the balanced layout with at most `branching_factor` links
+    /// per link block. Shorthand for
+    /// `with_collector(BalancedCollector::with_branching_factor(branching_factor))`.
+    ///
+    /// A smaller branching factor creates deeper trees of smaller link blocks, which are faster to
+    /// load when seeking, while a larger one creates shallower trees. The default is 174.
+    ///
+    /// # Panics
+    ///
+    /// When `branching_factor` is zero.
+    pub fn with_branching_factor(self, branching_factor: usize) -> Self {
+        self.with_collector(BalancedCollector::with_branching_factor(branching_factor))
+    }
+
     /// Configures the builder to use the given collector or layout.
     pub fn with_collector(self, collector: impl Into<Collector>) -> Self {
         FileAdderBuilder {
@@ -131,13 +189,47 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to write the given metadata (mode and mtime) to the root block of
+    /// the file. With the balanced layout, a file of a single chunk is a single block holding the
+    /// metadata like with go-ipfs, so the first chunk is only returned from [`FileAdder::push`]
+    /// once more content follows it.
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        FileAdderBuilder { metadata, ..self }
+    }
+
+    /// Configures the builder to write the leaf chunks as `raw` codec blocks with Cid version 1,
+    /// like go-ipfs `--raw-leaves`. The link blocks are still dag-pb blocks with Cid version 0.
+    ///
+    /// A file of only a single chunk will be a single `raw` block, unless there is metadata to be
+    /// written, in which case the root will be a UnixFS File block.
+    pub fn with_raw_leaves(self, raw_leaves: bool) -> Self {
+        FileAdderBuilder { raw_leaves, ..self }
+    }
+
+    /// Configures the builder to create the Cids with the given version and hash function.
+    pub fn with_cid_options(self, cid_options: CidOptions) -> Self {
+        FileAdderBuilder {
+            cid_options,
+            ..self
+        }
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            metadata,
+            raw_leaves,
+            cid_options,
+        } = self;
 
         FileAdder {
             chunker,
             collector,
+            metadata,
+            raw_leaves,
+            cid_options,
             ..Default::default()
         }
     }
@@ -159,12 +251,19 @@ impl FileAdder {
 
     /// Called to push new file bytes into the tree builder.
     ///
-    /// Returns the newly created blocks (at most 2) and their respective Cids, and the amount of
-    /// `input` consumed.
+    /// Returns the newly created blocks (at most 2, or 3 when the first chunk of a file with
+    /// metadata was held back) and their respective Cids, and the amount of `input` consumed.
     pub fn push(&mut self, input: &[u8]) -> (impl Iterator<Item = (Cid, Vec<u8>)>, usize) {
         let (accepted, ready) = self.chunker.accept(input, &self.block_buffer);
 
-        if self.block_buffer.is_empty() && ready {
+        // the held back first chunk cannot be the root when more content follows it
+        let held = if accepted.is_empty() {
+            Vec::new()
+        } else {
+            self.flush_first_chunk()
+        };
+
+        let (leaf, links) = if self.block_buffer.is_empty() && ready {
             // save single copy as the caller is giving us whole chunks.
             //
             // TODO: though, this path does make one question if there is any point in keeping
@@ -172,11 +271,23 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
-            assert!(leaf.is_some(), "chunk completed, must produce a new block");
-            self.block_buffer.clear();
-            let links = self.flush_buffered_links(false);
-            (leaf.into_iter().chain(links.into_iter()), accepted.len())
+            if self.should_hold_back() {
+                self.first_chunk = Some(accepted.to_vec());
+                (None, Vec::new())
+            } else {
+                let leaf = Self::flush_buffered_leaf(
+                    accepted,
+                    &mut self.unflushed_links,
+                    self.raw_leaves,
+                    &self.cid_options,
+                    &self.collector,
+                    None,
+                );
+                assert!(leaf.is_some(), "chunk completed, must produce a new block");
+                self.block_buffer.clear();
+                let links = self.flush_buffered_links(false);
+                (leaf, links)
+            }
         } else {
             // slower path as we manage the buffer.
 
@@ -187,26 +298,123 @@ impl FileAdder {
             }
 
             self.block_buffer.extend_from_slice(accepted);
-            let written = accepted.len();
 
-            let (leaf, links) = if !ready {
+            if !ready {
                 // a new block did not become ready, which means we couldn't have gotten a new cid.
                 (None, Vec::new())
+            } else if self.should_hold_back() {
+                self.first_chunk = Some(core::mem::take(&mut self.block_buffer));
+                (None, Vec::new())
             } else {
                 // a new leaf must be output, as well as possibly a new link block
                 let leaf = Self::flush_buffered_leaf(
                     self.block_buffer.as_slice(),
                     &mut self.unflushed_links,
-                    false,
+                    self.raw_leaves,
+                    &self.cid_options,
+                    &self.collector,
+                    None,
                 );
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
                 self.block_buffer.clear();
                 let links = self.flush_buffered_links(false);
 
                 (leaf, links)
-            };
-            (leaf.into_iter().chain(links.into_iter()), written)
+            }
+        };
+
+        let blocks = held.into_iter().chain(leaf).chain(links.into_iter());
+        (blocks, accepted.len())
+    }
+
+    /// Returns true when the next whole chunk is the first one of a file with metadata, which is
+    /// held back until it is known whether it is also the root.
+    fn should_hold_back(&self) -> bool {
+        !self.metadata.is_empty()
+            && self.first_chunk.is_none()
+            && self.unflushed_links.is_empty()
+            && self.collector.leaf_can_be_root()
+    }
+
+    /// Flushes the held back first chunk as a leaf which is not the root, returning the new
+    /// blocks.
+    fn flush_first_chunk(&mut self) -> Vec<(Cid, Vec<u8>)> {
+        let chunk = match self.first_chunk.take() {
+            Some(chunk) => chunk,
+            None => return Vec::new(),
+        };
+
+        let leaf = Self::flush_buffered_leaf(
+            &chunk,
+            &mut self.unflushed_links,
+            self.raw_leaves,
+            &self.cid_options,
+            &self.collector,
+            None,
+        );
+        assert!(leaf.is_some(), "chunk completed, must produce a new block");
+
+        leaf.into_iter()
+            .chain(self.flush_buffered_links(false))
+            .collect()
+    }
+
+    /// Like [`FileAdder::push`] but consumes all of the `input`, hashing all of the whole chunks
+    /// in it in parallel on the rayon thread pool. The link blocks are still created in order, so
+    /// the produced blocks are the same as with `push`.
+    ///
+    /// Returns all of the newly created blocks and their respective Cids in the same order as
+    /// `push` would had returned them, and the amount of `input` consumed which is always the
+    /// length of `input`. To benefit from the parallelism, `input` should span many chunks.
+    #[cfg(feature = "parallel")]
+    pub fn push_parallel(&mut self, input: &[u8]) -> (Vec<(Cid, Vec<u8>)>, usize) {
+        use rayon::prelude::*;
+
+        let mut blocks = Vec::new();
+        let mut consumed = 0;
+
+        // complete the partially buffered chunk, or the first chunk which might be held back,
+        // through the usual path
+        while (!self.block_buffer.is_empty() || self.should_hold_back()) && consumed < input.len() {
+            let (completed, written) = self.push(&input[consumed..]);
+            blocks.extend(completed);
+            consumed += written;
+        }
+
+        if consumed < input.len() {
+            blocks.extend(self.flush_first_chunk());
+        }
+
+        let mut chunks = Vec::new();
+
+        while consumed < input.len() {
+            let (accepted, ready) = self.chunker.accept(&input[consumed..], &[]);
+            consumed += accepted.len();
+
+            if ready {
+                chunks.push(accepted);
+            } else {
+                // what remains is not a whole chunk, buffer it for the next push or finish
+                self.block_buffer.extend_from_slice(accepted);
+            }
+        }
+
+        let raw_leaves = self.raw_leaves;
+        let cid_options = self.cid_options;
+        let leaf_type = self.collector.leaf_type();
+
+        let leaves = chunks
+            .par_iter()
+            .map(|chunk| Self::render_leaf(chunk, raw_leaves, leaf_type, &cid_options, None))
+            .collect::<Vec<_>>();
+
+        for (cid, block, link) in leaves {
+            self.unflushed_links.push(link);
+            blocks.push((cid, block));
+            blocks.extend(self.flush_buffered_links(false));
         }
+
+        (blocks, consumed)
     }
 
     /// Called after the last [`FileAdder::push`] to finish the tree construction.
@@ -216,27 +424,77 @@ impl FileAdder {
     /// Note: the API will hopefully evolve in a direction which will not allocate a new Vec for
     /// every block in the near-ish future.
     pub fn finish(mut self) -> impl Iterator<Item = (Cid, Vec<u8>)> {
+        if let Some(chunk) = self.first_chunk.take() {
+            // nothing followed the first chunk, so it is the root
+            debug_assert!(self.block_buffer.is_empty());
+            self.block_buffer = chunk;
+        }
+
         let last_leaf = Self::flush_buffered_leaf(
             &self.block_buffer.as_slice(),
             &mut self.unflushed_links,
-            true,
+            self.raw_leaves,
+            &self.cid_options,
+            &self.collector,
+            Some(&self.metadata),
         );
         let root_links = self.flush_buffered_links(true);
+
         // should probably error if there is neither?
         last_leaf.into_iter().chain(root_links.into_iter())
     }
 
     /// Returns `None` when the input is empty but there are links, otherwise a new Cid and a
-    /// block.
+    /// block. `root_metadata` is given only when finishing, and it is written to the block only
+    /// when it ends up being the root of the file, which the `collector` may not allow. With
+    /// `raw_leaves` the block is the `input` as is, except for a root with metadata.
     fn flush_buffered_leaf(
         input: &[u8],
         unflushed_links: &mut Vec<Link>,
-        finishing: bool,
+        raw_leaves: bool,
+        cid_options: &CidOptions,
+        collector: &Collector,
+        root_metadata: Option<&Metadata>,
     ) -> Option<(Cid, Vec<u8>)> {
-        if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
+        let finishing = root_metadata.is_some();
+        let can_be_root = collector.leaf_can_be_root() && unflushed_links.is_empty();
+
+        if input.is_empty() && (!finishing || !can_be_root) {
             return None;
         }
 
+        let root_metadata = root_metadata.filter(|_| can_be_root);
+        let leaf_type = collector.leaf_type();
+
+        let (cid, block, link) =
+            Self::render_leaf(input, raw_leaves, leaf_type, cid_options, root_metadata);
+        unflushed_links.push(link);
+        Some((cid, block))
+    }
+
+    /// Renders and hashes a single leaf block, returning it with the link to it. Does not depend
+    /// on the state of the tree, so the leaves can be created in any order. The root of the file
+    /// is always a UnixFS `File`, the other leaves are of the `leaf_type`.
+    fn render_leaf(
+        input: &[u8],
+        raw_leaves: bool,
+        leaf_type: UnixFsType,
+        cid_options: &CidOptions,
+        root_metadata: Option<&Metadata>,
+    ) -> (Cid, Vec<u8>, Link) {
+        if raw_leaves && root_metadata.map(Metadata::is_empty).unwrap_or(true) {
+            let cid = cid_options.raw(input);
+
+            let link = Link {
+                depth: 0,
+                target: cid.clone(),
+                total_size: input.len() as u64,
+                file_size: input.len() as u64,
+            };
+
+            return (cid, input.to_vec(), link);
+        }
+
         // for empty unixfs file the bytes is missing but filesize is present.
 
         let data = if !input.is_empty() {
@@ -247,10 +505,14 @@ impl FileAdder {
 
         let filesize = Some(input.len() as u64);
 
-        let inner = FlatUnixFs {
+        let mut inner = FlatUnixFs {
             links: Vec::new(),
             data: UnixFs {
-                Type: UnixFsType::File,
+                Type: if root_metadata.is_some() {
+                    UnixFsType::File
+                } else {
+                    leaf_type
+                },
                 Data: data,
                 filesize,
                 // no blocksizes as there are no links
@@ -258,7 +520,11 @@ impl FileAdder {
             },
         };
 
-        let (cid, vec) = render_and_hash(&inner);
+        if let Some(metadata) = root_metadata {
+            metadata.apply_to(&mut inner.data);
+        }
+
+        let (cid, vec) = render_and_hash(&inner, cid_options);
 
         let total_size = vec.len();
 
@@ -269,14 +535,18 @@ impl FileAdder {
             file_size: input.len() as u64,
         };
 
-        unflushed_links.push(link);
-
-        Some((cid, vec))
+        (cid, vec, link)
     }
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+        let root_metadata = if finishing {
+            Some(&self.metadata)
+        } else {
+            None
+        };
+
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, root_metadata, &self.cid_options)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -284,7 +554,11 @@ impl FileAdder {
     /// chunker, otherwise `all_content` is pushed at `amt` sized slices with the idea of catching
     /// bugs in chunkers.
     #[cfg(test)]
-    fn collect_blocks(mut self, all_content: &[u8], mut amt: usize) -> Vec<(Cid, Vec<u8>)> {
+    pub(crate) fn collect_blocks(
+        mut self,
+        all_content: &[u8],
+        mut amt: usize,
+    ) -> Vec<(Cid, Vec<u8>)> {
         let mut written = 0;
         let mut blocks_received = Vec::new();
 
@@ -308,7 +582,7 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+fn render_and_hash(flat: &FlatUnixFs<'_>, cid_options: &CidOptions) -> (Cid, Vec<u8>) {
     // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
     // either just render a fixed header and continue with the body OR links, though the links are
     // a bit more complicated.
@@ -316,16 +590,25 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
     let mut writer = Writer::new(&mut out);
     flat.write_message(&mut writer)
         .expect("unsure how this could fail");
-    let mh = multihash::Code::Sha2_256.digest(&out);
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+    let cid = cid_options.dag_pb(&out);
     (cid, out)
 }
 
 /// Chunker strategy
+///
+/// Can be parsed from the go-ipfs style profile strings: `size-{bytes}`, `rabin`, `rabin-{avg}`,
+/// `rabin-{min}-{avg}-{max}` and `buzhash`. The sizes can be suffixed with `k` or `m` for KiB and
+/// MiB, for example `rabin-256k-512k-1m`. Like in go-ipfs, the chunks can be at most
+/// [`MAX_CHUNK_SIZE`] bytes.
 #[derive(Debug, Clone)]
 pub enum Chunker {
     /// Size based chunking
     Size(usize),
+    /// Content defined chunking using Rabin fingerprints
+    Rabin(RabinChunker),
+    /// Content defined chunking using buzhash; the boundaries, and so the Cids, differ from
+    /// go-ipfs `--chunker=buzhash`
+    Buzhash(BuzhashChunker),
 }
 
 impl Default for Chunker {
@@ -346,6 +629,8 @@ impl Chunker {
                 let ready = buffered.len() + l >= *max;
                 (accepted, ready)
             }
+            Rabin(rabin) => rabin.accept(input, buffered),
+            Buzhash(buzhash) => buzhash.accept(input, buffered),
         }
     }
 
@@ -354,18 +639,82 @@ impl Chunker {
 
         match self {
             Size(max) => *max,
+            Rabin(rabin) => rabin.max_size(),
+            Buzhash(buzhash) => buzhash.max_size(),
+        }
+    }
+}
+
+/// The largest chunk size accepted when parsing a [`Chunker`], the same as the go-ipfs limit. The
+/// chunker strings can come from untrusted input, and a chunk of the maximum size is buffered.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+impl core::str::FromStr for Chunker {
+    type Err = InvalidChunker;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidChunker(s.to_owned());
+
+        let mut parts = s.split('-');
+        let kind = parts
+            .next()
+            .expect("split always returns at least one element");
+        let sizes = parts
+            .map(parse_size)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+
+        match (kind, sizes.as_slice()) {
+            ("size", &[size]) if size > 0 && size <= MAX_CHUNK_SIZE => Ok(Chunker::Size(size)),
+            ("rabin", &[]) => Ok(Chunker::Rabin(RabinChunker::default())),
+            // the maximum of `with_avg` is one and a half times the average
+            ("rabin", &[avg]) if avg / 3 >= 16 && avg <= MAX_CHUNK_SIZE / 3 * 2 => {
+                Ok(Chunker::Rabin(RabinChunker::with_avg(avg)))
+            }
+            ("rabin", &[min, avg, max])
+                if 16 <= min && min <= avg && avg <= max && max <= MAX_CHUNK_SIZE =>
+            {
+                Ok(Chunker::Rabin(RabinChunker::new(min, avg, max)))
+            }
+            ("buzhash", &[]) => Ok(Chunker::Buzhash(BuzhashChunker::default())),
+            _ => Err(invalid()),
         }
     }
 }
 
+/// Parses a size in bytes with the optional `k` or `m` suffix.
+fn parse_size(s: &str) -> Option<usize> {
+    let (digits, multiplier) = match s.as_bytes().last()? {
+        b'k' | b'K' => (&s[..s.len() - 1], 1024),
+        b'm' | b'M' => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// The chunker profile string could not be parsed; see [`Chunker`] for the supported values.
+#[derive(Debug)]
+pub struct InvalidChunker(String);
+
+impl fmt::Display for InvalidChunker {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "invalid or unsupported chunker: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidChunker {}
+
 /// Collector or layout strategy. For more information, see the [Layout section of the spec].
-/// Currently only the default balanced collector/layout has been implemented.
+/// The default is the balanced layout, and the trickle layout is also available.
 ///
 /// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
 #[derive(Debug, Clone)]
 pub enum Collector {
     /// Balanced trees.
     Balanced(BalancedCollector),
+    /// Trickle trees, like go-ipfs `add --trickle`.
+    Trickle(TrickleCollector),
 }
 
 impl Default for Collector {
@@ -375,13 +724,39 @@ impl Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    /// `root_metadata` is given only when finishing.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        root_metadata: Option<&Metadata>,
+        cid_options: &CidOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, root_metadata, cid_options),
+            Trickle(tc) => tc.flush_links(pending, root_metadata, cid_options),
         }
     }
+
+    /// Returns true if a file of a single leaf can have the leaf as the root.
+    fn leaf_can_be_root(&self) -> bool {
+        matches!(self, Collector::Balanced(_))
+    }
+
+    /// Returns the UnixFS type of the leaves which are not the root.
+    fn leaf_type(&self) -> UnixFsType {
+        match self {
+            Collector::Balanced(_) => UnixFsType::File,
+            Collector::Trickle(_) => UnixFsType::Raw,
+        }
+    }
+}
+
+impl From<TrickleCollector> for Collector {
+    fn from(t: TrickleCollector) -> Self {
+        Collector::Trickle(t)
+    }
 }
 
 /// BalancedCollector creates balanced UnixFs trees, most optimized for random access to different
@@ -436,10 +811,15 @@ impl BalancedCollector {
         }
     }
 
-    /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
-    /// links will be compressed iteratively from the lowest level to produce a single root link
-    /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    /// In-place compression of the `pending` links to a balanced hierarchy. When finishing, as
+    /// signalled by `root_metadata` being `Some`, the links will be compressed iteratively from the
+    /// lowest level to produce a single root link block, which will contain the metadata.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        root_metadata: Option<&Metadata>,
+        cid_options: &CidOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -486,6 +866,8 @@ impl BalancedCollector {
         new link block #3 is created for A, and #2. (the root block)
         */
 
+        let finishing = root_metadata.is_some();
+
         let mut ret = Vec::new();
 
         let mut reused_links = core::mem::take(&mut self.reused_links);
@@ -569,7 +951,7 @@ impl BalancedCollector {
 
                 debug_assert_eq!(reused_links.len(), reused_blocksizes.len());
 
-                let inner = FlatUnixFs {
+                let mut inner = FlatUnixFs {
                     links: reused_links,
                     data: UnixFs {
                         Type: UnixFsType::File,
@@ -579,7 +961,14 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                if first_at == 0 && last == pending.len() {
+                    // when finishing, compressing all of the pending links produces the root
+                    if let Some(metadata) = root_metadata {
+                        metadata.apply_to(&mut inner.data);
+                    }
+                }
+
+                let (cid, vec) = render_and_hash(&inner, cid_options);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -644,8 +1033,9 @@ impl BalancedCollector {
 #[cfg(test)]
 mod tests {
 
-    use super::{BalancedCollector, Chunker, FileAdder};
+    use super::{BalancedCollector, Chunker, FileAdder, TrickleCollector};
     use crate::test_support::FakeBlockstore;
+    use crate::Metadata;
     use cid::Cid;
     use core::convert::TryFrom;
     use hex_literal::hex;
@@ -669,6 +1059,105 @@ mod tests {
         (accepted.len(), ready)
     }
 
+    #[test]
+    fn parse_chunkers() {
+        let valid = [
+            ("size-262144", "Size(262144)"),
+            ("size-1k", "Size(1024)"),
+            (
+                "rabin",
+                "Rabin(RabinChunker { min: 87381, avg: 262144, max: 393216 })",
+            ),
+            (
+                "rabin-1024",
+                "Rabin(RabinChunker { min: 341, avg: 1024, max: 1536 })",
+            ),
+            ("size-1m", "Size(1048576)"),
+            (
+                "rabin-256k-512k-1m",
+                "Rabin(RabinChunker { min: 262144, avg: 524288, max: 1048576 })",
+            ),
+            (
+                "rabin-699050",
+                "Rabin(RabinChunker { min: 233016, avg: 699050, max: 1048575 })",
+            ),
+            (
+                "buzhash",
+                "Buzhash(BuzhashChunker { min: 131072, max: 524288, mask: 0x1ffff })",
+            ),
+        ];
+
+        for (input, expected) in &valid {
+            let chunker = input.parse::<Chunker>().unwrap();
+            assert_eq!(&format!("{:?}", chunker), expected);
+        }
+
+        let invalid = [
+            "",
+            "size",
+            "size-0",
+            "size-foo",
+            "size-1-2",
+            "rabin-1m-1k-4m",
+            "rabin-1-2-3",
+            // larger than the maximum chunk size
+            "size-1048577",
+            "size-2m",
+            "size-4000000000m",
+            "rabin-699051",
+            "rabin-1m",
+            "rabin-16-16-99999999m",
+            "rabin-256k-1m-4m",
+            "rabin-1-2",
+            "buzhash-1",
+            "trickle",
+        ];
+
+        for input in &invalid {
+            input.parse::<Chunker>().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn content_defined_chunkers_roundtrip() {
+        use crate::file::visit::IdleFileVisit;
+
+        let content = crate::test_support::pseudo_random(64 * 1024);
+
+        for chunker in &["rabin-1k-4k-8k", "buzhash"] {
+            let adder = FileAdder::builder()
+                .with_chunker(chunker.parse().unwrap())
+                .build();
+
+            let mut blocks = FakeBlockstore::default();
+            let mut root = None;
+
+            for (cid, block) in adder.collect_blocks(&content, 1000) {
+                assert_eq!(blocks.insert_v0(&block), cid);
+                root = Some(cid);
+            }
+
+            let root = root.unwrap();
+
+            let mut read_back = Vec::new();
+            let (bytes, _, _, mut visit) = IdleFileVisit::default()
+                .start(blocks.get_by_cid(&root))
+                .unwrap();
+            read_back.extend_from_slice(bytes);
+
+            while let Some(v) = visit {
+                let (first, _) = v.pending_links();
+                let (bytes, next) = v
+                    .continue_walk(blocks.get_by_cid(first), &mut None)
+                    .unwrap();
+                read_back.extend_from_slice(bytes);
+                visit = next;
+            }
+
+            assert_eq!(read_back, content, "{}", chunker);
+        }
+    }
+
     #[test]
     fn favourite_single_block_file() {
         let blocks = FakeBlockstore::with_fixtures();
@@ -772,6 +1261,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn chunk_size_shorthand() {
+        let blocks_received = FileAdder::builder()
+            .with_chunk_size(2)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        // same as `favourite_multi_block_file`
+        assert_eq!(blocks_received.len(), 5);
+        assert_eq!(
+            blocks_received.last().unwrap().0.to_string(),
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_chunk_size() {
+        FileAdder::builder().with_chunk_size(0);
+    }
+
     #[test]
     fn empty_file() {
         let blocks = FileAdder::default().collect_blocks(b"", 0);
@@ -870,4 +1380,343 @@ mod tests {
 
         assert_eq!(blocks_count, 175);
     }
+
+    #[test]
+    fn metadata_on_single_block_root() {
+        let metadata = Metadata::default()
+            .with_mode(0o644)
+            .with_mtime(1_600_000_000, 5);
+
+        let blocks = FileAdder::builder()
+            .with_metadata(metadata.clone())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(read_metadata(&blocks[0].1), metadata);
+    }
+
+    #[test]
+    fn metadata_on_multi_block_root() {
+        let metadata = Metadata::default().with_mode(0o600).with_mtime(-1, 0);
+
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_metadata(metadata.clone())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        let (root, leaves) = blocks.split_last().unwrap();
+
+        // "fo", "ob", "ar", "\n" are not changed by the metadata
+        assert_eq!(leaves.len(), 4);
+        assert!(leaves
+            .iter()
+            .all(|(_, block)| read_metadata(block).is_empty()));
+
+        assert_eq!(read_metadata(&root.1), metadata);
+    }
+
+    #[test]
+    fn metadata_on_single_whole_chunk_root() {
+        let metadata = Metadata::default().with_mode(0o755);
+        let content = b"foobar\n";
+
+        let mut adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(content.len()))
+            .with_metadata(metadata.clone())
+            .build();
+
+        // held back as it could be the root
+        let (pushed, written) = adder.push(content);
+        assert_eq!(written, content.len());
+        assert_eq!(pushed.count(), 0);
+
+        // a single leaf holding the metadata like with go-ipfs
+        let last = adder.finish().collect::<Vec<_>>();
+        assert_eq!(last.len(), 1);
+
+        let root = crate::pb::FlatUnixFs::try_from(last[0].1.as_slice()).unwrap();
+        assert!(root.links.is_empty());
+        assert_eq!(root.data.Data.as_deref(), Some(&content[..]));
+        assert_eq!(Metadata::from(&root.data), metadata);
+    }
+
+    #[test]
+    fn held_back_chunk_flushed_when_more_follows() {
+        let metadata = Metadata::default().with_mode(0o755);
+        let content = b"foobar\n";
+
+        let mut adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(content.len()))
+            .with_metadata(metadata.clone())
+            .build();
+
+        let (pushed, _) = adder.push(content);
+        assert_eq!(pushed.count(), 0);
+
+        let (pushed, written) = adder.push(b"x");
+        let pushed = pushed.collect::<Vec<_>>();
+        assert_eq!(written, 1);
+
+        // same as the default file without metadata
+        assert_eq!(pushed.len(), 1);
+        assert_eq!(
+            pushed[0].0.to_string(),
+            "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
+        );
+
+        let last = adder.finish().collect::<Vec<_>>();
+        let root = crate::pb::FlatUnixFs::try_from(last.last().unwrap().1.as_slice()).unwrap();
+        assert_eq!(root.links.len(), 2);
+        assert_eq!(Metadata::from(&root.data), metadata);
+    }
+
+    #[test]
+    fn raw_leaves_single_chunk() {
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4"
+        );
+        assert_eq!(blocks[0].1, b"foobar\n");
+    }
+
+    #[test]
+    fn raw_leaves_multi_block() {
+        use crate::file::visit::IdleFileVisit;
+
+        let content = b"foobar\n";
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .build();
+
+        let mut blocks = FakeBlockstore::default();
+        let received = adder.collect_blocks(content, 0);
+        let (root, leaves) = received.split_last().unwrap();
+
+        for (cid, block) in leaves {
+            assert_eq!(blocks.insert_raw(block), *cid);
+        }
+        assert_eq!(blocks.insert_v0(&root.1), root.0);
+
+        let (bytes, file_size, _, mut visit) = IdleFileVisit::default()
+            .start(blocks.get_by_cid(&root.0))
+            .unwrap();
+
+        assert!(bytes.is_empty());
+        assert_eq!(file_size, content.len() as u64);
+
+        let mut read_back = Vec::new();
+        while let Some(v) = visit {
+            let (first, _) = v.pending_links();
+            let (bytes, next) = v
+                .continue_walk(blocks.get_by_cid(first), &mut None)
+                .unwrap();
+            read_back.extend_from_slice(bytes);
+            visit = next;
+        }
+
+        assert_eq!(read_back, content);
+    }
+
+    #[test]
+    fn raw_leaves_with_metadata() {
+        let metadata = Metadata::default().with_mode(0o644);
+
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .with_metadata(metadata.clone())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        // the metadata cannot be written to a raw block
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0.version(), cid::Version::V0);
+        assert_eq!(read_metadata(&blocks[0].1), metadata);
+    }
+
+    #[test]
+    fn cid_v1_with_blake3() {
+        use crate::{CidOptions, HashFunction};
+
+        let cid_options = CidOptions::v1(HashFunction::Blake3_256);
+
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_cid_options(cid_options)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 5);
+
+        for (cid, block) in &blocks {
+            assert_eq!(*cid, cid_options.dag_pb(block));
+        }
+
+        let (root, leaves) = blocks.split_last().unwrap();
+        let root = crate::pb::FlatUnixFs::try_from(root.1.as_slice()).unwrap();
+
+        let links = root
+            .links
+            .iter()
+            .map(|link| Cid::try_from(link.Hash.as_deref().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let expected = leaves
+            .iter()
+            .map(|(cid, _)| cid.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(links, expected);
+    }
+
+    #[test]
+    fn cid_v1_raw_leaves() {
+        use crate::{CidOptions, HashFunction};
+
+        let blocks = FileAdder::builder()
+            .with_cid_options(CidOptions::v1(HashFunction::Sha2_256))
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        // same as with the default options as raw leaves are always Cid version 1
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn push_parallel_matches_push() {
+        let content = crate::test_support::pseudo_random(10_000);
+
+        for &raw_leaves in &[false, true] {
+            let builder = || {
+                FileAdder::builder()
+                    .with_chunker(Chunker::Size(37))
+                    .with_collector(BalancedCollector::with_branching_factor(4))
+                    .with_raw_leaves(raw_leaves)
+            };
+
+            let expected = builder().build().collect_blocks(&content, 0);
+
+            let mut adder = builder().build();
+            let mut blocks = Vec::new();
+
+            // uneven slices to have partially buffered chunks between the pushes
+            for slice in content.chunks(1000) {
+                let (completed, consumed) = adder.push_parallel(slice);
+                assert_eq!(consumed, slice.len());
+                blocks.extend(completed);
+            }
+            blocks.extend(adder.finish());
+
+            assert_eq!(blocks, expected);
+        }
+    }
+
+    #[test]
+    fn narrow_branching_factor() {
+        let blocks = FileAdder::builder()
+            .with_chunk_size(1)
+            .with_branching_factor(2)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        for (_, block) in &blocks {
+            let flat = crate::pb::FlatUnixFs::try_from(block.as_slice()).unwrap();
+            assert!(flat.links.len() <= 2, "{:?}", flat.links);
+        }
+
+        // with the default branching factor, the root would link to all seven leaves
+        let (_, root) = blocks.last().unwrap();
+        let root = crate::pb::FlatUnixFs::try_from(root.as_slice()).unwrap();
+        assert_eq!(root.links.len(), 2);
+        assert_eq!(root.data.filesize, Some(7));
+    }
+
+    #[test]
+    fn trickle_multi_block_file() {
+        let blocks = FakeBlockstore::with_fixtures();
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_collector(TrickleCollector::default())
+            .build();
+
+        let blocks_received = adder.collect_blocks(b"foobar\n", 0);
+
+        let root = "QmWfQ48ChJUj4vWKFsUDe4646xCBmXgdmNfhjz9T7crywd";
+        let (cid, block) = blocks_received.last().unwrap();
+        assert_eq!(cid.to_string(), root);
+        assert_eq!(block.as_slice(), blocks.get_by_str(root));
+
+        // the leaves are of type raw
+        for (cid, block) in &blocks_received[..4] {
+            assert_eq!(block.as_slice(), blocks.get_by_cid(cid));
+        }
+        assert_eq!(blocks_received.len(), 5);
+    }
+
+    #[test]
+    fn trickle_single_block_file_has_link_block_root() {
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 2);
+
+        let (_, root) = blocks.last().unwrap();
+        let root = crate::pb::FlatUnixFs::try_from(root.as_slice()).unwrap();
+        assert_eq!(root.links.len(), 1);
+        assert_eq!(root.data.filesize, Some(7));
+    }
+
+    #[test]
+    fn trickle_empty_file() {
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(b"", 0);
+
+        // same as `empty_file`
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+    }
+
+    #[test]
+    fn trickle_repeats_subtrees_of_each_depth() {
+        let blocks = FileAdder::builder()
+            .with_chunk_size(1)
+            .with_collector(TrickleCollector::with_max_links(2))
+            .build()
+            .collect_blocks(b"hello world", 0);
+
+        // the root links to two leaves, four subtrees of depth one, and then to the incomplete
+        // subtree of depth two holding the last leaf
+        let (_, root) = blocks.last().unwrap();
+        let root = crate::pb::FlatUnixFs::try_from(root.as_slice()).unwrap();
+        assert_eq!(root.data.blocksizes, &[1, 1, 2, 2, 2, 2, 1]);
+        assert_eq!(root.data.filesize, Some(11));
+
+        // 11 leaves, 4 complete and 1 incomplete subtree, and the root
+        assert_eq!(blocks.len(), 17);
+    }
+
+    fn read_metadata(block: &[u8]) -> Metadata {
+        let flat = crate::pb::FlatUnixFs::try_from(block).unwrap();
+        Metadata::from(&flat.data)
+    }
 }
@@ -0,0 +1,419 @@
+use super::{Chunker, Collector, FileAdder, FileAdderBuilder, Link};
+use crate::file::{FileError, FileReadFailed};
+use crate::pb::{FlatUnixFs, UnixFsType};
+use crate::{InvalidCidInLink, Metadata};
+use cid::Cid;
+use core::convert::TryFrom;
+use core::fmt;
+
+/// The existing file, read by `read_existing`.
+struct Existing {
+    /// The leaves which can be reused as is.
+    leaves: Vec<Link>,
+    /// The content of the last leaf, when it needs to be chunked again with the appended bytes.
+    tail: Option<Vec<u8>>,
+    metadata: Metadata,
+}
+
+impl FileAdderBuilder {
+    /// Returns a new `FileAdder` which continues the existing file at `root`, loading the blocks
+    /// of the existing file through the `fetch` callback. The bytes pushed to the returned adder
+    /// are appended to the existing content, and finishing it produces the root of the longer
+    /// file.
+    ///
+    /// The link blocks of the existing file and its last leaf are loaded, but the other leaves are
+    /// reused without loading them. The last leaf is loaded to find the depth of the tree, and
+    /// unless it is a full chunk of the size chunker, its content is chunked again with the
+    /// appended bytes. The link blocks are created again, and the ones completed while resuming
+    /// are returned along with the adder; with the same options as the existing file was created
+    /// with, these are the same blocks as the existing ones.
+    ///
+    /// Only the balanced layout, which is the default, is supported: the builder must use the
+    /// balanced collector, and an existing file with the leaves at different depths, such as a
+    /// trickle file of more than one link block, is refused with [`AppendFailed::Unbalanced`].
+    /// The metadata of the existing root is kept unless other metadata has been configured.
+    pub fn build_appending<F, B, E>(
+        self,
+        root: &Cid,
+        mut fetch: F,
+    ) -> Result<(FileAdder, Vec<(Cid, Vec<u8>)>), AppendFailed<E>>
+    where
+        F: FnMut(&Cid) -> Result<B, E>,
+        B: AsRef<[u8]>,
+    {
+        if let Collector::Trickle(_) = self.collector {
+            return Err(AppendFailed::UnsupportedCollector);
+        }
+
+        let Existing {
+            mut leaves,
+            mut tail,
+            metadata,
+        } = read_existing(root, &mut fetch)?;
+
+        let full_chunk = match self.chunker {
+            Chunker::Size(size) => Some(size as u64),
+            // the content defined chunkers could find a different boundary with more content
+            _ => None,
+        };
+
+        if let Some(last) = leaves.last() {
+            if Some(last.file_size) == full_chunk {
+                tail = None;
+            } else {
+                leaves.pop();
+            }
+        }
+
+        let metadata = if self.metadata.is_empty() {
+            metadata
+        } else {
+            self.metadata.clone()
+        };
+
+        let mut adder = self.with_metadata(metadata).build();
+        let mut blocks = Vec::new();
+
+        // the same steps as with `FileAdder::push` for each of the leaves
+        for link in leaves {
+            adder.unflushed_links.push(link);
+            blocks.extend(adder.flush_buffered_links(false));
+        }
+
+        if let Some(tail) = tail {
+            let mut pushed = 0;
+            while pushed < tail.len() {
+                let (completed, consumed) = adder.push(&tail[pushed..]);
+                blocks.extend(completed);
+                pushed += consumed;
+            }
+        }
+
+        Ok((adder, blocks))
+    }
+}
+
+fn read_existing<F, B, E>(root: &Cid, fetch: &mut F) -> Result<Existing, AppendFailed<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    let block = fetch(root).map_err(AppendFailed::Fetch)?;
+
+    if u64::from(root.codec()) == crate::RAW_CODEC {
+        return Ok(Existing {
+            leaves: Vec::new(),
+            tail: Some(block.as_ref().to_vec()),
+            metadata: Metadata::default(),
+        });
+    }
+
+    let block = block.as_ref().to_vec();
+    let flat = parse_file(&block)?;
+    let metadata = Metadata::from(&flat.data);
+
+    if flat.links.is_empty() {
+        // a single block file; the root cannot be reused as a leaf as it could have metadata
+        return Ok(Existing {
+            leaves: Vec::new(),
+            tail: Some(flat.data.Data.as_deref().unwrap_or_default().to_vec()),
+            metadata,
+        });
+    }
+
+    // in a balanced tree all of the leaves are at the same depth; find it and the content of the
+    // last leaf by following the last links
+    let mut depth = 0;
+    let mut current = block.clone();
+
+    let tail = loop {
+        let flat = parse_file(&current)?;
+        let nth = flat.links.len().saturating_sub(1);
+        let last = match flat.links.into_iter().last() {
+            Some(last) => to_cid(nth, last)?,
+            None => return Err(AppendFailed::Read(FileError::NoLinksNoContent.into())),
+        };
+
+        depth += 1;
+
+        let next = fetch(&last).map_err(AppendFailed::Fetch)?;
+
+        if u64::from(last.codec()) == crate::RAW_CODEC {
+            break next.as_ref().to_vec();
+        }
+
+        let flat = parse_file(next.as_ref())?;
+        if flat.links.is_empty() {
+            break flat.data.Data.as_deref().unwrap_or_default().to_vec();
+        }
+
+        current = next.as_ref().to_vec();
+    };
+
+    let mut leaves = Vec::new();
+    collect_leaves(&block, 0, depth, fetch, &mut leaves)?;
+
+    Ok(Existing {
+        leaves,
+        tail: Some(tail),
+        metadata,
+    })
+}
+
+/// Collects the links to the leaves at `depth`, loading only the link blocks above them.
+fn collect_leaves<F, B, E>(
+    block: &[u8],
+    level: usize,
+    depth: usize,
+    fetch: &mut F,
+    leaves: &mut Vec<Link>,
+) -> Result<(), AppendFailed<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    let flat = parse_file(block)?;
+
+    if flat.links.is_empty() {
+        return Err(AppendFailed::Unbalanced);
+    }
+
+    if flat.links.len() != flat.data.blocksizes.len() {
+        return Err(AppendFailed::Read(
+            FileError::LinksAndBlocksizesMismatch.into(),
+        ));
+    }
+
+    let blocksizes = flat.data.blocksizes;
+
+    for (nth, (link, file_size)) in flat.links.into_iter().zip(blocksizes).enumerate() {
+        let total_size = link.Tsize.unwrap_or(0);
+        let target = to_cid(nth, link)?;
+
+        if level + 1 == depth {
+            leaves.push(Link {
+                depth: 0,
+                target,
+                total_size,
+                file_size,
+            });
+        } else if u64::from(target.codec()) == crate::RAW_CODEC {
+            // a leaf above the depth of the last leaf
+            return Err(AppendFailed::Unbalanced);
+        } else {
+            let child = fetch(&target).map_err(AppendFailed::Fetch)?;
+            collect_leaves(child.as_ref(), level + 1, depth, fetch, leaves)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_file<E>(block: &[u8]) -> Result<FlatUnixFs<'_>, AppendFailed<E>> {
+    let flat = FlatUnixFs::try_from(block).map_err(|e| AppendFailed::Read(e.into()))?;
+
+    match flat.data.Type {
+        UnixFsType::File | UnixFsType::Raw => Ok(flat),
+        other => Err(AppendFailed::Read(FileReadFailed::UnexpectedType(
+            other.into(),
+        ))),
+    }
+}
+
+fn to_cid<E>(nth: usize, link: crate::pb::PBLink<'_>) -> Result<Cid, AppendFailed<E>> {
+    let hash = link.Hash.as_deref().unwrap_or_default();
+
+    Cid::try_from(hash).map_err(|e| {
+        AppendFailed::Read(FileReadFailed::InvalidCid(InvalidCidInLink::from((
+            nth, link, e,
+        ))))
+    })
+}
+
+/// Errors which can happen while resuming a file with [`FileAdderBuilder::build_appending`].
+#[derive(Debug)]
+pub enum AppendFailed<E> {
+    /// The `fetch` callback failed.
+    Fetch(E),
+    /// A block of the existing file could not be read.
+    Read(FileReadFailed),
+    /// The leaves of the existing file are not all at the same depth, so it was not created with
+    /// the balanced layout.
+    Unbalanced,
+    /// The builder was configured with a collector other than the balanced one, which is the only
+    /// one supported for appending.
+    UnsupportedCollector,
+}
+
+impl<E: fmt::Display> fmt::Display for AppendFailed<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use AppendFailed::*;
+
+        match self {
+            Fetch(e) => write!(fmt, "failed to fetch a block: {}", e),
+            Read(e) => write!(fmt, "failed to read the existing file: {}", e),
+            Unbalanced => write!(fmt, "existing file does not have the balanced layout"),
+            UnsupportedCollector => write!(fmt, "appending requires the balanced layout"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for AppendFailed<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use AppendFailed::*;
+
+        match self {
+            Fetch(e) => Some(e),
+            Read(e) => Some(e),
+            Unbalanced | UnsupportedCollector => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppendFailed;
+    use crate::file::adder::{FileAdder, TrickleCollector};
+    use crate::Metadata;
+    use cid::Cid;
+    use std::collections::HashMap;
+    use std::convert::{Infallible, TryFrom};
+
+    fn builder(raw_leaves: bool) -> crate::file::adder::FileAdderBuilder {
+        FileAdder::builder()
+            .with_chunk_size(2)
+            .with_branching_factor(3)
+            .with_raw_leaves(raw_leaves)
+    }
+
+    fn add(blocks: &mut HashMap<Cid, Vec<u8>>, adder: FileAdder, content: &[u8]) -> Cid {
+        let mut last = None;
+        for (cid, block) in adder.collect_blocks(content, 0) {
+            blocks.insert(cid.clone(), block);
+            last = Some(cid);
+        }
+        last.unwrap()
+    }
+
+    fn append(
+        blocks: &mut HashMap<Cid, Vec<u8>>,
+        raw_leaves: bool,
+        root: &Cid,
+        content: &[u8],
+    ) -> (Cid, Vec<Cid>) {
+        let mut fetched = Vec::new();
+
+        let (adder, resumed) = builder(raw_leaves)
+            .build_appending(root, |cid: &Cid| {
+                fetched.push(cid.clone());
+                Ok::<_, Infallible>(blocks[cid].clone())
+            })
+            .unwrap();
+
+        blocks.extend(resumed);
+        (add(blocks, adder, content), fetched)
+    }
+
+    #[test]
+    fn append_after_short_leaf() {
+        for &raw_leaves in &[false, true] {
+            let mut blocks = HashMap::new();
+
+            let root = add(&mut blocks, builder(raw_leaves).build(), b"foobar\nf");
+            let (appended, _) = append(&mut blocks, raw_leaves, &root, b"oobar\n");
+
+            let expected = add(
+                &mut HashMap::new(),
+                builder(raw_leaves).build(),
+                b"foobar\nfoobar\n",
+            );
+
+            assert_eq!(appended, expected, "raw_leaves: {}", raw_leaves);
+        }
+    }
+
+    #[test]
+    fn append_after_full_leaf() {
+        let mut blocks = HashMap::new();
+
+        let root = add(&mut blocks, builder(false).build(), b"foobar\nfooba");
+        let (appended, fetched) = append(&mut blocks, false, &root, b"r\n");
+
+        let expected = add(
+            &mut HashMap::new(),
+            builder(false).build(),
+            b"foobar\nfoobar\n",
+        );
+
+        assert_eq!(appended, expected);
+
+        // only the link blocks and the last leaf are loaded, none of the other 5 leaves
+        let leaves_fetched = fetched
+            .iter()
+            .filter(|cid| {
+                let flat = crate::pb::FlatUnixFs::try_from(&blocks[*cid][..]).unwrap();
+                flat.links.is_empty()
+            })
+            .count();
+        assert_eq!(leaves_fetched, 1);
+    }
+
+    #[test]
+    fn append_to_single_block_file() {
+        let metadata = Metadata::default().with_mode(0o644);
+        let mut blocks = HashMap::new();
+
+        let root = add(
+            &mut blocks,
+            builder(false).with_metadata(metadata.clone()).build(),
+            b"f",
+        );
+        let (appended, _) = append(&mut blocks, false, &root, b"oobar\n");
+
+        let expected = add(
+            &mut HashMap::new(),
+            builder(false).with_metadata(metadata).build(),
+            b"foobar\n",
+        );
+
+        assert_eq!(appended, expected);
+    }
+
+    #[test]
+    fn trickle_files_are_refused() {
+        for &raw_leaves in &[false, true] {
+            let mut blocks = HashMap::new();
+
+            // seven leaves, of which the first three are linked from the root and the rest from
+            // the subtrees below it
+            let trickle = builder(raw_leaves)
+                .with_collector(TrickleCollector::with_max_links(3))
+                .build();
+            let root = add(&mut blocks, trickle, b"foobar\nfoobar\n");
+
+            let e = builder(raw_leaves)
+                .build_appending(&root, |cid: &Cid| Ok::<_, Infallible>(blocks[cid].clone()))
+                .unwrap_err();
+
+            assert!(
+                matches!(e, AppendFailed::Unbalanced),
+                "raw_leaves: {}: {:?}",
+                raw_leaves,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn trickle_collector_is_refused() {
+        let mut blocks = HashMap::new();
+        let root = add(&mut blocks, builder(false).build(), b"foobar\n");
+
+        let e = builder(false)
+            .with_collector(TrickleCollector::default())
+            .build_appending(&root, |cid: &Cid| Ok::<_, Infallible>(blocks[cid].clone()))
+            .unwrap_err();
+
+        assert!(matches!(e, AppendFailed::UnsupportedCollector), "{:?}", e);
+    }
+}
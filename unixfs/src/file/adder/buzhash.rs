@@ -0,0 +1,196 @@
+use core::fmt;
+
+/// Size of the rolling window in bytes. Being 32 allows removing the byte sliding out of the
+/// window without any bookkeeping, as rotating a `u32` by 32 is the identity.
+const WINDOW_SIZE: usize = 32;
+
+/// Content defined chunker using a cyclic polynomial (buzhash) rolling hash over a 32 byte window,
+/// using the same size limits and boundary condition as the go-ipfs `buzhash` chunker.
+///
+/// Note: the per-byte hash values are generated from a fixed seed and are **not** the same as in
+/// go-ipfs, so the resulting chunk boundaries and Cids will differ from go-ipfs.
+#[derive(Clone)]
+pub struct BuzhashChunker {
+    min: usize,
+    max: usize,
+    mask: u32,
+    table: Box<[u32; 256]>,
+    state: State,
+}
+
+#[derive(Clone)]
+struct State {
+    window: [u8; WINDOW_SIZE],
+    wpos: usize,
+    hash: u32,
+    /// Amount of bytes in the current chunk.
+    count: usize,
+}
+
+impl fmt::Debug for BuzhashChunker {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "BuzhashChunker {{ min: {}, max: {}, mask: {:#x} }}",
+            self.min, self.max, self.mask
+        )
+    }
+}
+
+impl Default for BuzhashChunker {
+    /// Returns a chunker with the go-ipfs limits: chunks are at least 128 KiB and at most 512 KiB,
+    /// with boundaries where the lowest 17 bits of the hash are zero.
+    fn default() -> Self {
+        Self::new(128 * 1024, 512 * 1024, 17)
+    }
+}
+
+impl BuzhashChunker {
+    /// Creates a chunker producing chunks from `min` to `max` bytes, cutting the chunk where the
+    /// lowest `mask_bits` of the hash are zero.
+    ///
+    /// # Panics
+    ///
+    /// When `min` is smaller than the 32 byte window or larger than `max`, or if the `mask_bits` is
+    /// not less than 32.
+    pub fn new(min: usize, max: usize, mask_bits: u32) -> Self {
+        assert!(
+            WINDOW_SIZE <= min && min <= max,
+            "invalid buzhash chunker sizes: min={}, max={}",
+            min,
+            max
+        );
+        assert!(mask_bits < 32, "invalid buzhash mask bits: {}", mask_bits);
+
+        BuzhashChunker {
+            min,
+            max,
+            mask: (1 << mask_bits) - 1,
+            table: Box::new(byte_table()),
+            state: State {
+                window: [0; WINDOW_SIZE],
+                wpos: 0,
+                hash: 0,
+                count: 0,
+            },
+        }
+    }
+
+    /// Returns the largest chunk size this chunker can produce.
+    pub fn max_size(&self) -> usize {
+        self.max
+    }
+
+    /// See `Chunker::accept`.
+    pub(super) fn accept<'a>(&mut self, input: &'a [u8], buffered: &[u8]) -> (&'a [u8], bool) {
+        debug_assert_eq!(self.state.count, buffered.len());
+
+        // bytes before this do not need to be hashed as a chunk cannot end before `min`
+        let skipped = self.min - WINDOW_SIZE;
+
+        for (i, &b) in input.iter().enumerate() {
+            let state = &mut self.state;
+
+            if state.count < skipped {
+                state.count += 1;
+                continue;
+            }
+
+            let out = state.window[state.wpos];
+            state.window[state.wpos] = b;
+            state.wpos = (state.wpos + 1) % WINDOW_SIZE;
+
+            // the first window is filled from zeroes, which for a hash of zero were never added
+            let out = if state.count < self.min {
+                0
+            } else {
+                self.table[out as usize]
+            };
+
+            state.hash = state.hash.rotate_left(1) ^ out ^ self.table[b as usize];
+            state.count += 1;
+
+            if state.count < self.min {
+                continue;
+            }
+
+            if state.hash & self.mask == 0 || state.count >= self.max {
+                self.state = State {
+                    window: [0; WINDOW_SIZE],
+                    wpos: 0,
+                    hash: 0,
+                    count: 0,
+                };
+                return (&input[..=i], true);
+            }
+        }
+
+        (input, false)
+    }
+}
+
+/// Generates the per byte hash values with splitmix64.
+fn byte_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut x = 0x6275_7a68_6173_6821u64;
+
+    for value in table.iter_mut() {
+        x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        *value = (z >> 32) as u32;
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BuzhashChunker;
+    use crate::test_support::pseudo_random;
+
+    #[test]
+    fn chunk_sizes_are_within_bounds() {
+        let data = pseudo_random(64 * 1024);
+        let mut chunker = BuzhashChunker::new(512, 2048, 10);
+
+        let mut offset = 0;
+        let mut buffered = 0;
+        let mut chunks = Vec::new();
+
+        while offset < data.len() {
+            let (accepted, ready) =
+                chunker.accept(&data[offset..], &data[offset - buffered..offset]);
+            offset += accepted.len();
+            buffered += accepted.len();
+
+            if ready {
+                chunks.push(buffered);
+                buffered = 0;
+            }
+        }
+
+        assert!(
+            chunks.iter().all(|len| (512..=2048).contains(len)),
+            "{:?}",
+            chunks
+        );
+        assert!(chunks.iter().any(|&len| len < 2048), "{:?}", chunks);
+    }
+
+    #[test]
+    fn hash_is_rolling() {
+        // the value after the window has been filled depends only on the last 32 bytes
+        let data = pseudo_random(200);
+        let mut a = BuzhashChunker::new(64, 1024, 31);
+        let mut b = BuzhashChunker::new(64, 1024, 31);
+
+        // both have hashed more than a full window, ending at the same byte
+        a.accept(&data[..100], &[]);
+        b.accept(&data[10..100], &[]);
+
+        assert_eq!(a.state.hash, b.state.hash);
+    }
+}
@@ -0,0 +1,299 @@
+use core::fmt;
+
+/// Irreducible polynomial used by go-ipfs (`IpfsRabinPoly`).
+const POLYNOMIAL: u64 = 0x3DF3_05DF_B2A8_05;
+
+/// Size of the rolling window in bytes, as in go-ipfs.
+const WINDOW_SIZE: usize = 16;
+
+/// Content defined chunker using a Rabin fingerprint over a rolling window, compatible with the
+/// go-ipfs `rabin` chunker. Chunk boundaries are found where the lowest `log2(avg)` bits of the
+/// fingerprint are zero, which makes the boundaries stay in place even if bytes are inserted or
+/// removed elsewhere in the file.
+#[derive(Clone)]
+pub struct RabinChunker {
+    min: usize,
+    avg: usize,
+    max: usize,
+    split_mask: u64,
+    pol_shift: u32,
+    tables: Box<Tables>,
+    state: State,
+}
+
+#[derive(Clone)]
+struct Tables {
+    /// Values for sliding out bytes from the window.
+    out: [u64; 256],
+    /// Values for reducing the digest modulo the polynomial.
+    modulo: [u64; 256],
+}
+
+#[derive(Clone)]
+struct State {
+    window: [u8; WINDOW_SIZE],
+    wpos: usize,
+    digest: u64,
+    /// Amount of bytes in the current chunk.
+    count: usize,
+}
+
+impl fmt::Debug for RabinChunker {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "RabinChunker {{ min: {}, avg: {}, max: {} }}",
+            self.min, self.avg, self.max
+        )
+    }
+}
+
+impl Default for RabinChunker {
+    /// Returns the chunker go-ipfs uses for plain `rabin`, with 256 KiB average chunk size.
+    fn default() -> Self {
+        Self::with_avg(256 * 1024)
+    }
+}
+
+impl RabinChunker {
+    /// Creates a chunker with the given average chunk size, and minimum of a third and maximum of
+    /// one and a half of the average, like go-ipfs `rabin-{avg}`.
+    pub fn with_avg(avg: usize) -> Self {
+        Self::new(avg / 3, avg, avg + avg / 2)
+    }
+
+    /// Creates a chunker with the given minimum, average and maximum chunk sizes, like go-ipfs
+    /// `rabin-{min}-{avg}-{max}`. The average is rounded down to the closest power of two.
+    ///
+    /// # Panics
+    ///
+    /// When the sizes are not in increasing order or when the minimum is smaller than the 16 byte
+    /// window.
+    pub fn new(min: usize, avg: usize, max: usize) -> Self {
+        assert!(
+            WINDOW_SIZE <= min && min <= avg && avg <= max,
+            "invalid rabin chunker sizes: min={}, avg={}, max={}",
+            min,
+            avg,
+            max
+        );
+
+        let bits = deg(avg as u64);
+        let degree = deg(POLYNOMIAL);
+
+        let mut ret = RabinChunker {
+            min,
+            avg,
+            max,
+            split_mask: (1u64 << bits) - 1,
+            pol_shift: degree - 8,
+            tables: Box::new(Tables::new(degree)),
+            state: State {
+                window: [0; WINDOW_SIZE],
+                wpos: 0,
+                digest: 0,
+                count: 0,
+            },
+        };
+
+        ret.reset();
+        ret
+    }
+
+    /// Returns the largest chunk size this chunker can produce.
+    pub fn max_size(&self) -> usize {
+        self.max
+    }
+
+    /// See `Chunker::accept`.
+    pub(super) fn accept<'a>(&mut self, input: &'a [u8], buffered: &[u8]) -> (&'a [u8], bool) {
+        debug_assert_eq!(self.state.count, buffered.len());
+
+        // bytes before this do not need to be hashed as a chunk cannot end before `min`
+        let skipped = self.min - WINDOW_SIZE;
+
+        for (i, &b) in input.iter().enumerate() {
+            let state = &mut self.state;
+
+            if state.count < skipped {
+                state.count += 1;
+                continue;
+            }
+
+            let out = state.window[state.wpos];
+            state.window[state.wpos] = b;
+            state.digest ^= self.tables.out[out as usize];
+            state.wpos = (state.wpos + 1) % WINDOW_SIZE;
+            state.digest = self.append(state.digest, b);
+            state.count += 1;
+
+            if state.count < self.min {
+                continue;
+            }
+
+            if state.digest & self.split_mask == 0 || state.count >= self.max {
+                self.reset();
+                return (&input[..=i], true);
+            }
+        }
+
+        (input, false)
+    }
+
+    fn append(&self, digest: u64, b: u8) -> u64 {
+        let index = (digest >> self.pol_shift) as usize;
+        ((digest << 8) | u64::from(b)) ^ self.tables.modulo[index]
+    }
+
+    fn reset(&mut self) {
+        self.state.window = [0; WINDOW_SIZE];
+        self.state.wpos = 0;
+        self.state.digest = 0;
+        self.state.count = 0;
+
+        // go-ipfs starts every chunk by sliding in a single byte of value one
+        self.state.window[0] = 1;
+        self.state.wpos = 1;
+        self.state.digest = self.append(0, 1);
+    }
+}
+
+impl Tables {
+    fn new(degree: u32) -> Self {
+        let mut out = [0u64; 256];
+        let mut modulo = [0u64; 256];
+
+        for b in 0..256u64 {
+            // out[b] = hash of b followed by WINDOW_SIZE - 1 zero bytes, which is the value to
+            // cancel out the byte sliding out of the window
+            let mut h = append_byte(0, b as u8);
+            for _ in 0..WINDOW_SIZE - 1 {
+                h = append_byte(h, 0);
+            }
+            out[b as usize] = h;
+
+            // modulo[b] = (b * x^k mod pol) | (b * x^k) where the latter cancels out the top
+            // bits so that a single xor is enough to reduce the digest
+            modulo[b as usize] = pol_mod(b << degree, POLYNOMIAL) | (b << degree);
+        }
+
+        Tables { out, modulo }
+    }
+}
+
+fn append_byte(hash: u64, b: u8) -> u64 {
+    pol_mod((hash << 8) | u64::from(b), POLYNOMIAL)
+}
+
+/// Degree of the polynomial; the polynomial must not be zero.
+fn deg(pol: u64) -> u32 {
+    63 - pol.leading_zeros()
+}
+
+/// Remainder of the polynomial division over GF(2).
+fn pol_mod(mut x: u64, d: u64) -> u64 {
+    let d_deg = deg(d);
+
+    while x != 0 && deg(x) >= d_deg {
+        x ^= d << (deg(x) - d_deg);
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RabinChunker;
+    use crate::test_support::pseudo_random;
+
+    #[test]
+    fn chunk_sizes_are_within_bounds() {
+        let data = pseudo_random(64 * 1024);
+        let chunks = chunk_all(RabinChunker::new(512, 1024, 2048), &data, data.len());
+
+        assert_eq!(chunks.iter().sum::<usize>(), data.len());
+
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(*last <= 2048);
+        assert!(
+            rest.iter().all(|len| (512..=2048).contains(len)),
+            "{:?}",
+            rest
+        );
+        // the content is random enough not to be cut only at the max
+        assert!(rest.iter().any(|&len| len < 2048), "{:?}", rest);
+    }
+
+    #[test]
+    fn boundaries_do_not_depend_on_push_sizes() {
+        let data = pseudo_random(16 * 1024);
+        let expected = chunk_all(RabinChunker::new(64, 256, 1024), &data, data.len());
+
+        for amt in &[1, 7, 100, 1023] {
+            let actual = chunk_all(RabinChunker::new(64, 256, 1024), &data, *amt);
+            assert_eq!(actual, expected, "amt: {}", amt);
+        }
+    }
+
+    #[test]
+    fn boundaries_resync_after_prefix() {
+        let data = pseudo_random(32 * 1024);
+        let mut prefixed = b"a few additional bytes".to_vec();
+        prefixed.extend_from_slice(&data);
+
+        let original = boundaries(&chunk_all(RabinChunker::new(64, 256, 1024), &data, 4096));
+        let shifted = boundaries(&chunk_all(
+            RabinChunker::new(64, 256, 1024),
+            &prefixed,
+            4096,
+        ));
+
+        let prefix_len = prefixed.len() - data.len();
+        let shifted = shifted
+            .into_iter()
+            .map(|end| end - prefix_len)
+            .collect::<Vec<_>>();
+
+        let common = original.iter().filter(|end| shifted.contains(end)).count();
+        assert!(
+            common * 2 > original.len(),
+            "most of the boundaries should be the same, {} out of {}",
+            common,
+            original.len()
+        );
+    }
+
+    fn boundaries(chunks: &[usize]) -> Vec<usize> {
+        chunks
+            .iter()
+            .scan(0, |acc, len| {
+                *acc += len;
+                Some(*acc)
+            })
+            .collect()
+    }
+
+    fn chunk_all(mut chunker: RabinChunker, data: &[u8], amt: usize) -> Vec<usize> {
+        let mut chunks = Vec::new();
+        let mut buffered = Vec::new();
+
+        for slice in data.chunks(amt) {
+            let mut slice = slice;
+            while !slice.is_empty() {
+                let (accepted, ready) = chunker.accept(slice, &buffered);
+                buffered.extend_from_slice(accepted);
+                slice = &slice[accepted.len()..];
+                if ready {
+                    chunks.push(buffered.len());
+                    buffered.clear();
+                }
+            }
+        }
+
+        if !buffered.is_empty() {
+            chunks.push(buffered.len());
+        }
+
+        chunks
+    }
+}
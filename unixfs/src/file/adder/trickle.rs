@@ -0,0 +1,205 @@
+use super::{render_and_hash, BalancedCollector, Link};
+use crate::pb::{FlatUnixFs, UnixFs, UnixFsType};
+use crate::{CidOptions, Metadata};
+use cid::Cid;
+use core::fmt;
+
+/// The number of subtrees of each depth under a link block, as in go-ipfs.
+const DEPTH_REPEAT: usize = 4;
+
+/// TrickleCollector creates trickle UnixFs trees, the same as go-ipfs `add --trickle`, which are
+/// optimized for reading the file sequentially from the start. Each link block first links to
+/// leaves, and then to `DEPTH_REPEAT` subtrees of each increasing depth, the root having no limit
+/// on the depth.
+///
+/// Unlike with the balanced layout, the leaves are UnixFS `Raw` blocks (unless raw leaves are
+/// used) and the root is always a link block, even for a file of a single chunk.
+#[derive(Clone)]
+pub struct TrickleCollector {
+    max_links: usize,
+    /// The link blocks being filled, from the root to the one the next leaf goes to.
+    stack: Vec<Frame>,
+}
+
+#[derive(Clone)]
+struct Frame {
+    /// The depth of the subtree rooted at this link block, or `None` for the root.
+    max_depth: Option<usize>,
+    links: Vec<Link>,
+    /// Zero while linking to leaves, after which the depth of the subtrees being linked to.
+    depth: usize,
+    /// The number of subtrees of `depth` already linked to.
+    repeat: usize,
+}
+
+impl Frame {
+    fn new(max_depth: Option<usize>) -> Self {
+        Frame {
+            max_depth,
+            links: Vec::new(),
+            depth: 0,
+            repeat: 0,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.max_depth
+            .map(|max_depth| self.depth >= max_depth)
+            .unwrap_or(false)
+    }
+
+    fn push_subtree(&mut self, link: Link) {
+        self.links.push(link);
+        self.repeat += 1;
+
+        if self.repeat == DEPTH_REPEAT {
+            self.depth += 1;
+            self.repeat = 0;
+        }
+    }
+}
+
+impl fmt::Debug for TrickleCollector {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "TrickleCollector {{ max_links: {} }}", self.max_links)
+    }
+}
+
+impl Default for TrickleCollector {
+    /// Returns a default collector which matches go-ipfs 0.6, using the same number of links per
+    /// link block as the default balanced collector.
+    fn default() -> Self {
+        Self::with_max_links(174)
+    }
+}
+
+impl TrickleCollector {
+    /// Configure Trickle collector with at most `max_links` leaves per link block.
+    pub fn with_max_links(max_links: usize) -> Self {
+        assert!(max_links > 0);
+
+        Self {
+            max_links,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Links all of the `pending` leaves into the tree, returning the link blocks completed by
+    /// them. When finishing, as signalled by `root_metadata` being `Some`, the incomplete link
+    /// blocks are completed as well, with the root block containing the metadata, and the link to
+    /// the root is left in `pending`.
+    pub(super) fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        root_metadata: Option<&Metadata>,
+        cid_options: &CidOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
+        let mut ret = Vec::new();
+
+        for leaf in pending.drain(..) {
+            debug_assert_eq!(leaf.depth, 0);
+            self.push_leaf(leaf, cid_options, &mut ret);
+        }
+
+        if let Some(metadata) = root_metadata {
+            if self.stack.is_empty() {
+                // an empty file is still a link block, just without any links
+                self.stack.push(Frame::new(None));
+            }
+
+            while let Some(frame) = self.stack.pop() {
+                let metadata = Some(metadata).filter(|_| self.stack.is_empty());
+                let link = Self::render(frame.links, metadata, cid_options, &mut ret);
+
+                match self.stack.last_mut() {
+                    Some(parent) => parent.links.push(link),
+                    None => pending.push(link),
+                }
+            }
+        }
+
+        ret
+    }
+
+    fn push_leaf(&mut self, leaf: Link, cid_options: &CidOptions, ret: &mut Vec<(Cid, Vec<u8>)>) {
+        if self.stack.is_empty() {
+            self.stack.push(Frame::new(None));
+        }
+
+        // descend into new subtrees until reaching a link block which still links to leaves
+        loop {
+            let top = self.stack.last().expect("root was pushed");
+            if top.depth == 0 {
+                break;
+            }
+            let depth = top.depth;
+            self.stack.push(Frame::new(Some(depth)));
+        }
+
+        let top = self.stack.last_mut().expect("root was pushed");
+        top.links.push(leaf);
+
+        if top.links.len() == self.max_links {
+            top.depth = 1;
+        }
+
+        while self.stack.last().map(Frame::is_complete).unwrap_or(false) {
+            let frame = self.stack.pop().expect("just checked");
+            let link = Self::render(frame.links, None, cid_options, ret);
+
+            self.stack
+                .last_mut()
+                .expect("the root is never complete")
+                .push_subtree(link);
+        }
+    }
+
+    /// Renders a link block of the `links`, returning the link to it.
+    fn render(
+        links: Vec<Link>,
+        root_metadata: Option<&Metadata>,
+        cid_options: &CidOptions,
+        ret: &mut Vec<(Cid, Vec<u8>)>,
+    ) -> Link {
+        let mut pb_links = Vec::with_capacity(links.len());
+        let mut blocksizes = Vec::with_capacity(links.len());
+        let mut nested_size = 0;
+        let mut nested_total_size = 0;
+
+        for link in &links {
+            BalancedCollector::partition_link(
+                link,
+                &mut pb_links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+        }
+
+        let mut inner = FlatUnixFs {
+            links: pb_links,
+            data: UnixFs {
+                Type: UnixFsType::File,
+                filesize: Some(nested_size),
+                blocksizes,
+                ..Default::default()
+            },
+        };
+
+        if let Some(metadata) = root_metadata {
+            metadata.apply_to(&mut inner.data);
+        }
+
+        let (cid, vec) = render_and_hash(&inner, cid_options);
+
+        let link = Link {
+            depth: links.iter().map(|l| l.depth + 1).max().unwrap_or(1),
+            target: cid.clone(),
+            total_size: nested_total_size + vec.len() as u64,
+            file_size: nested_size,
+        };
+
+        ret.push((cid, vec));
+        link
+    }
+}
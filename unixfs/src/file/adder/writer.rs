@@ -0,0 +1,103 @@
+use super::FileAdder;
+use cid::Cid;
+use core::fmt;
+use std::io;
+
+/// [`std::io::Write`] adapter for [`FileAdder`], which hands over the completed blocks to the
+/// `sink` callback. This allows using `std::io::copy` to add a file instead of calling
+/// [`FileAdder::push`] in a loop.
+///
+/// Once all of the content has been written, the tree must be completed with
+/// [`FileAdderWriter::finish`].
+pub struct FileAdderWriter<F> {
+    adder: FileAdder,
+    sink: F,
+    last: Option<Cid>,
+}
+
+impl<F> fmt::Debug for FileAdderWriter<F> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FileAdderWriter")
+            .field("adder", &self.adder)
+            .finish()
+    }
+}
+
+impl<F> FileAdderWriter<F>
+where
+    F: FnMut(Cid, Vec<u8>) -> io::Result<()>,
+{
+    /// Creates a new writer, passing the blocks created by `adder` to `sink`.
+    pub fn new(adder: FileAdder, sink: F) -> Self {
+        FileAdderWriter {
+            adder,
+            sink,
+            last: None,
+        }
+    }
+
+    /// Completes the tree, passing the remaining blocks to the `sink`. Returns the Cid of the root
+    /// block.
+    pub fn finish(mut self) -> io::Result<Cid> {
+        for (cid, block) in self.adder.finish() {
+            (self.sink)(cid.clone(), block)?;
+            self.last = Some(cid);
+        }
+
+        Ok(self
+            .last
+            .expect("finishing FileAdder always produces at least the root block"))
+    }
+}
+
+impl<F> io::Write for FileAdderWriter<F>
+where
+    F: FnMut(Cid, Vec<u8>) -> io::Result<()>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (blocks, consumed) = self.adder.push(buf);
+
+        for (cid, block) in blocks {
+            (self.sink)(cid.clone(), block)?;
+            self.last = Some(cid);
+        }
+
+        Ok(consumed)
+    }
+
+    /// Does nothing, as the content not yet forming a complete block can only be written when
+    /// calling [`FileAdderWriter::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileAdderWriter;
+    use crate::file::adder::{Chunker, FileAdder};
+    use std::io::Write;
+
+    #[test]
+    fn copy_into_writer() {
+        let adder = FileAdder::builder().with_chunker(Chunker::Size(2)).build();
+        let mut blocks = Vec::new();
+
+        let mut writer = FileAdderWriter::new(adder, |cid, block| {
+            blocks.push((cid, block));
+            Ok(())
+        });
+
+        std::io::copy(&mut &b"foobar\n"[..], &mut writer).unwrap();
+        writer.flush().unwrap();
+        let root = writer.finish().unwrap();
+
+        // same as in `adder::tests::favourite_multi_block_file`
+        assert_eq!(
+            root.to_string(),
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+        );
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks.last().unwrap().0, root);
+    }
+}
@@ -0,0 +1,416 @@
+use crate::file::{FileError, FileReadFailed, UnwrapBorrowedExt};
+use crate::pb::{FlatUnixFs, UnixFsType};
+use crate::InvalidCidInLink;
+use cid::Cid;
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::Range;
+
+/// The largest difference between the cumulative size and the file size of a link for the linked
+/// block to be considered a leaf without loading it. The protobuf framing of a dag-pb leaf takes
+/// at most 20 bytes, while a link block takes at least 40 bytes for every link it has. As the
+/// sizes come from the untrusted link block, [`BlockRange::content`] checks the leaf once it has
+/// been loaded.
+const MAX_LEAF_OVERHEAD: u64 = 32;
+
+/// A block of an UnixFS file and the range of the file content within it, returned by
+/// [`block_ranges`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRange {
+    /// The block with the file content.
+    pub cid: Cid,
+    /// The range within the file content of the block. For `raw` codec blocks this is also the
+    /// range within the block, for dag-pb blocks the content is the `Data` of the UnixFS message.
+    pub range: Range<u64>,
+    /// The length of the file content of the block, as recorded in the block linking to it.
+    pub size: u64,
+}
+
+impl BlockRange {
+    /// Returns the range of the file content of the loaded `block`. Fails when the length of the
+    /// content is not the one recorded in the linking block, as when the sizes in the links made
+    /// a link block look like a leaf, instead of reading past the content.
+    pub fn content<'a>(&self, block: &'a [u8]) -> Result<&'a [u8], FileReadFailed> {
+        let content = if u64::from(self.cid.codec()) == crate::RAW_CODEC {
+            if block.len() as u64 != self.size {
+                return Err(FileError::RawLeafSizeMismatch {
+                    expected: self.size,
+                    actual: block.len() as u64,
+                }
+                .into());
+            }
+            block
+        } else {
+            let flat = FlatUnixFs::try_from(block)?;
+
+            match flat.data.Type {
+                UnixFsType::File | UnixFsType::Raw => {}
+                other => return Err(FileReadFailed::UnexpectedType(other.into())),
+            }
+
+            let data = flat.data.Data.unwrap_borrowed_or_empty();
+            if data.len() as u64 != self.size {
+                return Err(FileError::LeafSizeMismatch {
+                    expected: self.size,
+                    actual: data.len() as u64,
+                }
+                .into());
+            }
+            data
+        };
+
+        // the range is within the size as computed by `push_overlap`
+        Ok(&content[self.range.start as usize..self.range.end as usize])
+    }
+}
+
+/// Maps the `range` of the file at `root` to the blocks holding the content, in the file order.
+///
+/// Only the link blocks are loaded through the `fetch` callback. The leaves are recognized by the
+/// `raw` codec or by the sizes recorded in the links, so in practice the leaves are not loaded,
+/// which allows requesting exactly the needed blocks for example over bitswap. The content of the
+/// leaves should be read with [`BlockRange::content`], which checks the sizes the leaves were
+/// recognized by. A range past the end of the file is truncated to the end.
+pub fn block_ranges<F, B, E>(
+    root: &Cid,
+    range: Range<u64>,
+    mut fetch: F,
+) -> Result<Vec<BlockRange>, BlockRangesFailed<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    let mut ranges = Vec::new();
+
+    if range.start >= range.end {
+        return Ok(ranges);
+    }
+
+    let block = fetch(root).map_err(BlockRangesFailed::Fetch)?;
+    visit(root, block.as_ref(), 0, &range, &mut fetch, &mut ranges)?;
+    Ok(ranges)
+}
+
+fn visit<F, B, E>(
+    cid: &Cid,
+    block: &[u8],
+    offset: u64,
+    range: &Range<u64>,
+    fetch: &mut F,
+    ranges: &mut Vec<BlockRange>,
+) -> Result<(), BlockRangesFailed<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    if u64::from(cid.codec()) == crate::RAW_CODEC {
+        push_overlap(cid, offset, block.len() as u64, range, ranges);
+        return Ok(());
+    }
+
+    let flat = FlatUnixFs::try_from(block).map_err(|e| BlockRangesFailed::Read(e.into()))?;
+
+    match flat.data.Type {
+        UnixFsType::File | UnixFsType::Raw => {}
+        other => {
+            return Err(BlockRangesFailed::Read(FileReadFailed::UnexpectedType(
+                other.into(),
+            )))
+        }
+    }
+
+    if flat.links.len() != flat.data.blocksizes.len() {
+        return Err(BlockRangesFailed::Read(
+            FileError::LinksAndBlocksizesMismatch.into(),
+        ));
+    }
+
+    // the content of the block itself comes before the linked content
+    let data_len = flat
+        .data
+        .Data
+        .as_deref()
+        .map(|d| d.len() as u64)
+        .unwrap_or(0);
+
+    // the offsets of the linked content come from the blocksizes, which need to add up to the
+    // size of the file
+    if let Some(filesize) = flat.data.filesize {
+        let content = flat
+            .data
+            .blocksizes
+            .iter()
+            .fold(data_len, |acc, &size| acc.saturating_add(size));
+
+        if content > filesize {
+            return Err(BlockRangesFailed::Read(
+                FileError::TreeExpandsOnLinks.into(),
+            ));
+        } else if content < filesize {
+            return Err(BlockRangesFailed::Read(
+                FileError::TreeJumpsBetweenLinks.into(),
+            ));
+        }
+    }
+
+    push_overlap(cid, offset, data_len, range, ranges);
+
+    let mut start = offset + data_len;
+
+    for (nth, (link, &file_size)) in flat
+        .links
+        .into_iter()
+        .zip(flat.data.blocksizes.iter())
+        .enumerate()
+    {
+        let end = start + file_size;
+        let child_start = start;
+        start = end;
+
+        if end <= range.start {
+            continue;
+        }
+
+        if child_start >= range.end {
+            break;
+        }
+
+        // a leaf is at least as large as its content
+        let probably_leaf = link
+            .Tsize
+            .map(|total_size| {
+                file_size <= total_size && total_size <= file_size.saturating_add(MAX_LEAF_OVERHEAD)
+            })
+            .unwrap_or(false);
+
+        let hash = link.Hash.as_deref().unwrap_or_default();
+        let target = match Cid::try_from(hash) {
+            Ok(cid) => cid,
+            Err(e) => {
+                return Err(BlockRangesFailed::Read(FileReadFailed::InvalidCid(
+                    InvalidCidInLink::from((nth, link, e)),
+                )))
+            }
+        };
+
+        if probably_leaf || u64::from(target.codec()) == crate::RAW_CODEC {
+            push_overlap(&target, child_start, file_size, range, ranges);
+        } else {
+            let block = fetch(&target).map_err(BlockRangesFailed::Fetch)?;
+            visit(&target, block.as_ref(), child_start, range, fetch, ranges)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pushes the part of `range` within the content of `len` bytes starting at `offset`, relative to
+/// the `offset`.
+fn push_overlap(
+    cid: &Cid,
+    offset: u64,
+    len: u64,
+    range: &Range<u64>,
+    ranges: &mut Vec<BlockRange>,
+) {
+    let start = range.start.max(offset);
+    let end = range.end.min(offset + len);
+
+    if start < end {
+        ranges.push(BlockRange {
+            cid: cid.to_owned(),
+            range: (start - offset)..(end - offset),
+            size: len,
+        });
+    }
+}
+
+/// Errors which can happen while resolving the blocks with [`block_ranges`].
+#[derive(Debug)]
+pub enum BlockRangesFailed<E> {
+    /// The `fetch` callback failed.
+    Fetch(E),
+    /// A link block of the file could not be read.
+    Read(FileReadFailed),
+}
+
+impl<E: fmt::Display> fmt::Display for BlockRangesFailed<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use BlockRangesFailed::*;
+
+        match self {
+            Fetch(e) => write!(fmt, "failed to fetch a block: {}", e),
+            Read(e) => write!(fmt, "failed to read the file: {}", e),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for BlockRangesFailed<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use BlockRangesFailed::*;
+
+        match self {
+            Fetch(e) => Some(e),
+            Read(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_ranges, BlockRange, BlockRangesFailed};
+    use crate::file::adder::FileAdder;
+    use crate::file::{FileError, FileReadFailed};
+    use crate::pb::{FlatUnixFs, PBLink};
+    use cid::Cid;
+    use std::collections::HashMap;
+    use std::convert::{Infallible, TryFrom};
+
+    const CONTENT: &[u8] = b"foobar\nfoobar\n";
+
+    fn add(raw_leaves: bool, content: &[u8]) -> (Cid, HashMap<Cid, Vec<u8>>) {
+        let blocks = FileAdder::builder()
+            .with_chunk_size(2)
+            .with_branching_factor(3)
+            .with_raw_leaves(raw_leaves)
+            .build()
+            .collect_blocks(content, 0);
+
+        let root = blocks.last().unwrap().0.clone();
+        (root, blocks.into_iter().collect())
+    }
+
+    /// Renders the modified root block, returning it with its Cid.
+    fn forge(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+        use quick_protobuf::{MessageWrite, Writer};
+
+        let mut out = Vec::with_capacity(flat.get_size());
+        flat.write_message(&mut Writer::new(&mut out)).unwrap();
+        (crate::CidOptions::default().dag_pb(&out), out)
+    }
+
+    fn resolve(
+        root: &Cid,
+        blocks: &HashMap<Cid, Vec<u8>>,
+        range: core::ops::Range<u64>,
+    ) -> (Vec<BlockRange>, Vec<Cid>) {
+        let mut fetched = Vec::new();
+        let ranges = block_ranges(root, range, |cid: &Cid| {
+            fetched.push(cid.clone());
+            Ok::<_, Infallible>(&blocks[cid])
+        })
+        .unwrap();
+        (ranges, fetched)
+    }
+
+    fn read(ranges: &[BlockRange], blocks: &HashMap<Cid, Vec<u8>>) -> Vec<u8> {
+        ranges
+            .iter()
+            .flat_map(|range| range.content(&blocks[&range.cid]).unwrap().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn ranges_without_loading_leaves() {
+        for &raw_leaves in &[false, true] {
+            let (root, blocks) = add(raw_leaves, CONTENT);
+
+            let (ranges, fetched) = resolve(&root, &blocks, 3..9);
+
+            // "ar", "\nf", "oo", "ba" of the two byte leaves
+            assert_eq!(ranges.len(), 4);
+            assert_eq!(ranges[0].range, 1..2);
+            assert_eq!(ranges[3].range, 0..1);
+            assert_eq!(read(&ranges, &blocks), &CONTENT[3..9]);
+
+            assert!(fetched
+                .iter()
+                .all(|cid| !ranges.iter().any(|range| &range.cid == cid)));
+        }
+    }
+
+    #[test]
+    fn range_past_end() {
+        let (root, blocks) = add(false, CONTENT);
+
+        let (ranges, _) = resolve(&root, &blocks, 11..100);
+        assert_eq!(read(&ranges, &blocks), &CONTENT[11..]);
+
+        let (ranges, fetched) = resolve(&root, &blocks, 100..200);
+        assert!(ranges.is_empty());
+        assert_eq!(fetched, vec![root]);
+    }
+
+    #[test]
+    fn single_block_file() {
+        let (root, blocks) = add(false, b"f");
+
+        let (ranges, _) = resolve(&root, &blocks, 0..10);
+        assert_eq!(
+            ranges,
+            vec![BlockRange {
+                cid: root,
+                range: 0..1,
+                size: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn link_block_posing_as_a_leaf() {
+        let (root, mut blocks) = add(false, CONTENT);
+
+        // the link block is listed with the sizes of a leaf in a new root
+        let mut flat = FlatUnixFs::try_from(&blocks[&root][..]).unwrap();
+        let link_block = Cid::try_from(flat.links[0].Hash.as_deref().unwrap()).unwrap();
+        let file_size = flat.data.blocksizes[0];
+        flat.links = vec![PBLink {
+            Tsize: Some(file_size),
+            ..flat.links[0].clone()
+        }];
+        flat.data.blocksizes = vec![file_size];
+        flat.data.filesize = Some(file_size);
+
+        let (forged_root, forged) = forge(&flat);
+        blocks.insert(forged_root.clone(), forged);
+
+        let (ranges, _) = resolve(&forged_root, &blocks, 0..file_size);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].cid, link_block);
+
+        let e = ranges[0].content(&blocks[&link_block]).unwrap_err();
+        assert!(
+            matches!(
+                e,
+                FileReadFailed::File(FileError::LeafSizeMismatch { actual: 0, .. })
+            ),
+            "{:?}",
+            e
+        );
+    }
+
+    #[test]
+    fn blocksizes_must_add_up_to_the_filesize() {
+        let (root, mut blocks) = add(false, CONTENT);
+
+        let mut flat = FlatUnixFs::try_from(&blocks[&root][..]).unwrap();
+        flat.data.filesize = Some(CONTENT.len() as u64 + 1);
+
+        let (forged_root, forged) = forge(&flat);
+        blocks.insert(forged_root.clone(), forged);
+
+        let e = block_ranges(&forged_root, 0..1, |cid: &Cid| {
+            Ok::<_, Infallible>(&blocks[cid])
+        })
+        .unwrap_err();
+
+        assert!(
+            matches!(
+                e,
+                BlockRangesFailed::Read(FileReadFailed::File(FileError::TreeJumpsBetweenLinks))
+            ),
+            "{:?}",
+            e
+        );
+    }
+}
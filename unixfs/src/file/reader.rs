@@ -212,6 +212,30 @@ impl Traversal {
         FileReader::from_continued(self, tree_range.start, next_block)
     }
 
+    /// Continues the walk with a leaf block of the `raw` codec, which has no dag-pb or UnixFS
+    /// envelope and is the file content as is. The length of the block must match the range
+    /// recorded for it in the linking block.
+    pub fn continue_raw<'a>(
+        mut self,
+        next_block: &'a [u8],
+        tree_range: &Range<u64>,
+    ) -> Result<(&'a [u8], Traversal), FileReadFailed> {
+        self.last_ending
+            .check_is_suitable_next(self.last_offset, tree_range)?;
+
+        let expected = tree_range.end - tree_range.start;
+        let actual = next_block.len() as u64;
+
+        if expected != actual {
+            return Err(FileError::RawLeafSizeMismatch { expected, actual }.into());
+        }
+
+        self.last_offset = tree_range.start;
+        self.last_ending = Ending::Chunk(tree_range.end);
+
+        Ok((next_block, self))
+    }
+
     /// Returns the total size of the file.
     pub fn file_size(&self) -> u64 {
         self.file_size
@@ -0,0 +1,303 @@
+use crate::file::visit::IdleFileVisit;
+use crate::file::FileReadFailed;
+use cid::Cid;
+use core::fmt;
+use std::io::{self, SeekFrom};
+
+/// Random access reader over an UnixFS file tree.
+///
+/// Blocks are loaded through the `fetch` callback as they are needed. Reads use the sizes recorded
+/// in the links of the tree to only load the blocks which overlap the requested range, so reading
+/// at a large offset does not require loading any of the leaves before it.
+///
+/// Implements [`std::io::Read`] and [`std::io::Seek`] when the `fetch` errors are
+/// `std::error::Error`s.
+pub struct SeekableFile<F> {
+    root: Cid,
+    fetch: F,
+    position: u64,
+    file_size: Option<u64>,
+}
+
+impl<F> fmt::Debug for SeekableFile<F> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SeekableFile")
+            .field("root", &format_args!("{}", self.root))
+            .field("position", &self.position)
+            .field("file_size", &self.file_size)
+            .finish()
+    }
+}
+
+impl<F, B, E> SeekableFile<F>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    /// Creates a new reader for the file rooted at `root`, positioned at the start of the file.
+    pub fn new(root: Cid, fetch: F) -> Self {
+        SeekableFile {
+            root,
+            fetch,
+            position: 0,
+            file_size: None,
+        }
+    }
+
+    /// Returns the size of the file, loading the root block if it has not yet been loaded.
+    pub fn file_size(&mut self) -> Result<u64, SeekableFileError<E>> {
+        if let Some(file_size) = self.file_size {
+            return Ok(file_size);
+        }
+
+        // reading nothing only loads the root block
+        self.visit(0..0, &mut Vec::new())?;
+        Ok(self.file_size.expect("visit always sets the file_size"))
+    }
+
+    /// Returns the current position, as set by [`SeekableFile::seek`] and advanced by reads.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Reads at most `len` bytes starting from `offset`, without changing the current position.
+    /// Returns less than `len` bytes only when the end of the file is reached.
+    pub fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, SeekableFileError<E>> {
+        let mut out = Vec::new();
+
+        if len == 0 {
+            return Ok(out);
+        }
+
+        let end = offset.saturating_add(len as u64);
+        self.visit(offset..end, &mut out)?;
+        Ok(out)
+    }
+
+    /// Moves the current position. Seeking past the end of the file is allowed, but reading from
+    /// there will not produce any bytes.
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64, SeekableFileError<E>> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.position = n;
+                return Ok(n);
+            }
+            SeekFrom::Current(n) => (self.position, n),
+            SeekFrom::End(n) => (self.file_size()?, n),
+        };
+
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(SeekableFileError::InvalidSeek),
+        }
+    }
+
+    fn visit(
+        &mut self,
+        range: core::ops::Range<u64>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), SeekableFileError<E>> {
+        let root = (self.fetch)(&self.root).map_err(SeekableFileError::Fetch)?;
+        let root = root.as_ref();
+
+        if u64::from(self.root.codec()) == crate::RAW_CODEC {
+            // the whole file is in the single raw block
+            let file_size = root.len() as u64;
+            self.file_size = Some(file_size);
+
+            let start = range.start.min(file_size) as usize;
+            let end = range.end.min(file_size) as usize;
+            out.extend_from_slice(&root[start..end]);
+            return Ok(());
+        }
+
+        let (bytes, file_size, _, mut visit) = IdleFileVisit::default()
+            .with_target_range(range)
+            .start(root)?;
+
+        self.file_size = Some(file_size);
+        out.extend_from_slice(bytes);
+
+        let mut cache = None;
+
+        while let Some(v) = visit {
+            let block = (self.fetch)(v.pending_links().0).map_err(SeekableFileError::Fetch)?;
+            let (bytes, next) = v.continue_walk(block.as_ref(), &mut cache)?;
+            out.extend_from_slice(bytes);
+            visit = next;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F, B, E> io::Read for SeekableFile<F>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.read_at(self.position, buf.len())?;
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        self.position += bytes.len() as u64;
+        Ok(bytes.len())
+    }
+}
+
+impl<F, B, E> io::Seek for SeekableFile<F>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        Ok(SeekableFile::seek(self, pos)?)
+    }
+}
+
+/// Errors which can happen while reading with [`SeekableFile`].
+#[derive(Debug)]
+pub enum SeekableFileError<E> {
+    /// The `fetch` callback failed.
+    Fetch(E),
+    /// The file tree could not be read.
+    Read(FileReadFailed),
+    /// Seeking would have moved the position before the start of the file, or overflown.
+    InvalidSeek,
+}
+
+impl<E> From<FileReadFailed> for SeekableFileError<E> {
+    fn from(e: FileReadFailed) -> Self {
+        SeekableFileError::Read(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for SeekableFileError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SeekableFileError::*;
+        match self {
+            Fetch(e) => write!(fmt, "failed to fetch a block: {}", e),
+            Read(e) => write!(fmt, "failed to read the file: {}", e),
+            InvalidSeek => write!(fmt, "invalid seek to a negative or overflowing position"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for SeekableFileError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use SeekableFileError::*;
+        match self {
+            Fetch(e) => Some(e),
+            Read(e) => Some(e),
+            InvalidSeek => None,
+        }
+    }
+}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<SeekableFileError<E>> for io::Error {
+    fn from(e: SeekableFileError<E>) -> io::Error {
+        let kind = match e {
+            SeekableFileError::InvalidSeek => io::ErrorKind::InvalidInput,
+            SeekableFileError::Read(_) => io::ErrorKind::InvalidData,
+            SeekableFileError::Fetch(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeekableFile;
+    use crate::file::adder::{Chunker, FileAdder};
+    use crate::test_support::{pseudo_random, FakeBlockstore};
+    use cid::Cid;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::convert::Infallible;
+    use std::io::{Read, Seek, SeekFrom};
+
+    #[test]
+    fn read_at_loads_only_the_needed_leaves() {
+        let content = pseudo_random(1000);
+        let (root, blocks) = add(&content, Chunker::Size(10), false);
+
+        let fetched = RefCell::new(HashSet::new());
+        let mut file = SeekableFile::new(root, |cid: &Cid| {
+            fetched.borrow_mut().insert(cid.clone());
+            Ok::<_, Infallible>(blocks.get_by_cid(cid))
+        });
+
+        assert_eq!(file.read_at(505, 10).unwrap(), &content[505..515]);
+
+        // the root and the two leaves at 500..510 and 510..520
+        assert_eq!(fetched.borrow().len(), 3, "{:?}", fetched.borrow());
+
+        assert_eq!(file.file_size().unwrap(), 1000);
+        assert_eq!(file.read_at(995, 10).unwrap(), &content[995..]);
+        assert!(file.read_at(1000, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_and_seek() {
+        let content = pseudo_random(100);
+
+        for &raw_leaves in &[false, true] {
+            for &chunk_size in &[7, 100] {
+                let (root, blocks) = add(&content, Chunker::Size(chunk_size), raw_leaves);
+
+                let mut file = SeekableFile::new(root, |cid: &Cid| {
+                    Ok::<_, Infallible>(blocks.get_by_cid(cid))
+                });
+
+                let mut buf = [0u8; 16];
+
+                // through the std::io::Seek for coverage
+                assert_eq!(Seek::seek(&mut file, SeekFrom::End(-20)).unwrap(), 80);
+                file.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf[..], &content[80..96]);
+
+                assert_eq!(file.seek(SeekFrom::Current(-50)).unwrap(), 46);
+                file.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf[..], &content[46..62]);
+
+                let mut rest = Vec::new();
+                file.read_to_end(&mut rest).unwrap();
+                assert_eq!(rest, &content[62..]);
+
+                assert!(file.seek(SeekFrom::Current(-101)).is_err());
+            }
+        }
+    }
+
+    fn add(content: &[u8], chunker: Chunker, raw_leaves: bool) -> (Cid, FakeBlockstore) {
+        let mut blocks = FakeBlockstore::default();
+        let mut root = None;
+
+        let received = FileAdder::builder()
+            .with_chunker(chunker)
+            .with_raw_leaves(raw_leaves)
+            .build()
+            .collect_blocks(content, 0);
+
+        for (cid, block) in received {
+            if cid.version() == cid::Version::V0 {
+                blocks.insert_v0(&block);
+            } else {
+                blocks.insert_raw(&block);
+            }
+            root = Some(cid);
+        }
+
+        (root.unwrap(), blocks)
+    }
+}
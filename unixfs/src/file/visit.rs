@@ -155,25 +155,25 @@ impl FileVisit {
         cache: &mut Option<Cache>,
     ) -> Result<(&'a [u8], Option<Self>), FileReadFailed> {
         let traversal = self.state;
-        let (_, range) = self
+        let (cid, range) = self
             .pending
             .pop()
             .expect("User called continue_walk there must have been a next link");
 
+        if u64::from(cid.codec()) == crate::RAW_CODEC {
+            // raw leaves are the file content as is
+            let (content, traversal) = traversal.continue_raw(next, &range)?;
+            self.state = traversal;
+            return Ok(self.leaf_content(content, &range, cache));
+        }
+
         // interesting, validation doesn't trigger if the range is the same?
         let fr = traversal.continue_walk(next, &range)?;
         let (content, traversal) = fr.content();
         match content {
             FileContent::Bytes(content) => {
-                let content = maybe_target_slice(content, &range, self.range.as_ref());
-
-                if !self.pending.is_empty() {
-                    self.state = traversal;
-                    Ok((content, Some(self)))
-                } else {
-                    *cache = Some(self.pending.into());
-                    Ok((content, None))
-                }
+                self.state = traversal;
+                Ok(self.leaf_content(content, &range, cache))
             }
             FileContent::Links(iter) => {
                 let before = self.pending.len();
@@ -195,6 +195,24 @@ impl FileVisit {
         }
     }
 
+    /// Returns the part of the leaf `content` inside the target range, and `self` if there are
+    /// still more blocks to visit.
+    fn leaf_content<'a>(
+        self,
+        content: &'a [u8],
+        range: &Range<u64>,
+        cache: &mut Option<Cache>,
+    ) -> (&'a [u8], Option<Self>) {
+        let content = maybe_target_slice(content, range, self.range.as_ref());
+
+        if !self.pending.is_empty() {
+            (content, Some(self))
+        } else {
+            *cache = Some(self.pending.into());
+            (content, None)
+        }
+    }
+
     /// Returns the total size of the file in bytes.
     pub fn file_size(&self) -> u64 {
         self.state.file_size()
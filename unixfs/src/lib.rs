@@ -26,12 +26,35 @@ pub use dir::{resolve, LookupError, MaybeResolved, ResolveError};
 mod pb;
 use pb::{UnixFs, UnixFsType};
 
+/// Multicodec code for the `raw` codec used for file leaves which are stored without the dag-pb
+/// and UnixFS envelope.
+pub(crate) const RAW_CODEC: u64 = 0x55;
+
 /// Support operations for the dag-pb, the outer shell of UnixFS
 pub mod dagpb;
 
 /// Support for walking over all UnixFs trees
 pub mod walk;
 
+/// Information about UnixFS nodes
+pub mod stat;
+pub use stat::{stat, stat_block};
+
+/// Verification of block contents against their Cids
+pub mod verify;
+pub use verify::{verify_block, VerificationFailed};
+
+/// Importing tar archives as UnixFS trees. Enabled only in the `tar` feature.
+#[cfg(feature = "tar")]
+pub mod tar;
+
+/// Exporting UnixFS trees to the local filesystem
+pub mod export;
+pub use export::{export, ExportFailed, ExportOptions};
+
+mod cid_options;
+pub use cid_options::{CidOptions, HashFunction, UnsupportedHashFunction, MAX_INLINE_LIMIT};
+
 #[cfg(test)]
 pub(crate) mod test_support;
 
@@ -159,6 +182,54 @@ impl Metadata {
         self.mtime()
             .map(|(seconds, nanos)| filetime::FileTime::from_unix_time(seconds, nanos))
     }
+
+    /// Returns a copy of this metadata with the given file mode. See [`Metadata::mode`] for the
+    /// meaning of the bits; readers are expected to only look at the permission bits (`0o7777`).
+    pub fn with_mode(self, mode: u32) -> Self {
+        Metadata {
+            mode: Some(mode),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this metadata with the given modification time. See [`Metadata::mtime`]
+    /// for the meaning of the values.
+    ///
+    /// # Panics
+    ///
+    /// When `nanos` is not less than one second.
+    pub fn with_mtime(self, seconds: i64, nanos: u32) -> Self {
+        assert!(
+            nanos < 1_000_000_000,
+            "fractional nanoseconds must be less than one second"
+        );
+        Metadata {
+            mtime: Some((seconds, nanos)),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this metadata with the modification time from the `FileTime`. Enabled
+    /// only in the `filetime` feature.
+    #[cfg(feature = "filetime")]
+    pub fn with_mtime_from_filetime(self, mtime: filetime::FileTime) -> Self {
+        self.with_mtime(mtime.unix_seconds(), mtime.nanoseconds())
+    }
+
+    /// Returns `true` if neither mode or mtime has been specified.
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.mtime.is_none()
+    }
+
+    /// Writes the metadata as the UnixFS 1.5 `mode` and `mtime` fields of the given message.
+    pub(crate) fn apply_to(&self, data: &mut UnixFs<'_>) {
+        data.mode = self.mode;
+        data.mtime = self.mtime.map(|(seconds, nanos)| pb::UnixTime {
+            Seconds: seconds,
+            // the spec requires this field to be omitted instead of being zero
+            FractionalNanoseconds: if nanos != 0 { Some(nanos) } else { None },
+        });
+    }
 }
 
 impl<'a> From<&'a UnixFs<'_>> for Metadata {
@@ -0,0 +1,237 @@
+use crate::pb::{FlatUnixFs, UnixFsType};
+use crate::walk::Error;
+use crate::{InvalidCidInLink, Metadata, UnexpectedNodeType};
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use cid::Cid;
+use core::convert::TryFrom;
+use core::fmt;
+
+/// The kind of an UnixFS node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeType {
+    /// A file, or a `raw` codec block.
+    File,
+    /// A plain directory.
+    Directory,
+    /// A HAMT sharded directory, or one of its buckets.
+    HamtShard,
+    /// A symbolic link.
+    Symlink,
+}
+
+/// Information about an UnixFS node, similar to `ipfs files stat`, returned by [`stat`] and
+/// [`stat_block`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    /// The kind of the node.
+    pub node_type: NodeType,
+    /// The size of the file content or the length of the symlink target. Zero for directories.
+    pub size: u64,
+    /// The size of the block and all of the blocks linked from it, as recorded in the links.
+    pub cumulative_size: u64,
+    /// The number of links in the node: the child blocks of a file, or the entries of a plain
+    /// directory.
+    pub links: usize,
+    /// The number of distinct blocks in the DAG of the node, including the root block, as counted
+    /// by [`stat`]. `None` when only the root block was read with [`stat_block`].
+    pub blocks: Option<u64>,
+    /// The metadata of the node.
+    pub metadata: Metadata,
+}
+
+/// Reads the information about the UnixFS node at `root`, loading the blocks through the `fetch`
+/// callback in order to count the blocks of the DAG. The `raw` codec blocks cannot have links, so
+/// they are counted without loading them, as are the blocks linked more than once.
+///
+/// For the information recorded within the root block alone, such as needed for answering the
+/// `HEAD` requests, see [`stat_block`] which needs no other blocks.
+pub fn stat<F, B, E>(root: &Cid, mut fetch: F) -> Result<Stat, StatFailed<E>>
+where
+    F: FnMut(&Cid) -> Result<B, E>,
+    B: AsRef<[u8]>,
+{
+    let block = fetch(root).map_err(StatFailed::Fetch)?;
+    let mut stat = stat_block(root, block.as_ref()).map_err(StatFailed::Read)?;
+
+    let mut pending = links(root, block.as_ref()).map_err(StatFailed::Read)?;
+    let mut seen = BTreeSet::new();
+    seen.insert(root.to_owned());
+
+    while let Some(cid) = pending.pop() {
+        if !seen.insert(cid.clone()) {
+            continue;
+        }
+
+        if u64::from(cid.codec()) == crate::RAW_CODEC {
+            continue;
+        }
+
+        let block = fetch(&cid).map_err(StatFailed::Fetch)?;
+        pending.extend(links(&cid, block.as_ref()).map_err(StatFailed::Read)?);
+    }
+
+    stat.blocks = Some(seen.len() as u64);
+    Ok(stat)
+}
+
+/// Reads the information about the UnixFS node from its root block. As the sizes are recorded
+/// within the root block, no other blocks are needed, but the blocks of the DAG are not counted.
+pub fn stat_block(cid: &Cid, block: &[u8]) -> Result<Stat, Error> {
+    if u64::from(cid.codec()) == crate::RAW_CODEC {
+        return Ok(Stat {
+            node_type: NodeType::File,
+            size: block.len() as u64,
+            cumulative_size: block.len() as u64,
+            links: 0,
+            blocks: None,
+            metadata: Metadata::default(),
+        });
+    }
+
+    let flat = FlatUnixFs::try_from(block)?;
+
+    let (node_type, size) = match flat.data.Type {
+        UnixFsType::File | UnixFsType::Raw => {
+            let content = flat.data.Data.as_deref().map(|d| d.len() as u64);
+            let size = flat.data.filesize.or(content).unwrap_or(0);
+            (NodeType::File, size)
+        }
+        UnixFsType::Directory => (NodeType::Directory, 0),
+        UnixFsType::HAMTShard => (NodeType::HamtShard, 0),
+        UnixFsType::Symlink => {
+            let target = flat.data.Data.as_deref().map(|d| d.len() as u64);
+            (NodeType::Symlink, target.unwrap_or(0))
+        }
+        other => return Err(Error::UnsupportedType(UnexpectedNodeType::from(other))),
+    };
+
+    let cumulative_size = block.len() as u64
+        + flat
+            .links
+            .iter()
+            .map(|link| link.Tsize.unwrap_or(0))
+            .sum::<u64>();
+
+    Ok(Stat {
+        node_type,
+        size,
+        cumulative_size,
+        links: flat.links.len(),
+        blocks: None,
+        metadata: Metadata::from(&flat.data),
+    })
+}
+
+/// Returns the targets of the links of the dag-pb block, or none for a `raw` codec block.
+fn links(cid: &Cid, block: &[u8]) -> Result<Vec<Cid>, Error> {
+    if u64::from(cid.codec()) == crate::RAW_CODEC {
+        return Ok(Vec::new());
+    }
+
+    let flat = FlatUnixFs::try_from(block)?;
+    let mut targets = Vec::with_capacity(flat.links.len());
+
+    for (nth, link) in flat.links.into_iter().enumerate() {
+        let hash = link.Hash.as_deref().unwrap_or_default();
+        match Cid::try_from(hash) {
+            Ok(cid) => targets.push(cid),
+            Err(e) => return Err(InvalidCidInLink::from((nth, link, e)).into()),
+        }
+    }
+
+    Ok(targets)
+}
+
+/// Errors which can happen while reading the information with [`stat`].
+#[derive(Debug)]
+pub enum StatFailed<E> {
+    /// The `fetch` callback failed.
+    Fetch(E),
+    /// A block of the DAG could not be read.
+    Read(Error),
+}
+
+impl<E: fmt::Display> fmt::Display for StatFailed<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use StatFailed::*;
+
+        match self {
+            Fetch(e) => write!(fmt, "failed to fetch a block: {}", e),
+            Read(e) => write!(fmt, "failed to read the node: {}", e),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for StatFailed<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use StatFailed::*;
+
+        match self {
+            Fetch(e) => Some(e),
+            Read(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stat, stat_block, NodeType};
+    use crate::test_support::FakeBlockstore;
+    use cid::Cid;
+    use core::convert::TryFrom;
+    use std::convert::Infallible;
+
+    #[test]
+    fn stat_file() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        // the five block foobar\n
+        let cid = Cid::try_from("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6").unwrap();
+        let st = stat_block(&cid, blocks.get_by_cid(&cid)).unwrap();
+
+        assert_eq!(st.node_type, NodeType::File);
+        assert_eq!(st.size, 7);
+        assert_eq!(st.links, 4);
+        // same as the Tsize when linking to the file in `dir::builder` tests
+        assert_eq!(st.cumulative_size, 221);
+        assert_eq!(st.blocks, None);
+    }
+
+    #[test]
+    fn stat_counts_the_blocks() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let cid = Cid::try_from("QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6").unwrap();
+        let st = stat(&cid, |cid| Ok::<_, Infallible>(blocks.get_by_cid(cid))).unwrap();
+
+        assert_eq!(st.size, 7);
+        assert_eq!(st.links, 4);
+        // the root and the four leaves
+        assert_eq!(st.blocks, Some(5));
+    }
+
+    #[test]
+    fn stat_directory() {
+        // empty directory
+        let block = [0x0a, 0x02, 0x08, 0x01];
+        let cid = Cid::try_from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+        let st = stat_block(&cid, &block).unwrap();
+
+        assert_eq!(st.node_type, NodeType::Directory);
+        assert_eq!(st.size, 0);
+        assert_eq!(st.links, 0);
+        assert_eq!(st.cumulative_size, 4);
+    }
+
+    #[test]
+    fn stat_raw() {
+        let cid = crate::CidOptions::default().raw(b"foobar\n");
+        let st = stat(&cid, |_| Ok::<_, Infallible>(b"foobar\n")).unwrap();
+
+        assert_eq!(st.node_type, NodeType::File);
+        assert_eq!(st.size, 7);
+        assert_eq!(st.cumulative_size, 7);
+        assert_eq!(st.blocks, Some(1));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::dir::builder::{
+    BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeOptions,
+};
+use crate::file::adder::{FileAdderBuilder, FileAdderWriter};
+use crate::Metadata;
+use cid::Cid;
+use core::fmt;
+use std::io::{self, Read};
+use tar::EntryType;
+
+/// Imports the files, directories and symlinks of a tar archive as a UnixFS tree wrapped in a
+/// directory, with the default options of [`TarImporter`]. The created blocks are handed to the
+/// `sink` in an order where the linked blocks come before the blocks linking to them. Returns
+/// the Cid of the root directory.
+pub fn import_tar<R, F>(reader: R, sink: F) -> Result<Cid, TarImportFailed>
+where
+    R: Read,
+    F: FnMut(Cid, Vec<u8>) -> io::Result<()>,
+{
+    TarImporter::default().import(reader, sink)
+}
+
+/// Configurable version of [`import_tar`]. Enabled only in the `tar` feature.
+pub struct TarImporter {
+    adder: FileAdderBuilder,
+    tree: TreeOptions,
+    metadata: bool,
+}
+
+impl fmt::Debug for TarImporter {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("TarImporter")
+            .field("tree", &self.tree)
+            .field("metadata", &self.metadata)
+            .finish()
+    }
+}
+
+impl Default for TarImporter {
+    /// Returns an importer with the default file adder options, wrapping the archive contents in
+    /// a directory and not preserving the metadata.
+    fn default() -> Self {
+        let mut tree = TreeOptions::default();
+        tree.wrap_with_directory();
+
+        TarImporter {
+            adder: FileAdderBuilder::default(),
+            tree,
+            metadata: false,
+        }
+    }
+}
+
+impl TarImporter {
+    /// Configures the chunker, layout and the other options for the files.
+    pub fn with_file_adder(self, adder: FileAdderBuilder) -> Self {
+        TarImporter { adder, ..self }
+    }
+
+    /// Configures the tree building options. Without the `wrap_with_directory` option, the
+    /// archive can contain only a single top level entry, which will be the returned root.
+    pub fn with_tree_options(self, tree: TreeOptions) -> Self {
+        TarImporter { tree, ..self }
+    }
+
+    /// When true, the mode and the modification time from the tar headers are written for the
+    /// files and the directories.
+    pub fn with_metadata(self, metadata: bool) -> Self {
+        TarImporter { metadata, ..self }
+    }
+
+    /// Imports the archive read from `reader`, see [`import_tar`].
+    pub fn import<R, F>(self, reader: R, mut sink: F) -> Result<Cid, TarImportFailed>
+    where
+        R: Read,
+        F: FnMut(Cid, Vec<u8>) -> io::Result<()>,
+    {
+        let mut archive = tar::Archive::new(reader);
+        let mut tree = BufferingTreeBuilder::new(self.tree);
+        // the only entry is the root when not wrapping with directory
+        let mut last = None;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+
+            let path = {
+                let bytes = entry.path_bytes();
+                let path = core::str::from_utf8(&bytes)
+                    .map_err(|_| TarImportFailed::InvalidPath(bytes.to_vec()))?;
+                normalize(path).to_owned()
+            };
+
+            let metadata = if self.metadata {
+                let header = entry.header();
+                Metadata::default()
+                    .with_mode(header.mode()?)
+                    .with_mtime(header.mtime()? as i64, 0)
+            } else {
+                Metadata::default()
+            };
+
+            match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous => {
+                    let adder = self.adder.clone().with_metadata(metadata).build();
+                    let mut total_size = 0u64;
+
+                    let mut writer = FileAdderWriter::new(adder, |cid, block| {
+                        total_size += block.len() as u64;
+                        sink(cid, block)
+                    });
+
+                    io::copy(&mut entry, &mut writer)?;
+                    let cid = writer.finish()?;
+
+                    tree.put_link(&path, cid.clone(), total_size)?;
+                    last = Some(cid);
+                }
+                EntryType::Directory if path.is_empty() => {
+                    // the archive root, for example "./"
+                }
+                EntryType::Directory => {
+                    tree.set_metadata(&path, metadata)?;
+                }
+                EntryType::Symlink => {
+                    let target = entry.link_name_bytes().unwrap_or_default();
+                    let target = core::str::from_utf8(&target)
+                        .map_err(|_| TarImportFailed::InvalidPath(target.to_vec()))?;
+
+                    let (cid, block) = tree.put_symlink(&path, target)?;
+                    sink(cid.clone(), block)?;
+                    last = Some(cid);
+                }
+                EntryType::XGlobalHeader | EntryType::XHeader => {
+                    // extensions which have already been applied to the entries by `tar`
+                }
+                _ => return Err(TarImportFailed::UnsupportedEntry(path)),
+            }
+        }
+
+        let mut nodes = tree.build();
+
+        while let Some(node) = nodes.next_borrowed() {
+            let node = node?;
+            sink(node.cid.to_owned(), node.block.to_vec())?;
+            last = Some(node.cid.to_owned());
+        }
+
+        last.ok_or(TarImportFailed::Empty)
+    }
+}
+
+/// Removes the leading `./` or `/` and the trailing `/` of directories.
+fn normalize(path: &str) -> &str {
+    let path = path.trim_end_matches('/');
+    let path = path.strip_prefix("./").unwrap_or(path);
+    let path = path.trim_start_matches('/');
+    if path == "." {
+        ""
+    } else {
+        path
+    }
+}
+
+/// Errors which can happen while importing a tar archive.
+#[derive(Debug)]
+pub enum TarImportFailed {
+    /// Reading the archive or handing the blocks to the sink failed.
+    Io(io::Error),
+    /// The path or the symlink target of an entry was not valid UTF-8.
+    InvalidPath(Vec<u8>),
+    /// The entry at the path was not a file, a directory or a symlink; for example a hard link.
+    UnsupportedEntry(String),
+    /// The entry could not be added to the tree.
+    Building(TreeBuildingFailed),
+    /// A directory block could not be created.
+    Construction(TreeConstructionFailed),
+    /// The archive had no entries and the importer was not configured to wrap with a directory.
+    Empty,
+}
+
+impl From<io::Error> for TarImportFailed {
+    fn from(e: io::Error) -> Self {
+        TarImportFailed::Io(e)
+    }
+}
+
+impl From<TreeBuildingFailed> for TarImportFailed {
+    fn from(e: TreeBuildingFailed) -> Self {
+        TarImportFailed::Building(e)
+    }
+}
+
+impl From<TreeConstructionFailed> for TarImportFailed {
+    fn from(e: TreeConstructionFailed) -> Self {
+        TarImportFailed::Construction(e)
+    }
+}
+
+impl fmt::Display for TarImportFailed {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TarImportFailed::*;
+
+        match self {
+            Io(e) => write!(fmt, "{}", e),
+            InvalidPath(bytes) => write!(
+                fmt,
+                "path is not valid UTF-8: {:?}",
+                String::from_utf8_lossy(bytes)
+            ),
+            UnsupportedEntry(path) => write!(fmt, "unsupported entry type at {:?}", path),
+            Building(e) => write!(fmt, "{}", e),
+            Construction(e) => write!(fmt, "{}", e),
+            Empty => write!(fmt, "archive had no entries"),
+        }
+    }
+}
+
+impl std::error::Error for TarImportFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use TarImportFailed::*;
+
+        match self {
+            Io(e) => Some(e),
+            Building(e) => Some(e),
+            Construction(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{import_tar, TarImporter};
+    use crate::dir::builder::{BufferingTreeBuilder, TreeOptions};
+    use crate::file::adder::FileAdder;
+    use cid::Cid;
+    use std::collections::HashMap;
+
+    fn append(builder: &mut tar::Builder<Vec<u8>>, path: &str, kind: tar::EntryType, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_entry_type(kind);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o640);
+        header.set_mtime(1_600_000_000);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    fn sample_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        append(&mut builder, "a/", tar::EntryType::Directory, b"");
+        append(
+            &mut builder,
+            "a/foobar.txt",
+            tar::EntryType::Regular,
+            b"foobar\n",
+        );
+        append(&mut builder, "empty/", tar::EntryType::Directory, b"");
+
+        let mut header = tar::Header::new_gnu();
+        header.set_path("a/link").unwrap();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_link_name("foobar.txt").unwrap();
+        header.set_size(0);
+        header.set_cksum();
+        builder.append(&header, &b""[..]).unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn same_as_tree_builder() {
+        let mut blocks = HashMap::new();
+        let root = import_tar(&sample_archive()[..], |cid, block| {
+            blocks.insert(cid, block);
+            Ok(())
+        })
+        .unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        let mut tree = BufferingTreeBuilder::new(opts);
+
+        let mut adder = FileAdder::default();
+        let (_, consumed) = adder.push(b"foobar\n");
+        assert_eq!(consumed, 7);
+        let (file, block) = adder.finish().last().unwrap();
+
+        tree.put_link("a/foobar.txt", file, block.len() as u64)
+            .unwrap();
+        tree.put_symlink("a/link", "foobar.txt").unwrap();
+        tree.set_metadata("empty", Default::default()).unwrap();
+
+        let expected = tree.build().last().unwrap().unwrap();
+
+        assert_eq!(root, expected.cid);
+        // the root, "a", "empty", the file and the symlink
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(&blocks[&root][..], &expected.block[..]);
+    }
+
+    #[test]
+    fn preserved_metadata() {
+        let mut builder = tar::Builder::new(Vec::new());
+        append(
+            &mut builder,
+            "foobar.txt",
+            tar::EntryType::Regular,
+            b"foobar\n",
+        );
+        let archive = builder.into_inner().unwrap();
+
+        let mut blocks = HashMap::<Cid, Vec<u8>>::new();
+        let root = TarImporter::default()
+            .with_tree_options(TreeOptions::default())
+            .with_metadata(true)
+            .import(&archive[..], |cid, block| {
+                blocks.insert(cid, block);
+                Ok(())
+            })
+            .unwrap();
+
+        let stat = crate::stat(&root, &blocks[&root]).unwrap();
+        assert_eq!(stat.size, 7);
+        assert_eq!(stat.metadata.mode(), Some(0o640));
+        assert_eq!(stat.metadata.mtime(), Some((1_600_000_000, 0)));
+    }
+
+    #[test]
+    fn normalized_paths() {
+        use super::normalize;
+
+        assert_eq!(normalize("./"), "");
+        assert_eq!(normalize("."), "");
+        assert_eq!(normalize("./a/"), "a");
+        assert_eq!(normalize("/a/b.txt"), "a/b.txt");
+        assert_eq!(normalize("a/b.txt"), "a/b.txt");
+    }
+
+    #[test]
+    fn hard_links_are_unsupported() {
+        let mut builder = tar::Builder::new(Vec::new());
+        append(&mut builder, "a", tar::EntryType::Link, b"");
+        let archive = builder.into_inner().unwrap();
+
+        let err = import_tar(&archive[..], |_, _| Ok(())).unwrap_err();
+        assert!(
+            matches!(err, super::TarImportFailed::UnsupportedEntry(ref path) if path == "a"),
+            "{:?}",
+            err
+        );
+    }
+}
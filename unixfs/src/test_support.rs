@@ -41,6 +41,20 @@ impl FakeBlockstore {
         cid
     }
 
+    pub fn insert_raw(&mut self, block: &[u8]) -> Cid {
+        use multihash::MultihashDigest;
+        let mh = multihash::Code::Sha2_256.digest(block);
+        let cid = Cid::new_v1(crate::RAW_CODEC, mh);
+
+        assert!(
+            self.blocks.insert(cid.clone(), block.to_vec()).is_none(),
+            "duplicate cid {}",
+            cid
+        );
+
+        cid
+    }
+
     pub fn with_fixtures() -> Self {
         let mut this = Self::default();
         let foobar_blocks: &[&[u8]] = &[
@@ -114,3 +128,16 @@ impl FakeBlockstore {
         this
     }
 }
+
+/// Deterministic xorshift64 generated bytes for testing content defined chunking.
+pub fn pseudo_random(len: usize) -> Vec<u8> {
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 32) as u8
+        })
+        .collect()
+}
@@ -0,0 +1,94 @@
+use cid::Cid;
+use core::convert::TryFrom;
+use core::fmt;
+use multihash::MultihashDigest;
+
+/// Multihash code for the identity "hash", which is the content itself.
+const IDENTITY: u64 = 0x00;
+
+/// Checks that the `block` hashes to the multihash of the `cid`. This should be used on blocks
+/// received from untrusted sources before any of their content is handed out, for example while
+/// streaming a file to a consumer block by block.
+pub fn verify_block(cid: &Cid, block: &[u8]) -> Result<(), VerificationFailed> {
+    let expected = cid.hash();
+    let code = expected.code();
+
+    let matches = if code == IDENTITY {
+        expected.digest() == block
+    } else {
+        let hasher = multihash::Code::try_from(code)
+            .map_err(|_| VerificationFailed::UnsupportedHash(code))?;
+        &hasher.digest(block) == expected
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(VerificationFailed::HashMismatch)
+    }
+}
+
+/// Errors which can happen while verifying a block with [`verify_block`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum VerificationFailed {
+    /// The block did not hash to the multihash of the Cid; the content is corrupted.
+    HashMismatch,
+    /// The multihash of the Cid uses a hash function which is not supported.
+    UnsupportedHash(u64),
+}
+
+impl fmt::Display for VerificationFailed {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use VerificationFailed::*;
+        match self {
+            HashMismatch => write!(fmt, "block content does not match the Cid"),
+            UnsupportedHash(code) => write!(fmt, "unsupported multihash code: {:#x}", code),
+        }
+    }
+}
+
+impl std::error::Error for VerificationFailed {}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_block, VerificationFailed};
+    use crate::test_support::FakeBlockstore;
+    use crate::{CidOptions, HashFunction};
+    use cid::Cid;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn fixtures_verify() {
+        let blocks = FakeBlockstore::with_fixtures();
+        // the root and one of the leaves of the five block foobar\n
+        for cid in &[
+            "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6",
+            "QmfVyMoStzTvdnUR7Uotzh82gmL427q9z3xW5Y8fUoszi4",
+        ] {
+            let cid = Cid::try_from(*cid).unwrap();
+            verify_block(&cid, blocks.get_by_cid(&cid)).unwrap();
+        }
+    }
+
+    #[test]
+    fn other_hashes_verify() {
+        let opts = CidOptions::v1(HashFunction::Blake3_256);
+        verify_block(&opts.raw(b"foobar\n"), b"foobar\n").unwrap();
+
+        let inlined = CidOptions::default().with_inline_limit(8);
+        verify_block(&inlined.raw(b"foobar\n"), b"foobar\n").unwrap();
+        assert_eq!(
+            verify_block(&inlined.raw(b"foobar\n"), b"foobaz\n"),
+            Err(VerificationFailed::HashMismatch)
+        );
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let cid = CidOptions::default().raw(b"foobar\n");
+        assert_eq!(
+            verify_block(&cid, b"foobaz\n"),
+            Err(VerificationFailed::HashMismatch)
+        );
+    }
+}
@@ -11,8 +11,39 @@ use either::Either;
 use std::path::{Path, PathBuf};
 
 /// `Walker` helps with walking a UnixFS tree, including all of the content and files. It is
-/// created with `Walker::new` and walked over each block with `Walker::continue_block`. Use
+/// created with `Walker::new` and walked over each block with `Walker::next`. Use
 /// `Walker::pending_links` to obtain the next [`Cid`] to be loaded and the prefetchable links.
+///
+/// The walk is driven by the caller supplying the blocks one at a time, and the walker never holds
+/// more than the block given to `Walker::next`, which makes it suitable for streaming exports of
+/// large trees:
+///
+/// ```
+/// # use ipfs_unixfs::walk::{ContinuedWalk, Walker};
+/// # use cid::Cid;
+/// # fn load(_: &Cid) -> Vec<u8> { unimplemented!() }
+/// # fn example(root: Cid) -> Result<(), ipfs_unixfs::walk::Error> {
+/// let mut walker = Walker::new(root, String::new());
+/// let mut cache = None;
+///
+/// while walker.should_continue() {
+///     let block = load(walker.pending_links().0);
+///
+///     match walker.next(&block, &mut cache)? {
+///         ContinuedWalk::RootDirectory(_, path, metadata)
+///         | ContinuedWalk::Directory(_, path, metadata) => {
+///             // a directory starts; it has ended when a later entry is no longer below `path`
+///         }
+///         ContinuedWalk::File(segment, _, path, metadata, size) => {
+///             // files are received in segments, from `segment.is_first()` to `segment.is_last()`
+///         }
+///         ContinuedWalk::Symlink(target, _, path, metadata) => {}
+///         ContinuedWalk::Bucket(..) => { /* HAMT internal node, nothing to export */ }
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct Walker {
     /// This is `None` until the first block has been visited. Any failing unwraps would be logic
@@ -145,6 +176,49 @@ impl Walker {
             return Ok(ContinuedWalk::File(segment, cid, path, metadata, *sz));
         }
 
+        let is_raw = next
+            .as_ref()
+            .map(|(cid, ..)| u64::from(cid.codec()) == crate::RAW_CODEC)
+            .unwrap_or(false);
+
+        if is_raw {
+            // a single block file without the dag-pb and UnixFS envelope, as created with raw
+            // leaves; the block is the whole file and it cannot have any metadata.
+            let (cid, name, depth) = next.take().expect("validated at new and earlier");
+            let file_size = bytes.len() as u64;
+
+            match current {
+                None => {
+                    let ie = InnerEntry::new_root_file(
+                        cid,
+                        Metadata::default(),
+                        &name,
+                        None,
+                        file_size,
+                        depth,
+                    );
+                    *current = Some(ie);
+                }
+                Some(ie) => {
+                    ie.as_file(cid, &name, depth, Metadata::default(), None, file_size);
+                }
+            };
+
+            if let next_local @ Some(_) = pending.pop() {
+                *next = next_local;
+                *should_continue = true;
+            }
+
+            let ie = current.as_ref().unwrap();
+            return Ok(ContinuedWalk::File(
+                FileSegment::first(bytes, true),
+                &ie.cid,
+                &ie.path,
+                &ie.metadata,
+                file_size,
+            ));
+        }
+
         let flat = FlatUnixFs::try_from(bytes)?;
         let metadata = Metadata::from(&flat.data);
 
@@ -563,8 +637,8 @@ pub enum ContinuedWalk<'a> {
 }
 
 impl ContinuedWalk<'_> {
-    #[cfg(test)]
-    fn path(&self) -> &Path {
+    /// Returns the path of the entry being looked at, relative to the root of the walk.
+    pub fn path(&self) -> &Path {
         match self {
             Self::Bucket(_, p)
             | Self::Directory(_, p, ..)
@@ -919,6 +993,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn walk_raw_leaves() {
+        use crate::file::adder::{Chunker, FileAdder};
+
+        let content = b"foobar\n";
+
+        for chunk_size in &[2, content.len()] {
+            let received = FileAdder::builder()
+                .with_chunker(Chunker::Size(*chunk_size))
+                .with_raw_leaves(true)
+                .build()
+                .collect_blocks(content, 0);
+
+            let mut blocks = FakeBlockstore::default();
+            for (cid, block) in &received {
+                if cid.version() == cid::Version::V0 {
+                    blocks.insert_v0(block);
+                } else {
+                    blocks.insert_raw(block);
+                }
+            }
+
+            let root = received.last().unwrap().0.clone();
+            let mut walker = Walker::new(root, "foobar".into());
+            let mut read_back = Vec::new();
+
+            while walker.should_continue() {
+                let (next, _) = walker.pending_links();
+                let block = blocks.get_by_cid(next);
+                match walker.next(block, &mut None).unwrap() {
+                    ContinuedWalk::File(segment, _, path, _, size) => {
+                        assert_eq!(path, Path::new("foobar"));
+                        assert_eq!(size, content.len() as u64);
+                        read_back.extend_from_slice(segment.as_ref());
+                    }
+                    x => unreachable!("{:?}", x),
+                }
+            }
+
+            assert_eq!(read_back, content, "chunk_size: {}", chunk_size);
+        }
+    }
+
     fn walk_everything(root_name: &str, cid: &str) -> HashMap<PathBuf, usize> {
         let mut ret = HashMap::new();
 